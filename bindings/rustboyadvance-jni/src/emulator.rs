@@ -1,10 +1,12 @@
+use rustboyadvance_core::keypad::KEYINPUT_ALL_RELEASED;
 use rustboyadvance_core::prelude::*;
-use rustboyadvance_core::util::audio::{AudioRingBuffer, Producer};
+use rustboyadvance_core::util::audio::{AudioRingBuffer, Consumer, Producer};
 // use rustboyadvance_core::util::FpsCounter;
 
 use std::cell::RefCell;
 use std::path::Path;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
@@ -14,6 +16,10 @@ use jni::sys::{jboolean, jbyteArray, jintArray, jmethodID};
 use jni::JNIEnv;
 
 use crate::audio::{self, connector::AudioJNIConnector, thread::AudioThreadCommand};
+use crate::surface::NativeWindow;
+
+/// The sample rate when there is no `IAudioPlayer` and the samples are pulled with `read_audio`
+const DEFAULT_SAMPLE_RATE: i32 = 44100;
 
 struct Hardware {
     sample_rate: i32,
@@ -152,16 +158,70 @@ impl Default for EmulationState {
     }
 }
 
+/// The `IFrameRenderer`, `IAudioPlayer` and `Keypad` objects are optional. Without them the frames
+/// go to the surface given to `set_surface`, the samples are pulled with `read_audio` and the keys
+/// are set with `set_key_state`.
 pub struct EmulatorContext {
     hwif: Rc<RefCell<Hardware>>,
-    renderer: Renderer,
-    audio_player_ref: GlobalRef,
-    keypad: Keypad,
+    renderer: Option<Renderer>,
+    surface: Mutex<Option<NativeWindow>>,
+    audio_player_ref: Option<GlobalRef>,
+    audio_consumer: Mutex<Option<Consumer<i16>>>,
+    keypad: Option<Keypad>,
+    /// Set from the UI thread, e.g by a touch overlay, and combined with the keypad state
+    key_state: AtomicU16,
+    /// Copied from `hwif` at construction, so the UI thread reads it without borrowing `hwif`
+    /// while the emulation thread uses it
+    sample_rate: i32,
     pub emustate: Mutex<EmulationState>,
     pub gba: GameBoyAdvance,
 }
 
+fn get_sample_rate(env: &JNIEnv, audio_player: JObject) -> i32 {
+    if audio_player.is_null() {
+        DEFAULT_SAMPLE_RATE
+    } else {
+        audio::util::get_sample_rate(env, audio_player)
+    }
+}
+
 impl EmulatorContext {
+    fn new(
+        env: &JNIEnv,
+        gba: GameBoyAdvance,
+        hwif: Rc<RefCell<Hardware>>,
+        renderer: Option<Renderer>,
+        audio_player: JObject,
+        keypad_obj: JObject,
+    ) -> Result<EmulatorContext, String> {
+        let keypad = if keypad_obj.is_null() {
+            None
+        } else {
+            Some(Keypad::new(env, keypad_obj))
+        };
+        let audio_player_ref = if audio_player.is_null() {
+            None
+        } else {
+            let audio_player_ref = env
+                .new_global_ref(audio_player)
+                .map_err(|e| format!("failed to add new global ref, error: {:?}", e))?;
+            Some(audio_player_ref)
+        };
+        let sample_rate = hwif.borrow().sample_rate;
+        Ok(EmulatorContext {
+            gba,
+            keypad,
+            key_state: AtomicU16::new(KEYINPUT_ALL_RELEASED),
+            sample_rate,
+            renderer,
+            surface: Mutex::new(None),
+            audio_player_ref,
+            audio_consumer: Mutex::new(None),
+            emustate: Mutex::new(EmulationState::default()),
+            hwif,
+        })
+    }
+
     pub fn native_open_context(
         env: &JNIEnv,
         bios: jbyteArray,
@@ -192,11 +252,15 @@ impl EmulatorContext {
         info!("Loaded ROM file {:?}", gamepak.header);
 
         info!("Creating renderer");
-        let renderer = Renderer::new(env, renderer_obj)?;
+        let renderer = if renderer_obj.is_null() {
+            None
+        } else {
+            Some(Renderer::new(env, renderer_obj)?)
+        };
 
         info!("Creating GBA Instance");
         let hw = Rc::new(RefCell::new(Hardware {
-            sample_rate: audio::util::get_sample_rate(env, audio_player),
+            sample_rate: get_sample_rate(env, audio_player),
             audio_producer: None,
             key_state: 0xffff,
         }));
@@ -206,20 +270,8 @@ impl EmulatorContext {
             gba.skip_bios();
         }
 
-        info!("creating keypad");
-        let keypad = Keypad::new(env, keypad_obj);
-
         info!("creating context");
-        let audio_player_ref = env.new_global_ref(audio_player).unwrap();
-        let context = EmulatorContext {
-            gba,
-            keypad,
-            renderer,
-            audio_player_ref,
-            emustate: Mutex::new(EmulationState::default()),
-            hwif: hw.clone(),
-        };
-        Ok(context)
+        EmulatorContext::new(env, gba, hw, renderer, audio_player, keypad_obj)
     }
 
     pub fn native_open_saved_state(
@@ -243,10 +295,14 @@ impl EmulatorContext {
             .convert_byte_array(savestate)
             .map_err(|e| format!("could not get savestate buffer, error {}", e))?;
 
-        let renderer = Renderer::new(env, renderer_obj)?;
+        let renderer = if renderer_obj.is_null() {
+            None
+        } else {
+            Some(Renderer::new(env, renderer_obj)?)
+        };
 
         let hw = Rc::new(RefCell::new(Hardware {
-            sample_rate: audio::util::get_sample_rate(env, audio_player),
+            sample_rate: get_sample_rate(env, audio_player),
             audio_producer: None,
             key_state: 0xffff,
         }));
@@ -258,21 +314,50 @@ impl EmulatorContext {
             )
         })?;
 
-        let keypad = Keypad::new(env, keypad_obj);
-
-        let audio_player_ref = env.new_global_ref(audio_player).unwrap();
-        Ok(EmulatorContext {
-            gba,
-            keypad,
-            renderer,
-            audio_player_ref,
-            emustate: Mutex::new(EmulationState::default()),
-            hwif: hw.clone(),
-        })
+        EmulatorContext::new(env, gba, hw, renderer, audio_player, keypad_obj)
     }
 
     fn render_video(&mut self, env: &JNIEnv) {
-        self.renderer.render_frame(env, self.gba.get_frame_buffer());
+        let frame = self.gba.get_frame_buffer();
+        if let Some(window) = self.surface.lock().unwrap().as_mut() {
+            if let Err(e) = window.render_frame(frame) {
+                warn!("failed to render to the surface: {}", e);
+            }
+        } else if let Some(renderer) = &self.renderer {
+            renderer.render_frame(env, frame);
+        }
+    }
+
+    /// Renders the next frames to `surface`, or to the `IFrameRenderer` when it is null
+    pub fn set_surface(&self, env: &JNIEnv, surface: JObject) -> Result<(), String> {
+        let window = if surface.is_null() {
+            None
+        } else {
+            Some(NativeWindow::from_surface(env, surface)?)
+        };
+        // the previous window is released once the emulation thread is done drawing to it
+        *self.surface.lock().unwrap() = window;
+        Ok(())
+    }
+
+    /// Sets the keys pressed on the UI, as a KEYINPUT value where a cleared bit is a pressed key
+    pub fn set_key_state(&self, key_state: u16) {
+        self.key_state.store(key_state, Ordering::Relaxed);
+    }
+
+    /// Fills `samples` with interleaved stereo samples when there is no `IAudioPlayer`, in the
+    /// 16bit PCM format an `AudioTrack` is written with. Returns how many samples were read.
+    pub fn read_audio(&self, samples: &mut [i16]) -> usize {
+        // keep the stereo frames whole
+        let len = samples.len() & !1;
+        match self.audio_consumer.lock().unwrap().as_mut() {
+            Some(consumer) => consumer.pop_slice(&mut samples[..len]),
+            None => 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate
     }
 
     /// Lock the emulation loop in order to perform updates to the struct
@@ -284,24 +369,45 @@ impl EmulatorContext {
     pub fn native_run(&mut self, env: &JNIEnv) -> Result<(), jni::errors::Error> {
         const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000u64 / 60);
 
-        // Set the state to running
-        *self.emustate.lock().unwrap() = EmulationState::Running(false);
+        // Set the state to running, unless the context was stopped before the loop got to run
+        {
+            let mut emustate = self.emustate.lock().unwrap();
+            if *emustate == EmulationState::Stopped {
+                return Ok(());
+            }
+            *emustate = EmulationState::Running(false);
+        }
 
         // Extract current JVM
         let jvm = env.get_java_vm().unwrap();
 
-        // Instanciate an audio player connector
-        let audio_connector = AudioJNIConnector::new(env, self.audio_player_ref.as_obj());
+        let audio_worker = match &self.audio_player_ref {
+            Some(audio_player_ref) => {
+                // Instanciate an audio player connector
+                let audio_connector = AudioJNIConnector::new(env, audio_player_ref.as_obj());
 
-        // Create a ringbuffer between the emulator and the audio thread
-        let (prod, cons) = AudioRingBuffer::new_with_capacity(audio_connector.sample_count).split();
+                // Create a ringbuffer between the emulator and the audio thread
+                let (prod, cons) =
+                    AudioRingBuffer::new_with_capacity(audio_connector.sample_count).split();
 
-        // Store the ringbuffer producer in the emulator
-        self.hwif.borrow_mut().audio_producer = Some(prod);
+                // Store the ringbuffer producer in the emulator
+                self.hwif.borrow_mut().audio_producer = Some(prod);
 
-        // Spawn the audio worker thread, give it the audio connector, jvm and ringbuffer consumer
-        let (audio_thread_handle, audio_thread_tx) =
-            audio::thread::spawn_audio_worker_thread(audio_connector, jvm, cons);
+                // Spawn the audio worker thread, give it the audio connector, jvm and ringbuffer consumer
+                Some(audio::thread::spawn_audio_worker_thread(
+                    audio_connector,
+                    jvm,
+                    cons,
+                ))
+            }
+            None => {
+                // The frontend pulls the samples from the ringbuffer itself
+                let (prod, cons) = AudioRingBuffer::new().split();
+                self.hwif.borrow_mut().audio_producer = Some(prod);
+                *self.audio_consumer.lock().unwrap() = Some(cons);
+                None
+            }
+        };
 
         info!("starting main emulation loop");
 
@@ -315,17 +421,27 @@ impl EmulatorContext {
                 EmulationState::Stopped => unsafe { std::hint::unreachable_unchecked() },
                 EmulationState::Pausing => {
                     info!("emulation pause requested");
+                    // the process may be killed any time once the activity is paused
+                    self.gba.flush_save();
                     *self.emustate.lock().unwrap() = EmulationState::Paused;
                     continue;
                 }
                 EmulationState::Paused => continue,
-                EmulationState::Stopping => break 'running,
+                EmulationState::Stopping => {
+                    self.gba.flush_save();
+                    break 'running;
+                }
                 EmulationState::Running(turbo) => !turbo,
             };
 
             let start_time = Instant::now();
             // check key state
-            self.hwif.borrow_mut().key_state = self.keypad.get_key_state(env);
+            let mut key_state = self.key_state.load(Ordering::Relaxed);
+            if let Some(keypad) = &self.keypad {
+                // both are active low, a key is pressed if it is pressed on either
+                key_state &= keypad.get_key_state(env);
+            }
+            self.hwif.borrow_mut().key_state = key_state;
 
             // run frame
             self.gba.frame();
@@ -334,9 +450,11 @@ impl EmulatorContext {
             self.render_video(env);
 
             // request audio worker to render the audio now
-            audio_thread_tx
-                .send(AudioThreadCommand::RenderAudio)
-                .unwrap();
+            if let Some((_, audio_thread_tx)) = &audio_worker {
+                audio_thread_tx
+                    .send(AudioThreadCommand::RenderAudio)
+                    .unwrap();
+            }
 
             // if let Some(fps) = fps_counter.tick() {
             //     info!("FPS {}", fps);
@@ -354,16 +472,21 @@ impl EmulatorContext {
             }
         }
 
-        info!("stopping, terminating audio worker");
-        audio_thread_tx.send(AudioThreadCommand::Terminate).unwrap(); // we surely have an endpoint, so it will work
-        info!("waiting for audio worker to complete");
+        if let Some((audio_thread_handle, audio_thread_tx)) = audio_worker {
+            info!("stopping, terminating audio worker");
+            audio_thread_tx.send(AudioThreadCommand::Terminate).unwrap(); // we surely have an endpoint, so it will work
+            info!("waiting for audio worker to complete");
 
-        let audio_connector = audio_thread_handle.join().unwrap();
-        info!("audio worker terminated");
+            let audio_connector = audio_thread_handle.join().unwrap();
+            info!("audio worker terminated");
 
-        audio_connector.pause(env);
+            audio_connector.pause(env);
+        }
 
         self.hwif.borrow_mut().audio_producer = None;
+        *self.audio_consumer.lock().unwrap() = None;
+
+        self.gba.flush_save();
 
         *self.emustate.lock().unwrap() = EmulationState::Stopped;
 
@@ -372,7 +495,7 @@ impl EmulatorContext {
 
     pub fn native_get_framebuffer(&mut self, env: &JNIEnv) -> jintArray {
         let fb = env.new_int_array(240 * 160).unwrap();
-        self.pause();
+        let was_running = self.pause();
         unsafe {
             env.set_int_array_region(
                 fb,
@@ -381,29 +504,58 @@ impl EmulatorContext {
             )
             .unwrap();
         }
-        self.resume();
+        if was_running {
+            self.resume();
+        }
 
         fb
     }
 
-    pub fn pause(&mut self) {
-        *self.emustate.lock().unwrap() = EmulationState::Pausing;
+    /// Waits for the emulation loop to pause, returns false when it wasn't running.
+    /// The emulation thread may be gone already when the activity is paused or stopped, so it
+    /// can't be waited for then.
+    pub fn pause(&mut self) -> bool {
+        {
+            let mut emustate = self.emustate.lock().unwrap();
+            match *emustate {
+                EmulationState::Running(_) => *emustate = EmulationState::Pausing,
+                EmulationState::Pausing => {}
+                _ => return false,
+            }
+        }
         while *self.emustate.lock().unwrap() != EmulationState::Paused {
             info!("awaiting pause...")
         }
+        true
     }
 
     pub fn resume(&mut self) {
-        *self.emustate.lock().unwrap() = EmulationState::Running(false);
+        self.set_running(EmulationState::Running(false));
     }
 
     pub fn set_turbo(&mut self, turbo: bool) {
-        *self.emustate.lock().unwrap() = EmulationState::Running(turbo);
+        self.set_running(EmulationState::Running(turbo));
+    }
+
+    /// Only while the emulation loop is alive, so a late call from the activity doesn't leave a
+    /// stopped context waiting for a loop that is gone
+    fn set_running(&mut self, running: EmulationState) {
+        let mut emustate = self.emustate.lock().unwrap();
+        match *emustate {
+            EmulationState::Running(_) | EmulationState::Pausing | EmulationState::Paused => {
+                *emustate = running
+            }
+            _ => {}
+        }
     }
 
     pub fn request_stop(&mut self) {
-        if EmulationState::Stopped != *self.emustate.lock().unwrap() {
-            *self.emustate.lock().unwrap() = EmulationState::Stopping;
+        let mut emustate = self.emustate.lock().unwrap();
+        match *emustate {
+            // the emulation loop never ran, there is nothing to wait for
+            EmulationState::Initial => *emustate = EmulationState::Stopped,
+            EmulationState::Stopped => {}
+            _ => *emustate = EmulationState::Stopping,
         }
     }
 
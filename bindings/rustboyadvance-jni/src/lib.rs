@@ -3,6 +3,7 @@ mod emulator;
 /// JNI Bindings for rustboyadvance
 ///
 mod rom_helper;
+mod surface;
 
 use emulator::EmulatorContext;
 
@@ -225,18 +226,18 @@ pub mod bindings {
         ctx: jlong,
     ) -> jbyteArray {
         let ctx = cast_ctx(ctx);
-        ctx.pause();
+        let was_running = ctx.pause();
         let (_lock, gba) = ctx.lock_and_get_gba();
-        match save_state(&env, gba) {
-            Ok(result) => {
-                drop(_lock);
-                drop(gba);
-                ctx.resume();
-                return result;
-            }
+        let result = save_state(&env, gba);
+        drop(_lock);
+        if was_running {
+            ctx.resume();
+        }
+        match result {
+            Ok(result) => result,
             Err(msg) => {
                 env.throw_new(NATIVE_EXCEPTION_CLASS, msg).unwrap();
-                return JObject::null().into_inner();
+                JObject::null().into_inner()
             }
         }
     }
@@ -249,16 +250,76 @@ pub mod bindings {
         state: jbyteArray,
     ) {
         let ctx = cast_ctx(ctx);
-        ctx.pause();
+        let was_running = ctx.pause();
         let (_lock, gba) = ctx.lock_and_get_gba();
-        match load_state(&env, gba, state) {
-            Ok(_) => {
-                drop(_lock);
-                drop(gba);
-                ctx.resume();
+        let result = load_state(&env, gba, state);
+        drop(_lock);
+        if was_running {
+            ctx.resume();
+        }
+        if let Err(msg) = result {
+            env.throw_new(NATIVE_EXCEPTION_CLASS, msg).unwrap();
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn Java_com_mrmichel_rustboyadvance_EmulatorBindings_setSurface(
+        env: JNIEnv,
+        _obj: JClass,
+        ctx: jlong,
+        surface: JObject,
+    ) {
+        let ctx = cast_ctx(ctx);
+        if let Err(msg) = ctx.set_surface(&env, surface) {
+            env.throw_new(NATIVE_EXCEPTION_CLASS, msg).unwrap();
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn Java_com_mrmichel_rustboyadvance_EmulatorBindings_setKeyState(
+        _env: JNIEnv,
+        _obj: JClass,
+        ctx: jlong,
+        key_state: jint,
+    ) {
+        let ctx = cast_ctx(ctx);
+        ctx.set_key_state(key_state as u16);
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn Java_com_mrmichel_rustboyadvance_EmulatorBindings_readAudioSamples(
+        env: JNIEnv,
+        _obj: JClass,
+        ctx: jlong,
+        buffer: jshortArray,
+    ) -> jint {
+        let ctx = cast_ctx(ctx);
+        let len = match env.get_array_length(buffer) {
+            Ok(len) => len as usize,
+            Err(e) => {
+                env.throw_new(NATIVE_EXCEPTION_CLASS, format!("Error: {:?}", e))
+                    .unwrap();
+                return 0;
             }
-            Err(msg) => env.throw_new(NATIVE_EXCEPTION_CLASS, msg).unwrap(),
+        };
+        let mut samples = vec![0; len];
+        let count = ctx.read_audio(&mut samples);
+        if let Err(e) = env.set_short_array_region(buffer, 0, &samples[..count]) {
+            env.throw_new(NATIVE_EXCEPTION_CLASS, format!("Error: {:?}", e))
+                .unwrap();
+            return 0;
         }
+        count as jint
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn Java_com_mrmichel_rustboyadvance_EmulatorBindings_getSampleRate(
+        _env: JNIEnv,
+        _obj: JClass,
+        ctx: jlong,
+    ) -> jint {
+        let ctx = cast_ctx(ctx);
+        ctx.sample_rate()
     }
 
     #[no_mangle]
@@ -0,0 +1,132 @@
+//! Renders the frames straight to an `android.view.Surface` through the NDK `ANativeWindow` API,
+//! without going through a java int array and an `IFrameRenderer`
+use jni::objects::JObject;
+use jni::JNIEnv;
+
+#[cfg(target_os = "android")]
+mod ffi {
+    use std::os::raw::c_void;
+
+    use jni::sys::{jobject, JNIEnv};
+
+    pub const WINDOW_FORMAT_RGBA_8888: i32 = 1;
+
+    #[repr(C)]
+    pub struct ANativeWindow {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct ANativeWindowBuffer {
+        pub width: i32,
+        pub height: i32,
+        /// In pixels
+        pub stride: i32,
+        pub format: i32,
+        pub bits: *mut c_void,
+        pub reserved: [u32; 6],
+    }
+
+    #[link(name = "android")]
+    extern "C" {
+        pub fn ANativeWindow_fromSurface(env: *mut JNIEnv, surface: jobject) -> *mut ANativeWindow;
+        pub fn ANativeWindow_release(window: *mut ANativeWindow);
+        pub fn ANativeWindow_setBuffersGeometry(
+            window: *mut ANativeWindow,
+            width: i32,
+            height: i32,
+            format: i32,
+        ) -> i32;
+        pub fn ANativeWindow_lock(
+            window: *mut ANativeWindow,
+            out_buffer: *mut ANativeWindowBuffer,
+            in_out_dirty_bounds: *mut c_void,
+        ) -> i32;
+        pub fn ANativeWindow_unlockAndPost(window: *mut ANativeWindow) -> i32;
+    }
+}
+
+#[cfg(target_os = "android")]
+pub struct NativeWindow {
+    window: *mut ffi::ANativeWindow,
+}
+
+#[cfg(target_os = "android")]
+impl NativeWindow {
+    pub fn from_surface(env: &JNIEnv, surface: JObject) -> Result<NativeWindow, String> {
+        use rustboyadvance_core::prelude::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+        let window = unsafe {
+            ffi::ANativeWindow_fromSurface(env.get_native_interface(), surface.into_inner())
+        };
+        if window.is_null() {
+            return Err("failed to get a native window from the surface".to_string());
+        }
+        // the window keeps the GBA resolution, the compositor scales it to the surface size
+        let result = unsafe {
+            ffi::ANativeWindow_setBuffersGeometry(
+                window,
+                DISPLAY_WIDTH as i32,
+                DISPLAY_HEIGHT as i32,
+                ffi::WINDOW_FORMAT_RGBA_8888,
+            )
+        };
+        if result != 0 {
+            unsafe { ffi::ANativeWindow_release(window) };
+            return Err(format!(
+                "failed to set the window geometry, error {}",
+                result
+            ));
+        }
+        Ok(NativeWindow { window })
+    }
+
+    /// Draws `frame`, in the 0x00RRGGBB format of `GameBoyAdvance::get_frame_buffer`
+    pub fn render_frame(&mut self, frame: &[u32]) -> Result<(), String> {
+        use rustboyadvance_core::prelude::DISPLAY_WIDTH;
+
+        let mut buffer: ffi::ANativeWindowBuffer = unsafe { std::mem::zeroed() };
+        let result =
+            unsafe { ffi::ANativeWindow_lock(self.window, &mut buffer, std::ptr::null_mut()) };
+        if result != 0 {
+            return Err(format!("failed to lock the window, error {}", result));
+        }
+
+        let stride = buffer.stride as usize;
+        let height = buffer.height as usize;
+        let bits =
+            unsafe { std::slice::from_raw_parts_mut(buffer.bits as *mut u32, stride * height) };
+        for (line, pixels) in frame.chunks(DISPLAY_WIDTH).take(height).enumerate() {
+            let row = &mut bits[line * stride..line * stride + DISPLAY_WIDTH.min(stride)];
+            for (dst, &src) in row.iter_mut().zip(pixels) {
+                let [b, g, r, _] = src.to_le_bytes();
+                *dst = u32::from_ne_bytes([r, g, b, 0xff]);
+            }
+        }
+
+        unsafe { ffi::ANativeWindow_unlockAndPost(self.window) };
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "android")]
+impl Drop for NativeWindow {
+    fn drop(&mut self) {
+        unsafe { ffi::ANativeWindow_release(self.window) };
+    }
+}
+
+/// Stands in for the NDK window when the bindings are built for the host
+#[cfg(not(target_os = "android"))]
+pub struct NativeWindow;
+
+#[cfg(not(target_os = "android"))]
+impl NativeWindow {
+    pub fn from_surface(_env: &JNIEnv, _surface: JObject) -> Result<NativeWindow, String> {
+        Err("surfaces are only supported on android".to_string())
+    }
+
+    pub fn render_frame(&mut self, _frame: &[u32]) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -1,37 +1,34 @@
-/// Measure first 60 frames bigmap.gba from tonc demos
+/// Measure first 60 frames bigmap.gba from tonc demos, along with the parts of the emulation
+/// that take the most time: the interpreter loop, the scanline rendering and DMA transfers
 ///
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 
-use std::cell::RefCell;
-use std::rc::Rc;
-
+use rustboyadvance_core::gpu::consts::CYCLES_FULL_REFRESH;
 use rustboyadvance_core::prelude::*;
 
-struct BenchmarkHardware {}
-impl AudioInterface for BenchmarkHardware {}
-impl InputInterface for BenchmarkHardware {}
-
-fn create_gba() -> GameBoyAdvance {
+fn bios() -> Box<[u8]> {
     // TODO: do I really want this file in my repository ?
-    let bios = include_bytes!("roms/normatt_gba_bios.bin");
-    let bigmap_rom = include_bytes!("roms/bigmap.gba");
+    include_bytes!("roms/normatt_gba_bios.bin")
+        .to_vec()
+        .into_boxed_slice()
+}
 
-    let gpak = GamepakBuilder::new()
-        .take_buffer(bigmap_rom.to_vec().into_boxed_slice())
-        .with_sram()
-        .without_backup_to_file()
+fn create_gba_with_rom(rom: &[u8]) -> GameBoyAdvance {
+    GameBoyAdvanceBuilder::new()
+        .with_bios(bios())
+        .with_gamepak(
+            GamepakBuilder::new()
+                .buffer(rom)
+                .with_sram()
+                .without_backup_to_file(),
+        )
+        .skip_bios()
         .build()
-        .unwrap();
-
-    let dummy = Rc::new(RefCell::new(BenchmarkHardware {}));
+        .unwrap()
+}
 
-    let mut gba = GameBoyAdvance::new(
-        bios.to_vec().into_boxed_slice(),
-        gpak,
-        dummy.clone(),
-        dummy.clone(),
-    );
-    gba.skip_bios();
+fn create_gba() -> GameBoyAdvance {
+    let mut gba = create_gba_with_rom(include_bytes!("roms/bigmap.gba"));
     // skip initialization of the ROM to get to a stabilized scene
     for _ in 0..60 {
         gba.frame();
@@ -55,9 +52,59 @@ pub fn performance_benchmark(c: &mut Criterion) {
     });
 }
 
+/// The cpu alone, on a loop that never halts and doesn't touch the io registers
+pub fn interpreter_benchmark(c: &mut Criterion) {
+    let program: &[u32] = &[
+        0xe280_0001, // add r0, r0, #1
+        0xe1a0_1100, // mov r1, r0, lsl #2
+        0xeaff_fffc, // b 0x08000000
+    ];
+    let mut rom: Vec<u8> = program
+        .iter()
+        .flat_map(|insn| insn.to_le_bytes().to_vec())
+        .collect();
+    // room for the cartridge header
+    rom.resize(0x200, 0);
+    let mut gba = create_gba_with_rom(&rom);
+    c.bench_function("interpreter_loop_1_frame", |b| {
+        b.iter(|| black_box(gba.run_cycles(CYCLES_FULL_REFRESH)))
+    });
+}
+
+pub fn ppu_benchmark(c: &mut Criterion) {
+    let mut gba = create_gba();
+    c.bench_function("render_160_scanlines", |b| {
+        b.iter(|| {
+            let gpu = &mut gba.io_devs.gpu;
+            for line in 0..DISPLAY_HEIGHT {
+                gpu.vcount = line;
+                gpu.render_scanline();
+            }
+            black_box(gpu.get_frame_buffer()[0])
+        })
+    });
+}
+
+/// An immediate 32KB transfer from EWRAM to VRAM with DMA3
+pub fn dma_benchmark(c: &mut Criterion) {
+    let mut gba = create_gba_with_rom(&[0; 0x200]);
+    c.bench_function("dma3_32kb", |b| {
+        b.iter(|| {
+            gba.sysbus.write_32(0x0400_00d4, 0x0200_0000);
+            gba.sysbus.write_32(0x0400_00d8, 0x0600_0000);
+            gba.sysbus.write_16(0x0400_00dc, 0x2000);
+            // enabled, 32 bit units, starts right away
+            gba.sysbus.write_16(0x0400_00de, 0x8400);
+            while gba.sysbus.read_16(0x0400_00de) & 0x8000 != 0 {
+                gba.run_cycles(16);
+            }
+        })
+    });
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets = performance_benchmark
+    targets = performance_benchmark, interpreter_benchmark, ppu_benchmark, dma_benchmark
 }
 criterion_main!(benches);
@@ -276,6 +276,10 @@ fn arm_decode(i: u32) -> (&'static str, String) {
                 result
             } else {
                 match (i.ibit(25), i.ibit(22), i.ibit(7), i.ibit(4)) {
+                    // the multiply and swap encodings that are not valid
+                    (0, _, 1, 1) if i.bit_range(5..7) == 0 => {
+                        ("Undefined", String::from("arm_undefined"))
+                    }
                     (0, 0, 1, 1) => (
                         "HalfwordDataTransferRegOffset",
                         format!(
@@ -367,6 +371,83 @@ fn arm_decode(i: u32) -> (&'static str, String) {
     }
 }
 
+/// The masks and values the ARM formats are told apart with, the first match wins. Written out
+/// along with `ARM_FORMAT_LUT` for `ArmFormat::from` to match the instructions the LUT can't tell.
+const ARM_FORMAT_PATTERNS: [(u32, u32, &str); 15] = [
+    (0x0fff_fff0, 0x012f_ff10, "BranchExchange"),
+    (0x0e00_0000, 0x0a00_0000, "BranchLink"),
+    (0xe000_0010, 0x0600_0000, "Undefined"),
+    (0x0fb0_0ff0, 0x0100_0090, "SingleDataSwap"),
+    (0x0fc0_00f0, 0x0000_0090, "Multiply"),
+    (0x0f80_00f0, 0x0080_0090, "MultiplyLong"),
+    (0x0fbf_0fff, 0x010f_0000, "MoveFromStatus"),
+    (0x0fbf_fff0, 0x0129_f000, "MoveToStatus"),
+    (0x0dbf_f000, 0x0128_f000, "MoveToFlags"),
+    (0x0c00_0000, 0x0400_0000, "SingleDataTransfer"),
+    (0x0e40_0f90, 0x0000_0090, "HalfwordDataTransferRegOffset"),
+    (
+        0x0e40_0090,
+        0x0040_0090,
+        "HalfwordDataTransferImmediateOffset",
+    ),
+    (0x0e00_0000, 0x0800_0000, "BlockDataTransfer"),
+    (0x0f00_0000, 0x0f00_0000, "SoftwareInterrupt"),
+    (0x0c00_0000, 0x0000_0000, "DataProcessing"),
+];
+
+/// The bits the ARM tables are indexed by, 27-20 and 7-4
+const ARM_INDEX_MASK: u32 = 0x0ff0_00f0;
+
+/// The format of every ARM instruction with the index bits of `i`, or None when the format also
+/// depends on the other bits
+fn arm_format(i: u32) -> Option<&'static str> {
+    for &(mask, value, fmt) in ARM_FORMAT_PATTERNS.iter() {
+        if value & !mask != 0 || (i ^ value) & mask & ARM_INDEX_MASK != 0 {
+            continue;
+        }
+        if mask & !ARM_INDEX_MASK != 0 {
+            return None;
+        }
+        return Some(fmt);
+    }
+    Some("Undefined")
+}
+
+fn generate_arm_format_lut(file: &mut fs::File) -> Result<(), std::io::Error> {
+    writeln!(file, "const ARM_FORMAT_LUT: [Option<ArmFormat>; 4096] = [")?;
+    for i in 0..4096 {
+        match arm_format(((i & 0xff0) << 16) | ((i & 0x00f) << 4)) {
+            Some(fmt) => writeln!(file, "    /* {:#x} */ Some(ArmFormat::{}),", i, fmt)?,
+            None => writeln!(file, "    /* {:#x} */ None,", i)?,
+        }
+    }
+    writeln!(file, "];")?;
+    writeln!(
+        file,
+        "const ARM_FORMAT_PATTERNS: [(u32, u32, ArmFormat); {}] = [",
+        ARM_FORMAT_PATTERNS.len()
+    )?;
+    for &(mask, value, fmt) in ARM_FORMAT_PATTERNS.iter() {
+        writeln!(
+            file,
+            "    ({:#010x}, {:#010x}, ArmFormat::{}),",
+            mask, value, fmt
+        )?;
+    }
+    writeln!(file, "];")?;
+    Ok(())
+}
+
+fn generate_thumb_format_lut(file: &mut fs::File) -> Result<(), std::io::Error> {
+    writeln!(file, "const THUMB_FORMAT_LUT: [ThumbFormat; 1024] = [")?;
+    for i in 0..1024 {
+        let (thumb_fmt, _) = thumb_decode(i << 6);
+        writeln!(file, "    /* {:#x} */ ThumbFormat::{},", i, thumb_fmt)?;
+    }
+    writeln!(file, "];")?;
+    Ok(())
+}
+
 fn generate_thumb_lut(file: &mut fs::File) -> Result<(), std::io::Error> {
     writeln!(file, "impl<I: MemoryInterface> Core<I> {{")?;
     writeln!(
@@ -430,5 +511,16 @@ fn main() {
     let mut arm_lut_file = fs::File::create(&arm_lut_path).expect("failed to create file");
     generate_arm_lut(&mut arm_lut_file).expect("failed to generate arm table");
 
+    let arm_format_lut_path = Path::new(&out_dir).join("arm_format_lut.rs");
+    let mut arm_format_lut_file =
+        fs::File::create(&arm_format_lut_path).expect("failed to create file");
+    generate_arm_format_lut(&mut arm_format_lut_file).expect("failed to generate arm format table");
+
+    let thumb_format_lut_path = Path::new(&out_dir).join("thumb_format_lut.rs");
+    let mut thumb_format_lut_file =
+        fs::File::create(&thumb_format_lut_path).expect("failed to create file");
+    generate_thumb_format_lut(&mut thumb_format_lut_file)
+        .expect("failed to generate thumb format table");
+
     println!("cargo:rerun-if-changed=build.rs");
 }
@@ -3,7 +3,7 @@ use bit::BitIndex;
 use super::memory::MemoryInterface;
 use super::{Core, REG_PC};
 
-#[derive(Debug, Primitive, Eq, PartialEq)]
+#[derive(Debug, Primitive, Eq, PartialEq, Copy, Clone)]
 pub enum AluOpCode {
     AND = 0b0000,
     EOR = 0b0001,
@@ -253,10 +253,11 @@ impl<I: MemoryInterface> Core<I> {
         rs: usize,
         carry: &mut bool,
     ) -> u32 {
-        let mut val = self.get_reg(reg);
-        if reg == REG_PC {
-            val += 4; // PC prefetching
-        }
+        let val = if reg == REG_PC {
+            self.pc_operand_late()
+        } else {
+            self.get_reg(reg)
+        };
         let amount = self.get_reg(rs) & 0xff;
         self.barrel_shift_op(bs_op, val, amount, carry, false)
     }
@@ -1,4 +1,5 @@
 use crate::bit::BitIndex;
+use crate::logging;
 
 use super::super::alu::*;
 use crate::arm7tdmi::psr::RegPSR;
@@ -13,11 +14,17 @@ use super::*;
 
 impl<I: MemoryInterface> Core<I> {
     pub fn arm_undefined(&mut self, insn: u32) -> CpuAction {
-        panic!(
+        warn!(
+            target: logging::CPU,
             "executing undefined arm instruction {:08x} at @{:08x}",
             insn,
             self.pc_arm()
-        )
+        );
+        // the game may well handle the exception, the history is only of use when tracing
+        #[cfg(feature = "debugger")]
+        self.dbg.history.log(log::Level::Trace);
+        self.undefined_instruction(self.pc - 4); // Implies 2S + 1N
+        CpuAction::PipelineFlushed
     }
 
     /// Branch and Branch with Link (B, BL)
@@ -28,7 +35,6 @@ impl<I: MemoryInterface> Core<I> {
         }
         self.pc = (self.pc as i32).wrapping_add(insn.branch_offset()) as u32 & !1;
 
-        self.reload_pipeline32(); // Implies 2S + 1N
         CpuAction::PipelineFlushed
     }
 
@@ -37,12 +43,10 @@ impl<I: MemoryInterface> Core<I> {
             addr = addr & !0x1;
             self.cpsr.set_state(CpuState::THUMB);
             self.pc = addr;
-            self.reload_pipeline16();
         } else {
             addr = addr & !0x3;
             self.cpsr.set_state(CpuState::ARM);
             self.pc = addr;
-            self.reload_pipeline32();
         }
         CpuAction::PipelineFlushed
     }
@@ -105,19 +109,29 @@ impl<I: MemoryInterface> Core<I> {
         match self.cpsr.mode() {
             CpuMode::User => {
                 if SPSR_FLAG {
-                    panic!("User mode can't access SPSR")
+                    // there is no SPSR to write to
+                    self.unpredictable("MSR to the SPSR in user mode");
+                } else {
+                    self.cpsr.set_flag_bits(value);
                 }
-                self.cpsr.set_flag_bits(value);
             }
             _ => {
                 if SPSR_FLAG {
                     self.spsr.set(value);
                 } else {
                     let old_mode = self.cpsr.mode();
-                    let new_psr = RegPSR::new((self.cpsr.get() & !mask) | (value & mask));
-                    let new_mode = new_psr.mode();
-                    if old_mode != new_mode {
-                        self.change_mode(old_mode, new_mode);
+                    let mut new_psr = RegPSR::new((self.cpsr.get() & !mask) | (value & mask));
+                    match new_psr.valid_mode() {
+                        Some(new_mode) => {
+                            if old_mode != new_mode {
+                                self.change_mode(old_mode, new_mode);
+                            }
+                        }
+                        None => {
+                            // the rest of the CPSR is written, the cpu stays in its mode
+                            self.unpredictable("MSR to an invalid cpu mode");
+                            new_psr.set_mode(old_mode);
+                        }
                     }
                     self.cpsr = new_psr;
                 }
@@ -127,10 +141,19 @@ impl<I: MemoryInterface> Core<I> {
         CpuAction::AdvancePC(Seq)
     }
 
+    /// Restores the CPSR from the SPSR, for the data processing and LDM instructions that load
+    /// the pc with the S bit set
     fn transfer_spsr_mode(&mut self) {
         let spsr = self.spsr;
-        if self.cpsr.mode() != spsr.mode() {
-            self.change_mode(self.cpsr.mode(), spsr.mode());
+        let new_mode = match spsr.valid_mode() {
+            Some(mode) => mode,
+            None => {
+                self.unpredictable("CPSR restored from an SPSR with an invalid cpu mode");
+                return;
+            }
+        };
+        if self.cpsr.mode() != new_mode {
+            self.change_mode(self.cpsr.mode(), new_mode);
         }
         self.cpsr = spsr;
     }
@@ -152,7 +175,7 @@ impl<I: MemoryInterface> Core<I> {
         let rn = insn.bit_range(16..20) as usize;
         let rd = insn.bit_range(12..16) as usize;
         let mut op1 = if rn == REG_PC {
-            self.pc_arm() + 8
+            self.pc_operand()
         } else {
             self.get_reg(rn)
         };
@@ -171,7 +194,7 @@ impl<I: MemoryInterface> Core<I> {
 
             let shift_by = if SHIFT_BY_REG {
                 if rn == REG_PC {
-                    op1 += 4;
+                    op1 = self.pc_operand_late();
                 }
                 self.idle_cycle();
                 let rs = insn.bit_range(8..12) as usize;
@@ -190,7 +213,8 @@ impl<I: MemoryInterface> Core<I> {
             self.register_shift(&shifted_reg, &mut carry)
         };
 
-        if rd == REG_PC && s_flag {
+        // without an SPSR to restore, the flags are set as usual
+        if rd == REG_PC && s_flag && self.cpsr.mode().has_spsr() {
             self.transfer_spsr_mode();
             s_flag = false;
         }
@@ -242,11 +266,6 @@ impl<I: MemoryInterface> Core<I> {
         if let Some(alu_res) = alu_res {
             self.set_reg(rd, alu_res as u32);
             if rd == REG_PC {
-                // T bit might have changed
-                match self.cpsr.state() {
-                    CpuState::ARM => self.reload_pipeline32(),
-                    CpuState::THUMB => self.reload_pipeline16(),
-                };
                 result = CpuAction::PipelineFlushed;
             }
         }
@@ -280,7 +299,7 @@ impl<I: MemoryInterface> Core<I> {
         let dest_reg = insn.bit_range(12..16) as usize;
         let mut addr = self.get_reg(base_reg);
         if base_reg == REG_PC {
-            addr = self.pc_arm() + 8; // prefetching
+            addr = self.pc_operand();
         }
         let mut offset = insn.bit_range(0..12);
         if SHIFT {
@@ -317,12 +336,11 @@ impl<I: MemoryInterface> Core<I> {
             self.idle_cycle();
 
             if dest_reg == REG_PC {
-                self.reload_pipeline32();
                 result = CpuAction::PipelineFlushed;
             }
         } else {
             let value = if dest_reg == REG_PC {
-                self.pc_arm() + 12
+                self.pc_operand_late()
             } else {
                 self.get_reg(dest_reg)
             };
@@ -399,15 +417,11 @@ impl<I: MemoryInterface> Core<I> {
         let dest_reg = insn.bit_range(12..16) as usize;
         let mut addr = self.get_reg(base_reg);
         if base_reg == REG_PC {
-            addr = self.pc_arm() + 8; // prefetching
-        }
-
-        // TODO - confirm this
-        let old_mode = self.cpsr.mode();
-        if !PRE_INDEX && WRITEBACK {
-            self.change_mode(old_mode, CpuMode::User);
+            addr = self.pc_operand();
         }
 
+        // Unlike LDRT/STRT, post-indexed halfword transfers have no user mode variant, the W bit
+        // is ignored since they always write back
         let effective_addr = (addr as i32).wrapping_add(offset as i32) as Addr;
         addr = if PRE_INDEX { effective_addr } else { addr };
 
@@ -426,12 +440,11 @@ impl<I: MemoryInterface> Core<I> {
             self.idle_cycle();
 
             if dest_reg == REG_PC {
-                self.reload_pipeline32();
                 result = CpuAction::PipelineFlushed;
             }
         } else {
             let value = if dest_reg == REG_PC {
-                self.pc_arm() + 12
+                self.pc_operand_late()
             } else {
                 self.get_reg(dest_reg)
             };
@@ -440,7 +453,8 @@ impl<I: MemoryInterface> Core<I> {
                 ArmHalfwordTransferType::UnsignedHalfwords => {
                     self.store_aligned_16(addr, value as u16, NonSeq);
                 }
-                _ => panic!("invalid HS flags for L=0"),
+                // the ARMv5 LDRD/STRD encodings
+                _ => self.unpredictable("signed halfword or byte store"),
             };
         }
 
@@ -475,15 +489,6 @@ impl<I: MemoryInterface> Core<I> {
 
         let rlist = insn.register_list();
 
-        if FLAG_S {
-            match self.cpsr.mode() {
-                CpuMode::User | CpuMode::System => {
-                    panic!("LDM/STM with S bit in unprivileged mode")
-                }
-                _ => {}
-            };
-        }
-
         let user_bank_transfer = if FLAG_S {
             if LOAD {
                 !rlist.bit(REG_PC)
@@ -499,7 +504,9 @@ impl<I: MemoryInterface> Core<I> {
             self.change_mode(old_mode, CpuMode::User);
         }
 
-        let psr_transfer = FLAG_S & LOAD & rlist.bit(REG_PC);
+        // in User and System mode the S bit changes nothing, their bank is the user bank and they
+        // have no SPSR
+        let psr_transfer = FLAG_S & LOAD & rlist.bit(REG_PC) & old_mode.has_spsr();
 
         let rlist_count = rlist.count_ones();
 
@@ -529,14 +536,14 @@ impl<I: MemoryInterface> Core<I> {
                         }
                         let val = self.load_32(addr, access);
                         access = Seq;
-                        self.set_reg(r, val);
                         if r == REG_PC {
+                            // restore the cpsr first, the pc is aligned for the state returned to
                             if psr_transfer {
                                 self.transfer_spsr_mode();
                             }
-                            self.reload_pipeline32();
                             result = CpuAction::PipelineFlushed;
                         }
+                        self.set_reg(r, val);
                         if !full {
                             addr = addr.wrapping_add(4);
                         }
@@ -550,7 +557,7 @@ impl<I: MemoryInterface> Core<I> {
                     if rlist.bit(r) {
                         let val = if r != base_reg {
                             if r == REG_PC {
-                                self.pc_arm() + 12
+                                self.pc_operand_late()
                             } else {
                                 self.get_reg(r)
                             }
@@ -585,7 +592,6 @@ impl<I: MemoryInterface> Core<I> {
             if LOAD {
                 let val = self.ldr_word(addr, NonSeq);
                 self.set_reg(REG_PC, val & !3);
-                self.reload_pipeline32();
                 result = CpuAction::PipelineFlushed;
             } else {
                 // block data store with empty rlist
@@ -595,7 +601,7 @@ impl<I: MemoryInterface> Core<I> {
                     (true, false) => addr,
                     (true, true) => addr.wrapping_add(4),
                 };
-                self.store_aligned_32(addr, self.pc + 4, NonSeq);
+                self.store_aligned_32(addr, self.pc_operand_late(), NonSeq);
             }
             addr = if ascending {
                 addr.wrapping_add(0x40)
@@ -725,7 +731,6 @@ impl<I: MemoryInterface> Core<I> {
     /// ARM Software Interrupt
     /// Execution Time: 2S+1N
     pub fn exec_arm_swi(&mut self, insn: u32) -> CpuAction {
-        self.software_interrupt(self.pc - 4, insn.swi_comment()); // Implies 2S + 1N
-        CpuAction::PipelineFlushed
+        self.software_interrupt(self.pc - 4, insn.swi_comment()) // Implies 2S + 1N
     }
 }
@@ -84,42 +84,26 @@ pub enum ArmFormat {
     Undefined,
 }
 
+// ARM_FORMAT_LUT, the format by bits 27-20 and 7-4, None where the other bits matter too, and
+// ARM_FORMAT_PATTERNS, the masks and values both the LUT and `match_patterns` are built on
+include!(concat!(env!("OUT_DIR"), "/arm_format_lut.rs"));
+
 impl From<u32> for ArmFormat {
     fn from(raw: u32) -> ArmFormat {
-        use ArmFormat::*;
-        if (0x0fff_fff0 & raw) == 0x012f_ff10 {
-            BranchExchange
-        } else if (0x0e00_0000 & raw) == 0x0a00_0000 {
-            BranchLink
-        } else if (0xe000_0010 & raw) == 0x0600_0000 {
-            Undefined
-        } else if (0x0fb0_0ff0 & raw) == 0x0100_0090 {
-            SingleDataSwap
-        } else if (0x0fc0_00f0 & raw) == 0x0000_0090 {
-            Multiply
-        } else if (0x0f80_00f0 & raw) == 0x0080_0090 {
-            MultiplyLong
-        } else if (0x0fbf_0fff & raw) == 0x010f_0000 {
-            MoveFromStatus
-        } else if (0x0fbf_fff0 & raw) == 0x0129_f000 {
-            MoveToStatus
-        } else if (0x0dbf_f000 & raw) == 0x0128_f000 {
-            MoveToFlags
-        } else if (0x0c00_0000 & raw) == 0x0400_0000 {
-            SingleDataTransfer
-        } else if (0x0e40_0F90 & raw) == 0x0000_0090 {
-            HalfwordDataTransferRegOffset
-        } else if (0x0e40_0090 & raw) == 0x0040_0090 {
-            HalfwordDataTransferImmediateOffset
-        } else if (0x0e00_0000 & raw) == 0x0800_0000 {
-            BlockDataTransfer
-        } else if (0x0f00_0000 & raw) == 0x0f00_0000 {
-            SoftwareInterrupt
-        } else if (0x0c00_0000 & raw) == 0x0000_0000 {
-            DataProcessing
-        } else {
-            Undefined
+        let index = (((raw >> 16) & 0xff0) | ((raw >> 4) & 0xf)) as usize;
+        ARM_FORMAT_LUT[index].unwrap_or_else(|| ArmFormat::match_patterns(raw))
+    }
+}
+
+impl ArmFormat {
+    /// The first of `ARM_FORMAT_PATTERNS` that matches `raw`
+    fn match_patterns(raw: u32) -> ArmFormat {
+        for &(mask, value, fmt) in ARM_FORMAT_PATTERNS.iter() {
+            if raw & mask == value {
+                return fmt;
+            }
         }
+        ArmFormat::Undefined
     }
 }
 
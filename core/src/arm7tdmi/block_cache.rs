@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use super::{Addr, CpuState};
+
+/// Blocks never cross one of these, so a single write generation tells whether a block is stale
+pub const CODE_PAGE_SIZE: u32 = 0x100;
+/// The cache is dropped when it grows past this many blocks, games only run a few thousand
+const MAX_BLOCKS: usize = 0x4000;
+
+/// An opcode along with its index in `ARM_LUT` or `THUMB_LUT`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CachedInsn {
+    pub opcode: u32,
+    pub lut_index: u16,
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    start: Addr,
+    state: CpuState,
+    /// The write generation of the code page when the block was decoded
+    generation: u32,
+    insns: Vec<CachedInsn>,
+}
+
+impl Block {
+    fn addr_of(&self, pos: usize) -> Addr {
+        let size = match self.state {
+            CpuState::ARM => 4,
+            CpuState::THUMB => 2,
+        };
+        self.start.wrapping_add((pos * size) as u32)
+    }
+}
+
+/// The decoded instructions of straight-line code, keyed by their address and cpu state.
+///
+/// A block starts wherever sequential fetching starts (after a branch), and grows one
+/// instruction at a time as the code runs for the first time, up to the end of its code page.
+/// Running it again takes the opcodes from the block instead of reading and decoding them.
+/// Every fetch still goes through the bus to charge its waitstates, and instructions still
+/// execute one at a time, so the timing is the same with or without the cache.
+///
+/// The memory interface gives a write generation for each code page (see
+/// `MemoryInterface::code_generation`), a block decoded with another generation is decoded again.
+#[derive(Debug, Clone, Default)]
+pub struct BlockCache {
+    blocks: Vec<Block>,
+    index: HashMap<(Addr, CpuState), usize>,
+    /// The block and position of the next sequential fetch
+    cursor: Option<(usize, usize)>,
+}
+
+impl BlockCache {
+    /// Returns the cached instruction at `addr`, or `None` if it has to be fetched and then
+    /// passed to `record`
+    #[inline]
+    pub fn fetch(&mut self, addr: Addr, state: CpuState, generation: u32) -> Option<CachedInsn> {
+        let (block, pos) = self.seek(addr, state);
+        let cached = &mut self.blocks[block];
+        if cached.generation != generation {
+            cached.generation = generation;
+            cached.insns.clear();
+            if pos != 0 {
+                // the start of the block is stale too, it is decoded again the next time it runs
+                self.cursor = None;
+                return None;
+            }
+        }
+        match cached.insns.get(pos) {
+            Some(&insn) => {
+                self.cursor = Some((block, pos + 1));
+                Some(insn)
+            }
+            None => {
+                self.cursor = Some((block, pos));
+                None
+            }
+        }
+    }
+
+    /// Appends the instruction fetched after `fetch` missed to its block
+    #[inline]
+    pub fn record(&mut self, insn: CachedInsn) {
+        if let Some((block, pos)) = self.cursor {
+            let insns = &mut self.blocks[block].insns;
+            if insns.len() == pos {
+                insns.push(insn);
+                self.cursor = Some((block, pos + 1));
+            }
+        }
+    }
+
+    /// Forgets the position of the next fetch, for code that can't be cached
+    #[inline]
+    pub fn skip(&mut self) {
+        self.cursor = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.index.clear();
+        self.cursor = None;
+    }
+
+    /// The block that continues to `addr`, or the one starting there
+    fn seek(&mut self, addr: Addr, state: CpuState) -> (usize, usize) {
+        if let Some((block, pos)) = self.cursor {
+            let cached = &self.blocks[block];
+            let same_page = cached.start / CODE_PAGE_SIZE == addr / CODE_PAGE_SIZE;
+            if cached.state == state && same_page && cached.addr_of(pos) == addr {
+                return (block, pos);
+            }
+        }
+        if let Some(&block) = self.index.get(&(addr, state)) {
+            return (block, 0);
+        }
+        if self.blocks.len() >= MAX_BLOCKS {
+            self.clear();
+        }
+        self.blocks.push(Block {
+            start: addr,
+            state,
+            generation: 0,
+            insns: Vec::new(),
+        });
+        let block = self.blocks.len() - 1;
+        self.index.insert((addr, state), block);
+        (block, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insn(opcode: u32) -> CachedInsn {
+        CachedInsn {
+            opcode,
+            lut_index: 0,
+        }
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let mut cache = BlockCache::default();
+        for (i, addr) in (0x0800_0000..0x0800_000c).step_by(4).enumerate() {
+            assert_eq!(cache.fetch(addr, CpuState::ARM, 0), None);
+            cache.record(insn(i as u32));
+        }
+        // branch back to the start
+        for (i, addr) in (0x0800_0000..0x0800_000c).step_by(4).enumerate() {
+            assert_eq!(cache.fetch(addr, CpuState::ARM, 0), Some(insn(i as u32)));
+        }
+        // into the middle of the block, a new one starts there
+        assert_eq!(cache.fetch(0x0800_0004, CpuState::ARM, 0), None);
+        // the same address in THUMB state is other code
+        assert_eq!(cache.fetch(0x0800_0000, CpuState::THUMB, 0), None);
+    }
+
+    #[test]
+    fn test_block_cache_generation() {
+        let mut cache = BlockCache::default();
+        assert_eq!(cache.fetch(0x0300_0000, CpuState::THUMB, 1), None);
+        cache.record(insn(0x2001));
+        assert_eq!(
+            cache.fetch(0x0300_0000, CpuState::THUMB, 1),
+            Some(insn(0x2001))
+        );
+        // the page was written since
+        assert_eq!(cache.fetch(0x0300_0000, CpuState::THUMB, 2), None);
+        cache.record(insn(0x2002));
+        assert_eq!(
+            cache.fetch(0x0300_0000, CpuState::THUMB, 2),
+            Some(insn(0x2002))
+        );
+    }
+
+    #[test]
+    fn test_block_cache_page_boundary() {
+        let mut cache = BlockCache::default();
+        assert_eq!(cache.fetch(0x0300_00fe, CpuState::THUMB, 0), None);
+        cache.record(insn(1));
+        // the next page is a block of its own
+        assert_eq!(cache.fetch(0x0300_0100, CpuState::THUMB, 0), None);
+        cache.record(insn(2));
+        assert_eq!(cache.fetch(0x0300_00fe, CpuState::THUMB, 0), Some(insn(1)));
+        assert_eq!(cache.fetch(0x0300_0100, CpuState::THUMB, 0), Some(insn(2)));
+    }
+}
@@ -2,11 +2,15 @@ use serde::{Deserialize, Serialize};
 
 pub use super::exception::Exception;
 
-use super::{arm::ArmCond, psr::RegPSR, Addr, CpuMode, CpuState};
+use super::{arm::ArmCond, psr::RegPSR, Addr, CpuError, CpuMode, CpuState};
 
 use crate::util::{Shared, WeakPointer};
 
+use super::block_cache::{BlockCache, CachedInsn};
+#[cfg(feature = "jit")]
+use super::jit::Jit;
 use super::memory::{MemoryAccess, MemoryInterface};
+use super::watchdog::Watchdog;
 use MemoryAccess::*;
 
 use cfg_if::cfg_if;
@@ -37,7 +41,14 @@ cfg_if! {
         use super::arm::ArmInstruction;
         use super::thumb::ThumbInstruction;
         use super::reg_string;
+        use super::history::{HistoryEntry, InstructionHistory};
+        use super::hooks::Watchpoints;
+        use super::step_report::MemoryWrite;
+        use super::trace::InstructionTrace;
+        use crate::logging;
+        use std::cell::RefCell;
         use std::fmt;
+        use std::rc::Rc;
 
         use ansi_term::{Colour, Style};
     } else {
@@ -45,11 +56,10 @@ cfg_if! {
     }
 }
 
-use bit::BitIndex;
-use num::FromPrimitive;
-
 pub enum CpuAction {
     AdvancePC(MemoryAccess),
+    /// The instruction wrote to the pc, `step` refills the pipeline from the new pc once the
+    /// instruction completed
     PipelineFlushed,
 }
 
@@ -75,6 +85,8 @@ pub struct SavedCpuState {
     pub(super) spsr: RegPSR,
 
     pub(super) banks: BankedRegisters,
+
+    hle_intr_waiting: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -84,9 +96,20 @@ pub struct DebuggerState {
     /// store the gpr before executing an instruction to show diff in the Display impl
     pub gpr_previous: [u32; 15],
     pub breakpoints: Vec<u32>,
+    pub watchpoints: Watchpoints,
     pub verbose: bool,
     pub trace_opcodes: bool,
     pub trace_exceptions: bool,
+    pub trace_transitions: bool,
+    /// The last executed instructions, dumped when something goes wrong
+    pub history: InstructionHistory,
+    /// A copy of `history` taken when the last error was raised, the cpu keeps running until the
+    /// frontend gets to `take_error`
+    pub error_history: Option<InstructionHistory>,
+    /// The memory writes of the instruction being executed by `step_debug`
+    pub(super) step_writes: Option<Vec<MemoryWrite>>,
+    /// Writes every executed instruction that passes its filter to a file, see `Core::set_trace`
+    pub trace: Option<Rc<RefCell<InstructionTrace>>>,
 }
 
 #[cfg(feature = "debugger")]
@@ -96,9 +119,15 @@ impl Default for DebuggerState {
             last_executed: None,
             gpr_previous: [0; 15],
             breakpoints: Vec::new(),
+            watchpoints: Watchpoints::default(),
             verbose: false,
             trace_opcodes: false,
             trace_exceptions: false,
+            trace_transitions: false,
+            history: InstructionHistory::default(),
+            error_history: None,
+            step_writes: None,
+            trace: None,
         }
     }
 }
@@ -110,6 +139,8 @@ pub struct Core<I: MemoryInterface> {
 
     next_fetch_access: MemoryAccess,
     pipeline: [u32; 2],
+    /// The ARM_LUT or THUMB_LUT index of each opcode in `pipeline`
+    pipeline_lut: [u16; 2],
     pub gpr: [u32; 15],
 
     pub cpsr: RegPSR,
@@ -117,10 +148,65 @@ pub struct Core<I: MemoryInterface> {
 
     pub(super) banks: BankedRegisters,
 
+    block_cache: BlockCache,
+    #[cfg(feature = "jit")]
+    jit: Jit,
+
+    /// Software interrupts are high level emulated instead of entering the bios
+    hle_swi: bool,
+    /// Set while an emulated IntrWait is waiting for its interrupt
+    pub(super) hle_intr_waiting: bool,
+
+    /// The first error encountered since the last call to `take_error`
+    error: Option<CpuError>,
+    watchdog: Watchdog,
+
     #[cfg(feature = "debugger")]
     pub dbg: DebuggerState,
 }
 
+/// For every condition code, a bitmask of the NZCV values (cpsr bits 31-28) that pass it.
+/// The Invalid condition (0b1111) never passes.
+const COND_LUT: [u16; 16] = build_cond_lut();
+
+const fn build_cond_lut() -> [u16; 16] {
+    let mut lut = [0u16; 16];
+    let mut cond = 0;
+    while cond < 16 {
+        let mut nzcv = 0;
+        while nzcv < 16 {
+            let n = nzcv & 0b1000 != 0;
+            let z = nzcv & 0b0100 != 0;
+            let c = nzcv & 0b0010 != 0;
+            let v = nzcv & 0b0001 != 0;
+            let pass = match cond {
+                0b0000 => z,
+                0b0001 => !z,
+                0b0010 => c,
+                0b0011 => !c,
+                0b0100 => n,
+                0b0101 => !n,
+                0b0110 => v,
+                0b0111 => !v,
+                0b1000 => c && !z,
+                0b1001 => !c || z,
+                0b1010 => n == v,
+                0b1011 => n != v,
+                0b1100 => !z && (n == v),
+                0b1101 => z || (n != v),
+                0b1110 => true,
+                _ => false,
+            };
+            if pass {
+                lut[cond] |= 1 << nzcv;
+            }
+            nzcv += 1;
+        }
+        cond += 1;
+    }
+    lut
+}
+
 impl<I: MemoryInterface> Core<I> {
     pub fn new(bus: Shared<I>) -> Core<I> {
         let cpsr = RegPSR::new(0x0000_00D3);
@@ -129,10 +215,18 @@ impl<I: MemoryInterface> Core<I> {
             pc: 0,
             gpr: [0; 15],
             pipeline: [0; 2],
+            pipeline_lut: [0; 2],
             next_fetch_access: MemoryAccess::NonSeq,
             cpsr,
             spsr: Default::default(),
             banks: BankedRegisters::default(),
+            block_cache: BlockCache::default(),
+            #[cfg(feature = "jit")]
+            jit: Jit::default(),
+            hle_swi: false,
+            hle_intr_waiting: false,
+            error: None,
+            watchdog: Watchdog::default(),
 
             #[cfg(feature = "debugger")]
             dbg: DebuggerState::default(),
@@ -144,7 +238,7 @@ impl<I: MemoryInterface> Core<I> {
     }
 
     pub fn from_saved_state(bus: Shared<I>, state: SavedCpuState) -> Core<I> {
-        Core {
+        let mut core = Core {
             bus,
 
             pc: state.pc,
@@ -154,12 +248,22 @@ impl<I: MemoryInterface> Core<I> {
             spsr: state.spsr,
 
             pipeline: state.pipeline,
+            pipeline_lut: [0; 2],
             next_fetch_access: state.next_fetch_access,
+            block_cache: BlockCache::default(),
+            #[cfg(feature = "jit")]
+            jit: Jit::default(),
+            hle_swi: false,
+            hle_intr_waiting: state.hle_intr_waiting,
+            error: None,
+            watchdog: Watchdog::default(),
 
             // savestate does not keep debugger related information, so just reinitialize to default
             #[cfg(feature = "debugger")]
             dbg: DebuggerState::default(),
-        }
+        };
+        core.decode_pipeline();
+        core
     }
 
     pub fn save_state(&self) -> SavedCpuState {
@@ -171,6 +275,7 @@ impl<I: MemoryInterface> Core<I> {
             banks: self.banks.clone(),
             pipeline: self.pipeline.clone(),
             next_fetch_access: self.next_fetch_access,
+            hle_intr_waiting: self.hle_intr_waiting,
         }
     }
 
@@ -182,12 +287,39 @@ impl<I: MemoryInterface> Core<I> {
         self.banks = state.banks;
         self.pipeline = state.pipeline;
         self.next_fetch_access = state.next_fetch_access;
+        self.hle_intr_waiting = state.hle_intr_waiting;
+        self.decode_pipeline();
+        self.block_cache.clear();
+        #[cfg(feature = "jit")]
+        self.jit.clear();
     }
 
     pub fn set_memory_interface(&mut self, i: Shared<I>) {
         self.bus = i;
     }
 
+    /// Enables high level emulation of the bios software interrupts, for when there is no bios to run
+    pub fn set_hle_swi(&mut self, enabled: bool) {
+        self.hle_swi = enabled;
+    }
+
+    pub fn hle_swi(&self) -> bool {
+        self.hle_swi
+    }
+
+    /// Runs the ARM code that only computes registers as compiled code (see `jit`), on hosts
+    /// the jit supports. The instructions of a compiled run execute in a single `step`, so the
+    /// debugger doesn't see them one by one.
+    #[cfg(feature = "jit")]
+    pub fn set_jit_enabled(&mut self, enabled: bool) {
+        self.jit.set_enabled(enabled);
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn jit(&self) -> &Jit {
+        &self.jit
+    }
+
     #[cfg(feature = "debugger")]
     pub fn set_verbose(&mut self, v: bool) {
         self.dbg.verbose = v;
@@ -213,6 +345,22 @@ impl<I: MemoryInterface> Core<I> {
         self.pc.wrapping_sub(4)
     }
 
+    /// The value of R15 as an operand. While an instruction executes, `pc` is the address of the
+    /// fetch stage, two instructions past the execute stage (see `step`), so R15 reads as the
+    /// address of the instruction + 8 in ARM state and + 4 in THUMB state.
+    #[inline]
+    pub fn pc_operand(&self) -> u32 {
+        self.pc
+    }
+
+    /// The value of R15 read in the second cycle of an instruction, once the next opcode has been
+    /// fetched: one instruction past `pc_operand`. Register specified shifts read their operands
+    /// then, and STR/STM store this value on the ARM7TDMI (other ARM cores may differ).
+    #[inline]
+    pub fn pc_operand_late(&self) -> u32 {
+        self.pc.wrapping_add(self.word_size() as u32)
+    }
+
     pub fn get_reg_user(&mut self, r: usize) -> u32 {
         match r {
             0..=7 => self.gpr[r],
@@ -303,6 +451,7 @@ impl<I: MemoryInterface> Core<I> {
     /// Resets the cpu
     pub fn reset(&mut self) {
         self.exception(Exception::Reset, 0);
+        self.reload_pipeline32();
     }
 
     pub fn word_size(&self) -> usize {
@@ -326,39 +475,74 @@ impl<I: MemoryInterface> Core<I> {
 
     #[inline(always)]
     pub(super) fn check_arm_cond(&self, cond: ArmCond) -> bool {
-        use ArmCond::*;
-        match cond {
-            Invalid => {
-                // TODO - we would normally want to panic here
-                false
-            }
-            EQ => self.cpsr.Z(),
-            NE => !self.cpsr.Z(),
-            HS => self.cpsr.C(),
-            LO => !self.cpsr.C(),
-            MI => self.cpsr.N(),
-            PL => !self.cpsr.N(),
-            VS => self.cpsr.V(),
-            VC => !self.cpsr.V(),
-            HI => self.cpsr.C() && !self.cpsr.Z(),
-            LS => !self.cpsr.C() || self.cpsr.Z(),
-            GE => self.cpsr.N() == self.cpsr.V(),
-            LT => self.cpsr.N() != self.cpsr.V(),
-            GT => !self.cpsr.Z() && (self.cpsr.N() == self.cpsr.V()),
-            LE => self.cpsr.Z() || (self.cpsr.N() != self.cpsr.V()),
-            AL => true,
-        }
+        Self::check_cond_bits(cond as u32, self.cpsr.get())
+    }
+
+    /// Checks a raw 4bit condition field against the NZCV flags of `cpsr` with a single table lookup
+    #[inline(always)]
+    fn check_cond_bits(cond: u32, cpsr: u32) -> bool {
+        COND_LUT[cond as usize] & (1 << (cpsr >> 28)) != 0
     }
 
     #[cfg(feature = "debugger")]
     fn debugger_record_step(&mut self, d: DecodedInstruction) {
         self.dbg.gpr_previous = self.get_registers();
+        self.dbg.history.push(HistoryEntry {
+            insn: d.clone(),
+            gpr: self.dbg.gpr_previous,
+            cpsr: self.cpsr,
+        });
+        let traced = match &self.dbg.trace {
+            Some(trace) => trace.borrow_mut().record(
+                self.bus.cycles(),
+                &d,
+                &self.dbg.gpr_previous,
+                self.pc_operand(),
+                self.cpsr,
+            ),
+            None => Ok(()),
+        };
+        if let Err(e) = traced {
+            error!(target: logging::CPU, "instruction trace stopped: {}", e);
+            self.dbg.trace = None;
+        }
         self.dbg.last_executed = Some(d);
     }
 
-    fn step_arm_exec(&mut self, insn: u32) -> CpuAction {
-        let hash = (((insn >> 16) & 0xff0) | ((insn >> 4) & 0xf)) as usize;
-        let arm_info = &Self::ARM_LUT[hash];
+    /// Starts writing the executed instructions to `trace`, or stops with `None`.
+    /// The previous trace is flushed, and closed unless it is still referenced elsewhere.
+    #[cfg(feature = "debugger")]
+    pub fn set_trace(&mut self, trace: Option<InstructionTrace>) {
+        if let Some(previous) = self.dbg.trace.take() {
+            if let Err(e) = previous.borrow_mut().flush() {
+                error!(target: logging::CPU, "failed to flush the instruction trace: {}", e);
+            }
+        }
+        self.dbg.trace = trace.map(|trace| Rc::new(RefCell::new(trace)));
+    }
+
+    #[inline(always)]
+    fn arm_lut_index(insn: u32) -> u16 {
+        (((insn >> 16) & 0xff0) | ((insn >> 4) & 0xf)) as u16
+    }
+
+    #[inline(always)]
+    fn thumb_lut_index(insn: u16) -> u16 {
+        insn >> 6
+    }
+
+    /// Looks up the handlers of the opcodes in the pipeline, for when it was set from outside
+    fn decode_pipeline(&mut self) {
+        for i in 0..2 {
+            self.pipeline_lut[i] = match self.cpsr.state() {
+                CpuState::ARM => Self::arm_lut_index(self.pipeline[i]),
+                CpuState::THUMB => Self::thumb_lut_index(self.pipeline[i] as u16),
+            };
+        }
+    }
+
+    fn step_arm_exec(&mut self, insn: u32, lut_index: u16) -> CpuAction {
+        let arm_info = &Self::ARM_LUT[lut_index as usize];
         #[cfg(feature = "debugger")]
         self.debugger_record_step(DecodedInstruction::Arm(ArmInstruction::new(
             insn,
@@ -368,8 +552,8 @@ impl<I: MemoryInterface> Core<I> {
         (arm_info.handler_fn)(self, insn)
     }
 
-    fn step_thumb_exec(&mut self, insn: u16) -> CpuAction {
-        let thumb_info = &Self::THUMB_LUT[(insn >> 6) as usize];
+    fn step_thumb_exec(&mut self, insn: u16, lut_index: u16) -> CpuAction {
+        let thumb_info = &Self::THUMB_LUT[lut_index as usize];
         #[cfg(feature = "debugger")]
         self.debugger_record_step(DecodedInstruction::Thumb(ThumbInstruction::new(
             insn,
@@ -379,24 +563,36 @@ impl<I: MemoryInterface> Core<I> {
         (thumb_info.handler_fn)(self, insn)
     }
 
+    /// Refills the pipeline from `pc` in the current state
+    /// 2S + 1N
+    #[inline]
+    pub fn reload_pipeline(&mut self) {
+        match self.cpsr.state() {
+            CpuState::ARM => self.reload_pipeline32(),
+            CpuState::THUMB => self.reload_pipeline16(),
+        }
+    }
+
     /// 2S + 1N
     #[inline(always)]
     pub fn reload_pipeline16(&mut self) {
-        self.pipeline[0] = self.load_16(self.pc, NonSeq) as u32;
+        self.pipeline[0] = self.bus.fetch_16(self.pc, NonSeq) as u32;
         self.advance_thumb();
-        self.pipeline[1] = self.load_16(self.pc, Seq) as u32;
+        self.pipeline[1] = self.bus.fetch_16(self.pc, Seq) as u32;
         self.advance_thumb();
         self.next_fetch_access = Seq;
+        self.decode_pipeline();
     }
 
     /// 2S + 1N
     #[inline(always)]
     pub fn reload_pipeline32(&mut self) {
-        self.pipeline[0] = self.load_32(self.pc, NonSeq);
+        self.pipeline[0] = self.bus.fetch_32(self.pc, NonSeq);
         self.advance_arm();
-        self.pipeline[1] = self.load_32(self.pc, Seq);
+        self.pipeline[1] = self.bus.fetch_32(self.pc, Seq);
         self.advance_arm();
         self.next_fetch_access = Seq;
+        self.decode_pipeline();
     }
 
     #[inline]
@@ -419,53 +615,247 @@ impl<I: MemoryInterface> Core<I> {
         self.pipeline[1]
     }
 
+    /// Moves the pipeline one stage forward: the decoded opcode goes to the execute stage and is
+    /// returned with its LUT index, the prefetched opcode is decoded and `fetched` takes the
+    /// fetch stage.
+    #[inline(always)]
+    fn advance_pipeline(&mut self, fetched: CachedInsn) -> (u32, u16) {
+        let executed = (self.pipeline[0], self.pipeline_lut[0]);
+        self.pipeline[0] = self.pipeline[1];
+        self.pipeline_lut[0] = self.pipeline_lut[1];
+        self.pipeline[1] = fetched.opcode;
+        self.pipeline_lut[1] = fetched.lut_index;
+        executed
+    }
+
+    /// Fetches the opcode at `pc` for the fetch stage along with its LUT index, from the block
+    /// cache when the code there didn't change since it was decoded
+    #[inline(always)]
+    fn fetch_arm(&mut self, pc: Addr) -> CachedInsn {
+        let access = self.next_fetch_access;
+        let generation = self.bus.code_generation(pc);
+        if let Some(generation) = generation {
+            if let Some(cached) = self.block_cache.fetch(pc, CpuState::ARM, generation) {
+                self.bus.fetch_cycles_32(pc, access);
+                return cached;
+            }
+        } else {
+            self.block_cache.skip();
+        }
+        let opcode = self.bus.fetch_32(pc, access);
+        let fetched = CachedInsn {
+            opcode,
+            lut_index: Self::arm_lut_index(opcode),
+        };
+        if generation.is_some() {
+            self.block_cache.record(fetched);
+        }
+        fetched
+    }
+
+    /// See `fetch_arm`
+    #[inline(always)]
+    fn fetch_thumb(&mut self, pc: Addr) -> CachedInsn {
+        let access = self.next_fetch_access;
+        let generation = self.bus.code_generation(pc);
+        if let Some(generation) = generation {
+            if let Some(cached) = self.block_cache.fetch(pc, CpuState::THUMB, generation) {
+                self.bus.fetch_cycles_16(pc, access);
+                return cached;
+            }
+        } else {
+            self.block_cache.skip();
+        }
+        let opcode = self.bus.fetch_16(pc, access);
+        let fetched = CachedInsn {
+            opcode: opcode as u32,
+            lut_index: Self::thumb_lut_index(opcode),
+        };
+        if generation.is_some() {
+            self.block_cache.record(fetched);
+        }
+        fetched
+    }
+
+    /// Runs the compiled run starting with the instruction in the execute stage, as far as
+    /// `MemoryInterface::can_run_ahead` allows. Returns `false` if there is none.
+    #[cfg(feature = "jit")]
+    fn run_compiled(&mut self) -> bool {
+        let pc = self.pc & !3;
+        let addr = pc.wrapping_sub(8);
+        let generation = match self.bus.code_generation(addr) {
+            Some(generation) => generation,
+            None => return false,
+        };
+        let (entry, len) = match self.jit.lookup(addr, generation) {
+            Some(run) => run,
+            None => return false,
+        };
+        // the pipeline was filled before the code was written
+        let start = self.jit.pipeline_after(addr, 0);
+        if start[0].opcode != self.pipeline[0] || start[1].opcode != self.pipeline[1] {
+            return false;
+        }
+        let mut access = self.next_fetch_access;
+        let mut count = 0;
+        while count < len {
+            // the caller of `step` checked before the first one
+            if count != 0 && !self.bus.can_run_ahead() {
+                break;
+            }
+            self.bus
+                .fetch_cycles_32(pc.wrapping_add(4 * count as u32), access);
+            access = Seq;
+            count += 1;
+        }
+        // the compiled code only accesses r0-r14
+        unsafe { entry(self.gpr.as_mut_ptr(), count as u32) };
+        let pipeline = self.jit.pipeline_after(addr, count);
+        for (i, insn) in pipeline.iter().enumerate() {
+            self.pipeline[i] = insn.opcode;
+            self.pipeline_lut[i] = insn.lut_index;
+        }
+        self.pc = pc.wrapping_add(4 * count as u32);
+        self.next_fetch_access = Seq;
+        self.block_cache.skip();
+        true
+    }
+
+    /// Lets the jit record the ARM instruction at `addr` the interpreter is about to execute
+    #[cfg(feature = "jit")]
+    fn jit_record(&mut self, addr: Addr, insn: u32, lut_index: u16) {
+        let generation = self.bus.code_generation(addr);
+        let stage = |i: usize| CachedInsn {
+            opcode: self.pipeline[i],
+            lut_index: self.pipeline_lut[i],
+        };
+        let pipeline = [stage(0), stage(1)];
+        let opcode = CachedInsn {
+            opcode: insn,
+            lut_index,
+        };
+        self.jit.record(addr, opcode, generation, pipeline);
+    }
+
     /// Perform a pipeline step
-    /// If an instruction was executed in this step, return it.
+    ///
+    /// `pipeline[0]` holds the decode stage and `pipeline[1]` the fetch stage. The opcode at `pc`
+    /// is fetched in the first cycle of the executed instruction, the pipeline advances and the
+    /// instruction in the execute stage runs. An instruction that writes the pc only reports
+    /// `PipelineFlushed`: the refill happens here once all of its cycles are done, in the state it
+    /// left the cpu in (BX, MOVS pc / LDM^ restoring a THUMB cpsr, exceptions).
     #[inline]
     pub fn step(&mut self) {
         match self.cpsr.state() {
             CpuState::ARM => {
+                #[cfg(feature = "jit")]
+                {
+                    if self.jit.is_enabled() && self.run_compiled() {
+                        return;
+                    }
+                }
                 let pc = self.pc & !3;
 
-                let fetched_now = self.load_32(pc, self.next_fetch_access);
-                let insn = self.pipeline[0];
-                self.pipeline[0] = self.pipeline[1];
-                self.pipeline[1] = fetched_now;
-                let cond = ArmCond::from_u8(insn.bit_range(28..32) as u8)
-                    .unwrap_or_else(|| unsafe { std::hint::unreachable_unchecked() });
-                if cond != ArmCond::AL {
-                    if !self.check_arm_cond(cond) {
+                // opcode fetches bypass the watchpoint checks of the data accesses
+                let fetched_now = self.fetch_arm(pc);
+                let (insn, lut_index) = self.advance_pipeline(fetched_now);
+                #[cfg(feature = "jit")]
+                {
+                    if self.jit.is_enabled() {
+                        self.jit_record(pc.wrapping_sub(8), insn, lut_index);
+                    }
+                }
+                let cond = insn >> 28;
+                if cond != ArmCond::AL as u32 {
+                    if !Self::check_cond_bits(cond, self.cpsr.get()) {
                         self.advance_arm();
                         self.next_fetch_access = MemoryAccess::NonSeq;
                         return;
                     }
                 }
-                match self.step_arm_exec(insn) {
+                match self.step_arm_exec(insn, lut_index) {
                     CpuAction::AdvancePC(access) => {
                         self.next_fetch_access = access;
                         self.advance_arm();
                     }
-                    CpuAction::PipelineFlushed => {}
+                    CpuAction::PipelineFlushed => {
+                        self.reload_pipeline();
+                        self.check_pc(pc.wrapping_sub(8));
+                    }
                 }
             }
             CpuState::THUMB => {
                 let pc = self.pc & !1;
 
-                let fetched_now = self.load_16(pc, self.next_fetch_access);
-                let insn = self.pipeline[0];
-                self.pipeline[0] = self.pipeline[1];
-                self.pipeline[1] = fetched_now as u32;
-                match self.step_thumb_exec(insn as u16) {
+                let fetched_now = self.fetch_thumb(pc);
+                let (insn, lut_index) = self.advance_pipeline(fetched_now);
+                match self.step_thumb_exec(insn as u16, lut_index) {
                     CpuAction::AdvancePC(access) => {
                         self.advance_thumb();
                         self.next_fetch_access = access;
                     }
-                    CpuAction::PipelineFlushed => {}
+                    CpuAction::PipelineFlushed => {
+                        self.reload_pipeline();
+                        self.check_pc(pc.wrapping_sub(4));
+                    }
                 }
             }
         }
     }
 
+    /// Called after the pipeline was flushed by the instruction at `previous_pc`,
+    /// to catch jumps into unmapped memory or to misaligned addresses.
+    #[inline]
+    fn check_pc(&mut self, previous_pc: Addr) {
+        let pc = self.get_next_pc();
+        let misaligned = pc & (self.word_size() as u32 - 1) != 0;
+        if misaligned || !self.bus.is_executable(pc) {
+            self.set_error(CpuError::InvalidPc {
+                pc,
+                previous_pc,
+                lr: self.gpr[14],
+            });
+        }
+        if let Some(targets) = self.watchdog.on_flush(pc) {
+            self.set_error(CpuError::Stuck {
+                targets,
+                flushes: self.watchdog.limit().unwrap(),
+            });
+        }
+    }
+
+    /// Keeps `error` unless an earlier one wasn't taken yet, along with the instructions that led
+    /// to it
+    fn set_error(&mut self, error: CpuError) {
+        if self.error.is_none() {
+            #[cfg(feature = "debugger")]
+            {
+                self.dbg.error_history = Some(self.dbg.history.clone());
+            }
+            self.error = Some(error);
+        }
+    }
+
+    /// Sets how many consecutive flushes between the same addresses are reported as
+    /// `CpuError::Stuck`, `None` (the default) disables the check
+    pub fn set_watchdog_limit(&mut self, limit: Option<u32>) {
+        self.watchdog.set_limit(limit);
+    }
+
+    /// Reports the execution of an arm instruction the ARM7TDMI doesn't define the behavior of,
+    /// as `CpuError::Unpredictable`
+    pub(super) fn unpredictable(&mut self, reason: &'static str) {
+        self.set_error(CpuError::Unpredictable {
+            pc: self.pc_arm(),
+            reason,
+        });
+    }
+
+    /// Returns and clears the last error
+    pub fn take_error(&mut self) -> Option<CpuError> {
+        self.error.take()
+    }
+
     /// Get's the address of the next instruction that is going to be executed
     pub fn get_next_pc(&self) -> Addr {
         let insn_size = self.word_size() as u32;
@@ -476,7 +866,10 @@ impl<I: MemoryInterface> Core<I> {
         self.cpsr.state()
     }
 
-    pub fn skip_bios(&mut self) {
+    /// Puts the cpu in the same state the bios leaves it in right before jumping to `entry_point`,
+    /// the cartridge or a multiboot image. An entry point with bit 0 set starts in THUMB state.
+    pub fn skip_bios(&mut self, entry_point: Addr) {
+        self.banks = BankedRegisters::default();
         self.banks.gpr_banked_r13[0] = 0x0300_7f00; // USR/SYS
         self.banks.gpr_banked_r13[1] = 0x0300_7f00; // FIQ
         self.banks.gpr_banked_r13[2] = 0x0300_7fa0; // IRQ
@@ -484,10 +877,20 @@ impl<I: MemoryInterface> Core<I> {
         self.banks.gpr_banked_r13[4] = 0x0300_7f00; // ABT
         self.banks.gpr_banked_r13[5] = 0x0300_7f00; // UND
 
+        self.gpr = [0; 15];
         self.gpr[13] = 0x0300_7f00;
-        self.pc = 0x0800_0000;
+        self.spsr = Default::default();
+        self.hle_intr_waiting = false;
+
+        // System mode, ARM state, IRQ and FIQ enabled
+        self.cpsr.set(0x1f);
+        if entry_point & 1 != 0 {
+            self.cpsr.set_state(CpuState::THUMB);
+        }
 
-        self.cpsr.set(0x5f);
+        // fill the pipeline, so the first executed instruction is the entry point
+        self.pc = entry_point & !1;
+        self.reload_pipeline();
     }
 }
 
@@ -35,21 +35,72 @@ where
     D: InstructionDecoder + fmt::Display,
     <D as InstructionDecoder>::IntType: std::fmt::LowerHex,
 {
+    /// The address of the instruction and its line, the two halves of a THUMB `bl` share one
+    /// like in GNU objdump
     type Item = (Addr, String);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut line = String::new();
-
-        let addr = self.base + self.pos as Addr;
-        let decoded: D = D::decode_from_bytes(&self.bytes[(self.pos as usize)..], addr);
+        if self.pos + self.word_size > self.bytes.len() {
+            return None;
+        }
+        let addr = self.base.wrapping_add(self.pos as Addr);
+        let decoded: D = D::decode_from_bytes(&self.bytes[self.pos..], addr);
         self.pos += self.word_size;
-        line.push_str(&format!(
-            "{:8x}:\t{:08x} \t{}",
-            addr,
-            decoded.get_raw(),
-            decoded
-        ));
-
-        Some((self.pos as Addr, line))
+
+        if self.pos + self.word_size <= self.bytes.len() {
+            let next_addr = addr.wrapping_add(self.word_size as Addr);
+            let next: D = D::decode_from_bytes(&self.bytes[self.pos..], next_addr);
+            if let Some(text) = decoded.fmt_pair(&next) {
+                self.pos += self.word_size;
+                let line = format!(
+                    "{:8x}:\t{:x} {:x} \t{}",
+                    addr,
+                    decoded.get_raw(),
+                    next.get_raw(),
+                    text
+                );
+                return Some((addr, line));
+            }
+        }
+
+        let line = format!("{:8x}:\t{:08x} \t{}", addr, decoded.get_raw(), decoded);
+        Some((addr, line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::thumb::ThumbInstruction;
+    use super::*;
+
+    // bl 0x8000108, movs r0, #0x27
+    const BYTES: [u8; 6] = [0x00, 0xf0, 0x82, 0xf8, 0x27, 0x20];
+
+    #[test]
+    fn test_long_branch_pair() {
+        let hi = ThumbInstruction::decode(0xf000, 0x0800_0000);
+        let lo = ThumbInstruction::decode(0xf882, 0x0800_0002);
+        assert_eq!(hi.long_branch_target(&lo), Some(0x0800_0108));
+        assert_eq!(hi.fmt_pair(&lo), Some("bl\t0x8000108".to_string()));
+        // the halves the other way around, or apart, are not a pair
+        assert_eq!(lo.fmt_pair(&hi), None);
+        let lo = ThumbInstruction::decode(0xf882, 0x0800_0004);
+        assert_eq!(hi.fmt_pair(&lo), None);
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn test_disassemble_long_branch() {
+        let lines: Vec<(Addr, String)> =
+            Disassembler::<ThumbInstruction>::new(0x0800_0000, &BYTES).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            (
+                0x0800_0000,
+                " 8000000:\tf000 f882 \tbl\t0x8000108".to_string()
+            )
+        );
+        assert_eq!(lines[1].0, 0x0800_0004);
     }
 }
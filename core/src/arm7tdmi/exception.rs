@@ -1,6 +1,7 @@
-use super::cpu::Core;
+use super::cpu::{Core, CpuAction};
 use super::memory::MemoryInterface;
 use super::{CpuMode, CpuState};
+use crate::logging;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
@@ -17,11 +18,12 @@ pub enum Exception {
 }
 
 impl<I: MemoryInterface> Core<I> {
+    /// Switches to the mode of `e` and sets the pc to its vector, the caller refills the pipeline
     pub fn exception(&mut self, e: Exception, lr: u32) {
         use Exception::*;
         let (new_mode, irq_disable, fiq_disable) = match e {
             Reset => (CpuMode::Supervisor, true, true),
-            UndefinedInstruction => (CpuMode::Undefined, false, false),
+            UndefinedInstruction => (CpuMode::Undefined, true, false),
             SoftwareInterrupt => (CpuMode::Supervisor, true, false),
             DataAbort => (CpuMode::Abort, false, false),
             PrefatchAbort => (CpuMode::Abort, false, false),
@@ -33,7 +35,11 @@ impl<I: MemoryInterface> Core<I> {
         #[cfg(feature = "debugger")]
         {
             if self.dbg.trace_exceptions {
-                trace!("exception {:?} lr={:x} new_mode={:?}", e, lr, new_mode);
+                trace!(
+                    target: logging::CPU,
+                    "exception {:?} lr={:x} new_mode={:?}",
+                    e, lr, new_mode
+                );
             }
         }
 
@@ -54,7 +60,6 @@ impl<I: MemoryInterface> Core<I> {
 
         // Set PC to vector address
         self.pc = e as u32;
-        self.reload_pipeline32();
     }
 
     #[inline]
@@ -62,11 +67,37 @@ impl<I: MemoryInterface> Core<I> {
         if !self.cpsr.irq_disabled() {
             let lr = self.get_next_pc() + 4;
             self.exception(Exception::Irq, lr);
+            self.reload_pipeline32();
         }
     }
 
+    /// `cmt` is the comment field of the swi instruction, which the bios uses as the function number
     #[inline]
-    pub fn software_interrupt(&mut self, lr: u32, _cmt: u32) {
+    pub fn software_interrupt(&mut self, lr: u32, cmt: u32) -> CpuAction {
+        if self.hle_swi() {
+            let function = match self.cpsr.state() {
+                CpuState::ARM => (cmt >> 16) as u8,
+                CpuState::THUMB => cmt as u8,
+            };
+            match self.hle_software_interrupt(function, lr) {
+                Some(action) => return action,
+                None => warn!(
+                    target: logging::CPU,
+                    "unsupported bios function {:#x} @{:08x}",
+                    function, lr
+                ),
+            }
+        }
         self.exception(Exception::SoftwareInterrupt, lr);
+        CpuAction::PipelineFlushed
+    }
+
+    /// Enters the undefined instruction vector, `lr` should point to the instruction following the undefined one.
+    /// The instruction reports `PipelineFlushed` so that `step` refills the pipeline from the vector
+    /// Execution Time: 2S+1I+1N
+    #[inline]
+    pub fn undefined_instruction(&mut self, lr: u32) {
+        self.idle_cycle();
+        self.exception(Exception::UndefinedInstruction, lr);
     }
 }
@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use log::Level;
+
+use super::{reg_string, DecodedInstruction, RegPSR};
+use crate::logging;
+
+pub const DEFAULT_HISTORY_SIZE: usize = 32;
+
+/// An executed instruction along with the register state it was executed with
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub insn: DecodedInstruction,
+    pub gpr: [u32; 15],
+    pub cpsr: RegPSR,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "@{:08x}:\t{}", self.insn.get_pc(), self.insn)?;
+        write!(f, "\t\tcpsr: {}", self.cpsr)?;
+        for (i, r) in self.gpr.iter().enumerate() {
+            if i % 5 == 0 {
+                write!(f, "\n\t\t")?;
+            }
+            write!(f, "{:>3}={:08x} ", reg_string(i), r)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer of the last executed instructions, for post-mortem diagnostics
+#[derive(Debug, Clone)]
+pub struct InstructionHistory {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl Default for InstructionHistory {
+    fn default() -> InstructionHistory {
+        InstructionHistory::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+impl InstructionHistory {
+    pub fn new(capacity: usize) -> InstructionHistory {
+        InstructionHistory {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Resizes the history, a capacity of 0 disables recording
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Iterates the history from the oldest to the most recent instruction
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    /// Logs the whole history as an error, most recent instruction last
+    pub fn dump(&self) {
+        self.log(Level::Error);
+    }
+
+    /// Logs the whole history at `level`, most recent instruction last
+    pub fn log(&self, level: Level) {
+        if !log_enabled!(target: logging::CPU, level) {
+            return;
+        }
+        log!(target: logging::CPU, level, "last {} executed instructions:", self.entries.len());
+        for entry in self.iter() {
+            log!(target: logging::CPU, level, "{}", entry);
+        }
+    }
+}
@@ -0,0 +1,475 @@
+/// High level emulation of the GBA bios functions, used when no bios image is available.
+///
+/// Only the software interrupts are emulated, the exception vectors are still served from memory
+/// (see `bios::hle_bios_image`).
+use std::f64::consts::PI;
+
+use super::cpu::{Core, CpuAction};
+use super::memory::{MemoryAccess, MemoryInterface};
+use super::Addr;
+use crate::logging;
+use MemoryAccess::*;
+
+const REG_IME: Addr = 0x0400_0208;
+const REG_HALTCNT: Addr = 0x0400_0301;
+/// The interrupt flags the user irq handler acknowledges for IntrWait
+const BIOS_IF: Addr = 0x0300_7ff8;
+
+const BIOS_CHECKSUM: u32 = 0xbaae_187f;
+
+impl<I: MemoryInterface> Core<I> {
+    /// Executes bios function `function` in place of the software interrupt exception.
+    /// Returns None if the function isn't supported.
+    pub(super) fn hle_software_interrupt(&mut self, function: u8, lr: Addr) -> Option<CpuAction> {
+        match function {
+            0x01 => self.hle_register_ram_reset(),
+            0x02 => self.store_8(REG_HALTCNT, 0, NonSeq),
+            0x03 => self.store_8(REG_HALTCNT, 0x80, NonSeq),
+            0x04 => return Some(self.hle_intr_wait(self.gpr[0] != 0, self.gpr[1] as u16, lr)),
+            0x05 => return Some(self.hle_intr_wait(true, 1, lr)),
+            0x06 => self.hle_div(self.gpr[0] as i32, self.gpr[1] as i32),
+            0x07 => self.hle_div(self.gpr[1] as i32, self.gpr[0] as i32),
+            0x08 => self.gpr[0] = isqrt(self.gpr[0]),
+            0x09 => self.gpr[0] = arctan(self.gpr[0] as i16 as i32) as u32,
+            0x0a => self.gpr[0] = arctan2(self.gpr[0] as i16 as i32, self.gpr[1] as i16 as i32),
+            0x0b => self.hle_cpu_set(),
+            0x0c => self.hle_cpu_fast_set(),
+            0x0d => self.gpr[0] = BIOS_CHECKSUM,
+            0x0e => self.hle_bg_affine_set(),
+            0x0f => self.hle_obj_affine_set(),
+            0x10 => self.hle_bit_unpack(),
+            0x11 => self.hle_decompress(lz77_decompress, false),
+            0x12 => self.hle_decompress(lz77_decompress, true),
+            // the output is written 32bit at a time, so it can go to vram as well
+            0x13 => self.hle_decompress(huff_decompress, true),
+            0x14 => self.hle_decompress(rl_decompress, false),
+            0x15 => self.hle_decompress(rl_decompress, true),
+            _ => return None,
+        }
+        Some(CpuAction::AdvancePC(Seq))
+    }
+
+    fn hle_register_ram_reset(&mut self) {
+        let flags = self.gpr[0];
+        // (flag bit, start, size), the top 0x200 bytes of iwram are kept for the stacks
+        let regions: [(u32, Addr, u32); 5] = [
+            (0, 0x0200_0000, 0x40000),
+            (1, 0x0300_0000, 0x7e00),
+            (2, 0x0500_0000, 0x400),
+            (3, 0x0600_0000, 0x18000),
+            (4, 0x0700_0000, 0x400),
+        ];
+        for &(bit, start, size) in regions.iter() {
+            if flags & (1 << bit) != 0 {
+                for offset in (0..size).step_by(4) {
+                    self.store_32(start + offset, 0, Seq);
+                }
+            }
+        }
+        if flags & 0xe0 != 0 {
+            warn!(
+                target: logging::CPU,
+                "RegisterRamReset: resetting io registers is not supported"
+            );
+        }
+    }
+
+    /// IntrWait and VBlankIntrWait
+    ///
+    /// The cpu is halted and the swi is executed again after every interrupt, until one of `flags`
+    /// is acknowledged by the user irq handler.
+    fn hle_intr_wait(&mut self, discard: bool, flags: u16, lr: Addr) -> CpuAction {
+        if discard && !self.hle_intr_waiting {
+            let bios_if = self.load_16(BIOS_IF, NonSeq);
+            self.store_16(BIOS_IF, bios_if & !flags, NonSeq);
+        }
+        self.store_16(REG_IME, 1, NonSeq);
+
+        let bios_if = self.load_16(BIOS_IF, NonSeq);
+        if bios_if & flags != 0 {
+            self.store_16(BIOS_IF, bios_if & !flags, NonSeq);
+            self.hle_intr_waiting = false;
+            CpuAction::AdvancePC(Seq)
+        } else {
+            self.hle_intr_waiting = true;
+            self.store_8(REG_HALTCNT, 0, NonSeq);
+            // rewind to the swi instruction, so the irq handler returns to it
+            self.pc = lr.wrapping_sub(self.word_size() as u32);
+            CpuAction::PipelineFlushed
+        }
+    }
+
+    fn hle_div(&mut self, number: i32, denom: i32) {
+        if denom == 0 {
+            // the real bios never returns from a division by zero
+            warn!(target: logging::CPU, "Div: division by zero ({} / 0)", number);
+            self.gpr[0] = if number < 0 { -1i32 as u32 } else { 1 };
+            self.gpr[1] = number as u32;
+            self.gpr[3] = 1;
+            return;
+        }
+        let quotient = number.wrapping_div(denom);
+        self.gpr[0] = quotient as u32;
+        self.gpr[1] = number.wrapping_rem(denom) as u32;
+        self.gpr[3] = quotient.wrapping_abs() as u32;
+    }
+
+    fn hle_cpu_set(&mut self) {
+        let mut src = self.gpr[0];
+        let mut dst = self.gpr[1];
+        let control = self.gpr[2];
+        let count = control & 0x1f_ffff;
+        let fill = control & (1 << 24) != 0;
+        if control & (1 << 26) != 0 {
+            src &= !3;
+            dst &= !3;
+            let value = self.load_32(src, NonSeq);
+            for _ in 0..count {
+                let value = if fill { value } else { self.load_32(src, Seq) };
+                self.store_32(dst, value, Seq);
+                if !fill {
+                    src = src.wrapping_add(4);
+                }
+                dst = dst.wrapping_add(4);
+            }
+        } else {
+            src &= !1;
+            dst &= !1;
+            let value = self.load_16(src, NonSeq);
+            for _ in 0..count {
+                let value = if fill { value } else { self.load_16(src, Seq) };
+                self.store_16(dst, value, Seq);
+                if !fill {
+                    src = src.wrapping_add(2);
+                }
+                dst = dst.wrapping_add(2);
+            }
+        }
+    }
+
+    fn hle_cpu_fast_set(&mut self) {
+        let mut src = self.gpr[0] & !3;
+        let mut dst = self.gpr[1] & !3;
+        let control = self.gpr[2];
+        // transfers are done in blocks of 8 words
+        let count = ((control & 0x1f_ffff) + 7) & !7;
+        let fill = control & (1 << 24) != 0;
+        let value = self.load_32(src, NonSeq);
+        for _ in 0..count {
+            let value = if fill { value } else { self.load_32(src, Seq) };
+            self.store_32(dst, value, Seq);
+            if !fill {
+                src = src.wrapping_add(4);
+            }
+            dst = dst.wrapping_add(4);
+        }
+    }
+
+    fn hle_bg_affine_set(&mut self) {
+        let mut src = self.gpr[0];
+        let mut dst = self.gpr[1];
+        for _ in 0..self.gpr[2] {
+            let ox = self.load_32(src, NonSeq) as i32 as f64 / 256.0;
+            let oy = self.load_32(src.wrapping_add(4), NonSeq) as i32 as f64 / 256.0;
+            let cx = self.load_16(src.wrapping_add(8), NonSeq) as i16 as f64;
+            let cy = self.load_16(src.wrapping_add(10), NonSeq) as i16 as f64;
+            let sx = self.load_16(src.wrapping_add(12), NonSeq) as i16 as f64 / 256.0;
+            let sy = self.load_16(src.wrapping_add(14), NonSeq) as i16 as f64 / 256.0;
+            let theta = bios_angle(self.load_16(src.wrapping_add(16), NonSeq));
+            src = src.wrapping_add(20);
+
+            let (pa, pb, pc, pd) = affine_params(sx, sy, theta);
+            let x = ox - (pa * cx + pb * cy);
+            let y = oy - (pc * cx + pd * cy);
+
+            self.store_16(dst, to_fixed(pa) as u16, NonSeq);
+            self.store_16(dst.wrapping_add(2), to_fixed(pb) as u16, NonSeq);
+            self.store_16(dst.wrapping_add(4), to_fixed(pc) as u16, NonSeq);
+            self.store_16(dst.wrapping_add(6), to_fixed(pd) as u16, NonSeq);
+            self.store_32(dst.wrapping_add(8), to_fixed(x) as u32, NonSeq);
+            self.store_32(dst.wrapping_add(12), to_fixed(y) as u32, NonSeq);
+            dst = dst.wrapping_add(16);
+        }
+    }
+
+    fn hle_obj_affine_set(&mut self) {
+        let mut src = self.gpr[0];
+        let mut dst = self.gpr[1];
+        let stride = self.gpr[3];
+        for _ in 0..self.gpr[2] {
+            let sx = self.load_16(src, NonSeq) as i16 as f64 / 256.0;
+            let sy = self.load_16(src.wrapping_add(2), NonSeq) as i16 as f64 / 256.0;
+            let theta = bios_angle(self.load_16(src.wrapping_add(4), NonSeq));
+            src = src.wrapping_add(8);
+
+            let (pa, pb, pc, pd) = affine_params(sx, sy, theta);
+            for &p in [pa, pb, pc, pd].iter() {
+                self.store_16(dst, to_fixed(p) as u16, NonSeq);
+                dst = dst.wrapping_add(stride);
+            }
+        }
+    }
+
+    /// Widens the r0 bitfields to r1, as described by the info at r2
+    fn hle_bit_unpack(&mut self) {
+        let mut src = self.gpr[0];
+        let mut dst = self.gpr[1] & !3;
+        let info = self.gpr[2];
+        let length = self.load_16(info, NonSeq) as u32;
+        let src_width = self.load_8(info.wrapping_add(2), Seq) as u32;
+        let dst_width = self.load_8(info.wrapping_add(3), Seq) as u32;
+        let offset = self.load_32(info.wrapping_add(4), Seq);
+        // bit 31 adds the offset to zero values as well
+        let offset_zero = offset & (1 << 31) != 0;
+        let offset = offset & 0x7fff_ffff;
+        if ![1, 2, 4, 8].contains(&src_width) || ![1, 2, 4, 8, 16, 32].contains(&dst_width) {
+            warn!(target: logging::CPU, "BitUnPack: invalid widths {} -> {}", src_width, dst_width);
+            return;
+        }
+        let src_mask = (1u32 << src_width) - 1;
+        let dst_mask = ((1u64 << dst_width) - 1) as u32;
+
+        let mut word = 0u32;
+        let mut word_bits = 0;
+        for _ in 0..length {
+            let byte = self.load_8(src, Seq) as u32;
+            src = src.wrapping_add(1);
+            for shift in (0..8).step_by(src_width as usize) {
+                let mut value = (byte >> shift) & src_mask;
+                if value != 0 || offset_zero {
+                    value = value.wrapping_add(offset);
+                }
+                word |= (value & dst_mask) << word_bits;
+                word_bits += dst_width;
+                if word_bits == 32 {
+                    self.store_32(dst, word, Seq);
+                    dst = dst.wrapping_add(4);
+                    word = 0;
+                    word_bits = 0;
+                }
+            }
+        }
+    }
+
+    /// Decompresses the data at r0 to r1, vram can only be written 16bit at a time
+    fn hle_decompress(
+        &mut self,
+        decompress: fn(&mut Self, Addr) -> Result<Vec<u8>, String>,
+        vram: bool,
+    ) {
+        let src = self.gpr[0];
+        let data = match decompress(self, src) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(target: logging::CPU, "invalid compressed data at {:#010x}: {}", src, e);
+                return;
+            }
+        };
+        let dst = self.gpr[1];
+        if vram {
+            for (i, chunk) in data.chunks(2).enumerate() {
+                let lo = chunk[0] as u16;
+                let hi = *chunk.get(1).unwrap_or(&0) as u16;
+                self.store_16(dst.wrapping_add(2 * i as u32), lo | hi << 8, Seq);
+            }
+        } else {
+            for (i, byte) in data.iter().enumerate() {
+                self.store_8(dst.wrapping_add(i as u32), *byte, Seq);
+            }
+        }
+    }
+}
+
+/// Reads the 4 byte header shared by the bios decompression functions, returns the decompressed size
+fn decompressed_size<I: MemoryInterface>(core: &mut Core<I>, src: Addr) -> usize {
+    (core.load_32(src, NonSeq) >> 8) as usize
+}
+
+fn lz77_decompress<I: MemoryInterface>(core: &mut Core<I>, src: Addr) -> Result<Vec<u8>, String> {
+    let size = decompressed_size(core, src);
+    let mut out = Vec::with_capacity(size);
+    let mut src = src.wrapping_add(4);
+    while out.len() < size {
+        let flags = core.load_8(src, Seq);
+        src = src.wrapping_add(1);
+        for i in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+            if flags & (1 << i) == 0 {
+                out.push(core.load_8(src, Seq));
+                src = src.wrapping_add(1);
+            } else {
+                let b0 = core.load_8(src, Seq) as usize;
+                let b1 = core.load_8(src.wrapping_add(1), Seq) as usize;
+                src = src.wrapping_add(2);
+                let length = (b0 >> 4) + 3;
+                let disp = ((b0 & 0xf) << 8 | b1) + 1;
+                for _ in 0..length {
+                    let byte = if disp <= out.len() {
+                        out[out.len() - disp]
+                    } else {
+                        0
+                    };
+                    out.push(byte);
+                }
+            }
+        }
+    }
+    out.truncate(size);
+    Ok(out)
+}
+
+/// Fails when the walk of the tree leaves it, or takes more steps than there are nodes
+fn huff_decompress<I: MemoryInterface>(core: &mut Core<I>, src: Addr) -> Result<Vec<u8>, String> {
+    let size = decompressed_size(core, src);
+    // the data is made of 4 or 8 bit units, packed into little endian words
+    let unit_bits = if core.load_8(src, NonSeq) & 0xf == 4 {
+        4
+    } else {
+        8
+    };
+    let root = src.wrapping_add(5);
+    let tree_size = (core.load_8(src.wrapping_add(4), Seq) as u32 + 1) * 2;
+    let tree_end = src.wrapping_add(4 + tree_size);
+    let mut stream = tree_end;
+
+    let mut out = Vec::with_capacity(size);
+    let mut word = 0u32;
+    let mut word_bits = 0;
+    let mut node = root;
+    let mut node_value = core.load_8(node, Seq);
+    // the number of nodes walked since the root
+    let mut depth = 0;
+    while out.len() < size {
+        // the bitstream is read a word at a time, starting from the top bit
+        let bits = core.load_32(stream, Seq);
+        stream = stream.wrapping_add(4);
+        for i in (0..32).rev() {
+            let bit = (bits >> i) & 1;
+            let child = (node & !1).wrapping_add((node_value as u32 & 0x3f) * 2 + 2 + bit);
+            // bit 7 marks child 0 as data, bit 6 child 1
+            let child_is_data = node_value & (0x80 >> bit) != 0;
+            depth += 1;
+            if child >= tree_end {
+                return Err(format!("node {:#010x} is past the end of the tree", child));
+            }
+            if depth > tree_size {
+                return Err("the tree has a cycle".to_string());
+            }
+            node_value = core.load_8(child, Seq);
+            if !child_is_data {
+                node = child;
+                continue;
+            }
+            word |= (node_value as u32 & ((1 << unit_bits) - 1)) << word_bits;
+            word_bits += unit_bits;
+            if word_bits == 32 {
+                out.extend_from_slice(&word.to_le_bytes());
+                word = 0;
+                word_bits = 0;
+                if out.len() >= size {
+                    break;
+                }
+            }
+            node = root;
+            node_value = core.load_8(node, Seq);
+            depth = 0;
+        }
+    }
+    out.truncate(size);
+    Ok(out)
+}
+
+fn rl_decompress<I: MemoryInterface>(core: &mut Core<I>, src: Addr) -> Result<Vec<u8>, String> {
+    let size = decompressed_size(core, src);
+    let mut out = Vec::with_capacity(size);
+    let mut src = src.wrapping_add(4);
+    while out.len() < size {
+        let flag = core.load_8(src, Seq);
+        src = src.wrapping_add(1);
+        if flag & 0x80 == 0 {
+            for _ in 0..(flag & 0x7f) + 1 {
+                out.push(core.load_8(src, Seq));
+                src = src.wrapping_add(1);
+            }
+        } else {
+            let byte = core.load_8(src, Seq);
+            src = src.wrapping_add(1);
+            for _ in 0..(flag & 0x7f) + 3 {
+                out.push(byte);
+            }
+        }
+    }
+    out.truncate(size);
+    Ok(out)
+}
+
+fn isqrt(value: u32) -> u32 {
+    let mut root = (value as f64).sqrt() as u32;
+    // fix up floating point rounding
+    while root * root > value {
+        root -= 1;
+    }
+    while (root + 1) as u64 * (root + 1) as u64 <= value as u64 {
+        root += 1;
+    }
+    root
+}
+
+/// The polynomial approximation used by the bios, `tan` is in 1.14 fixed point
+fn arctan(tan: i32) -> i32 {
+    let a = -(tan.wrapping_mul(tan) >> 14);
+    let mut b = ((0xa9 * a) >> 14) + 0x390;
+    for &c in [0x91c, 0xfb6, 0x16aa, 0x2081, 0x3651, 0xa2f9].iter() {
+        b = (b.wrapping_mul(a) >> 14) + c;
+    }
+    tan.wrapping_mul(b) >> 16
+}
+
+/// Returns the angle of (x, y), where 0x10000 is a full turn
+fn arctan2(x: i32, y: i32) -> u32 {
+    let angle = if y == 0 {
+        if x >= 0 {
+            0
+        } else {
+            0x8000
+        }
+    } else if x == 0 {
+        if y >= 0 {
+            0x4000
+        } else {
+            0xc000
+        }
+    } else if y >= 0 {
+        if x >= 0 && x >= y {
+            arctan((y << 14) / x)
+        } else if x < 0 && -x >= y {
+            arctan((y << 14) / x) + 0x8000
+        } else {
+            0x4000 - arctan((x << 14) / y)
+        }
+    } else if x <= 0 && -x > -y {
+        arctan((y << 14) / x) + 0x8000
+    } else if x > 0 && x >= -y {
+        arctan((y << 14) / x) + 0x10000
+    } else {
+        0xc000 - arctan((x << 14) / y)
+    };
+    (angle as u32) & 0xffff
+}
+
+/// Only the upper 8 bits of the bios angles are used
+fn bios_angle(theta: u16) -> f64 {
+    (theta >> 8) as f64 / 128.0 * PI
+}
+
+fn affine_params(sx: f64, sy: f64, theta: f64) -> (f64, f64, f64, f64) {
+    let (sin, cos) = theta.sin_cos();
+    (cos * sx, -sin * sx, sin * sy, cos * sy)
+}
+
+/// Converts to 8.8 fixed point
+fn to_fixed(value: f64) -> i32 {
+    (value * 256.0) as i32
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::Addr;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Either a read or a write
+    Access,
+}
+
+impl WatchKind {
+    fn matches(&self, kind: WatchKind) -> bool {
+        match self {
+            WatchKind::Access => true,
+            _ => *self == kind,
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchKind::Read => write!(f, "read"),
+            WatchKind::Write => write!(f, "write"),
+            WatchKind::Access => write!(f, "access"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Watchpoint {
+    pub range: Range<Addr>,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// Returns true if a `width` bytes wide access at `addr` touches the watched range
+    fn matches(&self, addr: Addr, width: u32, kind: WatchKind) -> bool {
+        self.kind.matches(kind)
+            && addr < self.range.end
+            && addr.wrapping_add(width) > self.range.start
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} 0x{:08x}..0x{:08x}",
+            self.kind, self.range.start, self.range.end
+        )
+    }
+}
+
+/// Reports which breakpoint or watchpoint stopped the emulation
+#[derive(Debug, PartialEq, Clone)]
+pub enum HookResult {
+    Breakpoint(Addr),
+    Watchpoint {
+        index: usize,
+        /// The kind of the memory access, either Read or Write
+        kind: WatchKind,
+        addr: Addr,
+        /// Width of the access in bytes
+        width: u32,
+        value: u32,
+        /// Address of the instruction that did the access
+        pc: Addr,
+    },
+}
+
+impl fmt::Display for HookResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookResult::Breakpoint(addr) => write!(f, "breakpoint @0x{:08x}", addr),
+            HookResult::Watchpoint {
+                index,
+                kind,
+                addr,
+                width,
+                value,
+                pc,
+            } => write!(
+                f,
+                "watchpoint [{}] {} 0x{:08x} = 0x{:0digits$x} by instruction @0x{:08x}",
+                index,
+                kind,
+                addr,
+                value,
+                pc,
+                digits = 2 * *width as usize
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    /// The first watchpoint that fired since the last call to `take_hit`
+    hit: Option<HookResult>,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, range: Range<Addr>, kind: WatchKind) -> usize {
+        self.list.push(Watchpoint { range, kind });
+        self.list.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.list.len() {
+            Some(self.list.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.hit = None;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.list.iter()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub(super) fn check(&mut self, addr: Addr, width: u32, kind: WatchKind, value: u32, pc: Addr) {
+        if self.hit.is_some() {
+            return;
+        }
+        if let Some(index) = self.list.iter().position(|w| w.matches(addr, width, kind)) {
+            self.hit = Some(HookResult::Watchpoint {
+                index,
+                kind,
+                addr,
+                width,
+                value,
+                pc,
+            });
+        }
+    }
+
+    pub fn take_hit(&mut self) -> Option<HookResult> {
+        self.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchpoint_ranges() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(0x0300_0010..0x0300_0014, WatchKind::Write);
+
+        // reads and accesses outside of the range are ignored
+        watchpoints.check(0x0300_0010, 4, WatchKind::Read, 0, 0);
+        watchpoints.check(0x0300_000c, 4, WatchKind::Write, 0, 0);
+        watchpoints.check(0x0300_0014, 1, WatchKind::Write, 0, 0);
+        assert_eq!(watchpoints.take_hit(), None);
+
+        // a halfword write overlapping the end of the range
+        watchpoints.check(0x0300_0012, 2, WatchKind::Write, 0x1234, 0x0800_0000);
+        assert_eq!(
+            watchpoints.take_hit(),
+            Some(HookResult::Watchpoint {
+                index: 0,
+                kind: WatchKind::Write,
+                addr: 0x0300_0012,
+                width: 2,
+                value: 0x1234,
+                pc: 0x0800_0000,
+            })
+        );
+        assert_eq!(watchpoints.take_hit(), None);
+    }
+}
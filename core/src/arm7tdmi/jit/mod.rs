@@ -0,0 +1,363 @@
+//! Runs of ARM data processing instructions compiled to host code.
+//!
+//! The interpreter records the straight-line runs of ARM instructions it executes. A run of
+//! instructions that only compute registers from registers and immediates (see `lower`) is
+//! compiled once it ends, and the next time the cpu reaches its first instruction the run
+//! executes in a single call to the compiled code.
+//!
+//! Only the execution is compiled: every opcode fetch of a run is still charged on the bus, and
+//! a run stops early once something other than the cpu is due (`MemoryInterface::can_run_ahead`),
+//! so the timing is the same as in the interpreter. The runs of a code page that keeps being
+//! written (self-modifying code) are dropped and its code stays in the interpreter.
+//!
+//! Only x86-64 unix hosts have a backend, elsewhere nothing is compiled.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use bit::BitIndex;
+use cfg_if::cfg_if;
+
+use super::alu::{AluOpCode, BarrelShiftOpCode};
+use super::arm::{ArmCond, ArmDecodeHelper};
+use super::block_cache::{CachedInsn, CODE_PAGE_SIZE};
+use super::{Addr, REG_PC};
+
+use crate::logging;
+
+cfg_if! {
+    if #[cfg(all(target_arch = "x86_64", unix))] {
+        mod x86_64;
+        use x86_64::{Backend, RunFn};
+    } else {
+        type RunFn = unsafe fn(regs: *mut u32, count: u32);
+
+        /// No backend for this host, nothing is compiled
+        struct Backend;
+
+        impl Backend {
+            fn new() -> Option<Backend> {
+                None
+            }
+
+            fn compile(&mut self, _insns: &[LoweredInsn]) -> Option<RunFn> {
+                None
+            }
+
+            fn clear(&mut self) {}
+        }
+    }
+}
+
+/// Shorter runs cost more to call than to interpret
+const MIN_RUN_LEN: usize = 3;
+/// A code page whose runs were dropped this many times isn't compiled anymore
+const MAX_INVALIDATIONS: u32 = 4;
+
+/// A data processing instruction the backend compiles, `rd = op(rn, operand)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct LoweredInsn {
+    pub op: AluOpCode,
+    pub rd: usize,
+    pub rn: usize,
+    pub operand: Operand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Operand {
+    Immediate(u32),
+    /// `amount` is 0 to 31 for LSL, 1 to 31 for ROR and 1 to 32 for LSR and ASR
+    Register {
+        reg: usize,
+        shift: BarrelShiftOpCode,
+        amount: u32,
+    },
+}
+
+/// Returns the instruction as compiled by the backends, or `None` for anything that reads or
+/// writes the pc or the flags, accesses memory, or takes more than the cycle of its fetch
+pub(super) fn lower(insn: u32) -> Option<LoweredInsn> {
+    use AluOpCode::*;
+
+    // always executed data processing without the S bit
+    if insn.bit_range(28..32) != ArmCond::AL as u32 || insn & 0x0c00_0000 != 0 || insn.bit(20) {
+        return None;
+    }
+    let op = insn.opcode();
+    match op {
+        // TST, TEQ, CMP and CMN without the S bit are psr transfers
+        ADC | SBC | RSC | TST | TEQ | CMP | CMN => return None,
+        _ => {}
+    }
+    let rn = insn.bit_range(16..20) as usize;
+    let rd = insn.bit_range(12..16) as usize;
+    let reads_rn = op != MOV && op != MVN;
+    if rd == REG_PC || (reads_rn && rn == REG_PC) {
+        return None;
+    }
+    let operand = if insn.bit(25) {
+        let rotate = 2 * insn.bit_range(8..12);
+        Operand::Immediate((insn & 0xff).rotate_right(rotate))
+    } else {
+        let reg = insn.rm();
+        // shifting by a register takes an idle cycle
+        if insn.bit(4) || reg == REG_PC {
+            return None;
+        }
+        let shift = insn.get_bs_op();
+        let amount = match (shift, insn.bit_range(7..12)) {
+            (BarrelShiftOpCode::LSL, amount) => amount,
+            // RRX shifts the carry in
+            (BarrelShiftOpCode::ROR, 0) => return None,
+            (_, 0) => 32,
+            (_, amount) => amount,
+        };
+        Operand::Register { reg, shift, amount }
+    };
+    Some(LoweredInsn {
+        op,
+        rd,
+        rn,
+        operand,
+    })
+}
+
+/// The run the interpreter is executing
+#[derive(Debug, Clone)]
+struct Recording {
+    start: Addr,
+    generation: u32,
+    insns: Vec<LoweredInsn>,
+    opcodes: Vec<CachedInsn>,
+    /// The pipeline after the last instruction was executed
+    tail: [CachedInsn; 2],
+}
+
+impl Recording {
+    fn next_addr(&self) -> Addr {
+        self.start.wrapping_add(4 * self.insns.len() as u32)
+    }
+}
+
+#[derive(Debug)]
+struct CompiledRun {
+    generation: u32,
+    entry: RunFn,
+    /// The opcodes of the run followed by the two after it, the pipeline after the first n
+    /// instructions is `opcodes[n..n + 2]`
+    opcodes: Vec<CachedInsn>,
+}
+
+/// The compiled runs, keyed by the address of their first instruction
+#[derive(Default)]
+pub struct Jit {
+    enabled: bool,
+    backend: Option<Backend>,
+    runs: HashMap<Addr, CompiledRun>,
+    /// How many times the runs of each code page were dropped because the page was written
+    invalidations: HashMap<Addr, u32>,
+    recording: Option<Recording>,
+}
+
+impl fmt::Debug for Jit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jit")
+            .field("enabled", &self.enabled)
+            .field("compiled_runs", &self.runs.len())
+            .finish()
+    }
+}
+
+/// The compiled code is a cache, a clone compiles its own
+impl Clone for Jit {
+    fn clone(&self) -> Jit {
+        let mut jit = Jit::default();
+        jit.set_enabled(self.enabled);
+        jit
+    }
+}
+
+impl Jit {
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Stays disabled on hosts without a backend
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && self.backend.is_none() {
+            self.backend = Backend::new();
+        }
+        self.enabled = enabled && self.backend.is_some();
+        if !self.enabled {
+            self.clear();
+        }
+    }
+
+    pub fn compiled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.runs.clear();
+        self.invalidations.clear();
+        self.recording = None;
+        if let Some(backend) = &mut self.backend {
+            backend.clear();
+        }
+    }
+
+    /// Returns the entry and the length of the run starting at `addr`, if it was compiled from
+    /// the code of `generation`
+    pub(super) fn lookup(&mut self, addr: Addr, generation: u32) -> Option<(RunFn, usize)> {
+        let run = self.runs.get(&addr)?;
+        if run.generation != generation {
+            self.runs.remove(&addr);
+            *self.invalidations.entry(addr / CODE_PAGE_SIZE).or_insert(0) += 1;
+            return None;
+        }
+        Some((run.entry, run.opcodes.len() - 2))
+    }
+
+    /// The pipeline after the first `count` instructions of the run at `addr`
+    pub(super) fn pipeline_after(&self, addr: Addr, count: usize) -> [CachedInsn; 2] {
+        let opcodes = &self.runs[&addr].opcodes;
+        [opcodes[count], opcodes[count + 1]]
+    }
+
+    /// Called by the interpreter before it executes the ARM instruction at `addr`, with the
+    /// write generation of its code (`None` if it can't be cached) and the pipeline behind it
+    pub(super) fn record(
+        &mut self,
+        addr: Addr,
+        opcode: CachedInsn,
+        generation: Option<u32>,
+        pipeline: [CachedInsn; 2],
+    ) {
+        let generation = match generation {
+            Some(generation) => generation,
+            None => return self.finish(),
+        };
+        let lowered = match lower(opcode.opcode) {
+            Some(lowered) => lowered,
+            None => return self.finish(),
+        };
+        // the fetches of a run stay in the code page it starts in
+        let last_fetch = addr.wrapping_add(8);
+        if let Some(recording) = &mut self.recording {
+            let same_page = recording.start / CODE_PAGE_SIZE == last_fetch / CODE_PAGE_SIZE;
+            if recording.next_addr() == addr && recording.generation == generation && same_page {
+                recording.insns.push(lowered);
+                recording.opcodes.push(opcode);
+                recording.tail = pipeline;
+                return;
+            }
+        }
+        self.finish();
+        let page = addr / CODE_PAGE_SIZE;
+        let invalidations = self.invalidations.get(&page).copied().unwrap_or(0);
+        if page == last_fetch / CODE_PAGE_SIZE
+            && invalidations < MAX_INVALIDATIONS
+            && !self.runs.contains_key(&addr)
+        {
+            self.recording = Some(Recording {
+                start: addr,
+                generation,
+                insns: vec![lowered],
+                opcodes: vec![opcode],
+                tail: pipeline,
+            });
+        }
+    }
+
+    /// Compiles the recorded run, if it is long enough
+    fn finish(&mut self) {
+        let recording = match self.recording.take() {
+            Some(recording) if recording.insns.len() >= MIN_RUN_LEN => recording,
+            _ => return,
+        };
+        let backend = match &mut self.backend {
+            Some(backend) => backend,
+            None => return,
+        };
+        match backend.compile(&recording.insns) {
+            Some(entry) => {
+                let mut opcodes = recording.opcodes;
+                opcodes.extend_from_slice(&recording.tail);
+                self.runs.insert(
+                    recording.start,
+                    CompiledRun {
+                        generation: recording.generation,
+                        entry,
+                        opcodes,
+                    },
+                );
+            }
+            None => {
+                debug!(target: logging::CPU, "the code buffer is full, compiling everything again");
+                self.runs.clear();
+                backend.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lower() {
+        // add r1, r0, r0, lsl #2
+        assert_eq!(
+            lower(0xe080_1100),
+            Some(LoweredInsn {
+                op: AluOpCode::ADD,
+                rd: 1,
+                rn: 0,
+                operand: Operand::Register {
+                    reg: 0,
+                    shift: BarrelShiftOpCode::LSL,
+                    amount: 2
+                },
+            })
+        );
+        // rsb r4, r3, #0x100
+        assert_eq!(
+            lower(0xe263_4c01),
+            Some(LoweredInsn {
+                op: AluOpCode::RSB,
+                rd: 4,
+                rn: 3,
+                operand: Operand::Immediate(0x100),
+            })
+        );
+        // mvn r6, r5, asr #32
+        assert_eq!(
+            lower(0xe1e0_6045).map(|insn| insn.operand),
+            Some(Operand::Register {
+                reg: 5,
+                shift: BarrelShiftOpCode::ASR,
+                amount: 32
+            })
+        );
+    }
+
+    #[test]
+    fn test_lower_rejects() {
+        for &insn in &[
+            0x0280_0001, // addeq r0, r0, #1
+            0xe290_0001, // adds r0, r0, #1
+            0xe0a0_0001, // adc r0, r0, r1
+            0xe28f_0004, // add r0, pc, #4
+            0xe1a0_f00e, // mov pc, lr
+            0xe1a0_0060, // rrx r0, r0
+            0xe1a0_0110, // mov r0, r0, lsl r1
+            0xe10f_0000, // mrs r0, cpsr
+            0xe590_0000, // ldr r0, [r0]
+            0xe000_0091, // mul r0, r1, r0
+        ] {
+            assert_eq!(lower(insn), None, "{:08x}", insn);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! The x86-64 backend. A run compiles to a function taking a pointer to r0-r14 of the cpu and
+//! how many of its instructions to execute, at least one.
+
+use std::ptr;
+
+use super::{LoweredInsn, Operand};
+use crate::arm7tdmi::alu::{AluOpCode, BarrelShiftOpCode};
+use crate::logging;
+
+pub(super) type RunFn = unsafe extern "sysv64" fn(regs: *mut u32, count: u32);
+
+/// The size of the executable memory, everything is compiled again when it is full
+const CODE_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Emits the few x86-64 instructions the runs compile to.
+/// `rdi` points to the registers and `esi` counts the instructions left to execute, `ecx` holds
+/// the second operand and `eax` the first.
+#[derive(Debug, Default)]
+struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn disp(reg: usize) -> u8 {
+        (4 * reg) as u8
+    }
+
+    /// mov ecx, imm32
+    fn mov_ecx_imm(&mut self, imm: u32) {
+        self.emit(&[0xb9]);
+        self.emit(&imm.to_le_bytes());
+    }
+
+    /// mov eax, [rdi + 4 * reg]
+    fn load_eax(&mut self, reg: usize) {
+        self.emit(&[0x8b, 0x47, Self::disp(reg)]);
+    }
+
+    /// mov ecx, [rdi + 4 * reg]
+    fn load_ecx(&mut self, reg: usize) {
+        self.emit(&[0x8b, 0x4f, Self::disp(reg)]);
+    }
+
+    /// mov [rdi + 4 * reg], eax
+    fn store_eax(&mut self, reg: usize) {
+        self.emit(&[0x89, 0x47, Self::disp(reg)]);
+    }
+
+    /// mov [rdi + 4 * reg], ecx
+    fn store_ecx(&mut self, reg: usize) {
+        self.emit(&[0x89, 0x4f, Self::disp(reg)]);
+    }
+
+    /// sub ecx, [rdi + 4 * reg]
+    fn sub_ecx_reg(&mut self, reg: usize) {
+        self.emit(&[0x2b, 0x4f, Self::disp(reg)]);
+    }
+
+    /// not ecx
+    fn not_ecx(&mut self) {
+        self.emit(&[0xf7, 0xd1]);
+    }
+
+    /// shl, shr, sar or ror ecx, imm8
+    fn shift_ecx(&mut self, shift: BarrelShiftOpCode, amount: u32) {
+        match (shift, amount) {
+            (BarrelShiftOpCode::LSL, 0) => {}
+            // xor ecx, ecx
+            (BarrelShiftOpCode::LSR, 32) => self.emit(&[0x31, 0xc9]),
+            (BarrelShiftOpCode::ASR, 32) => self.emit(&[0xc1, 0xf9, 31]),
+            (shift, amount) => {
+                let modrm = match shift {
+                    BarrelShiftOpCode::LSL => 0xe1,
+                    BarrelShiftOpCode::LSR => 0xe9,
+                    BarrelShiftOpCode::ASR => 0xf9,
+                    BarrelShiftOpCode::ROR => 0xc9,
+                };
+                self.emit(&[0xc1, modrm, amount as u8]);
+            }
+        }
+    }
+
+    /// and, xor, sub, add or or eax, ecx
+    fn alu_eax_ecx(&mut self, op: AluOpCode) {
+        let opcode = match op {
+            AluOpCode::AND | AluOpCode::BIC => 0x21,
+            AluOpCode::EOR => 0x31,
+            AluOpCode::SUB => 0x29,
+            AluOpCode::ADD => 0x01,
+            AluOpCode::ORR => 0x09,
+            _ => unreachable!("{:?} is not lowered", op),
+        };
+        self.emit(&[opcode, 0xc8]);
+    }
+
+    /// sub esi, 1; jnz +1; ret
+    fn count_down(&mut self) {
+        self.emit(&[0x83, 0xee, 0x01, 0x75, 0x01, 0xc3]);
+    }
+
+    fn ret(&mut self) {
+        self.emit(&[0xc3]);
+    }
+}
+
+fn assemble(insns: &[LoweredInsn]) -> Vec<u8> {
+    use AluOpCode::*;
+
+    let mut asm = Assembler::default();
+    for (i, insn) in insns.iter().enumerate() {
+        if i != 0 {
+            asm.count_down();
+        }
+        match insn.operand {
+            Operand::Immediate(value) => asm.mov_ecx_imm(value),
+            Operand::Register { reg, shift, amount } => {
+                asm.load_ecx(reg);
+                asm.shift_ecx(shift, amount);
+            }
+        }
+        match insn.op {
+            MOV => asm.store_ecx(insn.rd),
+            MVN => {
+                asm.not_ecx();
+                asm.store_ecx(insn.rd);
+            }
+            RSB => {
+                asm.sub_ecx_reg(insn.rn);
+                asm.store_ecx(insn.rd);
+            }
+            op => {
+                if op == BIC {
+                    asm.not_ecx();
+                }
+                asm.load_eax(insn.rn);
+                asm.alu_eax_ecx(op);
+                asm.store_eax(insn.rd);
+            }
+        }
+    }
+    asm.ret();
+    asm.code
+}
+
+/// The executable memory the runs are compiled to
+#[derive(Debug)]
+pub(super) struct Backend {
+    memory: *mut u8,
+    len: usize,
+}
+
+impl Backend {
+    pub(super) fn new() -> Option<Backend> {
+        let memory = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                CODE_BUFFER_SIZE,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if memory == libc::MAP_FAILED {
+            warn!(target: logging::CPU, "failed to map the jit code buffer");
+            return None;
+        }
+        Some(Backend {
+            memory: memory as *mut u8,
+            len: 0,
+        })
+    }
+
+    /// Returns `None` when the buffer is full
+    pub(super) fn compile(&mut self, insns: &[LoweredInsn]) -> Option<RunFn> {
+        let code = assemble(insns);
+        if self.len + code.len() > CODE_BUFFER_SIZE {
+            return None;
+        }
+        unsafe {
+            // the buffer is only writable while the code is copied
+            self.protect(libc::PROT_READ | libc::PROT_WRITE)?;
+            let entry = self.memory.add(self.len);
+            ptr::copy_nonoverlapping(code.as_ptr(), entry, code.len());
+            self.protect(libc::PROT_READ | libc::PROT_EXEC)?;
+            self.len = (self.len + code.len() + 15) & !15;
+            Some(std::mem::transmute::<*mut u8, RunFn>(entry))
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    unsafe fn protect(&mut self, prot: libc::c_int) -> Option<()> {
+        let memory = self.memory as *mut libc::c_void;
+        if libc::mprotect(memory, CODE_BUFFER_SIZE, prot) == 0 {
+            Some(())
+        } else {
+            warn!(target: logging::CPU, "failed to change the protection of the jit code buffer");
+            None
+        }
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.memory as *mut libc::c_void, CODE_BUFFER_SIZE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lower;
+    use super::*;
+
+    fn run(opcodes: &[u32], regs: &mut [u32; 15], count: u32) {
+        let insns: Vec<LoweredInsn> = opcodes.iter().map(|&op| lower(op).unwrap()).collect();
+        let mut backend = Backend::new().unwrap();
+        let entry = backend.compile(&insns).unwrap();
+        unsafe { entry(regs.as_mut_ptr(), count) };
+    }
+
+    #[test]
+    fn test_assemble() {
+        // add r1, r0, r0, lsl #2
+        #[rustfmt::skip]
+        let expected = [
+            0x8b, 0x4f, 0x00, // mov ecx, [rdi]
+            0xc1, 0xe1, 0x02, // shl ecx, 2
+            0x8b, 0x47, 0x00, // mov eax, [rdi]
+            0x01, 0xc8,       // add eax, ecx
+            0x89, 0x47, 0x04, // mov [rdi + 4], eax
+            0xc3,             // ret
+        ];
+        assert_eq!(assemble(&[lower(0xe080_1100).unwrap()]), expected);
+    }
+
+    #[test]
+    fn test_run() {
+        #[rustfmt::skip]
+        let opcodes = [
+            0xe3a0_0001, // mov r0, #1
+            0xe080_1100, // add r1, r0, r0, lsl #2
+            0xe241_2003, // sub r2, r1, #3
+            0xe022_30a1, // eor r3, r2, r1, lsr #1
+            0xe263_4c01, // rsb r4, r3, #0x100
+            0xe3c4_500f, // bic r5, r4, #0xf
+            0xe1e0_6045, // mvn r6, r5, asr #32
+            0xe185_7fe5, // orr r7, r5, r5, ror #31
+        ];
+        let mut regs = [0; 15];
+        run(&opcodes, &mut regs, opcodes.len() as u32);
+        assert_eq!(&regs[..8], &[1, 5, 2, 0, 0x100, 0x100, 0xffff_ffff, 0x300]);
+
+        // only the first two
+        let mut regs = [0xaa; 15];
+        run(&opcodes, &mut regs, 2);
+        assert_eq!(&regs[..3], &[1, 5, 0xaa]);
+    }
+}
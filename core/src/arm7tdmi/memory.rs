@@ -1,4 +1,8 @@
 use super::cpu::Core;
+#[cfg(feature = "debugger")]
+use super::hooks::WatchKind;
+#[cfg(feature = "debugger")]
+use super::step_report::MemoryWrite;
 use super::Addr;
 use std::fmt;
 
@@ -78,35 +82,105 @@ pub trait MemoryInterface {
     fn store_32(&mut self, addr: u32, value: u32, access: MemoryAccess);
 
     fn idle_cycle(&mut self);
+
+    /// Fetch a halfword opcode, implementations that time code fetches differently from data
+    /// reads (like the gamepak prefetch buffer) override it
+    fn fetch_16(&mut self, addr: u32, access: MemoryAccess) -> u16 {
+        self.load_16(addr, access)
+    }
+
+    /// Fetch a word opcode, see `fetch_16`
+    fn fetch_32(&mut self, addr: u32, access: MemoryAccess) -> u32 {
+        self.load_32(addr, access)
+    }
+
+    /// Charges the cycles of a halfword opcode fetch whose value came from the block cache
+    fn fetch_cycles_16(&mut self, addr: u32, access: MemoryAccess) {
+        self.fetch_16(addr, access);
+    }
+
+    /// Charges the cycles of a word opcode fetch, see `fetch_cycles_16`
+    fn fetch_cycles_32(&mut self, addr: u32, access: MemoryAccess) {
+        self.fetch_32(addr, access);
+    }
+
+    /// The write generation of the code page holding `addr` (see `block_cache::CODE_PAGE_SIZE`),
+    /// it must change whenever something there may read differently. `None`, the default, keeps
+    /// the code there out of the block cache.
+    fn code_generation(&self, _addr: u32) -> Option<u32> {
+        None
+    }
+
+    /// Whether nothing but the cpu is due before the next instruction, so it can run without
+    /// returning from `Core::step`. `false`, the default, runs one instruction per step.
+    fn can_run_ahead(&self) -> bool {
+        false
+    }
+
+    /// Cycles elapsed since an arbitrary point, only used to measure instructions.
+    /// Implementations that don't keep track of time may return 0.
+    fn cycles(&self) -> usize {
+        0
+    }
+
+    /// Returns false if opcodes can't be fetched from `addr`
+    fn is_executable(&self, _addr: u32) -> bool {
+        true
+    }
 }
 
 impl<I: MemoryInterface> MemoryInterface for Core<I> {
     #[inline]
     fn load_8(&mut self, addr: u32, access: MemoryAccess) -> u8 {
-        self.bus.load_8(addr, access)
+        let value = self.bus.load_8(addr, access);
+        #[cfg(feature = "debugger")]
+        self.check_watchpoints(addr, 1, WatchKind::Read, value as u32);
+        value
     }
 
     #[inline]
     fn load_16(&mut self, addr: u32, access: MemoryAccess) -> u16 {
-        self.bus.load_16(addr & !1, access)
+        let value = self.bus.load_16(addr & !1, access);
+        #[cfg(feature = "debugger")]
+        self.check_watchpoints(addr & !1, 2, WatchKind::Read, value as u32);
+        value
     }
 
     #[inline]
     fn load_32(&mut self, addr: u32, access: MemoryAccess) -> u32 {
-        self.bus.load_32(addr & !3, access)
+        let value = self.bus.load_32(addr & !3, access);
+        #[cfg(feature = "debugger")]
+        self.check_watchpoints(addr & !3, 4, WatchKind::Read, value);
+        value
+    }
+
+    #[inline]
+    fn fetch_16(&mut self, addr: u32, access: MemoryAccess) -> u16 {
+        self.bus.fetch_16(addr & !1, access)
+    }
+
+    #[inline]
+    fn fetch_32(&mut self, addr: u32, access: MemoryAccess) -> u32 {
+        self.bus.fetch_32(addr & !3, access)
     }
 
     #[inline]
     fn store_8(&mut self, addr: u32, value: u8, access: MemoryAccess) {
+        #[cfg(feature = "debugger")]
+        self.check_watchpoints(addr, 1, WatchKind::Write, value as u32);
         self.bus.store_8(addr, value, access);
     }
     #[inline]
     fn store_16(&mut self, addr: u32, value: u16, access: MemoryAccess) {
+        #[cfg(feature = "debugger")]
+        self.check_watchpoints(addr & !1, 2, WatchKind::Write, value as u32);
         self.bus.store_16(addr & !1, value, access);
     }
 
     #[inline]
     fn store_32(&mut self, addr: u32, value: u32, access: MemoryAccess) {
+        #[cfg(feature = "debugger")]
+        self.check_watchpoints(addr & !3, 4, WatchKind::Write, value);
         self.bus.store_32(addr & !3, value, access);
     }
 
@@ -118,6 +192,25 @@ impl<I: MemoryInterface> MemoryInterface for Core<I> {
 
 /// Implementation of memory access helpers
 impl<I: MemoryInterface> Core<I> {
+    /// Checks the watchpoints, and records the writes of an instruction executed by `step_debug`
+    #[cfg(feature = "debugger")]
+    #[inline]
+    fn check_watchpoints(&mut self, addr: Addr, width: u32, kind: WatchKind, value: u32) {
+        if !self.dbg.watchpoints.is_empty() {
+            let pc = self
+                .dbg
+                .last_executed
+                .as_ref()
+                .map_or(self.pc, |insn| insn.get_pc());
+            self.dbg.watchpoints.check(addr, width, kind, value, pc);
+        }
+        if kind == WatchKind::Write {
+            if let Some(writes) = &mut self.dbg.step_writes {
+                writes.push(MemoryWrite { addr, width, value });
+            }
+        }
+    }
+
     #[inline]
     pub(super) fn store_aligned_32(&mut self, addr: Addr, value: u32, access: MemoryAccess) {
         self.store_32(addr & !0x3, value, access);
@@ -129,36 +222,33 @@ impl<I: MemoryInterface> Core<I> {
     }
 
     /// Helper function for "ldr" instruction that handles misaligned addresses
+    /// The word is read from the aligned address and rotated right by 8 times the misalignment.
+    /// Note: the rotation is done by the load unit and does not affect the CPSR flags.
     #[inline]
     pub(super) fn ldr_word(&mut self, addr: Addr, access: MemoryAccess) -> u32 {
         if addr & 0x3 != 0 {
             let rotation = (addr & 0x3) << 3;
             let value = self.load_32(addr & !0x3, access);
-            let mut carry = self.cpsr.C();
-            let v = self.ror(value, rotation, &mut carry, false, false);
-            self.cpsr.set_C(carry);
-            v
+            value.rotate_right(rotation)
         } else {
             self.load_32(addr, access)
         }
     }
 
     /// Helper function for "ldrh" instruction that handles misaligned addresses
+    /// The halfword is read from the aligned address and rotated right by 8 bits into a 32bit value.
     #[inline]
     pub(super) fn ldr_half(&mut self, addr: Addr, access: MemoryAccess) -> u32 {
         if addr & 0x1 != 0 {
-            let rotation = (addr & 0x1) << 3;
             let value = self.load_16(addr & !0x1, access);
-            let mut carry = self.cpsr.C();
-            let v = self.ror(value as u32, rotation, &mut carry, false, false);
-            self.cpsr.set_C(carry);
-            v
+            (value as u32).rotate_right(8)
         } else {
             self.load_16(addr, access) as u32
         }
     }
 
     /// Helper function for "ldrsh" instruction that handles misaligned addresses
+    /// On the ARM7TDMI a misaligned "ldrsh" behaves like "ldrsb" from the same address.
     #[inline]
     pub(super) fn ldr_sign_half(&mut self, addr: Addr, access: MemoryAccess) -> u32 {
         if addr & 0x1 != 0 {
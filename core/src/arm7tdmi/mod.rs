@@ -12,12 +12,25 @@ use thumb::ThumbInstruction;
 pub mod cpu;
 pub use cpu::*;
 pub mod alu;
+pub mod block_cache;
 pub mod memory;
 pub use alu::*;
 pub mod exception;
+mod hle;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod psr;
 pub use psr::*;
 pub mod disass;
+#[cfg(feature = "debugger")]
+pub mod history;
+#[cfg(feature = "debugger")]
+pub mod hooks;
+#[cfg(feature = "debugger")]
+pub mod step_report;
+#[cfg(feature = "debugger")]
+pub mod trace;
+pub mod watchdog;
 
 pub const REG_PC: usize = 15;
 pub const REG_LR: usize = 14;
@@ -57,6 +70,11 @@ pub trait InstructionDecoder: Sized {
     /// Helper functions for the Disassembler
     fn decode_from_bytes(bytes: &[u8], addr: Addr) -> Self;
     fn get_raw(&self) -> Self::IntType;
+    /// GNU objdump shows some pairs of opcodes as a single instruction, returns its text when
+    /// `self` and `next` are one
+    fn fmt_pair(&self, _next: &Self) -> Option<String> {
+        None
+    }
 }
 
 pub fn reg_string<T: Into<usize>>(reg: T) -> &'static str {
@@ -67,7 +85,7 @@ pub fn reg_string<T: Into<usize>>(reg: T) -> &'static str {
     reg_names[reg.into()]
 }
 
-#[derive(Debug, PartialEq, Primitive, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Primitive, Copy, Clone)]
 #[repr(u8)]
 pub enum CpuState {
     ARM = 0,
@@ -107,6 +125,14 @@ impl CpuMode {
         }
     }
 
+    /// User and System mode have no SPSR
+    pub fn has_spsr(&self) -> bool {
+        match self {
+            CpuMode::User | CpuMode::System => false,
+            _ => true,
+        }
+    }
+
     pub fn bank_index(&self) -> usize {
         match self {
             CpuMode::User | CpuMode::System => 0,
@@ -133,3 +159,46 @@ impl fmt::Display for CpuMode {
         }
     }
 }
+
+/// Errors the cpu can recover from, usually caused by bugs in the running game
+#[derive(Debug, Clone, PartialEq)]
+pub enum CpuError {
+    /// The pc was set to an address opcodes can't be fetched from
+    InvalidPc {
+        pc: Addr,
+        /// The instruction that jumped to `pc`
+        previous_pc: Addr,
+        lr: u32,
+    },
+    /// The pipeline kept being flushed to the same one or two addresses, see `watchdog::Watchdog`
+    Stuck { targets: (Addr, Addr), flushes: u32 },
+    /// An instruction the ARM7TDMI doesn't define the behavior of, it was emulated in a way that
+    /// keeps the cpu in a consistent state
+    Unpredictable { pc: Addr, reason: &'static str },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::InvalidPc {
+                pc,
+                previous_pc,
+                lr,
+            } => write!(
+                f,
+                "invalid pc @{:08x} (jumped from @{:08x}, lr={:08x})",
+                pc, previous_pc, lr
+            ),
+            CpuError::Stuck { targets, flushes } => write!(
+                f,
+                "cpu is stuck branching between @{:08x} and @{:08x} ({} times in a row)",
+                targets.0, targets.1, flushes
+            ),
+            CpuError::Unpredictable { pc, reason } => {
+                write!(f, "unpredictable instruction @{:08x}: {}", pc, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
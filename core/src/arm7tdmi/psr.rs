@@ -69,8 +69,14 @@ impl RegPSR {
         self.raw.set_bit(5, state.into());
     }
 
+    /// The cpu never switches to an invalid mode, the instructions that change it check `valid_mode`
     pub fn mode(&self) -> CpuMode {
-        CpuMode::from_u32(self.raw.bit_range(0..5)).unwrap()
+        self.valid_mode().unwrap()
+    }
+
+    /// Returns None when the mode bits don't encode a mode, e.g in a value written to the SPSR
+    pub fn valid_mode(&self) -> Option<CpuMode> {
+        CpuMode::from_u32(self.raw.bit_range(0..5))
     }
 
     pub fn set_mode(&mut self, mode: CpuMode) {
@@ -0,0 +1,145 @@
+/// What executing a single instruction changed, see `Core::step_debug`
+use std::fmt;
+
+use super::cpu::Core;
+use super::memory::MemoryInterface;
+use super::{reg_string, Addr, CpuMode, CpuState, RegPSR, REG_PC};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RegisterChange {
+    /// 0-14, or `REG_PC` for the address of the next instruction
+    pub reg: usize,
+    pub old: u32,
+    pub new: u32,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FlagChange {
+    /// One of N, Z, C, V, I (irq disabled), F (fiq disabled) and T (thumb state)
+    pub flag: char,
+    pub new: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MemoryWrite {
+    pub addr: Addr,
+    /// 1, 2 or 4 bytes
+    pub width: u32,
+    pub value: u32,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StepReport {
+    /// Address of the executed instruction
+    pub pc: Addr,
+    pub registers: Vec<RegisterChange>,
+    pub flags: Vec<FlagChange>,
+    /// The old and new mode, if the instruction (or an exception) switched modes
+    pub mode: Option<(CpuMode, CpuMode)>,
+    pub writes: Vec<MemoryWrite>,
+    /// As counted by `MemoryInterface::cycles`
+    pub cycles: usize,
+}
+
+fn psr_flags(psr: RegPSR) -> [(char, bool); 7] {
+    [
+        ('N', psr.N()),
+        ('Z', psr.Z()),
+        ('C', psr.C()),
+        ('V', psr.V()),
+        ('I', psr.irq_disabled()),
+        ('F', psr.fiq_disabled()),
+        ('T', psr.state() == CpuState::THUMB),
+    ]
+}
+
+impl<I: MemoryInterface> Core<I> {
+    /// Executes a single instruction and reports what it changed
+    pub fn step_debug(&mut self) -> StepReport {
+        let pc = self.get_next_pc();
+        let gpr = self.get_registers();
+        let cpsr = self.cpsr;
+        let cycles = self.bus.cycles();
+
+        self.dbg.step_writes = Some(Vec::new());
+        self.step();
+        let writes = self.dbg.step_writes.take().unwrap_or_default();
+
+        let mut registers: Vec<RegisterChange> = gpr
+            .iter()
+            .zip(self.get_registers().iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(reg, (old, new))| RegisterChange {
+                reg,
+                old: *old,
+                new: *new,
+            })
+            .collect();
+        let next_pc = self.get_next_pc();
+        if next_pc != pc.wrapping_add(self.word_size() as u32) {
+            registers.push(RegisterChange {
+                reg: REG_PC,
+                old: pc,
+                new: next_pc,
+            });
+        }
+
+        let flags = psr_flags(cpsr)
+            .iter()
+            .zip(psr_flags(self.cpsr).iter())
+            .filter(|(old, new)| old.1 != new.1)
+            .map(|(_, &(flag, new))| FlagChange { flag, new })
+            .collect();
+        let mode = if cpsr.mode() != self.cpsr.mode() {
+            Some((cpsr.mode(), self.cpsr.mode()))
+        } else {
+            None
+        };
+
+        StepReport {
+            pc,
+            registers,
+            flags,
+            mode,
+            writes,
+            cycles: self.bus.cycles().wrapping_sub(cycles),
+        }
+    }
+}
+
+impl fmt::Display for StepReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@0x{:08x} ({} cycles)", self.pc, self.cycles)?;
+        for change in self.registers.iter() {
+            write!(
+                f,
+                "\n\t{}: 0x{:08x} -> 0x{:08x}",
+                reg_string(change.reg),
+                change.old,
+                change.new
+            )?;
+        }
+        for change in self.flags.iter() {
+            write!(
+                f,
+                "\n\t{}{}",
+                if change.new { '+' } else { '-' },
+                change.flag
+            )?;
+        }
+        if let Some((old, new)) = self.mode {
+            write!(f, "\n\tmode: {} -> {}", old, new)?;
+        }
+        for write in self.writes.iter() {
+            write!(
+                f,
+                "\n\t[0x{:08x}] = 0x{:0width$x}",
+                write.addr,
+                write.value,
+                width = 2 * write.width as usize
+            )?;
+        }
+        Ok(())
+    }
+}
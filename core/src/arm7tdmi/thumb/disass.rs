@@ -54,7 +54,7 @@ impl ThumbInstruction {
     fn fmt_thumb_high_reg_op_or_bx(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let op = self.raw.format5_op();
         let dst_reg = if self.raw.flag(consts::flags::FLAG_H1) {
-            self.raw & 0b111 + 8
+            (self.raw & 0b111) + 8
         } else {
             self.raw & 0b111
         };
@@ -82,7 +82,7 @@ impl ThumbInstruction {
             "ldr\t{Rd}, [pc, #{Imm:#x}] ; = #{effective:#x}",
             Rd = reg_string(self.raw.bit_range(8..11)),
             Imm = self.raw.word8(),
-            effective = (self.pc + 4 & !0b10) + (self.raw.word8() as Addr)
+            effective = ((self.pc + 4) & !0b10) + (self.raw.word8() as Addr)
         )
     }
 
@@ -109,8 +109,8 @@ impl ThumbInstruction {
                 ) {
                     (false, false) => "strh",
                     (false, true) => "ldrh",
-                    (true, false) => "ldsb",
-                    (true, true) => "ldsh",
+                    (true, false) => "ldrsb",
+                    (true, true) => "ldrsh",
                 }
             },
             Rd = reg_string(self.raw & 0b111),
@@ -176,7 +176,7 @@ impl ThumbInstruction {
 
     fn fmt_thumb_add_sub(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let operand = if self.raw.is_immediate_operand() {
-            format!("#{:x}", self.raw.bit_range(6..9))
+            format!("#{:#x}", self.raw.bit_range(6..9))
         } else {
             String::from(reg_string(self.raw.rn()))
         };
@@ -192,7 +192,12 @@ impl ThumbInstruction {
     }
 
     fn fmt_thumb_add_sp(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "add\tsp, #{imm:x}", imm = self.raw.sword7())
+        let imm = self.raw.sword7();
+        if imm < 0 {
+            write!(f, "sub\tsp, #{:#x}", -imm)
+        } else {
+            write!(f, "add\tsp, #{:#x}", imm)
+        }
     }
 
     fn fmt_register_list(&self, f: &mut fmt::Formatter<'_>, rlist: u8) -> fmt::Result {
@@ -249,7 +254,7 @@ impl ThumbInstruction {
     }
 
     fn fmt_thumb_swi(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "swi\t{value:#x}", value = self.raw & 0xff,)
+        write!(f, "swi\t#{value:#x}", value = self.raw & 0xff,)
     }
 
     fn fmt_thumb_branch(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -263,15 +268,19 @@ impl ThumbInstruction {
         )
     }
 
+    /// BL is split into two halfwords, the first one loads the upper part of the offset into lr
+    /// and the second one branches to lr plus the lower part.
     fn fmt_thumb_branch_long_with_link(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "bl\t#0x{:08x}", {
-            let offset11 = self.raw.offset11();
-            if self.raw.flag(consts::flags::FLAG_LOW_OFFSET) {
-                (offset11 << 1) as i32
-            } else {
-                ((offset11 << 21) >> 9) as i32
-            }
-        })
+        let offset11 = self.raw.offset11();
+        if self.raw.flag(consts::flags::FLAG_LOW_OFFSET) {
+            write!(f, "bl\tlr, #{:#x}", offset11 << 1)
+        } else {
+            write!(
+                f,
+                "bl\tlr, {addr:#x}",
+                addr = (self.pc as i32 + 4).wrapping_add((offset11 << 21) >> 9) as Addr
+            )
+        }
     }
 }
 
@@ -1,4 +1,5 @@
 use crate::arm7tdmi::*;
+use crate::logging;
 
 use crate::bit::BitIndex;
 
@@ -174,7 +175,6 @@ impl<I: MemoryInterface> Core<I> {
             OpFormat5::ADD => {
                 self.set_reg(dst_reg, op1.wrapping_add(op2));
                 if dst_reg == REG_PC {
-                    self.reload_pipeline16();
                     result = CpuAction::PipelineFlushed;
                 }
             }
@@ -187,7 +187,6 @@ impl<I: MemoryInterface> Core<I> {
             OpFormat5::MOV => {
                 self.set_reg(dst_reg, op2 as u32);
                 if dst_reg == REG_PC {
-                    self.reload_pipeline16();
                     result = CpuAction::PipelineFlushed;
                 }
             }
@@ -200,7 +199,7 @@ impl<I: MemoryInterface> Core<I> {
     /// Execution Time: 1S+1N+1I
     pub(in super::super) fn exec_thumb_ldr_pc<const RD: usize>(&mut self, insn: u16) -> CpuAction {
         let ofs = insn.word8() as Addr;
-        let addr = (self.pc & !3) + ofs;
+        let addr = (self.pc_operand() & !3) + ofs;
 
         self.gpr[RD] = self.load_32(addr, NonSeq);
 
@@ -364,7 +363,7 @@ impl<I: MemoryInterface> Core<I> {
         self.gpr[RD] = if SP {
             self.gpr[REG_SP] + (insn.word8() as Addr)
         } else {
-            (self.pc_thumb() & !0b10) + 4 + (insn.word8() as Addr)
+            (self.pc_operand() & !0b10) + (insn.word8() as Addr)
         };
 
         CpuAction::AdvancePC(Seq)
@@ -426,7 +425,6 @@ impl<I: MemoryInterface> Core<I> {
                 pop!(REG_PC);
                 self.pc = self.pc & !1;
                 result = CpuAction::PipelineFlushed;
-                self.reload_pipeline16();
             }
             // Idle 1 cycle
             self.idle_cycle();
@@ -501,9 +499,8 @@ impl<I: MemoryInterface> Core<I> {
                 let val = self.load_32(addr, NonSeq);
                 self.pc = val & !1;
                 result = CpuAction::PipelineFlushed;
-                self.reload_pipeline16();
             } else {
-                self.store_32(addr, self.pc + 2, NonSeq);
+                self.store_32(addr, self.pc_operand_late(), NonSeq);
             }
             addr += 0x40;
             self.gpr[RB] = addr + align_preserve;
@@ -526,16 +523,14 @@ impl<I: MemoryInterface> Core<I> {
         } else {
             let offset = insn.bcond_offset();
             self.pc = (self.pc as i32).wrapping_add(offset) as u32;
-            self.reload_pipeline16();
             CpuAction::PipelineFlushed
         }
     }
 
     /// Format 17
     /// Execution Time: 2S+1N
-    pub(in super::super) fn exec_thumb_swi(&mut self, _insn: u16) -> CpuAction {
-        self.exception(Exception::SoftwareInterrupt, self.pc - 2); // implies pipeline reload
-        CpuAction::PipelineFlushed
+    pub(in super::super) fn exec_thumb_swi(&mut self, insn: u16) -> CpuAction {
+        self.software_interrupt(self.pc - 2, (insn & 0xff) as u32)
     }
 
     /// Format 18
@@ -543,7 +538,6 @@ impl<I: MemoryInterface> Core<I> {
     pub(in super::super) fn exec_thumb_branch(&mut self, insn: u16) -> CpuAction {
         let offset = ((insn.offset11() << 21) >> 20) as i32;
         self.pc = (self.pc as i32).wrapping_add(offset) as u32;
-        self.reload_pipeline16(); // 2S + 1N
         CpuAction::PipelineFlushed
     }
 
@@ -559,7 +553,6 @@ impl<I: MemoryInterface> Core<I> {
             let next_pc = (self.pc - 2) | 1;
             self.pc = ((self.gpr[REG_LR] & !1) as i32).wrapping_add(off) as u32;
             self.gpr[REG_LR] = next_pc;
-            self.reload_pipeline16(); // implies 2S + 1N
             CpuAction::PipelineFlushed
         } else {
             off = (off << 21) >> 9;
@@ -569,10 +562,16 @@ impl<I: MemoryInterface> Core<I> {
     }
 
     pub fn thumb_undefined(&mut self, insn: u16) -> CpuAction {
-        panic!(
-            "executing undefind thumb instruction {:04x} at @{:08x}",
+        warn!(
+            target: logging::CPU,
+            "executing undefined thumb instruction {:04x} at @{:08x}",
             insn,
             self.pc_thumb()
-        )
+        );
+        // the game may well handle the exception, the history is only of use when tracing
+        #[cfg(feature = "debugger")]
+        self.dbg.history.log(log::Level::Trace);
+        self.undefined_instruction(self.pc - 2); // Implies 2S + 1N
+        CpuAction::PipelineFlushed
     }
 }
@@ -54,50 +54,12 @@ pub enum ThumbFormat {
     Undefined,
 }
 
+// THUMB_FORMAT_LUT, the format by bits 15-6, generated with the THUMB_LUT of the cpu
+include!(concat!(env!("OUT_DIR"), "/thumb_format_lut.rs"));
+
 impl From<u16> for ThumbFormat {
     fn from(raw: u16) -> ThumbFormat {
-        use ThumbFormat::*;
-        if raw & 0xf800 == 0x1800 {
-            AddSub
-        } else if raw & 0xe000 == 0x0000 {
-            MoveShiftedReg
-        } else if raw & 0xe000 == 0x2000 {
-            DataProcessImm
-        } else if raw & 0xfc00 == 0x4000 {
-            AluOps
-        } else if raw & 0xfc00 == 0x4400 {
-            HiRegOpOrBranchExchange
-        } else if raw & 0xf800 == 0x4800 {
-            LdrPc
-        } else if raw & 0xf200 == 0x5000 {
-            LdrStrRegOffset
-        } else if raw & 0xf200 == 0x5200 {
-            LdrStrSHB
-        } else if raw & 0xe000 == 0x6000 {
-            LdrStrImmOffset
-        } else if raw & 0xf000 == 0x8000 {
-            LdrStrHalfWord
-        } else if raw & 0xf000 == 0x9000 {
-            LdrStrSp
-        } else if raw & 0xf000 == 0xa000 {
-            LoadAddress
-        } else if raw & 0xff00 == 0xb000 {
-            AddSp
-        } else if raw & 0xf600 == 0xb400 {
-            PushPop
-        } else if raw & 0xf000 == 0xc000 {
-            LdmStm
-        } else if raw & 0xff00 == 0xdf00 {
-            Swi
-        } else if raw & 0xf000 == 0xd000 {
-            BranchConditional
-        } else if raw & 0xf800 == 0xe000 {
-            Branch
-        } else if raw & 0xf000 == 0xf000 {
-            BranchLongWithLink
-        } else {
-            Undefined
-        }
+        THUMB_FORMAT_LUT[(raw >> 6) as usize]
     }
 }
 
@@ -112,6 +74,19 @@ impl ThumbInstruction {
     pub fn new(raw: u16, pc: Addr, fmt: ThumbFormat) -> ThumbInstruction {
         ThumbInstruction { fmt, raw, pc }
     }
+
+    /// The target of the `bl` made of `self`, the half with the upper part of the offset, and
+    /// `next`, the half with the lower part (bit 11 set) that follows it
+    pub fn long_branch_target(&self, next: &ThumbInstruction) -> Option<Addr> {
+        let is_bl_half = |insn: &ThumbInstruction, low: bool| {
+            insn.fmt == ThumbFormat::BranchLongWithLink && insn.raw.flag(11) == low
+        };
+        if !is_bl_half(self, false) || !is_bl_half(next, true) || next.pc != self.pc + 2 {
+            return None;
+        }
+        let lr = (self.pc as i32 + 4).wrapping_add((self.raw.offset11() << 21) >> 9);
+        Some(lr.wrapping_add(next.raw.offset11() << 1) as Addr)
+    }
 }
 
 impl InstructionDecoder for ThumbInstruction {
@@ -131,6 +106,11 @@ impl InstructionDecoder for ThumbInstruction {
     fn get_raw(&self) -> u16 {
         self.raw
     }
+
+    fn fmt_pair(&self, next: &Self) -> Option<String> {
+        self.long_branch_target(next)
+            .map(|target| format!("bl\t{:#x}", target))
+    }
 }
 
 #[derive(Debug, Primitive, PartialEq)]
@@ -474,7 +454,7 @@ thumb_decode_helper_impl!(u16);
 //         core.gpr[7] = 0x10;
 //         core.gpr[1] = 0x5;
 //         let decoded = ThumbInstruction::decode(0x567a, 0).unwrap();
-//         assert_eq!(format!("{}", decoded), "ldsb\tr2, [r7, r1]");
+//         assert_eq!(format!("{}", decoded), "ldrsb\tr2, [r7, r1]");
 //         core.exec_thumb(&mut mem, decoded).unwrap();
 //         assert_eq!(core.gpr[2], mem.read_8(0x15) as i8 as u32);
 
@@ -483,7 +463,7 @@ thumb_decode_helper_impl!(u16);
 //         core.gpr[4] = 0x0;
 //         core.gpr[2] = 0x6;
 //         let decoded = ThumbInstruction::decode(0x5ea3, 0).unwrap();
-//         assert_eq!(format!("{}", decoded), "ldsh\tr3, [r4, r2]");
+//         assert_eq!(format!("{}", decoded), "ldrsh\tr3, [r4, r2]");
 //         core.exec_thumb(&mut mem, decoded).unwrap();
 //         assert_eq!(core.gpr[3], 0x5678);
 //     }
@@ -0,0 +1,303 @@
+/// Writes the executed instructions to a file, in a line based format that can be diffed
+/// against the traces of other emulators
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use super::arm::ArmFormat;
+use super::thumb::ThumbFormat;
+use super::{Addr, CpuMode, DecodedInstruction, RegPSR};
+
+/// Coarse grouping of the instruction formats, to trace only some kinds of instructions
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InsnClass {
+    /// Branches, including BX and the thumb long branch with link
+    Branch,
+    DataProcessing,
+    Multiply,
+    /// Single loads, stores and swaps
+    LoadStore,
+    /// LDM/STM and PUSH/POP
+    BlockTransfer,
+    /// MRS and MSR
+    Psr,
+    Swi,
+    Undefined,
+}
+
+impl InsnClass {
+    pub fn of(insn: &DecodedInstruction) -> InsnClass {
+        match insn {
+            DecodedInstruction::Arm(a) => match a.fmt {
+                ArmFormat::BranchExchange | ArmFormat::BranchLink => InsnClass::Branch,
+                ArmFormat::SoftwareInterrupt => InsnClass::Swi,
+                ArmFormat::Multiply | ArmFormat::MultiplyLong => InsnClass::Multiply,
+                ArmFormat::SingleDataTransfer
+                | ArmFormat::HalfwordDataTransferRegOffset
+                | ArmFormat::HalfwordDataTransferImmediateOffset
+                | ArmFormat::SingleDataSwap => InsnClass::LoadStore,
+                ArmFormat::DataProcessing => InsnClass::DataProcessing,
+                ArmFormat::BlockDataTransfer => InsnClass::BlockTransfer,
+                ArmFormat::MoveFromStatus | ArmFormat::MoveToStatus | ArmFormat::MoveToFlags => {
+                    InsnClass::Psr
+                }
+                ArmFormat::Undefined => InsnClass::Undefined,
+            },
+            DecodedInstruction::Thumb(t) => match t.fmt {
+                // MUL is one of the alu operations
+                ThumbFormat::AluOps if (t.raw >> 6) & 0xf == 0b1101 => InsnClass::Multiply,
+                // BX is the 4th hi register operation
+                ThumbFormat::HiRegOpOrBranchExchange if (t.raw >> 8) & 0b11 == 0b11 => {
+                    InsnClass::Branch
+                }
+                ThumbFormat::MoveShiftedReg
+                | ThumbFormat::AddSub
+                | ThumbFormat::DataProcessImm
+                | ThumbFormat::AluOps
+                | ThumbFormat::HiRegOpOrBranchExchange
+                | ThumbFormat::LoadAddress
+                | ThumbFormat::AddSp => InsnClass::DataProcessing,
+                ThumbFormat::LdrPc
+                | ThumbFormat::LdrStrRegOffset
+                | ThumbFormat::LdrStrSHB
+                | ThumbFormat::LdrStrImmOffset
+                | ThumbFormat::LdrStrHalfWord
+                | ThumbFormat::LdrStrSp => InsnClass::LoadStore,
+                ThumbFormat::PushPop | ThumbFormat::LdmStm => InsnClass::BlockTransfer,
+                ThumbFormat::BranchConditional
+                | ThumbFormat::Branch
+                | ThumbFormat::BranchLongWithLink => InsnClass::Branch,
+                ThumbFormat::Swi => InsnClass::Swi,
+                ThumbFormat::Undefined => InsnClass::Undefined,
+            },
+        }
+    }
+}
+
+impl FromStr for InsnClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InsnClass, String> {
+        match s {
+            "branch" => Ok(InsnClass::Branch),
+            "alu" | "data" => Ok(InsnClass::DataProcessing),
+            "mul" | "multiply" => Ok(InsnClass::Multiply),
+            "ldst" | "load-store" => Ok(InsnClass::LoadStore),
+            "block" | "ldm-stm" => Ok(InsnClass::BlockTransfer),
+            "psr" => Ok(InsnClass::Psr),
+            "swi" => Ok(InsnClass::Swi),
+            "undefined" => Ok(InsnClass::Undefined),
+            _ => Err(format!(
+                "unknown instruction class {:?}, expected branch, alu, mul, ldst, block, psr, swi or undefined",
+                s
+            )),
+        }
+    }
+}
+
+fn parse_mode(s: &str) -> Result<CpuMode, String> {
+    match s {
+        "usr" | "user" => Ok(CpuMode::User),
+        "fiq" => Ok(CpuMode::Fiq),
+        "irq" => Ok(CpuMode::Irq),
+        "svc" | "supervisor" => Ok(CpuMode::Supervisor),
+        "abt" | "abort" => Ok(CpuMode::Abort),
+        "und" | "undefined" => Ok(CpuMode::Undefined),
+        "sys" | "system" => Ok(CpuMode::System),
+        _ => Err(format!("unknown cpu mode {:?}", s)),
+    }
+}
+
+fn parse_addr(s: &str) -> Result<Addr, String> {
+    let digits = s.trim_start_matches("0x");
+    u32::from_str_radix(digits, 16).map_err(|_| format!("invalid address {:?}", s))
+}
+
+/// Selects the traced instructions, an instruction is traced when it passes every filter that
+/// was set. Empty lists don't filter anything.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TraceFilter {
+    pub pc_range: Option<Range<Addr>>,
+    pub modes: Vec<CpuMode>,
+    pub classes: Vec<InsnClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, insn: &DecodedInstruction, mode: CpuMode) -> bool {
+        let pc = insn.get_pc();
+        self.pc_range.as_ref().map_or(true, |r| r.contains(&pc))
+            && (self.modes.is_empty() || self.modes.contains(&mode))
+            && (self.classes.is_empty() || self.classes.contains(&InsnClass::of(insn)))
+    }
+
+    /// Adds the filter `key=value`, where key is `pc` (a `<start>-<end>` range of hex addresses,
+    /// the end is exclusive), `mode` or `class`
+    pub fn add(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "pc" => {
+                let (start, end) = match value.find('-') {
+                    Some(i) => (parse_addr(&value[..i])?, parse_addr(&value[i + 1..])?),
+                    None => return Err(format!("expected pc=<start>-<end>, got {:?}", value)),
+                };
+                self.pc_range = Some(start..end);
+            }
+            "mode" => self.modes.push(parse_mode(value)?),
+            "class" => self.classes.push(value.parse()?),
+            _ => return Err(format!("unknown trace filter {:?}", key)),
+        }
+        Ok(())
+    }
+}
+
+/// A comma separated list of filters, e.g `pc=08000000-08001000,mode=irq,class=branch`
+impl FromStr for TraceFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceFilter, String> {
+        let mut filter = TraceFilter::default();
+        for part in s.split(',').filter(|part| !part.is_empty()) {
+            match part.find('=') {
+                Some(i) => filter.add(&part[..i], &part[i + 1..])?,
+                None => return Err(format!("expected key=value, got {:?}", part)),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// One line per traced instruction:
+/// the cycle count, the address, the opcode, the disassembly, then r0-r15 and the cpsr as they
+/// were before the instruction executed. r15 reads as the pipelined pc (the address + 8 or + 4).
+pub struct InstructionTrace {
+    writer: Box<dyn Write>,
+    filter: TraceFilter,
+    lines: u64,
+}
+
+impl fmt::Debug for InstructionTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstructionTrace")
+            .field("filter", &self.filter)
+            .field("lines", &self.lines)
+            .finish()
+    }
+}
+
+impl InstructionTrace {
+    pub fn new(writer: Box<dyn Write>, filter: TraceFilter) -> InstructionTrace {
+        InstructionTrace {
+            writer,
+            filter,
+            lines: 0,
+        }
+    }
+
+    /// Creates the trace file, gzip compressed when the file name ends with `.gz`
+    pub fn create(path: &Path, filter: TraceFilter) -> io::Result<InstructionTrace> {
+        let file = BufWriter::new(File::create(path)?);
+        let writer: Box<dyn Write> = match path.extension() {
+            Some(ext) if ext == "gz" => Box::new(GzEncoder::new(file, Compression::fast())),
+            _ => Box::new(file),
+        };
+        Ok(InstructionTrace::new(writer, filter))
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    /// Number of instructions written so far
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn record(
+        &mut self,
+        cycles: usize,
+        insn: &DecodedInstruction,
+        gpr: &[u32; 15],
+        r15: u32,
+        cpsr: RegPSR,
+    ) -> io::Result<()> {
+        if !self.filter.matches(insn, cpsr.mode()) {
+            return Ok(());
+        }
+        let w = &mut self.writer;
+        match insn {
+            DecodedInstruction::Arm(a) => {
+                write!(w, "{:010} {:08x}: {:08x} {:<32}", cycles, a.pc, a.raw, a)?
+            }
+            DecodedInstruction::Thumb(t) => write!(
+                w,
+                "{:010} {:08x}: {:>8} {:<32}",
+                cycles,
+                t.pc,
+                format!("{:04x}", t.raw),
+                t
+            )?,
+        }
+        for r in gpr.iter().chain(std::iter::once(&r15)) {
+            write!(w, " {:08x}", r)?;
+        }
+        writeln!(w, " cpsr={:08x}", cpsr.get())?;
+        self.lines += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::arm::ArmInstruction;
+    use super::super::thumb::ThumbInstruction;
+    use super::*;
+
+    #[test]
+    fn test_trace_filter() {
+        let filter: TraceFilter = "pc=0x08000000-08000100,mode=irq,mode=svc,class=branch"
+            .parse()
+            .unwrap();
+        assert_eq!(filter.pc_range, Some(0x0800_0000..0x0800_0100));
+        assert_eq!(filter.modes, vec![CpuMode::Irq, CpuMode::Supervisor]);
+        assert_eq!(filter.classes, vec![InsnClass::Branch]);
+
+        // b 0x08000010
+        let branch = DecodedInstruction::Arm(ArmInstruction::new(
+            0xea00_0002,
+            0x0800_0000,
+            ArmFormat::from(0xea00_0002),
+        ));
+        // mov r0, #1
+        let mov = DecodedInstruction::Arm(ArmInstruction::new(
+            0xe3a0_0001,
+            0x0800_0004,
+            ArmFormat::from(0xe3a0_0001),
+        ));
+        // thumb bx lr
+        let bx = DecodedInstruction::Thumb(ThumbInstruction::new(
+            0x4770,
+            0x0800_0100,
+            ThumbFormat::from(0x4770),
+        ));
+        assert_eq!(InsnClass::of(&bx), InsnClass::Branch);
+
+        assert!(filter.matches(&branch, CpuMode::Irq));
+        assert!(!filter.matches(&branch, CpuMode::System));
+        assert!(!filter.matches(&mov, CpuMode::Irq));
+        // the end of the range is exclusive
+        assert!(!filter.matches(&bx, CpuMode::Irq));
+
+        assert!("pc=08000000".parse::<TraceFilter>().is_err());
+        assert!("class=float".parse::<TraceFilter>().is_err());
+        assert_eq!("".parse::<TraceFilter>(), Ok(TraceFilter::default()));
+    }
+}
@@ -0,0 +1,91 @@
+use super::Addr;
+
+/// A suggested number of pipeline flushes between the same one or two addresses before the cpu
+/// is considered stuck. At a few cycles per iteration this is around a second of emulated time.
+pub const DEFAULT_WATCHDOG_LIMIT: u32 = 1 << 22;
+
+/// Detects the cpu spinning in a tight loop it can't leave,
+/// like a `b .` with interrupts disabled or two routines branching to each other.
+///
+/// Only pipeline flushes are looked at, so the common case of sequential execution costs nothing.
+/// Any branch to a third address (including the ones taken by an interrupt handler) resets it,
+/// so idle loops that wait for an interrupt are not reported.
+/// Some games do spin forever on purpose (after a fatal error, or at the end of a test rom), so
+/// it is disabled unless a limit is set.
+#[derive(Clone, Debug)]
+pub struct Watchdog {
+    limit: Option<u32>,
+    /// The last two branch targets
+    targets: [Addr; 2],
+    /// Number of consecutive flushes that went to one of `targets`
+    count: u32,
+}
+
+impl Default for Watchdog {
+    fn default() -> Watchdog {
+        Watchdog {
+            limit: None,
+            targets: [0; 2],
+            count: 0,
+        }
+    }
+}
+
+impl Watchdog {
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    /// `None` disables the watchdog
+    pub fn set_limit(&mut self, limit: Option<u32>) {
+        self.limit = limit;
+        self.count = 0;
+    }
+
+    /// Records a pipeline flush to `target`,
+    /// returns the two looping addresses once the limit is reached
+    #[inline]
+    pub fn on_flush(&mut self, target: Addr) -> Option<(Addr, Addr)> {
+        let limit = self.limit?;
+        if target == self.targets[0] || target == self.targets[1] {
+            self.count = self.count.saturating_add(1);
+            if self.count == limit {
+                return Some((self.targets[0], self.targets[1]));
+            }
+        } else {
+            self.targets = [self.targets[1], target];
+            self.count = 0;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog() {
+        let mut watchdog = Watchdog::default();
+        for _ in 0..1000 {
+            assert_eq!(watchdog.on_flush(0x0800_0100), None);
+        }
+        watchdog.set_limit(Some(100));
+
+        // a loop that calls another routine now and then is fine
+        for i in 0..1000 {
+            assert_eq!(watchdog.on_flush(0x0800_0100), None);
+            if i % 50 == 0 {
+                assert_eq!(watchdog.on_flush(0x0800_0200), None);
+                assert_eq!(watchdog.on_flush(0x0800_0300), None);
+            }
+        }
+
+        let mut stuck = None;
+        for _ in 0..1000 {
+            stuck = stuck.or(watchdog.on_flush(0x0800_0200));
+            stuck = stuck.or(watchdog.on_flush(0x0800_0100));
+        }
+        assert_eq!(stuck, Some((0x0800_0100, 0x0800_0200)));
+    }
+}
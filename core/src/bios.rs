@@ -4,11 +4,14 @@ use super::util::WeakPointer;
 use super::SysBus;
 
 /// Struct representing the sytem ROM
+///
+/// The bios can only be read by its own code. While the cpu runs outside of the bios, reads
+/// return the last opcode the cpu fetched from it, a few games check this as a copy protection.
 #[derive(Clone)]
 pub struct Bios {
     /// Underlying memory
     rom: Box<[u8]>,
-    /// Last read value
+    /// The last word fetched by the cpu
     last_opcode: u32,
     /// Arm pointer - used only to read the PC register
     arm_core: WeakPointer<arm7tdmi::Core<SysBus>>,
@@ -31,6 +34,61 @@ impl Bios {
     fn read_allowed(&self) -> bool {
         self.arm_core.pc < 0x4000
     }
+
+    /// The cpu fetches its opcodes at r15, the data reads of the bios code (literal pools,
+    /// tables) don't change the value the protection returns
+    #[inline]
+    fn is_fetch(&self, addr: Addr) -> bool {
+        (addr ^ self.arm_core.pc) & !3 == 0
+    }
+
+    /// The value reads from outside of the bios return
+    pub(super) fn last_opcode(&self) -> u32 {
+        self.last_opcode
+    }
+
+    pub(super) fn set_last_opcode(&mut self, value: u32) {
+        self.last_opcode = value;
+    }
+}
+
+const BIOS_SIZE: usize = 0x4000;
+
+/// Builds a minimal bios image to use along with the high level emulated software interrupts.
+/// It only contains the exception vectors and the irq handler, which dispatches to the user
+/// handler at [03FFFFFCh] exactly like the real bios does.
+pub fn hle_bios_image() -> Box<[u8]> {
+    #[rustfmt::skip]
+    const VECTORS: [u32; 8] = [
+        0xe3a0f408, // 00h reset: mov pc, #0x08000000
+        0xe1b0f00e, // 04h undefined: movs pc, lr
+        0xe1b0f00e, // 08h swi: movs pc, lr
+        0xe25ef004, // 0Ch prefetch abort: subs pc, lr, #4
+        0xe25ef004, // 10h data abort: subs pc, lr, #4
+        0xe1b0f00e, // 14h reserved: movs pc, lr
+        0xea000042, // 18h irq: b 0x128
+        0xe25ef004, // 1Ch fiq: subs pc, lr, #4
+    ];
+    #[rustfmt::skip]
+    const IRQ_HANDLER: [u32; 6] = [
+        0xe92d500f, // stmfd sp!, {r0-r3, r12, lr}
+        0xe3a00301, // mov r0, #0x04000000
+        0xe28fe000, // add lr, pc, #0
+        0xe510f004, // ldr pc, [r0, #-4]
+        0xe8bd500f, // ldmfd sp!, {r0-r3, r12, lr}
+        0xe25ef004, // subs pc, lr, #4
+    ];
+
+    let mut rom = vec![0; BIOS_SIZE];
+    let mut write_code = |offset: usize, code: &[u32]| {
+        for (i, opcode) in code.iter().enumerate() {
+            let ofs = offset + 4 * i;
+            rom[ofs..ofs + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+    };
+    write_code(0x00, &VECTORS);
+    write_code(0x128, &IRQ_HANDLER);
+    rom.into_boxed_slice()
 }
 
 /// Impl of Bus trait for Bios
@@ -39,8 +97,9 @@ impl Bus for Bios {
     fn read_32(&mut self, addr: Addr) -> u32 {
         if self.read_allowed() {
             let value = self.rom.read_32(addr);
-            // 32-bit read from bios is most probably an opcode fetch
-            self.last_opcode = value;
+            if self.is_fetch(addr) {
+                self.last_opcode = value;
+            }
             value
         } else {
             self.last_opcode
@@ -49,6 +108,10 @@ impl Bus for Bios {
     #[inline]
     fn read_16(&mut self, addr: Addr) -> u16 {
         if self.read_allowed() {
+            if self.is_fetch(addr) {
+                // the bios is on a 32 bit bus, thumb fetches latch the whole word
+                self.last_opcode = self.rom.read_32(addr & !3);
+            }
             self.rom.read_16(addr) as u16
         } else {
             (self.last_opcode >> ((addr & 2) << 3)) as u16
@@ -72,6 +135,7 @@ impl Bus for Bios {
 
 impl DebugRead for Bios {
     fn debug_read_8(&mut self, addr: Addr) -> u8 {
-        self.rom[addr as usize]
+        // past the end of the bios is unmapped memory
+        self.rom.get(addr as usize).copied().unwrap_or(0)
     }
 }
@@ -1,27 +1,25 @@
 use std::fmt;
-use std::fs::{File, OpenOptions};
-use std::io::prelude::*;
-use std::io::SeekFrom;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
 
 use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 use super::BackupMemoryInterface;
-use crate::util::write_bin_file;
-
-#[derive(Debug)]
+use crate::logging;
+use crate::util::read_bin_file;
+
+/// The backup memory of a cartridge along with the save file it is persisted to.
+///
+/// Writes only change the memory, `flush` writes it out when it changed.
+/// The file is replaced atomically, so a crash while saving leaves the previous save intact.
+#[derive(Debug, Clone)]
 pub struct BackupFile {
     size: usize,
     path: Option<PathBuf>,
-    file: Option<File>,
     buffer: Vec<u8>,
-}
-
-impl Clone for BackupFile {
-    fn clone(&self) -> Self {
-        BackupFile::new(self.size, self.path.clone())
-    }
+    dirty: bool,
 }
 
 impl Serialize for BackupFile {
@@ -71,35 +69,24 @@ impl<'de> Deserialize<'de> for BackupFile {
 
 impl BackupFile {
     pub fn new(size: usize, path: Option<PathBuf>) -> BackupFile {
-        // TODO handle errors without unwrap
-        let mut file: Option<File> = None;
-        let buffer = if let Some(path) = &path {
-            if !path.is_file() {
-                write_bin_file(&path, &vec![0xff; size]).unwrap();
-            }
-
-            let mut _file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .open(&path)
-                .unwrap();
-
-            let mut buffer = Vec::new();
-            _file.read_to_end(&mut buffer).unwrap();
-            buffer.resize(size, 0xff);
-
-            file = Some(_file);
-
-            buffer
-        } else {
-            vec![0xff; size]
+        let mut buffer = match &path {
+            Some(path) if path.is_file() => read_bin_file(path).unwrap_or_else(|e| {
+                error!(
+                    target: logging::CARTRIDGE,
+                    "failed to read save file {}: {}",
+                    path.display(), e
+                );
+                Vec::new()
+            }),
+            _ => Vec::new(),
         };
+        buffer.resize(size, 0xff);
 
         BackupFile {
             size,
             path,
-            file: file,
             buffer: buffer,
+            dirty: false,
         }
     }
 
@@ -108,23 +95,53 @@ impl BackupFile {
     }
 
     pub fn bytes_mut(&mut self) -> &mut [u8] {
+        self.dirty = true;
         &mut self.buffer
     }
 
+    /// Returns true if the memory changed since the last flush
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Writes the memory to the save file if it changed since the last flush.
+    /// On failure the memory stays dirty, so the next flush tries again.
     pub fn flush(&mut self) {
-        if let Some(file) = &mut self.file {
-            file.seek(SeekFrom::Start(0)).unwrap();
-            file.write_all(&self.buffer).unwrap();
+        if !self.dirty {
+            return;
+        }
+        if let Some(path) = &self.path {
+            if let Err(e) = write_atomic(path, &self.buffer) {
+                error!(
+                    target: logging::CARTRIDGE,
+                    "failed to write save file {}: {}",
+                    path.display(), e
+                );
+                return;
+            }
         }
+        self.dirty = false;
     }
 }
 
+/// Writes to a temporary file next to `path` and renames it over `path` once it's on disk
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)
+}
+
 impl BackupMemoryInterface for BackupFile {
     fn write(&mut self, offset: usize, value: u8) {
-        self.buffer[offset] = value;
-        if let Some(file) = &mut self.file {
-            file.seek(SeekFrom::Start(offset as u64)).unwrap();
-            file.write_all(&[value]).unwrap();
+        if self.buffer[offset] != value {
+            self.buffer[offset] = value;
+            self.dirty = true;
         }
     }
 
@@ -133,8 +150,10 @@ impl BackupMemoryInterface for BackupFile {
     }
 
     fn resize(&mut self, new_size: usize) {
-        self.size = new_size;
-        self.buffer.resize(new_size, 0xff);
-        self.flush();
+        if new_size != self.size {
+            self.size = new_size;
+            self.buffer.resize(new_size, 0xff);
+            self.dirty = true;
+        }
     }
 }
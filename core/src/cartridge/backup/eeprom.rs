@@ -1,4 +1,5 @@
 use super::{BackupFile, BackupMemoryInterface};
+use crate::logging;
 
 use bytesize;
 use num::FromPrimitive;
@@ -142,7 +143,7 @@ impl EepromChip {
         use SpiState::*;
 
         // Read the si signal into the rx_buffer
-        trace!("({:?}) addr={:#x} RX bit {}", self.state, address, si);
+        trace!(target: logging::CARTRIDGE, "({:?}) addr={:#x} RX bit {}", self.state, address, si);
         self.rx_buffer = (self.rx_buffer << 1) | (if si & 1 != 0 { 1 } else { 0 });
         self.rx_count += 1;
 
@@ -152,18 +153,24 @@ impl EepromChip {
             RxInstruction => {
                 // If instruction was recvd, proceed to recv the address
                 if self.rx_count >= 2 {
-                    let insn = SpiInstruction::from_u64(self.rx_buffer).expect(&format!(
-                        "invalid spi command {:#010b}",
-                        self.rx_buffer as u8
-                    ));
-                    next_state = Some(RxAddress(insn));
+                    match SpiInstruction::from_u64(self.rx_buffer) {
+                        Some(insn) => next_state = Some(RxAddress(insn)),
+                        None => warn!(
+                            target: logging::CARTRIDGE,
+                            "ignoring invalid spi command {:#04b}",
+                            self.rx_buffer
+                        ),
+                    }
                     self.reset_rx_buffer();
                 }
             }
             RxAddress(insn) => {
                 if self.rx_count == self.addr_bits.into() {
-                    self.address = (self.rx_buffer as usize) * 8;
+                    // the 8k chip only decodes the low 10 bits of the 14bit address
+                    let num_blocks = self.memory.bytes().len() / 8;
+                    self.address = (self.rx_buffer as usize % num_blocks) * 8;
                     trace!(
+                        target: logging::CARTRIDGE,
                         "{:?} mode , recvd address = {:#x} (rx_buffer={:#x})",
                         insn,
                         self.address,
@@ -189,7 +196,11 @@ impl EepromChip {
             RxData => {
                 if self.rx_count == 64 {
                     let mut data = self.rx_buffer;
-                    debug!("writing {:#x} to memory address {:#x}", data, self.address);
+                    debug!(
+                        target: logging::CARTRIDGE,
+                        "writing {:#x} to memory address {:#x}",
+                        data, self.address
+                    );
                     for i in 0..8 {
                         self.memory
                             .write(self.address + (7 - i), (data & 0xff) as u8);
@@ -222,7 +233,11 @@ impl EepromChip {
                 if self.tx_count == 4 {
                     next_state = Some(TxData);
                     self.fill_tx_buffer();
-                    trace!("transmitting data bits, tx_buffer = {:#x}", self.tx_buffer);
+                    trace!(
+                        target: logging::CARTRIDGE,
+                        "transmitting data bits, tx_buffer = {:#x}",
+                        self.tx_buffer
+                    );
                 }
                 0
             }
@@ -246,7 +261,11 @@ impl EepromChip {
             }
         };
 
-        trace!("({:?}) addr={:#x} TX bit {}", self.state, address, result);
+        trace!(
+            target: logging::CARTRIDGE,
+            "({:?}) addr={:#x} TX bit {}",
+            self.state, address, result
+        );
         if let Some(next_state) = next_state {
             self.state = next_state;
         }
@@ -291,17 +310,23 @@ impl EepromController {
         if let Some(path) = &path {
             if let Ok(metadata) = fs::metadata(&path) {
                 let human_size = bytesize::ByteSize::b(metadata.len());
-                let assumed_type = match metadata.len() {
-                    512 => EepromType::Eeprom512,
-                    8192 => EepromType::Eeprom8k,
-                    _ => panic!("invalid file size ({}) for eeprom save", human_size),
+                match metadata.len() {
+                    512 => eeprom_type = EepromType::Eeprom512,
+                    8192 => eeprom_type = EepromType::Eeprom8k,
+                    _ => warn!(
+                        target: logging::CARTRIDGE,
+                        "invalid file size ({}) for eeprom save",
+                        human_size
+                    ),
                 };
-                detect = false;
-                info!(
-                    "save file is size {}, assuming eeprom type is {:?}",
-                    human_size, assumed_type
-                );
-                eeprom_type = assumed_type;
+                if metadata.len() == eeprom_type.size() as u64 {
+                    detect = false;
+                    info!(
+                        target: logging::CARTRIDGE,
+                        "save file is size {}, assuming eeprom type is {:?}",
+                        human_size, eeprom_type
+                    );
+                }
             }
         }
 
@@ -319,13 +344,28 @@ impl EepromController {
         }
     }
 
+    pub fn is_dirty(&self) -> bool {
+        self.chip.borrow().memory.is_dirty()
+    }
+
+    pub fn flush(&mut self) {
+        self.chip.borrow_mut().memory.flush();
+    }
+
     pub fn write_half(&mut self, address: u32, value: u16) {
-        assert!(!self.detect);
+        if self.detect {
+            // the size is detected from dma transfers, a game bit-banging the eeprom gets the default
+            warn!(target: logging::CARTRIDGE, "eeprom accessed without dma, assuming 512 bytes");
+            self.detect = false;
+        }
         self.chip.borrow_mut().clock_data_in(address, value as u8);
     }
 
     pub fn read_half(&self, address: u32) -> u16 {
-        assert!(!self.detect);
+        if self.detect {
+            // nothing was requested yet, so the chip just reports it is ready
+            return 1;
+        }
         let mut chip = self.chip.borrow_mut();
         chip.clock_data_out(address) as u16
     }
@@ -337,6 +377,7 @@ impl EepromController {
                 // DMA to EEPROM
                 (_, 0x0d000000..=0x0dffffff) => {
                     debug!(
+                        target: logging::CARTRIDGE,
                         "caught eeprom dma transfer src={:#x} dst={:#x} count={}",
                         src, dst, count
                     );
@@ -349,18 +390,25 @@ impl EepromController {
                         73 => Eeprom512,
                         // Write(11) + 14bit address + 64bit value + stop bit
                         81 => Eeprom8k,
-                        _ => panic!(
-                            "unexpected bit count ({}) when detecting eeprom size",
-                            count
-                        ),
+                        _ => {
+                            warn!(
+                                target: logging::CARTRIDGE,
+                                "unexpected bit count ({}) when detecting eeprom size",
+                                count
+                            );
+                            return;
+                        }
                     };
-                    info!("detected eeprom type: {:?}", eeprom_type);
+                    info!(target: logging::CARTRIDGE, "detected eeprom type: {:?}", eeprom_type);
                     self.chip.borrow_mut().set_type(eeprom_type);
                     self.detect = false;
                 }
                 // EEPROM to DMA
                 (0x0d000000..=0x0dffffff, _) => {
-                    panic!("reading from eeprom when real size is not detected yet is not supported by this emulator")
+                    warn!(
+                        target: logging::CARTRIDGE,
+                        "reading from eeprom before its size was detected"
+                    );
                 }
                 _ => { /* Not a eeprom dma, doing nothing */ }
             }
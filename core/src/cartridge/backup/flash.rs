@@ -1,8 +1,10 @@
 use super::{BackupFile, BackupMemoryInterface};
+use crate::logging;
 
 use num::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
+use std::convert::TryFrom;
 use std::path::PathBuf;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -48,6 +50,56 @@ impl Into<usize> for FlashSize {
     }
 }
 
+/// The flash chip manufacturer, games read the chip ID to pick their flash routines
+/// and some of them only accept the chips they shipped with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlashVendor {
+    Macronix,
+    Panasonic,
+    Sst,
+    Atmel,
+    Sanyo,
+}
+
+impl Default for FlashVendor {
+    fn default() -> FlashVendor {
+        FlashVendor::Macronix
+    }
+}
+
+impl TryFrom<&str> for FlashVendor {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        use FlashVendor::*;
+        match s {
+            "macronix" => Ok(Macronix),
+            "panasonic" => Ok(Panasonic),
+            "sst" => Ok(Sst),
+            "atmel" => Ok(Atmel),
+            "sanyo" => Ok(Sanyo),
+            _ => Err(format!("{} is not a valid flash vendor", s)),
+        }
+    }
+}
+
+impl FlashVendor {
+    /// The (device << 8 | manufacturer) ID reported in ID mode, from GBATEK.
+    /// Only Macronix and Sanyo made 128k chips, the 64k-only vendors fall back to the Macronix 128k chip.
+    fn chip_id(&self, flash_size: &FlashSize) -> u16 {
+        use FlashVendor::*;
+        match (flash_size, self) {
+            (FlashSize::Flash64k, Macronix) => 0x1cc2,
+            (FlashSize::Flash64k, Panasonic) => 0x1b32,
+            (FlashSize::Flash64k, Sst) => 0xd4bf,
+            (FlashSize::Flash64k, Atmel) => 0x3d1f,
+            (FlashSize::Flash64k, Sanyo) => 0x1cc2,
+            (FlashSize::Flash128k, Sanyo) => 0x1362,
+            (FlashSize::Flash128k, _) => 0x09c2,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Flash {
     chip_id: u16,
@@ -59,18 +111,26 @@ pub struct Flash {
     memory: BackupFile,
 }
 
-const MACRONIX_64K_CHIP_ID: u16 = 0x1CC2;
-const MACRONIX_128K_CHIP_ID: u16 = 0x09c2;
-
 const SECTOR_SIZE: usize = 0x1000;
 const BANK_SIZE: usize = 0x10000;
 
 impl Flash {
     pub fn new(flash_path: Option<PathBuf>, flash_size: FlashSize) -> Flash {
-        let chip_id = match flash_size {
-            FlashSize::Flash64k => MACRONIX_64K_CHIP_ID,
-            FlashSize::Flash128k => MACRONIX_128K_CHIP_ID,
-        };
+        Flash::with_vendor(flash_path, flash_size, FlashVendor::default())
+    }
+
+    pub fn with_vendor(
+        flash_path: Option<PathBuf>,
+        flash_size: FlashSize,
+        vendor: FlashVendor,
+    ) -> Flash {
+        let chip_id = vendor.chip_id(&flash_size);
+        if vendor == FlashVendor::Sanyo && chip_id != 0x1362 {
+            warn!(
+                target: logging::CARTRIDGE,
+                "Sanyo only made 128k flash chips, using the Macronix 64k chip ID"
+            );
+        }
 
         let size: usize = flash_size.into();
         let memory = BackupFile::new(size, flash_path);
@@ -85,6 +145,14 @@ impl Flash {
         }
     }
 
+    pub fn is_dirty(&self) -> bool {
+        self.memory.is_dirty()
+    }
+
+    pub fn flush(&mut self) {
+        self.memory.flush();
+    }
+
     fn reset_sequence(&mut self) {
         self.wrseq = FlashWriteSequence::Initial;
     }
@@ -132,11 +200,17 @@ impl Flash {
                     self.wrseq = FlashWriteSequence::Argument;
                 }
                 (addr, command) => {
-                    panic!("[FLASH] Invalid command {:?} addr {:#x}", command, addr);
+                    warn!(
+                        target: logging::CARTRIDGE,
+                        "[FLASH] Invalid command {:?} addr {:#x}",
+                        command, addr
+                    );
+                    self.reset_sequence();
                 }
             };
         } else {
-            panic!("[FLASH] unknown command {:x}", value);
+            warn!(target: logging::CARTRIDGE, "[FLASH] unknown command {:x}", value);
+            self.reset_sequence();
         }
     }
 
@@ -153,7 +227,7 @@ impl Flash {
             match offset {
                 0 => (self.chip_id & 0xff) as u8,
                 1 => (self.chip_id >> 8) as u8,
-                _ => panic!("Tried to read invalid flash offset while reading chip ID"),
+                _ => self.memory.read(self.flash_offset(offset)),
             }
         } else {
             self.memory.read(self.flash_offset(offset))
@@ -163,7 +237,7 @@ impl Flash {
     }
 
     pub fn write(&mut self, addr: u32, value: u8) {
-        trace!("[FLASH] write {:#x}={:#x}", addr, value);
+        trace!(target: logging::CARTRIDGE, "[FLASH] write {:#x}={:#x}", addr, value);
         match self.wrseq {
             FlashWriteSequence::Initial => {
                 if addr == 0x0E00_5555 && value == 0xAA {
@@ -185,11 +259,16 @@ impl Flash {
                             .write(self.flash_offset((addr & 0xffff) as usize), value);
                     }
                     FlashMode::Select => {
+                        // 64k chips have a single bank and ignore the command
                         if addr == 0x0E00_0000 {
-                            self.bank = value as usize;
+                            self.bank = (value as usize) & (self.size / BANK_SIZE - 1);
                         }
                     }
-                    _ => panic!("Flash sequence is invalid"),
+                    _ => warn!(
+                        target: logging::CARTRIDGE,
+                        "[FLASH] write to {:#x} in mode {:?}",
+                        addr, self.mode
+                    ),
                 };
                 self.mode = FlashMode::Initial;
                 self.reset_sequence();
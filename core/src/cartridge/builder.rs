@@ -1,7 +1,7 @@
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
 
 use memmem::{Searcher, TwoWaySearcher};
-use num::FromPrimitive;
 
 use super::super::overrides;
 use super::super::{GBAError, GBAResult};
@@ -10,27 +10,51 @@ use super::backup::flash::*;
 use super::backup::{BackupFile, BackupType};
 use super::gpio::Gpio;
 use super::header;
+use super::tilt::TiltSensor;
 use super::BackupMedia;
 use super::Cartridge;
+use crate::logging;
 
 use super::loader::{load_from_bytes, load_from_file, LoadRom};
 
-#[derive(Debug)]
-#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum GpioDeviceType {
     Rtc,
+    /// Comes with an RTC as well
     SolarSensor,
+    /// Comes with a rumble motor as well
     Gyro,
+    Rumble,
     None,
 }
 
+impl TryFrom<&str> for GpioDeviceType {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        use GpioDeviceType::*;
+        match s {
+            "rtc" => Ok(Rtc),
+            "solar" => Ok(SolarSensor),
+            "gyro" => Ok(Gyro),
+            "rumble" => Ok(Rumble),
+            _ => Err(format!("{} is not a valid gpio device", s)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct GamepakBuilder {
     path: Option<PathBuf>,
     bytes: Option<Box<[u8]>>,
     save_path: Option<PathBuf>,
+    save_dir: Option<PathBuf>,
     save_type: BackupType,
+    flash_vendor: FlashVendor,
     gpio_device: GpioDeviceType,
+    tilt_sensor: bool,
+    mirror_rom: bool,
+    multiboot: bool,
     create_backup_file: bool,
 }
 
@@ -38,10 +62,15 @@ impl GamepakBuilder {
     pub fn new() -> GamepakBuilder {
         GamepakBuilder {
             save_type: BackupType::AutoDetect,
+            flash_vendor: FlashVendor::default(),
             path: None,
             save_path: None,
+            save_dir: None,
             bytes: None,
             gpio_device: GpioDeviceType::None,
+            tilt_sensor: false,
+            mirror_rom: false,
+            multiboot: false,
             create_backup_file: true,
         }
     }
@@ -66,11 +95,23 @@ impl GamepakBuilder {
         self
     }
 
+    /// Keeps the save file in `dir` instead of next to the rom, `save_path` takes priority
+    pub fn save_dir(mut self, dir: &Path) -> Self {
+        self.save_dir = Some(dir.to_path_buf());
+        self
+    }
+
     pub fn save_type(mut self, save_type: BackupType) -> Self {
         self.save_type = save_type;
         self
     }
 
+    /// The chip ID games see when the backup is flash
+    pub fn flash_vendor(mut self, vendor: FlashVendor) -> Self {
+        self.flash_vendor = vendor;
+        self
+    }
+
     pub fn with_sram(mut self) -> Self {
         self.save_type = BackupType::Sram;
         self
@@ -101,54 +142,121 @@ impl GamepakBuilder {
         self
     }
 
+    pub fn with_solar_sensor(mut self) -> Self {
+        self.gpio_device = GpioDeviceType::SolarSensor;
+        self
+    }
+
+    pub fn with_gyro(mut self) -> Self {
+        self.gpio_device = GpioDeviceType::Gyro;
+        self
+    }
+
+    pub fn with_rumble(mut self) -> Self {
+        self.gpio_device = GpioDeviceType::Rumble;
+        self
+    }
+
+    pub fn with_tilt_sensor(mut self) -> Self {
+        self.tilt_sensor = true;
+        self
+    }
+
+    /// Makes reads past the end of the ROM wrap around, like on the Classic NES Series carts
+    pub fn with_rom_mirroring(mut self) -> Self {
+        self.mirror_rom = true;
+        self
+    }
+
+    /// Loads the rom as a multiboot image, files with the .mb extension are detected as such.
+    /// The image runs from EWRAM with the cartridge slot empty.
+    pub fn multiboot(mut self) -> Self {
+        self.multiboot = true;
+        self
+    }
+
     pub fn build(mut self) -> GBAResult<Cartridge> {
-        let (bytes, symbols) = if let Some(bytes) = self.bytes {
-            match load_from_bytes(bytes.to_vec())? {
-                #[cfg(feature = "elf_support")]
-                LoadRom::Elf { data, symbols } => Ok((data, Some(symbols))),
-                LoadRom::Raw(data) => Ok((data, None)),
-            }
+        let loaded = if let Some(bytes) = self.bytes {
+            load_from_bytes(bytes.to_vec())
         } else if let Some(path) = &self.path {
-            match load_from_file(&path)? {
-                #[cfg(feature = "elf_support")]
-                LoadRom::Elf { data, symbols } => Ok((data, Some(symbols))),
-                LoadRom::Raw(data) => Ok((data, None)),
-            }
+            load_from_file(&path)
         } else {
             Err(GBAError::CartridgeLoadError(
                 "either provide file() or buffer()".to_string(),
             ))
         }?;
+        let loaded = match loaded {
+            LoadRom::Raw(data) if self.multiboot => LoadRom::Multiboot(data),
+            loaded => loaded,
+        };
+        let (bytes, symbols, ram_segments, multiboot, entry_point) = match loaded {
+            #[cfg(feature = "elf_support")]
+            LoadRom::Elf {
+                data,
+                symbols,
+                ram_segments,
+                entry_point,
+            } => (data, Some(symbols), ram_segments, false, entry_point),
+            LoadRom::Multiboot(image) => {
+                if image.len() > header::MULTIBOOT_MAX_SIZE {
+                    return Err(GBAError::CartridgeLoadError(format!(
+                        "multiboot image is {} bytes, it must fit in the {} bytes of EWRAM",
+                        image.len(),
+                        header::MULTIBOOT_MAX_SIZE
+                    )));
+                }
+                let segments = vec![(header::MULTIBOOT_LOAD_ADDR, image)];
+                let entry_point = header::MULTIBOOT_ENTRY_POINT;
+                (Vec::new(), None, segments, true, entry_point)
+            }
+            LoadRom::Raw(data) => (data, None, Vec::new(), false, header::CARTRIDGE_ENTRY_POINT),
+        };
 
-        let header = header::parse(&bytes)?;
-        info!("Loaded ROM: {:?}", header);
+        let header = if multiboot {
+            header::parse(&ram_segments[0].1)?
+        } else {
+            header::parse(&bytes)?
+        };
+        info!(target: logging::CARTRIDGE, "Loaded ROM: {:?}", header);
 
-        if !self.create_backup_file {
+        if !self.create_backup_file || multiboot {
             self.save_path = None;
         } else if self.save_path.is_none() {
             if let Some(path) = &self.path {
-                self.save_path = Some(path.with_extension(BACKUP_FILE_EXT));
+                let save_path = path.with_extension(BACKUP_FILE_EXT);
+                self.save_path = match (&self.save_dir, save_path.file_name()) {
+                    (Some(dir), Some(file_name)) => Some(dir.join(file_name)),
+                    _ => Some(save_path),
+                };
             } else {
-                warn!("can't create save file as no save path was provided")
+                warn!(
+                    target: logging::CARTRIDGE,
+                    "can't create save file as no save path was provided"
+                )
             }
         }
 
         let mut save_type = self.save_type;
         let mut gpio_device = self.gpio_device;
+        let mut tilt_sensor = self.tilt_sensor;
+        let mut mirror_rom = self.mirror_rom;
 
         if let Some(overrides) = overrides::get_game_overrides(&header.game_code) {
             info!(
+                target: logging::CARTRIDGE,
                 "Found game overrides for {}: {:#?}",
                 header.game_code, overrides
             );
             if let Some(override_save_type) = overrides.save_type() {
-                if override_save_type != save_type && save_type != BackupType::AutoDetect {
+                if save_type == BackupType::AutoDetect {
+                    save_type = override_save_type;
+                } else if override_save_type != save_type {
                     warn!(
+                        target: logging::CARTRIDGE,
                         "Forced save type {:?} takes priority of {:?}",
                         save_type, override_save_type
                     );
                 }
-                save_type = override_save_type;
             }
 
             if overrides.force_rtc() {
@@ -157,74 +265,150 @@ impl GamepakBuilder {
                     GpioDeviceType::Rtc => {}
                     _ => {
                         warn!(
+                            target: logging::CARTRIDGE,
                             "Can't use RTC due to forced gpio device type {:?}",
                             gpio_device
                         );
                     }
                 }
             }
+
+            if let Some(override_gpio_device) = overrides.gpio_device() {
+                if gpio_device == GpioDeviceType::None {
+                    gpio_device = override_gpio_device;
+                } else if override_gpio_device != gpio_device {
+                    warn!(
+                        target: logging::CARTRIDGE,
+                        "Forced gpio device {:?} takes priority of {:?}",
+                        gpio_device, override_gpio_device
+                    );
+                }
+            }
+
+            tilt_sensor |= overrides.tilt_sensor();
+            mirror_rom |= overrides.mirror_rom();
         }
 
-        if save_type == BackupType::AutoDetect {
+        if save_type == BackupType::AutoDetect && !multiboot {
             if let Some(detected) = detect_backup_type(&bytes) {
-                info!("Detected Backup: {:?}", detected);
+                info!(target: logging::CARTRIDGE, "Detected Backup: {:?}", detected);
                 save_type = detected;
             } else {
-                warn!("could not detect backup save type");
+                warn!(target: logging::CARTRIDGE, "could not detect backup save type");
             }
         }
 
-        let backup = create_backup(save_type, self.save_path);
+        let backup = create_backup(save_type, self.flash_vendor, self.save_path.clone());
 
-        let gpio = match gpio_device {
+        let mut gpio = match gpio_device {
             GpioDeviceType::None => None,
             GpioDeviceType::Rtc => {
-                info!("Emulating RTC!");
+                info!(target: logging::CARTRIDGE, "Emulating RTC!");
                 Some(Gpio::new_rtc())
             }
-            _ => unimplemented!("Gpio device {:?} not implemented", gpio_device),
+            GpioDeviceType::SolarSensor => {
+                info!(target: logging::CARTRIDGE, "Emulating RTC and solar sensor!");
+                Some(Gpio::new_solar_sensor())
+            }
+            GpioDeviceType::Gyro => {
+                info!(target: logging::CARTRIDGE, "Emulating gyro sensor and rumble!");
+                Some(Gpio::new_gyro())
+            }
+            GpioDeviceType::Rumble => {
+                info!(target: logging::CARTRIDGE, "Emulating rumble!");
+                Some(Gpio::new_rumble())
+            }
         };
+        if let (Some(rtc), Some(save_path)) = (
+            gpio.as_mut().and_then(|gpio| gpio.rtc.as_mut()),
+            &self.save_path,
+        ) {
+            rtc.set_save_path(save_path.with_extension(RTC_FILE_EXT));
+        }
 
         let size = bytes.len();
         Ok(Cartridge {
             header: header,
             gpio: gpio,
+            tilt_sensor: if tilt_sensor {
+                info!(target: logging::CARTRIDGE, "Emulating tilt sensor!");
+                Some(TiltSensor::default())
+            } else {
+                None
+            },
             bytes: bytes.into_boxed_slice(),
             size: size,
+            mirror_rom: mirror_rom,
             backup: backup,
             symbols: symbols,
+            ram_segments,
+            multiboot,
+            entry_point,
+            // the multiboot images run without a cartridge
+            removed: multiboot,
         })
     }
 }
 
 const BACKUP_FILE_EXT: &'static str = "sav";
-fn create_backup(backup_type: BackupType, rom_path: Option<PathBuf>) -> BackupMedia {
-    let backup_path = if let Some(rom_path) = rom_path {
-        Some(rom_path.with_extension(BACKUP_FILE_EXT))
-    } else {
-        None
-    };
+const RTC_FILE_EXT: &'static str = "rtc";
+fn create_backup(
+    backup_type: BackupType,
+    flash_vendor: FlashVendor,
+    backup_path: Option<PathBuf>,
+) -> BackupMedia {
     match backup_type {
-        BackupType::Flash | BackupType::Flash512 => {
-            BackupMedia::Flash(Flash::new(backup_path, FlashSize::Flash64k))
-        }
-        BackupType::Flash1M => BackupMedia::Flash(Flash::new(backup_path, FlashSize::Flash128k)),
+        BackupType::Flash | BackupType::Flash512 => BackupMedia::Flash(Flash::with_vendor(
+            backup_path,
+            FlashSize::Flash64k,
+            flash_vendor,
+        )),
+        BackupType::Flash1M => BackupMedia::Flash(Flash::with_vendor(
+            backup_path,
+            FlashSize::Flash128k,
+            flash_vendor,
+        )),
         BackupType::Sram => BackupMedia::Sram(BackupFile::new(0x8000, backup_path)),
         BackupType::Eeprom => BackupMedia::Eeprom(EepromController::new(backup_path)),
         BackupType::AutoDetect => BackupMedia::Undetected,
     }
 }
 
+/// The library ID strings the Nintendo SDK links into roms, along with the backup chip they drive.
+/// The `_V` suffix (followed by the library version) keeps game text like "SRAM" from matching.
+const BACKUP_ID_STRINGS: [(&'static str, BackupType); 6] = [
+    ("EEPROM_V", BackupType::Eeprom),
+    ("SRAM_V", BackupType::Sram),
+    ("SRAM_F_V", BackupType::Sram),
+    ("FLASH_V", BackupType::Flash),
+    ("FLASH512_V", BackupType::Flash512),
+    ("FLASH1M_V", BackupType::Flash1M),
+];
+
 fn detect_backup_type(bytes: &[u8]) -> Option<BackupType> {
-    const ID_STRINGS: &'static [&'static str] =
-        &["EEPROM", "SRAM", "FLASH_", "FLASH512_", "FLASH1M_"];
-
-    for i in 0..5 {
-        let search = TwoWaySearcher::new(ID_STRINGS[i].as_bytes());
-        match search.search_in(bytes) {
-            Some(_) => return Some(BackupType::from_u8(i as u8).unwrap()),
-            _ => {}
-        }
+    BACKUP_ID_STRINGS
+        .iter()
+        .find(|(id, _)| {
+            TwoWaySearcher::new(id.as_bytes())
+                .search_in(bytes)
+                .is_some()
+        })
+        .map(|&(_, backup_type)| backup_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_backup_type() {
+        let mut rom = vec![0u8; 0x1000];
+        assert_eq!(detect_backup_type(&rom), None);
+
+        rom[0x100..0x10a].copy_from_slice(b"SRAM saves");
+        assert_eq!(detect_backup_type(&rom), None);
+
+        rom[0x800..0x80c].copy_from_slice(b"FLASH1M_V103");
+        assert_eq!(detect_backup_type(&rom), Some(BackupType::Flash1M));
     }
-    None
 }
@@ -1,4 +1,6 @@
+use super::gyro::Gyro;
 use super::rtc::Rtc;
+use super::solar::SolarSensor;
 use super::{GPIO_PORT_CONTROL, GPIO_PORT_DATA, GPIO_PORT_DIRECTION};
 
 use bit::BitIndex;
@@ -28,6 +30,10 @@ pub trait GpioDevice: Sized {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Gpio {
     pub(in crate) rtc: Option<Rtc>,
+    pub(in crate) solar_sensor: Option<SolarSensor>,
+    pub(in crate) gyro: Option<Gyro>,
+    /// State of the rumble motor driven by pin 3, `None` for carts without one
+    rumble: Option<bool>,
     direction: GpioState,
     control: GpioPortControl,
 }
@@ -36,6 +42,9 @@ impl Gpio {
     pub fn new_none() -> Self {
         Gpio {
             rtc: None,
+            solar_sensor: None,
+            gyro: None,
+            rumble: None,
             direction: [GpioDirection::Out; 4],
             control: GpioPortControl::WriteOnly,
         }
@@ -44,11 +53,38 @@ impl Gpio {
     pub fn new_rtc() -> Self {
         Gpio {
             rtc: Some(Rtc::new()),
-            direction: [GpioDirection::Out; 4],
-            control: GpioPortControl::WriteOnly,
+            ..Gpio::new_none()
+        }
+    }
+
+    /// The Boktai carts have an RTC next to the light sensor
+    pub fn new_solar_sensor() -> Self {
+        Gpio {
+            rtc: Some(Rtc::new()),
+            solar_sensor: Some(SolarSensor::default()),
+            ..Gpio::new_none()
+        }
+    }
+
+    pub fn new_gyro() -> Self {
+        Gpio {
+            gyro: Some(Gyro::default()),
+            rumble: Some(false),
+            ..Gpio::new_none()
+        }
+    }
+
+    pub fn new_rumble() -> Self {
+        Gpio {
+            rumble: Some(false),
+            ..Gpio::new_none()
         }
     }
 
+    pub fn rumble(&self) -> bool {
+        self.rumble.unwrap_or(false)
+    }
+
     pub fn is_readable(&self) -> bool {
         self.control != GpioPortControl::WriteOnly
     }
@@ -56,11 +92,17 @@ impl Gpio {
     pub fn read(&self, addr: u32) -> u16 {
         match addr {
             GPIO_PORT_DATA => {
+                let mut data = 0;
                 if let Some(rtc) = &self.rtc {
-                    rtc.read(&self.direction)
-                } else {
-                    0
+                    data |= rtc.read(&self.direction);
                 }
+                if let Some(solar_sensor) = &self.solar_sensor {
+                    data |= solar_sensor.read(&self.direction);
+                }
+                if let Some(gyro) = &self.gyro {
+                    data |= gyro.read(&self.direction);
+                }
+                data
             }
             GPIO_PORT_DIRECTION => {
                 let mut direction = 0u16;
@@ -80,6 +122,17 @@ impl Gpio {
                 if let Some(rtc) = &mut self.rtc {
                     rtc.write(&self.direction, value);
                 }
+                if let Some(solar_sensor) = &mut self.solar_sensor {
+                    solar_sensor.write(&self.direction, value);
+                }
+                if let Some(gyro) = &mut self.gyro {
+                    gyro.write(&self.direction, value);
+                }
+                if let Some(rumble) = &mut self.rumble {
+                    if self.direction[3] == GpioDirection::Out {
+                        *rumble = value.bit(3);
+                    }
+                }
             }
             GPIO_PORT_DIRECTION => {
                 for i in 0..4 {
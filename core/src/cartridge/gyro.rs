@@ -0,0 +1,52 @@
+use bit::BitIndex;
+use serde::{Deserialize, Serialize};
+
+use super::gpio::{GpioDevice, GpioDirection, GpioState};
+
+/// The reading when the cartridge isn't rotating
+const GYRO_CENTER: i32 = 0x6c0;
+
+/// The gyro sensor of WarioWare Twisted.
+///
+/// While pin 0 is high the rotation rate is sampled, it is then shifted out on pin 2
+/// msb first, one bit for each falling edge of the pin 1 clock.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Gyro {
+    /// Rotation rate around the axis facing the player, positive is clockwise
+    rotation: i16,
+    sample: u16,
+    clock: bool,
+    output: bool,
+}
+
+impl Gyro {
+    pub fn rotation(&self) -> i16 {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, rotation: i16) {
+        self.rotation = rotation;
+    }
+}
+
+impl GpioDevice for Gyro {
+    fn write(&mut self, _gpio_state: &GpioState, data: u16) {
+        if data.bit(0) {
+            self.sample = (GYRO_CENTER + (self.rotation as i32 >> 5)) as u16;
+        }
+        let clock = data.bit(1);
+        if self.clock && !clock {
+            self.output = self.sample.bit(15);
+            self.sample <<= 1;
+        }
+        self.clock = clock;
+    }
+
+    fn read(&self, gpio_state: &GpioState) -> u16 {
+        if gpio_state[2] == GpioDirection::In && self.output {
+            1 << 2
+        } else {
+            0
+        }
+    }
+}
@@ -1,7 +1,23 @@
+use super::super::bus::Addr;
+use super::super::{GBAError, GBAResult};
+use crate::logging;
 use serde::{Deserialize, Serialize};
-use std::str::from_utf8;
 
-use super::super::{GBAError, GBAResult};
+/// Where the bios jumps to after the boot sequence
+pub const CARTRIDGE_ENTRY_POINT: Addr = 0x0800_0000;
+/// Multiboot images are loaded at the start of EWRAM
+pub const MULTIBOOT_LOAD_ADDR: Addr = 0x0200_0000;
+/// The largest multiboot image, the size of EWRAM
+pub const MULTIBOOT_MAX_SIZE: usize = 0x4_0000;
+/// Where the bios starts a multiboot image, the RAM entry point of its header
+pub const MULTIBOOT_ENTRY_POINT: Addr = 0x0200_00c0;
+/// The header offset of the boot mode the bios fills in
+pub const MULTIBOOT_BOOT_MODE: usize = 0xc4;
+/// The header offset of the slave ID the bios fills in, 1 to 3 for the children of a link
+pub const MULTIBOOT_SLAVE_ID: usize = 0xc5;
+/// Boot mode of the images sent through the multiplayer link, the others are 1 for JOY Bus and
+/// 2 for normal mode
+pub const MULTIBOOT_MODE_MULTIPLAY: u8 = 3;
 
 /// From GBATEK
 ///
@@ -27,19 +43,51 @@ use super::super::{GBAError, GBAResult};
 ///   0C5h    1     Slave ID Number  (init as 00h - BIOS overwrites this value!)
 ///   0C6h    26    Not used         (seems to be unused)
 ///   0E0h    4     JOYBUS Entry Pt. (32bit ARM branch opcode, eg. "B joy_start")
-///
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CartridgeHeader {
-    // rom_entry_point: Addr,
+    /// The branch opcode at 000h, not an address
+    pub rom_entry_point: u32,
+    /// NUL padding is trimmed off the string fields
     pub game_title: String,
     pub game_code: String,
     pub maker_code: String,
+    pub main_unit_code: u8,
+    pub device_type: u8,
     pub software_version: u8,
     pub checksum: u8,
-    // ram_entry_point: Addr,
-    // joybus_entry_point: Addr,
+    /// Whether the complement check matches the header contents
+    pub checksum_ok: bool,
 }
 
+impl CartridgeHeader {
+    /// The address the ROM entry point branches to, if it is an ARM `B` opcode
+    pub fn rom_entry_address(&self) -> Option<Addr> {
+        if self.rom_entry_point & 0x0f00_0000 != 0x0a00_0000 {
+            return None;
+        }
+        let offset = ((self.rom_entry_point << 8) as i32 >> 6) as u32;
+        Some(0x0800_0008u32.wrapping_add(offset))
+    }
+}
+
+/// The compressed Nintendo logo bitmap, the bios compares it against the cartridge header on boot
+#[rustfmt::skip]
+const NINTENDO_LOGO: [u8; 156] = [
+    0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A,
+    0x84, 0xE4, 0x09, 0xAD, 0x11, 0x24, 0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21,
+    0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09, 0xCE, 0x20, 0x10, 0x46, 0x4A, 0x4A,
+    0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82, 0xE3, 0xCE, 0xBF,
+    0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B, 0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0,
+    0x13, 0x72, 0xA7, 0xFC, 0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61,
+    0x58, 0x97, 0xA3, 0x27, 0xFC, 0x03, 0x98, 0x76, 0x23, 0x1D, 0xC7, 0x61,
+    0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38, 0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD,
+    0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97, 0xFB, 0xC0, 0x85,
+    0x60, 0xD6, 0x80, 0x25, 0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2,
+    0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E, 0x03, 0x44, 0x78, 0x00, 0x90, 0xCB,
+    0x88, 0x11, 0x3A, 0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF,
+    0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A, 0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07,
+];
+
 fn calculate_checksum(bytes: &[u8]) -> u8 {
     bytes
         .iter()
@@ -48,6 +96,20 @@ fn calculate_checksum(bytes: &[u8]) -> u8 {
         .wrapping_sub(0x19)
 }
 
+/// Performs the same header checks the bios does before booting a cartridge:
+/// the Nintendo logo, the fixed value at 0B2h and the complement check.
+/// Real hardware would refuse to boot a cartridge that fails any of these.
+pub fn verify_boot_checks(bytes: &[u8]) -> bool {
+    if bytes.len() < 0xc0 {
+        return false;
+    }
+    let logo_ok = bytes[0x04..0xa0] == NINTENDO_LOGO[..];
+    let fixed_value_ok = bytes[0xb2] == 0x96;
+    let checksum_ok = calculate_checksum(&bytes[0xa0..=0xbc]) == bytes[0xbd];
+
+    logo_ok && fixed_value_ok && checksum_ok
+}
+
 pub fn parse(bytes: &[u8]) -> GBAResult<CartridgeHeader> {
     if bytes.len() < 0xc0 {
         return Err(GBAError::CartridgeLoadError(
@@ -59,32 +121,70 @@ pub fn parse(bytes: &[u8]) -> GBAResult<CartridgeHeader> {
     let calculated_checksum = calculate_checksum(&bytes[0xa0..=0xbc]);
     if calculated_checksum != checksum {
         warn!(
+            target: logging::CARTRIDGE,
             "invalid header checksum, calculated {:02x} but expected {:02x}",
             calculated_checksum, checksum
         );
     }
 
-    let game_title = from_utf8(&bytes[0xa0..0xac])
-        .map_err(|_| GBAError::CartridgeLoadError("invalid game title".to_string()))?;
-
-    let game_code = from_utf8(&bytes[0xac..0xb0])
-        .map_err(|_| GBAError::CartridgeLoadError("invalid game code".to_string()))?;
-
-    let maker_code = from_utf8(&bytes[0xb0..0xb2])
-        .map_err(|_| GBAError::CartridgeLoadError("invalid marker code".to_string()))?;
+    if bytes[0xb2] != 0x96 {
+        warn!(
+            target: logging::CARTRIDGE,
+            "invalid header fixed value {:02x}, expected 96",
+            bytes[0xb2]
+        );
+    }
 
-    // let (_, rom_entry_point) = le_u32(bytes).unwrap();
-    // let (_, ram_entry_point) = le_u32(&bytes[0xc0..]).unwrap();
-    // let (_, joybus_entry_point) = le_u32(&bytes[0xc0..]).unwrap();
+    let mut rom_entry_point = [0; 4];
+    rom_entry_point.copy_from_slice(&bytes[0..4]);
 
     Ok(CartridgeHeader {
-        // rom_entry_point: rom_entry_point,
-        game_title: String::from(game_title),
-        game_code: String::from(game_code),
-        maker_code: String::from(maker_code),
+        rom_entry_point: u32::from_le_bytes(rom_entry_point),
+        game_title: header_string(&bytes[0xa0..0xac]),
+        game_code: header_string(&bytes[0xac..0xb0]),
+        maker_code: header_string(&bytes[0xb0..0xb2]),
+        main_unit_code: bytes[0xb3],
+        device_type: bytes[0xb4],
         software_version: bytes[0xbc],
         checksum: checksum,
-        // ram_entry_point: ram_entry_point,
-        // joybus_entry_point: joybus_entry_point,
+        checksum_ok: calculated_checksum == checksum,
     })
 }
+
+/// Homebrew headers are often zero filled or contain garbage, which shouldn't stop the rom from loading
+fn header_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let mut bytes = [0u8; 0xc0];
+        bytes[0..4].copy_from_slice(&0xea00_002eu32.to_le_bytes());
+        bytes[0x04..0xa0].copy_from_slice(&NINTENDO_LOGO);
+        bytes[0xa0..0xa4].copy_from_slice(b"TEST");
+        bytes[0xac..0xb0].copy_from_slice(b"ATSE");
+        bytes[0xb0..0xb2].copy_from_slice(b"01");
+        bytes[0xb2] = 0x96;
+        bytes[0xbd] = calculate_checksum(&bytes[0xa0..=0xbc]);
+
+        let header = parse(&bytes).unwrap();
+        assert_eq!(header.game_title, "TEST");
+        assert_eq!(header.game_code, "ATSE");
+        assert_eq!(header.maker_code, "01");
+        assert!(header.checksum_ok);
+        assert_eq!(header.rom_entry_address(), Some(0x0800_00c0));
+        assert!(verify_boot_checks(&bytes));
+
+        bytes[0xa0] = 0xff;
+        let header = parse(&bytes).unwrap();
+        assert!(!header.checksum_ok);
+        assert!(!verify_boot_checks(&bytes));
+    }
+}
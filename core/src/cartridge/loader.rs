@@ -1,4 +1,5 @@
 use super::super::{GBAError, GBAResult};
+use crate::logging;
 
 #[cfg(feature = "elf_support")]
 use std::collections::HashMap;
@@ -17,7 +18,13 @@ pub enum LoadRom {
     Elf {
         data: Vec<u8>,
         symbols: HashMap<String, u32>,
+        /// Segments that go to EWRAM or IWRAM, by address
+        ram_segments: Vec<(u32, Vec<u8>)>,
+        /// `e_entry`, with the thumb bit of a thumb entry point
+        entry_point: u32,
     },
+    /// An image that runs from EWRAM without a cartridge, received through the link cable
+    Multiboot(Vec<u8>),
     Raw(Vec<u8>),
 }
 type LoadRomResult = GBAResult<LoadRom>;
@@ -34,70 +41,110 @@ fn try_load_zip(data: &[u8]) -> LoadRomResult {
     let mut archive = ZipArchive::new(reader)?;
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
-        if file.name().ends_with(".gba") {
+        let multiboot = file.name().ends_with(".mb");
+        if file.name().ends_with(".gba") || multiboot {
             let mut buf = Vec::new();
             file.read_to_end(&mut buf)?;
-            return Ok(LoadRom::Raw(buf));
+            return Ok(if multiboot {
+                LoadRom::Multiboot(buf)
+            } else {
+                LoadRom::Raw(buf)
+            });
         }
     }
     Err(GBAError::CartridgeLoadError(
-        "no .gba files found within the zip archive".to_owned(),
+        "no .gba or .mb files found within the zip archive".to_owned(),
     ))
 }
 
+/// The named functions, objects and labels of an elf file. The mapping symbols (`$a`, `$t`, `$d`)
+/// are skipped, and the thumb bit is cleared from the addresses of thumb functions.
+#[cfg(feature = "elf_support")]
+pub(crate) fn elf_symbols(elf: &goblin::elf::Elf) -> HashMap<String, u32> {
+    use goblin::elf::sym::{STT_FUNC, STT_NOTYPE, STT_OBJECT};
+
+    let mut symbols = HashMap::new();
+    for sym in elf.syms.iter() {
+        let typ = sym.st_type();
+        if typ != STT_FUNC && typ != STT_OBJECT && typ != STT_NOTYPE {
+            continue;
+        }
+        match elf.strtab.get(sym.st_name) {
+            Some(Ok(name)) if name.is_empty() || name.starts_with('$') => {}
+            Some(Ok(name)) => {
+                // TODO do I also want to save the symbol size ?
+                let mut addr = sym.st_value as u32;
+                if typ == STT_FUNC {
+                    addr &= !1;
+                }
+                symbols.insert(name.to_owned(), addr);
+            }
+            _ => warn!(target: logging::CARTRIDGE, "failed to parse symbol name sym {:?}", sym),
+        }
+    }
+    symbols
+}
+
 #[cfg(feature = "elf_support")]
 fn try_load_elf(elf_bytes: &[u8]) -> LoadRomResult {
     const CART_BASE: usize = 0x0800_0000;
+    const CART_SIZE: usize = 0x0200_0000;
 
     let elf = goblin::elf::Elf::parse(&elf_bytes)?;
 
-    let entry = elf.entry;
-    if entry != (CART_BASE as u64) {
-        return Err(GBAError::CartridgeLoadError(
-            "bad elf entry point, maybe multiboot rom ?".to_owned(),
-        ));
+    // the images linked to run from RAM (multiboot) start there as well
+    match elf.entry >> 24 {
+        0x02 | 0x03 | 0x08 | 0x09 => {}
+        _ => {
+            return Err(GBAError::CartridgeLoadError(format!(
+                "elf entry point {:#x} is not in the ROM, EWRAM or IWRAM",
+                elf.entry
+            )))
+        }
     }
 
-    let mut rom = vec![0; 0x200_0000];
+    let mut rom = vec![0; CART_SIZE];
+    let mut rom_end = 0;
+    let mut ram_segments = Vec::new();
+    // Segments are placed at their load address, so initialized data that the startup code copies
+    // to RAM stays in the ROM, where the copy loop expects it
     for phdr in &elf.program_headers {
-        if phdr.p_type == goblin::elf::program_header::PT_LOAD {
-            let file_range = phdr.file_range();
-            let phys_range =
-                (phdr.p_paddr as usize)..(phdr.p_paddr as usize + phdr.p_memsz as usize);
-            let phys_range_adjusted = (phdr.p_paddr as usize - CART_BASE)
-                ..(phdr.p_paddr as usize + phdr.p_memsz as usize - CART_BASE);
-
-            if phys_range_adjusted.start + (phdr.p_filesz as usize) >= rom.len() {
-                warn!("ELF: skipping program header {:?}", phdr);
+        if phdr.p_type != goblin::elf::program_header::PT_LOAD || phdr.p_filesz == 0 {
+            continue;
+        }
+        let file_range = phdr.file_range();
+        let addr = phdr.p_paddr as usize;
+        let src = match elf_bytes.get(file_range.clone()) {
+            Some(src) => src,
+            None => {
+                warn!(target: logging::CARTRIDGE, "ELF: truncated program header {:?}", phdr);
                 continue;
             }
-
-            info!(
-                "ELF: loading segment phdr: {:?} range {:#x?} vec range {:#x?}",
-                phdr, file_range, phys_range,
-            );
-
-            let src = &elf_bytes[file_range];
-            let dst = &mut rom[phys_range_adjusted];
-            dst.copy_from_slice(src);
-        }
-    }
-
-    let mut symbols = HashMap::new();
-
-    let strtab = elf.strtab;
-    for sym in elf.syms.iter() {
-        if let Some(Ok(name)) = strtab.get(sym.st_name) {
-            // TODO do I also want to save the symbol size ?
-            symbols.insert(name.to_owned(), sym.st_value as u32);
-        } else {
-            warn!("failed to parse symbol name sym {:?}", sym);
+        };
+
+        info!(
+            target: logging::CARTRIDGE,
+            "ELF: loading segment phdr: {:?} file range {:#x?} to {:#x}", phdr, file_range, addr,
+        );
+
+        match addr >> 24 {
+            0x02 | 0x03 => ram_segments.push((addr as u32, src.to_vec())),
+            0x08 | 0x09 if addr - CART_BASE + src.len() <= CART_SIZE => {
+                let start = addr - CART_BASE;
+                rom[start..start + src.len()].copy_from_slice(src);
+                rom_end = rom_end.max(start + src.len());
+            }
+            _ => warn!(target: logging::CARTRIDGE, "ELF: skipping program header {:?}", phdr),
         }
     }
+    // keep enough for the cartridge header even if the elf has nothing in the ROM
+    rom.truncate(((rom_end + 3) & !3).max(0xc0));
 
     Ok(LoadRom::Elf {
         data: rom,
-        symbols: symbols,
+        symbols: elf_symbols(&elf),
+        ram_segments,
+        entry_point: elf.entry as u32,
     })
 }
 
@@ -109,8 +156,12 @@ pub(super) fn load_from_file(path: &Path) -> LoadRomResult {
             Some("zip") => try_load_zip(&bytes),
             #[cfg(feature = "elf_support")]
             Some("elf") => try_load_elf(&bytes),
+            Some("mb") => Ok(LoadRom::Multiboot(bytes)),
             _ => {
-                warn!("unknown file extension, loading as raw binary file");
+                warn!(
+                    target: logging::CARTRIDGE,
+                    "unknown file extension, loading as raw binary file"
+                );
                 Ok(LoadRom::Raw(bytes))
             }
         },
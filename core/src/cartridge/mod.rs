@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
 use super::bus::*;
+use crate::logging;
 
 pub mod header;
 use header::CartridgeHeader;
@@ -10,16 +11,27 @@ use header::CartridgeHeader;
 mod backup;
 use backup::eeprom::EepromController;
 use backup::flash::Flash;
+pub use backup::flash::FlashVendor;
 pub use backup::BackupType;
 use backup::{BackupFile, BackupMemoryInterface};
 
 mod gpio;
+mod gyro;
 mod rtc;
+mod solar;
+mod tilt;
 use gpio::Gpio;
+pub use gyro::Gyro;
+pub use rtc::RtcClock;
+pub use solar::SolarSensor;
+use tilt::is_tilt_access;
+pub use tilt::TiltSensor;
 
 mod builder;
 mod loader;
-pub use builder::GamepakBuilder;
+#[cfg(feature = "elf_support")]
+pub(crate) use loader::elf_symbols;
+pub use builder::{GamepakBuilder, GpioDeviceType};
 
 pub const GPIO_PORT_DATA: u32 = 0xC4;
 pub const GPIO_PORT_DIRECTION: u32 = 0xC6;
@@ -42,19 +54,87 @@ pub struct Cartridge {
     bytes: Box<[u8]>,
     #[serde(skip)]
     size: usize,
+    /// Reads past the end of the ROM wrap around instead of seeing open bus
+    #[serde(skip)]
+    mirror_rom: bool,
     gpio: Option<Gpio>,
+    /// Sits in the SRAM area, so it isn't a gpio device
+    tilt_sensor: Option<TiltSensor>,
     symbols: Option<SymbolTable>, // TODO move it somewhere else
+    /// The segments of an elf image that are loaded to EWRAM or IWRAM, by address
+    #[serde(skip)]
+    ram_segments: Vec<(Addr, Vec<u8>)>,
+    /// The rom is a multiboot image, in `ram_segments`
+    #[serde(skip)]
+    multiboot: bool,
+    /// Where `skip_bios` starts the cpu, the thumb bit set for thumb code
+    #[serde(skip)]
+    entry_point: Addr,
     pub(in crate) backup: BackupMedia,
+    /// Set while the cartridge is pulled out of the slot
+    removed: bool,
 }
 
 impl Cartridge {
     pub fn get_symbols(&self) -> &Option<SymbolTable> {
         &self.symbols
     }
+
+    pub fn ram_segments(&self) -> &[(Addr, Vec<u8>)] {
+        &self.ram_segments
+    }
+
+    pub fn is_multiboot(&self) -> bool {
+        self.multiboot
+    }
+
+    /// The start of the cartridge or of the multiboot image, or the entry point of an elf file
+    pub fn entry_point(&self) -> Addr {
+        self.entry_point
+    }
     pub fn get_gpio(&self) -> &Option<Gpio> {
         &self.gpio
     }
 
+    /// The clock of the cartridge RTC, if it has one
+    pub fn rtc_clock_mut(&mut self) -> Option<&mut RtcClock> {
+        self.gpio
+            .as_mut()
+            .and_then(|gpio| gpio.rtc.as_mut())
+            .map(|rtc| rtc.clock_mut())
+    }
+
+    /// The light sensor of the Boktai carts
+    pub fn solar_sensor_mut(&mut self) -> Option<&mut SolarSensor> {
+        self.gpio
+            .as_mut()
+            .and_then(|gpio| gpio.solar_sensor.as_mut())
+    }
+
+    /// The gyro sensor of WarioWare Twisted
+    pub fn gyro_mut(&mut self) -> Option<&mut Gyro> {
+        self.gpio.as_mut().and_then(|gpio| gpio.gyro.as_mut())
+    }
+
+    pub fn tilt_sensor_mut(&mut self) -> Option<&mut TiltSensor> {
+        self.tilt_sensor.as_mut()
+    }
+
+    /// Whether the game has the rumble motor turned on, false for carts without one
+    pub fn rumble(&self) -> bool {
+        self.gpio.as_ref().map_or(false, |gpio| gpio.rumble())
+    }
+
+    pub fn is_inserted(&self) -> bool {
+        !self.removed
+    }
+
+    /// Emulates pulling the cartridge out of the slot or putting it back in.
+    /// While removed, the ROM area reads as open bus and the backup memory is unreachable.
+    pub fn set_inserted(&mut self, inserted: bool) {
+        self.removed = !inserted;
+    }
+
     pub fn set_rom_bytes(&mut self, bytes: Box<[u8]>) {
         self.size = bytes.len();
         self.bytes = bytes;
@@ -70,17 +150,54 @@ impl Cartridge {
             header: self.header.clone(),
             bytes: Default::default(),
             size: 0,
+            mirror_rom: self.mirror_rom,
             gpio: self.gpio.clone(),
+            tilt_sensor: self.tilt_sensor.clone(),
             symbols: self.symbols.clone(),
+            ram_segments: Vec::new(),
+            multiboot: self.multiboot,
+            entry_point: self.entry_point,
             backup: self.backup.clone(),
+            removed: self.removed,
         }
     }
 
     pub fn update_from(&mut self, other: Cartridge) {
         self.header = other.header;
         self.gpio = other.gpio;
+        self.tilt_sensor = other.tilt_sensor;
         self.symbols = other.symbols;
         self.backup = other.backup;
+        self.removed = other.removed;
+    }
+
+    /// Returns true if the backup memory or the RTC time changed since the last flush
+    pub fn is_backup_dirty(&self) -> bool {
+        let backup_dirty = match &self.backup {
+            BackupMedia::Sram(memory) => memory.is_dirty(),
+            BackupMedia::Flash(flash) => flash.is_dirty(),
+            BackupMedia::Eeprom(eeprom) => eeprom.is_dirty(),
+            BackupMedia::Undetected => false,
+        };
+        let rtc_dirty = self
+            .gpio
+            .as_ref()
+            .and_then(|gpio| gpio.rtc.as_ref())
+            .map_or(false, |rtc| rtc.is_dirty());
+        backup_dirty || rtc_dirty
+    }
+
+    /// Writes the backup memory and the RTC time out to their save files if they changed
+    pub fn flush_backup(&mut self) {
+        match &mut self.backup {
+            BackupMedia::Sram(memory) => memory.flush(),
+            BackupMedia::Flash(flash) => flash.flush(),
+            BackupMedia::Eeprom(eeprom) => eeprom.flush(),
+            BackupMedia::Undetected => {}
+        }
+        if let Some(rtc) = self.gpio.as_mut().and_then(|gpio| gpio.rtc.as_mut()) {
+            rtc.flush();
+        }
     }
 
     #[inline]
@@ -112,27 +229,57 @@ fn is_gpio_access(addr: u32) -> bool {
 impl Bus for Cartridge {
     fn read_8(&mut self, addr: Addr) -> u8 {
         let offset = (addr & 0x01ff_ffff) as usize;
+        if self.removed {
+            return match addr & 0xff000000 {
+                SRAM_LO | SRAM_HI => 0xff,
+                _ => self.read_unused(addr),
+            };
+        }
         match addr & 0xff000000 {
+            SRAM_LO | SRAM_HI if self.tilt_sensor.is_some() && is_tilt_access(addr) => {
+                self.tilt_sensor.as_ref().unwrap().read(addr)
+            }
             SRAM_LO | SRAM_HI => match &self.backup {
                 BackupMedia::Sram(memory) => memory.read((addr & 0x7FFF) as usize),
                 BackupMedia::Flash(flash) => flash.read(addr),
                 _ => 0,
             },
             _ => {
-                if offset >= self.size {
-                    self.read_unused(addr)
-                } else {
+                if offset < self.size {
                     unsafe { *self.bytes.get_unchecked(offset as usize) }
+                } else if self.mirror_rom && self.size != 0 {
+                    self.bytes[offset % self.size]
+                } else {
+                    self.read_unused(addr)
                 }
             }
         }
     }
 
+    /// The backup memory sits on an 8bit bus, wider reads see the same byte repeated
+    fn read_32(&mut self, addr: Addr) -> u32 {
+        match addr & 0xff000000 {
+            SRAM_LO | SRAM_HI => (self.read_8(addr) as u32) * 0x0101_0101,
+            _ => self.read_16(addr) as u32 | (self.read_16(addr + 2) as u32) << 16,
+        }
+    }
+
     fn read_16(&mut self, addr: u32) -> u16 {
+        if addr & 0xfe000000 == SRAM_LO {
+            return (self.read_8(addr) as u16) * 0x0101;
+        }
+
+        if self.removed {
+            return self.default_read_16(addr);
+        }
+
         if is_gpio_access(addr) {
             if let Some(gpio) = &self.gpio {
                 if !(gpio.is_readable()) {
-                    warn!("trying to read GPIO when reads are not allowed");
+                    warn!(
+                        target: logging::CARTRIDGE,
+                        "trying to read GPIO when reads are not allowed"
+                    );
                 }
                 return gpio.read(addr & 0x1ff_ffff);
             }
@@ -149,7 +296,13 @@ impl Bus for Cartridge {
     }
 
     fn write_8(&mut self, addr: u32, value: u8) {
+        if self.removed {
+            return;
+        }
         match addr & 0xff000000 {
+            SRAM_LO | SRAM_HI if self.tilt_sensor.is_some() && is_tilt_access(addr) => {
+                self.tilt_sensor.as_mut().unwrap().write(addr, value)
+            }
             SRAM_LO | SRAM_HI => match &mut self.backup {
                 BackupMedia::Flash(flash) => flash.write(addr, value),
                 BackupMedia::Sram(memory) => memory.write((addr & 0x7FFF) as usize, value),
@@ -159,7 +312,25 @@ impl Bus for Cartridge {
         };
     }
 
+    /// Wider writes to the 8bit backup memory only store a single byte
+    fn write_32(&mut self, addr: Addr, value: u32) {
+        match addr & 0xff000000 {
+            SRAM_LO | SRAM_HI => self.write_8(addr, (value >> (8 * (addr & 3))) as u8),
+            _ => {
+                self.write_16(addr, (value & 0xffff) as u16);
+                self.write_16(addr + 2, (value >> 16) as u16);
+            }
+        }
+    }
+
     fn write_16(&mut self, addr: u32, value: u16) {
+        if self.removed {
+            return;
+        }
+        if addr & 0xfe000000 == SRAM_LO {
+            return self.write_8(addr, (value >> (8 * (addr & 1))) as u8);
+        }
+
         if is_gpio_access(addr) {
             if let Some(gpio) = &mut self.gpio {
                 gpio.write(addr & 0x1ff_ffff, value);
@@ -6,8 +6,11 @@ use serde::{Deserialize, Serialize};
 use num::FromPrimitive;
 
 use std::cmp;
+use std::fs;
+use std::path::PathBuf;
 
 use super::gpio::{GpioDevice, GpioDirection, GpioState};
+use crate::logging;
 
 fn num2bcd(mut num: u8) -> u8 {
     num = cmp::min(num, 99);
@@ -24,6 +27,90 @@ fn num2bcd(mut num: u8) -> u8 {
     bcd
 }
 
+fn bcd2num(bcd: u8) -> u32 {
+    u32::from(bcd >> 4) * 10 + u32::from(bcd & 0xf)
+}
+
+fn host_millis() -> i64 {
+    Local::now().naive_local().timestamp_millis()
+}
+
+/// The time source of the RTC.
+///
+/// By default it follows the host clock, but it can be set to an arbitrary date, frozen or run
+/// at a different rate, which is handy for testing time based events in games.
+/// Times are kept as milliseconds since the unix epoch in the host local timezone.
+#[derive(SmartDefault, Serialize, Deserialize, Clone, Debug)]
+pub struct RtcClock {
+    /// The emulated time at `anchor`
+    base: i64,
+    /// The host time the clock was last adjusted at
+    anchor: i64,
+    #[default(1.0)]
+    speed: f64,
+    frozen: bool,
+}
+
+impl RtcClock {
+    pub fn now(&self) -> NaiveDateTime {
+        let millis = if self.frozen {
+            self.base
+        } else {
+            let elapsed = (host_millis() - self.anchor) as f64 * self.speed;
+            self.base + elapsed as i64
+        };
+        NaiveDateTime::from_timestamp_opt(
+            millis.div_euclid(1000),
+            (millis.rem_euclid(1000) * 1_000_000) as u32,
+        )
+        .unwrap_or_else(|| Local::now().naive_local())
+    }
+
+    /// Restarts the clock from `time`
+    pub fn set_time(&mut self, time: NaiveDateTime) {
+        self.base = time.timestamp_millis();
+        self.anchor = host_millis();
+    }
+
+    /// How far the clock is ahead of the host clock, in milliseconds
+    pub fn offset(&self) -> i64 {
+        self.now().timestamp_millis() - host_millis()
+    }
+
+    /// Runs the clock `offset` milliseconds ahead of the host clock
+    pub fn set_offset(&mut self, offset: i64) {
+        self.anchor = host_millis();
+        self.base = self.anchor + offset;
+    }
+
+    /// Makes the clock follow the host clock again
+    pub fn sync_to_host(&mut self) {
+        *self = RtcClock::default();
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Sets how fast the clock runs compared to the host clock, negative values are ignored
+    pub fn set_speed(&mut self, speed: f64) {
+        if speed >= 0.0 {
+            self.set_time(self.now());
+            self.speed = speed;
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Stops the clock at the current time, or lets it run again
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.set_time(self.now());
+        self.frozen = frozen;
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 enum Port {
     /// Serial Clock
@@ -194,6 +281,11 @@ pub struct Rtc {
     status: registers::StatusRegister,
     serial_buffer: SerialBuffer,
     internal_buffer: [u8; 8],
+    clock: RtcClock,
+    /// Where the time set by the game is kept between sessions
+    save_path: Option<PathBuf>,
+    /// Set when the game changed the time since the last flush
+    dirty: bool,
 }
 
 impl Rtc {
@@ -206,7 +298,68 @@ impl Rtc {
             status: registers::StatusRegister(0x82),
             serial_buffer: SerialBuffer::new(),
             internal_buffer: [0; 8],
+            clock: RtcClock::default(),
+            save_path: None,
+            dirty: false,
+        }
+    }
+
+    /// Keeps the time set by the game in `path`, restoring it from there if the file exists.
+    /// Only the offset from the host clock is stored, so the clock keeps running while the game is off.
+    pub fn set_save_path(&mut self, path: PathBuf) {
+        if let Ok(content) = fs::read_to_string(&path) {
+            match content.trim().parse::<i64>() {
+                Ok(offset) => self.clock.set_offset(offset),
+                Err(_) => warn!(
+                    target: logging::CARTRIDGE,
+                    "RTC: ignoring invalid clock file {}",
+                    path.display()
+                ),
+            }
+        }
+        self.save_path = Some(path);
+    }
+
+    /// Returns true if the game set the time since the last flush
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Writes the clock offset to the save path if the game set the time
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        if let Some(path) = &self.save_path {
+            if let Err(e) = fs::write(path, format!("{}\n", self.clock.offset())) {
+                error!(
+                    target: logging::CARTRIDGE,
+                    "RTC: failed to write {}: {}",
+                    path.display(), e
+                );
+                return;
+            }
         }
+        self.dirty = false;
+    }
+
+    pub fn clock(&self) -> &RtcClock {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut RtcClock {
+        &mut self.clock
+    }
+
+    fn load_hour(&self, now: &NaiveDateTime) -> u8 {
+        let hour = if self.status.mode_24h() {
+            now.hour()
+        } else {
+            // bit 6 is the PM flag in 12h mode
+            let (pm, hour12) = now.hour12();
+            (hour12 % 12) | if pm { 0x40 } else { 0 }
+        };
+        hour as u8
     }
 
     fn serial_read(&mut self) {
@@ -234,38 +387,27 @@ impl Rtc {
         match r {
             RegisterKind::Status => self.internal_buffer[0] = self.status.read(),
             RegisterKind::DateTime => {
-                let local: DateTime<Local> = Local::now();
-                let year = local.year();
-                assert!(year >= 2000 && year <= 2099); // Wonder if I will live to see this one fail
-
-                let hour = if self.status.mode_24h() {
-                    local.hour()
-                } else {
-                    let (_, hour12) = local.hour12();
-                    hour12 - 1
-                };
-
-                self.internal_buffer[0] = num2bcd((year % 100) as u8);
-                self.internal_buffer[1] = num2bcd(local.month() as u8);
-                self.internal_buffer[2] = num2bcd(local.day() as u8);
-                self.internal_buffer[3] = num2bcd(local.weekday().number_from_monday() as u8);
-                self.internal_buffer[4] = num2bcd(hour as u8);
-                self.internal_buffer[5] = num2bcd(local.minute() as u8);
-                self.internal_buffer[6] = num2bcd(local.second() as u8);
+                let now = self.clock.now();
+                // the chip only counts years 2000-2099, other centuries wrap around
+                let year = now.year().rem_euclid(100);
+                let hour = self.load_hour(&now);
+
+                self.internal_buffer[0] = num2bcd(year as u8);
+                self.internal_buffer[1] = num2bcd(now.month() as u8);
+                self.internal_buffer[2] = num2bcd(now.day() as u8);
+                self.internal_buffer[3] = num2bcd(now.weekday().number_from_monday() as u8);
+                self.internal_buffer[4] = (hour & 0x40) | num2bcd(hour & 0x3f);
+                self.internal_buffer[5] = num2bcd(now.minute() as u8);
+                self.internal_buffer[6] = num2bcd(now.second() as u8);
             }
             RegisterKind::Time => {
-                let local: DateTime<Local> = Local::now();
-                let hour = if self.status.mode_24h() {
-                    local.hour()
-                } else {
-                    let (_, hour12) = local.hour12();
-                    hour12 - 1
-                };
-                self.internal_buffer[0] = num2bcd(hour as u8);
-                self.internal_buffer[1] = num2bcd(local.minute() as u8);
-                self.internal_buffer[2] = num2bcd(local.second() as u8);
+                let now = self.clock.now();
+                let hour = self.load_hour(&now);
+                self.internal_buffer[0] = (hour & 0x40) | num2bcd(hour & 0x3f);
+                self.internal_buffer[1] = num2bcd(now.minute() as u8);
+                self.internal_buffer[2] = num2bcd(now.second() as u8);
             }
-            _ => warn!("RTC: read {:?} not implemented", r),
+            _ => warn!(target: logging::CARTRIDGE, "RTC: read {:?} not implemented", r),
         }
     }
 
@@ -274,7 +416,45 @@ impl Rtc {
         match r {
             Status => self.status.write(self.internal_buffer[0]),
             ForceReset => self.force_reset(),
-            _ => warn!("RTC: write {:?} not implemented", r),
+            DateTime => {
+                let date = NaiveDate::from_ymd_opt(
+                    2000 + bcd2num(self.internal_buffer[0]) as i32,
+                    bcd2num(self.internal_buffer[1]),
+                    bcd2num(self.internal_buffer[2]),
+                );
+                let buf = &self.internal_buffer;
+                let time = [buf[4], buf[5], buf[6]];
+                self.store_time(date, time);
+            }
+            Time => {
+                let date = Some(self.clock.now().date());
+                let buf = &self.internal_buffer;
+                let time = [buf[0], buf[1], buf[2]];
+                self.store_time(date, time);
+            }
+            _ => warn!(target: logging::CARTRIDGE, "RTC: write {:?} not implemented", r),
+        }
+    }
+
+    /// Sets the clock from the date and the hour, minute and second registers written by the game
+    fn store_time(&mut self, date: Option<NaiveDate>, time: [u8; 3]) {
+        let mut hour = bcd2num(time[0] & 0x3f);
+        if !self.status.mode_24h() && time[0].bit(6) {
+            hour += 12;
+        }
+        let date_time = date.and_then(|date| {
+            date.and_hms_opt(hour, bcd2num(time[1] & 0x7f), bcd2num(time[2] & 0x7f))
+        });
+        match date_time {
+            Some(date_time) => {
+                self.clock.set_time(date_time);
+                self.dirty = true;
+            }
+            None => warn!(
+                target: logging::CARTRIDGE,
+                "RTC: ignoring invalid date/time {:x?}",
+                time
+            ),
         }
     }
 }
@@ -297,13 +477,14 @@ impl GpioDevice for Rtc {
         }
 
         if self.cs.high() && old_cs.low() {
-            trace!("RTC: CS went from low to high!");
+            trace!(target: logging::CARTRIDGE, "RTC: CS went from low to high!");
         }
 
         use RtcState::*;
 
         if self.cs.low() && self.serial_transfer_in_progress() {
             debug!(
+                target: logging::CARTRIDGE,
                 "RTC: CS set low from state {:?}, resetting state",
                 self.state
             );
@@ -358,6 +539,7 @@ impl GpioDevice for Rtc {
                 let is_read_operation = command.bit(7);
 
                 debug!(
+                    target: logging::CARTRIDGE,
                     "RTC: got command: {} {:?} args len: {}",
                     if is_read_operation { "READ" } else { "WRITE" },
                     reg,
@@ -409,7 +591,7 @@ impl GpioDevice for Rtc {
                     return;
                 };
 
-                trace!("RTC TX BIT {}", bit);
+                trace!(target: logging::CARTRIDGE, "RTC TX BIT {}", bit);
                 assert_eq!(gpio_state[Port::Sio.index()], GpioDirection::In);
                 self.sio.set(bit as u16);
 
@@ -646,4 +828,67 @@ mod tests {
         assert_eq!(bytes[1], num2bcd(local.month() as u8));
         assert_eq!(bytes[2], num2bcd(local.day() as u8));
     }
+
+    #[test]
+    fn test_frozen_clock() {
+        setup_rtc!(rtc, gpio_state);
+
+        let time = NaiveDate::from_ymd(2001, 2, 3).and_hms(4, 5, 6);
+        rtc.clock_mut().set_time(time);
+        rtc.clock_mut().set_frozen(true);
+        assert_eq!(rtc.clock().now(), time);
+
+        start_serial_transfer(&mut rtc, &mut gpio_state);
+        transmit_bits(&mut rtc, &gpio_state, &[0, 1, 1, 0, 0, 1, 0, 1]);
+
+        gpio_state[Port::Sio.index()] = GpioDirection::In;
+        let mut bytes = [0; 7];
+        receive_bytes(&mut rtc, &gpio_state, &mut bytes);
+        assert_eq!(bytes, [0x01, 0x02, 0x03, 0x06, 0x04, 0x05, 0x06]);
+    }
+
+    #[test]
+    fn test_12h_mode() {
+        setup_rtc!(rtc, gpio_state);
+
+        rtc.status.set_mode_24h(false);
+        let date = NaiveDate::from_ymd(2001, 2, 3);
+        rtc.clock_mut().set_time(date.and_hms(15, 5, 6));
+        rtc.clock_mut().set_frozen(true);
+
+        // read Time register command
+        start_serial_transfer(&mut rtc, &mut gpio_state);
+        transmit_bits(&mut rtc, &gpio_state, &[0, 1, 1, 0, 0, 1, 1, 1]);
+
+        gpio_state[Port::Sio.index()] = GpioDirection::In;
+        let mut bytes = [0; 3];
+        receive_bytes(&mut rtc, &gpio_state, &mut bytes);
+        // bit 6 of the hour is the PM flag
+        assert_eq!(bytes, [0x43, 0x05, 0x06]);
+
+        // write Time register command, with the time that was just read
+        rtc.clock_mut().set_time(date.and_hms(0, 0, 0));
+        gpio_state[Port::Sio.index()] = GpioDirection::Out;
+        start_serial_transfer(&mut rtc, &mut gpio_state);
+        transmit_bits(&mut rtc, &gpio_state, &[0, 1, 1, 0, 0, 1, 1, 0]);
+
+        let mut serial_buffer = SerialBuffer::new();
+        for byte in bytes.iter() {
+            serial_buffer.load_byte(*byte);
+            while let Some(bit) = serial_buffer.pop_bit() {
+                transmit(&mut rtc, &gpio_state, bit as u8);
+            }
+        }
+        assert_eq!(rtc.clock().now(), date.and_hms(15, 5, 6));
+    }
+
+    #[test]
+    fn test_clock_offset() {
+        let mut clock = RtcClock::default();
+        assert!(clock.offset().abs() < 1000);
+
+        let day = 24 * 60 * 60 * 1000;
+        clock.set_offset(-day);
+        assert!((clock.offset() + day).abs() < 1000);
+    }
 }
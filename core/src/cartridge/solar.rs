@@ -0,0 +1,92 @@
+use bit::BitIndex;
+use serde::{Deserialize, Serialize};
+
+use super::gpio::{GpioDevice, GpioDirection, GpioState};
+
+/// Counter values the flag goes up at in complete darkness and in direct sunlight
+const DARK_THRESHOLD: u32 = 0xe8;
+const BRIGHT_THRESHOLD: u32 = 0x50;
+
+/// The light sensor of the Boktai carts.
+///
+/// The game resets a counter through pin 1 and clocks it through pin 0 until the flag on pin 3
+/// goes high, the more light hits the sensor the fewer clocks that takes.
+/// Pin 2 is the chip select of the RTC that shares the port, the sensor ignores writes while it is high.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SolarSensor {
+    /// 0 is darkness and 255 is direct sunlight
+    light_level: u8,
+    counter: u8,
+    clock: bool,
+}
+
+impl SolarSensor {
+    pub fn light_level(&self) -> u8 {
+        self.light_level
+    }
+
+    pub fn set_light_level(&mut self, light_level: u8) {
+        self.light_level = light_level;
+    }
+
+    fn threshold(&self) -> u8 {
+        let range = DARK_THRESHOLD - BRIGHT_THRESHOLD;
+        (DARK_THRESHOLD - self.light_level as u32 * range / 255) as u8
+    }
+}
+
+impl GpioDevice for SolarSensor {
+    fn write(&mut self, _gpio_state: &GpioState, data: u16) {
+        if data.bit(2) {
+            return;
+        }
+        let clock = data.bit(0);
+        if data.bit(1) {
+            self.counter = 0;
+        } else if clock && !self.clock {
+            self.counter = self.counter.saturating_add(1);
+        }
+        self.clock = clock;
+    }
+
+    fn read(&self, gpio_state: &GpioState) -> u16 {
+        if gpio_state[3] == GpioDirection::In && self.counter >= self.threshold() {
+            1 << 3
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clocks the counter like the game does, returns the count the flag went up at
+    fn measure(sensor: &mut SolarSensor) -> u32 {
+        let gpio_state = [
+            GpioDirection::Out,
+            GpioDirection::Out,
+            GpioDirection::Out,
+            GpioDirection::In,
+        ];
+        sensor.write(&gpio_state, 0b0010);
+        sensor.write(&gpio_state, 0b0000);
+        for count in 0..=255 {
+            if sensor.read(&gpio_state) != 0 {
+                return count;
+            }
+            sensor.write(&gpio_state, 0b0001);
+            sensor.write(&gpio_state, 0b0000);
+        }
+        256
+    }
+
+    #[test]
+    fn test_solar_sensor() {
+        let mut sensor = SolarSensor::default();
+        assert_eq!(measure(&mut sensor), DARK_THRESHOLD);
+        sensor.set_light_level(255);
+        assert_eq!(measure(&mut sensor), BRIGHT_THRESHOLD);
+    }
+}
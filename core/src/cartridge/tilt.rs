@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use super::super::bus::Addr;
+
+/// The reading of an axis when the cartridge is held flat
+const TILT_CENTER: i32 = 0x3a0;
+
+/// The accelerometer of Yoshi Topsy-Turvy and Koro Koro Puzzle, mapped in the SRAM area.
+///
+/// Writing 55h to E008000h and then AAh to E008100h samples both axes, the 12bit results are read from
+/// E008200h (x low), E008300h (x high, bit 7 is set once sampled), E008400h (y low) and E008500h (y high).
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TiltSensor {
+    /// Tilt to the right
+    x: i16,
+    /// Tilt towards the player
+    y: i16,
+    sample: (u16, u16),
+    armed: bool,
+    ready: bool,
+}
+
+pub(super) fn is_tilt_access(addr: Addr) -> bool {
+    match addr & 0xffff {
+        0x8000 | 0x8100 | 0x8200 | 0x8300 | 0x8400 | 0x8500 => true,
+        _ => false,
+    }
+}
+
+impl TiltSensor {
+    pub fn tilt(&self) -> (i16, i16) {
+        (self.x, self.y)
+    }
+
+    pub fn set_tilt(&mut self, x: i16, y: i16) {
+        self.x = x;
+        self.y = y;
+    }
+
+    fn axis_sample(value: i16) -> u16 {
+        (TILT_CENTER + (value as i32 >> 6)) as u16
+    }
+
+    pub(super) fn write(&mut self, addr: Addr, value: u8) {
+        match (addr & 0xffff, value) {
+            (0x8000, 0x55) => self.armed = true,
+            (0x8100, 0xaa) if self.armed => {
+                self.sample = (Self::axis_sample(self.x), Self::axis_sample(self.y));
+                self.armed = false;
+                self.ready = true;
+            }
+            _ => {}
+        }
+    }
+
+    pub(super) fn read(&self, addr: Addr) -> u8 {
+        let (x, y) = self.sample;
+        match addr & 0xffff {
+            0x8200 => x as u8,
+            0x8300 => (x >> 8) as u8 & 0xf | if self.ready { 0x80 } else { 0 },
+            0x8400 => y as u8,
+            0x8500 => (y >> 8) as u8 & 0xf,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,721 @@
+//! GameShark and CodeBreaker cheat codes.
+//!
+//! Codes are parsed into a list of writes and conditions which are applied to the bus once per
+//! frame, before the frame runs, so "freeze" codes keep overwriting whatever the game wrote.
+//! Every write and test uses the width of its code type (8, 16 or 32 bits), which matters for IO
+//! registers and VRAM where a byte write is not the same as a halfword write.
+//!
+//! The constant writes that are not behind a condition also hook the bus (see `WriteHook`): when
+//! the game writes to their location in the middle of a frame, the cheat value is written back
+//! right away. The or/and/add codes only apply once per frame, as they would pile up otherwise.
+use std::fmt;
+use std::str::FromStr;
+
+use super::bus::{Addr, Bus};
+
+/// Decryption seeds of the GameShark v1/v2
+const GSV1_SEEDS: [u32; 4] = [0x09F4_FBBD, 0x9681_884A, 0x3520_27E9, 0xF3DE_E5A7];
+/// Decryption seeds of the GameShark v3 / Action Replay v3
+const GSV3_SEEDS: [u32; 4] = [0x7AA9_648F, 0x7FAE_6994, 0xC0EF_AAD5, 0x4271_2C57];
+const TEA_DELTA: u32 = 0x9E37_79B9;
+/// Multiplier and increment of the CodeBreaker random generator
+const CB_RAND_MUL: u32 = 0x41C6_4E6D;
+const CB_RAND_INC: u32 = 0x3039;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CheatFormat {
+    /// Encrypted GameShark v1/v2 codes, as printed in the code lists
+    GameSharkV1,
+    GameSharkV1Raw,
+    /// Encrypted GameShark v3 / Action Replay v3 codes
+    GameSharkV3,
+    GameSharkV3Raw,
+    /// The lines after a `9xxxxxxx yyyy` seed code are decrypted with it
+    CodeBreaker,
+}
+
+impl FromStr for CheatFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CheatFormat, String> {
+        match s.to_lowercase().as_str() {
+            "gsv1" | "gs" => Ok(CheatFormat::GameSharkV1),
+            "gsv1-raw" | "gs-raw" => Ok(CheatFormat::GameSharkV1Raw),
+            "gsv3" | "ar" => Ok(CheatFormat::GameSharkV3),
+            "gsv3-raw" | "ar-raw" => Ok(CheatFormat::GameSharkV3Raw),
+            "cb" | "codebreaker" => Ok(CheatFormat::CodeBreaker),
+            _ => Err(format!(
+                "unknown cheat format {:?}, expected gsv1, gsv1-raw, gsv3, gsv3-raw or cb",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Test {
+    Equal,
+    NotEqual,
+    /// True when any of the bits of the value are set in memory
+    AnyBitSet,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Op {
+    /// Writes `value` to `count` consecutive `width` bytes wide locations starting at `addr`
+    Write {
+        addr: Addr,
+        value: u32,
+        width: u32,
+        count: u32,
+    },
+    Or16(Addr, u16),
+    And16(Addr, u16),
+    Add16(Addr, u16),
+    /// Runs the next `skip` ops only if the test passes
+    If {
+        test: Test,
+        addr: Addr,
+        value: u32,
+        width: u32,
+        skip: usize,
+    },
+}
+
+/// Keeps `count` consecutive `width` bytes wide locations starting at `addr` at `value`, even
+/// when the game writes to them during the frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WriteHook {
+    addr: Addr,
+    value: u32,
+    width: u32,
+    count: u32,
+}
+
+impl WriteHook {
+    /// Writes the value back to the locations overlapped by the `width` bytes wide write at `addr`
+    pub fn on_write<B: Bus>(&self, bus: &mut B, addr: Addr, width: u32) {
+        let start = addr as u64;
+        let end = start + width as u64;
+        let hook_start = self.addr as u64;
+        let hook_end = hook_start + (self.width * self.count) as u64;
+        if start >= hook_end || end <= hook_start {
+            return;
+        }
+        let first = (start.max(hook_start) - hook_start) / self.width as u64;
+        let last = (end.min(hook_end) - 1 - hook_start) / self.width as u64;
+        for i in first..=last {
+            write(
+                bus,
+                self.addr.wrapping_add(i as u32 * self.width),
+                self.width,
+                self.value,
+            );
+        }
+    }
+}
+
+fn read<B: Bus>(bus: &mut B, addr: Addr, width: u32) -> u32 {
+    match width {
+        1 => bus.read_8(addr) as u32,
+        2 => bus.read_16(addr) as u32,
+        _ => bus.read_32(addr),
+    }
+}
+
+fn write<B: Bus>(bus: &mut B, addr: Addr, width: u32, value: u32) {
+    match width {
+        1 => bus.write_8(addr, value as u8),
+        2 => bus.write_16(addr, value as u16),
+        _ => bus.write_32(addr, value),
+    }
+}
+
+impl Op {
+    fn write(addr: Addr, value: u32, width: u32) -> Op {
+        Op::Write {
+            addr,
+            value,
+            width,
+            count: 1,
+        }
+    }
+
+    fn test_if(test: Test, addr: Addr, value: u32, width: u32, skip: usize) -> Op {
+        Op::If {
+            test,
+            addr,
+            value,
+            width,
+            skip,
+        }
+    }
+
+    /// Returns the number of ops to skip after this one
+    fn apply<B: Bus>(&self, bus: &mut B) -> usize {
+        match *self {
+            Op::Write {
+                addr,
+                value,
+                width,
+                count,
+            } => {
+                for i in 0..count {
+                    write(bus, addr.wrapping_add(i * width), width, value);
+                }
+            }
+            Op::Or16(addr, value) => {
+                let old = bus.read_16(addr);
+                bus.write_16(addr, old | value);
+            }
+            Op::And16(addr, value) => {
+                let old = bus.read_16(addr);
+                bus.write_16(addr, old & value);
+            }
+            Op::Add16(addr, value) => {
+                let old = bus.read_16(addr);
+                bus.write_16(addr, old.wrapping_add(value));
+            }
+            Op::If {
+                test,
+                addr,
+                value,
+                width,
+                skip,
+            } => {
+                let mem = read(bus, addr, width);
+                let pass = match test {
+                    Test::Equal => mem == value,
+                    Test::NotEqual => mem != value,
+                    Test::AnyBitSet => mem & value != 0,
+                };
+                if !pass {
+                    return skip;
+                }
+            }
+        }
+        0
+    }
+}
+
+fn tea_decrypt(addr: &mut u32, value: &mut u32, seeds: &[u32; 4]) {
+    let mut sum = TEA_DELTA.wrapping_mul(32);
+    for _ in 0..32 {
+        *value = value.wrapping_sub(
+            ((*addr << 4).wrapping_add(seeds[2]))
+                ^ addr.wrapping_add(sum)
+                ^ ((*addr >> 5).wrapping_add(seeds[3])),
+        );
+        *addr = addr.wrapping_sub(
+            ((*value << 4).wrapping_add(seeds[0]))
+                ^ value.wrapping_add(sum)
+                ^ ((*value >> 5).wrapping_add(seeds[1])),
+        );
+        sum = sum.wrapping_sub(TEA_DELTA);
+    }
+}
+
+/// The pseudo random generator the CodeBreaker keys are derived with
+fn cb_rand(state: &mut u32) -> u32 {
+    let x = state.wrapping_mul(CB_RAND_MUL).wrapping_add(CB_RAND_INC);
+    let y = x.wrapping_mul(CB_RAND_MUL).wrapping_add(CB_RAND_INC);
+    *state = y.wrapping_mul(CB_RAND_MUL).wrapping_add(CB_RAND_INC);
+    ((x >> 16) << 30) | (((y >> 16) & 0x7fff) << 15) | ((*state >> 16) & 0x7fff)
+}
+
+/// The 48 bits of a CodeBreaker line, the address and the value in big endian
+fn cb_bytes(code: u32, value: u16) -> [u8; 6] {
+    let mut bytes = [0; 6];
+    bytes[..4].copy_from_slice(&code.to_be_bytes());
+    bytes[4..].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+fn cb_split(bytes: &[u8; 6]) -> (u32, u16) {
+    (
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_be_bytes([bytes[4], bytes[5]]),
+    )
+}
+
+fn swap_bits(bytes: &mut [u8; 6], a: usize, b: usize) {
+    let bit_a = (bytes[a >> 3] >> (a & 7)) & 1;
+    let bit_b = (bytes[b >> 3] >> (b & 7)) & 1;
+    bytes[a >> 3] = (bytes[a >> 3] & !(1 << (a & 7))) | (bit_b << (a & 7));
+    bytes[b >> 3] = (bytes[b >> 3] & !(1 << (b & 7))) | (bit_a << (b & 7));
+}
+
+/// The CodeBreaker decryption key, derived from a `9xxxxxxx yyyy` seed code
+struct CbKey {
+    /// Every bit of a line is swapped with the one at its index, from the last to the first
+    bit_swaps: [u8; 48],
+    seeds: [u32; 4],
+    /// The address part of the seed code
+    seed_code: u32,
+}
+
+impl CbKey {
+    fn new(code: u32, value: u32) -> CbKey {
+        let mut bit_swaps = [0u8; 48];
+        for (i, b) in bit_swaps.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut state = (value & 0xff) ^ 0x1111;
+        for _ in 0..0x50 {
+            let a = (cb_rand(&mut state) % 48) as usize;
+            let b = (cb_rand(&mut state) % 48) as usize;
+            bit_swaps.swap(a, b);
+        }
+
+        let mut state = 0x4EFA_D1C3;
+        for _ in 0..(code >> 24) & 0xf {
+            state = cb_rand(&mut state);
+        }
+        let seed2 = cb_rand(&mut state);
+        let seed3 = cb_rand(&mut state);
+
+        let rounds = (value >> 8) & 0xff;
+        let mut state = rounds ^ 0xF254;
+        for _ in 0..rounds {
+            state = cb_rand(&mut state);
+        }
+        let seed0 = cb_rand(&mut state);
+        let seed1 = cb_rand(&mut state);
+
+        CbKey {
+            bit_swaps,
+            seeds: [seed0, seed1, seed2, seed3],
+            seed_code: code,
+        }
+    }
+
+    fn decrypt(&self, code: u32, value: u16) -> (u32, u16) {
+        let mut bytes = cb_bytes(code, value);
+        for (i, &other) in self.bit_swaps.iter().enumerate().rev() {
+            swap_bits(&mut bytes, i, other as usize);
+        }
+        let (code, value) = cb_split(&bytes);
+        let mut bytes = cb_bytes(code ^ self.seeds[0], value ^ self.seeds[1] as u16);
+        let lo = self.seed_code as u8;
+        let hi = (self.seed_code >> 8) as u8;
+        for i in 0..5 {
+            bytes[i] ^= hi ^ bytes[i + 1];
+        }
+        bytes[5] ^= hi;
+        for i in (1..6).rev() {
+            bytes[i] ^= lo ^ bytes[i - 1];
+        }
+        bytes[0] ^= lo;
+        let (code, value) = cb_split(&bytes);
+        (code ^ self.seeds[2], value ^ self.seeds[3] as u16)
+    }
+}
+
+/// Splits a code line into its address and value parts, spaces between them are optional
+fn parse_line(line: &str, value_digits: usize) -> Result<(u32, u32), String> {
+    let digits: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() != 8 + value_digits || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "invalid code {:?}, expected {} hex digits",
+            line,
+            8 + value_digits
+        ));
+    }
+    let addr = u32::from_str_radix(&digits[..8], 16).unwrap();
+    let value = u32::from_str_radix(&digits[8..], 16).unwrap();
+    Ok((addr, value))
+}
+
+fn unsupported(line: &str) -> String {
+    format!("unsupported code type {:?}", line)
+}
+
+fn parse_gsv1(lines: &[&str], encrypted: bool) -> Result<Vec<Op>, String> {
+    let mut ops = Vec::new();
+    for line in lines {
+        let (mut addr, mut value) = parse_line(line, 8)?;
+        if encrypted {
+            tea_decrypt(&mut addr, &mut value, &GSV1_SEEDS);
+        }
+        let target = addr & 0x0fff_ffff;
+        match addr >> 28 {
+            0x0 => ops.push(Op::write(target, value & 0xff, 1)),
+            0x1 => ops.push(Op::write(target, value & 0xffff, 2)),
+            0x2 => ops.push(Op::write(target, value, 4)),
+            0xd => ops.push(Op::test_if(Test::Equal, target, value & 0xffff, 2, 1)),
+            // E0zzxxxx 0aaaaaaa: runs the next zz lines if the halfword at aaaaaaa is xxxx
+            0xe => ops.push(Op::test_if(
+                Test::Equal,
+                value & 0x0fff_ffff,
+                addr & 0xffff,
+                2,
+                ((addr >> 16) & 0xff) as usize,
+            )),
+            // the master code hooks the game for the real device, nothing to do for an emulator
+            0xf => {}
+            _ => return Err(unsupported(line)),
+        }
+    }
+    Ok(ops)
+}
+
+fn parse_gsv3(lines: &[&str], encrypted: bool) -> Result<Vec<Op>, String> {
+    let mut ops = Vec::new();
+    for line in lines {
+        let (mut code, mut value) = parse_line(line, 8)?;
+        if encrypted {
+            tea_decrypt(&mut code, &mut value, &GSV3_SEEDS);
+        }
+        if code == 0 {
+            // the multi line special codes (ROM patches, slides, master codes)
+            return Err(unsupported(line));
+        }
+        // the address nibble of the memory region is packed next to the offset
+        let addr = ((code & 0x00f0_0000) << 4) | (code & 0x000f_ffff);
+        match code >> 24 {
+            0x00 => ops.push(Op::Write {
+                addr,
+                value: value & 0xff,
+                width: 1,
+                count: (value >> 8) + 1,
+            }),
+            0x02 => ops.push(Op::Write {
+                addr,
+                value: value & 0xffff,
+                width: 2,
+                count: (value >> 16) + 1,
+            }),
+            0x04 => ops.push(Op::write(addr, value, 4)),
+            0x08 => ops.push(Op::test_if(Test::Equal, addr, value & 0xff, 1, 1)),
+            0x0a => ops.push(Op::test_if(Test::Equal, addr, value & 0xffff, 2, 1)),
+            0x0c => ops.push(Op::test_if(Test::Equal, addr, value, 4, 1)),
+            0x10 => ops.push(Op::test_if(Test::NotEqual, addr, value & 0xff, 1, 1)),
+            0x12 => ops.push(Op::test_if(Test::NotEqual, addr, value & 0xffff, 2, 1)),
+            0x14 => ops.push(Op::test_if(Test::NotEqual, addr, value, 4, 1)),
+            _ => return Err(unsupported(line)),
+        }
+    }
+    Ok(ops)
+}
+
+fn parse_codebreaker(lines: &[&str]) -> Result<Vec<Op>, String> {
+    let mut ops = Vec::new();
+    let mut key: Option<CbKey> = None;
+    for line in lines {
+        let (mut code, mut value) = parse_line(line, 4)?;
+        // the seed codes themselves are never encrypted
+        if code >> 28 == 0x9 {
+            key = Some(CbKey::new(code, value));
+            continue;
+        }
+        if let Some(key) = &key {
+            let (decrypted_code, decrypted_value) = key.decrypt(code, value as u16);
+            code = decrypted_code;
+            value = decrypted_value as u32;
+        }
+        let addr = code & 0x0fff_ffff;
+        let value16 = value as u16;
+        match code >> 28 {
+            // the master code and the game id, only needed by the real device
+            0x0 | 0x1 => {}
+            0x2 => ops.push(Op::Or16(addr, value16)),
+            0x3 => ops.push(Op::write(addr, value & 0xff, 1)),
+            0x6 => ops.push(Op::And16(addr, value16)),
+            0x7 => ops.push(Op::test_if(Test::Equal, addr, value, 2, 1)),
+            0x8 => ops.push(Op::write(addr, value, 2)),
+            0xa => ops.push(Op::test_if(Test::NotEqual, addr, value, 2, 1)),
+            0xe => ops.push(Op::Add16(addr, value16)),
+            0xf => ops.push(Op::test_if(Test::AnyBitSet, addr, value, 2, 1)),
+            _ => return Err(unsupported(line)),
+        }
+    }
+    Ok(ops)
+}
+
+#[derive(Debug, Clone)]
+pub struct Cheat {
+    pub name: String,
+    pub format: CheatFormat,
+    pub enabled: bool,
+    ops: Vec<Op>,
+}
+
+impl Cheat {
+    /// Parses a code, its lines are separated by newlines or ';'
+    pub fn parse(name: &str, format: CheatFormat, code: &str) -> Result<Cheat, String> {
+        let lines: Vec<&str> = code
+            .split(|c| c == '\n' || c == ';')
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.is_empty() {
+            return Err(format!("cheat {:?} has no code", name));
+        }
+        let ops = match format {
+            CheatFormat::GameSharkV1 => parse_gsv1(&lines, true),
+            CheatFormat::GameSharkV1Raw => parse_gsv1(&lines, false),
+            CheatFormat::GameSharkV3 => parse_gsv3(&lines, true),
+            CheatFormat::GameSharkV3Raw => parse_gsv3(&lines, false),
+            CheatFormat::CodeBreaker => parse_codebreaker(&lines),
+        }
+        .map_err(|e| format!("cheat {:?}: {}", name, e))?;
+        Ok(Cheat {
+            name: name.to_string(),
+            format,
+            enabled: true,
+            ops,
+        })
+    }
+
+    pub fn apply<B: Bus>(&self, bus: &mut B) {
+        let mut i = 0;
+        while i < self.ops.len() {
+            i += 1 + self.ops[i].apply(bus);
+        }
+    }
+
+    /// The writes that do not depend on a condition
+    fn write_hooks(&self) -> impl Iterator<Item = WriteHook> + '_ {
+        let mut conditional = 0;
+        self.ops.iter().filter_map(move |op| {
+            let guarded = conditional > 0;
+            conditional = match *op {
+                Op::If { skip, .. } => skip.max(conditional.saturating_sub(1)),
+                _ => conditional.saturating_sub(1),
+            };
+            match *op {
+                Op::Write {
+                    addr,
+                    value,
+                    width,
+                    count,
+                } if !guarded => Some(WriteHook {
+                    addr,
+                    value,
+                    width,
+                    count,
+                }),
+                _ => None,
+            }
+        })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        write!(f, "{} ({:?}, {})", self.name, self.format, state)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheatEngine {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatEngine {
+    /// Adds an enabled cheat and returns its index
+    pub fn add(&mut self, name: &str, format: CheatFormat, code: &str) -> Result<usize, String> {
+        self.cheats.push(Cheat::parse(name, format, code)?);
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.cheats.len() {
+            Some(self.cheats.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns false if there is no cheat at `index`
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    pub fn apply<B: Bus>(&self, bus: &mut B) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cheat.apply(bus);
+        }
+    }
+
+    /// The writes of the enabled cheats that must also be enforced whenever the game writes to
+    /// their location
+    pub fn write_hooks(&self) -> Vec<WriteHook> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .flat_map(Cheat::write_hooks)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct TestBus(HashMap<Addr, u8>);
+
+    impl Bus for TestBus {
+        fn read_8(&mut self, addr: Addr) -> u8 {
+            *self.0.get(&addr).unwrap_or(&0)
+        }
+
+        fn write_8(&mut self, addr: Addr, value: u8) {
+            self.0.insert(addr, value);
+        }
+    }
+
+    fn tea_encrypt(addr: &mut u32, value: &mut u32, seeds: &[u32; 4]) {
+        let mut sum = 0u32;
+        for _ in 0..32 {
+            sum = sum.wrapping_add(TEA_DELTA);
+            *addr = addr.wrapping_add(
+                ((*value << 4).wrapping_add(seeds[0]))
+                    ^ value.wrapping_add(sum)
+                    ^ ((*value >> 5).wrapping_add(seeds[1])),
+            );
+            *value = value.wrapping_add(
+                ((*addr << 4).wrapping_add(seeds[2]))
+                    ^ addr.wrapping_add(sum)
+                    ^ ((*addr >> 5).wrapping_add(seeds[3])),
+            );
+        }
+    }
+
+    #[test]
+    fn test_encrypted_gameshark() {
+        let (mut addr, mut value) = (0x1200_0100, 0x0000_beef);
+        tea_encrypt(&mut addr, &mut value, &GSV1_SEEDS);
+        let code = format!("{:08X} {:08X}", addr, value);
+
+        let mut engine = CheatEngine::default();
+        let index = engine.add("test", CheatFormat::GameSharkV1, &code).unwrap();
+        let mut bus = TestBus::default();
+        engine.apply(&mut bus);
+        assert_eq!(bus.read_16(0x0200_0100), 0xbeef);
+
+        bus.write_16(0x0200_0100, 0);
+        engine.set_enabled(index, false);
+        engine.apply(&mut bus);
+        assert_eq!(bus.read_16(0x0200_0100), 0);
+    }
+
+    /// The inverse of `CbKey::decrypt`
+    fn cb_encrypt(key: &CbKey, code: u32, value: u16) -> (u32, u16) {
+        let mut bytes = cb_bytes(code ^ key.seeds[2], value ^ key.seeds[3] as u16);
+        let lo = key.seed_code as u8;
+        let hi = (key.seed_code >> 8) as u8;
+        bytes[0] ^= lo;
+        for i in 1..6 {
+            bytes[i] ^= lo ^ bytes[i - 1];
+        }
+        bytes[5] ^= hi;
+        for i in (0..5).rev() {
+            bytes[i] ^= hi ^ bytes[i + 1];
+        }
+        let (code, value) = cb_split(&bytes);
+        let mut bytes = cb_bytes(code ^ key.seeds[0], value ^ key.seeds[1] as u16);
+        for (i, &other) in key.bit_swaps.iter().enumerate() {
+            swap_bits(&mut bytes, i, other as usize);
+        }
+        cb_split(&bytes)
+    }
+
+    #[test]
+    fn test_encrypted_codebreaker() {
+        let seed = (0x9123_4567, 0x89ab);
+        let key = CbKey::new(seed.0, seed.1);
+        let (code, value) = cb_encrypt(&key, 0x8200_0100, 0xbeef);
+        assert_ne!((code, value), (0x8200_0100, 0xbeef));
+        assert_eq!(key.decrypt(code, value), (0x8200_0100, 0xbeef));
+
+        let mut engine = CheatEngine::default();
+        engine
+            .add(
+                "test",
+                CheatFormat::CodeBreaker,
+                &format!("{:08X} {:04X}\n{:08X} {:04X}", seed.0, seed.1, code, value),
+            )
+            .unwrap();
+        let mut bus = TestBus::default();
+        engine.apply(&mut bus);
+        assert_eq!(bus.read_16(0x0200_0100), 0xbeef);
+    }
+
+    #[test]
+    fn test_write_hooks() {
+        let mut engine = CheatEngine::default();
+        engine
+            .add(
+                "test",
+                CheatFormat::CodeBreaker,
+                "82000020 1234; 74000130 03FF; 82000030 5678; 22000040 0001",
+            )
+            .unwrap();
+        // only the unconditional constant write is enforced on the game writes
+        let hooks = engine.write_hooks();
+        assert_eq!(
+            hooks,
+            vec![WriteHook {
+                addr: 0x0200_0020,
+                value: 0x1234,
+                width: 2,
+                count: 1
+            }]
+        );
+
+        let mut bus = TestBus::default();
+        bus.write_32(0x0200_0020, 0xffff_ffff);
+        hooks[0].on_write(&mut bus, 0x0200_0020, 4);
+        assert_eq!(bus.read_32(0x0200_0020), 0xffff_1234);
+        bus.write_16(0x0200_0022, 0);
+        hooks[0].on_write(&mut bus, 0x0200_0022, 2);
+        assert_eq!(bus.read_32(0x0200_0020), 0x0000_1234);
+    }
+
+    #[test]
+    fn test_codebreaker_conditions() {
+        let mut engine = CheatEngine::default();
+        engine
+            .add(
+                "test",
+                CheatFormat::CodeBreaker,
+                "74000130 03FF; 32000010 0063\n82000020 1234",
+            )
+            .unwrap();
+        let mut bus = TestBus::default();
+        engine.apply(&mut bus);
+        assert_eq!(bus.read_8(0x0200_0010), 0);
+        assert_eq!(bus.read_16(0x0200_0020), 0x1234);
+
+        bus.write_16(0x0400_0130, 0x03ff);
+        engine.apply(&mut bus);
+        assert_eq!(bus.read_8(0x0200_0010), 0x63);
+
+        assert!(Cheat::parse("bad", CheatFormat::CodeBreaker, "C2000010 1234").is_err());
+        assert!(Cheat::parse("bad", CheatFormat::CodeBreaker, "32000010").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+/// Best effort call stack for the `backtrace` command
+///
+/// Games are built without frame pointers or unwind tables, so the return addresses are found by
+/// scanning the stack for values that point right after a BL instruction, the way most crash
+/// handlers do it. Stale return addresses left on the stack may show up as extra frames.
+use crate::arm7tdmi::memory::MemoryInterface;
+use crate::arm7tdmi::{REG_LR, REG_SP};
+use crate::bus::{Addr, DebugRead};
+
+use super::GameBoyAdvance;
+
+/// How far up from sp the stack is scanned
+const MAX_STACK_SCAN: u32 = 0x800;
+
+/// Returns true if `value` looks like the lr saved by a BL, a thumb one if bit 0 is set
+fn is_return_address(gba: &mut GameBoyAdvance, value: u32) -> bool {
+    let sysbus = &mut gba.sysbus;
+    if value & 1 != 0 {
+        let addr = value & !1;
+        if addr < 4 || !sysbus.is_executable(addr - 4) {
+            return false;
+        }
+        // the two halves of a thumb long branch with link
+        let prefix = sysbus.debug_read_16(addr - 4);
+        let suffix = sysbus.debug_read_16(addr - 2);
+        prefix & 0xf800 == 0xf000 && suffix & 0xf800 == 0xf800
+    } else {
+        if value & 3 != 0 || value < 4 || !sysbus.is_executable(value - 4) {
+            return false;
+        }
+        let insn = sysbus.debug_read_32(value - 4);
+        insn >> 28 != 0xf && insn & 0x0f00_0000 == 0x0b00_0000
+    }
+}
+
+/// The address of the next instruction, followed by the likely return addresses
+/// from the innermost to the outermost call
+pub fn backtrace(gba: &mut GameBoyAdvance, max_frames: usize) -> Vec<Addr> {
+    let mut frames = vec![gba.cpu.get_next_pc()];
+
+    let lr = gba.cpu.get_reg(REG_LR);
+    let mut candidates = vec![lr];
+    let sp = gba.cpu.get_reg(REG_SP) & !3;
+    // don't run past the end of the memory region of the stack
+    let region_end = (sp | 0x00ff_ffff).wrapping_add(1);
+    let scan_end = if region_end == 0 {
+        sp.saturating_add(MAX_STACK_SCAN)
+    } else {
+        region_end.min(sp.saturating_add(MAX_STACK_SCAN))
+    };
+    let mut addr = sp;
+    while addr < scan_end {
+        candidates.push(gba.sysbus.debug_read_32(addr));
+        addr += 4;
+    }
+
+    for value in candidates {
+        if frames.len() >= max_frames {
+            break;
+        }
+        if is_return_address(gba, value) {
+            let ret = value & !1;
+            if frames.last() != Some(&ret) {
+                frames.push(ret);
+            }
+        }
+    }
+    frames
+}
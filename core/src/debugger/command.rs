@@ -1,18 +1,29 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::time;
 
 use crate::arm7tdmi::arm::ArmInstruction;
+use crate::arm7tdmi::hooks::{HookResult, WatchKind};
 use crate::arm7tdmi::thumb::ThumbInstruction;
-use crate::arm7tdmi::CpuState;
+use crate::arm7tdmi::trace::{InstructionTrace, TraceFilter};
+use crate::arm7tdmi::{CpuError, CpuState, InstructionDecoder, REG_LR, REG_SP};
 use crate::bus::{Addr, Bus, DebugRead};
-use crate::disass::Disassembler;
+use crate::cartridge::elf_symbols;
+use crate::gpu::consts::CYCLES_FULL_REFRESH;
+use crate::iodev::io_reg_string;
 use crate::util::{read_bin_file, write_bin_file};
 
 // use super::palette_view::create_palette_view;
 // use super::tile_view::create_tile_view;
+use super::backtrace::backtrace;
+use super::hashbreak::HashBreakpoint;
+use super::io_regs::{dump_io_registers, load_io_registers};
+use super::strings::{find_strings, Encoding, TextDecoder};
 use super::GameBoyAdvance;
-use super::{parser::Value, Debugger, DebuggerError, DebuggerResult};
+use super::{parser::Value, Condition, Debugger, DebuggerError, DebuggerResult};
 
 use ansi_term::Colour;
 
@@ -30,12 +41,22 @@ pub enum DisassMode {
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub enum MemWriteCommandSize {
+pub enum MemAccessSize {
     Byte,
     Half,
     Word,
 }
 
+impl MemAccessSize {
+    fn width(&self) -> usize {
+        match self {
+            MemAccessSize::Byte => 1,
+            MemAccessSize::Half => 2,
+            MemAccessSize::Word => 4,
+        }
+    }
+}
+
 bitflags! {
     pub struct TraceFlags: u32 {
         const TRACE_SYSBUS = 0b00000001;
@@ -43,6 +64,9 @@ bitflags! {
         const TRACE_DMA = 0b00000100;
         const TRACE_TIMERS = 0b000001000;
         const TRACE_EXCEPTIONS = 0b000001000;
+        const TRACE_SCANLINE = 0b000010000;
+        const TRACE_IO = 0b000100000;
+        const TRACE_TRANSITIONS = 0b001000000;
     }
 }
 
@@ -58,58 +82,293 @@ pub enum InfoCommand {
 pub enum Command {
     Info(InfoCommand),
     Step(usize),
+    /// Steps over calls, by running until the instruction after a BL or SWI
+    Next,
+    /// Runs until the current function returns
+    Finish,
+    /// Runs until the instruction at the address
+    Until(Addr),
     Continue,
     Frame(usize),
     HexDump(Addr, u32),
-    MemWrite(MemWriteCommandSize, Addr, u32),
+    MemRead(MemAccessSize, Addr),
+    /// address, length, access size, value to look for
+    MemSearch(Addr, u32, MemAccessSize, u32),
+    /// address, length, encoding, minimum number of characters
+    Strings(Addr, u32, Encoding, usize),
+    MemWrite(MemAccessSize, Addr, u32),
     Disass(DisassMode, Addr, u32),
-    AddBreakpoint(Addr),
+    AddBreakpoint(Addr, Option<Condition>),
     DelBreakpoint(Addr),
     // PaletteView,
     // TileView(u32),
     ClearBreakpoints,
     ListBreakpoints,
+    AddWatchpoint(WatchKind, Addr, u32),
+    DelWatchpoint(usize),
+    ClearWatchpoints,
+    ListWatchpoints,
+    /// address, length of a region that breaks at the end of a frame that changed it
+    AddHashBreakpoint(Addr, u32),
+    DelHashBreakpoint(usize),
+    ClearHashBreakpoints,
+    ListHashBreakpoints,
     Reset,
+    SetCartridgeInserted(bool),
     Quit,
     TraceToggle(TraceFlags),
+    /// Starts writing the executed instructions to a file, or stops with `None`
+    TraceFile(Option<(PathBuf, TraceFilter)>),
     SaveState(String),
     LoadState(String),
+    DumpIoRegisters(String),
+    LoadIoRegisters(String),
     AddSymbolsFile(PathBuf, Option<u32>),
     ListSymbols(Option<String>),
+    Timeline(Option<usize>),
+    Backtrace(usize),
+    History(Option<usize>),
+    HistorySize(usize),
+    #[cfg(feature = "debugger_tui")]
+    Tui,
+}
+
+/// How many instructions `Debugger::run_until_break` runs between checks of its `interrupted`
+/// callback
+const INTERRUPT_CHECK_INTERVAL: usize = 0x10000;
+
+/// Why `Debugger::run_until_break` returned
+pub enum BreakReason {
+    /// A watchpoint, or a breakpoint whose condition held
+    Hook(HookResult),
+    CpuError(CpuError),
+    /// The indices of the hash breakpoints whose region changed during the last frame
+    HashChanged(Vec<usize>),
+    Interrupted,
 }
 
-fn find_nearest_symbol(addr: u32, symbols: &HashMap<String, u32>) -> Option<(String, u32)> {
-    let mut smallest_distance = u32::MAX;
-    let mut symbol = String::new();
+/// Steps until the cpu executed an instruction, it can be halted or stalled by a dma for a while
+pub fn step_instruction(gba: &mut GameBoyAdvance) -> Option<HookResult> {
+    let mut hook = gba.step_debugger();
+    while gba.cpu.dbg.last_executed.is_none() {
+        hook = hook.or(gba.step_debugger());
+    }
+    hook
+}
 
-    for (k, v) in symbols.iter().filter(|(_k, v)| **v > addr) {
-        let distance = v - addr;
-        if distance < smallest_distance {
-            smallest_distance = distance;
-            symbol = k.to_string();
+fn print_cpu_error(gba: &GameBoyAdvance, err: &CpuError) {
+    println!(
+        "{}",
+        Colour::Red.bold().paint(format!("cpu error: {}", err))
+    );
+    match &gba.cpu.dbg.error_history {
+        Some(history) => history.dump(),
+        None => gba.cpu.dbg.history.dump(),
+    }
+}
+
+/// Stops execution when the cpu ran into an error, so it can be inspected
+fn report_cpu_error(gba: &mut GameBoyAdvance) -> bool {
+    match gba.cpu.take_error() {
+        Some(err) => {
+            print_cpu_error(gba, &err);
+            true
         }
+        None => false,
     }
+}
 
-    if smallest_distance < 0x1000 {
-        let symaddr = *symbols.get(&symbol).unwrap();
-        Some((symbol, symaddr))
+/// Parses the count of an `x/<n>` command, either decimal or 0x prefixed hex
+fn parse_count(s: &str) -> Option<u32> {
+    if s.starts_with("0x") {
+        u32::from_str_radix(&s[2..], 16).ok()
     } else {
-        None
+        s.parse().ok()
+    }
+}
+
+/// Prints one instruction per line, with its bytes in memory order, and marks the one at `pc`.
+/// Instructions that `label` names are preceded by a `<label>:` line.
+/// Like GNU objdump, the two halves of a THUMB `bl` are shown as a single instruction.
+fn print_disassembly<D>(addr: Addr, bytes: &[u8], pc: Addr, label: &dyn Fn(Addr) -> Option<String>)
+where
+    D: InstructionDecoder + fmt::Display,
+{
+    let width = mem::size_of::<D::IntType>();
+    let mut offset = 0;
+    while offset + width <= bytes.len() {
+        let insn_addr = addr.wrapping_add(offset as u32);
+        if let Some(label) = label(insn_addr) {
+            println!("{}", Colour::Cyan.paint(format!("<{}>:", label)));
+        }
+        let insn = D::decode_from_bytes(&bytes[offset..], insn_addr);
+        let pair = if offset + 2 * width <= bytes.len() {
+            let next_addr = insn_addr.wrapping_add(width as u32);
+            insn.fmt_pair(&D::decode_from_bytes(&bytes[offset + width..], next_addr))
+        } else {
+            None
+        };
+        let (text, len) = match pair {
+            Some(text) => (text, 2 * width),
+            None => (insn.to_string(), width),
+        };
+        let raw: Vec<String> = bytes[offset..offset + len]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let line = format!("{:08x}:  {:<11}  {}", insn_addr, raw.join(" "), text);
+        offset += len;
+        if pc.wrapping_sub(insn_addr) < len as u32 {
+            println!("{}", Colour::Green.bold().paint(format!("=> {}", line)));
+        } else {
+            println!("   {}", line);
+        }
+    }
+}
+
+/// If the next instruction is a call (a BL or a SWI), the address it returns to
+fn next_call_return(gba: &mut GameBoyAdvance) -> Option<Addr> {
+    let pc = gba.cpu.get_next_pc();
+    match gba.cpu.get_cpu_state() {
+        CpuState::ARM => {
+            let insn = gba.sysbus.debug_read_32(pc);
+            let is_call = insn >> 28 != 0xf
+                && (insn & 0x0f00_0000 == 0x0b00_0000 || insn & 0x0f00_0000 == 0x0f00_0000);
+            if is_call {
+                Some(pc.wrapping_add(4))
+            } else {
+                None
+            }
+        }
+        CpuState::THUMB => {
+            let insn = gba.sysbus.debug_read_16(pc);
+            if insn & 0xf800 == 0xf000 {
+                // the first half of a long branch with link, the second half follows it
+                Some(pc.wrapping_add(4))
+            } else if insn & 0xff00 == 0xdf00 {
+                Some(pc.wrapping_add(2))
+            } else {
+                None
+            }
+        }
     }
 }
 
 impl Debugger {
+    fn report_break(&self, gba: &GameBoyAdvance, reason: BreakReason) {
+        match reason {
+            BreakReason::CpuError(err) => print_cpu_error(gba, &err),
+            BreakReason::Hook(hit @ HookResult::Watchpoint { .. }) => {
+                println!("Watchpoint reached! {}", hit)
+            }
+            BreakReason::Hook(HookResult::Breakpoint(breakpoint)) => {
+                println!("Breakpoint reached! @{}", self.format_addr(gba, breakpoint));
+            }
+            BreakReason::HashChanged(changed) => self.report_hash_changes(gba, &changed),
+            BreakReason::Interrupted => {}
+        }
+    }
+
+    /// Runs with a temporary breakpoint at `addr`, until it is reached with a stack pointer of at
+    /// least `min_sp` (so that deeper recursive calls don't stop it), or until something else breaks
+    fn run_to(&mut self, gba: &mut GameBoyAdvance, addr: Addr, min_sp: Option<u32>) {
+        let temporary = gba.add_breakpoint(addr).is_some();
+        let reason = loop {
+            let reason = self.run_until_break(gba, || false);
+            let deeper = min_sp.map_or(false, |sp| gba.cpu.get_reg(REG_SP) < sp);
+            match reason {
+                BreakReason::Hook(HookResult::Breakpoint(hit)) if hit == addr && deeper => {}
+                reason => break reason,
+            }
+        };
+        if temporary {
+            gba.cpu.dbg.breakpoints.retain(|&a| a != addr);
+        }
+        match reason {
+            BreakReason::Hook(HookResult::Breakpoint(hit)) if hit == addr && temporary => {
+                println!("Stopped at {}", self.format_addr(gba, addr));
+            }
+            reason => self.report_break(gba, reason),
+        }
+    }
+
+    /// Runs until a watchpoint, a breakpoint whose condition holds, the end of a frame that
+    /// changed the region of a hash breakpoint or a cpu error.
+    /// `interrupted` is polled every now and then, to stop early.
+    pub fn run_until_break<F>(
+        &mut self,
+        gba: &mut GameBoyAdvance,
+        mut interrupted: F,
+    ) -> BreakReason
+    where
+        F: FnMut() -> bool,
+    {
+        let mut steps = 0;
+        let mut frame = gba.scheduler.timestamp() / CYCLES_FULL_REFRESH;
+        loop {
+            gba.key_poll();
+            let hook = gba.step_debugger();
+            if let Some(err) = gba.cpu.take_error() {
+                return BreakReason::CpuError(err);
+            }
+            match hook {
+                Some(HookResult::Breakpoint(breakpoint)) => {
+                    let holds = match self.breakpoint_conditions.get(&breakpoint) {
+                        Some(condition) => condition.eval(gba),
+                        None => true,
+                    };
+                    if holds {
+                        return BreakReason::Hook(HookResult::Breakpoint(breakpoint));
+                    }
+                }
+                Some(hit) => return BreakReason::Hook(hit),
+                None => {}
+            }
+            let current_frame = gba.scheduler.timestamp() / CYCLES_FULL_REFRESH;
+            if current_frame != frame {
+                frame = current_frame;
+                let changed = self.check_hash_breakpoints(gba);
+                if !changed.is_empty() {
+                    return BreakReason::HashChanged(changed);
+                }
+            }
+            steps += 1;
+            if steps % INTERRUPT_CHECK_INTERVAL == 0 && interrupted() {
+                return BreakReason::Interrupted;
+            }
+        }
+    }
+
+    /// Rehashes the regions of the hash breakpoints, returns the indices of the ones that changed
+    fn check_hash_breakpoints(&mut self, gba: &mut GameBoyAdvance) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for (index, bp) in self.hash_breakpoints.iter_mut().enumerate() {
+            let bytes = gba.sysbus.debug_get_bytes(bp.range());
+            if bp.update(&bytes) {
+                changed.push(index);
+            }
+        }
+        changed
+    }
+
+    fn report_hash_changes(&self, gba: &GameBoyAdvance, changed: &[usize]) {
+        for &index in changed {
+            println!(
+                "Hash breakpoint [{}] changed! {} @0x{:08x}",
+                index,
+                self.hash_breakpoints[index],
+                gba.cpu.get_next_pc()
+            );
+        }
+    }
+
     pub fn run_command(&mut self, gba: &mut GameBoyAdvance, command: Command) {
         use Command::*;
         #[allow(unreachable_patterns)]
         match command {
             Info(InfoCommand::Cpu) => {
-                let pc = gba.cpu.pc;
-                if let Some((sym, addr)) = find_nearest_symbol(pc, &self.symbols) {
-                    println!("PC at {}+{:#x} ({:08x})", sym, addr - pc, pc);
-                } else {
-                    println!("PC at {:08x}", pc);
-                }
+                let pc = gba.cpu.get_next_pc();
+                println!("PC at {}", self.format_addr(gba, pc));
 
                 println!("{}", gba.cpu);
                 // println!("IME={}", gba.io_devs.intc.interrupt_master_enable);
@@ -125,16 +384,19 @@ impl Debugger {
             Info(InfoCommand::Gpio) => println!("GPIO: {:#?}", gba.sysbus.cartridge.get_gpio()),
             Step(count) => {
                 for _ in 0..count {
-                    gba.step_debugger();
-                    while gba.cpu.dbg.last_executed.is_none() {
-                        gba.step_debugger();
+                    let watchpoint = step_instruction(gba);
+                    if report_cpu_error(gba) {
+                        break;
+                    }
+                    if let Some(hit @ HookResult::Watchpoint { .. }) = watchpoint {
+                        println!("{}", Colour::Yellow.paint(format!("Hit {}", hit)));
                     }
                     if let Some(last_executed) = &gba.cpu.dbg.last_executed {
                         let pc = last_executed.get_pc();
-                        let symbol =
-                            self.symbols
-                                .iter()
-                                .find_map(|(key, &val)| if val == pc { Some(key) } else { None });
+                        let symbol = match self.nearest_symbol(gba, pc) {
+                            Some((symbol, 0)) => Some(symbol),
+                            _ => None,
+                        };
 
                         let text = if let Some(symbol) = symbol {
                             format!("Executed at {} @0x{:08x}:", symbol, pc)
@@ -158,57 +420,112 @@ impl Debugger {
                 println!("cycles: {}", gba.scheduler.timestamp());
                 println!("{}\n", gba.cpu);
             }
-            Continue => 'running: loop {
-                gba.key_poll();
-                if let Some(breakpoint) = gba.step_debugger() {
-                    let mut bp_sym = None;
-                    if let Some(symbols) = gba.sysbus.cartridge.get_symbols() {
-                        for s in symbols.keys() {
-                            if symbols.get(s).unwrap() == &breakpoint {
-                                bp_sym = Some(s.clone());
-                            }
-                        }
-                    }
-                    if let Some(sym) = bp_sym {
-                        println!("Breakpoint reached! @{}", sym);
-                    } else {
-                        println!("Breakpoint reached! @{:x}", breakpoint);
-                    }
-                    break 'running;
+            Continue => {
+                let reason = self.run_until_break(gba, || false);
+                self.report_break(gba, reason);
+            }
+            Next => {
+                let sp = gba.cpu.get_reg(REG_SP);
+                match next_call_return(gba) {
+                    Some(addr) => self.run_to(gba, addr, Some(sp)),
+                    None => self.run_command(gba, Step(1)),
                 }
-            },
+            }
+            Finish => {
+                let lr = gba.cpu.get_reg(REG_LR) & !1;
+                // the caller's stack pointer is at least the current one
+                let sp = gba.cpu.get_reg(REG_SP);
+                self.run_to(gba, lr, Some(sp));
+            }
+            Until(addr) => self.run_to(gba, addr, None),
             Frame(count) => {
                 let start = time::Instant::now();
                 for _ in 0..count {
                     gba.frame();
+                    if report_cpu_error(gba) {
+                        break;
+                    }
+                    let changed = self.check_hash_breakpoints(gba);
+                    if !changed.is_empty() {
+                        self.report_hash_changes(gba, &changed);
+                        break;
+                    }
                 }
                 let end = time::Instant::now();
                 println!("that took {:?} seconds", end - start);
             }
             HexDump(addr, nbytes) => {
-                let bytes = gba.sysbus.debug_get_bytes(addr..addr + nbytes);
+                let bytes = gba
+                    .sysbus
+                    .debug_get_bytes(addr..addr.saturating_add(nbytes));
                 hexdump::hexdump(&bytes);
             }
+            Strings(addr, len, encoding, min_len) => {
+                let decoder = match TextDecoder::new(&encoding) {
+                    Ok(decoder) => decoder,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                };
+                let bytes = gba.sysbus.debug_get_bytes(addr..addr.saturating_add(len));
+                let strings = find_strings(&bytes, addr, &decoder, min_len);
+                for (addr, s) in strings.iter() {
+                    println!("{:08x}: {}", addr, s);
+                }
+                println!("found {} strings", strings.len());
+            }
+            MemRead(size, addr) => match size {
+                MemAccessSize::Byte => {
+                    println!("0x{:08x}: 0x{:02x}", addr, gba.sysbus.read_8(addr))
+                }
+                MemAccessSize::Half => {
+                    println!("0x{:08x}: 0x{:04x}", addr, gba.sysbus.read_16(addr))
+                }
+                MemAccessSize::Word => {
+                    println!("0x{:08x}: 0x{:08x}", addr, gba.sysbus.read_32(addr))
+                }
+            },
             MemWrite(size, addr, val) => match size {
-                MemWriteCommandSize::Byte => gba.sysbus.write_8(addr, val as u8),
-                MemWriteCommandSize::Half => gba.sysbus.write_16(addr, val as u16),
-                MemWriteCommandSize::Word => gba.sysbus.write_32(addr, val as u32),
+                MemAccessSize::Byte => gba.sysbus.write_8(addr, val as u8),
+                MemAccessSize::Half => gba.sysbus.write_16(addr, val as u16),
+                MemAccessSize::Word => gba.sysbus.write_32(addr, val as u32),
             },
+            MemSearch(addr, len, size, val) => {
+                let bytes = gba.sysbus.debug_get_bytes(addr..addr.saturating_add(len));
+                let width = size.width();
+                let mut found = 0;
+                for (i, chunk) in bytes.chunks_exact(width).enumerate() {
+                    let mut current = 0u32;
+                    for (j, b) in chunk.iter().enumerate() {
+                        current |= (*b as u32) << (8 * j);
+                    }
+                    if current == val {
+                        println!("0x{:08x}", addr + (i * width) as u32);
+                        found += 1;
+                    }
+                }
+                println!("found {} matches", found);
+            }
             Disass(mode, addr, n) => {
+                let pc = gba.cpu.get_next_pc();
+                let width = match mode {
+                    DisassMode::ModeArm => 4,
+                    DisassMode::ModeThumb => 2,
+                };
+                let bytes = gba
+                    .sysbus
+                    .debug_get_bytes(addr..addr.saturating_add(width.saturating_mul(n)));
+                let label = |addr| match self.nearest_symbol(gba, addr) {
+                    Some((symbol, 0)) => Some(symbol.to_string()),
+                    _ => None,
+                };
                 match mode {
                     DisassMode::ModeArm => {
-                        let bytes = gba.sysbus.debug_get_bytes(addr..addr + 4 * n);
-                        let disass = Disassembler::<ArmInstruction>::new(addr, &bytes);
-                        for (_, line) in disass.take(n as usize) {
-                            println!("{}", line)
-                        }
+                        print_disassembly::<ArmInstruction>(addr, &bytes, pc, &label)
                     }
                     DisassMode::ModeThumb => {
-                        let bytes = gba.sysbus.debug_get_bytes(addr..addr + 2 * n);
-                        let disass = Disassembler::<ThumbInstruction>::new(addr, &bytes);
-                        for (_, line) in disass.take(n as usize) {
-                            println!("{}", line)
-                        }
+                        print_disassembly::<ThumbInstruction>(addr, &bytes, pc, &label)
                     }
                 };
             }
@@ -216,16 +533,72 @@ impl Debugger {
                 print!("Quitting!");
                 self.stop();
             }
-            AddBreakpoint(addr) => match gba.add_breakpoint(addr) {
-                Some(index) => println!("Added breakpoint [{}] 0x{:08x}", index, addr),
-                None => println!("Breakpint already exists."),
-            },
+            AddBreakpoint(addr, condition) => {
+                match gba.add_breakpoint(addr) {
+                    Some(index) => println!("Added breakpoint [{}] 0x{:08x}", index, addr),
+                    None if condition.is_some() => println!("Updated breakpoint condition."),
+                    None if self.breakpoint_conditions.contains_key(&addr) => {
+                        println!("Breakpoint already exists, its condition is kept.")
+                    }
+                    None => println!("Breakpint already exists."),
+                }
+                // `breakdel` drops the condition along with the breakpoint
+                if let Some(condition) = condition {
+                    self.breakpoint_conditions.insert(addr, condition);
+                }
+            }
             DelBreakpoint(addr) => self.delete_breakpoint(gba, addr),
-            ClearBreakpoints => gba.cpu.dbg.breakpoints.clear(),
+            ClearBreakpoints => {
+                gba.cpu.dbg.breakpoints.clear();
+                self.breakpoint_conditions.clear();
+            }
             ListBreakpoints => {
                 println!("breakpoint list:");
                 for (i, b) in gba.cpu.dbg.breakpoints.iter().enumerate() {
-                    println!("[{}] 0x{:08x}", i, b)
+                    match self.breakpoint_conditions.get(b) {
+                        Some(condition) => println!("[{}] 0x{:08x} if {}", i, b, condition),
+                        None => println!("[{}] 0x{:08x}", i, b),
+                    }
+                }
+            }
+            AddWatchpoint(kind, addr, len) => {
+                let index = gba.add_watchpoint(addr..addr.wrapping_add(len), kind);
+                println!("Added watchpoint [{}] {} 0x{:08x}", index, kind, addr)
+            }
+            DelWatchpoint(index) => match gba.cpu.dbg.watchpoints.remove(index) {
+                Some(w) => println!("Deleted watchpoint [{}] {}", index, w),
+                None => println!("No such watchpoint [{}]", index),
+            },
+            ClearWatchpoints => gba.cpu.dbg.watchpoints.clear(),
+            ListWatchpoints => {
+                println!("watchpoint list:");
+                for (i, w) in gba.cpu.dbg.watchpoints.iter().enumerate() {
+                    println!("[{}] {}", i, w)
+                }
+            }
+            AddHashBreakpoint(addr, len) => {
+                let bytes = gba.sysbus.debug_get_bytes(addr..addr.saturating_add(len));
+                let bp = HashBreakpoint::new(addr, len, &bytes);
+                println!(
+                    "Added hash breakpoint [{}] {}",
+                    self.hash_breakpoints.len(),
+                    bp
+                );
+                self.hash_breakpoints.push(bp);
+            }
+            DelHashBreakpoint(index) => {
+                if index < self.hash_breakpoints.len() {
+                    let bp = self.hash_breakpoints.remove(index);
+                    println!("Deleted hash breakpoint [{}] {}", index, bp);
+                } else {
+                    println!("No such hash breakpoint [{}]", index);
+                }
+            }
+            ClearHashBreakpoints => self.hash_breakpoints.clear(),
+            ListHashBreakpoints => {
+                println!("hash breakpoint list:");
+                for (i, bp) in self.hash_breakpoints.iter().enumerate() {
+                    println!("[{}] {}", i, bp)
                 }
             }
             // PaletteView => create_palette_view(&gba.sysbus.palette_ram.mem),
@@ -235,6 +608,14 @@ impl Debugger {
                 gba.cpu.reset();
                 println!("cpu is restarted!")
             }
+            SetCartridgeInserted(inserted) => {
+                gba.set_cartridge_inserted(inserted);
+                if inserted {
+                    println!("cartridge inserted");
+                } else {
+                    println!("cartridge removed");
+                }
+            }
             TraceToggle(flags) => {
                 if flags.contains(TraceFlags::TRACE_OPCODE) {
                     println!("[*] opcode tracing not implemented")
@@ -272,6 +653,96 @@ impl Debugger {
                         }
                     )
                 }
+                if flags.contains(TraceFlags::TRACE_IO) {
+                    gba.sysbus.io.trace_writes = !gba.sysbus.io.trace_writes;
+                    println!(
+                        "[*] io write tracing {}",
+                        if gba.sysbus.io.trace_writes {
+                            "on"
+                        } else {
+                            "off"
+                        }
+                    )
+                }
+                if flags.contains(TraceFlags::TRACE_TRANSITIONS) {
+                    gba.cpu.dbg.trace_transitions = !gba.cpu.dbg.trace_transitions;
+                    println!(
+                        "[*] cpu mode/state transition tracing {}",
+                        if gba.cpu.dbg.trace_transitions {
+                            "on"
+                        } else {
+                            "off"
+                        }
+                    )
+                }
+                if flags.contains(TraceFlags::TRACE_SCANLINE) {
+                    let trace = &mut gba.sysbus.io.gpu.scanline_trace;
+                    trace.set_enabled(!trace.is_enabled());
+                    println!(
+                        "[*] scanline tracing {}",
+                        if trace.is_enabled() { "on" } else { "off" }
+                    )
+                }
+            }
+            TraceFile(None) => {
+                if let Some(trace) = &gba.cpu.dbg.trace {
+                    println!("[*] traced {} instructions", trace.borrow().lines());
+                }
+                gba.cpu.set_trace(None);
+            }
+            TraceFile(Some((path, filter))) => match InstructionTrace::create(&path, filter) {
+                Ok(trace) => {
+                    gba.cpu.set_trace(Some(trace));
+                    println!("[*] tracing instructions to {}", path.display());
+                }
+                Err(e) => println!("failed to create {}: {}", path.display(), e),
+            },
+            Timeline(line) => {
+                let trace = &gba.sysbus.io.gpu.scanline_trace;
+                if !trace.is_enabled() {
+                    println!("[*] scanline tracing is off, enable it with 'trace scanline'");
+                    return;
+                }
+                let mut current_line = None;
+                for record in trace
+                    .last_frame()
+                    .iter()
+                    .filter(|r| line.map_or(true, |l| l == r.scanline))
+                {
+                    if current_line != Some(record.scanline) {
+                        current_line = Some(record.scanline);
+                        println!(
+                            "{}",
+                            Colour::Cyan.paint(format!("line {:3}:", record.scanline))
+                        );
+                    }
+                    println!("\t{} <= 0x{:04x}", io_reg_string(record.addr), record.value);
+                }
+                if current_line.is_none() {
+                    println!("no io writes recorded");
+                }
+            }
+            Backtrace(max_frames) => {
+                for (i, addr) in backtrace(gba, max_frames).into_iter().enumerate() {
+                    println!("#{:<2} {}", i, self.format_addr(gba, addr));
+                }
+            }
+            History(count) => {
+                let history = &gba.cpu.dbg.history;
+                let skip = count.map_or(0, |count| history.len().saturating_sub(count));
+                for entry in history.iter().skip(skip) {
+                    println!("{}", entry);
+                }
+            }
+            HistorySize(size) => {
+                gba.cpu.dbg.history.set_capacity(size);
+                println!("[*] keeping the last {} executed instructions", size);
+            }
+            #[cfg(feature = "debugger_tui")]
+            Tui => {
+                if let Err(e) = self.tui(gba) {
+                    println!("{}: {:?}", Colour::Red.paint("tui error"), e);
+                }
             }
             SaveState(save_path) => {
                 let state = gba.save_state().expect("failed to serialize");
@@ -283,18 +754,33 @@ impl Debugger {
                     .expect("failed to read save state from file");
                 gba.restore_state(&save).expect("failed to deserialize");
             }
+            DumpIoRegisters(path) => {
+                let dump = dump_io_registers(&mut gba.io_devs);
+                match fs::write(&path, dump) {
+                    Ok(_) => println!("[*] dumped io registers to {}", path),
+                    Err(e) => println!("failed to write {}: {}", path, e),
+                }
+            }
+            LoadIoRegisters(path) => {
+                let result = fs::read_to_string(&path)
+                    .map_err(|e| format!("failed to read {}: {}", path, e))
+                    .and_then(|content| load_io_registers(&mut gba.io_devs, &content));
+                match result {
+                    Ok(count) => println!("[*] loaded {} io registers from {}", count, path),
+                    Err(e) => println!("{}", e),
+                }
+            }
             ListSymbols(Some(pattern)) => {
                 let matcher = SkimMatcherV2::default();
                 for (k, v) in self
-                    .symbols
-                    .iter()
+                    .all_symbols(gba)
                     .filter(|(k, _v)| matcher.fuzzy_match(k, &pattern).is_some())
                 {
                     println!("{}=0x{:08x}", k, v);
                 }
             }
             ListSymbols(None) => {
-                for (k, v) in self.symbols.iter() {
+                for (k, v) in self.all_symbols(gba) {
                     println!("{}=0x{:08x}", k, v);
                 }
             }
@@ -302,14 +788,8 @@ impl Debugger {
                 let offset = offset.unwrap_or(0);
                 if let Ok(elf_buffer) = read_bin_file(&elf_file) {
                     if let Ok(elf) = goblin::elf::Elf::parse(&elf_buffer) {
-                        let strtab = elf.strtab;
-                        for sym in elf.syms.iter() {
-                            if let Some(Ok(name)) = strtab.get(sym.st_name) {
-                                self.symbols
-                                    .insert(name.to_owned(), offset + (sym.st_value as u32));
-                            } else {
-                                warn!("failed to parse symbol name sym {:?}", sym);
-                            }
+                        for (name, addr) in elf_symbols(&elf) {
+                            self.symbols.insert(name, offset + addr);
                         }
                     } else {
                         println!("[error] Failed to parse elf file!");
@@ -324,36 +804,73 @@ impl Debugger {
         }
     }
 
+    /// Parses `[addr] [n] [arm|thumb]`, the instruction set defaults to the current cpu state.
+    /// Without an address, continues after the last disassembled instruction or starts at the pc.
     fn get_disassembler_args(
         &self,
         gba: &GameBoyAdvance,
-        args: Vec<Value>,
-    ) -> DebuggerResult<(Addr, u32)> {
-        match args.len() {
+        mut args: Vec<Value>,
+        default_mode: Option<DisassMode>,
+    ) -> DebuggerResult<Command> {
+        let usage =
+            DebuggerError::InvalidCommandFormat("disass [addr] [n] [arm|thumb]".to_string());
+        let mut mode = default_mode;
+        if let Some(Value::Identifier(isa)) = args.last() {
+            let isa = match isa.as_ref() {
+                "arm" => Some(DisassMode::ModeArm),
+                "thumb" => Some(DisassMode::ModeThumb),
+                _ => None,
+            };
+            if isa.is_some() {
+                if default_mode.is_some() {
+                    return Err(usage);
+                }
+                mode = isa;
+                args.pop();
+            }
+        }
+        let mode = mode.unwrap_or_else(|| match gba.cpu.get_cpu_state() {
+            CpuState::ARM => DisassMode::ModeArm,
+            CpuState::THUMB => DisassMode::ModeThumb,
+        });
+        let word_size = match mode {
+            DisassMode::ModeArm => 4,
+            DisassMode::ModeThumb => 2,
+        };
+
+        let (addr, n) = match args.len() {
             2 => {
                 let addr = self.val_address(gba, &args[0])?;
                 let n = self.val_number(&args[1])?;
+                if n.checked_mul(word_size).is_none() {
+                    return Err(DebuggerError::InvalidArgument(format!(
+                        "can't disassemble {} instructions",
+                        n
+                    )));
+                }
 
-                Ok((addr, n))
+                (addr, n)
             }
             1 => {
                 let addr = self.val_address(gba, &args[0])?;
 
-                Ok((addr, 10))
+                (addr, 10)
             }
             0 => {
-                if let Some(Command::Disass(_mode, addr, n)) = &self.previous_command {
-                    Ok((*addr + (*n as u32), 10))
+                if let Some(Command::Disass(prev_mode, addr, n)) = &self.previous_command {
+                    let prev_size = match prev_mode {
+                        DisassMode::ModeArm => 4,
+                        DisassMode::ModeThumb => 2,
+                    };
+                    (addr.wrapping_add(n * prev_size), 10)
                 } else {
-                    Ok((gba.cpu.get_next_pc(), 10))
+                    (gba.cpu.get_next_pc(), 10)
                 }
             }
-            _ => {
-                return Err(DebuggerError::InvalidCommandFormat(
-                    "disass [addr] [n]".to_string(),
-                ))
-            }
-        }
+            _ => return Err(usage),
+        };
+
+        Ok(Command::Disass(mode, addr & !(word_size - 1), n))
     }
 
     pub fn eval_command(
@@ -404,6 +921,14 @@ impl Debugger {
                 };
                 Ok(Command::Step(count as usize))
             }
+            "n" | "next" => Ok(Command::Next),
+            "fin" | "finish" => Ok(Command::Finish),
+            "u" | "until" => match args.as_slice() {
+                [arg] => Ok(Command::Until(self.val_address(gba, arg)?)),
+                _ => Err(DebuggerError::InvalidCommandFormat(
+                    "until <addr>".to_string(),
+                )),
+            },
             "c" | "continue" => Ok(Command::Continue),
             "f" | "frame" => {
                 let count = match args.len() {
@@ -432,7 +957,7 @@ impl Debugger {
                     }
                     0 => {
                         if let Some(Command::HexDump(addr, n)) = self.previous_command {
-                            (addr + (4 * n as u32), 0x100)
+                            (addr + n, 0x100)
                         } else {
                             (gba.cpu.get_reg(15), 0x100)
                         }
@@ -445,6 +970,80 @@ impl Debugger {
                 };
                 Ok(Command::HexDump(addr, n))
             }
+            cmd if cmd.starts_with("x/") => {
+                let usage = DebuggerError::InvalidCommandFormat("x/<n> [addr]".to_string());
+                let n = match parse_count(&cmd[2..]) {
+                    Some(n) => n,
+                    None => return Err(usage),
+                };
+                let addr = match args.len() {
+                    1 => self.val_address(gba, &args[0])?,
+                    0 => gba.cpu.get_reg(15),
+                    _ => return Err(usage),
+                };
+                Ok(Command::HexDump(addr, n))
+            }
+            "mrb" | "mrh" | "mrw" => {
+                if args.len() != 1 {
+                    return Err(DebuggerError::InvalidCommandFormat(format!(
+                        "{} <addr>",
+                        command
+                    )));
+                }
+                let size = match command.as_ref() {
+                    "mrb" => MemAccessSize::Byte,
+                    "mrh" => MemAccessSize::Half,
+                    _ => MemAccessSize::Word,
+                };
+                let addr = self.val_address(gba, &args[0])?;
+                Ok(Command::MemRead(size, addr))
+            }
+            "search" => {
+                let usage = DebuggerError::InvalidCommandFormat(String::from(
+                    "search <addr> <len> <value> [b|h|w]",
+                ));
+                if args.len() < 3 || args.len() > 4 {
+                    return Err(usage);
+                }
+                let addr = self.val_address(gba, &args[0])?;
+                let len = self.val_number(&args[1])?;
+                let val = self.val_number(&args[2])?;
+                let size = match args.get(3) {
+                    None => MemAccessSize::Word,
+                    Some(Value::Identifier(s)) => match s.as_ref() {
+                        "b" => MemAccessSize::Byte,
+                        "h" => MemAccessSize::Half,
+                        "w" => MemAccessSize::Word,
+                        _ => return Err(usage),
+                    },
+                    _ => return Err(usage),
+                };
+                Ok(Command::MemSearch(addr, len, size, val))
+            }
+            "strings" => {
+                let usage = DebuggerError::InvalidCommandFormat(String::from(
+                    "strings <addr> <len> [ascii|sjis|path/to/table.tbl] [min_len]",
+                ));
+                if args.len() < 2 || args.len() > 4 {
+                    return Err(usage);
+                }
+                let addr = self.val_address(gba, &args[0])?;
+                let len = self.val_number(&args[1])?;
+                let encoding = match args.get(2) {
+                    None => Encoding::Ascii,
+                    Some(Value::Identifier(name)) => match name.as_ref() {
+                        "ascii" => Encoding::Ascii,
+                        "sjis" | "shift-jis" => Encoding::ShiftJis,
+                        path => Encoding::Table(PathBuf::from(path)),
+                    },
+                    Some(_) => return Err(usage),
+                };
+                let min_len = match args.get(3) {
+                    Some(arg) => self.val_number(arg)? as usize,
+                    None => 4,
+                };
+                Ok(Command::Strings(addr, len, encoding, min_len))
+            }
             "mwb" => {
                 let (addr, val) = match args.len() {
                     2 => {
@@ -459,11 +1058,7 @@ impl Debugger {
                         ))
                     }
                 };
-                Ok(Command::MemWrite(
-                    MemWriteCommandSize::Byte,
-                    addr,
-                    val as u32,
-                ))
+                Ok(Command::MemWrite(MemAccessSize::Byte, addr, val as u32))
             }
             "mwh" => {
                 let (addr, val) = match args.len() {
@@ -475,15 +1070,11 @@ impl Debugger {
                     }
                     _ => {
                         return Err(DebuggerError::InvalidCommandFormat(
-                            "mwb [addr] [n]".to_string(),
+                            "mwh [addr] [n]".to_string(),
                         ))
                     }
                 };
-                Ok(Command::MemWrite(
-                    MemWriteCommandSize::Half,
-                    addr,
-                    val as u32,
-                ))
+                Ok(Command::MemWrite(MemAccessSize::Half, addr, val as u32))
             }
             "mww" => {
                 let (addr, val) = match args.len() {
@@ -495,43 +1086,25 @@ impl Debugger {
                     }
                     _ => {
                         return Err(DebuggerError::InvalidCommandFormat(
-                            "mwb [addr] [n]".to_string(),
+                            "mww [addr] [n]".to_string(),
                         ))
                     }
                 };
-                Ok(Command::MemWrite(
-                    MemWriteCommandSize::Half,
-                    addr,
-                    val as u32,
-                ))
-            }
-            "d" | "disass" => {
-                let (addr, n) = self.get_disassembler_args(gba, args)?;
-
-                let m = match gba.cpu.get_cpu_state() {
-                    CpuState::ARM => DisassMode::ModeArm,
-                    CpuState::THUMB => DisassMode::ModeThumb,
-                };
-                Ok(Command::Disass(m, addr, n))
-            }
-            "da" | "disass-arm" => {
-                let (addr, n) = self.get_disassembler_args(gba, args)?;
-
-                Ok(Command::Disass(DisassMode::ModeArm, addr, n))
+                Ok(Command::MemWrite(MemAccessSize::Word, addr, val as u32))
             }
+            "d" | "disass" => self.get_disassembler_args(gba, args, None),
+            "da" | "disass-arm" => self.get_disassembler_args(gba, args, Some(DisassMode::ModeArm)),
             "dt" | "disass-thumb" => {
-                let (addr, n) = self.get_disassembler_args(gba, args)?;
-
-                Ok(Command::Disass(DisassMode::ModeThumb, addr, n))
+                self.get_disassembler_args(gba, args, Some(DisassMode::ModeThumb))
             }
             "b" | "break" => {
                 if args.len() != 1 {
                     Err(DebuggerError::InvalidCommandFormat(
-                        "break <addr>".to_string(),
+                        "break <addr> [if <condition>]".to_string(),
                     ))
                 } else {
                     let addr = self.val_address(gba, &args[0])?;
-                    Ok(Command::AddBreakpoint(addr))
+                    Ok(Command::AddBreakpoint(addr, None))
                 }
             }
             "bd" | "breakdel" => match args.len() {
@@ -553,11 +1126,55 @@ impl Debugger {
             //     Ok(Command::TileView(bg))
             // }
             "bl" => Ok(Command::ListBreakpoints),
+            "w" | "watch" | "rwatch" | "awatch" => {
+                let usage =
+                    DebuggerError::InvalidCommandFormat(format!("{} <addr> [len]", command));
+                let kind = match command.as_ref() {
+                    "rwatch" => WatchKind::Read,
+                    "awatch" => WatchKind::Access,
+                    _ => WatchKind::Write,
+                };
+                let (addr, len) = match args.len() {
+                    1 => (self.val_address(gba, &args[0])?, 1),
+                    2 => (self.val_address(gba, &args[0])?, self.val_number(&args[1])?),
+                    _ => return Err(usage),
+                };
+                Ok(Command::AddWatchpoint(kind, addr, len))
+            }
+            "wd" | "delwatch" => match args.as_slice() {
+                [arg] => Ok(Command::DelWatchpoint(self.val_number(arg)? as usize)),
+                _ => Err(DebuggerError::InvalidCommandFormat(
+                    "delwatch <index>".to_string(),
+                )),
+            },
+            "wc" | "clearwatch" => Ok(Command::ClearWatchpoints),
+            "wl" => Ok(Command::ListWatchpoints),
+            "hb" | "hashbreak" => match args.as_slice() {
+                [addr, len] => {
+                    let addr = self.val_address(gba, addr)?;
+                    Ok(Command::AddHashBreakpoint(addr, self.val_number(len)?))
+                }
+                _ => Err(DebuggerError::InvalidCommandFormat(
+                    "hashbreak <addr> <len>".to_string(),
+                )),
+            },
+            "hbd" | "hashdel" => match args.as_slice() {
+                [arg] => Ok(Command::DelHashBreakpoint(self.val_number(arg)? as usize)),
+                _ => Err(DebuggerError::InvalidCommandFormat(
+                    "hashdel <index>".to_string(),
+                )),
+            },
+            "hbc" | "hashclear" => Ok(Command::ClearHashBreakpoints),
+            "hbl" => Ok(Command::ListHashBreakpoints),
             "q" | "quit" => Ok(Command::Quit),
+            #[cfg(feature = "debugger_tui")]
+            "tui" => Ok(Command::Tui),
             "r" | "reset" => Ok(Command::Reset),
+            "eject" => Ok(Command::SetCartridgeInserted(false)),
+            "insert" => Ok(Command::SetCartridgeInserted(true)),
             "trace" => {
                 let usage = DebuggerError::InvalidCommandFormat(String::from(
-                    "trace [sysbus|opcode|dma|all|exceptions]",
+                    "trace [sysbus|opcode|dma|timers|io|scanline|transitions|all|exceptions]",
                 ));
                 if args.len() != 1 {
                     Err(usage)
@@ -569,6 +1186,9 @@ impl Debugger {
                             "exceptions" => TraceFlags::TRACE_EXCEPTIONS,
                             "dma" => TraceFlags::TRACE_DMA,
                             "timers" => TraceFlags::TRACE_TIMERS,
+                            "io" => TraceFlags::TRACE_IO,
+                            "scanline" => TraceFlags::TRACE_SCANLINE,
+                            "transitions" => TraceFlags::TRACE_TRANSITIONS,
                             "all" => TraceFlags::all(),
                             _ => return Err(usage),
                         };
@@ -578,6 +1198,68 @@ impl Debugger {
                     }
                 }
             }
+            "trace-file" => {
+                let usage = DebuggerError::InvalidCommandFormat(String::from(
+                    "trace-file off | trace-file <path[.gz]> [pc <start> <end>] [mode <mode>] [class <class>]...",
+                ));
+                let path = match args.first() {
+                    Some(Value::Identifier(path)) if path == "off" => {
+                        return Ok(Command::TraceFile(None))
+                    }
+                    Some(Value::Identifier(path)) => PathBuf::from(path),
+                    _ => return Err(usage),
+                };
+                let mut filter = TraceFilter::default();
+                let mut rest = &args[1..];
+                while !rest.is_empty() {
+                    rest = match rest {
+                        [Value::Identifier(key), start, end, tail @ ..] if key == "pc" => {
+                            let start = self.val_address(gba, start)?;
+                            let end = self.val_address(gba, end)?;
+                            filter.pc_range = Some(start..end);
+                            tail
+                        }
+                        [Value::Identifier(key), Value::Identifier(value), tail @ ..] => {
+                            filter
+                                .add(key, value)
+                                .map_err(DebuggerError::InvalidArgument)?;
+                            tail
+                        }
+                        _ => return Err(usage),
+                    };
+                }
+                Ok(Command::TraceFile(Some((path, filter))))
+            }
+            "timeline" => match args.len() {
+                0 => Ok(Command::Timeline(None)),
+                1 => Ok(Command::Timeline(Some(self.val_number(&args[0])? as usize))),
+                _ => Err(DebuggerError::InvalidCommandFormat(String::from(
+                    "timeline [scanline]",
+                ))),
+            },
+            "bt" | "backtrace" => match args.len() {
+                0 => Ok(Command::Backtrace(16)),
+                1 => Ok(Command::Backtrace(self.val_number(&args[0])? as usize)),
+                _ => Err(DebuggerError::InvalidCommandFormat(String::from(
+                    "backtrace [max frames]",
+                ))),
+            },
+            "history" => {
+                let usage_err = DebuggerError::InvalidCommandFormat(String::from(
+                    "history [count] | history size <n>",
+                ));
+                match args.len() {
+                    0 => Ok(Command::History(None)),
+                    1 => Ok(Command::History(Some(self.val_number(&args[0])? as usize))),
+                    2 => match &args[0] {
+                        Value::Identifier(what) if what == "size" => {
+                            Ok(Command::HistorySize(self.val_number(&args[1])? as usize))
+                        }
+                        _ => Err(usage_err),
+                    },
+                    _ => Err(usage_err),
+                }
+            }
             "save" | "load" => {
                 let usage = DebuggerError::InvalidCommandFormat(String::from("save/load <path>"));
                 if args.len() != 1 {
@@ -594,6 +1276,18 @@ impl Debugger {
                     }
                 }
             }
+            "io-dump" | "io-load" => {
+                let usage =
+                    DebuggerError::InvalidCommandFormat(String::from("io-dump/io-load <path>"));
+                match args.as_slice() {
+                    [Value::Identifier(path)] => match command.as_ref() {
+                        "io-dump" => Ok(Command::DumpIoRegisters(path.to_string())),
+                        "io-load" => Ok(Command::LoadIoRegisters(path.to_string())),
+                        _ => unreachable!(),
+                    },
+                    _ => Err(usage),
+                }
+            }
             "add-symbols-file" | "load-symbols" | "load-syms" => match args.len() {
                 1 => {
                     if let Value::Identifier(elf_file) = &args[0] {
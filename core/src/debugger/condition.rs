@@ -0,0 +1,306 @@
+use std::fmt;
+
+use nom;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while1};
+use nom::character::complete::{char, multispace0};
+use nom::combinator::{all_consuming, map, map_res, opt};
+use nom::error::{context, convert_error, ParseError, VerboseError};
+use nom::multi::separated_nonempty_list;
+use nom::sequence::{delimited, pair, tuple};
+use nom::IResult;
+
+use crate::bus::DebugRead;
+
+use super::parser::{parse_u32, parse_u32_hex, DerefType};
+use super::GameBoyAdvance;
+use super::{DebuggerError, DebuggerResult};
+
+const REG_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "fp", "ip", "sp", "lr", "pc",
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Flag {
+    N,
+    Z,
+    C,
+    V,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    Num(u32),
+    Reg(usize),
+    Flag(Flag),
+    /// `[addr]` reads a word, `u16[addr]` and `u8[addr]` read a halfword or a byte
+    Mem(Box<Operand>, DerefType),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A breakpoint condition, e.g `r0 == 0x40 && [0x03001234] != 0`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Condition {
+    Compare(Operand, CmpOp, Operand),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Operand {
+    fn eval(&self, gba: &mut GameBoyAdvance) -> u32 {
+        match self {
+            Operand::Num(n) => *n,
+            Operand::Reg(r) => gba.cpu.get_reg(*r),
+            Operand::Flag(flag) => {
+                let cpsr = &gba.cpu.cpsr;
+                let set = match flag {
+                    Flag::N => cpsr.N(),
+                    Flag::Z => cpsr.Z(),
+                    Flag::C => cpsr.C(),
+                    Flag::V => cpsr.V(),
+                };
+                set as u32
+            }
+            // use the debug reads so that evaluating the condition has no side effects
+            Operand::Mem(addr, deref_type) => {
+                let addr = addr.eval(gba);
+                match deref_type {
+                    DerefType::Word => gba.sysbus.debug_read_32(addr),
+                    DerefType::HalfWord => gba.sysbus.debug_read_16(addr) as u32,
+                    DerefType::Byte => gba.sysbus.debug_read_8(addr) as u32,
+                }
+            }
+        }
+    }
+}
+
+impl Condition {
+    pub fn eval(&self, gba: &mut GameBoyAdvance) -> bool {
+        match self {
+            Condition::Compare(lhs, op, rhs) => {
+                let lhs = lhs.eval(gba);
+                let rhs = rhs.eval(gba);
+                match op {
+                    CmpOp::Eq => lhs == rhs,
+                    CmpOp::Ne => lhs != rhs,
+                    CmpOp::Lt => lhs < rhs,
+                    CmpOp::Le => lhs <= rhs,
+                    CmpOp::Gt => lhs > rhs,
+                    CmpOp::Ge => lhs >= rhs,
+                }
+            }
+            Condition::And(lhs, rhs) => lhs.eval(gba) && rhs.eval(gba),
+            Condition::Or(lhs, rhs) => lhs.eval(gba) || rhs.eval(gba),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Num(n) => write!(f, "{:#x}", n),
+            Operand::Reg(r) => write!(f, "{}", REG_NAMES[*r]),
+            Operand::Flag(flag) => write!(f, "{:?}", flag),
+            Operand::Mem(addr, DerefType::Word) => write!(f, "[{}]", addr),
+            Operand::Mem(addr, DerefType::HalfWord) => write!(f, "u16[{}]", addr),
+            Operand::Mem(addr, DerefType::Byte) => write!(f, "u8[{}]", addr),
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmpOp::Eq => write!(f, "=="),
+            CmpOp::Ne => write!(f, "!="),
+            CmpOp::Lt => write!(f, "<"),
+            CmpOp::Le => write!(f, "<="),
+            CmpOp::Gt => write!(f, ">"),
+            CmpOp::Ge => write!(f, ">="),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Compare(lhs, op, rhs) => write!(f, "{} {} {}", lhs, op, rhs),
+            Condition::And(lhs, rhs) => write!(f, "({} && {})", lhs, rhs),
+            Condition::Or(lhs, rhs) => write!(f, "({} || {})", lhs, rhs),
+        }
+    }
+}
+
+fn operand_from_name(name: &str) -> Result<Operand, String> {
+    let operand = match name {
+        "n" | "N" => Operand::Flag(Flag::N),
+        "z" | "Z" => Operand::Flag(Flag::Z),
+        "c" | "C" => Operand::Flag(Flag::C),
+        "v" | "V" => Operand::Flag(Flag::V),
+        "r11" => Operand::Reg(11),
+        "r12" => Operand::Reg(12),
+        "r13" => Operand::Reg(13),
+        "r14" => Operand::Reg(14),
+        "r15" => Operand::Reg(15),
+        name => match REG_NAMES.iter().position(|r| *r == name) {
+            Some(index) => Operand::Reg(index),
+            None => return Err(format!("{:?} is not a register or a flag", name)),
+        },
+    };
+    Ok(operand)
+}
+
+fn parse_register_or_flag<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Operand, E> {
+    context(
+        "register",
+        map_res(
+            take_while1(|c: char| c.is_alphanumeric()),
+            operand_from_name,
+        ),
+    )(i)
+}
+
+fn parse_mem<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Operand, E> {
+    context(
+        "memory",
+        map(
+            pair(
+                opt(alt((
+                    map(tag("u32"), |_| DerefType::Word),
+                    map(tag("u16"), |_| DerefType::HalfWord),
+                    map(tag("u8"), |_| DerefType::Byte),
+                ))),
+                delimited(char('['), parse_operand, char(']')),
+            ),
+            |(deref_type, addr)| {
+                Operand::Mem(Box::new(addr), deref_type.unwrap_or(DerefType::Word))
+            },
+        ),
+    )(i)
+}
+
+fn parse_operand<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Operand, E> {
+    delimited(
+        multispace0,
+        alt((
+            parse_mem,
+            map(alt((parse_u32_hex, parse_u32)), Operand::Num),
+            parse_register_or_flag,
+        )),
+        multispace0,
+    )(i)
+}
+
+fn parse_cmp_op<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, CmpOp, E> {
+    alt((
+        map(tag("=="), |_| CmpOp::Eq),
+        map(tag("!="), |_| CmpOp::Ne),
+        map(tag("<="), |_| CmpOp::Le),
+        map(tag(">="), |_| CmpOp::Ge),
+        map(tag("<"), |_| CmpOp::Lt),
+        map(tag(">"), |_| CmpOp::Gt),
+    ))(i)
+}
+
+/// A single operand is true when it is not zero
+fn parse_compare<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Condition, E> {
+    map(
+        tuple((parse_operand, opt(tuple((parse_cmp_op, parse_operand))))),
+        |(lhs, rhs)| match rhs {
+            Some((op, rhs)) => Condition::Compare(lhs, op, rhs),
+            None => Condition::Compare(lhs, CmpOp::Ne, Operand::Num(0)),
+        },
+    )(i)
+}
+
+fn parse_primary<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Condition, E> {
+    alt((
+        delimited(
+            tuple((multispace0, char('('))),
+            parse_or,
+            tuple((char(')'), multispace0)),
+        ),
+        parse_compare,
+    ))(i)
+}
+
+fn parse_and<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Condition, E> {
+    map(separated_nonempty_list(tag("&&"), parse_primary), |conds| {
+        fold(conds, Condition::And)
+    })(i)
+}
+
+fn parse_or<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Condition, E> {
+    map(separated_nonempty_list(tag("||"), parse_and), |conds| {
+        fold(conds, Condition::Or)
+    })(i)
+}
+
+fn fold(conds: Vec<Condition>, op: fn(Box<Condition>, Box<Condition>) -> Condition) -> Condition {
+    let mut conds = conds.into_iter();
+    let first = conds.next().unwrap();
+    conds.fold(first, |acc, cond| op(Box::new(acc), Box::new(cond)))
+}
+
+pub fn parse_condition(i: &str) -> DebuggerResult<Condition> {
+    match all_consuming(context("condition", parse_or::<VerboseError<&str>>))(i) {
+        Ok((_, cond)) => Ok(cond),
+        Err(nom::Err::Failure(e)) | Err(nom::Err::Error(e)) => {
+            Err(DebuggerError::ParsingError(convert_error(i, e)))
+        }
+        _ => Err(DebuggerError::ParsingError(format!(
+            "incomplete condition {:?}",
+            i
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_condition() {
+        let cond = parse_condition("r0 == 0x40 && [0x03001234] != 0 || z").unwrap();
+        assert_eq!(
+            cond,
+            Condition::Or(
+                Box::new(Condition::And(
+                    Box::new(Condition::Compare(
+                        Operand::Reg(0),
+                        CmpOp::Eq,
+                        Operand::Num(0x40)
+                    )),
+                    Box::new(Condition::Compare(
+                        Operand::Mem(Box::new(Operand::Num(0x03001234)), DerefType::Word),
+                        CmpOp::Ne,
+                        Operand::Num(0)
+                    )),
+                )),
+                Box::new(Condition::Compare(
+                    Operand::Flag(Flag::Z),
+                    CmpOp::Ne,
+                    Operand::Num(0)
+                )),
+            )
+        );
+
+        let cond = parse_condition("u8[r1] >= 3 && (lr < 0x08000000 || c)").unwrap();
+        assert_eq!(
+            cond.to_string(),
+            "(u8[r1] >= 0x3 && (lr < 0x8000000 || C != 0x0))"
+        );
+
+        assert!(parse_condition("r0 ==").is_err());
+        assert!(parse_condition("r16 == 1").is_err());
+    }
+}
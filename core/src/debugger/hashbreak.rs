@@ -0,0 +1,78 @@
+/// Hash breakpoints for the `hashbreak` command
+///
+/// Finding out when a large structure (an OAM shadow, a save block) gets modified would otherwise
+/// take a breakpoint on every store that may touch it. Instead, the region is hashed at the end of
+/// every frame and execution stops after the first frame that changed it.
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::Hasher;
+use std::ops::Range;
+
+use crate::bus::Addr;
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashBreakpoint {
+    pub addr: Addr,
+    pub len: u32,
+    /// The hash of the region when it was last checked
+    hash: u64,
+}
+
+impl HashBreakpoint {
+    /// `bytes` is the current content of the region
+    pub fn new(addr: Addr, len: u32, bytes: &[u8]) -> HashBreakpoint {
+        HashBreakpoint {
+            addr,
+            len,
+            hash: hash_bytes(bytes),
+        }
+    }
+
+    pub fn range(&self) -> Range<Addr> {
+        self.addr..self.addr.saturating_add(self.len)
+    }
+
+    /// Hashes the current content of the region, returns true if it changed since the last check
+    pub fn update(&mut self, bytes: &[u8]) -> bool {
+        let hash = hash_bytes(bytes);
+        let changed = hash != self.hash;
+        self.hash = hash;
+        changed
+    }
+}
+
+impl fmt::Display for HashBreakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08x}..0x{:08x} (hash {:016x})",
+            self.range().start,
+            self.range().end,
+            self.hash
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_breakpoint() {
+        let mut block = vec![0u8; 0x400];
+        let mut bp = HashBreakpoint::new(0x0200_0000, 0x400, &block);
+        assert_eq!(bp.range(), 0x0200_0000..0x0200_0400);
+        assert!(!bp.update(&block));
+
+        block[0x3ff] = 1;
+        assert!(bp.update(&block));
+        // the new content is the reference for the next check
+        assert!(!bp.update(&block));
+    }
+}
@@ -0,0 +1,162 @@
+/// Dumping and re-applying the IO register state for the `io-dump` and `io-load` commands
+///
+/// The dump is a flat TOML table of `REG_NAME = 0x1234` entries, so it can be edited by hand and
+/// diffed between runs. Write-only registers are dumped with the value last written to them.
+/// Registers where a read or write has side effects (the sound fifos, IF, HALTCNT ...) are left out.
+use std::collections::HashMap;
+
+use num::FromPrimitive;
+
+use crate::bus::{Addr, Bus};
+use crate::gpu::DisplayControl;
+use crate::interrupt::Interrupt;
+use crate::iodev::consts::*;
+use crate::iodev::{io_reg_string, IoDevices};
+
+const IO_SIZE: Addr = 0x400;
+
+/// Registers that are dumped as one 32bit value rather than two halves
+const WORD_REGISTERS: [Addr; 10] = [
+    REG_DMA0SAD,
+    REG_DMA0DAD,
+    REG_DMA1SAD,
+    REG_DMA1DAD,
+    REG_DMA2SAD,
+    REG_DMA2DAD,
+    REG_DMA3SAD,
+    REG_DMA3DAD,
+    REG_JOY_RECV,
+    REG_JOY_TRANS,
+];
+
+/// Read-only registers and registers that can't be restored by a plain write
+const SKIPPED_REGISTERS: [Addr; 8] = [
+    REG_VCOUNT,
+    REG_KEYINPUT,
+    REG_WAVE_RAM,
+    REG_FIFO_A,
+    REG_FIFO_B,
+    REG_SIODATA8,
+    REG_IF,
+    REG_HALTCNT,
+];
+
+/// All the registers that go into a dump, in address order
+fn dumped_registers() -> Vec<(&'static str, Addr)> {
+    (IO_BASE..IO_BASE + IO_SIZE)
+        .step_by(2)
+        .map(|io_addr| (io_reg_string(io_addr), io_addr))
+        .filter(|(name, io_addr)| *name != "UNKNOWN" && !SKIPPED_REGISTERS.contains(io_addr))
+        .collect()
+}
+
+fn read_register(io: &mut IoDevices, io_addr: Addr) -> u16 {
+    match io.write_only_register_value(io_addr) {
+        Some(value) => value,
+        None => io.read_16(io_addr - IO_BASE),
+    }
+}
+
+pub fn dump_io_registers(io: &mut IoDevices) -> String {
+    let mut out = String::from("# IO registers, load with `io-load <path>`\n");
+    for (name, io_addr) in dumped_registers() {
+        if WORD_REGISTERS.contains(&io_addr) {
+            let value =
+                (read_register(io, io_addr + 2) as u32) << 16 | read_register(io, io_addr) as u32;
+            out += &format!("{} = 0x{:08x}\n", name, value);
+        } else {
+            out += &format!("{} = 0x{:04x}\n", name, read_register(io, io_addr));
+        }
+    }
+    out
+}
+
+/// The name, address and value of every register that goes into a dump, for the tui
+pub fn io_register_values(io: &mut IoDevices) -> Vec<(&'static str, Addr, u16)> {
+    dumped_registers()
+        .into_iter()
+        .map(|(name, io_addr)| (name, io_addr, read_register(io, io_addr)))
+        .collect()
+}
+
+/// Spells out the fields of the registers that are the most often looked at
+pub fn decode_io_register(io_addr: Addr, value: u16) -> Option<String> {
+    let interrupts = || {
+        let names: Vec<String> = (0..14)
+            .filter(|bit| value & (1 << bit) != 0)
+            .filter_map(Interrupt::from_usize)
+            .map(|irq| format!("{:?}", irq))
+            .collect();
+        names.join(" ")
+    };
+    match io_addr {
+        REG_DISPCNT => Some(format!("{:?}", DisplayControl::from(value))),
+        REG_DISPSTAT => Some(format!(
+            "vblank={} hblank={} vcount={} irq(vblank={} hblank={} vcount={}) lyc={}",
+            value & 1,
+            (value >> 1) & 1,
+            (value >> 2) & 1,
+            (value >> 3) & 1,
+            (value >> 4) & 1,
+            (value >> 5) & 1,
+            value >> 8
+        )),
+        REG_IME => Some(format!("master enable={}", value & 1)),
+        REG_IE | REG_IF => Some(interrupts()),
+        _ => None,
+    }
+}
+
+fn parse_value(s: &str) -> Option<u32> {
+    if s.starts_with("0x") {
+        u32::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse::<u32>().ok()
+    }
+}
+
+/// Writes every `REG_NAME = value` entry of a dump to the IO registers, in the order they appear.
+/// Returns the number of registers written.
+pub fn load_io_registers(io: &mut IoDevices, content: &str) -> Result<usize, String> {
+    let registers: HashMap<&str, Addr> = dumped_registers().into_iter().collect();
+
+    let mut entries = Vec::new();
+    for (line_num, line) in content.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim(), value.trim()),
+            _ => {
+                return Err(format!(
+                    "line {}: expected `REG_NAME = value`",
+                    line_num + 1
+                ))
+            }
+        };
+        let io_addr = *registers
+            .get(name)
+            .ok_or_else(|| format!("line {}: unknown register {}", line_num + 1, name))?;
+        let value = parse_value(value)
+            .ok_or_else(|| format!("line {}: invalid value {}", line_num + 1, value))?;
+        entries.push((io_addr, value));
+    }
+
+    // the sound registers ignore writes while the master enable is off, so that goes first
+    entries.sort_by_key(|&(io_addr, _)| io_addr != REG_SOUNDCNT_X);
+
+    // only touch the registers once the whole file was parsed
+    for &(io_addr, value) in entries.iter() {
+        io.write_16(io_addr - IO_BASE, value as u16);
+        if WORD_REGISTERS.contains(&io_addr) {
+            io.write_16(io_addr + 2 - IO_BASE, (value >> 16) as u16);
+        }
+    }
+    Ok(entries.len())
+}
@@ -1,23 +1,36 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
+use std::panic::{self, AssertUnwindSafe};
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
 use colored::*;
 
+use super::logging::LogCapture;
 use super::GameBoyAdvance;
 use super::{Addr, Bus};
 
 mod parser;
 use parser::{parse_expr, DerefType, Expr, Value};
 
+mod backtrace;
 mod command;
 use command::Command;
 
+mod condition;
+use condition::{parse_condition, Condition};
+
+mod hashbreak;
+use hashbreak::HashBreakpoint;
+
+mod io_regs;
 mod palette_view;
+mod strings;
 mod tile_view;
+#[cfg(feature = "debugger_tui")]
+mod tui;
 
 #[derive(Debug)]
 pub enum DebuggerError {
@@ -40,6 +53,26 @@ pub struct Debugger {
     running: bool,
     pub previous_command: Option<Command>,
     pub symbols: HashMap<String, u32>,
+    /// Conditions of the breakpoints that only stop when the condition holds
+    pub breakpoint_conditions: HashMap<u32, Condition>,
+    /// Memory regions that stop execution at the end of a frame that changed them
+    pub hash_breakpoints: Vec<HashBreakpoint>,
+    /// Set by the frontend when the log is kept in memory instead of going to the terminal, the
+    /// full screen debugger shows it in a pane and the line debugger prints it before the prompt
+    pub log_capture: Option<&'static LogCapture>,
+}
+
+/// Only breakpoints take a condition
+fn with_condition(command: Command, condition: Option<Condition>) -> DebuggerResult<Command> {
+    match (command, condition) {
+        (Command::AddBreakpoint(addr, None), condition) => {
+            Ok(Command::AddBreakpoint(addr, condition))
+        }
+        (command, None) => Ok(command),
+        (_, Some(_)) => Err(DebuggerError::InvalidCommandFormat(
+            "break <addr> [if <condition>]".to_string(),
+        )),
+    }
 }
 
 impl Debugger {
@@ -48,6 +81,17 @@ impl Debugger {
             running: false,
             previous_command: None,
             symbols: HashMap::new(),
+            breakpoint_conditions: HashMap::new(),
+            hash_breakpoints: Vec::new(),
+            log_capture: None,
+        }
+    }
+
+    fn print_captured_log(&self) {
+        if let Some(capture) = self.log_capture {
+            for record in capture.drain() {
+                println!("{}", record);
+            }
         }
     }
 
@@ -64,6 +108,7 @@ impl Debugger {
 
     pub fn delete_breakpoint(&mut self, gba: &mut GameBoyAdvance, addr: u32) {
         gba.cpu.dbg.breakpoints.retain(|&a| a != addr);
+        self.breakpoint_conditions.remove(&addr);
     }
 
     fn decode_reg(&self, s: &str) -> DebuggerResult<usize> {
@@ -106,14 +151,8 @@ impl Debugger {
         match arg {
             Value::Num(n) => Ok(*n),
             Value::Identifier(ident) => {
-                let symbol = if let Some(symbols) = gba.sysbus.cartridge.get_symbols() {
-                    symbols.get(ident)
-                } else {
-                    None
-                };
-
-                if let Some(address) = symbol {
-                    Ok(*address)
+                if let Some(address) = self.lookup_symbol(gba, ident) {
+                    Ok(address)
                 } else {
                     // otherwise, decode as register (TODO special token to separate symbol and register)
                     let reg = self.decode_reg(&ident)?;
@@ -127,6 +166,46 @@ impl Debugger {
         }
     }
 
+    /// The symbols of the loaded elf, followed by the ones added with `add-symbols-file`
+    fn all_symbols<'a>(
+        &'a self,
+        gba: &'a GameBoyAdvance,
+    ) -> impl Iterator<Item = (&'a String, &'a u32)> + 'a {
+        gba.sysbus
+            .cartridge
+            .get_symbols()
+            .iter()
+            .flat_map(|symbols| symbols.iter())
+            .chain(self.symbols.iter())
+    }
+
+    fn lookup_symbol(&self, gba: &GameBoyAdvance, name: &str) -> Option<Addr> {
+        gba.sysbus
+            .cartridge
+            .get_symbols()
+            .as_ref()
+            .and_then(|symbols| symbols.get(name))
+            .or_else(|| self.symbols.get(name))
+            .copied()
+    }
+
+    /// The closest symbol at or before `addr` (up to 4KB before it), and the offset from it
+    fn nearest_symbol<'a>(&'a self, gba: &'a GameBoyAdvance, addr: Addr) -> Option<(&'a str, u32)> {
+        self.all_symbols(gba)
+            .filter(|&(_, &value)| value <= addr && addr - value < 0x1000)
+            .min_by_key(|&(name, &value)| (addr - value, name.as_str()))
+            .map(|(name, &value)| (name.as_str(), addr - value))
+    }
+
+    /// Formats `addr` as `symbol+offset`, or as hex when no symbol is close
+    fn format_addr(&self, gba: &GameBoyAdvance, addr: Addr) -> String {
+        match self.nearest_symbol(gba, addr) {
+            Some((name, 0)) => format!("{} (0x{:08x})", name, addr),
+            Some((name, offset)) => format!("{}+{:#x} (0x{:08x})", name, offset, addr),
+            None => format!("0x{:08x}", addr),
+        }
+    }
+
     fn eval_assignment(
         &mut self,
         gba: &mut GameBoyAdvance,
@@ -149,24 +228,46 @@ impl Debugger {
         Ok(())
     }
 
-    fn eval_expr(&mut self, gba: &mut GameBoyAdvance, expr: Expr) {
+    /// Conditions don't fit the command grammar, so `break <addr> if <condition>` is split before
+    /// the command is parsed
+    fn eval_line(&mut self, gba: &mut GameBoyAdvance, line: &str) -> DebuggerResult<()> {
+        let (line, condition) = match line.find(" if ") {
+            Some(pos) => (&line[..pos], Some(parse_condition(&line[pos + 4..])?)),
+            None => (line, None),
+        };
+        let expr = parse_expr(line)?;
+        self.eval_expr(gba, expr, condition);
+        Ok(())
+    }
+
+    fn eval_expr(&mut self, gba: &mut GameBoyAdvance, expr: Expr, condition: Option<Condition>) {
         match expr {
-            Expr::Command(c, a) => match self.eval_command(gba, c, a) {
-                Ok(cmd) => {
-                    self.previous_command = Some(cmd.clone());
-                    self.run_command(gba, cmd)
-                }
-                Err(DebuggerError::InvalidCommand(c)) => {
-                    println!("{}: {:?}", "invalid command".red(), c)
-                }
-                Err(DebuggerError::InvalidArgument(m)) => {
-                    println!("{}: {}", "invalid argument".red(), m)
-                }
-                Err(DebuggerError::InvalidCommandFormat(m)) => {
-                    println!("help: {}", m.bright_yellow())
+            Expr::Command(c, a) => {
+                let command = self
+                    .eval_command(gba, c, a)
+                    .and_then(|cmd| with_condition(cmd, condition));
+                match command {
+                    Ok(cmd) => {
+                        self.previous_command = Some(cmd.clone());
+                        let result =
+                            panic::catch_unwind(AssertUnwindSafe(|| self.run_command(gba, cmd)));
+                        if let Err(e) = result {
+                            gba.cpu.dbg.history.dump();
+                            panic::resume_unwind(e);
+                        }
+                    }
+                    Err(DebuggerError::InvalidCommand(c)) => {
+                        println!("{}: {:?}", "invalid command".red(), c)
+                    }
+                    Err(DebuggerError::InvalidArgument(m)) => {
+                        println!("{}: {}", "invalid argument".red(), m)
+                    }
+                    Err(DebuggerError::InvalidCommandFormat(m)) => {
+                        println!("help: {}", m.bright_yellow())
+                    }
+                    Err(e) => println!("{} {:?}", "failed to build command".red(), e),
                 }
-                Err(e) => println!("{} {:?}", "failed to build command".red(), e),
-            },
+            }
             Expr::Assignment(lvalue, rvalue) => match self.eval_assignment(gba, lvalue, rvalue) {
                 Err(DebuggerError::InvalidArgument(m)) => {
                     println!("{}: {}", "assignment error".red(), m)
@@ -195,26 +296,29 @@ impl Debugger {
             let file = File::open(path)?;
             let reader = BufReader::new(file);
             for line in reader.lines() {
-                let expr = parse_expr(&line.unwrap())?;
-                self.eval_expr(gba, expr);
+                self.eval_line(gba, &line?)?;
             }
         }
         while self.running {
+            self.print_captured_log();
             let readline = rl.readline(&format!("({}) ᐅ ", "rustboyadvance-dbg".bold().cyan()));
             match readline {
                 Ok(line) => {
                     if line.is_empty() {
-                        if let Some(Command::Step(1)) = self.previous_command {
-                            self.run_command(gba, Command::Step(1));
+                        let repeated = match self.previous_command {
+                            Some(Command::Step(1)) => Some(Command::Step(1)),
+                            Some(Command::Next) => Some(Command::Next),
+                            _ => None,
+                        };
+                        if let Some(command) = repeated {
+                            self.run_command(gba, command);
                         } else {
                             self.previous_command = None;
                             continue;
                         }
                     }
                     rl.add_history_entry(line.as_str());
-                    let expr = parse_expr(&line);
-                    match expr {
-                        Ok(expr) => self.eval_expr(gba, expr),
+                    match self.eval_line(gba, &line) {
                         Err(DebuggerError::ParsingError(msg)) => println!("Parsing error: {}", msg),
                         _ => (),
                     }
@@ -34,14 +34,14 @@ pub enum Expr {
     Empty,
 }
 
-fn parse_u32_hex<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E> {
+pub(super) fn parse_u32_hex<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E> {
     let (i, _) = context("hex", tag("0x"))(i)?;
     map_res(take_while_m_n(1, 8, |c: char| c.is_digit(16)), |s| {
         u32::from_str_radix(s, 16)
     })(i)
 }
 
-fn parse_u32<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E> {
+pub(super) fn parse_u32<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E> {
     context("u32", map_res(digit1, |s| u32::from_str_radix(s, 10)))(i)
 }
 
@@ -61,7 +61,7 @@ fn parse_boolean<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Val
 
 fn parse_identifier<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value, E> {
     map(
-        take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-'),
+        take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '/'),
         |s: &str| Value::Identifier(String::from(s)),
     )(i)
 }
@@ -0,0 +1,219 @@
+/// Text extraction for the `strings` command
+///
+/// Scans memory for runs of characters in a given encoding, the way romhackers look for a game's
+/// script. Shift-JIS decoding only covers kana and the full-width symbols and letters,
+/// kanji are shown as 〓. Games with a custom encoding need a `.tbl` character map.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bus::Addr;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Encoding {
+    Ascii,
+    ShiftJis,
+    /// Path to a `.tbl` file
+    Table(PathBuf),
+}
+
+/// A character map in the `.tbl` format used by romhacking tools, one `hex=text` entry per line
+#[derive(Debug, Default)]
+pub struct CharTable {
+    entries: HashMap<Vec<u8>, String>,
+    max_key_len: usize,
+}
+
+impl CharTable {
+    pub fn load(path: &Path) -> Result<CharTable, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Ok(CharTable::parse(&content))
+    }
+
+    /// Lines that aren't `hex=text` entries (comments, control codes) are skipped
+    pub fn parse(content: &str) -> CharTable {
+        let mut table = CharTable::default();
+        for line in content.lines() {
+            let line = line.trim_end_matches('\r');
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value),
+                _ => continue,
+            };
+            if key.is_empty() || key.len() % 2 != 0 {
+                continue;
+            }
+            let bytes: Result<Vec<u8>, _> = (0..key.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&key[i..i + 2], 16))
+                .collect();
+            if let Ok(bytes) = bytes {
+                table.max_key_len = table.max_key_len.max(bytes.len());
+                table.entries.insert(bytes, value.to_string());
+            }
+        }
+        table
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Longest match first
+    fn decode(&self, bytes: &[u8]) -> Option<(String, usize)> {
+        let max_len = self.max_key_len.min(bytes.len());
+        (1..=max_len)
+            .rev()
+            .find_map(|len| self.entries.get(&bytes[..len]).map(|s| (s.clone(), len)))
+    }
+}
+
+pub enum TextDecoder {
+    Ascii,
+    ShiftJis,
+    Table(CharTable),
+}
+
+impl TextDecoder {
+    pub fn new(encoding: &Encoding) -> Result<TextDecoder, String> {
+        Ok(match encoding {
+            Encoding::Ascii => TextDecoder::Ascii,
+            Encoding::ShiftJis => TextDecoder::ShiftJis,
+            Encoding::Table(path) => TextDecoder::Table(CharTable::load(path)?),
+        })
+    }
+
+    /// Decodes the character at the start of `bytes`, returns it along with its size in bytes
+    fn decode(&self, bytes: &[u8]) -> Option<(String, usize)> {
+        match self {
+            TextDecoder::Ascii => decode_ascii(bytes[0]).map(|c| (c.to_string(), 1)),
+            TextDecoder::ShiftJis => decode_sjis(bytes).map(|(c, len)| (c.to_string(), len)),
+            TextDecoder::Table(table) => table.decode(bytes),
+        }
+    }
+}
+
+fn decode_ascii(b: u8) -> Option<char> {
+    match b {
+        0x20..=0x7e => Some(b as char),
+        _ => None,
+    }
+}
+
+fn decode_sjis(bytes: &[u8]) -> Option<(char, usize)> {
+    let lead = bytes[0];
+    match lead {
+        0x20..=0x7e => return Some((lead as char, 1)),
+        // half-width katakana
+        0xa1..=0xdf => return std::char::from_u32(0xff61 + (lead - 0xa1) as u32).map(|c| (c, 1)),
+        0x81..=0x9f | 0xe0..=0xef => {}
+        _ => return None,
+    }
+    let trail = *bytes.get(1)?;
+    if trail < 0x40 || trail > 0xfc || trail == 0x7f {
+        return None;
+    }
+
+    // convert to a JIS X 0208 row (ku) and cell (ten)
+    let lead_index = if lead <= 0x9f {
+        lead - 0x81
+    } else {
+        lead - 0xc1
+    };
+    let base_row = 2 * lead_index as u32 + 1;
+    let (row, cell) = if trail < 0x9f {
+        let cell = (trail - 0x40) as u32 + if trail > 0x7f { 0 } else { 1 };
+        (base_row, cell)
+    } else {
+        (base_row + 1, (trail - 0x9f) as u32 + 1)
+    };
+
+    let code = match (row, cell) {
+        (1, 1) => 0x3000,
+        (1, 2) => 0x3001,
+        (1, 3) => 0x3002,
+        (1, 4) => 0xff0c,
+        (1, 5) => 0xff0e,
+        (1, 6) => 0x30fb,
+        (1, 7) => 0xff1a,
+        (1, 8) => 0xff1b,
+        (1, 9) => 0xff1f,
+        (1, 10) => 0xff01,
+        (1, 28) => 0x30fc,
+        (3, 16..=25) => 0xff10 + cell - 16,
+        (3, 33..=58) => 0xff21 + cell - 33,
+        (3, 65..=90) => 0xff41 + cell - 65,
+        (4, 1..=83) => 0x3041 + cell - 1,
+        (5, 1..=86) => 0x30a1 + cell - 1,
+        // kanji
+        (16..=84, _) => 0x3013,
+        _ => return None,
+    };
+    std::char::from_u32(code).map(|c| (c, 2))
+}
+
+/// Lists the strings of at least `min_len` characters found in `bytes`, which were read from `base`
+pub fn find_strings(
+    bytes: &[u8],
+    base: Addr,
+    decoder: &TextDecoder,
+    min_len: usize,
+) -> Vec<(Addr, String)> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut current_start = 0;
+    let mut current_len = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match decoder.decode(&bytes[i..]) {
+            Some((s, size)) => {
+                if current_len == 0 {
+                    current_start = i;
+                }
+                current.push_str(&s);
+                current_len += 1;
+                i += size;
+            }
+            None => {
+                if current_len >= min_len {
+                    result.push((base + current_start as u32, current.clone()));
+                }
+                current.clear();
+                current_len = 0;
+                i += 1;
+            }
+        }
+    }
+    if current_len >= min_len {
+        result.push((base + current_start as u32, current));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_strings() {
+        let bytes = b"\x00\x01HELLO\x00ab\x00\x82\xa0\x82\xa2\x82\xa4\x82\xa6\xff";
+        let ascii = find_strings(bytes, 0x0800_0000, &TextDecoder::Ascii, 4);
+        assert_eq!(ascii, vec![(0x0800_0002, "HELLO".to_string())]);
+
+        let sjis = find_strings(bytes, 0x0800_0000, &TextDecoder::ShiftJis, 4);
+        assert_eq!(
+            sjis,
+            vec![
+                (0x0800_0002, "HELLO".to_string()),
+                (0x0800_000b, "あいうえ".to_string())
+            ]
+        );
+
+        let table = CharTable::parse("# comment\n01=A\n0102=<B>\n00=_\n");
+        assert_eq!(table.len(), 3);
+        let tbl = find_strings(b"\x01\x01\x02\x00\x05", 0, &TextDecoder::Table(table), 3);
+        assert_eq!(tbl, vec![(0, "A<B>_".to_string())]);
+    }
+}
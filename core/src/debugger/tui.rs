@@ -0,0 +1,693 @@
+//! A full screen debugger, with panes for the disassembly around the pc, the registers, a memory
+//! view, the IO registers and the breakpoints. Entered with the `tui` command. When the frontend
+//! captures the log (`Debugger::log_capture`), the last records are shown in a pane as well.
+//!
+//! Tab cycles through the panes, the arrow and page keys scroll the focused one.
+//! `s` steps, `c` continues until a breakpoint (any key interrupts it), `f` runs a frame,
+//! `b` toggles a breakpoint on the disassembly cursor, `d` deletes the selected breakpoint,
+//! `g` jumps the memory or disassembly view to an address, `p` follows the pc again,
+//! `:` runs a command of the line debugger and `q` goes back to it.
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use ratatui::Terminal;
+
+use crate::arm7tdmi::arm::ArmInstruction;
+use crate::arm7tdmi::hooks::HookResult;
+use crate::arm7tdmi::thumb::ThumbInstruction;
+use crate::arm7tdmi::CpuState;
+use crate::bus::{Addr, DebugRead};
+use crate::disass::Disassembler;
+
+use super::command::{step_instruction, BreakReason};
+use super::io_regs::{decode_io_register, io_register_values};
+use super::parser::{parse_expr, Expr};
+use super::{Debugger, DebuggerError, DebuggerResult, GameBoyAdvance};
+
+type Backend = CrosstermBackend<Stdout>;
+
+const MEMORY_ROW_SIZE: u32 = 16;
+/// Rows of the log pane, with its borders
+const LOG_PANE_HEIGHT: u16 = 8;
+/// Log lines kept by the full screen debugger
+const LOG_LINES: usize = 256;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Pane {
+    Disassembly,
+    Memory,
+    IoRegisters,
+    Breakpoints,
+}
+
+impl Pane {
+    fn next(self) -> Pane {
+        match self {
+            Pane::Disassembly => Pane::Memory,
+            Pane::Memory => Pane::IoRegisters,
+            Pane::IoRegisters => Pane::Breakpoints,
+            Pane::Breakpoints => Pane::Disassembly,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Prompt {
+    Goto,
+    Command,
+}
+
+struct TuiState {
+    focus: Pane,
+    /// The first disassembled address, None to keep the pc in view
+    disass_top: Option<Addr>,
+    /// Line of the cursor in the disassembly pane
+    disass_cursor: usize,
+    /// The address of every line of the disassembly pane, as last drawn
+    disass_addrs: Vec<Addr>,
+    memory_addr: Addr,
+    io_selected: usize,
+    breakpoint_selected: usize,
+    prompt: Option<(Prompt, String)>,
+    message: String,
+    /// The last captured log records, oldest first
+    log: VecDeque<String>,
+}
+
+struct Panes {
+    disassembly: Rect,
+    registers: Rect,
+    breakpoints: Rect,
+    memory: Rect,
+    io_registers: Rect,
+    log: Rect,
+    status: Rect,
+}
+
+/// `with_log` leaves room for the log pane at the bottom
+fn split_panes(area: Rect, with_log: bool) -> Panes {
+    let log_height = if with_log { LOG_PANE_HEIGHT } else { 0 };
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage(55),
+                Constraint::Min(6),
+                Constraint::Length(log_height),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+    let columns = [Constraint::Percentage(60), Constraint::Percentage(40)];
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(columns.as_ref())
+        .split(rows[0]);
+    let bottom = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(columns.as_ref())
+        .split(rows[1]);
+    let side = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(12), Constraint::Min(3)].as_ref())
+        .split(top[1]);
+    Panes {
+        disassembly: top[0],
+        registers: side[0],
+        breakpoints: side[1],
+        memory: bottom[0],
+        io_registers: bottom[1],
+        log: rows[2],
+        status: rows[3],
+    }
+}
+
+/// Rows inside the borders of a pane
+fn inner_height(area: Rect) -> usize {
+    area.height.saturating_sub(2) as usize
+}
+
+fn pane_block(title: &str, focused: bool) -> Block<'static> {
+    let border_style = if focused {
+        Style::default().fg(Color::Cyan)
+    } else {
+        Style::default()
+    };
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(border_style)
+        .title(Span::styled(
+            title.to_string(),
+            Style::default().add_modifier(Modifier::BOLD),
+        ))
+}
+
+fn word_size(gba: &GameBoyAdvance) -> u32 {
+    match gba.cpu.get_cpu_state() {
+        CpuState::ARM => 4,
+        CpuState::THUMB => 2,
+    }
+}
+
+/// The first disassembled address, a few instructions before the pc unless scrolled away
+fn disass_top(gba: &GameBoyAdvance, state: &TuiState, height: usize) -> Addr {
+    state.disass_top.unwrap_or_else(|| {
+        gba.cpu
+            .get_next_pc()
+            .wrapping_sub(word_size(gba) * (height as u32 / 4))
+    })
+}
+
+/// Keeps `selected` inside `0..len`, and returns the first row that keeps it visible
+fn scroll_offset(selected: &mut usize, len: usize, height: usize) -> usize {
+    *selected = (*selected).min(len.saturating_sub(1));
+    (*selected + 1).saturating_sub(height)
+}
+
+/// Moves the cursor or the view of the focused pane by `lines`
+fn scroll(gba: &GameBoyAdvance, state: &mut TuiState, height: usize, lines: i64) {
+    match state.focus {
+        Pane::Disassembly => {
+            let cursor = state.disass_cursor as i64 + lines;
+            if cursor < 0 || cursor >= height as i64 {
+                // scroll the view instead of moving the cursor out of it
+                let top = disass_top(gba, state, height);
+                let overflow = if cursor < 0 {
+                    cursor
+                } else {
+                    cursor - height as i64 + 1
+                };
+                state.disass_top =
+                    Some(top.wrapping_add((overflow * word_size(gba) as i64) as u32));
+            }
+            state.disass_cursor = cursor.max(0).min(height as i64 - 1) as usize;
+        }
+        Pane::Memory => {
+            state.memory_addr = state
+                .memory_addr
+                .wrapping_add((lines * MEMORY_ROW_SIZE as i64) as u32);
+        }
+        Pane::IoRegisters => {
+            state.io_selected = (state.io_selected as i64 + lines).max(0) as usize;
+        }
+        Pane::Breakpoints => {
+            state.breakpoint_selected = (state.breakpoint_selected as i64 + lines).max(0) as usize;
+        }
+    }
+}
+
+/// Puts the terminal back in its normal state when dropped, even on a panic
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+    }
+}
+
+impl Debugger {
+    fn symbol_at(&self, gba: &GameBoyAdvance, addr: Addr) -> Option<String> {
+        let symbol = self
+            .symbols
+            .iter()
+            .find(|(_, &symaddr)| symaddr == addr)
+            .map(|(name, _)| name.clone());
+        symbol.or_else(|| {
+            gba.sysbus
+                .cartridge
+                .get_symbols()?
+                .iter()
+                .find(|(_, &symaddr)| symaddr == addr)
+                .map(|(name, _)| name.clone())
+        })
+    }
+
+    fn disassembly_lines(
+        &self,
+        gba: &mut GameBoyAdvance,
+        state: &mut TuiState,
+        height: usize,
+    ) -> Vec<Spans<'static>> {
+        let pc = gba.cpu.get_next_pc();
+        let insn_size = word_size(gba);
+        let top = disass_top(gba, state, height);
+        state.disass_cursor = state.disass_cursor.min(height.saturating_sub(1));
+
+        // one more instruction, to see if the last line is the first half of a pair
+        let bytes = gba
+            .sysbus
+            .debug_get_bytes(top..top.wrapping_add(insn_size * (height as u32 + 1)));
+        let lines: Vec<(Addr, String)> = match gba.cpu.get_cpu_state() {
+            CpuState::ARM => Disassembler::<ArmInstruction>::new(top, &bytes)
+                .take(height)
+                .collect(),
+            CpuState::THUMB => Disassembler::<ThumbInstruction>::new(top, &bytes)
+                .take(height)
+                .collect(),
+        };
+
+        state.disass_addrs = lines.iter().map(|(addr, _)| *addr).collect();
+        let mut spans = Vec::new();
+        for (i, (addr, line)) in lines.into_iter().enumerate() {
+            let symbol = match self.symbol_at(gba, addr) {
+                Some(symbol) => format!("  <{}>", symbol),
+                None => String::new(),
+            };
+            let marker = if gba.cpu.dbg.breakpoints.contains(&addr) {
+                Span::styled("● ", Style::default().fg(Color::Red))
+            } else {
+                Span::raw("  ")
+            };
+            let mut style = Style::default();
+            // the pc may be on the second half of a pair
+            let end = state.disass_addrs.get(i + 1).copied();
+            if pc >= addr && pc < end.unwrap_or(addr + insn_size) {
+                style = style.fg(Color::Black).bg(Color::Green);
+            }
+            if i == state.disass_cursor && state.focus == Pane::Disassembly {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            spans.push(Spans::from(vec![
+                marker,
+                Span::styled(line.replace('\t', "  "), style),
+                Span::styled(symbol, Style::default().fg(Color::Yellow)),
+            ]));
+        }
+        spans
+    }
+
+    fn register_lines(&self, gba: &GameBoyAdvance) -> Vec<Spans<'static>> {
+        const NAMES: [&str; 16] = [
+            "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "fp", "ip", "sp",
+            "lr", "pc",
+        ];
+        let mut spans = Vec::new();
+        for pair in (0..16).collect::<Vec<usize>>().chunks(2) {
+            let mut line = Vec::new();
+            for &r in pair {
+                let value = if r == 15 {
+                    gba.cpu.get_next_pc()
+                } else {
+                    gba.cpu.get_reg(r)
+                };
+                line.push(Span::styled(
+                    format!("{:>4} ", NAMES[r]),
+                    Style::default().fg(Color::Cyan),
+                ));
+                line.push(Span::raw(format!("{:08x}   ", value)));
+            }
+            spans.push(Spans::from(line));
+        }
+
+        let cpsr = &gba.cpu.cpsr;
+        let flag = |name: &'static str, set: bool| {
+            if set {
+                Span::styled(name, Style::default().fg(Color::Green))
+            } else {
+                Span::styled(name, Style::default().fg(Color::DarkGray))
+            }
+        };
+        spans.push(Spans::from(vec![
+            Span::styled("cpsr ", Style::default().fg(Color::Cyan)),
+            Span::raw(format!("{:08x} ", cpsr.get())),
+            flag("N", cpsr.N()),
+            flag("Z", cpsr.Z()),
+            flag("C", cpsr.C()),
+            flag("V", cpsr.V()),
+            Span::raw(" "),
+            flag("I", cpsr.irq_disabled()),
+            flag("F", cpsr.fiq_disabled()),
+            flag("T", cpsr.state() == CpuState::THUMB),
+            Span::raw(format!(" {:?}", cpsr.mode())),
+        ]));
+        spans.push(Spans::from(Span::raw(format!(
+            "cycles {}",
+            gba.scheduler.timestamp()
+        ))));
+        spans
+    }
+
+    fn memory_lines(
+        &self,
+        gba: &mut GameBoyAdvance,
+        state: &TuiState,
+        height: usize,
+    ) -> Vec<Spans<'static>> {
+        (0..height as u32)
+            .map(|row| {
+                let addr = state.memory_addr.wrapping_add(row * MEMORY_ROW_SIZE);
+                let bytes = gba
+                    .sysbus
+                    .debug_get_bytes(addr..addr.wrapping_add(MEMORY_ROW_SIZE));
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                let ascii: String = bytes
+                    .iter()
+                    .map(|&b| {
+                        if b.is_ascii_graphic() || b == b' ' {
+                            b as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                Spans::from(vec![
+                    Span::styled(format!("{:08x}  ", addr), Style::default().fg(Color::Cyan)),
+                    Span::raw(format!("{}  ", hex.join(" "))),
+                    Span::styled(ascii, Style::default().fg(Color::DarkGray)),
+                ])
+            })
+            .collect()
+    }
+
+    fn io_register_lines(
+        &self,
+        gba: &mut GameBoyAdvance,
+        state: &mut TuiState,
+        height: usize,
+    ) -> Vec<Spans<'static>> {
+        let registers = io_register_values(&mut gba.io_devs);
+        // the last rows decode the selected register
+        let list_height = height.saturating_sub(3);
+        let offset = scroll_offset(&mut state.io_selected, registers.len(), list_height);
+        let mut spans: Vec<Spans> = registers
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(list_height)
+            .map(|(i, &(name, io_addr, value))| {
+                let style = if i == state.io_selected && state.focus == Pane::IoRegisters {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else {
+                    Style::default()
+                };
+                Spans::from(Span::styled(
+                    format!("{:08x} {:<16} {:04x}  {:016b}", io_addr, name, value, value),
+                    style,
+                ))
+            })
+            .collect();
+        if let Some(&(name, io_addr, value)) = registers.get(state.io_selected) {
+            let decoded = decode_io_register(io_addr, value).unwrap_or_default();
+            spans.push(Spans::from(Span::styled(
+                format!("{}: {}", name, decoded),
+                Style::default().fg(Color::Yellow),
+            )));
+        }
+        spans
+    }
+
+    fn breakpoint_lines(
+        &self,
+        gba: &GameBoyAdvance,
+        state: &mut TuiState,
+        height: usize,
+    ) -> Vec<Spans<'static>> {
+        let breakpoints = &gba.cpu.dbg.breakpoints;
+        let offset = scroll_offset(&mut state.breakpoint_selected, breakpoints.len(), height);
+        breakpoints
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(height)
+            .map(|(i, &addr)| {
+                let mut text = format!("[{}] {:08x}", i, addr);
+                if let Some(symbol) = self.symbol_at(gba, addr) {
+                    text += &format!(" <{}>", symbol);
+                }
+                if let Some(condition) = self.breakpoint_conditions.get(&addr) {
+                    text += &format!(" if {}", condition);
+                }
+                let style = if i == state.breakpoint_selected && state.focus == Pane::Breakpoints {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else {
+                    Style::default()
+                };
+                Spans::from(Span::styled(text, style))
+            })
+            .collect()
+    }
+
+    /// Moves the records logged since the last call to the log pane
+    fn update_log(&self, state: &mut TuiState) {
+        if let Some(capture) = self.log_capture {
+            for record in capture.drain() {
+                if state.log.len() == LOG_LINES {
+                    state.log.pop_front();
+                }
+                state.log.push_back(record.to_string());
+            }
+        }
+    }
+
+    fn draw(
+        &self,
+        terminal: &mut Terminal<Backend>,
+        gba: &mut GameBoyAdvance,
+        state: &mut TuiState,
+    ) -> io::Result<()> {
+        self.update_log(state);
+        let panes = split_panes(terminal.size()?, self.log_capture.is_some());
+        let log_height = inner_height(panes.log);
+        let log: Vec<Spans> = state
+            .log
+            .iter()
+            .skip(state.log.len().saturating_sub(log_height))
+            .map(|line| Spans::from(line.clone()))
+            .collect();
+        let disassembly = self.disassembly_lines(gba, state, inner_height(panes.disassembly));
+        let registers = self.register_lines(gba);
+        let memory = self.memory_lines(gba, state, inner_height(panes.memory));
+        let io_registers = self.io_register_lines(gba, state, inner_height(panes.io_registers));
+        let breakpoints = self.breakpoint_lines(gba, state, inner_height(panes.breakpoints));
+        let status = match &state.prompt {
+            Some((Prompt::Goto, input)) => format!("goto> {}", input),
+            Some((Prompt::Command, input)) => format!(":{}", input),
+            None => state.message.clone(),
+        };
+        let focus = state.focus;
+
+        terminal.draw(|f| {
+            let disassembly = Paragraph::new(disassembly)
+                .block(pane_block("Disassembly", focus == Pane::Disassembly));
+            f.render_widget(disassembly, panes.disassembly);
+            let registers = Paragraph::new(registers).block(pane_block("Registers", false));
+            f.render_widget(registers, panes.registers);
+            let breakpoints = Paragraph::new(breakpoints)
+                .block(pane_block("Breakpoints", focus == Pane::Breakpoints));
+            f.render_widget(breakpoints, panes.breakpoints);
+            let memory = Paragraph::new(memory).block(pane_block("Memory", focus == Pane::Memory));
+            f.render_widget(memory, panes.memory);
+            let io_registers = Paragraph::new(io_registers)
+                .block(pane_block("IO registers", focus == Pane::IoRegisters))
+                .wrap(Wrap { trim: true });
+            f.render_widget(io_registers, panes.io_registers);
+            if panes.log.height != 0 {
+                let log = Paragraph::new(log).block(pane_block("Log", false));
+                f.render_widget(log, panes.log);
+            }
+            f.render_widget(Paragraph::new(status), panes.status);
+        })?;
+        Ok(())
+    }
+
+    fn goto(&self, gba: &GameBoyAdvance, state: &mut TuiState, input: &str) {
+        let addr = match parse_expr(&format!("goto {}", input)) {
+            Ok(Expr::Command(_, args)) if args.len() == 1 => self.val_address(gba, &args[0]),
+            _ => Err(DebuggerError::InvalidArgument(format!(
+                "{:?} is not an address",
+                input
+            ))),
+        };
+        match (addr, state.focus) {
+            (Ok(addr), Pane::Memory) => state.memory_addr = addr & !(MEMORY_ROW_SIZE - 1),
+            (Ok(addr), _) => {
+                state.focus = Pane::Disassembly;
+                state.disass_top = Some(addr);
+                state.disass_cursor = 0;
+            }
+            (Err(e), _) => state.message = format!("{:?}", e),
+        }
+    }
+
+    fn toggle_breakpoint(&mut self, gba: &mut GameBoyAdvance, addr: Addr) -> String {
+        if gba.cpu.dbg.breakpoints.contains(&addr) {
+            self.delete_breakpoint(gba, addr);
+            format!("deleted breakpoint 0x{:08x}", addr)
+        } else {
+            gba.add_breakpoint(addr);
+            format!("added breakpoint 0x{:08x}", addr)
+        }
+    }
+
+    /// Why execution stopped in one line, the line debugger prints more with `report_break`
+    fn tui_break_message(&self, gba: &GameBoyAdvance, reason: BreakReason) -> String {
+        match reason {
+            BreakReason::Hook(HookResult::Breakpoint(addr)) => match self.symbol_at(gba, addr) {
+                Some(symbol) => format!("breakpoint reached @{}", symbol),
+                None => format!("breakpoint reached @{:08x}", addr),
+            },
+            BreakReason::Hook(hit) => format!("hit {}", hit),
+            BreakReason::CpuError(err) => format!("cpu error: {}", err),
+            BreakReason::HashChanged(changed) => format!("hash breakpoints {:?} changed", changed),
+            BreakReason::Interrupted => "interrupted".to_string(),
+        }
+    }
+
+    /// Leaves the full screen mode to run a command of the line debugger, and shows its output
+    /// until enter is pressed
+    fn run_line_command(&mut self, gba: &mut GameBoyAdvance, line: &str) -> io::Result<()> {
+        disable_raw_mode()?;
+        execute!(io::stdout(), LeaveAlternateScreen, Show)?;
+        if let Err(e) = self.eval_line(gba, line) {
+            println!("{:?}", e);
+        }
+        if self.running {
+            println!("\n(press enter to go back)");
+            let mut buf = String::new();
+            io::stdin().read_line(&mut buf)?;
+        }
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)
+    }
+
+    pub fn tui(&mut self, gba: &mut GameBoyAdvance) -> DebuggerResult<()> {
+        let _guard = TerminalGuard::enter()?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        let mut state = TuiState {
+            focus: Pane::Disassembly,
+            disass_top: None,
+            disass_cursor: 0,
+            disass_addrs: Vec::new(),
+            memory_addr: 0x0200_0000,
+            io_selected: 0,
+            breakpoint_selected: 0,
+            prompt: None,
+            message: "s: step  c: continue  f: frame  b: breakpoint  g: goto  p: follow pc  \
+                      ':': command  tab: next pane  q: quit"
+                .to_string(),
+            log: VecDeque::new(),
+        };
+        self.running = true;
+
+        while self.running {
+            self.draw(&mut terminal, gba, &mut state)?;
+            let key = match event::read()? {
+                Event::Key(KeyEvent {
+                    code,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => code,
+                _ => continue,
+            };
+            let panes = split_panes(terminal.size()?, self.log_capture.is_some());
+            let height = match state.focus {
+                Pane::Disassembly => inner_height(panes.disassembly),
+                Pane::Memory => inner_height(panes.memory),
+                Pane::IoRegisters => inner_height(panes.io_registers).saturating_sub(3),
+                Pane::Breakpoints => inner_height(panes.breakpoints),
+            };
+
+            if let Some((prompt, mut input)) = state.prompt.take() {
+                match key {
+                    KeyCode::Enter => match prompt {
+                        Prompt::Goto => self.goto(gba, &mut state, &input),
+                        Prompt::Command => {
+                            self.run_line_command(gba, &input)?;
+                            terminal.clear()?;
+                        }
+                    },
+                    KeyCode::Esc => {}
+                    KeyCode::Backspace => {
+                        input.pop();
+                        state.prompt = Some((prompt, input));
+                    }
+                    KeyCode::Char(c) => {
+                        input.push(c);
+                        state.prompt = Some((prompt, input));
+                    }
+                    _ => state.prompt = Some((prompt, input)),
+                }
+                continue;
+            }
+
+            match key {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Tab => state.focus = state.focus.next(),
+                KeyCode::Up => scroll(gba, &mut state, height, -1),
+                KeyCode::Down => scroll(gba, &mut state, height, 1),
+                KeyCode::PageUp => scroll(gba, &mut state, height, -(height as i64)),
+                KeyCode::PageDown => scroll(gba, &mut state, height, height as i64),
+                KeyCode::Char('s') => {
+                    let hook = step_instruction(gba);
+                    state.message = match (gba.cpu.take_error(), hook) {
+                        (Some(err), _) => format!("cpu error: {}", err),
+                        (None, Some(hit @ HookResult::Watchpoint { .. })) => format!("hit {}", hit),
+                        _ => String::new(),
+                    };
+                    state.disass_top = None;
+                }
+                KeyCode::Char('c') => {
+                    state.message = "running, press any key to stop".to_string();
+                    self.draw(&mut terminal, gba, &mut state)?;
+                    let reason = self.run_until_break(gba, || {
+                        // consume the key that interrupted it
+                        event::poll(Duration::from_secs(0)).unwrap_or(false)
+                            && event::read().is_ok()
+                    });
+                    state.message = self.tui_break_message(gba, reason);
+                    state.disass_top = None;
+                }
+                KeyCode::Char('f') => {
+                    gba.frame();
+                    state.message = match gba.cpu.take_error() {
+                        Some(err) => format!("cpu error: {}", err),
+                        None => format!("frame {}", gba.frame_count()),
+                    };
+                    state.disass_top = None;
+                }
+                KeyCode::Char('p') => {
+                    state.disass_top = None;
+                    state.disass_cursor = 0;
+                }
+                KeyCode::Char('b') if state.focus == Pane::Disassembly => {
+                    if let Some(&addr) = state.disass_addrs.get(state.disass_cursor) {
+                        state.message = self.toggle_breakpoint(gba, addr);
+                    }
+                }
+                KeyCode::Char('d') | KeyCode::Delete if state.focus == Pane::Breakpoints => {
+                    if let Some(&addr) = gba.cpu.dbg.breakpoints.get(state.breakpoint_selected) {
+                        self.delete_breakpoint(gba, addr);
+                        state.message = format!("deleted breakpoint 0x{:08x}", addr);
+                    }
+                }
+                KeyCode::Char('g') => state.prompt = Some((Prompt::Goto, String::new())),
+                KeyCode::Char(':') => state.prompt = Some((Prompt::Command, String::new())),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
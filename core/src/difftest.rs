@@ -0,0 +1,367 @@
+/// Differential testing of the cpu: runs this core in lockstep with a reference and reports the
+/// first instruction after which they disagree.
+///
+/// The reference is usually a `TraceReplay` of a log recorded by another emulator, or by another
+/// build of this one with `record_trace`. Traces are text, one line per executed instruction with
+/// the state before it executes, all values in hex:
+///
+/// ```text
+/// <pc> <r0> <r1> ... <r14> <cpsr> [<addr>=<word> ...]
+/// ```
+///
+/// The optional `addr=word` pairs are memory words to compare as well. Empty lines and lines
+/// starting with `#` are skipped. `random_arm_program` makes roms that stress the ALU, the barrel
+/// shifter and the multiplier, to run through both emulators.
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use super::bus::DebugRead;
+use super::{Addr, GameBoyAdvance};
+
+/// The state of the cpu before an instruction executes
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TraceEntry {
+    /// Address of the instruction about to execute
+    pub pc: Addr,
+    pub gpr: [u32; 15],
+    pub cpsr: u32,
+    /// Memory words, as (address, value)
+    pub memory: Vec<(Addr, u32)>,
+}
+
+impl TraceEntry {
+    /// Captures the state of `gba`, along with the memory words at `addresses`
+    pub fn capture(gba: &mut GameBoyAdvance, addresses: &[Addr]) -> TraceEntry {
+        let memory = addresses
+            .iter()
+            .map(|&addr| (addr, gba.sysbus.debug_read_32(addr)))
+            .collect();
+        TraceEntry {
+            pc: gba.cpu.get_next_pc(),
+            gpr: gba.cpu.get_registers(),
+            cpsr: gba.cpu.cpsr.get(),
+            memory,
+        }
+    }
+
+    /// Describes what differs from `expected`, empty if they agree
+    pub fn differences(&self, expected: &TraceEntry) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: String, actual: u32, expected: u32| {
+            if actual != expected {
+                differences.push(format!(
+                    "{}: {:08x}, expected {:08x}",
+                    name, actual, expected
+                ));
+            }
+        };
+        compare("pc".to_string(), self.pc, expected.pc);
+        for (i, (&actual, &expected)) in self.gpr.iter().zip(expected.gpr.iter()).enumerate() {
+            compare(format!("r{}", i), actual, expected);
+        }
+        compare("cpsr".to_string(), self.cpsr, expected.cpsr);
+        for (&(addr, actual), &(_, expected)) in self.memory.iter().zip(expected.memory.iter()) {
+            compare(format!("[{:08x}]", addr), actual, expected);
+        }
+        differences
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.pc)?;
+        for r in self.gpr.iter() {
+            write!(f, " {:08x}", r)?;
+        }
+        write!(f, " {:08x}", self.cpsr)?;
+        for (addr, value) in self.memory.iter() {
+            write!(f, " {:08x}={:08x}", addr, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid value {:?}", s))
+}
+
+impl FromStr for TraceEntry {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<TraceEntry, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 17 {
+            return Err(format!(
+                "expected the pc, 15 registers and the cpsr, got {} values",
+                fields.len()
+            ));
+        }
+        let mut entry = TraceEntry {
+            pc: parse_hex(fields[0])?,
+            cpsr: parse_hex(fields[16])?,
+            ..Default::default()
+        };
+        for (r, field) in entry.gpr.iter_mut().zip(fields[1..16].iter()) {
+            *r = parse_hex(field)?;
+        }
+        for field in fields[17..].iter() {
+            let mut parts = field.splitn(2, '=');
+            let addr = parse_hex(parts.next().unwrap())?;
+            let value = parse_hex(
+                parts
+                    .next()
+                    .ok_or(format!("expected addr=word, got {:?}", field))?,
+            )?;
+            entry.memory.push((addr, value));
+        }
+        Ok(entry)
+    }
+}
+
+/// The other end of a differential test
+pub trait Reference {
+    /// The state before the next instruction, None when the reference is done
+    fn next_state(&mut self) -> Option<TraceEntry>;
+}
+
+/// Replays a trace, see the module documentation for the format
+pub struct TraceReplay<R: BufRead> {
+    lines: io::Lines<R>,
+}
+
+impl<R: BufRead> TraceReplay<R> {
+    pub fn new(reader: R) -> TraceReplay<R> {
+        TraceReplay {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> Reference for TraceReplay<R> {
+    fn next_state(&mut self) -> Option<TraceEntry> {
+        for line in &mut self.lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("difftest: failed to read the trace: {}", e);
+                    return None;
+                }
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.parse() {
+                Ok(entry) => return Some(entry),
+                Err(e) => {
+                    warn!("difftest: {}: {:?}", e, line);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The first point where this core and the reference disagree
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Number of instructions both executed the same way before
+    pub step: usize,
+    /// The state before the last instruction that agreed, the culprit is usually that instruction
+    pub previous: Option<TraceEntry>,
+    pub expected: TraceEntry,
+    pub actual: TraceEntry,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "diverged after {} instructions", self.step)?;
+        if let Some(previous) = &self.previous {
+            write!(f, ", the last one at @{:08x}", previous.pc)?;
+        }
+        for difference in self.actual.differences(&self.expected) {
+            write!(f, "\n\t{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Steps `gba` along with `reference` for at most `max_steps` instructions, comparing their
+/// states before every instruction. Returns how many instructions were compared.
+pub fn run_diff<R: Reference + ?Sized>(
+    gba: &mut GameBoyAdvance,
+    reference: &mut R,
+    max_steps: usize,
+) -> Result<usize, Divergence> {
+    let mut previous = None;
+    for step in 0..max_steps {
+        let expected = match reference.next_state() {
+            Some(expected) => expected,
+            None => return Ok(step),
+        };
+        let addresses: Vec<Addr> = expected.memory.iter().map(|&(addr, _)| addr).collect();
+        let actual = TraceEntry::capture(gba, &addresses);
+        if actual != expected {
+            return Err(Divergence {
+                step,
+                previous,
+                expected,
+                actual,
+            });
+        }
+        gba.cpu_step();
+        previous = Some(actual);
+    }
+    Ok(max_steps)
+}
+
+/// Writes a trace of the next `steps` instructions of `gba`, with the memory words at `watch`
+pub fn record_trace<W: Write>(
+    gba: &mut GameBoyAdvance,
+    mut writer: W,
+    steps: usize,
+    watch: &[Addr],
+) -> io::Result<()> {
+    for _ in 0..steps {
+        writeln!(writer, "{}", TraceEntry::capture(gba, watch))?;
+        gba.cpu_step();
+    }
+    writer.flush()
+}
+
+/// xorshift64, deterministic so a failing seed can be reproduced
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+
+    /// A register other than the pc
+    fn reg(&mut self) -> u32 {
+        self.next() % 15
+    }
+}
+
+/// Loads `value` in `rd` with a MOV and 3 ORRs of rotated immediates
+fn load_immediate(rd: u32, value: u32) -> [u32; 4] {
+    let bytes = value.to_le_bytes();
+    let mov = 0xe3a0_0000 | rd << 12 | bytes[0] as u32;
+    let orr = |rotate: u32, byte: u8| 0xe380_0000 | rd << 16 | rd << 12 | rotate << 8 | byte as u32;
+    // rotating right by 24, 16 and 8 places the byte in bits 8, 16 and 24
+    [mov, orr(12, bytes[1]), orr(8, bytes[2]), orr(4, bytes[3])]
+}
+
+fn random_arm_instruction(rng: &mut Rng) -> u32 {
+    let cond = rng.next() % 15;
+    let insn = match rng.next() % 8 {
+        // multiply, rd and rm must differ on the ARM7TDMI
+        0 => {
+            let rd = rng.reg();
+            let rm = (rd + 1 + rng.next() % 14) % 15;
+            0x0000_0090
+                | (rng.next() & 0b11) << 20
+                | rd << 16
+                | rng.reg() << 12
+                | rng.reg() << 8
+                | rm
+        }
+        // multiply long, rdhi, rdlo and rm must all differ
+        1 => {
+            let rdhi = rng.reg();
+            let rdlo = (rdhi + 1 + rng.next() % 13) % 15;
+            let rm = (0..15).find(|&r| r != rdhi && r != rdlo).unwrap();
+            0x0080_0090 | (rng.next() & 0b111) << 20 | rdhi << 16 | rdlo << 12 | rng.reg() << 8 | rm
+        }
+        // data processing, TST, TEQ, CMP and CMN always set the flags (or they would be MSR/MRS)
+        _ => {
+            let opcode = rng.next() % 16;
+            let set_flags = opcode & 0b1100 == 0b1000 || rng.next() & 1 != 0;
+            let operand2 = match rng.next() % 3 {
+                // rotated immediate
+                0 => 1 << 25 | (rng.next() & 0xfff),
+                // register shifted by an immediate
+                1 => (rng.next() & 0xfe0) | rng.reg(),
+                // register shifted by a register
+                _ => rng.reg() << 8 | (rng.next() & 0x60) | 0x10 | rng.reg(),
+            };
+            opcode << 21 | (set_flags as u32) << 20 | rng.reg() << 16 | rng.reg() << 12 | operand2
+        }
+    };
+    cond << 28 | insn
+}
+
+/// Returns a rom that loads random values in r0-r14 and the flags, then runs `count` random ARM
+/// ALU and multiply instructions and loops forever
+pub fn random_arm_program(seed: u64, count: usize) -> Vec<u8> {
+    let mut rng = Rng(seed | 1);
+    let mut program = Vec::new();
+    for rd in 0..15 {
+        program.extend_from_slice(&load_immediate(rd, rng.next()));
+    }
+    // msr cpsr_f, #flags
+    program.push(0xe328_f400 | (rng.next() & 0xf) << 4);
+    for _ in 0..count {
+        program.push(random_arm_instruction(&mut rng));
+    }
+    // b .
+    program.push(0xeaff_fffe);
+
+    let mut rom: Vec<u8> = program
+        .iter()
+        .flat_map(|insn| insn.to_le_bytes().to_vec())
+        .collect();
+    rom.resize(rom.len().max(0x200), 0);
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::GamepakBuilder;
+    use crate::gba::GameBoyAdvanceBuilder;
+
+    fn make_gba(rom: &[u8]) -> GameBoyAdvance {
+        GameBoyAdvanceBuilder::new()
+            .with_bios(vec![0; 0x4000].into_boxed_slice())
+            .with_gamepak(GamepakBuilder::new().buffer(rom).without_backup_to_file())
+            .skip_bios()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_trace_replay() {
+        let rom = random_arm_program(0x1234, 200);
+        let steps = 15 * 4 + 1 + 200;
+        let mut trace = Vec::new();
+        record_trace(&mut make_gba(&rom), &mut trace, steps, &[0x0300_0000]).unwrap();
+
+        let mut replay = TraceReplay::new(&trace[..]);
+        assert_eq!(
+            run_diff(&mut make_gba(&rom), &mut replay, usize::MAX).unwrap(),
+            steps
+        );
+
+        // corrupt r3 before the 100th instruction
+        let mut lines: Vec<String> = String::from_utf8(trace)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        let mut entry: TraceEntry = lines[100].parse().unwrap();
+        entry.gpr[3] ^= 1;
+        lines[100] = entry.to_string();
+        let trace = lines.join("\n");
+        let mut replay = TraceReplay::new(trace.as_bytes());
+        let divergence = run_diff(&mut make_gba(&rom), &mut replay, usize::MAX).unwrap_err();
+        assert_eq!(divergence.step, 100);
+        assert_eq!(divergence.actual.differences(&divergence.expected).len(), 1);
+    }
+}
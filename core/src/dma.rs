@@ -3,11 +3,47 @@ use super::cartridge::BackupMedia;
 use super::interrupt::{self, Interrupt, InterruptConnect, SharedInterruptFlags};
 use super::iodev::consts::{REG_FIFO_A, REG_FIFO_B};
 use super::sched::{EventType, Scheduler, SchedulerConnect, SharedScheduler};
-use super::sysbus::SysBus;
+use super::sysbus::{is_gamepak_rom_page, SysBus};
+use crate::logging;
 
 use num::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
+/// DMA0 can only access internal memory, DMA3 is the only channel that can write to the gamepak
+const SRC_ADDR_MASK: [u32; 4] = [0x07ff_ffff, 0x0fff_ffff, 0x0fff_ffff, 0x0fff_ffff];
+const DST_ADDR_MASK: [u32; 4] = [0x07ff_ffff, 0x07ff_ffff, 0x07ff_ffff, 0x0fff_ffff];
+const WORD_COUNT_MASK: [u32; 4] = [0x3fff, 0x3fff, 0x3fff, 0xffff];
+
+fn is_gamepak_rom(addr: u32) -> bool {
+    is_gamepak_rom_page(((addr >> 24) & 0xf) as usize)
+}
+
+/// Read-only snapshot of a DMA channel, for hardware inspectors
+#[derive(Debug, Clone, Copy)]
+pub struct DmaChannelState {
+    pub id: usize,
+    pub enabled: bool,
+    /// Whether a transfer is in progress right now
+    pub running: bool,
+    /// 0=Immediately, 1=VBlank, 2=HBlank, 3=Special
+    pub timing: u16,
+    pub repeat: bool,
+    pub is_32bit: bool,
+    pub irq_enabled: bool,
+    /// 0=Increment, 1=Decrement, 2=Fixed, 3=Increment/Reload
+    pub src_adj: u16,
+    pub dst_adj: u16,
+    pub fifo_mode: bool,
+    /// DMAxSAD, DMAxDAD and DMAxCNT_L as last written
+    pub src: u32,
+    pub dst: u32,
+    pub word_count: u32,
+    /// The internal registers, latched when the channel is enabled
+    pub internal_src: u32,
+    pub internal_dst: u32,
+    pub internal_count: u32,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DmaChannel {
     id: usize,
@@ -34,6 +70,27 @@ struct DmaInternalRegs {
 }
 
 impl DmaChannel {
+    pub fn state(&self) -> DmaChannelState {
+        DmaChannelState {
+            id: self.id,
+            enabled: self.ctrl.is_enabled(),
+            running: self.running,
+            timing: self.ctrl.timing(),
+            repeat: self.ctrl.repeat(),
+            is_32bit: self.ctrl.is_32bit(),
+            irq_enabled: self.ctrl.is_triggering_irq(),
+            src_adj: self.ctrl.src_adj(),
+            dst_adj: self.ctrl.dst_adj(),
+            fifo_mode: self.fifo_mode,
+            src: self.src,
+            dst: self.dst,
+            word_count: self.wc,
+            internal_src: self.internal.src_addr,
+            internal_dst: self.internal.dst_addr,
+            internal_count: self.internal.count,
+        }
+    }
+
     pub fn new(id: usize, interrupt_flags: SharedInterruptFlags) -> DmaChannel {
         if id > 3 {
             panic!("invalid dma id {}", id);
@@ -64,8 +121,8 @@ impl DmaChannel {
 
     pub fn write_src_high(&mut self, high: u16) {
         let src = self.src;
-        let high = (high & 0xfff) as u32;
-        self.src = (src & 0xffff) | (high << 16);
+        let high = high as u32;
+        self.src = ((src & 0xffff) | (high << 16)) & SRC_ADDR_MASK[self.id];
     }
 
     pub fn write_dst_low(&mut self, low: u16) {
@@ -75,12 +132,12 @@ impl DmaChannel {
 
     pub fn write_dst_high(&mut self, high: u16) {
         let dst = self.dst;
-        let high = (high & 0xfff) as u32;
-        self.dst = (dst & 0xffff) | (high << 16);
+        let high = high as u32;
+        self.dst = ((dst & 0xffff) | (high << 16)) & DST_ADDR_MASK[self.id];
     }
 
     pub fn write_word_count(&mut self, value: u16) {
-        self.wc = value as u32;
+        self.wc = value as u32 & WORD_COUNT_MASK[self.id];
     }
 
     pub fn write_dma_ctrl(&mut self, value: u16, #[cfg(feature = "debugger")] trace: bool) -> bool {
@@ -92,6 +149,7 @@ impl DmaChannel {
             {
                 if trace {
                     trace!(
+                        target: logging::DMA,
                         "DMA{} enabled! timing={} src={:#x} dst={:#x} cnt={}",
                         self.id,
                         timing,
@@ -121,10 +179,7 @@ impl DmaChannel {
     fn xfer(&mut self, sb: &mut SysBus) {
         let word_size = if self.ctrl.is_32bit() { 4 } else { 2 };
         let count = match self.internal.count {
-            0 => match self.id {
-                3 => 0x1_0000,
-                _ => 0x0_4000,
-            },
+            0 => WORD_COUNT_MASK[self.id] + 1,
             _ => self.internal.count,
         };
 
@@ -140,49 +195,64 @@ impl DmaChannel {
 
         let fifo_mode = self.fifo_mode;
 
+        // the gamepak rom is always read incrementally, regardless of the source address control
+        let src_in_rom = is_gamepak_rom(self.internal.src_addr);
         let src_adj = match self.ctrl.src_adj() {
-            /* Increment */ 0 => word_size,
-            /* Decrement */ 1 => 0 - word_size,
+            _ if src_in_rom => word_size,
+            /* Decrement */ 1 => 0u32.wrapping_sub(word_size),
             /* Fixed */ 2 => 0,
-            _ => panic!("forbidden DMA source address adjustment"),
+            /* Increment (3 is prohibited and acts as increment) */ _ => word_size,
         };
         let dst_adj = match self.ctrl.dst_adj() {
             /* Increment[+Reload] */ 0 | 3 => word_size,
-            /* Decrement */ 1 => 0 - word_size,
-            /* Fixed */ 2 => 0,
-            _ => panic!("forbidden DMA dest address adjustment"),
+            /* Decrement */ 1 => 0u32.wrapping_sub(word_size),
+            /* Fixed */ _ => 0,
+        };
+
+        // 2 internal cycles of setup, 4 if both source and destination are on the gamepak
+        let idle_cycles = if src_in_rom && is_gamepak_rom(self.internal.dst_addr) {
+            4
+        } else {
+            2
         };
+        for _ in 0..idle_cycles {
+            sb.idle_cycle();
+        }
 
         let mut access = MemoryAccess::NonSeq;
         if fifo_mode {
+            // sound dma always transfers 4 words to the fixed fifo address
+            let src_adj = src_adj.wrapping_mul(4 / word_size);
             for _ in 0..4 {
                 let v = sb.load_32(self.internal.src_addr & !3, access);
                 sb.store_32(self.internal.dst_addr & !3, v, access);
                 access = MemoryAccess::Seq;
-                self.internal.src_addr += 4;
+                self.internal.src_addr = self.internal.src_addr.wrapping_add(src_adj);
             }
         } else if word_size == 4 {
             for _ in 0..count {
                 let w = sb.load_32(self.internal.src_addr & !3, access);
                 sb.store_32(self.internal.dst_addr & !3, w, access);
                 access = MemoryAccess::Seq;
-                self.internal.src_addr += src_adj;
-                self.internal.dst_addr += dst_adj;
+                self.internal.src_addr = self.internal.src_addr.wrapping_add(src_adj);
+                self.internal.dst_addr = self.internal.dst_addr.wrapping_add(dst_adj);
             }
         } else {
             for _ in 0..count {
                 let hw = sb.load_16(self.internal.src_addr & !1, access);
                 sb.store_16(self.internal.dst_addr & !1, hw, access);
                 access = MemoryAccess::Seq;
-                self.internal.src_addr += src_adj;
-                self.internal.dst_addr += dst_adj;
+                self.internal.src_addr = self.internal.src_addr.wrapping_add(src_adj);
+                self.internal.dst_addr = self.internal.dst_addr.wrapping_add(dst_adj);
             }
         }
         if self.ctrl.is_triggering_irq() {
             interrupt::signal_irq(&self.interrupt_flags, self.irq);
         }
-        if self.ctrl.repeat() {
+        // repeat has no effect on immediate transfers
+        if self.ctrl.repeat() && self.ctrl.timing() != 0 {
             /* reload */
+            self.internal.count = self.wc;
             if 3 == self.ctrl.dst_adj() {
                 self.internal.dst_addr = self.dst;
             }
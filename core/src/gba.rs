@@ -1,25 +1,51 @@
 /// Struct containing everything
 use std::cell::{Cell, RefCell};
+use std::io;
+use std::path::Path;
 use std::rc::Rc;
 
 use bincode;
+use chrono::NaiveDateTime;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use serde::{Deserialize, Serialize};
 
 use super::arm7tdmi;
-use super::cartridge::Cartridge;
-use super::dma::DmaController;
+#[cfg(feature = "debugger")]
+use super::arm7tdmi::hooks::{HookResult, WatchKind};
+use super::bios;
+use super::cartridge::{
+    header, Cartridge, GamepakBuilder, Gyro, RtcClock, SolarSensor, TiltSensor,
+};
+use super::cheats::CheatEngine;
+use super::dma::{DmaChannelState, DmaController};
 use super::gpu::*;
+use super::gpu_hooks::{GpuCallback, GpuHook, GpuHooks};
+use super::idle_loop::IdleLoopDetector;
 use super::interrupt::*;
 use super::iodev::*;
-use super::sched::{EventType, Scheduler, SchedulerConnect, SharedScheduler};
-use super::sound::SoundController;
+use super::keypad::{self, InputSchedule, KeyState, ScheduledInput};
+use super::meminit::MemoryInit;
+use super::movie::{Movie, MovieSession};
+use super::overrides;
+use super::perf::{PerfCounters, PerfReport, Profile, Subsystem};
+use super::record::{Recorder, Recording};
+use super::rewind::RewindBuffer;
+use super::sched::{EventType, GpuEvent, Scheduler, SchedulerConnect, SharedScheduler};
+use super::screenshot::Screenshot;
+use super::sio::SerialController;
+use super::sound::{AudioFilter, ResamplerKind, SoundController, SoundState, Volume};
 use super::sysbus::SysBus;
-use super::timer::Timers;
-use super::util::Shared;
+use super::timer::{TimerState, Timers};
+use super::util::{now, Shared};
 
 #[cfg(not(feature = "no_video_interface"))]
 use super::VideoInterface;
-use super::{AudioInterface, InputInterface};
+use super::{
+    Addr, AudioInterface, GBAError, GBAResult, InputInterface, LinkTransport, RumbleInterface,
+    SerialInterface,
+};
 
 pub struct GameBoyAdvance {
     pub cpu: Box<arm7tdmi::Core<SysBus>>,
@@ -31,8 +57,50 @@ pub struct GameBoyAdvance {
     pub video_device: Rc<RefCell<dyn VideoInterface>>,
     pub audio_device: Rc<RefCell<dyn AudioInterface>>,
     pub input_device: Rc<RefCell<dyn InputInterface>>,
+    /// When the cpu reaches this address, emulation skips ahead to the next event
+    idle_loop: Option<Addr>,
+    idle_loop_detector: Option<IdleLoopDetector>,
+    /// Number of calls to `frame` so far
+    frame_count: usize,
+    input_schedule: InputSchedule,
+    /// Keys set through `set_key_state`, merged with the ones the input device reports
+    key_state: KeyState,
+    polled_keys: KeyState,
+    rumble_device: Option<Rc<RefCell<dyn RumbleInterface>>>,
+    /// The rumble state last reported to `rumble_device`
+    rumble: bool,
+    /// Frames left until the save memory the game wrote to is flushed to disk
+    save_flush_countdown: Option<usize>,
+    rewind: Option<RewindBuffer>,
+    cheats: CheatEngine,
+    recorder: Option<Recorder>,
+    movie: Option<MovieSession>,
+    perf: Option<PerfCounters>,
+    gpu_hooks: GpuHooks,
+    #[cfg(feature = "debugger")]
+    frame_hook: Option<Box<dyn FrameHook>>,
+}
+
+/// Called by `GameBoyAdvance::frame`, see `set_frame_hook`
+#[cfg(feature = "debugger")]
+pub trait FrameHook {
+    /// A breakpoint or watchpoint was hit. While any are set, `frame` runs the cpu one
+    /// instruction at a time so they can be reported, which is a lot slower.
+    fn on_hit(&mut self, gba: &mut GameBoyAdvance, hit: HookResult);
+
+    /// The frame has been run
+    fn on_frame(&mut self, gba: &mut GameBoyAdvance);
 }
 
+/// Save states start with this magic, followed by the little endian format version and the
+/// deflate compressed `SaveState`
+const SAVE_STATE_MAGIC: &[u8; 4] = b"RBAS";
+/// Bumped whenever the serialized layout changes, so old states are refused instead of misread
+const SAVE_STATE_VERSION: u32 = 9;
+
+/// Frames the save memory may stay dirty before it is written to disk, about a second
+const SAVE_FLUSH_DELAY: usize = 60;
+
 #[derive(Serialize, Deserialize)]
 struct SaveState {
     scheduler: Scheduler,
@@ -42,6 +110,7 @@ struct SaveState {
     iwram: Box<[u8]>,
     interrupt_flags: u16,
     cpu_state: arm7tdmi::SavedCpuState,
+    bios_last_opcode: u32,
 }
 
 #[derive(Debug, PartialEq)]
@@ -50,6 +119,44 @@ enum BusMaster {
     Cpu,
 }
 
+fn encode_save_state(state: &SaveState) -> bincode::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(SAVE_STATE_MAGIC);
+    bytes.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+    let mut encoder = DeflateEncoder::new(bytes, Compression::fast());
+    bincode::serialize_into(&mut encoder, state)?;
+    Ok(encoder.finish()?)
+}
+
+fn decode_save_state(bytes: &[u8]) -> bincode::Result<Box<SaveState>> {
+    if !bytes.starts_with(SAVE_STATE_MAGIC) {
+        // states from before the versioned format are plain bincode
+        return bincode::deserialize_from(bytes);
+    }
+    if bytes.len() < 8 {
+        return Err(Box::new(bincode::ErrorKind::Custom(
+            "truncated save state".to_string(),
+        )));
+    }
+    let mut version = [0; 4];
+    version.copy_from_slice(&bytes[4..8]);
+    let version = u32::from_le_bytes(version);
+    if version != SAVE_STATE_VERSION {
+        return Err(Box::new(bincode::ErrorKind::Custom(format!(
+            "unsupported save state version {} (expected {})",
+            version, SAVE_STATE_VERSION
+        ))));
+    }
+    bincode::deserialize_from(DeflateDecoder::new(&bytes[8..]))
+}
+
+fn millis_to_datetime(millis: i64) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(
+        millis.div_euclid(1000),
+        (millis.rem_euclid(1000) * 1_000_000) as u32,
+    )
+}
+
 /// Checks if the bios provided is the real one
 fn check_real_bios(bios: &[u8]) -> bool {
     use sha2::{Digest, Sha256};
@@ -71,24 +178,42 @@ impl GameBoyAdvance {
         audio_device: Rc<RefCell<dyn AudioInterface>>,
         input_device: Rc<RefCell<dyn InputInterface>>,
     ) -> GameBoyAdvance {
-        // Warn the user if the bios is not the real one
-        match check_real_bios(&bios_rom) {
-            true => info!("Verified bios rom"),
-            false => warn!("This is not the real bios rom, some games may not be compatible"),
+        let hle_bios = bios_rom.is_empty();
+        let bios_rom = if hle_bios {
+            info!("No bios rom provided, using high level emulation of the bios functions");
+            bios::hle_bios_image()
+        } else {
+            // Warn the user if the bios is not the real one
+            match check_real_bios(&bios_rom) {
+                true => info!("Verified bios rom"),
+                false => warn!("This is not the real bios rom, some games may not be compatible"),
+            };
+            bios_rom
         };
 
         let interrupt_flags = Rc::new(Cell::new(IrqBitmask(0)));
         let scheduler = Scheduler::new_shared();
 
         let intc = InterruptController::new(interrupt_flags.clone());
-        let gpu = Box::new(Gpu::new(scheduler.clone(), interrupt_flags.clone()));
+        #[allow(unused_mut)]
+        let mut gpu = Box::new(Gpu::new(scheduler.clone(), interrupt_flags.clone()));
+        #[cfg(not(feature = "no_video_interface"))]
+        gpu.set_output_format(video_device.borrow().output_format());
         let dmac = DmaController::new(interrupt_flags.clone(), scheduler.clone());
         let timers = Timers::new(interrupt_flags.clone(), scheduler.clone());
         let sound_controller = Box::new(SoundController::new(
             scheduler.clone(),
             audio_device.borrow().get_sample_rate() as f32,
         ));
-        let io_devs = Shared::new(IoDevices::new(intc, gpu, dmac, timers, sound_controller));
+        let sio = SerialController::new(interrupt_flags.clone());
+        let io_devs = Shared::new(IoDevices::new(
+            intc,
+            gpu,
+            dmac,
+            timers,
+            sound_controller,
+            sio,
+        ));
         let sysbus = Shared::new(SysBus::new(
             scheduler.clone(),
             io_devs.clone(),
@@ -96,7 +221,8 @@ impl GameBoyAdvance {
             gamepak,
         ));
 
-        let cpu = Box::new(arm7tdmi::Core::new(sysbus.clone()));
+        let mut cpu = Box::new(arm7tdmi::Core::new(sysbus.clone()));
+        cpu.set_hle_swi(hle_bios);
 
         let mut gba = GameBoyAdvance {
             cpu,
@@ -111,9 +237,33 @@ impl GameBoyAdvance {
             scheduler: scheduler,
 
             interrupt_flags: interrupt_flags,
+            idle_loop: None,
+            idle_loop_detector: None,
+            frame_count: 0,
+            input_schedule: InputSchedule::default(),
+            key_state: KeyState::default(),
+            polled_keys: KeyState::default(),
+            rumble_device: None,
+            rumble: false,
+            save_flush_countdown: None,
+            rewind: None,
+            cheats: CheatEngine::default(),
+            recorder: None,
+            movie: None,
+            perf: None,
+            gpu_hooks: GpuHooks::default(),
+            #[cfg(feature = "debugger")]
+            frame_hook: None,
         };
+        gba.idle_loop = gba.idle_loop_override();
 
         gba.sysbus.init(gba.cpu.weak_ptr());
+        gba.sysbus.load_ram_segments();
+        if hle_bios || gba.sysbus.cartridge.is_multiboot() {
+            // there is no boot sequence to run, or in the case of a multiboot image the bios
+            // would wait for another GBA to send it
+            gba.skip_bios();
+        }
 
         gba
     }
@@ -126,11 +276,22 @@ impl GameBoyAdvance {
         audio_device: Rc<RefCell<dyn AudioInterface>>,
         input_device: Rc<RefCell<dyn InputInterface>>,
     ) -> bincode::Result<GameBoyAdvance> {
-        let decoded: Box<SaveState> = bincode::deserialize_from(savestate)?;
+        let decoded = decode_save_state(savestate)?;
+
+        let hle_bios = bios.is_empty();
+        let bios = if hle_bios {
+            bios::hle_bios_image()
+        } else {
+            bios
+        };
 
         let interrupts = Rc::new(Cell::new(IrqBitmask(decoded.interrupt_flags)));
         let scheduler = decoded.scheduler.make_shared();
         let mut io_devs = Shared::new(decoded.io_devs);
+        #[cfg(not(feature = "no_video_interface"))]
+        io_devs
+            .gpu
+            .set_output_format(video_device.borrow().output_format());
         let mut cartridge = decoded.cartridge;
         cartridge.set_rom_bytes(rom);
         io_devs.connect_irq(interrupts.clone());
@@ -147,10 +308,11 @@ impl GameBoyAdvance {
             sysbus.clone(),
             decoded.cpu_state,
         ));
+        arm7tdmi.set_hle_swi(hle_bios);
 
         sysbus.init(arm7tdmi.weak_ptr());
 
-        Ok(GameBoyAdvance {
+        let mut gba = GameBoyAdvance {
             cpu: arm7tdmi,
             sysbus: sysbus,
             io_devs,
@@ -163,11 +325,57 @@ impl GameBoyAdvance {
             input_device: input_device,
 
             scheduler,
-        })
+            idle_loop: None,
+            idle_loop_detector: None,
+            frame_count: 0,
+            input_schedule: InputSchedule::default(),
+            key_state: KeyState::default(),
+            polled_keys: KeyState::default(),
+            rumble_device: None,
+            rumble: false,
+            save_flush_countdown: None,
+            rewind: None,
+            cheats: CheatEngine::default(),
+            recorder: None,
+            movie: None,
+            perf: None,
+            gpu_hooks: GpuHooks::default(),
+            #[cfg(feature = "debugger")]
+            frame_hook: None,
+        };
+        gba.idle_loop = gba.idle_loop_override();
+
+        Ok(gba)
     }
 
-    pub fn save_state(&self) -> bincode::Result<Vec<u8>> {
-        let s = SaveState {
+    fn idle_loop_override(&self) -> Option<Addr> {
+        let game_code = &self.sysbus.cartridge.header.game_code;
+        let idle_loop = overrides::get_game_overrides(game_code)?.idle_loop()?;
+        info!("Skipping the idle loop at {:08x}", idle_loop);
+        Some(idle_loop)
+    }
+
+    /// Overrides the idle loop from the game database, None disables idle loop skipping
+    pub fn set_idle_loop(&mut self, idle_loop: Option<Addr>) {
+        self.idle_loop = idle_loop;
+    }
+
+    pub fn idle_loop(&self) -> Option<Addr> {
+        self.idle_loop
+    }
+
+    /// Enables the idle loop analysis mode, candidates are logged whenever the screen stays static.
+    /// Slows down the emulation considerably.
+    pub fn set_idle_loop_analysis(&mut self, enabled: bool) {
+        self.idle_loop_detector = if enabled {
+            Some(IdleLoopDetector::default())
+        } else {
+            None
+        };
+    }
+
+    fn snapshot(&self) -> SaveState {
+        SaveState {
             cpu_state: self.cpu.save_state(),
             io_devs: self.io_devs.clone_inner(),
             cartridge: self.sysbus.cartridge.thin_copy(),
@@ -175,22 +383,47 @@ impl GameBoyAdvance {
             ewram: Box::from(self.sysbus.get_ewram()),
             interrupt_flags: self.interrupt_flags.get().value(),
             scheduler: self.scheduler.clone_inner(),
-        };
+            bios_last_opcode: self.sysbus.bios_last_opcode(),
+        }
+    }
 
-        bincode::serialize(&s)
+    pub fn save_state(&self) -> bincode::Result<Vec<u8>> {
+        encode_save_state(&self.snapshot())
     }
 
     pub fn restore_state(&mut self, bytes: &[u8]) -> bincode::Result<()> {
-        let decoded: Box<SaveState> = bincode::deserialize_from(bytes)?;
+        let decoded = decode_save_state(bytes)?;
+
+        let output_format = self.io_devs.gpu.output_format();
+        let obj_cycle_limit = self.io_devs.gpu.obj_cycle_limit();
+        let frameskip = self.io_devs.gpu.frameskip();
+        let threaded_rendering = self.io_devs.gpu.is_threaded_rendering();
+        let volume = self.io_devs.sound.volume();
+        let resampler_kind = self.io_devs.sound.resampler_kind();
+        let audio_filter = self.io_devs.sound.filter();
+        let serial_host = self.io_devs.sio.host();
+        let link = self.io_devs.sio.link();
+        let recording = self.io_devs.sound.is_recording();
+        self.fade_out_audio();
 
         self.cpu.restore_state(decoded.cpu_state);
         self.scheduler = Scheduler::make_shared(decoded.scheduler);
         self.interrupt_flags = Rc::new(Cell::new(IrqBitmask(decoded.interrupt_flags)));
         self.io_devs = Shared::new(decoded.io_devs);
+        self.io_devs.gpu.set_output_format(output_format);
+        self.io_devs.gpu.set_obj_cycle_limit(obj_cycle_limit);
+        self.io_devs.gpu.set_frameskip(frameskip);
+        self.io_devs.sound.set_volume(volume);
+        self.io_devs.sound.set_resampler_kind(resampler_kind);
+        self.io_devs.sound.set_filter(audio_filter);
+        self.io_devs.sound.set_recording(recording);
+        self.io_devs.sio.set_host(serial_host);
+        self.io_devs.sio.set_link(link);
         // Restore memory state
         self.cpu.set_memory_interface(self.sysbus.clone());
         self.sysbus.set_iwram(decoded.iwram);
         self.sysbus.set_ewram(decoded.ewram);
+        self.sysbus.set_bios_last_opcode(decoded.bios_last_opcode);
         // Redistribute shared pointers
         self.io_devs.connect_irq(self.interrupt_flags.clone());
         self.io_devs.connect_scheduler(self.scheduler.clone());
@@ -198,6 +431,11 @@ impl GameBoyAdvance {
         self.sysbus.set_io_devices(self.io_devs.clone());
         self.sysbus.cartridge.update_from(decoded.cartridge);
         self.sysbus.init(self.cpu.weak_ptr());
+        // the memory timings depend on the restored registers
+        let (waitcnt, imc) = (self.io_devs.waitcnt, self.io_devs.imc);
+        self.sysbus.on_waitcnt_written(waitcnt);
+        self.sysbus.on_imc_written(imc);
+        self.set_threaded_rendering(threaded_rendering)?;
 
         Ok(())
     }
@@ -212,29 +450,449 @@ impl GameBoyAdvance {
 
     #[inline]
     pub fn key_poll(&mut self) {
-        self.sysbus.io.keyinput = self.input_device.borrow_mut().poll();
+        let keyinput = self.input_device.borrow_mut().poll();
+        self.polled_keys = KeyState::from_keyinput(keyinput);
+        self.update_keyinput();
     }
 
+    fn update_keyinput(&mut self) {
+        if let Some(MovieSession::Playing { .. }) = self.movie {
+            // the movie decides the keys of every frame
+            return;
+        }
+        self.sysbus.io.keyinput = (self.key_state | self.polled_keys).to_keyinput();
+        self.sysbus.io.update_keypad_irq();
+    }
+
+    /// Sets the keys that are held down, the game sees the change right away.
+    /// Frontends can use this instead of `InputInterface::poll`, keys reported by either are pressed.
+    pub fn set_key_state(&mut self, keys: KeyState) {
+        self.key_state = keys;
+        self.update_keyinput();
+    }
+
+    pub fn key_state(&self) -> KeyState {
+        self.key_state
+    }
+
+    pub fn key_down<K: Into<KeyState>>(&mut self, keys: K) {
+        self.set_key_state(self.key_state | keys.into());
+    }
+
+    pub fn key_up<K: Into<KeyState>>(&mut self, keys: K) {
+        self.set_key_state(self.key_state - keys.into());
+    }
+
+    /// Queues a key state (KEYINPUT value, 0 means pressed) to apply when the emulation reaches
+    /// `scanline` of `frame`, frames are counted by calls to `frame`.
+    /// It stays in effect until the next poll of the input device, which happens every frame.
+    pub fn schedule_input(&mut self, frame: usize, scanline: usize, keyinput: u16) {
+        self.input_schedule.push(ScheduledInput {
+            frame,
+            scanline,
+            keyinput: keyinput & keypad::KEYINPUT_ALL_RELEASED,
+        });
+    }
+
+    pub fn clear_scheduled_inputs(&mut self) {
+        self.input_schedule.clear();
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    fn apply_scheduled_input(&mut self) {
+        let io = &mut (*self.io_devs);
+        let scanline = io.gpu.vcount;
+        if let Some(keyinput) = self.input_schedule.take_due(self.frame_count, scanline) {
+            io.keyinput = keyinput;
+            io.update_keypad_irq();
+        }
+    }
+
+    /// Number of cycles emulated since power on
+    pub fn cycles(&self) -> usize {
+        self.scheduler.timestamp()
+    }
+
+    /// Runs one video frame, returning once scanline 227 completed.
+    /// Frames end every `CYCLES_FULL_REFRESH` cycles since power on, so whatever the previous
+    /// frame overran by, the same inputs always give the same frames.
     pub fn frame(&mut self) {
+        let frame_start = self.perf.as_ref().map(|_| now());
         self.key_poll();
-        static mut OVERSHOOT: usize = 0;
-        unsafe {
-            OVERSHOOT = self.run(CYCLES_FULL_REFRESH - OVERSHOOT);
+        if self.movie.is_some() {
+            self.movie_frame();
+        }
+        self.apply_scheduled_input();
+        self.io_devs.sio.poll();
+        self.cheats.apply(&mut *self.sysbus);
+        self.sysbus.set_write_hooks(self.cheats.write_hooks());
+        let frame_end = (self.cycles() / CYCLES_FULL_REFRESH + 1) * CYCLES_FULL_REFRESH;
+        #[cfg(feature = "debugger")]
+        let mut frame_hook = self.frame_hook.take();
+        #[cfg(feature = "debugger")]
+        match &mut frame_hook {
+            Some(hook) if self.has_debug_hooks() => self.run_hooked(frame_end, hook.as_mut()),
+            _ => {
+                self.run_until(frame_end);
+            }
+        }
+        #[cfg(not(feature = "debugger"))]
+        self.run_until(frame_end);
+        self.flush_save_delayed();
+        let rumble = self.sysbus.cartridge.rumble();
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            if let Some(rumble_device) = &self.rumble_device {
+                rumble_device.borrow_mut().set_rumble(rumble);
+            }
+        }
+        if let Some(detector) = &mut self.idle_loop_detector {
+            let gpu = &self.io_devs.gpu;
+            let report = if gpu.output_format().is_16bit() {
+                detector.end_frame(gpu.get_frame_buffer_rgb565())
+            } else {
+                detector.end_frame(gpu.get_frame_buffer())
+            };
+            if let Some(report) = report {
+                info!(
+                    "static screen since frame {}, idle loop candidates for {}:",
+                    report.first_static_frame, self.sysbus.cartridge.header.game_code
+                );
+                for (pc, count) in report.candidates {
+                    info!("\tidle_loop: {:#010x} (executed {} times)", pc, count);
+                }
+            }
+        }
+        if self.recorder.is_some() {
+            self.record_frame();
+        }
+        self.frame_count += 1;
+        let snapshot_due = self.rewind.as_mut().map_or(false, |r| r.end_frame());
+        if snapshot_due {
+            // the rewind buffer compresses the deltas itself, so it takes the plain bincode state
+            match bincode::serialize(&self.snapshot()) {
+                Ok(state) => self.rewind.as_mut().unwrap().push(state),
+                Err(e) => warn!("failed to take a rewind snapshot: {}", e),
+            }
+        }
+        if let (Some(perf), Some(start)) = (&mut self.perf, frame_start) {
+            perf.end_frame(start.elapsed());
+        }
+        #[cfg(feature = "debugger")]
+        {
+            if let Some(hook) = &mut frame_hook {
+                hook.on_frame(self);
+            }
+            // the hook may have replaced itself
+            if self.frame_hook.is_none() {
+                self.frame_hook = frame_hook;
+            }
+        }
+    }
+
+    /// Calls the hook at the end of every `frame`, and on the breakpoints and watchpoints hit
+    /// during it. Replaces the previous hook.
+    #[cfg(feature = "debugger")]
+    pub fn set_frame_hook(&mut self, hook: Option<Box<dyn FrameHook>>) {
+        self.frame_hook = hook;
+    }
+
+    #[cfg(feature = "debugger")]
+    fn has_debug_hooks(&self) -> bool {
+        !self.cpu.dbg.breakpoints.is_empty() || !self.cpu.dbg.watchpoints.is_empty()
+    }
+
+    /// Steps the cpu until `cycle_target` so `hook` gets the breakpoints and watchpoints on the way
+    #[cfg(feature = "debugger")]
+    fn run_hooked(&mut self, cycle_target: usize, hook: &mut dyn FrameHook) {
+        while self.cycles() < cycle_target {
+            if let Some(hit) = self.step_debugger() {
+                hook.on_hit(self, hit);
+            }
+        }
+    }
+
+    /// Counts the executed instructions and the time spent in the subsystems, for
+    /// `perf_report`. Measuring costs a bit of the emulation speed.
+    /// A profile started by `start_profiling` keeps them running until it is taken.
+    pub fn set_perf_counters(&mut self, enabled: bool) {
+        let profiling = self.perf.as_ref().and_then(|perf| perf.profile()).is_some();
+        if enabled {
+            self.perf.get_or_insert_with(PerfCounters::default);
+        } else if !profiling {
+            self.perf = None;
+        }
+    }
+
+    /// The performance counters of the last second, `None` when they are disabled or during the
+    /// first second
+    pub fn perf_report(&self) -> Option<PerfReport> {
+        self.perf.as_ref().and_then(|perf| perf.report())
+    }
+
+    /// Records the time each component takes on every one of the next `frames` frames, the
+    /// performance counters are enabled if they were not
+    pub fn start_profiling(&mut self, frames: usize) {
+        self.perf
+            .get_or_insert_with(PerfCounters::default)
+            .start_profile(frames);
+    }
+
+    /// Returns the profile started by `start_profiling` once all of its frames ran
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.perf
+            .as_mut()
+            .and_then(|perf| perf.take_complete_profile())
+    }
+
+    fn record_frame(&mut self) {
+        // a skipped frame repeats the last one, so the video keeps the pace of the audio
+        let frame = self.screenshot();
+        let samples = self.io_devs.sound.take_recorded_samples();
+        let recorder = self.recorder.as_mut().unwrap();
+        if let Err(e) = recorder.write_frame(&frame, &samples) {
+            warn!("recording stopped: {}", e);
+            if let Err(e) = self.stop_recording() {
+                warn!("failed to complete the recording: {}", e);
+            }
+        }
+    }
+
+    /// Records the video and audio of every frame from now on to `<path>.rgb` and `<path>.wav`,
+    /// until `stop_recording`. A recording already in progress is completed first.
+    pub fn start_recording(&mut self, path: &Path) -> std::io::Result<()> {
+        self.stop_recording()?;
+        let sample_rate = self.audio_device.borrow().get_sample_rate() as u32;
+        self.recorder = Some(Recorder::start(path, sample_rate)?);
+        self.io_devs.sound.set_recording(true);
+        Ok(())
+    }
+
+    /// Completes the recording in progress, if any, and returns its files
+    pub fn stop_recording(&mut self) -> std::io::Result<Option<Recording>> {
+        self.io_devs.sound.set_recording(false);
+        self.recorder.take().map(Recorder::finish).transpose()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Records the keys of every frame from now on, until `stop_movie`. The movie starts from a
+    /// save state of the current session, loading a state or rewinding while recording makes
+    /// the movie go out of sync.
+    pub fn start_movie_recording(&mut self) -> bincode::Result<()> {
+        self.stop_movie();
+        let rtc_start = self.start_movie_rtc(None);
+        let movie = Movie {
+            game_code: self.get_game_code(),
+            start_state: self.save_state()?,
+            rtc_start,
+            frames: Vec::new(),
+        };
+        self.movie = Some(MovieSession::Recording(movie));
+        Ok(())
+    }
+
+    /// Restores the starting state of `movie` and plays back its keys, the input devices are
+    /// ignored until the end of the movie or `stop_movie`
+    pub fn play_movie(&mut self, movie: Movie) -> bincode::Result<()> {
+        let game_code = self.get_game_code();
+        if movie.game_code != game_code {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "the movie was recorded with {:?}, not {:?}",
+                movie.game_code, game_code
+            ))));
+        }
+        self.stop_movie();
+        self.restore_state(&movie.start_state)?;
+        self.start_movie_rtc(Some(movie.rtc_start));
+        self.movie = Some(MovieSession::Playing { movie, position: 0 });
+        Ok(())
+    }
+
+    /// Stops the movie being recorded or played back and returns it
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        let session = self.movie.take()?;
+        if let Some(rtc) = self.rtc_clock_mut() {
+            rtc.set_frozen(false);
+        }
+        self.update_keyinput();
+        Some(session.into_movie())
+    }
+
+    pub fn is_recording_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieSession::Recording(_)))
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieSession::Playing { .. }))
+    }
+
+    /// The number of frames since the start of the movie, and its length
+    pub fn movie_position(&self) -> Option<(usize, usize)> {
+        self.movie
+            .as_ref()
+            .map(|session| (session.position(), session.movie().len()))
+    }
+
+    /// Stops the RTC, it's moved forward every frame while a movie runs.
+    /// Returns the time it's started from, which is its current time unless `start` is given.
+    fn start_movie_rtc(&mut self, start: Option<i64>) -> i64 {
+        match self.rtc_clock_mut() {
+            Some(rtc) => {
+                rtc.set_frozen(true);
+                let start = start.unwrap_or_else(|| rtc.now().timestamp_millis());
+                rtc.set_time(millis_to_datetime(start));
+                start
+            }
+            None => start.unwrap_or(0),
+        }
+    }
+
+    fn movie_frame(&mut self) {
+        let session = self.movie.as_mut().unwrap();
+        let position = session.position();
+        let rtc_time = session.movie().rtc_time(position);
+        match session.next_frame(self.io_devs.keyinput) {
+            Some(keyinput) => {
+                self.io_devs.keyinput = keyinput;
+                self.io_devs.update_keypad_irq();
+                if let Some(rtc) = self.rtc_clock_mut() {
+                    rtc.set_time(millis_to_datetime(rtc_time));
+                }
+            }
+            None => {
+                info!("movie playback completed after {} frames", position);
+                self.stop_movie();
+            }
+        }
+    }
+
+    /// Calls `callback` on every `hook`, while the emulation runs. Returns an id for
+    /// `remove_gpu_hook`.
+    pub fn add_gpu_hook(&mut self, hook: GpuHook, callback: GpuCallback) -> usize {
+        self.gpu_hooks.add(hook, callback)
+    }
+
+    pub fn remove_gpu_hook(&mut self, id: usize) -> bool {
+        self.gpu_hooks.remove(id)
+    }
+
+    fn fire_gpu_hooks(&mut self, event: GpuEvent) {
+        let scanline = self.io_devs.gpu.vcount;
+        // the callbacks get the whole emulator, so the hooks are set aside while they run
+        let mut hooks = std::mem::take(&mut self.gpu_hooks);
+        match event {
+            GpuEvent::HDraw | GpuEvent::VBlankHDraw => hooks.fire(self, GpuHook::HBlank, scanline),
+            GpuEvent::HBlank | GpuEvent::VBlankHBlank => {
+                if event == GpuEvent::HBlank && scanline == DISPLAY_HEIGHT {
+                    hooks.fire(self, GpuHook::VBlank, scanline);
+                }
+                hooks.fire(self, GpuHook::Scanline, scanline);
+            }
+        }
+        self.gpu_hooks = hooks;
+    }
+
+    /// The cheats are applied at the start of every frame
+    pub fn cheats_mut(&mut self) -> &mut CheatEngine {
+        &mut self.cheats
+    }
+
+    /// Keeps a snapshot every `interval` frames, up to `capacity` snapshots, for `rewind`.
+    /// A capacity of 0 disables rewinding.
+    pub fn set_rewind(&mut self, interval: usize, capacity: usize) {
+        self.rewind = if capacity > 0 {
+            Some(RewindBuffer::new(interval, capacity))
+        } else {
+            None
+        };
+    }
+
+    /// Goes back to the last rewind snapshot, each call goes further back.
+    /// Returns false when there are no snapshots left.
+    pub fn rewind(&mut self) -> bool {
+        let state = match self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+            Some(state) => state,
+            None => return false,
+        };
+        match self.restore_state(&state) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("failed to restore a rewind snapshot: {}", e);
+                false
+            }
         }
     }
 
     #[inline]
     fn dma_step(&mut self) {
+        let start = self.perf.as_ref().map(|_| now());
         self.io_devs.dmac.perform_work(&mut self.sysbus);
+        if let (Some(perf), Some(start)) = (&mut self.perf, start) {
+            perf.add_time(Subsystem::Dma, start.elapsed());
+        }
     }
 
     #[inline]
     pub fn cpu_step(&mut self) {
-        if self.io_devs.intc.irq_pending() {
+        if self.io_devs.intc.irq_line() {
+            #[cfg(feature = "debugger")]
+            let previous = (self.cpu.cpsr, self.cpu.get_next_pc());
             self.cpu.irq();
             self.io_devs.haltcnt = HaltState::Running;
+            #[cfg(feature = "debugger")]
+            self.trace_cpu_transition(previous);
         }
+        #[cfg(feature = "debugger")]
+        let previous = (self.cpu.cpsr, self.cpu.get_next_pc());
         self.cpu.step();
+        self.io_devs.intc.sync_irq_line();
+        #[cfg(feature = "debugger")]
+        self.trace_cpu_transition(previous);
+    }
+
+    /// Logs exception entries, cpu mode and ARM/THUMB state changes, with the cycle they occured on
+    #[cfg(feature = "debugger")]
+    fn trace_cpu_transition(&self, (previous, previous_pc): (arm7tdmi::RegPSR, u32)) {
+        if !self.cpu.dbg.trace_transitions {
+            return;
+        }
+        let cpsr = self.cpu.cpsr;
+        let pc = self.cpu.get_next_pc();
+        let timestamp = self.scheduler.timestamp();
+        // the exception vectors are only ever reached by taking an exception
+        if pc < 0x20 && previous_pc >= 0x20 {
+            info!(
+                "[{}] exception entry: {:?} -> {:?} @{:08x} (from @{:08x})",
+                timestamp,
+                previous.mode(),
+                cpsr.mode(),
+                pc,
+                previous_pc
+            );
+        } else if cpsr.mode() != previous.mode() {
+            info!(
+                "[{}] mode change: {:?} -> {:?} @{:08x}",
+                timestamp,
+                previous.mode(),
+                cpsr.mode(),
+                pc
+            );
+        }
+        if cpsr.state() != previous.state() {
+            info!(
+                "[{}] state change: {:?} -> {:?} @{:08x}",
+                timestamp,
+                previous.state(),
+                cpsr.state(),
+                pc
+            );
+        }
     }
 
     #[inline]
@@ -246,6 +904,33 @@ impl GameBoyAdvance {
         }
     }
 
+    #[inline]
+    fn skip_to_next_event(&mut self, running: &mut bool) {
+        self.scheduler.fast_forward_to_next();
+        let (event, cycles_late) = self
+            .scheduler
+            .pop_pending_event()
+            .unwrap_or_else(|| unreachable!());
+        self.handle_event(event, cycles_late, running);
+    }
+
+    /// Runs at least `cycles` cycles, returns how many more were run since the last instruction
+    /// or DMA transfer can end past the limit. Unlike `frame`, nothing of the once per frame work
+    /// (input polling, cheats, save flushing, rewind snapshots, ...) is done.
+    pub fn run_cycles(&mut self, cycles: usize) -> usize {
+        self.run(cycles)
+    }
+
+    /// Runs until `cycle_target` cycles since power on (see `cycles`), returns how many more were
+    /// run. Returns right away when the target was already reached.
+    pub fn run_until(&mut self, cycle_target: usize) -> usize {
+        let cycles = self.cycles();
+        if cycles >= cycle_target {
+            return cycles - cycle_target;
+        }
+        self.run(cycle_target - cycles)
+    }
+
     /// Runs the emulation for a given amount of cycles
     /// @return number of extra cycle ran in this iteration
     #[inline]
@@ -268,17 +953,34 @@ impl GameBoyAdvance {
                 // 3. DMA inactive and halt state is HALT - CPU is blocked
                 match self.get_bus_master() {
                     Some(BusMaster::Dma) => self.dma_step(),
-                    Some(BusMaster::Cpu) => self.cpu_step(),
+                    Some(BusMaster::Cpu) => {
+                        if let Some(detector) = &mut self.idle_loop_detector {
+                            detector.sample_pc(self.cpu.get_next_pc());
+                        }
+                        if self.perf.is_some() {
+                            let start = self.scheduler.timestamp();
+                            self.cpu_step();
+                            let cycles = self.scheduler.timestamp() - start;
+                            self.perf.as_mut().unwrap().add_instruction(cycles);
+                        } else {
+                            self.cpu_step();
+                        }
+                        if self.idle_loop == Some(self.cpu.get_next_pc()) {
+                            // nothing changes until the next event, so there's no use in spinning
+                            self.skip_to_next_event(&mut running);
+                        }
+                    }
                     None => {
-                        if self.io_devs.intc.irq_pending() {
+                        let wake_up = match self.io_devs.haltcnt {
+                            HaltState::Stop => self.io_devs.intc.stop_exit_pending(),
+                            _ => self.io_devs.intc.halt_exit_pending(),
+                        };
+                        if wake_up {
                             self.io_devs.haltcnt = HaltState::Running;
+                            // the cpu was idle, so the irq is taken as soon as it wakes up
+                            self.io_devs.intc.settle_irq_line();
                         } else {
-                            self.scheduler.fast_forward_to_next();
-                            let (event, cycles_late) = self
-                                .scheduler
-                                .pop_pending_event()
-                                .unwrap_or_else(|| unreachable!());
-                            self.handle_event(event, cycles_late, &mut running);
+                            self.skip_to_next_event(&mut running);
                         }
                     }
                 }
@@ -307,20 +1009,78 @@ impl GameBoyAdvance {
                 let apu = &mut io.sound;
                 timers.handle_overflow_event(channel_id, cycles_late, apu, dmac);
             }
-            EventType::Gpu(event) => io.gpu.on_event(
-                event,
-                cycles_late,
-                &mut *self.sysbus,
-                #[cfg(not(feature = "no_video_interface"))]
-                &self.video_device,
-            ),
-            EventType::Apu(event) => io.sound.on_event(event, cycles_late, &self.audio_device),
+            EventType::Gpu(event) => {
+                let start = self.perf.as_ref().map(|_| now());
+                io.gpu.on_event(
+                    event,
+                    cycles_late,
+                    &mut *self.sysbus,
+                    #[cfg(not(feature = "no_video_interface"))]
+                    &self.video_device,
+                );
+                if !self.input_schedule.is_empty() {
+                    self.apply_scheduled_input();
+                }
+                // serial transfers complete on the other end of the cable, check for them
+                // every h/vblank so games busy waiting on them don't wait for the next frame
+                self.io_devs.sio.poll_transfer();
+                if !self.gpu_hooks.is_empty() {
+                    self.fire_gpu_hooks(event);
+                }
+                if let (Some(perf), Some(start)) = (&mut self.perf, start) {
+                    perf.add_time(Subsystem::Ppu, start.elapsed());
+                }
+            }
+            EventType::Apu(event) => {
+                let start = self.perf.as_ref().map(|_| now());
+                io.sound.on_event(event, cycles_late, &self.audio_device);
+                if let (Some(perf), Some(start)) = (&mut self.perf, start) {
+                    perf.add_time(Subsystem::Apu, start.elapsed());
+                }
+            }
         }
     }
 
+    /// Fills EWRAM, IWRAM, VRAM, palette RAM and OAM with their power-on contents.
+    /// Should be called before the emulation starts (and before `skip_bios`).
+    pub fn init_memory(&mut self, init: MemoryInit) {
+        let mut filler = init.filler();
+        filler.fill(self.sysbus.get_ewram_mut());
+        filler.fill(self.sysbus.get_iwram_mut());
+        let gpu = &mut self.io_devs.gpu;
+        filler.fill(&mut gpu.vram);
+        filler.fill(&mut gpu.palette_ram);
+        filler.fill(&mut gpu.oam);
+        gpu.invalidate_caches();
+        self.sysbus.load_ram_segments();
+    }
+
     pub fn skip_bios(&mut self) {
-        self.cpu.skip_bios();
+        let multiboot = self.sysbus.cartridge.is_multiboot();
+        let rom: &[u8] = if multiboot {
+            &self.sysbus.cartridge.ram_segments()[0].1
+        } else {
+            self.sysbus.cartridge.get_rom_bytes()
+        };
+        // The bios would hang on the Nintendo logo screen if the header checks fail
+        if !header::verify_boot_checks(rom) {
+            warn!("cartridge header failed the bios boot checks, real hardware would not boot it");
+        }
+        if multiboot {
+            // As if the image was sent by the first player of a multiplayer link, the images that
+            // are meant to be started some other way usually don't look at these
+            let ewram = self.sysbus.get_ewram_mut();
+            ewram[header::MULTIBOOT_BOOT_MODE] = header::MULTIBOOT_MODE_MULTIPLAY;
+            ewram[header::MULTIBOOT_SLAVE_ID] = 1;
+        }
+        self.cpu.skip_bios(self.sysbus.cartridge.entry_point());
         self.sysbus.io.gpu.skip_bios();
+        // The bios clears the top of IWRAM (its stack and the irq handler pointer) on boot
+        let iwram = self.sysbus.get_iwram_mut();
+        let iwram_len = iwram.len();
+        iwram[iwram_len - 0x200..].iter_mut().for_each(|b| *b = 0);
+        // The bios sets POSTFLG after the boot sequence is done
+        self.sysbus.io.post_boot_flag = true;
     }
 
     #[cfg(feature = "debugger")]
@@ -335,6 +1095,12 @@ impl GameBoyAdvance {
         }
     }
 
+    #[cfg(feature = "debugger")]
+    /// Watch the cpu memory accesses to `range`, returns the index of the new watchpoint
+    pub fn add_watchpoint(&mut self, range: std::ops::Range<Addr>, kind: WatchKind) -> usize {
+        self.cpu.dbg.watchpoints.add(range, kind)
+    }
+
     #[cfg(feature = "debugger")]
     pub fn check_breakpoint(&self) -> Option<u32> {
         let next_pc = self.cpu.get_next_pc();
@@ -348,23 +1114,30 @@ impl GameBoyAdvance {
     }
 
     #[cfg(feature = "debugger")]
-    /// 'step' function that checks for breakpoints
+    /// 'step' function that checks for breakpoints and watchpoints
     /// TODO avoid code duplication
-    pub fn step_debugger(&mut self) -> Option<u32> {
+    pub fn step_debugger(&mut self) -> Option<HookResult> {
+        // hits recorded while running without the debugger (by `frame`) are stale
+        self.cpu.dbg.watchpoints.take_hit();
+
         // clear any pending DMAs
         self.dma_step();
 
         // Run the CPU
         self.cpu_step();
 
-        let breakpoint = self.check_breakpoint();
-
         let mut _running = true;
         while let Some((event, cycles_late)) = self.scheduler.pop_pending_event() {
             self.handle_event(event, cycles_late, &mut _running);
         }
 
-        breakpoint
+        // a watchpoint fires in the middle of an instruction, report it before the breakpoint
+        // at the next one
+        self.cpu
+            .dbg
+            .watchpoints
+            .take_hit()
+            .or_else(|| self.check_breakpoint().map(HookResult::Breakpoint))
     }
 
     /// Query the emulator for the recently drawn framebuffer.
@@ -373,54 +1146,1113 @@ impl GameBoyAdvance {
         self.sysbus.io.gpu.get_frame_buffer()
     }
 
+    /// Returns a copy of the last drawn frame, in either output format
+    pub fn screenshot(&self) -> Screenshot {
+        let pixels = match self.io_devs.gpu.output_format() {
+            OutputFormat::Xrgb8888 => self.get_frame_buffer().to_vec(),
+            OutputFormat::Rgba8888 => self
+                .get_frame_buffer()
+                .iter()
+                .map(|c| {
+                    let [r, g, b, _] = c.to_ne_bytes();
+                    (r as u32) << 16 | (g as u32) << 8 | b as u32
+                })
+                .collect(),
+            OutputFormat::Bgra8888 => self
+                .get_frame_buffer()
+                .iter()
+                .map(|c| {
+                    let [b, g, r, _] = c.to_ne_bytes();
+                    (r as u32) << 16 | (g as u32) << 8 | b as u32
+                })
+                .collect(),
+            OutputFormat::Rgb565 => self
+                .get_frame_buffer_rgb565()
+                .iter()
+                .map(|&c| {
+                    let (r, g, b) = ((c >> 11) & 0x1f, (c >> 5) & 0x3f, c & 0x1f);
+                    (r as u32) << 19 | (g as u32) << 10 | (b as u32) << 3
+                })
+                .collect(),
+        };
+        Screenshot { pixels }
+    }
+
+    /// Like `get_frame_buffer`, for frontends that selected `OutputFormat::Rgb565`
+    pub fn get_frame_buffer_rgb565(&self) -> &[u16] {
+        self.sysbus.io.gpu.get_frame_buffer_rgb565()
+    }
+
+    /// Selects the frame buffer format, frontends with a `VideoInterface` negotiate it through
+    /// `VideoInterface::output_format` instead.
+    pub fn set_output_format(&mut self, output_format: OutputFormat) {
+        self.io_devs.gpu.set_output_format(output_format);
+    }
+
+    /// Makes sure the cartridge save file is up to date, e.g before switching to another game
+    pub fn flush_save(&mut self) {
+        self.sysbus.cartridge.flush_backup();
+        self.save_flush_countdown = None;
+    }
+
+    /// Flushes the save memory `SAVE_FLUSH_DELAY` frames after the game wrote to it, games write
+    /// their saves over many frames (a flash sector, an eeprom block at a time) and that would
+    /// otherwise rewrite the save file on every one of them
+    fn flush_save_delayed(&mut self) {
+        if !self.sysbus.cartridge.is_backup_dirty() {
+            self.save_flush_countdown = None;
+            return;
+        }
+        match self.save_flush_countdown {
+            Some(0) => self.flush_save(),
+            Some(frames) => self.save_flush_countdown = Some(frames - 1),
+            None => self.save_flush_countdown = Some(SAVE_FLUSH_DELAY),
+        }
+    }
+
+    /// Connects the serial port (in UART, normal and general purpose modes) to the host, it is not
+    /// part of the save state
+    pub fn set_serial_interface(&mut self, serial: Option<Rc<RefCell<dyn SerialInterface>>>) {
+        self.io_devs.sio.set_host(serial);
+    }
+
+    /// Connects the serial port (in multiplayer mode) to other emulators, it is not part of the
+    /// save state
+    pub fn set_link_transport(&mut self, link: Option<Rc<RefCell<dyn LinkTransport>>>) {
+        self.io_devs.sio.set_link(link);
+    }
+
+    /// Sends a command from the JOY Bus host (e.g a GameCube) and returns the reply of the GBA,
+    /// empty when the game didn't put the serial port in JOY Bus mode
+    pub fn joybus_command(&mut self, command: &[u8]) -> Vec<u8> {
+        self.io_devs.sio.joybus_command(command)
+    }
+
+    /// Snapshots of the DMA channels, for frontends that want to display the hardware state
+    pub fn dma_channels_state(&self) -> [DmaChannelState; 4] {
+        let channels = &self.io_devs.dmac.channels;
+        [
+            channels[0].state(),
+            channels[1].state(),
+            channels[2].state(),
+            channels[3].state(),
+        ]
+    }
+
+    pub fn timers_state(&self) -> [TimerState; 4] {
+        let timers = &self.io_devs.timers;
+        [
+            timers.state(0),
+            timers.state(1),
+            timers.state(2),
+            timers.state(3),
+        ]
+    }
+
+    pub fn sound_state(&self) -> SoundState {
+        self.io_devs.sound.state()
+    }
+
+    pub fn is_cartridge_inserted(&self) -> bool {
+        self.sysbus.cartridge.is_inserted()
+    }
+
+    /// Pulls the cartridge out of the slot or puts it back in.
+    /// Removing it raises the GamePak interrupt, which is how games detect it.
+    pub fn set_cartridge_inserted(&mut self, inserted: bool) {
+        if self.sysbus.cartridge.is_inserted() && !inserted {
+            signal_irq(&self.interrupt_flags, Interrupt::GamePak);
+        }
+        self.sysbus.cartridge.set_inserted(inserted);
+    }
+
+    /// The real-time clock of the cartridge, `None` if it doesn't have one.
+    /// The clock is part of the save state.
+    pub fn rtc_clock_mut(&mut self) -> Option<&mut RtcClock> {
+        self.sysbus.cartridge.rtc_clock_mut()
+    }
+
+    /// The light sensor of the Boktai carts, `None` for other carts
+    pub fn solar_sensor_mut(&mut self) -> Option<&mut SolarSensor> {
+        self.sysbus.cartridge.solar_sensor_mut()
+    }
+
+    /// The gyro sensor of WarioWare Twisted, `None` for other carts
+    pub fn gyro_mut(&mut self) -> Option<&mut Gyro> {
+        self.sysbus.cartridge.gyro_mut()
+    }
+
+    /// The tilt sensor of Yoshi Topsy-Turvy and Koro Koro Puzzle, `None` for other carts
+    pub fn tilt_sensor_mut(&mut self) -> Option<&mut TiltSensor> {
+        self.sysbus.cartridge.tilt_sensor_mut()
+    }
+
+    /// Connects the cartridge rumble motor to the host, for carts that have one
+    pub fn set_rumble_interface(&mut self, rumble: Option<Rc<RefCell<dyn RumbleInterface>>>) {
+        self.rumble_device = rumble;
+    }
+
+    /// Enables sprite dropout when a scanline has more objects than the hardware can render
+    /// (on by default), it is not part of the save state
+    pub fn set_obj_cycle_limit(&mut self, enabled: bool) {
+        self.io_devs.gpu.set_obj_cycle_limit(enabled);
+    }
+
+    /// Skips composing some frames to save time, it is not part of the save state
+    pub fn set_frameskip(&mut self, mode: FrameSkip) {
+        self.io_devs.gpu.set_frameskip(mode);
+    }
+
+    /// Composes the scanlines on a worker thread, overlapping with the emulation of the next
+    /// scanlines. The frame buffer is then only updated at vblank. It is not part of the save state
+    pub fn set_threaded_rendering(&mut self, enabled: bool) -> io::Result<()> {
+        self.io_devs.gpu.set_threaded_rendering(enabled)
+    }
+
+    /// Returns true when the last frame was skipped, so its frame buffer is stale
+    pub fn frame_skipped(&self) -> bool {
+        self.io_devs.gpu.last_frame_skipped()
+    }
+
+    /// Sets the master volume of the audio output, it is not part of the save state
+    pub fn set_volume(&mut self, volume: Volume) {
+        self.io_devs.sound.set_volume(volume);
+    }
+
+    pub fn volume(&self) -> Volume {
+        self.io_devs.sound.volume()
+    }
+
+    /// Selects how the audio is resampled to the host's sample rate, it is not part of the save state
+    pub fn set_resampler(&mut self, kind: ResamplerKind) {
+        self.io_devs.sound.set_resampler_kind(kind);
+    }
+
+    /// Selects the interpolation of the DirectSound channels and the low-pass filtering of the
+    /// audio output, it is not part of the save state
+    pub fn set_audio_filter(&mut self, filter: AudioFilter) {
+        self.io_devs.sound.set_filter(filter);
+    }
+
+    pub fn audio_filter(&self) -> AudioFilter {
+        self.io_devs.sound.filter()
+    }
+
+    /// Fades the audio output to silence, frontends should call this before pausing
+    /// or otherwise interrupting emulation to avoid an audible pop.
+    /// The audio fades back in when emulation continues.
+    pub fn fade_out_audio(&mut self) {
+        self.io_devs.sound.fade_out(&self.audio_device);
+    }
+
     /// Reset the emulator
     pub fn soft_reset(&mut self) {
         self.cpu.reset();
     }
 }
 
+/// Stands in for the frontend interfaces that were not given to the builder
+struct NullInterface;
+
+#[cfg(not(feature = "no_video_interface"))]
+impl VideoInterface for NullInterface {}
+impl AudioInterface for NullInterface {}
+impl InputInterface for NullInterface {}
+
+/// Creates a `GameBoyAdvance` along with all of its subsystems:
+///
+/// ```ignore
+/// let mut gba = GameBoyAdvanceBuilder::new()
+///     .with_bios(read_bin_file(Path::new("gba_bios.bin"))?)
+///     .with_rom(Path::new("game.gba"))
+///     .skip_bios()
+///     .with_audio(audio)
+///     .build()?;
+/// loop {
+///     gba.frame();
+/// }
+/// ```
+///
+/// Without a bios the bios functions are emulated, interfaces that are not given are left
+/// unconnected: no picture, no sound and no keys pressed.
+pub struct GameBoyAdvanceBuilder {
+    bios: Box<[u8]>,
+    gamepak: Option<GamepakBuilder>,
+    skip_bios: bool,
+    memory_init: Option<MemoryInit>,
+    output_format: Option<OutputFormat>,
+    #[cfg(not(feature = "no_video_interface"))]
+    video_device: Option<Rc<RefCell<dyn VideoInterface>>>,
+    audio_device: Option<Rc<RefCell<dyn AudioInterface>>>,
+    input_device: Option<Rc<RefCell<dyn InputInterface>>>,
+}
+
+impl Default for GameBoyAdvanceBuilder {
+    fn default() -> GameBoyAdvanceBuilder {
+        GameBoyAdvanceBuilder::new()
+    }
+}
+
+impl GameBoyAdvanceBuilder {
+    pub fn new() -> GameBoyAdvanceBuilder {
+        GameBoyAdvanceBuilder {
+            bios: Box::new([]),
+            gamepak: None,
+            skip_bios: false,
+            memory_init: None,
+            output_format: None,
+            #[cfg(not(feature = "no_video_interface"))]
+            video_device: None,
+            audio_device: None,
+            input_device: None,
+        }
+    }
+
+    pub fn with_bios(mut self, bios: Box<[u8]>) -> Self {
+        self.bios = bios;
+        self
+    }
+
+    pub fn with_rom(self, path: &Path) -> Self {
+        self.with_gamepak(GamepakBuilder::new().file(path))
+    }
+
+    pub fn with_rom_buffer(self, bytes: &[u8]) -> Self {
+        self.with_gamepak(GamepakBuilder::new().buffer(bytes))
+    }
+
+    /// Loads the cartridge from a `GamepakBuilder`, to choose its save type, save file and devices
+    pub fn with_gamepak(mut self, gamepak: GamepakBuilder) -> Self {
+        self.gamepak = Some(gamepak);
+        self
+    }
+
+    /// Starts the rom right away instead of going through the bios boot sequence
+    pub fn skip_bios(mut self) -> Self {
+        self.skip_bios = true;
+        self
+    }
+
+    pub fn with_memory_init(mut self, init: MemoryInit) -> Self {
+        self.memory_init = Some(init);
+        self
+    }
+
+    /// Overrides the frame buffer format asked for by the `VideoInterface`
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = Some(output_format);
+        self
+    }
+
+    #[cfg(not(feature = "no_video_interface"))]
+    pub fn with_video(mut self, video: Rc<RefCell<dyn VideoInterface>>) -> Self {
+        self.video_device = Some(video);
+        self
+    }
+
+    pub fn with_audio(mut self, audio: Rc<RefCell<dyn AudioInterface>>) -> Self {
+        self.audio_device = Some(audio);
+        self
+    }
+
+    pub fn with_input(mut self, input: Rc<RefCell<dyn InputInterface>>) -> Self {
+        self.input_device = Some(input);
+        self
+    }
+
+    pub fn build(self) -> GBAResult<GameBoyAdvance> {
+        let gamepak = self
+            .gamepak
+            .ok_or_else(|| GBAError::CartridgeLoadError("no rom was given".to_string()))?
+            .build()?;
+        let null = Rc::new(RefCell::new(NullInterface));
+        let hle_bios = self.bios.is_empty();
+        let mut gba = GameBoyAdvance::new(
+            self.bios,
+            gamepak,
+            #[cfg(not(feature = "no_video_interface"))]
+            self.video_device.unwrap_or_else(|| null.clone()),
+            self.audio_device.unwrap_or_else(|| null.clone()),
+            self.input_device.unwrap_or(null),
+        );
+        if let Some(init) = self.memory_init {
+            gba.init_memory(init);
+        }
+        if let Some(output_format) = self.output_format {
+            gba.set_output_format(output_format);
+        }
+        // the memory init overwrote what the boot setup of `new` put in memory: the boot mode of
+        // multiboot images, and the stack and irq handler pointer cleared without a bios
+        if self.skip_bios || hle_bios || gba.sysbus.cartridge.is_multiboot() {
+            gba.skip_bios();
+        }
+        Ok(gba)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::cell::RefCell;
-    use std::rc::Rc;
 
     use super::super::bus::Bus;
-    use super::super::cartridge::GamepakBuilder;
 
-    struct DummyInterface {}
+    fn make_mock_gba(rom: &[u8]) -> GameBoyAdvance {
+        GameBoyAdvanceBuilder::new()
+            .with_bios(vec![0; 0x4000].into_boxed_slice())
+            .with_gamepak(
+                GamepakBuilder::new()
+                    .buffer(rom)
+                    .with_sram()
+                    .without_backup_to_file(),
+            )
+            .skip_bios()
+            .build()
+            .unwrap()
+    }
 
-    impl DummyInterface {
-        fn new() -> DummyInterface {
-            DummyInterface {}
+    /// A rom with `words` at its start, padded to a size the cartridge accepts
+    fn arm_rom(words: &[u32]) -> Vec<u8> {
+        let mut rom = vec![0; 0x200];
+        for (i, word) in words.iter().enumerate() {
+            rom[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
         }
+        rom
     }
 
-    #[cfg(not(feature = "no_video_interface"))]
-    impl VideoInterface for DummyInterface {}
-    impl AudioInterface for DummyInterface {}
-    impl InputInterface for DummyInterface {}
+    #[test]
+    fn test_prefetch_serves_code_fetches_only() {
+        use crate::iodev::WaitControl;
+        use arm7tdmi::memory::{MemoryAccess, MemoryInterface};
 
-    fn make_mock_gba(rom: &[u8]) -> GameBoyAdvance {
-        let bios = vec![0; 0x4000].into_boxed_slice();
-        let cartridge = GamepakBuilder::new()
-            .buffer(rom)
-            .with_sram()
-            .without_backup_to_file()
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        gba.sysbus.on_waitcnt_written(WaitControl(1 << 14));
+
+        // a miss starts prefetching right after it
+        gba.sysbus.fetch_16(0x0800_0000, MemoryAccess::NonSeq);
+        for _ in 0..32 {
+            gba.sysbus.idle_cycle();
+        }
+        let start = gba.scheduler.timestamp();
+        gba.sysbus.fetch_16(0x0800_0002, MemoryAccess::Seq);
+        assert_eq!(gba.scheduler.timestamp() - start, 1);
+
+        // data reads take the gamepak bus, the next opcode misses the stopped buffer
+        let start = gba.scheduler.timestamp();
+        gba.sysbus.load_16(0x0800_0004, MemoryAccess::Seq);
+        assert!(gba.scheduler.timestamp() - start > 1);
+        let start = gba.scheduler.timestamp();
+        gba.sysbus.fetch_16(0x0800_0004, MemoryAccess::Seq);
+        assert!(gba.scheduler.timestamp() - start > 1);
+    }
+
+    #[test]
+    fn test_save_flush_delay() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        gba.sysbus.write_8(0x0e00_0000, 0x42);
+        assert!(gba.sysbus.cartridge.is_backup_dirty());
+
+        // the save is written once it was left dirty for a while, not at the end of every frame
+        for _ in 0..=SAVE_FLUSH_DELAY {
+            gba.frame();
+        }
+        assert!(gba.sysbus.cartridge.is_backup_dirty());
+        gba.frame();
+        assert!(!gba.sysbus.cartridge.is_backup_dirty());
+
+        gba.sysbus.write_8(0x0e00_0000, 0x43);
+        gba.flush_save();
+        assert!(!gba.sysbus.cartridge.is_backup_dirty());
+    }
+
+    #[test]
+    fn test_save_state_roundtrip() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        gba.sysbus.write_32(0x0200_0000, 0xdead_beef);
+        gba.cpu.set_reg(0, 0x1337);
+        let state = gba.save_state().unwrap();
+        assert!(state.starts_with(SAVE_STATE_MAGIC));
+
+        gba.sysbus.write_32(0x0200_0000, 0);
+        gba.cpu.set_reg(0, 0);
+        gba.restore_state(&state).unwrap();
+        assert_eq!(gba.sysbus.read_32(0x0200_0000), 0xdead_beef);
+        assert_eq!(gba.cpu.get_reg(0), 0x1337);
+
+        let mut future_state = state.clone();
+        future_state[4] = 0xff;
+        assert!(gba.restore_state(&future_state).is_err());
+    }
+
+    #[test]
+    fn test_internal_memory_control() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        assert_eq!(gba.sysbus.read_32(0x0400_0800), 0x0d00_0020);
+        // mirrored every 64K
+        assert_eq!(gba.sysbus.read_32(0x04ab_0800), 0x0d00_0020);
+
+        gba.sysbus.write_32(0x0200_0000, 0x1234_5678);
+        gba.sysbus.write_32(0x0300_0000, 0xcafe_babe);
+        // with EWRAM disabled, IWRAM shows through
+        gba.sysbus.write_32(0x0401_0800, 0x0d00_0000);
+        assert_eq!(gba.sysbus.read_32(0x0200_0000), 0xcafe_babe);
+        gba.sysbus.write_32(0x0400_0800, 0x0e00_0020);
+        assert_eq!(gba.sysbus.read_32(0x0200_0000), 0x1234_5678);
+        assert_eq!(gba.sysbus.io.imc.ewram_wait_control(), 0xe);
+
+        // POSTFLG only keeps bit 0, and writing it doesn't halt the cpu
+        gba.sysbus.write_8(0x0400_0300, 0xff);
+        assert_eq!(gba.sysbus.read_8(0x0400_0300), 1);
+        assert_eq!(gba.io_devs.haltcnt, HaltState::Running);
+        gba.sysbus.write_8(0x0400_0301, 0);
+        assert_eq!(gba.io_devs.haltcnt, HaltState::Halt);
+        assert_eq!(gba.sysbus.read_8(0x0400_0300), 1);
+    }
+
+    #[test]
+    fn test_frame_boundaries() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        gba.run_cycles(1000);
+        for i in 1..=3 {
+            gba.frame();
+            assert_eq!(gba.io_devs.gpu.vcount, 0);
+            // only the last instruction runs past the end of the frame
+            assert!(gba.cycles() >= i * CYCLES_FULL_REFRESH);
+            assert!(gba.cycles() < i * CYCLES_FULL_REFRESH + 16);
+        }
+
+        let target = gba.cycles() + 1234;
+        let overrun = gba.run_until(target);
+        assert_eq!(gba.cycles(), target + overrun);
+        assert_eq!(gba.run_until(target), overrun);
+    }
+
+    #[test]
+    fn test_multiboot_image() {
+        let mut image = vec![0; 0xec];
+        let code: [(usize, u32); 3] = [
+            (0xc0, 0xea00_0007), // b 0x020000e4, over the boot mode and slave ID
+            (0xe4, 0xe3a0_1042), // mov r1, #0x42
+            (0xe8, 0xeaff_fffe), // b 0x020000e8
+        ];
+        for (offset, insn) in &code {
+            image[*offset..*offset + 4].copy_from_slice(&insn.to_le_bytes());
+        }
+        let mut gba = GameBoyAdvanceBuilder::new()
+            .with_bios(vec![0; 0x4000].into_boxed_slice())
+            .with_gamepak(GamepakBuilder::new().buffer(&image).multiboot())
             .build()
             .unwrap();
-        let dummy = Rc::new(RefCell::new(DummyInterface::new()));
-        let mut gba = GameBoyAdvance::new(
-            bios,
-            cartridge,
-            #[cfg(not(feature = "no_video_interface"))]
-            dummy.clone(),
-            dummy.clone(),
-            dummy.clone(),
+        assert!(!gba.is_cartridge_inserted());
+        assert_eq!(
+            gba.sysbus.read_8(0x0200_00c4),
+            header::MULTIBOOT_MODE_MULTIPLAY
         );
-        gba.skip_bios();
+        gba.frame();
+        assert_eq!(gba.cpu.get_reg(1), 0x42);
+
+        let too_large = GamepakBuilder::new()
+            .buffer(&[0; header::MULTIBOOT_MAX_SIZE + 4])
+            .multiboot()
+            .build();
+        assert!(too_large.is_err());
+    }
 
-        gba
+    #[test]
+    fn test_write_only_register_byte_writes() {
+        use crate::iodev::consts::{REG_BG0HOFS, REG_WIN0H};
+
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        // each byte write keeps the other half of the register
+        gba.sysbus.write_8(REG_WIN0H, 0xa0);
+        gba.sysbus.write_8(REG_WIN0H + 1, 0x10);
+        assert_eq!(
+            gba.io_devs.write_only_register_value(REG_WIN0H),
+            Some(0x10a0)
+        );
+        gba.sysbus.write_8(REG_BG0HOFS + 1, 0x01);
+        gba.sysbus.write_8(REG_BG0HOFS, 0x23);
+        assert_eq!(
+            gba.io_devs.write_only_register_value(REG_BG0HOFS),
+            Some(0x0123)
+        );
+    }
+
+    #[test]
+    fn test_bios_read_protection() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        // the cpu runs from the cartridge, the reads return the opcode the bios left behind
+        // instead of its zeroes
+        assert_eq!(gba.sysbus.read_32(0x0), 0xe129_f000);
+        assert_eq!(gba.sysbus.read_16(0x2), 0xe129);
+        assert_eq!(gba.sysbus.read_8(0x1), 0xf0);
+
+        // the value returned after a SWI is part of the save state
+        gba.sysbus.set_bios_last_opcode(0xe3a0_2004);
+        let state = gba.save_state().unwrap();
+        gba.sysbus.set_bios_last_opcode(0);
+        gba.restore_state(&state).unwrap();
+        assert_eq!(gba.sysbus.read_32(0x100), 0xe3a0_2004);
+    }
+
+    #[test]
+    fn test_movie_playback() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        gba.frame();
+        gba.start_movie_recording().unwrap();
+        let cycles = gba.cycles();
+        for keys in &[KeyState::A, KeyState::A | KeyState::UP, KeyState::empty()] {
+            gba.set_key_state(*keys);
+            gba.frame();
+        }
+        assert!(gba.is_recording_movie());
+        let movie = gba.stop_movie().unwrap();
+        assert_eq!(movie.len(), 3);
+        assert_eq!(movie.frames[1], (KeyState::A | KeyState::UP).to_keyinput());
+
+        gba.set_key_state(KeyState::START);
+        gba.play_movie(movie.clone()).unwrap();
+        assert_eq!(gba.cycles(), cycles);
+        for i in 0..3 {
+            gba.frame();
+            assert_eq!(gba.io_devs.keyinput, movie.frames[i]);
+        }
+        assert_eq!(gba.movie_position(), Some((3, 3)));
+        // back to the input devices once the movie is over
+        gba.frame();
+        assert!(!gba.is_playing_movie());
+        assert_eq!(gba.io_devs.keyinput, KeyState::START.to_keyinput());
+    }
+
+    #[test]
+    fn test_perf_counters() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        assert_eq!(gba.perf_report(), None);
+        gba.start_profiling(2);
+        gba.frame();
+        // the rom is all andeq r0, r0, r0 which never halts
+        assert!(gba.perf.as_ref().unwrap().instructions() > 0);
+        assert_eq!(gba.take_profile(), None);
+        // toggling the counters doesn't lose the profile in progress
+        gba.set_perf_counters(true);
+        gba.set_perf_counters(false);
+        gba.frame();
+        let profile = gba.take_profile().unwrap();
+        assert_eq!(profile.frames().len(), 2);
+        assert!(profile.frames()[0].total >= profile.frames()[0].ppu);
+        gba.set_perf_counters(false);
+        assert!(gba.perf.is_none());
+    }
+
+    #[test]
+    fn test_output_formats() {
+        for &format in [
+            OutputFormat::Xrgb8888,
+            OutputFormat::Rgba8888,
+            OutputFormat::Bgra8888,
+            OutputFormat::Rgb565,
+        ]
+        .iter()
+        {
+            let mut gba = make_mock_gba(&[0; 0x200]);
+            gba.set_output_format(format);
+            // mode 0 without any layer shows the backdrop, pure red
+            gba.sysbus.write_16(0x0400_0000, 0);
+            gba.sysbus.write_16(0x0500_0000, 0x001f);
+            gba.frame();
+            let screenshot = gba.screenshot();
+            assert!(
+                screenshot.pixels.iter().all(|&c| c == 0x00f8_0000),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn test_threaded_rendering() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        gba.set_threaded_rendering(true).unwrap();
+        gba.sysbus.write_16(0x0400_0000, 0);
+        gba.sysbus.write_16(0x0500_0000, 0x001f);
+        // the first line of a frame is drawn at the end of the previous one
+        gba.frame();
+        gba.frame();
+        assert!(gba.screenshot().pixels.iter().all(|&c| c == 0x00f8_0000));
+
+        gba.sysbus.write_16(0x0500_0000, 0x7c00);
+        gba.frame();
+        gba.frame();
+        gba.set_threaded_rendering(false).unwrap();
+        assert!(gba.screenshot().pixels.iter().all(|&c| c == 0x0000_00f8));
+    }
+
+    #[test]
+    fn test_threaded_rendering_vram_writes() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        gba.set_threaded_rendering(true).unwrap();
+        // mode 3, the first pixel red
+        gba.sysbus.write_16(0x0400_0000, 0x0403);
+        gba.sysbus.write_16(0x0600_0000, 0x001f);
+        gba.frame();
+        gba.frame();
+        let pixels = gba.screenshot().pixels;
+        assert_eq!(&pixels[..2], &[0x00f8_0000, 0]);
+
+        // only the written halfword reaches the worker, the rest of the block is kept
+        gba.sysbus.write_16(0x0600_0002, 0x7c00);
+        gba.frame();
+        gba.frame();
+        gba.set_threaded_rendering(false).unwrap();
+        let pixels = gba.screenshot().pixels;
+        assert_eq!(&pixels[..2], &[0x00f8_0000, 0x0000_00f8]);
+    }
+
+    #[test]
+    fn test_gpu_hooks() {
+        use std::cell::Cell;
+
+        let mut gba = make_mock_gba(&[0; 0x200]);
+        let counts = Rc::new(Cell::new((0, 0, 0)));
+        let last_line = Rc::new(Cell::new(0));
+        let (c, l) = (counts.clone(), last_line.clone());
+        gba.add_gpu_hook(
+            GpuHook::Scanline,
+            Box::new(move |_, line| {
+                let (s, h, v) = c.get();
+                c.set((s + 1, h, v));
+                l.set(line);
+            }),
+        );
+        let c = counts.clone();
+        gba.add_gpu_hook(
+            GpuHook::HBlank,
+            Box::new(move |_, _| {
+                let (s, h, v) = c.get();
+                c.set((s, h + 1, v));
+            }),
+        );
+        let c = counts.clone();
+        let vblank = gba.add_gpu_hook(
+            GpuHook::VBlank,
+            Box::new(move |gba, line| {
+                assert_eq!(line, DISPLAY_HEIGHT);
+                assert_eq!(gba.io_devs.gpu.vcount, DISPLAY_HEIGHT);
+                let (s, h, v) = c.get();
+                c.set((s, h, v + 1));
+            }),
+        );
+
+        gba.frame();
+        assert_eq!(counts.get(), (228, 228, 1));
+        assert_eq!(last_line.get(), 0);
+
+        assert!(gba.remove_gpu_hook(vblank));
+        assert!(!gba.remove_gpu_hook(vblank));
+        gba.frame();
+        assert_eq!(counts.get(), (456, 456, 1));
+    }
+
+    #[test]
+    fn test_memory_mirrors() {
+        let mut gba = make_mock_gba(&[0; 0x200]);
+
+        // the upper 32K of each 128K VRAM block mirror the OBJ area
+        gba.sysbus.write_16(0x0601_0000, 0x1234);
+        assert_eq!(gba.sysbus.read_16(0x0601_8000), 0x1234);
+        assert_eq!(gba.sysbus.read_16(0x0603_8000), 0x1234);
+
+        // SRAM is 8bit only
+        gba.sysbus.write_32(0x0e00_0000, 0xaabbccdd);
+        assert_eq!(gba.sysbus.read_8(0x0e00_0000), 0xdd);
+        assert_eq!(gba.sysbus.read_8(0x0e00_0001), 0xff);
+        assert_eq!(gba.sysbus.read_16(0x0e00_0000), 0xdddd);
+        assert_eq!(gba.sysbus.read_32(0x0e00_8000), 0xdddddddd);
+    }
+
+    #[test]
+    fn test_pc_store_value() {
+        #[rustfmt::skip]
+        let code: [u32; 5] = [
+            0xe3a00403, // 08000000: mov r0, #0x03000000
+            0xe580f000, // 08000004: str pc, [r0]
+            0xe2801004, // 08000008: add r1, r0, #4
+            0xe8818000, // 0800000c: stmia r1, {pc}
+            0xeafffffe, // 08000010: b .
+        ];
+        let mut gba = make_mock_gba(&arm_rom(&code));
+
+        for _ in 0..10 {
+            gba.cpu_step();
+        }
+
+        // both single and block transfers store the instruction address + 12
+        assert_eq!(gba.sysbus.read_32(0x0300_0000), 0x0800_0004 + 12);
+        assert_eq!(gba.sysbus.read_32(0x0300_0004), 0x0800_000c + 12);
+    }
+
+    #[test]
+    fn test_pc_operand_value() {
+        #[rustfmt::skip]
+        let code: [u32; 5] = [
+            0xe3a01000, // 08000000: mov r1, #0
+            0xe3a02000, // 08000004: mov r2, #0
+            0xe1a0300f, // 08000008: mov r3, pc
+            0xe08f4211, // 0800000c: add r4, pc, r1, lsl r2
+            0xeafffffe, // 08000010: b .
+        ];
+        let mut gba = make_mock_gba(&arm_rom(&code));
+
+        for _ in 0..6 {
+            gba.cpu_step();
+        }
+
+        // R15 reads two instructions ahead, and one more after a register specified shift
+        assert_eq!(gba.cpu.get_reg(3), 0x0800_0008 + 8);
+        assert_eq!(gba.cpu.get_reg(4), 0x0800_000c + 12);
+    }
+
+    #[test]
+    fn test_skip_bios_to_thumb_entry_point() {
+        let mut rom = vec![0; 0x200];
+        // 08000000: mov r2, #0x42
+        rom[0..2].copy_from_slice(&0x2242u16.to_le_bytes());
+        // 08000002: b .
+        rom[2..4].copy_from_slice(&0xe7feu16.to_le_bytes());
+        let mut gba = make_mock_gba(&rom);
+        // the entry point of an elf file with thumb startup code
+        gba.cpu.skip_bios(0x0800_0001);
+
+        gba.cpu_step();
+        assert_eq!(gba.cpu.cpsr.state(), arm7tdmi::CpuState::THUMB);
+        assert_eq!(gba.cpu.get_reg(2), 0x42);
+    }
+
+    #[test]
+    fn test_ldm_psr_transfer_returns_to_thumb() {
+        #[rustfmt::skip]
+        let code: [u32; 7] = [
+            0xe3a0003f, // 08000000: mov r0, #0x3f
+            0xe321f0d3, // 08000004: msr cpsr_c, #0xd3
+            0xe16ff000, // 08000008: msr spsr_fsxc, r0
+            0xe3a01302, // 0800000c: mov r1, #0x08000000
+            0xe2811022, // 08000010: add r1, r1, #0x22
+            0xe52d1004, // 08000014: str r1, [sp, #-4]!
+            0xe8fd8000, // 08000018: ldmfd sp!, {pc}^
+        ];
+        let mut rom = arm_rom(&code);
+        // 08000022: mov r2, #0x42
+        rom[0x22..0x24].copy_from_slice(&0x2242u16.to_le_bytes());
+        // 08000024: b .
+        rom[0x24..0x26].copy_from_slice(&0xe7feu16.to_le_bytes());
+        let mut gba = make_mock_gba(&rom);
+
+        for _ in 0..8 {
+            gba.cpu_step();
+        }
+
+        // the pipeline is refilled in the restored THUMB state, from a halfword aligned pc
+        assert_eq!(gba.cpu.cpsr.state(), arm7tdmi::CpuState::THUMB);
+        assert_eq!(gba.cpu.get_reg(2), 0x42);
+        assert_eq!(gba.cpu.get_next_pc(), 0x0800_0024);
+    }
+
+    #[test]
+    fn test_unpredictable_mode_change() {
+        #[rustfmt::skip]
+        let code: [u32; 3] = [
+            0xe321f000, // 08000000: msr cpsr_c, #0
+            0xe10f2000, // 08000004: mrs r2, cpsr
+            0xeafffffe, // 08000008: b .
+        ];
+        let mut gba = make_mock_gba(&arm_rom(&code));
+
+        for _ in 0..5 {
+            gba.cpu_step();
+        }
+
+        // 0 is not a mode, the cpu reports it and stays in system mode
+        match gba.cpu.take_error() {
+            Some(arm7tdmi::CpuError::Unpredictable { pc, .. }) => assert_eq!(pc, 0x0800_0000),
+            error => panic!("unexpected error {:?}", error),
+        }
+        assert_eq!(gba.cpu.get_reg(2) & 0x1f, 0x1f);
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn test_watchpoint_hit_outside_of_stepping() {
+        use arm7tdmi::hooks::WatchKind;
+
+        #[rustfmt::skip]
+        let code: [u32; 3] = [
+            0xe3a00403, // 08000000: mov r0, #0x03000000
+            0xe5800000, // 08000004: str r0, [r0]
+            0xeafffffe, // 08000008: b .
+        ];
+        let mut gba = make_mock_gba(&arm_rom(&code));
+        gba.add_watchpoint(0x0300_0000..0x0300_0004, WatchKind::Write);
+
+        // the write happened during the frame, it must not stop the next debugger step
+        gba.frame();
+        assert_eq!(gba.step_debugger(), None);
+    }
+
+    #[test]
+    fn test_memory_init_with_hle_bios() {
+        let rom = vec![0; 0x200];
+        let mut gba = GameBoyAdvanceBuilder::new()
+            .with_gamepak(
+                GamepakBuilder::new()
+                    .buffer(&rom)
+                    .with_sram()
+                    .without_backup_to_file(),
+            )
+            .with_memory_init(MemoryInit::Pattern(0xaa))
+            .build()
+            .unwrap();
+        assert_eq!(gba.sysbus.read_8(0x0300_0000), 0xaa);
+        // the irq handler pointer is cleared as the boot sequence would
+        assert_eq!(gba.sysbus.read_32(0x0300_7ffc), 0);
+        assert_eq!(gba.cpu.get_next_pc(), 0x0800_0000);
+    }
+
+    #[test]
+    fn test_block_cache_self_modifying_code() {
+        let rom = vec![0; 0x200];
+        let mut gba = make_mock_gba(&rom);
+        #[rustfmt::skip]
+        let code: [u32; 4] = [
+            0xe1a0_0000, // 03000000: nop
+            0xe1a0_0000, // 03000004: nop
+            0xe3a0_0001, // 03000008: mov r0, #1
+            0xeaff_fffb, // 0300000c: b 03000000
+        ];
+        for (i, insn) in code.iter().enumerate() {
+            gba.sysbus.write_32(0x0300_0000 + 4 * i as u32, *insn);
+        }
+        gba.cpu.set_reg(15, 0x0300_0000);
+        gba.cpu.reload_pipeline();
+        for _ in 0..20 {
+            gba.cpu_step();
+        }
+        assert_eq!(gba.cpu.get_reg(0), 1);
+
+        // the loop ran from the block cache, the write must make it decode the code again
+        gba.sysbus.write_32(0x0300_0008, 0xe3a0_0002); // mov r0, #2
+        for _ in 0..20 {
+            gba.cpu_step();
+        }
+        assert_eq!(gba.cpu.get_reg(0), 2);
+    }
+
+    #[test]
+    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+    fn test_jit_matches_interpreter() {
+        #[rustfmt::skip]
+        let code: [u32; 8] = [
+            0xe280_0001, // 08000000: add r0, r0, #1
+            0xe080_1100, // add r1, r0, r0, lsl #2
+            0xe241_2003, // sub r2, r1, #3
+            0xe022_30a1, // eor r3, r2, r1, lsr #1
+            0xe263_4c01, // rsb r4, r3, #0x100
+            0xe3c4_500f, // bic r5, r4, #0xf
+            0xe1e0_6045, // mvn r6, r5, asr #32
+            0xeaff_fff7, // b 08000000
+        ];
+        let rom = arm_rom(&code);
+        let mut interpreted = make_mock_gba(&rom);
+        let mut compiled = make_mock_gba(&rom);
+        compiled.cpu.set_jit_enabled(true);
+        for cycles in (1000..20000).step_by(1000) {
+            interpreted.run_until(cycles);
+            compiled.run_until(cycles);
+            assert_eq!(compiled.cycles(), interpreted.cycles());
+            assert_eq!(compiled.cpu.gpr, interpreted.cpu.gpr);
+            assert_eq!(compiled.cpu.get_next_pc(), interpreted.cpu.get_next_pc());
+        }
+        assert!(compiled.cpu.jit().compiled_runs() > 0);
+    }
+
+    #[test]
+    fn test_hle_cpu_set_wraps_around() {
+        // 08000000: swi 0x0b (CpuSet)
+        let rom = arm_rom(&[0xef0b_0000; 0x80]);
+        let mut gba = GameBoyAdvanceBuilder::new()
+            .with_gamepak(
+                GamepakBuilder::new()
+                    .buffer(&rom)
+                    .with_sram()
+                    .without_backup_to_file(),
+            )
+            .skip_bios()
+            .build()
+            .unwrap();
+        // 4 words from the very end of the address space, the source wraps around to the bios
+        gba.cpu.set_reg(0, 0xffff_fff8);
+        gba.cpu.set_reg(1, 0x0300_0000);
+        gba.cpu.set_reg(2, 1 << 26 | 4);
+
+        gba.cpu_step();
+        assert_eq!(gba.cpu.get_next_pc(), 0x0800_0004);
+    }
+
+    #[test]
+    fn test_hle_huffman_tree_out_of_bounds() {
+        // 08000000: swi 0x13 (HuffUnComp)
+        let rom = arm_rom(&[0xef13_0000; 0x80]);
+        let mut gba = GameBoyAdvanceBuilder::new()
+            .with_gamepak(
+                GamepakBuilder::new()
+                    .buffer(&rom)
+                    .with_sram()
+                    .without_backup_to_file(),
+            )
+            .skip_bios()
+            .build()
+            .unwrap();
+        // 4 bytes of 8 bit data, a two byte tree whose root points far past its end
+        gba.sysbus.write_32(0x0200_0000, 4 << 8 | 0x28);
+        gba.sysbus.write_16(0x0200_0004, 0x3f00);
+        gba.sysbus.write_32(0x0200_0008, 0xffff_ffff);
+        gba.sysbus.write_32(0x0300_0000, 0x1234_5678);
+        gba.cpu.set_reg(0, 0x0200_0000);
+        gba.cpu.set_reg(1, 0x0300_0000);
+
+        gba.cpu_step();
+        assert_eq!(gba.sysbus.read_32(0x0300_0000), 0x1234_5678);
+    }
+
+    #[test]
+    fn test_frame_skipped() {
+        let rom = vec![0; 0x200];
+        let mut gba = make_mock_gba(&rom);
+        gba.set_frameskip(FrameSkip::Fixed(1));
+
+        // the frame that was already running when frameskip got enabled is drawn
+        let skipped: Vec<bool> = (0..4)
+            .map(|_| {
+                gba.frame();
+                gba.frame_skipped()
+            })
+            .collect();
+        assert_eq!(skipped, vec![false, true, false, true]);
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn test_frame_hook() {
+        use arm7tdmi::hooks::WatchKind;
+
+        #[derive(Default)]
+        struct Events {
+            hits: Vec<HookResult>,
+            frames: usize,
+        }
+        struct Recorder(Rc<RefCell<Events>>);
+        impl FrameHook for Recorder {
+            fn on_hit(&mut self, _gba: &mut GameBoyAdvance, hit: HookResult) {
+                self.0.borrow_mut().hits.push(hit);
+            }
+            fn on_frame(&mut self, _gba: &mut GameBoyAdvance) {
+                self.0.borrow_mut().frames += 1;
+            }
+        }
+
+        #[rustfmt::skip]
+        let code: [u32; 3] = [
+            0xe3a00403, // 08000000: mov r0, #0x03000000
+            0xe5800000, // 08000004: str r0, [r0]
+            0xeafffffe, // 08000008: b .
+        ];
+        let mut gba = make_mock_gba(&arm_rom(&code));
+        let events = Rc::new(RefCell::new(Events::default()));
+        gba.set_frame_hook(Some(Box::new(Recorder(events.clone()))));
+        gba.add_watchpoint(0x0300_0000..0x0300_0004, WatchKind::Write);
+
+        gba.frame();
+        gba.frame();
+        let events = events.borrow();
+        assert_eq!(events.hits.len(), 1);
+        assert!(matches!(
+            events.hits[0],
+            HookResult::Watchpoint {
+                addr: 0x0300_0000,
+                ..
+            }
+        ));
+        assert_eq!(events.frames, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn test_error_history() {
+        #[rustfmt::skip]
+        let code: [u32; 3] = [
+            0xe3a00001, // 08000000: mov r0, #1
+            0xe321f000, // 08000004: msr cpsr_c, #0
+            0xeafffffe, // 08000008: b .
+        ];
+        let mut gba = make_mock_gba(&arm_rom(&code));
+
+        for _ in 0..8 {
+            gba.cpu_step();
+        }
+
+        // the history ends at the instruction that raised the error, not at the current one
+        assert!(gba.cpu.take_error().is_some());
+        let history = gba.cpu.dbg.error_history.as_ref().unwrap();
+        let last = history.iter().last().unwrap();
+        assert_eq!(last.insn.get_pc(), 0x0800_0004);
+        assert!(gba.cpu.dbg.history.len() > history.len());
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn test_step_debug() {
+        use arm7tdmi::step_report::{FlagChange, MemoryWrite, RegisterChange};
+
+        #[rustfmt::skip]
+        let code: [u32; 5] = [
+            0xe3a00403, // 08000000: mov r0, #0x03000000
+            0xe3a01042, // 08000004: mov r1, #0x42
+            0xe5801000, // 08000008: str r1, [r0]
+            0xe0502001, // 0800000c: subs r2, r0, r1
+            0xeafffffe, // 08000010: b .
+        ];
+        let mut gba = make_mock_gba(&arm_rom(&code));
+
+        gba.cpu.step_debug();
+        gba.cpu.step_debug();
+        let report = gba.cpu.step_debug();
+        assert_eq!(report.pc, 0x0800_0008);
+        assert!(report.registers.is_empty());
+        assert_eq!(
+            report.writes,
+            vec![MemoryWrite {
+                addr: 0x0300_0000,
+                width: 4,
+                value: 0x42
+            }]
+        );
+        assert!(report.cycles > 0);
+
+        let report = gba.cpu.step_debug();
+        assert_eq!(
+            report.registers,
+            vec![RegisterChange {
+                reg: 2,
+                old: 0,
+                new: 0x0300_0000 - 0x42
+            }]
+        );
+        assert_eq!(
+            report.flags,
+            vec![FlagChange {
+                flag: 'C',
+                new: true
+            }]
+        );
+        assert!(report.writes.is_empty());
     }
 
     #[test]
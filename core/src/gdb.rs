@@ -1,12 +1,9 @@
-use super::arm7tdmi::CpuState;
-use super::core::GameBoyAdvance;
-use super::interrupt::*;
-use super::iodev::IoDevices;
-use super::sysbus::SysBus;
+use super::arm7tdmi::hooks::{HookResult, WatchKind};
+use super::gba::GameBoyAdvance;
 use super::Bus;
 
 use byteorder::{LittleEndian, ReadBytesExt};
-use gdbstub::{Access, Target, TargetState};
+use gdbstub::{Access, AccessKind, Target, TargetState};
 
 use std::io::Cursor;
 
@@ -16,55 +13,30 @@ impl Target for GameBoyAdvance {
 
     fn step(
         &mut self,
-        mut _log_mem_access: impl FnMut(Access<u32>),
+        mut log_mem_access: impl FnMut(Access<u32>),
     ) -> Result<TargetState, Self::Error> {
-        static mut S_TOTAL_CYCLES: usize = 0;
-
-        let io = unsafe {
-            let ptr = &mut *self.sysbus as *mut SysBus;
-            &mut (*ptr).io as &mut IoDevices
-        };
-
-        // clear any pending DMAs
-        let mut irqs = IrqBitmask(0);
-        while io.dmac.is_active() {
-            io.dmac.perform_work(&mut self.sysbus, &mut irqs);
-        }
-        io.intc.request_irqs(irqs);
-
-        // run the CPU, ignore haltcnt
-        let cycles = self.step_cpu(io);
-        io.timers.update(cycles, &mut self.sysbus, &mut irqs);
-
-        unsafe {
-            S_TOTAL_CYCLES += cycles;
+        // gdbstub checks its own breakpoints against `read_pc`, watchpoints set from the REPL are
+        // reported as memory accesses, one per byte of the access
+        if let Some(HookResult::Watchpoint {
+            kind,
+            addr,
+            width,
+            value,
+            ..
+        }) = self.step_debugger()
+        {
+            for i in 0..width {
+                log_mem_access(Access {
+                    kind: match kind {
+                        WatchKind::Read => AccessKind::Read,
+                        _ => AccessKind::Write,
+                    },
+                    addr: addr.wrapping_add(i),
+                    val: (value >> (8 * i)) as u8,
+                });
+            }
         }
 
-        if self.cycles_to_next_event <= unsafe { S_TOTAL_CYCLES } {
-            let mut cycles_to_next_event = std::usize::MAX;
-            io.gpu.update(
-                unsafe { S_TOTAL_CYCLES },
-                &mut self.sysbus,
-                &mut irqs,
-                &mut cycles_to_next_event,
-                &self.video_device,
-            );
-            io.sound.update(
-                unsafe { S_TOTAL_CYCLES },
-                &mut cycles_to_next_event,
-                &self.audio_device,
-            );
-            self.cycles_to_next_event = cycles_to_next_event;
-
-            unsafe {
-                S_TOTAL_CYCLES = 0;
-            };
-        } else {
-            self.cycles_to_next_event -= unsafe { S_TOTAL_CYCLES };
-        }
-
-        io.intc.request_irqs(irqs);
-
         Ok(TargetState::Running)
     }
 
@@ -109,10 +81,7 @@ impl Target for GameBoyAdvance {
 
         self.cpu.cpsr.set(rdr.read_u32::<LittleEndian>().unwrap());
 
-        match self.cpu.cpsr.state() {
-            CpuState::ARM => self.cpu.reload_pipeline32(&mut self.sysbus),
-            CpuState::THUMB => self.cpu.reload_pipeline16(&mut self.sysbus),
-        };
+        self.cpu.reload_pipeline();
     }
 
     fn target_description_xml() -> Option<&'static str> {
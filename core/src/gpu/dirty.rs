@@ -0,0 +1,67 @@
+//! Tracks the writes to palette RAM and OAM, so what the gpu derives from them is only
+//! recomputed when they change. Static scenes (menus, dialogues) don't write them at all.
+
+#[derive(Debug, Clone)]
+pub struct DirtyFlags {
+    palette: bool,
+    oam: bool,
+}
+
+/// Everything starts dirty, also after loading a save state
+impl Default for DirtyFlags {
+    fn default() -> DirtyFlags {
+        DirtyFlags {
+            palette: true,
+            oam: true,
+        }
+    }
+}
+
+impl DirtyFlags {
+    #[inline]
+    pub fn mark_palette(&mut self) {
+        self.palette = true;
+    }
+
+    #[inline]
+    pub fn mark_oam(&mut self) {
+        self.oam = true;
+    }
+
+    pub fn mark_all(&mut self) {
+        *self = DirtyFlags::default();
+    }
+
+    /// Returns whether the palette RAM was written since the last call
+    #[inline]
+    pub fn take_palette(&mut self) -> bool {
+        std::mem::replace(&mut self.palette, false)
+    }
+
+    /// Returns whether OAM was written since the last call
+    #[inline]
+    pub fn take_oam(&mut self) -> bool {
+        std::mem::replace(&mut self.oam, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirty_flags() {
+        let mut dirty = DirtyFlags::default();
+        assert!(dirty.take_palette());
+        assert!(dirty.take_oam());
+        assert!(!dirty.take_palette());
+        assert!(!dirty.take_oam());
+
+        dirty.mark_oam();
+        assert!(!dirty.take_palette());
+        assert!(dirty.take_oam());
+
+        dirty.mark_all();
+        assert!(dirty.take_palette());
+    }
+}
@@ -0,0 +1,105 @@
+use std::str::FromStr;
+
+use crate::util::{now, Instant};
+
+use super::CYCLES_FULL_REFRESH;
+
+/// Duration of a frame on the hardware, in seconds
+const FRAME_DURATION: f64 = CYCLES_FULL_REFRESH as f64 / 16_777_216.0;
+/// Auto frameskip never drops more frames than this in a row, so the screen keeps moving
+const MAX_AUTO_SKIP: u32 = 4;
+/// How far behind auto frameskip remembers being, in seconds
+const MAX_LAG: f64 = 0.1;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameSkip {
+    Off,
+    /// Skips this many frames after each rendered frame
+    Fixed(u32),
+    /// Skips frames while the emulation runs slower than the hardware
+    Auto,
+}
+
+impl Default for FrameSkip {
+    fn default() -> FrameSkip {
+        FrameSkip::Off
+    }
+}
+
+impl FromStr for FrameSkip {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FrameSkip, String> {
+        match s {
+            "off" | "0" => Ok(FrameSkip::Off),
+            "auto" => Ok(FrameSkip::Auto),
+            n => n
+                .parse()
+                .map(FrameSkip::Fixed)
+                .map_err(|_| format!("invalid frameskip {:?}, expected off, auto or a number", s)),
+        }
+    }
+}
+
+/// Decides which frames the gpu composes
+#[derive(Debug, Clone, Default)]
+pub struct FrameSkipper {
+    mode: FrameSkip,
+    skipped_in_a_row: u32,
+    last_frame: Option<Instant>,
+    /// How far the emulation is behind the hardware, in seconds
+    lag: f64,
+}
+
+impl FrameSkipper {
+    pub fn mode(&self) -> FrameSkip {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FrameSkip) {
+        self.mode = mode;
+        self.skipped_in_a_row = 0;
+        self.last_frame = None;
+        self.lag = 0.0;
+    }
+
+    fn is_lagging(&mut self) -> bool {
+        let frame_start = now();
+        if let Some(last_frame) = self.last_frame {
+            let elapsed = frame_start.duration_since(last_frame).as_secs_f64();
+            self.lag = (self.lag + elapsed - FRAME_DURATION).max(0.0).min(MAX_LAG);
+        }
+        self.last_frame = Some(frame_start);
+        self.lag > FRAME_DURATION
+    }
+
+    /// Called when a frame starts, returns true if it should not be composed
+    pub fn next_frame(&mut self) -> bool {
+        let skip = match self.mode {
+            FrameSkip::Off => false,
+            FrameSkip::Fixed(frames) => self.skipped_in_a_row < frames,
+            FrameSkip::Auto => self.is_lagging() && self.skipped_in_a_row < MAX_AUTO_SKIP,
+        };
+        if skip {
+            self.skipped_in_a_row += 1;
+        } else {
+            self.skipped_in_a_row = 0;
+        }
+        skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_frameskip() {
+        let mut skipper = FrameSkipper::default();
+        assert!(!skipper.next_frame());
+
+        skipper.set_mode("2".parse().unwrap());
+        let frames: Vec<bool> = (0..6).map(|_| skipper.next_frame()).collect();
+        assert_eq!(frames, vec![true, true, false, true, true, false]);
+    }
+}
@@ -1,5 +1,7 @@
 #[cfg(not(feature = "no_video_interface"))]
 use std::cell::RefCell;
+use std::io;
+use std::ops::{Deref, DerefMut};
 #[cfg(not(feature = "no_video_interface"))]
 use std::rc::Rc;
 
@@ -12,21 +14,32 @@ use super::sched::*;
 pub use super::sysbus::consts::*;
 #[cfg(not(feature = "no_video_interface"))]
 use super::VideoInterface;
+use crate::logging;
 
 use crate::num::FromPrimitive;
 
 mod render;
 
+use render::obj::CachedObj;
 use render::Point;
 
+mod dirty;
+mod frameskip;
 mod layer;
 mod mosaic;
 mod rgb15;
+mod scanline_trace;
 mod sfx;
 mod window;
+mod worker;
 
+pub use dirty::DirtyFlags;
+pub use frameskip::FrameSkip;
+use frameskip::FrameSkipper;
 pub use rgb15::Rgb15;
+pub use scanline_trace::{IoWriteRecord, ScanlineTrace};
 pub use window::*;
+use worker::{PendingWrites, RenderWorker};
 
 pub mod regs;
 pub use regs::*;
@@ -67,6 +80,38 @@ pub enum PixelFormat {
     BPP8 = 1,
 }
 
+/// The color format of the frame buffer handed to the frontend
+#[derive(SmartDefault, Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    /// 32bit 0x00RRGGBB, the default
+    #[default]
+    Xrgb8888,
+    /// 32bit, R, G, B and A bytes in memory, e.g for a canvas ImageData
+    Rgba8888,
+    /// 32bit, B, G, R and A bytes in memory
+    Bgra8888,
+    /// 16bit RRRRRGGGGGGBBBBB, saves a conversion pass for libretro and embedded frontends
+    Rgb565,
+}
+
+impl OutputFormat {
+    /// Converts `color` to this format, the 16bit format in the low half
+    pub fn convert(self, color: Rgb15) -> u32 {
+        match self {
+            OutputFormat::Xrgb8888 => color.to_rgb24(),
+            OutputFormat::Rgba8888 => color.to_rgba8888(),
+            OutputFormat::Bgra8888 => color.to_bgra8888(),
+            OutputFormat::Rgb565 => color.to_rgb565() as u32,
+        }
+    }
+
+    /// Frames in this format are drawn to `Gpu::get_frame_buffer_rgb565` instead of
+    /// `Gpu::get_frame_buffer`
+    pub fn is_16bit(self) -> bool {
+        self == OutputFormat::Rgb565
+    }
+}
+
 #[derive(Debug, Default, Copy, Clone)]
 pub struct AffineMatrix {
     pub pa: i32,
@@ -109,22 +154,15 @@ impl Default for ObjBufferEntry {
 #[cfg(not(feature = "no_video_interface"))]
 type VideoDeviceRcRefCell = Rc<RefCell<dyn VideoInterface>>;
 
+/// The gpu state the scanlines are drawn from, and the buffers they are drawn to.
+///
+/// It has no connection to the rest of the system, so a copy of it can compose the scanlines on
+/// another thread, see `Gpu::set_threaded_rendering`.
 #[derive(Serialize, Deserialize, Clone, DebugStub)]
-pub struct Gpu {
-    interrupt_flags: SharedInterruptFlags,
-
-    /// When deserializing this struct using serde, make sure to call connect_scheduler
-    #[serde(skip)]
-    #[serde(default = "Scheduler::new_shared")]
-    scheduler: SharedScheduler,
-
-    /// how many cycles left until next gpu state ?
-    cycles_left_for_current_state: usize,
-
+pub struct Renderer {
     // registers
     pub vcount: usize, // VCOUNT
     pub dispcnt: DisplayControl,
-    pub dispstat: DisplayStatus,
 
     pub bgcnt: [BgControl; 4],
     pub bg_vofs: [u16; 4],
@@ -138,13 +176,74 @@ pub struct Gpu {
     pub bldcnt: BlendControl,
     pub bldalpha: BlendAlpha,
     pub bldy: u16,
+    /// Undocumented GREENSWAP, swaps the green components of every pair of adjacent pixels
+    pub green_swap: bool,
     pub palette_ram: Box<[u8]>,
     pub vram: Box<[u8]>,
     pub oam: Box<[u8]>,
+    /// Writes to palette RAM and OAM since the caches below were updated
+    #[serde(skip)]
+    dirty: DirtyFlags,
+    /// Writes to palette RAM, VRAM and OAM not sent to the render worker yet
+    #[serde(skip)]
+    pending_writes: PendingWrites,
+    /// Palette RAM converted to colors
+    #[serde(skip)]
+    #[serde(default = "alloc_palette_cache")]
+    pub(super) palette_cache: Box<[Rgb15]>,
+    /// The objects that aren't hidden, in OAM order
+    #[serde(skip)]
+    pub(super) obj_cache: Vec<CachedObj>,
     pub(super) vram_obj_tiles_start: u32,
     pub(super) obj_buffer: Box<[ObjBufferEntry]>,
     pub(super) frame_buffer: Box<[u32]>,
+    #[serde(skip)]
+    pub(super) frame_buffer_rgb565: Box<[u16]>,
+    #[serde(skip)]
+    pub(super) output_format: OutputFormat,
+    /// Every 15bit color converted to the output format
+    #[serde(skip)]
+    #[serde(default = "alloc_color_lut")]
+    color_lut: Box<[u32]>,
+    /// Drop the objects that exceed the per-scanline rendering budget, like the hardware does
+    #[serde(skip)]
+    pub(super) obj_cycle_limit: bool,
     pub(super) bg_line: [Box<[Rgb15]>; 4],
+    /// The composed scanline, kept until the green components are swapped
+    #[serde(skip)]
+    #[serde(default = "alloc_scanline_buffer")]
+    pub(super) green_swap_line: Box<[Rgb15]>,
+}
+
+#[derive(Serialize, Deserialize, Clone, DebugStub)]
+pub struct Gpu {
+    interrupt_flags: SharedInterruptFlags,
+
+    /// When deserializing this struct using serde, make sure to call connect_scheduler
+    #[serde(skip)]
+    #[serde(default = "Scheduler::new_shared")]
+    scheduler: SharedScheduler,
+
+    /// how many cycles left until next gpu state ?
+    cycles_left_for_current_state: usize,
+
+    pub dispstat: DisplayStatus,
+    renderer: Renderer,
+    /// Composes the scanlines when threaded rendering is on
+    #[serde(skip)]
+    #[debug_stub = "Option<RenderWorker>"]
+    worker: WorkerSlot,
+    #[serde(skip)]
+    frameskip: FrameSkipper,
+    /// Set for the frames the frame skipper dropped, their scanlines are not composed
+    #[serde(skip)]
+    skipping_frame: bool,
+    /// Whether the last frame that reached vblank was skipped
+    #[serde(skip)]
+    last_frame_skipped: bool,
+
+    #[serde(skip)]
+    pub scanline_trace: ScanlineTrace,
 }
 
 impl InterruptConnect for Gpu {
@@ -159,19 +258,34 @@ impl SchedulerConnect for Gpu {
     }
 }
 
-impl Gpu {
-    pub fn new(mut scheduler: SharedScheduler, interrupt_flags: SharedInterruptFlags) -> Gpu {
-        scheduler.push_gpu_event(GpuEvent::HDraw, CYCLES_HDRAW);
+/// The render worker isn't part of the state, a copy of the gpu renders on the emulation thread
+#[derive(Default)]
+struct WorkerSlot(Option<RenderWorker>);
 
-        fn alloc_scanline_buffer() -> Box<[Rgb15]> {
-            vec![Rgb15::TRANSPARENT; DISPLAY_WIDTH].into_boxed_slice()
-        }
+impl Clone for WorkerSlot {
+    fn clone(&self) -> WorkerSlot {
+        WorkerSlot(None)
+    }
+}
 
-        Gpu {
-            interrupt_flags,
-            scheduler,
+impl Deref for Gpu {
+    type Target = Renderer;
+
+    fn deref(&self) -> &Renderer {
+        &self.renderer
+    }
+}
+
+impl DerefMut for Gpu {
+    fn deref_mut(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+}
+
+impl Renderer {
+    fn new() -> Renderer {
+        Renderer {
             dispcnt: DisplayControl::from(0x80),
-            dispstat: Default::default(),
             bgcnt: Default::default(),
             bg_vofs: [0; 4],
             bg_hofs: [0; 4],
@@ -184,20 +298,29 @@ impl Gpu {
             bldcnt: BlendControl::default(),
             bldalpha: BlendAlpha::default(),
             bldy: 0,
+            green_swap: false,
 
             vcount: 0,
-            cycles_left_for_current_state: CYCLES_HDRAW,
             palette_ram: vec![0; PALETTE_RAM_SIZE].into_boxed_slice(),
             vram: vec![0; VIDEO_RAM_SIZE].into_boxed_slice(),
             oam: vec![0; OAM_SIZE].into_boxed_slice(),
+            dirty: DirtyFlags::default(),
+            pending_writes: PendingWrites::default(),
+            palette_cache: alloc_palette_cache(),
+            obj_cache: Vec::new(),
             obj_buffer: vec![Default::default(); DISPLAY_WIDTH * DISPLAY_HEIGHT].into_boxed_slice(),
             frame_buffer: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT].into_boxed_slice(),
+            frame_buffer_rgb565: Default::default(),
+            output_format: OutputFormat::Xrgb8888,
+            color_lut: alloc_color_lut(),
+            obj_cycle_limit: true,
             bg_line: [
                 alloc_scanline_buffer(),
                 alloc_scanline_buffer(),
                 alloc_scanline_buffer(),
                 alloc_scanline_buffer(),
             ],
+            green_swap_line: alloc_scanline_buffer(),
             vram_obj_tiles_start: VRAM_OBJ_TILES_START_TEXT,
         }
     }
@@ -208,7 +331,11 @@ impl Gpu {
         self.dispcnt.write(value);
         let new_mode = self.dispcnt.mode;
         if old_mode != new_mode {
-            debug!("[GPU] Display mode changed! {} -> {}", old_mode, new_mode);
+            debug!(
+                target: logging::GPU,
+                "[GPU] Display mode changed! {} -> {}",
+                old_mode, new_mode
+            );
             self.vram_obj_tiles_start = if new_mode >= 3 {
                 VRAM_OBJ_TILES_START_BITMAP
             } else {
@@ -257,12 +384,28 @@ impl Gpu {
         if index == 0 || (palette_bank != 0 && index % 16 == 0) {
             return Rgb15::TRANSPARENT;
         }
-        let value = self
-            .palette_ram
-            .read_16(offset + 2 * index + 0x20 * palette_bank);
+        self.palette_cache[(offset / 2 + index + 16 * palette_bank) as usize]
+    }
 
-        // top bit is ignored
-        Rgb15(value & 0x7FFF)
+    /// Converts the palette RAM again if it was written since the last time
+    fn update_palette_cache(&mut self) {
+        if !self.dirty.take_palette() {
+            return;
+        }
+        for (color, bytes) in self
+            .palette_cache
+            .iter_mut()
+            .zip(self.palette_ram.chunks_exact(2))
+        {
+            // top bit is ignored
+            *color = Rgb15(u16::from_le_bytes([bytes[0], bytes[1]]) & 0x7FFF);
+        }
+    }
+
+    /// Must be called after palette RAM, VRAM or OAM are modified without going through the bus
+    pub fn invalidate_caches(&mut self) {
+        self.dirty.mark_all();
+        self.pending_writes.mark_all();
     }
 
     #[inline]
@@ -284,64 +427,70 @@ impl Gpu {
     }
 
     pub fn render_scanline(&mut self) {
+        // the screen is white while forced blank is on, nothing is fetched from VRAM, OAM or the
+        // palette so the cpu can access them with no wait, even during hdraw
         if self.dispcnt.force_blank {
-            for x in self.frame_buffer[self.vcount * DISPLAY_WIDTH..]
-                .iter_mut()
-                .take(DISPLAY_WIDTH)
-            {
-                *x = 0xf8f8f8;
+            let line_start = self.vcount * DISPLAY_WIDTH;
+            for i in line_start..line_start + DISPLAY_WIDTH {
+                self.write_pixel(i, Rgb15::WHITE);
             }
             return;
         }
 
+        self.update_palette_cache();
         if self.dispcnt.enable_obj {
             self.render_objs();
         }
         match self.dispcnt.mode {
             0 => {
                 for bg in 0..=3 {
-                    if self.dispcnt.enable_bg[bg] {
-                        self.render_reg_bg(bg);
-                    }
+                    self.render_bg(bg, Renderer::render_reg_bg);
                 }
                 self.finalize_scanline(0, 3);
             }
             1 => {
-                if self.dispcnt.enable_bg[2] {
-                    self.render_aff_bg(2);
-                }
-                if self.dispcnt.enable_bg[1] {
-                    self.render_reg_bg(1);
-                }
-                if self.dispcnt.enable_bg[0] {
-                    self.render_reg_bg(0);
-                }
+                self.render_bg(2, Renderer::render_aff_bg);
+                self.render_bg(1, Renderer::render_reg_bg);
+                self.render_bg(0, Renderer::render_reg_bg);
                 self.finalize_scanline(0, 2);
             }
             2 => {
-                if self.dispcnt.enable_bg[3] {
-                    self.render_aff_bg(3);
-                }
-                if self.dispcnt.enable_bg[2] {
-                    self.render_aff_bg(2);
-                }
+                self.render_bg(3, Renderer::render_aff_bg);
+                self.render_bg(2, Renderer::render_aff_bg);
                 self.finalize_scanline(2, 3);
             }
             3 => {
-                self.render_mode3(2);
+                self.render_bg(2, Renderer::render_mode3);
                 self.finalize_scanline(2, 2);
             }
             4 => {
-                self.render_mode4(2);
+                self.render_bg(2, Renderer::render_mode4);
                 self.finalize_scanline(2, 2);
             }
             5 => {
-                self.render_mode5(2);
+                self.render_bg(2, Renderer::render_mode5);
                 self.finalize_scanline(2, 2);
             }
             _ => panic!("{:?} not supported", self.dispcnt.mode),
         }
-        // self.mosaic_sfx();
+        if self.green_swap {
+            self.flush_green_swap_line();
+        }
+    }
+
+    /// Writes the composed scanline to the frame buffer, with the green components of pixels
+    /// 2n and 2n+1 exchanged
+    fn flush_green_swap_line(&mut self) {
+        let line_start = self.vcount * DISPLAY_WIDTH;
+        for x in (0..DISPLAY_WIDTH).step_by(2) {
+            let mut left = self.green_swap_line[x];
+            let mut right = self.green_swap_line[x + 1];
+            let g = left.g();
+            left.set_g(right.g());
+            right.set_g(g);
+            self.write_pixel(line_start + x, left);
+            self.write_pixel(line_start + x + 1, right);
+        }
     }
 
     /// Clears the gpu obj buffer
@@ -355,11 +504,125 @@ impl Gpu {
         &self.frame_buffer
     }
 
+    /// The RGB565 frame buffer, only drawn to when the output format is `OutputFormat::Rgb565`,
+    /// the other formats are drawn to `get_frame_buffer`
+    pub fn get_frame_buffer_rgb565(&self) -> &[u16] {
+        &self.frame_buffer_rgb565
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    pub fn obj_cycle_limit(&self) -> bool {
+        self.obj_cycle_limit
+    }
+
+    /// Turning the limit off renders every object, at the cost of accuracy
+    /// (games that rely on sprite dropout will show sprites that should be hidden).
+    pub fn set_obj_cycle_limit(&mut self, enabled: bool) {
+        self.obj_cycle_limit = enabled;
+    }
+
+    /// Selects which frame buffer the renderer draws to.
+    /// Only one of the buffers is kept up to date, so there is no conversion pass at vblank.
+    pub fn set_output_format(&mut self, output_format: OutputFormat) {
+        if output_format != self.output_format {
+            self.color_lut = build_color_lut(output_format);
+        }
+        self.output_format = output_format;
+        if output_format.is_16bit() && self.frame_buffer_rgb565.is_empty() {
+            self.frame_buffer_rgb565 = vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT].into_boxed_slice();
+        }
+    }
+
+    #[inline]
+    pub(super) fn write_pixel(&mut self, index: usize, color: Rgb15) {
+        let color = self.color_lut[(color.0 & 0x7fff) as usize];
+        if self.output_format.is_16bit() {
+            self.frame_buffer_rgb565[index] = color as u16;
+        } else {
+            self.frame_buffer[index] = color;
+        }
+    }
+}
+
+impl Gpu {
+    pub fn new(mut scheduler: SharedScheduler, interrupt_flags: SharedInterruptFlags) -> Gpu {
+        scheduler.push_gpu_event(GpuEvent::HDraw, CYCLES_HDRAW);
+
+        Gpu {
+            interrupt_flags,
+            scheduler,
+            cycles_left_for_current_state: CYCLES_HDRAW,
+            dispstat: Default::default(),
+            renderer: Renderer::new(),
+            worker: WorkerSlot::default(),
+            frameskip: FrameSkipper::default(),
+            skipping_frame: false,
+            last_frame_skipped: false,
+            scanline_trace: ScanlineTrace::default(),
+        }
+    }
+
+    pub fn frameskip(&self) -> FrameSkip {
+        self.frameskip.mode()
+    }
+
+    /// Frames that are skipped still run, with the same timing, but nothing is drawn for them and
+    /// the video device isn't called at their vblank
+    pub fn set_frameskip(&mut self, mode: FrameSkip) {
+        self.frameskip.set_mode(mode);
+    }
+
+    /// Returns true if the frame currently being drawn (or the last one, during vblank) is skipped
+    pub fn is_skipping_frame(&self) -> bool {
+        self.skipping_frame
+    }
+
+    /// Returns true if the last completed frame was skipped. Unlike `is_skipping_frame`, this
+    /// doesn't change when the next frame starts, so it still holds after `GameBoyAdvance::frame`.
+    pub fn last_frame_skipped(&self) -> bool {
+        self.last_frame_skipped
+    }
+
+    /// Composes the scanlines on a worker thread, while the emulation goes on with the next ones.
+    ///
+    /// Every line is still drawn from the registers and memory as they were when it started, but
+    /// the frame buffers are only updated at vblank, and the lines aren't drawn yet when the
+    /// hblank that follows them is reached.
+    pub fn set_threaded_rendering(&mut self, enabled: bool) -> io::Result<()> {
+        match (enabled, self.worker.0.take()) {
+            (true, None) => self.worker.0 = Some(RenderWorker::start(&mut self.renderer)?),
+            (false, Some(worker)) => worker.stop(&mut self.renderer),
+            (_, worker) => self.worker.0 = worker,
+        }
+        Ok(())
+    }
+
+    pub fn is_threaded_rendering(&self) -> bool {
+        self.worker.0.is_some()
+    }
+
+    /// Draws the current line, or has the render worker draw it
+    fn render_line(&mut self) {
+        match self.worker.0 {
+            Some(ref mut worker) => worker.render_line(&mut self.renderer),
+            None => self.renderer.render_scanline(),
+        }
+    }
+
+    /// Writing DISPSTAT updates the V-Counter flag right away for the new VCOUNT setting
+    pub fn write_dispstat(&mut self, value: u16) {
+        self.dispstat.write(value);
+        self.dispstat.vcount_flag = self.dispstat.vcount_setting == self.renderer.vcount;
+    }
+
     #[inline]
     fn update_vcount(&mut self, value: usize) {
-        self.vcount = value;
+        self.renderer.vcount = value;
         let vcount_setting = self.dispstat.vcount_setting;
-        self.dispstat.vcount_flag = vcount_setting == self.vcount;
+        self.dispstat.vcount_flag = vcount_setting == value;
 
         if self.dispstat.vcount_irq_enable && self.dispstat.vcount_flag {
             interrupt::signal_irq(&self.interrupt_flags, Interrupt::LCD_VCounterMatch);
@@ -383,37 +646,57 @@ impl Gpu {
         dma_notifier: &mut D,
         #[cfg(not(feature = "no_video_interface"))] video_device: &VideoDeviceRcRefCell,
     ) -> (GpuEvent, usize) {
-        self.update_vcount(self.vcount + 1);
+        self.update_vcount(self.renderer.vcount + 1);
 
-        if self.vcount < DISPLAY_HEIGHT {
+        if self.renderer.vcount < DISPLAY_HEIGHT {
             self.dispstat.hblank_flag = false;
-            self.render_scanline();
+            if !self.skipping_frame {
+                self.render_line();
+            }
             // update BG2/3 reference points on the end of a scanline
-            for i in 0..2 {
-                self.bg_aff[i].internal_x += self.bg_aff[i].pb as i16 as i32;
-                self.bg_aff[i].internal_y += self.bg_aff[i].pd as i16 as i32;
+            for aff in self.renderer.bg_aff.iter_mut() {
+                aff.internal_x += aff.pb as i16 as i32;
+                aff.internal_y += aff.pd as i16 as i32;
             }
 
             (GpuEvent::HDraw, CYCLES_HDRAW)
         } else {
             // latch BG2/3 reference points on vblank
-            for i in 0..2 {
-                self.bg_aff[i].internal_x = self.bg_aff[i].x;
-                self.bg_aff[i].internal_y = self.bg_aff[i].y;
+            for aff in self.renderer.bg_aff.iter_mut() {
+                aff.internal_x = aff.x;
+                aff.internal_y = aff.y;
             }
 
             self.dispstat.vblank_flag = true;
             self.dispstat.hblank_flag = false;
+            self.last_frame_skipped = self.skipping_frame;
             if self.dispstat.vblank_irq_enable {
                 interrupt::signal_irq(&self.interrupt_flags, Interrupt::LCD_VBlank);
             };
 
             dma_notifier.notify(TIMING_VBLANK);
 
-            #[cfg(not(feature = "no_video_interface"))]
-            video_device.borrow_mut().render(&self.frame_buffer);
+            match self.worker.0 {
+                // the worker clears its own obj buffer
+                Some(ref mut worker) if !self.skipping_frame => {
+                    worker.finish_frame(&mut self.renderer)
+                }
+                Some(_) => {}
+                None => self.renderer.obj_buffer_reset(),
+            }
 
-            self.obj_buffer_reset();
+            #[cfg(not(feature = "no_video_interface"))]
+            {
+                if !self.skipping_frame {
+                    if self.output_format.is_16bit() {
+                        video_device
+                            .borrow_mut()
+                            .render_rgb565(&self.frame_buffer_rgb565)
+                    } else {
+                        video_device.borrow_mut().render(&self.frame_buffer)
+                    }
+                }
+            }
 
             (GpuEvent::VBlankHDraw, CYCLES_HDRAW)
         }
@@ -428,15 +711,23 @@ impl Gpu {
     }
 
     fn handle_vblank_hblank_end(&mut self) -> (GpuEvent, usize) {
-        if self.vcount < DISPLAY_HEIGHT + VBLANK_LINES - 1 {
-            self.update_vcount(self.vcount + 1);
+        let vcount = self.renderer.vcount;
+        if vcount < DISPLAY_HEIGHT + VBLANK_LINES - 1 {
+            self.update_vcount(vcount + 1);
             self.dispstat.hblank_flag = false;
+            // the vblank flag is cleared for the last line of vblank (227)
+            if vcount + 1 == DISPLAY_HEIGHT + VBLANK_LINES - 1 {
+                self.dispstat.vblank_flag = false;
+            }
             (GpuEvent::VBlankHDraw, CYCLES_HDRAW)
         } else {
+            self.scanline_trace.end_frame();
             self.update_vcount(0);
-            self.dispstat.vblank_flag = false;
             self.dispstat.hblank_flag = false;
-            self.render_scanline();
+            self.skipping_frame = self.frameskip.next_frame();
+            if !self.skipping_frame {
+                self.render_line();
+            }
             (GpuEvent::HDraw, CYCLES_HDRAW)
         }
     }
@@ -465,36 +756,57 @@ impl Gpu {
     }
 }
 
-impl Bus for Gpu {
+fn build_color_lut(format: OutputFormat) -> Box<[u32]> {
+    (0..0x8000).map(|c| format.convert(Rgb15(c))).collect()
+}
+
+fn alloc_color_lut() -> Box<[u32]> {
+    build_color_lut(OutputFormat::default())
+}
+
+fn alloc_scanline_buffer() -> Box<[Rgb15]> {
+    vec![Rgb15::TRANSPARENT; DISPLAY_WIDTH].into_boxed_slice()
+}
+
+fn alloc_palette_cache() -> Box<[Rgb15]> {
+    vec![Rgb15::BLACK; PALETTE_RAM_SIZE / 2].into_boxed_slice()
+}
+
+/// VRAM is 96K, mirrored in 128K steps where the upper 32K mirror the 32K OBJ area (06010000-06017FFF)
+#[inline]
+fn vram_offset(addr: Addr) -> u32 {
+    let ofs = addr & 0x1_ffff;
+    if ofs >= 0x1_8000 {
+        ofs - 0x8000
+    } else {
+        ofs
+    }
+}
+
+impl Bus for Renderer {
     fn read_8(&mut self, addr: Addr) -> u8 {
         let page = (addr >> 24) as usize;
         match page {
             PAGE_PALRAM => self.palette_ram.read_8(addr & 0x3ff),
-            PAGE_VRAM => {
-                // complicated
-                let mut ofs = addr & ((VIDEO_RAM_SIZE as u32) - 1);
-                if ofs > 0x18000 {
-                    ofs -= 0x8000;
-                }
-                self.vram.read_8(ofs)
-            }
+            PAGE_VRAM => self.vram.read_8(vram_offset(addr)),
             PAGE_OAM => self.oam.read_8(addr & 0x3ff),
             _ => unreachable!(),
         }
     }
 
     fn write_16(&mut self, addr: Addr, value: u16) {
+        self.pending_writes.push(addr, value);
         let page = (addr >> 24) as usize;
         match page {
-            PAGE_PALRAM => self.palette_ram.write_16(addr & 0x3fe, value),
-            PAGE_VRAM => {
-                let mut ofs = addr & ((VIDEO_RAM_SIZE as u32) - 1);
-                if ofs > 0x18000 {
-                    ofs -= 0x8000;
-                }
-                self.vram.write_16(ofs, value)
+            PAGE_PALRAM => {
+                self.dirty.mark_palette();
+                self.palette_ram.write_16(addr & 0x3fe, value)
+            }
+            PAGE_VRAM => self.vram.write_16(vram_offset(addr), value),
+            PAGE_OAM => {
+                self.dirty.mark_oam();
+                self.oam.write_16(addr & 0x3fe, value)
             }
-            PAGE_OAM => self.oam.write_16(addr & 0x3fe, value),
             _ => unreachable!(),
         }
     }
@@ -506,13 +818,15 @@ impl Bus for Gpu {
 
         let page = (addr >> 24) as usize;
         match page {
-            PAGE_PALRAM => self.palette_ram.write_16(addr & 0x3fe, expand_value(value)),
+            PAGE_PALRAM => {
+                self.dirty.mark_palette();
+                self.pending_writes.push(addr, expand_value(value));
+                self.palette_ram.write_16(addr & 0x3fe, expand_value(value))
+            }
             PAGE_VRAM => {
-                let mut ofs = addr & ((VIDEO_RAM_SIZE as u32) - 1);
-                if ofs > 0x18000 {
-                    ofs -= 0x8000;
-                }
+                let ofs = vram_offset(addr);
                 if ofs < self.vram_obj_tiles_start {
+                    self.pending_writes.push(addr, expand_value(value));
                     self.vram.write_16(ofs & !1, expand_value(value));
                 }
             }
@@ -522,12 +836,12 @@ impl Bus for Gpu {
     }
 }
 
-impl DebugRead for Gpu {
+impl DebugRead for Renderer {
     fn debug_read_8(&mut self, addr: Addr) -> u8 {
         let page = (addr >> 24) as usize;
         match page {
             PAGE_PALRAM => self.palette_ram.read_8(addr & 0x3ff),
-            PAGE_VRAM => self.vram.read_8(addr & ((VIDEO_RAM_SIZE as u32) - 1)),
+            PAGE_VRAM => self.vram.read_8(vram_offset(addr)),
             PAGE_OAM => self.oam.read_8(addr & 0x3ff),
             _ => unreachable!(),
         }
@@ -628,8 +942,10 @@ mod tests {
 
         for line in 0..68 {
             println!("line = {}", 160 + line);
+            // the vblank flag isn't set on the last line of vblank
+            let vblank_flag = line != 67;
             assert_eq!(gpu.dispstat.hblank_flag, false);
-            assert_eq!(gpu.dispstat.vblank_flag, true);
+            assert_eq!(gpu.dispstat.vblank_flag, vblank_flag);
             assert_eq!(
                 sched.peek_next(),
                 Some(EventType::Gpu(GpuEvent::VBlankHDraw))
@@ -638,7 +954,7 @@ mod tests {
             update!(CYCLES_HDRAW);
 
             assert_eq!(gpu.dispstat.hblank_flag, true);
-            assert_eq!(gpu.dispstat.vblank_flag, true);
+            assert_eq!(gpu.dispstat.vblank_flag, vblank_flag);
             assert_eq!(
                 sched.peek_next(),
                 Some(EventType::Gpu(GpuEvent::VBlankHBlank))
@@ -659,4 +975,35 @@ mod tests {
         assert_eq!(gpu.dispstat.vcount_flag, true);
         assert_eq!(gpu.dispstat.hblank_flag, false);
     }
+
+    #[test]
+    fn test_green_swap_and_forced_blank() {
+        let mut renderer = Renderer::new();
+        // mode 3 with BG2 on
+        renderer.dispcnt = DisplayControl::from(0x0403);
+        renderer.vcount = 5;
+        let red = Rgb15::from_rgb(31, 0, 0);
+        let green = Rgb15::from_rgb(0, 31, 0);
+        let line = 5 * DISPLAY_WIDTH;
+        renderer.vram.write_16(line as u32 * 2, red.0);
+        renderer.vram.write_16(line as u32 * 2 + 2, green.0);
+
+        renderer.render_scanline();
+        assert_eq!(renderer.frame_buffer[line], red.to_rgb24());
+        assert_eq!(renderer.frame_buffer[line + 1], green.to_rgb24());
+
+        renderer.green_swap = true;
+        renderer.render_scanline();
+        assert_eq!(
+            renderer.frame_buffer[line],
+            Rgb15::from_rgb(31, 31, 0).to_rgb24()
+        );
+        assert_eq!(renderer.frame_buffer[line + 1], Rgb15::BLACK.to_rgb24());
+
+        renderer.dispcnt.force_blank = true;
+        renderer.render_scanline();
+        assert!(renderer.frame_buffer[line..line + DISPLAY_WIDTH]
+            .iter()
+            .all(|&c| c == Rgb15::WHITE.to_rgb24()));
+    }
 }
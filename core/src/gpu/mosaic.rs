@@ -2,34 +2,40 @@ use super::*;
 use regs::RegMosaic;
 
 impl RegMosaic {
-    fn is_enabled_for_bg(&self) -> bool {
-        (self.bg_hsize() != 0) || (self.bg_vsize() != 0)
+    /// BG mosaic block (width, height) in pixels
+    pub(super) fn bg_size(&self) -> (usize, usize) {
+        (self.bg_hsize() as usize + 1, self.bg_vsize() as usize + 1)
     }
-}
-
-impl Gpu {
-    fn mosaic_bg(&mut self) {
-        // let hsize = (self.mosaic.bg_hsize() + 1) as usize;
-        // let vsize = (self.mosaic.bg_vsize() + 1) as usize;
 
-        // for bg in 0..4 {
-        //     if self.dispcnt.enable_bg[bg] && self.backgrounds[bg].mosaic {
-        //         let y = self.vcount as usize;
-        //         if y % vsize == 0 {
-        //             self.backgrounds[bg].mosaic_first_row = self.backgrounds[bg].line.clone();
-        //         }
-        //         for x in 0..DISPLAY_WIDTH {
-        //             let color = self.backgrounds[bg].mosaic_first_row[(x / hsize) * hsize];
-        //             self.backgrounds[bg].line[x] = color;
-        //         }
-        //     }
-        // }
+    /// OBJ mosaic block (width, height) in pixels
+    pub(super) fn obj_size(&self) -> (i32, i32) {
+        (self.obj_hsize() as i32 + 1, self.obj_vsize() as i32 + 1)
     }
+}
 
-    pub fn mosaic_sfx(&mut self) {
-        if self.mosaic.is_enabled_for_bg() {
-            self.mosaic_bg();
+impl Renderer {
+    /// Renders a line of `bg` if it's enabled, applying the mosaic effect when the bg has it set.
+    ///
+    /// Lines inside a mosaic block repeat the first line of the block, so the bg isn't rendered
+    /// for them at all and its line buffer keeps the contents from the previous scanline.
+    pub(super) fn render_bg(&mut self, bg: usize, render: fn(&mut Renderer, usize)) {
+        if !self.dispcnt.enable_bg[bg] {
+            return;
+        }
+        if !self.bgcnt[bg].mosaic {
+            render(self, bg);
+            return;
+        }
+        let (block_w, block_h) = self.mosaic.bg_size();
+        if self.vcount % block_h != 0 {
+            return;
+        }
+        render(self, bg);
+        if block_w > 1 {
+            let line = &mut self.bg_line[bg];
+            for x in 0..DISPLAY_WIDTH {
+                line[x] = line[x - x % block_w];
+            }
         }
-        // TODO obj mosaic
     }
 }
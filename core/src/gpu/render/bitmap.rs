@@ -1,14 +1,23 @@
-//! Rendering for modes 4-5
+//! Rendering for modes 3-5
 
 use super::super::consts::*;
-use super::super::Gpu;
+use super::super::Renderer;
 use super::super::Rgb15;
 
 use super::{utils, MODE5_VIEWPORT, SCREEN_VIEWPORT};
 
 use crate::Bus;
 
-impl Gpu {
+impl Renderer {
+    /// Modes 4 and 5 are double buffered, DISPCNT bit 4 selects the page being displayed
+    fn bitmap_page_ofs(&self) -> u32 {
+        match self.dispcnt.display_frame_select {
+            0 => 0x0600_0000 - VRAM_ADDR,
+            1 => 0x0600_a000 - VRAM_ADDR,
+            _ => unreachable!(),
+        }
+    }
+
     pub(in super::super) fn render_mode3(&mut self, bg: usize) {
         let _y = self.vcount;
 
@@ -16,18 +25,12 @@ impl Gpu {
         let pc = self.bg_aff[bg - 2].pc as i32;
         let ref_point = self.get_ref_point(bg);
 
-        let wraparound = self.bgcnt[bg].affine_wraparound;
-
         for x in 0..DISPLAY_WIDTH {
-            let mut t = utils::transform_bg_point(ref_point, x as i32, pa, pc);
+            let t = utils::transform_bg_point(ref_point, x as i32, pa, pc);
+            // bitmaps never wrap around, the wraparound bit only applies to affine tiled backgrounds
             if !SCREEN_VIEWPORT.contains_point(t) {
-                if wraparound {
-                    t.0 = t.0.rem_euclid(SCREEN_VIEWPORT.w);
-                    t.1 = t.1.rem_euclid(SCREEN_VIEWPORT.h);
-                } else {
-                    self.bg_line[bg][x] = Rgb15::TRANSPARENT;
-                    continue;
-                }
+                self.bg_line[bg][x] = Rgb15::TRANSPARENT;
+                continue;
             }
             let pixel_index = index2d!(u32, t.0, t.1, DISPLAY_WIDTH);
             let pixel_ofs = 2 * pixel_index;
@@ -37,11 +40,7 @@ impl Gpu {
     }
 
     pub(in super::super) fn render_mode4(&mut self, bg: usize) {
-        let page_ofs: u32 = match self.dispcnt.display_frame_select {
-            0 => 0x0600_0000 - VRAM_ADDR,
-            1 => 0x0600_a000 - VRAM_ADDR,
-            _ => unreachable!(),
-        };
+        let page_ofs = self.bitmap_page_ofs();
 
         let _y = self.vcount;
 
@@ -49,18 +48,11 @@ impl Gpu {
         let pc = self.bg_aff[bg - 2].pc as i32;
         let ref_point = self.get_ref_point(bg);
 
-        let wraparound = self.bgcnt[bg].affine_wraparound;
-
         for x in 0..DISPLAY_WIDTH {
-            let mut t = utils::transform_bg_point(ref_point, x as i32, pa, pc);
+            let t = utils::transform_bg_point(ref_point, x as i32, pa, pc);
             if !SCREEN_VIEWPORT.contains_point(t) {
-                if wraparound {
-                    t.0 = t.0.rem_euclid(SCREEN_VIEWPORT.w);
-                    t.1 = t.1.rem_euclid(SCREEN_VIEWPORT.h);
-                } else {
-                    self.bg_line[bg][x] = Rgb15::TRANSPARENT;
-                    continue;
-                }
+                self.bg_line[bg][x] = Rgb15::TRANSPARENT;
+                continue;
             }
             let bitmap_index = index2d!(u32, t.0, t.1, DISPLAY_WIDTH);
             let bitmap_ofs = page_ofs + (bitmap_index as u32);
@@ -71,11 +63,7 @@ impl Gpu {
     }
 
     pub(in super::super) fn render_mode5(&mut self, bg: usize) {
-        let page_ofs: u32 = match self.dispcnt.display_frame_select {
-            0 => 0x0600_0000 - VRAM_ADDR,
-            1 => 0x0600_a000 - VRAM_ADDR,
-            _ => unreachable!(),
-        };
+        let page_ofs = self.bitmap_page_ofs();
 
         let _y = self.vcount;
 
@@ -83,18 +71,11 @@ impl Gpu {
         let pc = self.bg_aff[bg - 2].pc as i32;
         let ref_point = self.get_ref_point(bg);
 
-        let wraparound = self.bgcnt[bg].affine_wraparound;
-
         for x in 0..DISPLAY_WIDTH {
-            let mut t = utils::transform_bg_point(ref_point, x as i32, pa, pc);
+            let t = utils::transform_bg_point(ref_point, x as i32, pa, pc);
             if !MODE5_VIEWPORT.contains_point(t) {
-                if wraparound {
-                    t.0 = t.0.rem_euclid(MODE5_VIEWPORT.w);
-                    t.1 = t.1.rem_euclid(MODE5_VIEWPORT.h);
-                } else {
-                    self.bg_line[bg][x] = Rgb15::TRANSPARENT;
-                    continue;
-                }
+                self.bg_line[bg][x] = Rgb15::TRANSPARENT;
+                continue;
             }
             let pixel_ofs = page_ofs + 2 * index2d!(u32, t.0, t.1, MODE5_VIEWPORT.w);
             let color = Rgb15(self.vram.read_16(pixel_ofs));
@@ -1,14 +1,30 @@
 use super::super::regs::*;
 use super::super::*;
 
+use std::cmp;
+
 const OVRAM: u32 = 0x0601_0000;
 const PALRAM_OFS_FG: u32 = 0x200;
 const ATTRS_SIZE: u32 = 2 * 3 + 2;
 
 struct ObjAttrs(Attribute0, Attribute1, Attribute2);
 
+/// An object of OAM that isn't hidden, with the scanlines its bounding box covers
+#[derive(Debug, Clone, Copy)]
+pub(in super::super) struct CachedObj {
+    num: usize,
+    attrs: [u16; 3],
+    top: i32,
+    height: i32,
+}
+
 const AFFINE_FILL: u32 = 2 * 3;
 
+/// Cycles available for rendering objects on each scanline
+const OBJ_CYCLES_PER_LINE: i32 = 304 * 4 - 6;
+/// When DISPCNT's H-Blank Interval Free bit is set, objects can't use the hblank period
+const OBJ_CYCLES_PER_LINE_HBLANK_FREE: i32 = 240 * 4 - 6;
+
 impl ObjAttrs {
     fn size(&self) -> (i32, i32) {
         match (self.1.size(), self.0.shape()) {
@@ -54,7 +70,12 @@ impl ObjAttrs {
     }
 }
 
-impl Gpu {
+/// Snaps a screen coordinate to the start of its mosaic block, without going past the object's edge
+fn mosaic_coord(coord: i32, block_size: i32, obj_start: i32) -> i32 {
+    cmp::max(coord - coord.rem_euclid(block_size), obj_start)
+}
+
+impl Renderer {
     fn get_affine_matrix(&mut self, affine_index: u32) -> AffineMatrix {
         let mut offset = AFFINE_FILL + affine_index * 16 * 2;
         let pa = self.oam.read_16(offset) as i16 as i32;
@@ -68,6 +89,15 @@ impl Gpu {
         AffineMatrix { pa, pb, pc, pd }
     }
 
+    /// The mosaic block size for `attrs`, 1x1 for objects without the mosaic bit
+    fn obj_mosaic_size(&self, attrs: &ObjAttrs) -> (i32, i32) {
+        if attrs.0.mosaic() {
+            self.mosaic.obj_size()
+        } else {
+            (1, 1)
+        }
+    }
+
     fn read_obj_attrs(&mut self, obj: usize) -> ObjAttrs {
         let addr = ATTRS_SIZE * (obj as u32);
         let attr0 = Attribute0(self.oam.read_16(addr + 0));
@@ -76,6 +106,30 @@ impl Gpu {
         ObjAttrs(attr0, attr1, attr2)
     }
 
+    /// Decodes OAM again if it was written since the last time
+    fn update_obj_cache(&mut self) {
+        if !self.dirty.take_oam() {
+            return;
+        }
+        self.obj_cache.clear();
+        for num in 0..128 {
+            let attrs = self.read_obj_attrs(num);
+            let (_, top) = attrs.coords();
+            let (_, h) = attrs.size();
+            let height = match attrs.0.objtype() {
+                ObjType::Hidden => continue,
+                ObjType::Normal | ObjType::Affine => h,
+                ObjType::AffineDoubleSize => 2 * h,
+            };
+            self.obj_cache.push(CachedObj {
+                num,
+                attrs: [(attrs.0).0, (attrs.1).0, (attrs.2).0],
+                top,
+                height,
+            });
+        }
+    }
+
     fn render_affine_obj(&mut self, attrs: ObjAttrs, _obj_num: usize) {
         let screen_y = self.vcount as i32;
 
@@ -120,11 +174,12 @@ impl Gpu {
         };
 
         let affine_matrix = self.get_affine_matrix(attrs.affine_index());
+        let (mosaic_w, mosaic_h) = self.obj_mosaic_size(&attrs);
 
         let half_width = bbox_w / 2;
         let half_height = bbox_h / 2;
         let screen_width = DISPLAY_WIDTH as i32;
-        let iy = screen_y - (ref_y + half_height);
+        let iy = mosaic_coord(screen_y, mosaic_h, ref_y) - (ref_y + half_height);
 
         macro_rules! render_loop {
             ($read_pixel_index_fn:ident) => {
@@ -145,6 +200,7 @@ impl Gpu {
                         continue;
                     }
 
+                    let ix = mosaic_coord(screen_x, mosaic_w, ref_x) - (ref_x + half_width);
                     let transformed_x = (affine_matrix.pa * ix + affine_matrix.pb * iy) >> 8;
                     let transformed_y = (affine_matrix.pc * ix + affine_matrix.pd * iy) >> 8;
                     let texture_x = transformed_x + obj_w / 2;
@@ -215,6 +271,8 @@ impl Gpu {
             }
         };
 
+        let (mosaic_w, mosaic_h) = self.obj_mosaic_size(&attrs);
+
         // render the pixels
         let screen_width = DISPLAY_WIDTH as i32;
         let end_x = ref_x + obj_w;
@@ -236,8 +294,8 @@ impl Gpu {
                     {
                         continue;
                     }
-                    let mut sprite_y = screen_y - ref_y;
-                    let mut sprite_x = screen_x - ref_x;
+                    let mut sprite_y = mosaic_coord(screen_y, mosaic_h, ref_y) - ref_y;
+                    let mut sprite_x = mosaic_coord(screen_x, mosaic_w, ref_x) - ref_x;
                     sprite_y = if attrs.1.v_flip() {
                         obj_h - sprite_y - 1
                     } else {
@@ -291,9 +349,55 @@ impl Gpu {
         }
     }
 
+    /// Number of cycles it takes to render a line of `obj`, 0 if it isn't on the current scanline
+    fn obj_render_cycles(&self, obj: &ObjAttrs) -> i32 {
+        let (_, y) = obj.coords();
+        let (w, h) = obj.size();
+        let (bbox_h, cycles) = match obj.0.objtype() {
+            ObjType::Hidden => return 0,
+            ObjType::Normal => (h, w),
+            ObjType::Affine => (h, 10 + 2 * w),
+            ObjType::AffineDoubleSize => (2 * h, 10 + 2 * 2 * w),
+        };
+        let screen_y = self.vcount as i32;
+        if screen_y >= y && screen_y < y + bbox_h {
+            cycles
+        } else {
+            0
+        }
+    }
+
     pub(in super::super) fn render_objs(&mut self) {
-        for obj_num in 0..128 {
-            let obj = self.read_obj_attrs(obj_num);
+        let mut cycles_left = if self.dispcnt.hblank_interval_free {
+            OBJ_CYCLES_PER_LINE_HBLANK_FREE
+        } else {
+            OBJ_CYCLES_PER_LINE
+        };
+        self.update_obj_cache();
+        let screen_y = self.vcount as i32;
+        for i in 0..self.obj_cache.len() {
+            let CachedObj {
+                num: obj_num,
+                attrs,
+                top,
+                height,
+            } = self.obj_cache[i];
+            // objects off the scanline don't use any of its budget either
+            if screen_y < top || screen_y >= top + height {
+                continue;
+            }
+            let obj = ObjAttrs(
+                Attribute0(attrs[0]),
+                Attribute1(attrs[1]),
+                Attribute2(attrs[2]),
+            );
+            if self.obj_cycle_limit {
+                // objects that don't fit in the budget of the scanline are dropped
+                cycles_left -= self.obj_render_cycles(&obj);
+                if cycles_left < 0 {
+                    break;
+                }
+            }
             match obj.0.objtype() {
                 ObjType::Hidden => continue,
                 ObjType::Normal => self.render_normal_obj(obj, obj_num),
@@ -1,13 +1,13 @@
-//! Rendering for modes 0-3
+//! Rendering for modes 0-2
 
 use super::super::consts::*;
 use super::super::Rgb15;
-use super::super::{Gpu, PixelFormat, SCREEN_BLOCK_SIZE};
+use super::super::{PixelFormat, Renderer, SCREEN_BLOCK_SIZE};
 use super::{utils, ViewPort};
 
 use crate::Bus;
 
-impl Gpu {
+impl Renderer {
     pub(in super::super) fn render_reg_bg(&mut self, bg: usize) {
         let (h_ofs, v_ofs) = (self.bg_hofs[bg] as u32, self.bg_vofs[bg] as u32);
         let tileset_base = self.bgcnt[bg].char_block();
@@ -56,17 +56,24 @@ impl Gpu {
                         let entry = TileMapEntry(self.vram.read_16(map_addr));
                         let tile_addr = tileset_base + entry.tile_index() * tile_size;
 
+                        // backgrounds can't fetch tiles from the OBJ part of VRAM
+                        let tile_in_bg_vram = tile_addr < VRAM_OBJ_TILES_START_TEXT;
+
                         for tile_px in start_tile_x..8 {
-                            let index = self.$read_pixel_index(
-                                tile_addr,
-                                if entry.x_flip() { 7 - tile_px } else { tile_px },
-                                if entry.y_flip() { 7 - tile_py } else { tile_py },
-                            );
-                            let palette_bank = match pixel_format {
-                                PixelFormat::BPP4 => entry.palette_bank() as u32,
-                                PixelFormat::BPP8 => 0u32,
+                            let color = if tile_in_bg_vram {
+                                let index = self.$read_pixel_index(
+                                    tile_addr,
+                                    if entry.x_flip() { 7 - tile_px } else { tile_px },
+                                    if entry.y_flip() { 7 - tile_py } else { tile_py },
+                                );
+                                let palette_bank = match pixel_format {
+                                    PixelFormat::BPP4 => entry.palette_bank() as u32,
+                                    PixelFormat::BPP8 => 0u32,
+                                };
+                                self.get_palette_color(index as u32, palette_bank, 0)
+                            } else {
+                                Rgb15::TRANSPARENT
                             };
-                            let color = self.get_palette_color(index as u32, palette_bank, 0);
                             self.bg_line[bg][screen_x as usize] = color;
                             screen_x += 1;
                             if (DISPLAY_WIDTH as u32) == screen_x {
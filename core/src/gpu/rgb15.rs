@@ -21,6 +21,32 @@ impl Rgb15 {
         ((self.r() as u32) << 19) | ((self.g() as u32) << 11) | ((self.b() as u32) << 3)
     }
 
+    /// Converts to RGB565, the green channel is widened to 6 bits by replicating its top bit
+    pub fn to_rgb565(&self) -> u16 {
+        let g6 = (self.g() << 1) | (self.g() >> 4);
+        (self.r() << 11) | (g6 << 5) | self.b()
+    }
+
+    /// Converts to 32bit RGBA, laid out in memory as R, G, B, A bytes whatever the host endianness
+    pub fn to_rgba8888(&self) -> u32 {
+        let (r, g, b) = self.to_rgb888();
+        u32::from_ne_bytes([r, g, b, 0xff])
+    }
+
+    /// Converts to 32bit BGRA, laid out in memory as B, G, R, A bytes whatever the host endianness
+    pub fn to_bgra8888(&self) -> u32 {
+        let (r, g, b) = self.to_rgb888();
+        u32::from_ne_bytes([b, g, r, 0xff])
+    }
+
+    fn to_rgb888(&self) -> (u8, u8, u8) {
+        (
+            (self.r() as u8) << 3,
+            (self.g() as u8) << 3,
+            (self.b() as u8) << 3,
+        )
+    }
+
     pub fn from_rgb(r: u16, g: u16, b: u16) -> Rgb15 {
         let mut c = Rgb15(0);
         c.set_r(r);
@@ -0,0 +1,57 @@
+use crate::bus::Addr;
+
+/// A single io register write, tagged with the scanline it happened on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoWriteRecord {
+    pub scanline: usize,
+    pub addr: Addr,
+    pub value: u16,
+}
+
+/// Records which io registers were written on which scanline.
+/// Writes are collected for the frame currently being drawn, and the complete timeline
+/// of the previous frame is kept around for inspection (e.g by the debugger).
+#[derive(Debug, Clone, Default)]
+pub struct ScanlineTrace {
+    enabled: bool,
+    current_frame: Vec<IoWriteRecord>,
+    last_frame: Vec<IoWriteRecord>,
+}
+
+impl ScanlineTrace {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.current_frame.clear();
+            self.last_frame.clear();
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, scanline: usize, addr: Addr, value: u16) {
+        if self.enabled {
+            self.current_frame.push(IoWriteRecord {
+                scanline,
+                addr,
+                value,
+            });
+        }
+    }
+
+    /// Must be called when the frame wraps around to scanline 0
+    pub fn end_frame(&mut self) {
+        if self.enabled {
+            std::mem::swap(&mut self.current_frame, &mut self.last_frame);
+            self.current_frame.clear();
+        }
+    }
+
+    /// Returns the io writes of the last complete frame, ordered by time
+    pub fn last_frame(&self) -> &[IoWriteRecord] {
+        &self.last_frame
+    }
+}
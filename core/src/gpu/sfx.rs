@@ -28,7 +28,7 @@ fn filter_window_backgrounds(
         .collect()
 }
 
-impl Gpu {
+impl Renderer {
     #[allow(unused)]
     fn layer_to_pixel(&mut self, x: usize, y: usize, layer: &RenderLayer) -> Rgb15 {
         match layer.kind {
@@ -65,7 +65,10 @@ impl Gpu {
             if self.dispcnt.enable_window0 && self.win0.contains_y(y) {
                 let win = WindowInfo::new(WindowType::Win0, self.win0.flags);
                 let backgrounds = filter_window_backgrounds(&sorted_backgrounds, win.flags);
-                for x in self.win0.left()..self.win0.right() {
+                for x in 0..DISPLAY_WIDTH {
+                    if !self.win0.contains_x(x) {
+                        continue;
+                    }
                     self.finalize_pixel(x, y, &win, &backgrounds, backdrop_color);
                     occupied[x] = true;
                     occupied_count += 1;
@@ -77,8 +80,8 @@ impl Gpu {
             if self.dispcnt.enable_window1 && self.win1.contains_y(y) {
                 let win = WindowInfo::new(WindowType::Win1, self.win1.flags);
                 let backgrounds = filter_window_backgrounds(&sorted_backgrounds, win.flags);
-                for x in self.win1.left()..self.win1.right() {
-                    if occupied[x] {
+                for x in 0..DISPLAY_WIDTH {
+                    if occupied[x] || !self.win1.contains_x(x) {
                         continue;
                     }
                     self.finalize_pixel(x, y, &win, &backgrounds, backdrop_color);
@@ -127,11 +130,6 @@ impl Gpu {
         backgrounds: &[usize],
         backdrop_color: Rgb15,
     ) {
-        let output = unsafe {
-            let ptr = self.frame_buffer[y * DISPLAY_WIDTH..].as_mut_ptr();
-            std::slice::from_raw_parts_mut(ptr, DISPLAY_WIDTH)
-        };
-
         // The backdrop layer is the default
         let backdrop_layer = RenderLayer::backdrop(backdrop_color);
 
@@ -170,44 +168,44 @@ impl Gpu {
         let top_flags = self.bldcnt.target1;
         let bot_flags = self.bldcnt.target2;
 
-        let sfx_enabled = (self.bldcnt.mode != BlendMode::BldNone || obj_alpha_blend)
-            && top_flags.contains_render_layer(&top_layer); // sfx must at least have a first target configured
-
-        if win.flags.sfx_enabled() && sfx_enabled {
-            if top_layer.is_object()
-                && obj_alpha_blend
-                && bot_flags.contains_render_layer(&bot_layer)
-            {
-                output[x] = self.do_alpha(top_layer.pixel, bot_layer.pixel).to_rgb24();
-            } else {
-                let (top_layer, bot_layer) = (top_layer, bot_layer);
-
-                match self.bldcnt.mode {
-                    BlendMode::BldAlpha => {
-                        output[x] = if bot_flags.contains_render_layer(&bot_layer) {
-                            self.do_alpha(top_layer.pixel, bot_layer.pixel).to_rgb24()
-                        } else {
-                            // alpha blending must have a 2nd target
-                            top_layer.pixel.to_rgb24()
-                        }
+        let color = if !win.flags.sfx_enabled() {
+            // no blending, just use the top pixel
+            top_layer.pixel
+        } else if obj_alpha_blend && bot_flags.contains_render_layer(&bot_layer) {
+            // semi-transparent objects are blended whatever the first target and mode of BLDCNT are
+            self.do_alpha(top_layer.pixel, bot_layer.pixel)
+        } else if top_flags.contains_render_layer(&top_layer) {
+            match self.bldcnt.mode {
+                BlendMode::BldAlpha => {
+                    if bot_flags.contains_render_layer(&bot_layer) {
+                        self.do_alpha(top_layer.pixel, bot_layer.pixel)
+                    } else {
+                        // alpha blending must have a 2nd target
+                        top_layer.pixel
                     }
-                    BlendMode::BldWhite => output[x] = self.do_brighten(top_layer.pixel).to_rgb24(),
+                }
+                BlendMode::BldWhite => self.do_brighten(top_layer.pixel),
 
-                    BlendMode::BldBlack => output[x] = self.do_darken(top_layer.pixel).to_rgb24(),
+                BlendMode::BldBlack => self.do_darken(top_layer.pixel),
 
-                    BlendMode::BldNone => output[x] = top_layer.pixel.to_rgb24(),
-                }
+                BlendMode::BldNone => top_layer.pixel,
             }
         } else {
-            // no blending, just use the top pixel
-            output[x] = top_layer.pixel.to_rgb24();
+            top_layer.pixel
+        };
+
+        if self.green_swap {
+            self.green_swap_line[x] = color;
+        } else {
+            self.write_pixel(y * DISPLAY_WIDTH + x, color);
         }
     }
 
     #[inline]
     fn do_alpha(&self, upper: Rgb15, lower: Rgb15) -> Rgb15 {
-        let eva = self.bldalpha.eva;
-        let evb = self.bldalpha.evb;
+        // coefficients above 16 act as 16
+        let eva = cmp::min(self.bldalpha.eva, 16);
+        let evb = cmp::min(self.bldalpha.evb, 16);
         upper.blend_with(lower, eva, evb)
     }
 
@@ -223,3 +221,75 @@ impl Gpu {
         c.blend_with(Rgb15::BLACK, 16 - evy, evy)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb15 = Rgb15(0x001f);
+    const BLUE: Rgb15 = Rgb15(0x7c00);
+
+    /// A mode 0 renderer with BG0 and BG1 enabled, both drawing a single pixel at x=0
+    fn setup(bg0: Rgb15, bg0_priority: u16, bg1: Rgb15, bg1_priority: u16) -> Renderer {
+        let mut renderer = Renderer::new();
+        renderer.dispcnt = DisplayControl::from(0x1300);
+        renderer.bgcnt[0].priority = bg0_priority;
+        renderer.bgcnt[1].priority = bg1_priority;
+        renderer.bg_line[0][0] = bg0;
+        renderer.bg_line[1][0] = bg1;
+        renderer
+    }
+
+    fn set_obj(renderer: &mut Renderer, color: Rgb15, priority: u16, alpha: bool) {
+        let entry = renderer.obj_buffer_get_mut(0, 0);
+        entry.color = color;
+        entry.priority = priority;
+        entry.alpha = alpha;
+    }
+
+    fn pixel(renderer: &mut Renderer) -> u32 {
+        renderer.finalize_scanline(0, 3);
+        renderer.frame_buffer[0]
+    }
+
+    #[test]
+    fn test_priority_ties() {
+        // same priority, the lower background wins
+        let mut renderer = setup(RED, 1, BLUE, 1);
+        assert_eq!(pixel(&mut renderer), RED.to_rgb24());
+
+        // an object wins over a background of the same priority
+        let mut renderer = setup(RED, 1, BLUE, 0);
+        set_obj(&mut renderer, Rgb15::WHITE, 0, false);
+        assert_eq!(pixel(&mut renderer), Rgb15::WHITE.to_rgb24());
+
+        // but not over one of a higher priority
+        set_obj(&mut renderer, Rgb15::WHITE, 1, false);
+        assert_eq!(pixel(&mut renderer), BLUE.to_rgb24());
+    }
+
+    #[test]
+    fn test_semi_transparent_obj() {
+        let mut renderer = setup(RED, 1, Rgb15::TRANSPARENT, 1);
+        set_obj(&mut renderer, BLUE, 0, true);
+        // the object isn't a first target and brightness is selected, it is still blended
+        renderer.bldcnt.write(0x01 << 8 | 0b10 << 6);
+        renderer.bldalpha.write(0x0808);
+        renderer.bldy = 16;
+        let blended = Rgb15::from_rgb(15, 0, 15).to_rgb24();
+        assert_eq!(pixel(&mut renderer), blended);
+
+        // without a second target below it, the BLDCNT effect applies as usual
+        renderer.bldcnt.write(0x02 << 8 | 0b10 << 6);
+        assert_eq!(pixel(&mut renderer), BLUE.to_rgb24());
+        renderer.bldcnt.write(0x02 << 8 | 0b10 << 6 | 0x10);
+        assert_eq!(pixel(&mut renderer), Rgb15::WHITE.to_rgb24());
+
+        // an opaque object isn't blended unless it is a first target
+        set_obj(&mut renderer, BLUE, 0, false);
+        renderer.bldcnt.write(0x01 << 8 | 0b01 << 6);
+        assert_eq!(pixel(&mut renderer), BLUE.to_rgb24());
+        renderer.bldcnt.write(0x01 << 8 | 0b01 << 6 | 0x10);
+        assert_eq!(pixel(&mut renderer), blended);
+    }
+}
@@ -1,3 +1,5 @@
+use std::cmp;
+
 use serde::{Deserialize, Serialize};
 
 use super::consts::*;
@@ -14,46 +16,29 @@ pub struct Window {
 
 impl Window {
     pub fn inside(&self, x: usize, y: usize) -> bool {
-        let left = self.left();
-        let right = self.right();
-        self.contains_y(y) && (x >= left && x < right)
-    }
-
-    #[inline]
-    pub fn left(&self) -> usize {
-        self.left as usize
+        self.contains_x(x) && self.contains_y(y)
     }
 
     #[inline]
-    pub fn right(&self) -> usize {
-        let left = self.left as usize;
-        let mut right = self.right as usize;
-        if right > DISPLAY_WIDTH || right < left {
-            right = DISPLAY_WIDTH;
-        }
-        right
+    pub fn contains_x(&self, x: usize) -> bool {
+        in_window_range(x, self.left as usize, self.right as usize, DISPLAY_WIDTH)
     }
 
     #[inline]
-    pub fn top(&self) -> usize {
-        self.top as usize
-    }
-
-    #[inline]
-    pub fn bottom(&self) -> usize {
-        let top = self.top as usize;
-        let mut bottom = self.bottom as usize;
-        if bottom > DISPLAY_HEIGHT || bottom < top {
-            bottom = DISPLAY_HEIGHT;
-        }
-        bottom
+    pub fn contains_y(&self, y: usize) -> bool {
+        in_window_range(y, self.top as usize, self.bottom as usize, DISPLAY_HEIGHT)
     }
+}
 
-    #[inline]
-    pub fn contains_y(&self, y: usize) -> bool {
-        let top = self.top();
-        let bottom = self.bottom();
-        y >= top && y < bottom
+/// The end coordinate is clamped to the screen size, and when the start coordinate is past the end
+/// the window wraps around the edge of the screen.
+#[inline]
+fn in_window_range(value: usize, start: usize, end: usize, screen_size: usize) -> bool {
+    let end = cmp::min(end, screen_size);
+    if start <= end {
+        value >= start && value < end
+    } else {
+        value >= start || value < end
     }
 }
 
@@ -0,0 +1,253 @@
+//! Composes the scanlines on a worker thread, while the emulation goes on with the next ones.
+//!
+//! The worker has its own copy of the `Renderer`. The registers are latched at the start of every
+//! line and sent to it, with the palette RAM, VRAM and OAM writes made since the previous line, so
+//! the worker draws each line from the same state the emulation thread would have drawn it.
+use std::io;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use super::*;
+use crate::bus::{Addr, Bus};
+
+/// The palette RAM, VRAM and OAM writes the render worker didn't get yet, as 16 bit bus writes.
+/// They are only recorded while a worker runs, and a copy of the renderer doesn't record them.
+#[derive(Debug, Default)]
+pub(super) struct PendingWrites {
+    recording: bool,
+    /// Set when the memories were modified without going through the bus, they are then sent whole
+    all: bool,
+    writes: Vec<(Addr, u16)>,
+}
+
+impl Clone for PendingWrites {
+    fn clone(&self) -> PendingWrites {
+        PendingWrites::default()
+    }
+}
+
+impl PendingWrites {
+    #[inline]
+    pub(super) fn push(&mut self, addr: Addr, value: u16) {
+        if self.recording {
+            self.writes.push((addr & !1, value));
+        }
+    }
+
+    pub(super) fn mark_all(&mut self) {
+        self.all = true;
+        self.writes.clear();
+    }
+}
+
+/// The registers a line is drawn with
+struct LineRegs {
+    vcount: usize,
+    dispcnt: DisplayControl,
+    bgcnt: [BgControl; 4],
+    bg_vofs: [u16; 4],
+    bg_hofs: [u16; 4],
+    bg_aff: [BgAffine; 2],
+    win0: Window,
+    win1: Window,
+    winout_flags: WindowFlags,
+    winobj_flags: WindowFlags,
+    mosaic: RegMosaic,
+    bldcnt: BlendControl,
+    bldalpha: BlendAlpha,
+    bldy: u16,
+    green_swap: bool,
+    vram_obj_tiles_start: u32,
+    output_format: OutputFormat,
+    obj_cycle_limit: bool,
+}
+
+/// Palette RAM, VRAM and OAM
+struct VideoMemory {
+    palette_ram: Box<[u8]>,
+    vram: Box<[u8]>,
+    oam: Box<[u8]>,
+}
+
+struct LineJob {
+    regs: LineRegs,
+    /// Only sent after the memories were modified without going through the bus
+    memory: Option<VideoMemory>,
+    /// The writes since the previous line, in order
+    writes: Vec<(Addr, u16)>,
+}
+
+enum Job {
+    Line(LineJob),
+    /// Sends back the frame buffers once the lines before it are drawn
+    EndFrame,
+    Stop,
+}
+
+struct FrameBuffers {
+    frame_buffer: Box<[u32]>,
+    frame_buffer_rgb565: Box<[u16]>,
+}
+
+impl Renderer {
+    fn latch_regs(&self) -> LineRegs {
+        LineRegs {
+            vcount: self.vcount,
+            dispcnt: self.dispcnt.clone(),
+            bgcnt: self.bgcnt.clone(),
+            bg_vofs: self.bg_vofs,
+            bg_hofs: self.bg_hofs,
+            bg_aff: self.bg_aff,
+            win0: self.win0.clone(),
+            win1: self.win1.clone(),
+            winout_flags: self.winout_flags,
+            winobj_flags: self.winobj_flags,
+            mosaic: self.mosaic,
+            bldcnt: self.bldcnt,
+            bldalpha: self.bldalpha,
+            bldy: self.bldy,
+            green_swap: self.green_swap,
+            vram_obj_tiles_start: self.vram_obj_tiles_start,
+            output_format: self.output_format,
+            obj_cycle_limit: self.obj_cycle_limit,
+        }
+    }
+
+    fn apply_line(&mut self, line: LineJob) {
+        if let Some(memory) = line.memory {
+            self.palette_ram = memory.palette_ram;
+            self.vram = memory.vram;
+            self.oam = memory.oam;
+            self.dirty.mark_all();
+        }
+        for (addr, value) in line.writes {
+            self.write_16(addr, value);
+        }
+
+        let regs = line.regs;
+        self.vcount = regs.vcount;
+        self.dispcnt = regs.dispcnt;
+        self.bgcnt = regs.bgcnt;
+        self.bg_vofs = regs.bg_vofs;
+        self.bg_hofs = regs.bg_hofs;
+        self.bg_aff = regs.bg_aff;
+        self.win0 = regs.win0;
+        self.win1 = regs.win1;
+        self.winout_flags = regs.winout_flags;
+        self.winobj_flags = regs.winobj_flags;
+        self.mosaic = regs.mosaic;
+        self.bldcnt = regs.bldcnt;
+        self.bldalpha = regs.bldalpha;
+        self.bldy = regs.bldy;
+        self.green_swap = regs.green_swap;
+        self.vram_obj_tiles_start = regs.vram_obj_tiles_start;
+        self.set_output_format(regs.output_format);
+        self.obj_cycle_limit = regs.obj_cycle_limit;
+    }
+}
+
+fn run(renderer: &mut Renderer, jobs: Receiver<Job>, frames: Sender<FrameBuffers>) {
+    while let Ok(job) = jobs.recv() {
+        match job {
+            Job::Line(line) => {
+                renderer.apply_line(line);
+                renderer.render_scanline();
+            }
+            Job::EndFrame => {
+                let buffers = FrameBuffers {
+                    frame_buffer: renderer.frame_buffer.clone(),
+                    frame_buffer_rgb565: renderer.frame_buffer_rgb565.clone(),
+                };
+                renderer.obj_buffer_reset();
+                if frames.send(buffers).is_err() {
+                    break;
+                }
+            }
+            Job::Stop => break,
+        }
+    }
+}
+
+pub(super) struct RenderWorker {
+    jobs: Sender<Job>,
+    frames: Receiver<FrameBuffers>,
+    thread: Option<JoinHandle<Renderer>>,
+}
+
+impl RenderWorker {
+    /// Starts a worker with a copy of `renderer`
+    pub(super) fn start(renderer: &mut Renderer) -> io::Result<RenderWorker> {
+        let mut worker_renderer = renderer.clone();
+        let (jobs, job_receiver) = channel();
+        let (frame_sender, frames) = channel();
+        let thread = thread::Builder::new()
+            .name("render worker".to_string())
+            .spawn(move || {
+                run(&mut worker_renderer, job_receiver, frame_sender);
+                worker_renderer
+            })?;
+        renderer.pending_writes = PendingWrites {
+            recording: true,
+            ..Default::default()
+        };
+        Ok(RenderWorker {
+            jobs,
+            frames,
+            thread: Some(thread),
+        })
+    }
+
+    /// Sends the current line of `renderer` to be drawn
+    pub(super) fn render_line(&mut self, renderer: &mut Renderer) {
+        let pending = &mut renderer.pending_writes;
+        let memory = if mem::replace(&mut pending.all, false) {
+            Some(VideoMemory {
+                palette_ram: renderer.palette_ram.clone(),
+                vram: renderer.vram.clone(),
+                oam: renderer.oam.clone(),
+            })
+        } else {
+            None
+        };
+        let writes = mem::take(&mut pending.writes);
+        let _ = self.jobs.send(Job::Line(LineJob {
+            regs: renderer.latch_regs(),
+            memory,
+            writes,
+        }));
+    }
+
+    /// Waits for the lines of the frame to be drawn, and copies them to `renderer`
+    pub(super) fn finish_frame(&mut self, renderer: &mut Renderer) {
+        if self.jobs.send(Job::EndFrame).is_err() {
+            return;
+        }
+        if let Ok(buffers) = self.frames.recv() {
+            renderer.frame_buffer = buffers.frame_buffer;
+            renderer.frame_buffer_rgb565 = buffers.frame_buffer_rgb565;
+        }
+    }
+
+    /// Waits for the lines sent so far, and hands what they were drawn to back to `renderer`
+    pub(super) fn stop(mut self, renderer: &mut Renderer) {
+        renderer.pending_writes = PendingWrites::default();
+        let _ = self.jobs.send(Job::Stop);
+        if let Some(Ok(worker_renderer)) = self.thread.take().map(JoinHandle::join) {
+            renderer.frame_buffer = worker_renderer.frame_buffer;
+            renderer.frame_buffer_rgb565 = worker_renderer.frame_buffer_rgb565;
+            renderer.obj_buffer = worker_renderer.obj_buffer;
+            // lines inside a mosaic block reuse the previous line
+            renderer.bg_line = worker_renderer.bg_line;
+        }
+    }
+}
+
+impl Drop for RenderWorker {
+    fn drop(&mut self) {
+        let _ = self.jobs.send(Job::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,53 @@
+/// Callbacks fired as the gpu goes through a frame, for map viewers, overlays and other tools that
+/// observe the rendering. See `GameBoyAdvance::add_gpu_hook`.
+use super::gba::GameBoyAdvance;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuHook {
+    /// A scanline starts, including the ones in vblank. Scanline 0 starts a frame.
+    Scanline,
+    /// The hblank of a scanline starts, the scanline has been drawn
+    HBlank,
+    /// The vblank starts, the frame has been drawn
+    VBlank,
+}
+
+/// Called with the emulator and the current scanline (VCOUNT)
+pub type GpuCallback = Box<dyn FnMut(&GameBoyAdvance, usize)>;
+
+#[derive(Default)]
+pub struct GpuHooks {
+    hooks: Vec<(usize, GpuHook, GpuCallback)>,
+    next_id: usize,
+}
+
+impl GpuHooks {
+    /// Returns an id for `remove`
+    pub fn add(&mut self, hook: GpuHook, callback: GpuCallback) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.hooks.push((id, hook, callback));
+        id
+    }
+
+    /// Returns false if there is no hook with this id
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|(hook_id, _, _)| *hook_id != id);
+        self.hooks.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn fire(&mut self, gba: &GameBoyAdvance, hook: GpuHook, scanline: usize) {
+        for (_, _, callback) in self.hooks.iter_mut().filter(|(_, h, _)| *h == hook) {
+            callback(gba, scanline);
+        }
+    }
+}
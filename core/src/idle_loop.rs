@@ -0,0 +1,71 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use super::Addr;
+
+/// Number of identical frames in a row (one second) before the screen is considered static
+const MIN_STATIC_FRAMES: usize = 60;
+const MAX_CANDIDATES: usize = 5;
+
+/// The most executed instructions while the screen was static
+#[derive(Debug, Clone)]
+pub struct IdleLoopReport {
+    /// The frame the screen stopped changing on
+    pub first_static_frame: usize,
+    /// (pc, number of times executed), most executed first
+    pub candidates: Vec<(Addr, usize)>,
+}
+
+/// Analysis mode for finding idle loops.
+///
+/// Frames are hashed and the executed instructions are counted, once the screen has been static
+/// for long enough, the hottest addresses are reported as idle-loop candidates for the
+/// `idle_loop` entry of the game overrides database. Static screens are also where intros
+/// wait for input, so the reports double as a map of where a game idles.
+#[derive(Debug, Default)]
+pub struct IdleLoopDetector {
+    frame: usize,
+    last_frame_hash: u64,
+    static_frames: usize,
+    pc_samples: HashMap<Addr, usize>,
+}
+
+impl IdleLoopDetector {
+    #[inline]
+    pub fn sample_pc(&mut self, pc: Addr) {
+        *self.pc_samples.entry(pc).or_insert(0) += 1;
+    }
+
+    /// Must be called after every frame with the frame buffer of the selected output format,
+    /// returns a report once per static screen sequence
+    pub fn end_frame<T: Hash>(&mut self, frame_buffer: &[T]) -> Option<IdleLoopReport> {
+        let mut hasher = DefaultHasher::new();
+        frame_buffer.hash(&mut hasher);
+        let hash = hasher.finish();
+        self.frame += 1;
+
+        if hash != self.last_frame_hash {
+            self.last_frame_hash = hash;
+            self.static_frames = 0;
+            self.pc_samples.clear();
+            return None;
+        }
+
+        self.static_frames += 1;
+        if self.static_frames != MIN_STATIC_FRAMES {
+            return None;
+        }
+        let mut candidates: Vec<(Addr, usize)> = self
+            .pc_samples
+            .iter()
+            .map(|(&pc, &count)| (pc, count))
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        candidates.truncate(MAX_CANDIDATES);
+        Some(IdleLoopReport {
+            first_static_frame: self.frame - self.static_frames,
+            candidates,
+        })
+    }
+}
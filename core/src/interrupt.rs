@@ -32,6 +32,12 @@ pub struct InterruptController {
     pub interrupt_master_enable: bool,
     pub interrupt_enable: IrqBitmask,
     pub interrupt_flags: SharedInterruptFlags,
+    /// (IME AND IE AND IF) != 0, as sampled after the last instruction
+    irq_sync: bool,
+    /// The irq signal as seen by the cpu.
+    /// Changes to IME/IE/IF go through a synchronizer and only reach the cpu after the next
+    /// instruction, so an irq raised right before IME is cleared (or IF acknowledged) still fires.
+    irq_line: bool,
 }
 
 impl InterruptController {
@@ -45,8 +51,50 @@ impl InterruptController {
 
     #[inline]
     pub fn irq_pending(&self) -> bool {
-        self.interrupt_master_enable
-            & ((self.interrupt_flags.get().value() & self.interrupt_enable.0) != 0)
+        self.interrupt_master_enable & self.halt_exit_pending()
+    }
+
+    /// Halt is exited whenever (IE AND IF) != 0, regardless of IME
+    #[inline]
+    pub fn halt_exit_pending(&self) -> bool {
+        (self.interrupt_flags.get().value() & self.interrupt_enable.0) != 0
+    }
+
+    /// Stop is only exited by the interrupts that don't depend on the (stopped) system clock
+    #[inline]
+    pub fn stop_exit_pending(&self) -> bool {
+        const STOP_WAKEUP_IRQS: u16 = 1 << (Interrupt::SerialCommunication as u16)
+            | 1 << (Interrupt::Keypad as u16)
+            | 1 << (Interrupt::GamePak as u16);
+        (self.interrupt_flags.get().value() & self.interrupt_enable.0 & STOP_WAKEUP_IRQS) != 0
+    }
+
+    /// Returns the delayed irq signal the cpu should act upon
+    #[inline]
+    pub fn irq_line(&self) -> bool {
+        self.irq_line
+    }
+
+    /// Moves the IME/IE/IF state one stage further through the synchronizer,
+    /// should be called after every executed instruction.
+    #[inline]
+    pub fn sync_irq_line(&mut self) {
+        self.irq_line = self.irq_sync;
+        self.irq_sync = self.irq_pending();
+    }
+
+    /// Whether no irq is pending, nor on its way through the synchronizer
+    #[inline]
+    pub fn irq_idle(&self) -> bool {
+        !self.irq_line && !self.irq_sync && !self.irq_pending()
+    }
+
+    /// Propagates the current IME/IE/IF state to the cpu irq signal right away, for when the cpu
+    /// was idle long enough for the synchronizer to settle
+    #[inline]
+    pub fn settle_irq_line(&mut self) {
+        self.irq_sync = self.irq_pending();
+        self.irq_line = self.irq_sync;
     }
 
     #[inline]
@@ -112,3 +160,54 @@ bitfield! {
 }
 
 pub type SharedInterruptFlags = Rc<Cell<IrqBitmask>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer0_controller() -> InterruptController {
+        let mut intc = InterruptController::new(SharedInterruptFlags::default());
+        intc.interrupt_enable.set_Timer0_Overflow(true);
+        intc.interrupt_master_enable = true;
+        intc
+    }
+
+    #[test]
+    fn test_irq_latency() {
+        let mut intc = timer0_controller();
+        signal_irq(&intc.interrupt_flags, Interrupt::Timer0_Overflow);
+        // the instruction that saw the irq get raised
+        intc.sync_irq_line();
+        assert!(!intc.irq_line());
+        // one more instruction runs before the cpu takes it
+        intc.sync_irq_line();
+        assert!(intc.irq_line());
+    }
+
+    #[test]
+    fn test_ime_disable_race() {
+        let mut intc = timer0_controller();
+        signal_irq(&intc.interrupt_flags, Interrupt::Timer0_Overflow);
+        intc.sync_irq_line();
+        // the next instruction clears IME, the irq was already on its way
+        intc.interrupt_master_enable = false;
+        intc.sync_irq_line();
+        assert!(intc.irq_line());
+        intc.sync_irq_line();
+        assert!(!intc.irq_line());
+    }
+
+    #[test]
+    fn test_if_acknowledge_race() {
+        let mut intc = timer0_controller();
+        signal_irq(&intc.interrupt_flags, Interrupt::Timer0_Overflow);
+        intc.settle_irq_line();
+        assert!(intc.irq_line());
+        // the handler acknowledges IF, the line stays up for one more instruction
+        intc.clear(1 << Interrupt::Timer0_Overflow as u16);
+        intc.sync_irq_line();
+        assert!(intc.irq_line());
+        intc.sync_irq_line();
+        assert!(!intc.irq_line());
+    }
+}
@@ -5,13 +5,17 @@ use super::dma::DmaController;
 use super::gpu::regs::GpuMemoryMappedIO;
 use super::gpu::regs::WindowFlags;
 use super::gpu::*;
-use super::interrupt::{InterruptConnect, InterruptController, SharedInterruptFlags};
+use super::interrupt::{
+    self, Interrupt, InterruptConnect, InterruptController, SharedInterruptFlags,
+};
 use super::keypad;
 use super::mgba_debug::DebugPort;
 use super::sched::{SchedulerConnect, SharedScheduler};
+use super::sio::SerialController;
 use super::sound::SoundController;
 use super::sysbus::SysBusPtr;
 use super::timer::Timers;
+use crate::logging;
 
 use serde::{Deserialize, Serialize};
 
@@ -31,12 +35,19 @@ pub struct IoDevices {
     pub sound: Box<SoundController>,
     pub timers: Timers,
     pub dmac: DmaController,
+    pub sio: SerialController,
     pub keyinput: u16,
+    pub keycnt: u16,
     pub post_boot_flag: bool,
-    pub waitcnt: WaitControl, // TODO also implement 4000800
+    pub waitcnt: WaitControl,
+    pub imc: InternalMemoryControl,
     pub haltcnt: HaltState,
     pub debug: DebugPort,
 
+    /// Log every io register write along with a decoding of the written value
+    #[serde(skip)]
+    pub trace_writes: bool,
+
     // HACK
     // my ownership design sucks
     #[serde(skip)]
@@ -51,18 +62,23 @@ impl IoDevices {
         dmac: DmaController,
         timers: Timers,
         sound_controller: Box<SoundController>,
+        sio: SerialController,
     ) -> IoDevices {
         IoDevices {
             intc,
             gpu,
             timers,
             dmac,
+            sio,
             sound: sound_controller,
             post_boot_flag: false,
             haltcnt: HaltState::Running,
             keyinput: keypad::KEYINPUT_ALL_RELEASED,
+            keycnt: 0,
             waitcnt: WaitControl(0),
+            imc: InternalMemoryControl::default(),
             debug: DebugPort::new(),
+            trace_writes: false,
 
             sysbus_ptr: Default::default(),
         }
@@ -71,6 +87,68 @@ impl IoDevices {
     pub fn set_sysbus_ptr(&mut self, ptr: SysBusPtr) {
         self.sysbus_ptr = ptr;
     }
+
+    /// Requests the keypad interrupt while the key state matches KEYCNT
+    pub fn update_keypad_irq(&mut self) {
+        if keypad::irq_condition(self.keyinput, self.keycnt) {
+            interrupt::signal_irq(&self.intc.interrupt_flags, Interrupt::Keypad);
+        }
+    }
+
+    /// The BG2/3 affine registers are write-only, this returns the last value written to one
+    /// so that 8bit writes can be merged with it
+    fn affine_register_value(&self, io_addr: Addr) -> u16 {
+        let ofs = io_addr - REG_BG2PA;
+        let aff = &self.gpu.bg_aff[(ofs / 0x10) as usize];
+        match ofs % 0x10 {
+            0x0 => aff.pa as u16,
+            0x2 => aff.pb as u16,
+            0x4 => aff.pc as u16,
+            0x6 => aff.pd as u16,
+            0x8 => aff.x as u16,
+            0xa => (aff.x >> 16) as u16 & 0xfff,
+            0xc => aff.y as u16,
+            0xe => (aff.y >> 16) as u16 & 0xfff,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the last value written to a write-only register, as reading these through the bus gives 0.
+    /// Returns `None` for registers that can be read back normally.
+    pub fn write_only_register_value(&self, io_addr: Addr) -> Option<u16> {
+        let gpu = &self.gpu;
+        let value = match io_addr {
+            REG_BG0HOFS..=REG_BG3VOFS => {
+                let bg = ((io_addr - REG_BG0HOFS) / 4) as usize;
+                if io_addr & 2 == 0 {
+                    gpu.bg_hofs[bg]
+                } else {
+                    gpu.bg_vofs[bg]
+                }
+            }
+            REG_BG2PA..=REG_BG3Y_H => self.affine_register_value(io_addr),
+            REG_WIN0H => (gpu.win0.left as u16) << 8 | gpu.win0.right as u16,
+            REG_WIN1H => (gpu.win1.left as u16) << 8 | gpu.win1.right as u16,
+            REG_WIN0V => (gpu.win0.top as u16) << 8 | gpu.win0.bottom as u16,
+            REG_WIN1V => (gpu.win1.top as u16) << 8 | gpu.win1.bottom as u16,
+            REG_MOSAIC => gpu.mosaic.0,
+            REG_BLDY => gpu.bldy,
+            DMA_BASE..=REG_DMA3CNT_H => {
+                let ofs = io_addr - DMA_BASE;
+                let channel = &self.dmac.channels[(ofs / 12) as usize];
+                match ofs % 12 {
+                    0 => channel.src as u16,
+                    2 => (channel.src >> 16) as u16,
+                    4 => channel.dst as u16,
+                    6 => (channel.dst >> 16) as u16,
+                    8 => channel.wc as u16,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
 }
 
 impl InterruptConnect for IoDevices {
@@ -79,6 +157,7 @@ impl InterruptConnect for IoDevices {
         self.gpu.connect_irq(interrupt_flags.clone());
         self.dmac.connect_irq(interrupt_flags.clone());
         self.timers.connect_irq(interrupt_flags.clone());
+        self.sio.connect_irq(interrupt_flags.clone());
     }
 }
 
@@ -91,26 +170,47 @@ impl SchedulerConnect for IoDevices {
     }
 }
 
-impl Bus for IoDevices {
-    fn read_16(&mut self, addr: Addr) -> u16 {
+/// Returns the bits of a register that can be read back, unused bits always read as zero
+fn read_mask(io_addr: Addr) -> u16 {
+    match io_addr {
+        REG_DISPSTAT => 0xff3f,
+        REG_VCOUNT => 0x00ff,
+        // the wraparound bit only exists for the affine backgrounds
+        REG_BG0CNT | REG_BG1CNT => 0xdfff,
+        REG_WININ | REG_WINOUT => 0x3f3f,
+        REG_BLDCNT => 0x3fff,
+        REG_BLDALPHA => 0x1f1f,
+        REG_IE | REG_IF => 0x3fff,
+        REG_IME => 0x0001,
+        // only DMA3 has the gamepak DRQ bit
+        REG_DMA0CNT_H | REG_DMA1CNT_H | REG_DMA2CNT_H => 0xf7e0,
+        REG_DMA3CNT_H => 0xffe0,
+        // bit 15 is the gamepak type, which is always 0 for GBA cartridges
+        REG_WAITCNT => 0x5fff,
+        REG_KEYINPUT => 0x03ff,
+        _ => 0xffff,
+    }
+}
+
+impl IoDevices {
+    /// Reads a register without the side effects of a cpu read, for the debugger and for merging
+    /// byte writes
+    fn read_register(&mut self, addr: Addr) -> u16 {
         let io = self;
         let io_addr = addr + IO_BASE;
         // if addr > 0x0800 {
         //     return 0;
         // }
 
-        match io_addr {
+        let value = match io_addr {
             REG_DISPCNT => io.gpu.dispcnt.read(),
+            REG_GREENSWAP => io.gpu.green_swap as u16,
             REG_DISPSTAT => io.gpu.dispstat.read(),
             REG_VCOUNT => io.gpu.vcount as u16,
             REG_BG0CNT => io.gpu.bgcnt[0].read(),
             REG_BG1CNT => io.gpu.bgcnt[1].read(),
             REG_BG2CNT => io.gpu.bgcnt[2].read(),
             REG_BG3CNT => io.gpu.bgcnt[3].read(),
-            REG_WIN0H => ((io.gpu.win0.left as u16) << 8 | (io.gpu.win0.right as u16)),
-            REG_WIN1H => ((io.gpu.win1.left as u16) << 8 | (io.gpu.win1.right as u16)),
-            REG_WIN0V => ((io.gpu.win0.top as u16) << 8 | (io.gpu.win0.bottom as u16)),
-            REG_WIN1V => ((io.gpu.win1.top as u16) << 8 | (io.gpu.win1.bottom as u16)),
             REG_WININ => {
                 ((io.gpu.win1.flags.bits() as u16) << 8) | (io.gpu.win0.flags.bits() as u16)
             }
@@ -131,31 +231,51 @@ impl Bus for IoDevices {
             REG_DMA1CNT_H => io.dmac.channels[1].ctrl.0,
             REG_DMA2CNT_H => io.dmac.channels[2].ctrl.0,
             REG_DMA3CNT_H => io.dmac.channels[3].ctrl.0,
+
             // Even though these registers are write only,
             // some games may still try to read them.
-            // TODO: should this be treated as an open-bus read?
-            REG_DMA0CNT_L => 0,
-            REG_DMA1CNT_L => 0,
-            REG_DMA2CNT_L => 0,
-            REG_DMA3CNT_L => 0,
+            REG_BG0HOFS..=REG_BG3Y_H => 0,
+            REG_WIN0H..=REG_WIN1V => 0,
+            REG_MOSAIC | REG_BLDY => 0,
+            DMA_BASE..=REG_DMA3CNT_H => 0,
 
             REG_WAITCNT => io.waitcnt.0,
+            REG_IMC_L => io.imc.0 as u16,
+            REG_IMC_H => (io.imc.0 >> 16) as u16,
+
+            REG_SIOMULTI0..=REG_SIOMULTI3
+            | REG_SIOCNT
+            | REG_SIODATA8
+            | REG_RCNT
+            | REG_JOYCNT
+            | REG_JOY_RECV..=REG_JOYSTAT => io.sio.handle_read(io_addr),
 
             REG_POSTFLG => io.post_boot_flag as u16,
             REG_HALTCNT => 0,
             REG_KEYINPUT => io.keyinput as u16,
+            REG_KEYCNT => io.keycnt,
 
             x if DebugPort::is_debug_access(x) => io.debug.read(io_addr),
 
             _ => {
                 trace!(
+                    target: logging::BUS,
                     "Unimplemented read from {:x} {}",
                     io_addr,
                     io_reg_string(io_addr)
                 );
                 0
             }
-        }
+        };
+        value & read_mask(io_addr)
+    }
+}
+
+impl Bus for IoDevices {
+    fn read_16(&mut self, addr: Addr) -> u16 {
+        let value = self.read_register(addr);
+        self.sio.acknowledge_read(addr + IO_BASE);
+        value
     }
 
     fn read_8(&mut self, addr: Addr) -> u8 {
@@ -174,11 +294,17 @@ impl Bus for IoDevices {
         // }
         let io_addr = addr + IO_BASE;
 
+        let scanline = io.gpu.vcount;
+        io.gpu.scanline_trace.record(scanline, io_addr, value);
+
+        if io.trace_writes {
+            info!(target: logging::BUS, "{}", describe_io_write(io_addr, value));
+        }
+
         macro_rules! write_reference_point {
             (low bg $coord:ident $internal:ident) => {{
                 let i = ((io_addr - REG_BG2X_L) / 0x10) as usize;
                 let t = io.gpu.bg_aff[i].$coord as u32;
-                io.gpu.bg_aff[i].$coord = ((t & 0xffff0000) + (value as u32)) as i32;
                 let new_value = ((t & 0xffff0000) + (value as u32)) as i32;
                 io.gpu.bg_aff[i].$coord = new_value;
                 io.gpu.bg_aff[i].$internal = new_value;
@@ -195,7 +321,8 @@ impl Bus for IoDevices {
 
         match io_addr {
             REG_DISPCNT => io.gpu.write_dispcnt(value),
-            REG_DISPSTAT => io.gpu.dispstat.write(value),
+            REG_GREENSWAP => io.gpu.green_swap = value & 1 != 0,
+            REG_DISPSTAT => io.gpu.write_dispstat(value),
             REG_BG0CNT => io.gpu.bgcnt[0].write(value),
             REG_BG1CNT => io.gpu.bgcnt[1].write(value),
             REG_BG2CNT => io.gpu.bgcnt[2].write(value),
@@ -260,7 +387,7 @@ impl Bus for IoDevices {
             REG_BLDY => io.gpu.bldy = cmp::min(value & 0b11111, 16),
 
             REG_IME => io.intc.interrupt_master_enable = value != 0,
-            REG_IE => io.intc.interrupt_enable.0 = value,
+            REG_IE => io.intc.interrupt_enable.0 = value & 0x3fff,
             REG_IF => io.intc.clear(value),
 
             REG_TM0CNT_L..=REG_TM3CNT_H => io.timers.handle_write(io_addr, value),
@@ -279,21 +406,37 @@ impl Bus for IoDevices {
                 io.waitcnt.0 = value;
                 (*io.sysbus_ptr).on_waitcnt_written(io.waitcnt);
             }
-
-            REG_POSTFLG => io.post_boot_flag = value != 0,
-            REG_HALTCNT => {
-                if value & 0x80 != 0 {
-                    io.haltcnt = HaltState::Stop;
-                    panic!("Can't handle HaltCtrl == Stop yet");
+            REG_IMC_L | REG_IMC_H => {
+                io.imc.0 = if io_addr == REG_IMC_L {
+                    (io.imc.0 & 0xffff_0000) | value as u32
                 } else {
-                    io.haltcnt = HaltState::Halt;
-                }
+                    (io.imc.0 & 0x0000_ffff) | (value as u32) << 16
+                };
+                (*io.sysbus_ptr).on_imc_written(io.imc);
+            }
+
+            REG_SIOMULTI0..=REG_SIOMULTI3
+            | REG_SIOCNT
+            | REG_SIODATA8
+            | REG_RCNT
+            | REG_JOYCNT
+            | REG_JOY_RECV..=REG_JOYSTAT => io.sio.handle_write(io_addr, value),
+
+            // the key state comes from the frontend
+            REG_KEYINPUT => {}
+            REG_KEYCNT => {
+                io.keycnt = value & keypad::KEYCNT_MASK;
+                io.update_keypad_irq();
             }
 
+            // HALTCNT is only written by 8bit stores, see write_8
+            REG_POSTFLG => io.post_boot_flag = value & 1 != 0,
+
             x if DebugPort::is_debug_access(x) => io.debug.write(io_addr, value),
 
             _ => {
                 trace!(
+                    target: logging::BUS,
                     "Unimplemented write to {:x} {}",
                     io_addr,
                     io_reg_string(io_addr)
@@ -312,8 +455,21 @@ impl Bus for IoDevices {
             0x0400_00A4 | 0x0400_00A5 | 0x0400_00A6 | 0x0400_00A7 => {
                 self.sound.write_fifo(1, value as i8)
             }
+            // POSTFLG and HALTCNT share a halfword, but writing one must not touch the other
+            REG_POSTFLG => self.post_boot_flag = value & 1 != 0,
+            REG_HALTCNT => {
+                if value & 0x80 != 0 {
+                    self.haltcnt = HaltState::Stop;
+                } else {
+                    self.haltcnt = HaltState::Halt;
+                }
+            }
             _ => {
-                let t = self.read_16(addr & !1);
+                // merge with the last value written, write-only registers read back as 0
+                let t = match self.write_only_register_value((addr & !1) + IO_BASE) {
+                    Some(t) => t,
+                    None => self.read_register(addr & !1),
+                };
                 let t = if addr & 1 != 0 {
                     (t & 0xff) | (value as u16) << 8
                 } else {
@@ -327,7 +483,12 @@ impl Bus for IoDevices {
 
 impl DebugRead for IoDevices {
     fn debug_read_8(&mut self, addr: Addr) -> u8 {
-        self.read_8(addr)
+        let t = self.read_register(addr & !1);
+        if addr & 1 != 0 {
+            (t >> 8) as u8
+        } else {
+            t as u8
+        }
     }
 }
 
@@ -345,7 +506,28 @@ bitfield! {
     pub ws2_second_access, _:      10, 10;
     #[allow(non_snake_case)]
     PHI_terminal_output, _:    12, 11;
-    prefetch, _:               14;
+    pub prefetch, _:           14;
+}
+
+bitfield! {
+    /// Undocumented internal memory control at 04000800, mirrored every 64K of the io region
+    #[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+    pub struct InternalMemoryControl(u32);
+    impl Debug;
+    u32;
+    /// Both EWRAM and IWRAM read as open bus when set
+    pub disable_wram, _:       0;
+    /// EWRAM mirrors IWRAM when cleared
+    pub enable_ewram, _:       5;
+    /// 15 minus the EWRAM wait states, 15 locks up the hardware
+    pub ewram_wait_control, _: 27, 24;
+}
+
+impl Default for InternalMemoryControl {
+    /// The value set on power on, 2 wait states for EWRAM
+    fn default() -> InternalMemoryControl {
+        InternalMemoryControl(0x0d00_0020)
+    }
 }
 
 #[rustfmt::skip]
@@ -441,20 +623,22 @@ pub mod consts {
     pub const REG_TM3CNT_L: Addr = 0x0400_010C;     //  2    R/W    Timer 3 Counter/Reload
     pub const REG_TM3CNT_H: Addr = 0x0400_010E;     //  2    R/W    Timer 3 Control
     // pub const REG_SIODATA32: Addr = 0x0400_0120;    //  4    R/W    SIO Data (Normal-32bit Mode; shared with below)
-    // pub const REG_SIOMULTI0: Addr = 0x0400_0120;    //  2    R/W    SIO Data 0 (Parent)    (Multi-Player Mode)
-    // pub const REG_SIOMULTI1: Addr = 0x0400_0122;    //  2    R/W    SIO Data 1 (1st Child) (Multi-Player Mode)
-    // pub const REG_SIOMULTI2: Addr = 0x0400_0124;    //  2    R/W    SIO Data 2 (2nd Child) (Multi-Player Mode)
-    // pub const REG_SIOMULTI3: Addr = 0x0400_0126;    //  2    R/W    SIO Data 3 (3rd Child) (Multi-Player Mode)
-    // pub const REG_SIOCNT: Addr = 0x0400_0128;       //  2    R/W    SIO Control Register
+    pub const REG_SIOMULTI0: Addr = 0x0400_0120;    //  2    R/W    SIO Data 0 (Parent)    (Multi-Player Mode)
+    pub const REG_SIOMULTI1: Addr = 0x0400_0122;    //  2    R/W    SIO Data 1 (1st Child) (Multi-Player Mode)
+    pub const REG_SIOMULTI2: Addr = 0x0400_0124;    //  2    R/W    SIO Data 2 (2nd Child) (Multi-Player Mode)
+    pub const REG_SIOMULTI3: Addr = 0x0400_0126;    //  2    R/W    SIO Data 3 (3rd Child) (Multi-Player Mode)
+    pub const REG_SIOCNT: Addr = 0x0400_0128;       //  2    R/W    SIO Control Register
     // pub const REG_SIOMLT_SEND: Addr = 0x0400_012A;  //  2    R/W    SIO Data (Local of MultiPlayer; shared below)
-    // pub const REG_SIODATA8: Addr = 0x0400_012A;     //  2    R/W    SIO Data (Normal-8bit and UART Mode)
+    pub const REG_SIODATA8: Addr = 0x0400_012A;     //  2    R/W    SIO Data (Normal-8bit and UART Mode)
     pub const REG_KEYINPUT: Addr = 0x0400_0130;     //  2    R      Key Status
     pub const REG_KEYCNT: Addr = 0x0400_0132;       //  2    R/W    Key Interrupt Control
     pub const REG_RCNT: Addr = 0x0400_0134;         //  2    R/W    SIO Mode Select/General Purpose Data
     pub const REG_IR: Addr = 0x0400_0136;           //  -    -      Ancient - Infrared Register (Prototypes only)
     pub const REG_JOYCNT: Addr = 0x0400_0140;       //  2    R/W    SIO JOY Bus Control
     pub const REG_JOY_RECV: Addr = 0x0400_0150;     //  4    R/W    SIO JOY Bus Receive Data
+    pub const REG_JOY_RECV_H: Addr = 0x0400_0152;   //  -    R/W    (upper halfword of JOY_RECV)
     pub const REG_JOY_TRANS: Addr = 0x0400_0154;    //  4    R/W    SIO JOY Bus Transmit Data
+    pub const REG_JOY_TRANS_H: Addr = 0x0400_0156;  //  -    R/W    (upper halfword of JOY_TRANS)
     pub const REG_JOYSTAT: Addr = 0x0400_0158;      //  2    R/?    SIO JOY Bus Receive Status
     pub const REG_IE: Addr = 0x0400_0200;           //  2    R/W    Interrupt Enable Register
     pub const REG_IF: Addr = 0x0400_0202;           //  2    R/W    Interrupt Request Flags / IRQ Acknowledge
@@ -462,6 +646,8 @@ pub mod consts {
     pub const REG_IME: Addr = 0x0400_0208;          //  2    R/W    Interrupt Master Enable Register
     pub const REG_POSTFLG: Addr = 0x0400_0300;      //  1    R/W    Undocumented - Post Boot Flag
     pub const REG_HALTCNT: Addr = 0x0400_0301;      //  1    W      Undocumented - Power Down Control
+    pub const REG_IMC_L: Addr = 0x0400_0800;        //  4    R/W    Undocumented - Internal Memory Control
+    pub const REG_IMC_H: Addr = 0x0400_0802;
 
     pub const REG_DEBUG_STRING: Addr = 0x04FF_F600;
     pub const REG_DEBUG_FLAGS: Addr = 0x04FF_F700;
@@ -553,13 +739,13 @@ pub fn io_reg_string(addr: u32) -> &'static str {
         REG_TM3CNT_L => "REG_TM3CNT_L",
         REG_TM3CNT_H => "REG_TM3CNT_H",
         // REG_SIODATA32 => "REG_SIODATA32",
-        // REG_SIOMULTI0 => "REG_SIOMULTI0",
-        // REG_SIOMULTI1 => "REG_SIOMULTI1",
-        // REG_SIOMULTI2 => "REG_SIOMULTI2",
-        // REG_SIOMULTI3 => "REG_SIOMULTI3",
-        // REG_SIOCNT => "REG_SIOCNT",
+        REG_SIOMULTI0 => "REG_SIOMULTI0",
+        REG_SIOMULTI1 => "REG_SIOMULTI1",
+        REG_SIOMULTI2 => "REG_SIOMULTI2",
+        REG_SIOMULTI3 => "REG_SIOMULTI3",
+        REG_SIOCNT => "REG_SIOCNT",
         // REG_SIOMLT_SEND => "REG_SIOMLT_SEND",
-        // REG_SIODATA8 => "REG_SIODATA8",
+        REG_SIODATA8 => "REG_SIODATA8",
         REG_KEYINPUT => "REG_KEYINPUT",
         REG_KEYCNT => "REG_KEYCNT",
         REG_RCNT => "REG_RCNT",
@@ -574,6 +760,8 @@ pub fn io_reg_string(addr: u32) -> &'static str {
         REG_IME => "REG_IME",
         REG_POSTFLG => "REG_POSTFLG",
         REG_HALTCNT => "REG_HALTCNT",
+        REG_IMC_L => "REG_IMC_L",
+        REG_IMC_H => "REG_IMC_H",
         REG_DEBUG_STRING => "REG_DEBUG_STRING",
         REG_DEBUG_FLAGS => "REG_DEBUG_FLAGS",
         REG_DEBUG_ENABLE => "REG_DEBUG_ENABLE",
@@ -581,6 +769,99 @@ pub fn io_reg_string(addr: u32) -> &'static str {
     }
 }
 
+/// Formats an io register write as a human readable line, e.g
+/// `DISPCNT <= 0x1f40 (mode=0, frame=0, bg0|bg1|bg2|bg3|obj)`
+pub fn describe_io_write(io_addr: Addr, value: u16) -> String {
+    let bit = |n: u16| (value >> n) & 1 != 0;
+    let enabled_names = |names: &[(u16, &str)]| -> String {
+        let enabled: Vec<&str> = names
+            .iter()
+            .filter(|(n, _)| bit(*n))
+            .map(|(_, name)| *name)
+            .collect();
+        if enabled.is_empty() {
+            String::from("none")
+        } else {
+            enabled.join("|")
+        }
+    };
+
+    let details = match io_addr {
+        REG_DISPCNT => Some(format!(
+            "mode={}, frame={}, {}{}",
+            value & 0b111,
+            (value >> 4) & 1,
+            enabled_names(&[
+                (8, "bg0"),
+                (9, "bg1"),
+                (10, "bg2"),
+                (11, "bg3"),
+                (12, "obj"),
+                (13, "win0"),
+                (14, "win1"),
+                (15, "objwin"),
+            ]),
+            if bit(7) { ", forced_blank" } else { "" }
+        )),
+        REG_DISPSTAT => Some(format!(
+            "irqs={}, vcount_setting={}",
+            enabled_names(&[(3, "vblank"), (4, "hblank"), (5, "vcount")]),
+            value >> 8
+        )),
+        REG_BG0CNT | REG_BG1CNT | REG_BG2CNT | REG_BG3CNT => Some(format!(
+            "priority={}, char_block={}, screen_block={}, {}, size={}{}",
+            value & 0b11,
+            (value >> 2) & 0b11,
+            (value >> 8) & 0b11111,
+            if bit(7) { "8bpp" } else { "4bpp" },
+            (value >> 14) & 0b11,
+            if bit(6) { ", mosaic" } else { "" }
+        )),
+        REG_DMA0CNT_H | REG_DMA1CNT_H | REG_DMA2CNT_H | REG_DMA3CNT_H => Some(format!(
+            "enabled={}, timing={}, {}bit, dst_adj={}, src_adj={}, repeat={}, irq={}",
+            bit(15),
+            (value >> 12) & 0b11,
+            if bit(10) { 32 } else { 16 },
+            (value >> 5) & 0b11,
+            (value >> 7) & 0b11,
+            bit(9),
+            bit(14)
+        )),
+        REG_TM0CNT_H | REG_TM1CNT_H | REG_TM2CNT_H | REG_TM3CNT_H => Some(format!(
+            "enabled={}, prescaler={}, cascade={}, irq={}",
+            bit(7),
+            value & 0b11,
+            bit(2),
+            bit(6)
+        )),
+        REG_IE | REG_IF => Some(enabled_names(&[
+            (0, "vblank"),
+            (1, "hblank"),
+            (2, "vcount"),
+            (3, "timer0"),
+            (4, "timer1"),
+            (5, "timer2"),
+            (6, "timer3"),
+            (7, "serial"),
+            (8, "dma0"),
+            (9, "dma1"),
+            (10, "dma2"),
+            (11, "dma3"),
+            (12, "keypad"),
+            (13, "gamepak"),
+        ])),
+        REG_IME => Some(format!("enabled={}", bit(0))),
+        _ => None,
+    };
+
+    let name = io_reg_string(io_addr);
+    let name = name.trim_start_matches("REG_");
+    match details {
+        Some(details) => format!("{} <= {:#06x} ({})", name, value, details),
+        None => format!("{} <= {:#06x}", name, value),
+    }
+}
+
 fn sign_extend_i32(value: i32, size: u32) -> i32 {
     let shift = 32 - size;
     ((value << shift) as i32) >> shift
@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 #[derive(Debug, Primitive, PartialEq)]
 #[repr(u8)]
 pub enum Keys {
@@ -16,18 +18,152 @@ pub enum Keys {
 pub const NUM_KEYS: usize = 10;
 pub const KEYINPUT_ALL_RELEASED: u16 = 0b1111111111;
 
-#[derive(Debug, Primitive, PartialEq)]
-#[repr(u8)]
-pub enum KeyState {
-    Pressed = 0,
-    Released = 1,
+bitflags! {
+    /// The keys that are held down, unlike KEYINPUT a set bit means the key is pressed
+    #[derive(Default)]
+    pub struct KeyState: u16 {
+        const A = 1 << 0;
+        const B = 1 << 1;
+        const SELECT = 1 << 2;
+        const START = 1 << 3;
+        const RIGHT = 1 << 4;
+        const LEFT = 1 << 5;
+        const UP = 1 << 6;
+        const DOWN = 1 << 7;
+        const R = 1 << 8;
+        const L = 1 << 9;
+    }
+}
+
+impl KeyState {
+    /// Converts to the active low KEYINPUT value
+    pub fn to_keyinput(self) -> u16 {
+        !self.bits() & KEYINPUT_ALL_RELEASED
+    }
+
+    pub fn from_keyinput(keyinput: u16) -> KeyState {
+        KeyState::from_bits_truncate(!keyinput)
+    }
+}
+
+impl From<Keys> for KeyState {
+    fn from(key: Keys) -> KeyState {
+        KeyState::from_bits_truncate(1 << key as u16)
+    }
+}
+
+/// Writable bits of KEYCNT
+pub const KEYCNT_MASK: u16 = 0xc3ff;
+
+/// Checks the key state against the interrupt condition configured in KEYCNT
+///
+/// Bit 14 enables the interrupt, bit 15 selects between requiring any (0) or all (1)
+/// of the keys selected by bits 0-9 to be pressed.
+pub fn irq_condition(keyinput: u16, keycnt: u16) -> bool {
+    if keycnt & (1 << 14) == 0 {
+        return false;
+    }
+    let selected = keycnt & KEYINPUT_ALL_RELEASED;
+    let pressed = !keyinput & selected;
+    if keycnt & (1 << 15) != 0 {
+        selected != 0 && pressed == selected
+    } else {
+        pressed != 0
+    }
+}
+
+/// A key state (KEYINPUT value) to apply once the emulation reaches a frame and scanline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScheduledInput {
+    pub frame: usize,
+    pub scanline: usize,
+    pub keyinput: u16,
+}
+
+/// Key state changes queued ahead of time, for input that must land on a specific scanline
+/// (TAS movies, games that poll the keypad mid-frame).
+#[derive(Debug, Default, Clone)]
+pub struct InputSchedule {
+    queue: VecDeque<ScheduledInput>,
 }
 
-impl Into<bool> for KeyState {
-    fn into(self) -> bool {
-        match self {
-            KeyState::Pressed => false,
-            KeyState::Released => true,
+impl InputSchedule {
+    /// Inputs scheduled for the same frame and scanline are applied in the order they were pushed
+    pub fn push(&mut self, input: ScheduledInput) {
+        let key = (input.frame, input.scanline);
+        let index = self
+            .queue
+            .iter()
+            .position(|other| (other.frame, other.scanline) > key)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(index, input);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Removes the inputs that are due at `frame` and `scanline`, returns the latest of them
+    pub fn take_due(&mut self, frame: usize, scanline: usize) -> Option<u16> {
+        let mut keyinput = None;
+        while let Some(input) = self.queue.front() {
+            if (input.frame, input.scanline) > (frame, scanline) {
+                break;
+            }
+            keyinput = Some(input.keyinput);
+            self.queue.pop_front();
         }
+        keyinput
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keypad_irq_condition() {
+        let pressed = |keys: Vec<Keys>| {
+            keys.into_iter()
+                .fold(KEYINPUT_ALL_RELEASED, |keyinput, key| {
+                    keyinput & !(1 << key as u16)
+                })
+        };
+        // A+B+Select+Start, the BIOS soft reset combo
+        let combo = 0b1111;
+        let and_mode = 1 << 15 | 1 << 14 | combo;
+        let or_mode = 1 << 14 | combo;
+
+        assert!(!irq_condition(
+            pressed(vec![Keys::ButtonA, Keys::ButtonB]),
+            and_mode
+        ));
+        let all = pressed(vec![
+            Keys::ButtonA,
+            Keys::ButtonB,
+            Keys::Select,
+            Keys::Start,
+        ]);
+        assert!(irq_condition(all, and_mode));
+        assert!(irq_condition(pressed(vec![Keys::Start]), or_mode));
+        assert!(!irq_condition(pressed(vec![Keys::Up]), or_mode));
+        // the interrupt is disabled
+        assert!(!irq_condition(all, combo));
+    }
+
+    #[test]
+    fn test_key_state_polarity() {
+        let keys = KeyState::A | KeyState::from(Keys::Start);
+        assert_eq!(keys.to_keyinput(), 0b11_1111_0110);
+        assert_eq!(KeyState::from_keyinput(keys.to_keyinput()), keys);
+        assert_eq!(KeyState::default().to_keyinput(), KEYINPUT_ALL_RELEASED);
     }
 }
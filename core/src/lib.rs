@@ -43,6 +43,7 @@ pub use arm7tdmi::disass;
 mod bios;
 pub mod cartridge;
 pub mod gpu;
+pub use gpu::OutputFormat;
 mod sched;
 pub mod sound;
 pub mod sysbus;
@@ -52,14 +53,29 @@ pub mod iodev;
 pub use interrupt::Interrupt;
 pub use interrupt::SharedInterruptFlags;
 pub mod gba;
-pub use gba::GameBoyAdvance;
+pub use gba::{GameBoyAdvance, GameBoyAdvanceBuilder};
 pub mod bus;
+pub mod cheats;
+pub mod difftest;
 pub mod dma;
+pub mod gpu_hooks;
+pub mod idle_loop;
 pub mod keypad;
+pub mod logging;
+pub mod meminit;
+pub mod movie;
+pub mod sio;
 pub mod timer;
 pub use bus::*;
 mod mgba_debug;
-pub(crate) mod overrides;
+pub mod overrides;
+pub mod perf;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pacing;
+pub mod record;
+pub mod rewind;
+pub mod savestate_file;
+pub mod screenshot;
 
 #[cfg(feature = "gdb")]
 pub mod gdb;
@@ -67,32 +83,108 @@ pub mod gdb;
 #[cfg(feature = "debugger")]
 pub mod debugger;
 
+#[cfg(feature = "scripting")]
+pub mod scripting;
+
+/// Implemented by frontends to receive the picture, so the core doesn't depend on a windowing library.
+/// With the `no_video_interface` feature, frames are pulled with `GameBoyAdvance::get_frame_buffer`.
 #[cfg(not(feature = "no_video_interface"))]
 pub trait VideoInterface {
+    /// The frame buffer format this frontend wants to be handed
+    fn output_format(&self) -> OutputFormat {
+        OutputFormat::Xrgb8888
+    }
+
+    /// Called once per frame, at the start of vblank, for all the 32bit output formats.
+    /// `buffer` holds `DISPLAY_WIDTH * DISPLAY_HEIGHT` pixels in rows from the top-left corner.
     #[allow(unused_variables)]
     fn render(&mut self, buffer: &[u32]) {}
+
+    /// Called instead of `render` when the frontend asked for `OutputFormat::Rgb565`
+    #[allow(unused_variables)]
+    fn render_rgb565(&mut self, buffer: &[u16]) {}
 }
 
 pub type StereoSample<T> = (T, T);
 
+/// Implemented by frontends to play the sound, the core resamples its output to the rate asked for.
 pub trait AudioInterface {
+    /// The sample rate of the host audio device, queried once when the emulator is created
     fn get_sample_rate(&self) -> i32 {
         44100
     }
 
-    /// Pushes a stereo sample into the audio device
-    /// Sample should be normilized to siged 16bit values
-    /// Note: It is not guarentied that the sample will be played
+    /// Pushes a stereo sample into the audio device, as a `[left, right]` pair of signed 16bit values.
+    /// Called many times during `GameBoyAdvance::frame`, so implementations should only buffer
+    /// the samples (see `util::audio::AudioRingBuffer`).
+    /// Note: It is not guaranteed that the sample will be played
     #[allow(unused_variables)]
     fn push_sample(&mut self, samples: &[i16]) {}
 }
 
 pub trait InputInterface {
+    /// Returns the KEYINPUT value, a cleared bit means the key is pressed. Called once per frame.
     fn poll(&mut self) -> u16 {
         keypad::KEYINPUT_ALL_RELEASED
     }
 }
 
+/// The host end of the serial port in UART, normal and general purpose modes
+pub trait SerialInterface {
+    /// Called for every byte the GBA transmits in UART mode
+    fn send(&mut self, byte: u8);
+
+    /// Returns the next byte received from the host in UART mode, if there is one
+    fn receive(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Exchanges the `bits` (8 or 32) bits of a normal mode transfer, returns the data the other
+    /// end sent. Without `internal_clock` the other end drives the clock, and `None` means it
+    /// didn't run the transfer yet, it is retried until it does.
+    fn transfer(&mut self, _data: u32, bits: u32, internal_clock: bool) -> Option<u32> {
+        if internal_clock {
+            Some(!0 >> (32 - bits))
+        } else {
+            None
+        }
+    }
+
+    /// Called in general purpose mode when the game writes RCNT. Bits 0-3 are SC, SD, SI and SO,
+    /// `outputs` has the bits of the pins the GBA drives.
+    fn set_gpio(&mut self, _levels: u8, _outputs: u8) {}
+
+    /// The levels of SC, SD, SI and SO as driven by the other end, the pins are pulled up
+    fn gpio(&mut self) -> u8 {
+        0xf
+    }
+}
+
+/// Connects the serial ports of up to four emulators in multiplayer mode.
+/// Player 0 is the parent, it starts the transfers, the others are its children.
+pub trait LinkTransport {
+    /// This emulator's position on the link, 0 to 3
+    fn player_id(&self) -> usize;
+
+    /// Returns true when the other players are connected
+    fn is_ready(&self) -> bool;
+
+    /// Called when the game writes SIOMLT_SEND, the value this player sends in the next transfer
+    fn set_send_data(&mut self, data: u16);
+
+    /// Called on the parent when the game starts a transfer
+    fn start_transfer(&mut self);
+
+    /// Returns the data of the 4 players once a transfer completed, missing players send 0xffff
+    fn poll_transfer(&mut self) -> Option<[u16; 4]>;
+}
+
+/// The host end of the cartridge rumble motor
+pub trait RumbleInterface {
+    /// Called at the end of a frame when the game turned the motor on or off during it
+    fn set_rumble(&mut self, enabled: bool);
+}
+
 #[derive(Debug)]
 pub enum GBAError {
     IO(::std::io::Error),
@@ -137,13 +229,25 @@ impl From<zip::result::ZipError> for GBAError {
 pub mod prelude {
     pub use super::arm7tdmi;
     pub use super::cartridge::{Cartridge, GamepakBuilder};
+    pub use super::cheats::{CheatEngine, CheatFormat};
     #[cfg(feature = "debugger")]
     pub use super::debugger::Debugger;
-    pub use super::gpu::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+    #[cfg(feature = "debugger")]
+    pub use super::gba::FrameHook;
+    pub use super::gpu::{FrameSkip, OutputFormat, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+    pub use super::gpu_hooks::GpuHook;
+    pub use super::keypad::KeyState;
+    pub use super::record::Recording;
+    pub use super::screenshot::Screenshot;
+    #[cfg(feature = "scripting")]
+    pub use super::scripting::Script;
     pub use super::util::{read_bin_file, write_bin_file};
     pub use super::Bus;
     #[cfg(not(feature = "no_video_interface"))]
     pub use super::VideoInterface;
-    pub use super::{AudioInterface, InputInterface, StereoSample};
-    pub use super::{GBAError, GBAResult, GameBoyAdvance};
+    pub use super::{
+        AudioInterface, InputInterface, LinkTransport, RumbleInterface, SerialInterface,
+        StereoSample,
+    };
+    pub use super::{GBAError, GBAResult, GameBoyAdvance, GameBoyAdvanceBuilder};
 }
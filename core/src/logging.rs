@@ -0,0 +1,184 @@
+//! Log targets of the emulated subsystems, and a logger that keeps the records for the frontend.
+//!
+//! Every subsystem logs to its own target instead of its module path, so `RUST_LOG=dma=trace`
+//! (or `LogFilter`) enables the traces of the DMA channels only.
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+pub const CPU: &str = "arm7tdmi";
+/// The system bus and the IO registers
+pub const BUS: &str = "bus";
+pub const DMA: &str = "dma";
+pub const GPU: &str = "gpu";
+pub const APU: &str = "apu";
+/// The serial port and the link cable
+pub const SIO: &str = "sio";
+pub const TIMER: &str = "timer";
+/// The ROM loading, the save memory and the cartridge peripherals
+pub const CARTRIDGE: &str = "cartridge";
+
+/// Which records are enabled, in the `RUST_LOG` syntax: a default level and levels by target,
+/// e.g `warn,dma=trace,gpu=debug`. Without a default level, info and above are enabled.
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> LogFilter {
+        LogFilter {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFilter, String> {
+        let mut filter = LogFilter::default();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(target), Some(level)) => {
+                    let level = level
+                        .parse()
+                        .map_err(|_| format!("invalid log level {:?}", level))?;
+                    filter.targets.push((target.to_string(), level));
+                }
+                // a lone level is the default, a lone target enables everything for it
+                (Some(word), None) => match word.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter.targets.push((word.to_string(), LevelFilter::Trace)),
+                },
+                _ => unreachable!(),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl LogFilter {
+    /// The longest target prefix in the filter decides, the default level otherwise
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        let filter = self
+            .targets
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |&(_, filter)| filter);
+        level <= filter
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|&(_, filter)| filter)
+            .fold(self.default, cmp::max)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturedRecord {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for CapturedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:5} [{}] {}", self.level, self.target, self.message)
+    }
+}
+
+/// A logger that keeps the last records in memory, for frontends that show the log themselves
+pub struct LogCapture {
+    filter: LogFilter,
+    capacity: usize,
+    records: Mutex<VecDeque<CapturedRecord>>,
+}
+
+impl LogCapture {
+    /// Keeps up to `capacity` records, the oldest ones are dropped first
+    pub fn new(filter: LogFilter, capacity: usize) -> LogCapture {
+        LogCapture {
+            filter,
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Sets it as the logger of the process, which can only be done once
+    pub fn install(self) -> Result<&'static LogCapture, SetLoggerError> {
+        let capture: &'static LogCapture = Box::leak(Box::new(self));
+        log::set_logger(capture)?;
+        log::set_max_level(capture.filter.max_level());
+        Ok(capture)
+    }
+
+    /// Takes the records logged since the last call
+    pub fn drain(&self) -> Vec<CapturedRecord> {
+        match self.records.lock() {
+            Ok(mut records) => records.drain(..).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Log for LogCapture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata.target(), metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) || self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut records) = self.records.lock() {
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            records.push_back(CapturedRecord {
+                level: record.level(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_filter() {
+        let filter: LogFilter = "warn,dma=trace,arm7tdmi=debug,arm7tdmi::hle=off"
+            .parse()
+            .unwrap();
+        assert!(filter.enabled(DMA, Level::Trace));
+        assert!(filter.enabled(CPU, Level::Debug));
+        assert!(!filter.enabled(CPU, Level::Trace));
+        assert!(!filter.enabled("arm7tdmi::hle", Level::Error));
+        assert!(filter.enabled(GPU, Level::Warn));
+        assert!(!filter.enabled(GPU, Level::Info));
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+        assert!("dma=loud".parse::<LogFilter>().is_err());
+
+        // an empty filter is the default one
+        let filter: LogFilter = "".parse().unwrap();
+        assert!(filter.enabled(GPU, Level::Info));
+        assert!(!filter.enabled(GPU, Level::Debug));
+        assert_eq!(filter.max_level(), LogFilter::default().max_level());
+    }
+}
@@ -0,0 +1,138 @@
+/// Power-on contents of the RAMs
+///
+/// Real hardware powers on with RAM in an unpredictable state, which a few games end up reading.
+/// Filling it from a fixed pattern or seed keeps those games reproducible across runs and machines.
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryInit {
+    /// All zeroes, the default
+    Zero,
+    /// Every byte set to the same value
+    Pattern(u8),
+    /// Pseudo-random bytes generated from a seed
+    Seed(u64),
+}
+
+impl Default for MemoryInit {
+    fn default() -> MemoryInit {
+        MemoryInit::Zero
+    }
+}
+
+impl MemoryInit {
+    /// Returns a filler that generates the bytes for all the memories in turn, so the same
+    /// seed always produces the same memory contents as long as they are filled in the same order.
+    pub fn filler(self) -> MemoryFiller {
+        let rng_state = match self {
+            // xorshift can't be seeded with zero
+            MemoryInit::Seed(seed) => seed | 1,
+            _ => 0,
+        };
+        MemoryFiller {
+            init: self,
+            rng_state,
+        }
+    }
+}
+
+pub struct MemoryFiller {
+    init: MemoryInit,
+    rng_state: u64,
+}
+
+impl MemoryFiller {
+    fn next_random(&mut self) -> u64 {
+        // xorshift64*
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn fill(&mut self, memory: &mut [u8]) {
+        match self.init {
+            MemoryInit::Zero => memory.iter_mut().for_each(|b| *b = 0),
+            MemoryInit::Pattern(value) => memory.iter_mut().for_each(|b| *b = value),
+            MemoryInit::Seed(_) => {
+                for chunk in memory.chunks_mut(8) {
+                    let bytes = self.next_random().to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for MemoryInit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryInit::Zero => write!(f, "zero"),
+            MemoryInit::Pattern(value) => write!(f, "pattern:{:#04x}", value),
+            MemoryInit::Seed(seed) => write!(f, "seed:{}", seed),
+        }
+    }
+}
+
+/// Parses `zero`, `pattern:<byte>` or `seed:<number>`, numbers may be given in hex with a 0x prefix
+impl FromStr for MemoryInit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse_number(s: &str) -> Result<u64, String> {
+            let result = if s.starts_with("0x") {
+                u64::from_str_radix(&s[2..], 16)
+            } else {
+                s.parse()
+            };
+            result.map_err(|e| format!("invalid number {:?}: {}", s, e))
+        }
+
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("zero"), None) => Ok(MemoryInit::Zero),
+            (Some("pattern"), Some(value)) => {
+                let value = parse_number(value)?;
+                if value > 0xff {
+                    return Err(format!("pattern {:#x} doesn't fit in a byte", value));
+                }
+                Ok(MemoryInit::Pattern(value as u8))
+            }
+            (Some("seed"), Some(seed)) => Ok(MemoryInit::Seed(parse_number(seed)?)),
+            _ => Err(format!(
+                "invalid memory init {:?}, expected zero, pattern:<byte> or seed:<number>",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_is_deterministic() {
+        let mut a = [0u8; 37];
+        let mut b = [0u8; 37];
+        MemoryInit::Seed(1234).filler().fill(&mut a);
+        MemoryInit::Seed(1234).filler().fill(&mut b);
+        assert_eq!(a, b);
+        assert!(a.iter().any(|&x| x != 0));
+
+        MemoryInit::Seed(4321).filler().fill(&mut b);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("zero".parse(), Ok(MemoryInit::Zero));
+        assert_eq!("pattern:0xff".parse(), Ok(MemoryInit::Pattern(0xff)));
+        assert_eq!("seed:42".parse(), Ok(MemoryInit::Seed(42)));
+        assert!("pattern:256".parse::<MemoryInit>().is_err());
+        assert!("random".parse::<MemoryInit>().is_err());
+    }
+}
@@ -1,6 +1,4 @@
 /// mGBA 0.8.1 Debug peripheral support
-use std::str;
-
 use log::log;
 use log::Level;
 
@@ -29,7 +27,7 @@ impl DebugPort {
     pub fn is_debug_access(x: u32) -> bool {
         x == REG_DEBUG_ENABLE
             || x == REG_DEBUG_FLAGS
-            || (x >= REG_DEBUG_STRING && x <= REG_DEBUG_STRING + (DEBUG_STRING_SIZE as u32))
+            || (x >= REG_DEBUG_STRING && x < REG_DEBUG_STRING + (DEBUG_STRING_SIZE as u32))
     }
 
     pub fn read(&mut self, addr: u32) -> u16 {
@@ -39,7 +37,7 @@ impl DebugPort {
         match addr {
             REG_DEBUG_ENABLE => 0x1DEA,
             REG_DEBUG_FLAGS => self.flags.0,
-            x if x >= REG_DEBUG_STRING && x <= REG_DEBUG_STRING + (DEBUG_STRING_SIZE as u32) => {
+            x if x >= REG_DEBUG_STRING && x < REG_DEBUG_STRING + (DEBUG_STRING_SIZE as u32) => {
                 self.debug_string.read_16(addr - REG_DEBUG_STRING)
             }
             _ => 0,
@@ -58,7 +56,7 @@ impl DebugPort {
                     self.debug();
                 }
             }
-            x if x >= REG_DEBUG_STRING && x <= REG_DEBUG_STRING + (DEBUG_STRING_SIZE as u32) => {
+            x if x >= REG_DEBUG_STRING && x < REG_DEBUG_STRING + (DEBUG_STRING_SIZE as u32) => {
                 if self.enable {
                     self.debug_string.write_16(addr - REG_DEBUG_STRING, value);
                 }
@@ -69,15 +67,19 @@ impl DebugPort {
 
     fn debug(&mut self) {
         if self.flags.send() {
-            let message = str::from_utf8(&self.debug_string)
-                .expect("Failed to parse log message to valid utf8");
+            // the message is null terminated, games don't always clear the rest of the buffer
+            let len = self
+                .debug_string
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(DEBUG_STRING_SIZE);
+            let message = String::from_utf8_lossy(&self.debug_string[..len]);
 
             let level: Level = match self.flags.level() {
                 0 | 1 => Level::Error,
                 2 => Level::Warn,
                 3 => Level::Info,
-                4 => Level::Debug,
-                _ => panic!("invalid log level"),
+                _ => Level::Debug,
             };
 
             log!(level, "[mGBA mLOG]: {}", message);
@@ -0,0 +1,171 @@
+/// Input movies, the keys of every frame recorded from a save state so a session can be
+/// replayed frame by frame (TAS work, reproducible bug reports).
+///
+/// The emulation is deterministic given the same inputs, except for the real-time clock of the
+/// cartridge, so while a movie is recorded or played the RTC is driven by the frame count,
+/// starting from the time it showed when the recording started.
+/// Inputs that don't go through KEYINPUT (the solar sensor, the gyro and tilt sensors, scheduled
+/// mid-frame inputs) are not recorded.
+use std::fs;
+use std::path::Path;
+
+use bincode;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use super::gpu::consts::CYCLES_FULL_REFRESH;
+
+/// Movie files start with this magic, followed by the little endian format version and the
+/// deflate compressed `Movie`
+const MOVIE_MAGIC: &[u8; 4] = b"RBAM";
+const MOVIE_VERSION: u32 = 1;
+
+/// Cycles per second
+const CPU_CLOCK: i64 = 16 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// The game code of the cartridge the movie was recorded with
+    pub game_code: String,
+    /// The save state the movie starts from
+    pub start_state: Vec<u8>,
+    /// The RTC time at the first frame, in milliseconds since the unix epoch
+    pub rtc_start: i64,
+    /// The KEYINPUT value of every frame
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Number of recorded frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The RTC time at the start of `frame`
+    pub fn rtc_time(&self, frame: usize) -> i64 {
+        self.rtc_start + frame as i64 * CYCLES_FULL_REFRESH as i64 * 1000 / CPU_CLOCK
+    }
+
+    pub fn encode(&self) -> bincode::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MOVIE_MAGIC);
+        bytes.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        let mut encoder = DeflateEncoder::new(bytes, Compression::default());
+        bincode::serialize_into(&mut encoder, self)?;
+        Ok(encoder.finish()?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> bincode::Result<Movie> {
+        if !bytes.starts_with(MOVIE_MAGIC) || bytes.len() < 8 {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "not a movie file".to_string(),
+            )));
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[4..8]);
+        let version = u32::from_le_bytes(version);
+        if version != MOVIE_VERSION {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "unsupported movie version {} (expected {})",
+                version, MOVIE_VERSION
+            ))));
+        }
+        bincode::deserialize_from(DeflateDecoder::new(&bytes[8..]))
+    }
+
+    pub fn load(path: &Path) -> bincode::Result<Movie> {
+        Movie::decode(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> bincode::Result<()> {
+        fs::write(path, self.encode()?)?;
+        Ok(())
+    }
+}
+
+/// The movie the emulator is recording or playing back
+#[derive(Debug)]
+pub(crate) enum MovieSession {
+    Recording(Movie),
+    Playing { movie: Movie, position: usize },
+}
+
+impl MovieSession {
+    pub fn movie(&self) -> &Movie {
+        match self {
+            MovieSession::Recording(movie) => movie,
+            MovieSession::Playing { movie, .. } => movie,
+        }
+    }
+
+    pub fn into_movie(self) -> Movie {
+        match self {
+            MovieSession::Recording(movie) => movie,
+            MovieSession::Playing { movie, .. } => movie,
+        }
+    }
+
+    /// Number of frames since the start of the movie
+    pub fn position(&self) -> usize {
+        match self {
+            MovieSession::Recording(movie) => movie.frames.len(),
+            MovieSession::Playing { position, .. } => *position,
+        }
+    }
+
+    /// Takes the KEYINPUT the input devices reported for the next frame and returns the one to
+    /// run the frame with, `None` once the playback reached the end of the movie
+    pub fn next_frame(&mut self, keyinput: u16) -> Option<u16> {
+        match self {
+            MovieSession::Recording(movie) => {
+                movie.frames.push(keyinput);
+                Some(keyinput)
+            }
+            MovieSession::Playing { movie, position } => {
+                let keyinput = movie.frames.get(*position).copied();
+                if keyinput.is_some() {
+                    *position += 1;
+                }
+                keyinput
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movie() {
+        let movie = Movie {
+            game_code: "BPEE".to_string(),
+            start_state: vec![1, 2, 3],
+            rtc_start: 1_000_000,
+            frames: vec![0x3ff, 0x3fe, 0x3fe, 0x3f7],
+        };
+        let bytes = movie.encode().unwrap();
+        assert_eq!(Movie::decode(&bytes).unwrap(), movie);
+        assert!(Movie::decode(&bytes[..6]).is_err());
+        // 60 frames are a bit over a second
+        assert_eq!(movie.rtc_time(60), 1_000_000 + 1004);
+
+        let mut session = MovieSession::Playing { movie, position: 0 };
+        assert_eq!(session.next_frame(0), Some(0x3ff));
+        assert_eq!(session.next_frame(0), Some(0x3fe));
+        assert_eq!(session.next_frame(0), Some(0x3fe));
+        assert_eq!(session.next_frame(0), Some(0x3f7));
+        assert_eq!(session.next_frame(0), None);
+        assert_eq!(session.position(), 4);
+
+        let mut session = MovieSession::Recording(session.into_movie());
+        assert_eq!(session.next_frame(0x3fb), Some(0x3fb));
+        assert_eq!(session.movie().frames.len(), 5);
+    }
+}
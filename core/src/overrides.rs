@@ -1,23 +1,48 @@
+//! The built-in database of per-game settings, for the games the automatic detection gets wrong.
+//! Entries are keyed by the game code from the cartridge header and come from `overrides.yaml`.
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
 use yaml_rust::YamlLoader;
 
-use super::cartridge::BackupType;
+use super::cartridge::{BackupType, GpioDeviceType};
 
 #[derive(Debug)]
 pub struct GameOverride {
+    name: Option<String>,
     force_rtc: bool,
     save_type: Option<BackupType>,
+    /// Sensors and rumble on the gpio port, other than a plain RTC
+    gpio_device: Option<GpioDeviceType>,
+    tilt_sensor: bool,
+    /// Address of the loop the game spins in while waiting for an interrupt
+    idle_loop: Option<u32>,
+    /// Reads past the end of the ROM see the ROM repeated, the Classic NES Series checks for it
+    mirror_rom: bool,
 }
 
 impl GameOverride {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(String::as_str)
+    }
     pub fn force_rtc(&self) -> bool {
         self.force_rtc
     }
     pub fn save_type(&self) -> Option<BackupType> {
         self.save_type
     }
+    pub fn gpio_device(&self) -> Option<GpioDeviceType> {
+        self.gpio_device
+    }
+    pub fn tilt_sensor(&self) -> bool {
+        self.tilt_sensor
+    }
+    pub fn idle_loop(&self) -> Option<u32> {
+        self.idle_loop
+    }
+    pub fn mirror_rom(&self) -> bool {
+        self.mirror_rom
+    }
 }
 
 lazy_static! {
@@ -42,9 +67,24 @@ lazy_static! {
                 None
             };
 
+            let gpio_device = game["gpio"].as_str().map(|gpio| {
+                GpioDeviceType::try_from(gpio)
+                    .unwrap_or_else(|e| panic!("{}: invalid gpio device: {}", game_code, e))
+            });
+            let tilt_sensor = game["tilt"].as_bool().unwrap_or(false);
+
+            let idle_loop = game["idle_loop"].as_i64().map(|addr| addr as u32);
+            let mirror_rom = game["mirror_rom"].as_bool().unwrap_or(false);
+            let name = game["name"].as_str().map(String::from);
+
             let game_overrride = GameOverride {
+                name,
                 force_rtc,
                 save_type,
+                gpio_device,
+                tilt_sensor,
+                idle_loop,
+                mirror_rom,
             };
             m.insert(game_code, game_overrride);
         }
@@ -53,6 +93,7 @@ lazy_static! {
     };
 }
 
+/// Looks up the overrides for a game code, e.g `BPEE`
 pub fn get_game_overrides(game_code: &str) -> Option<&GameOverride> {
     GAME_OVERRIDES.get(game_code)
 }
@@ -0,0 +1,175 @@
+/// Run control for frontends: keeps the emulation at the speed of the real hardware, with
+/// fast-forward and pause.
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::gpu::consts::CYCLES_FULL_REFRESH;
+
+pub const CPU_CLOCK_HZ: f64 = 16_777_216.0;
+/// The refresh rate of the GBA screen, about 59.7275 fps
+pub const GBA_FPS: f64 = CPU_CLOCK_HZ / CYCLES_FULL_REFRESH as f64;
+
+/// Sleeping is only accurate to a millisecond or so, the rest of the wait is spent spinning
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FastForward {
+    Off,
+    X2,
+    X4,
+    Unlimited,
+}
+
+impl FastForward {
+    /// None means as fast as possible
+    fn multiplier(self) -> Option<u32> {
+        match self {
+            FastForward::Off => Some(1),
+            FastForward::X2 => Some(2),
+            FastForward::X4 => Some(4),
+            FastForward::Unlimited => None,
+        }
+    }
+
+    /// The next speed, for a key that cycles through them
+    pub fn next(self) -> FastForward {
+        match self {
+            FastForward::Off => FastForward::X2,
+            FastForward::X2 => FastForward::X4,
+            FastForward::X4 => FastForward::Unlimited,
+            FastForward::Unlimited => FastForward::Off,
+        }
+    }
+}
+
+impl Default for FastForward {
+    fn default() -> FastForward {
+        FastForward::Off
+    }
+}
+
+/// Paces calls to `GameBoyAdvance::frame`:
+///
+/// ```ignore
+/// loop {
+///     if !pacer.is_paused() {
+///         gba.frame();
+///     }
+///     pacer.wait();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    frame_duration: Duration,
+    fast_forward: FastForward,
+    paused: bool,
+    next_frame: Instant,
+}
+
+impl Default for FramePacer {
+    fn default() -> FramePacer {
+        FramePacer::new(GBA_FPS)
+    }
+}
+
+impl FramePacer {
+    pub fn new(fps: f64) -> FramePacer {
+        FramePacer {
+            frame_duration: Duration::from_secs_f64(1.0 / fps),
+            fast_forward: FastForward::Off,
+            paused: false,
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: FastForward) {
+        self.fast_forward = fast_forward;
+        self.next_frame = Instant::now();
+    }
+
+    pub fn fast_forward(&self) -> FastForward {
+        self.fast_forward
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        // don't race to catch up with the time spent paused
+        self.next_frame = Instant::now();
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused {
+            self.pause();
+        } else {
+            self.resume();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Duration of a frame at the current speed, None when running as fast as possible
+    pub fn frame_duration(&self) -> Option<Duration> {
+        if self.paused {
+            return Some(self.frame_duration);
+        }
+        self.fast_forward
+            .multiplier()
+            .map(|multiplier| self.frame_duration / multiplier)
+    }
+
+    /// Blocks until the next frame is due. While paused it still waits a frame, so the frontend
+    /// keeps handling its events at the usual rate.
+    pub fn wait(&mut self) {
+        let frame_duration = match self.frame_duration() {
+            Some(duration) => duration,
+            None => return,
+        };
+        // frames are due at fixed intervals, so the time spent sleeping too long is made up for
+        self.next_frame += frame_duration;
+        let now = Instant::now();
+        if self.next_frame < now {
+            // the emulation can't keep up, start over instead of running flat out to catch up
+            if now - self.next_frame > frame_duration {
+                self.next_frame = now;
+            }
+            return;
+        }
+        let remaining = self.next_frame - now;
+        if remaining > SPIN_THRESHOLD {
+            thread::sleep(remaining - SPIN_THRESHOLD);
+        }
+        while Instant::now() < self.next_frame {
+            thread::yield_now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_pacer_speed() {
+        let mut pacer = FramePacer::new(1000.0);
+        let start = Instant::now();
+        for _ in 0..20 {
+            pacer.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(19));
+
+        let normal = pacer.frame_duration().unwrap();
+        pacer.set_fast_forward(FastForward::X4);
+        assert_eq!(pacer.frame_duration(), Some(normal / 4));
+        pacer.set_fast_forward(FastForward::Unlimited);
+        assert_eq!(pacer.frame_duration(), None);
+        // pausing waits at the normal speed
+        pacer.pause();
+        assert_eq!(pacer.frame_duration(), Some(normal));
+    }
+}
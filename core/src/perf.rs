@@ -0,0 +1,380 @@
+/// Counts what the emulation spends its time on, for frontends to show a performance overlay.
+///
+/// Off by default, see `GameBoyAdvance::set_perf_counters`. The host time of the gpu, sound and
+/// DMA work is measured around their events, everything else is accounted to the cpu.
+/// A `Profile` keeps these times for every frame of a run, to track the performance of each
+/// component separately.
+use std::fmt::Write;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+use super::gpu::consts::CYCLES_FULL_REFRESH;
+use super::util::{now, Instant};
+
+/// How often the counters are turned into a `PerfReport`, in host time
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subsystem {
+    /// The gpu events, including the scanline rendering
+    Ppu,
+    /// The sound sample events, the mixing happens there
+    Apu,
+    Dma,
+}
+
+/// The host time spent on one frame (or the sum over several), by component
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameTimes {
+    pub total: Duration,
+    pub cpu: Duration,
+    pub ppu: Duration,
+    pub apu: Duration,
+    pub dma: Duration,
+}
+
+impl AddAssign for FrameTimes {
+    fn add_assign(&mut self, other: FrameTimes) {
+        self.total += other.total;
+        self.cpu += other.cpu;
+        self.ppu += other.ppu;
+        self.apu += other.apu;
+        self.dma += other.dma;
+    }
+}
+
+/// The components of `FrameTimes`, in the order the profiles list them
+const COMPONENTS: [(&str, fn(&FrameTimes) -> Duration); 5] = [
+    ("frame", |t| t.total),
+    ("cpu", |t| t.cpu),
+    ("ppu", |t| t.ppu),
+    ("apu", |t| t.apu),
+    ("dma", |t| t.dma),
+];
+
+/// Averages over the last report interval, the times are per frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PerfReport {
+    pub frames_per_second: f64,
+    pub instructions_per_second: f64,
+    /// Cycles the cpu ran per frame, the rest of the `CYCLES_FULL_REFRESH` cycles it was halted
+    /// or waiting for DMA
+    pub cpu_cycles_per_frame: f64,
+    pub frame_time: Duration,
+    pub cpu_time: Duration,
+    pub ppu_time: Duration,
+    pub apu_time: Duration,
+    pub dma_time: Duration,
+}
+
+impl PerfReport {
+    /// The part of the frame cycles the cpu ran, from 0 to 1
+    pub fn cpu_load(&self) -> f64 {
+        self.cpu_cycles_per_frame / CYCLES_FULL_REFRESH as f64
+    }
+}
+
+/// Statistics of one component over the frames of a profile
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ComponentStats {
+    pub mean: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// 95% of the frames took at most this long
+    pub p95: Duration,
+    /// The part of the total frame time, from 0 to 1
+    pub share: f64,
+}
+
+/// The `FrameTimes` of a run of frames, see `GameBoyAdvance::start_profiling`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    frames: Vec<FrameTimes>,
+    length: usize,
+}
+
+impl Profile {
+    /// A profile of the next `length` frames
+    pub fn new(length: usize) -> Profile {
+        Profile {
+            frames: Vec::with_capacity(length),
+            length,
+        }
+    }
+
+    pub fn push(&mut self, times: FrameTimes) {
+        if !self.is_complete() {
+            self.frames.push(times);
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.frames.len() >= self.length
+    }
+
+    pub fn frames(&self) -> &[FrameTimes] {
+        &self.frames
+    }
+
+    /// The statistics of every component, by name: frame (the whole frame), cpu, ppu, apu and dma
+    pub fn stats(&self) -> Vec<(&'static str, ComponentStats)> {
+        let total: Duration = self.frames.iter().map(|t| t.total).sum();
+        COMPONENTS
+            .iter()
+            .map(|(name, component)| {
+                let mut times: Vec<Duration> = self.frames.iter().map(component).collect();
+                times.sort();
+                let sum: Duration = times.iter().sum();
+                let stats = if times.is_empty() {
+                    ComponentStats::default()
+                } else {
+                    ComponentStats {
+                        mean: sum / times.len() as u32,
+                        min: times[0],
+                        max: times[times.len() - 1],
+                        p95: times[(times.len() * 95 + 99) / 100 - 1],
+                        share: if total == Duration::default() {
+                            0.0
+                        } else {
+                            sum.as_secs_f64() / total.as_secs_f64()
+                        },
+                    }
+                };
+                (*name, stats)
+            })
+            .collect()
+    }
+
+    /// The statistics as a text table, the times are in milliseconds
+    pub fn to_table(&self) -> String {
+        let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+        let mut table = format!(
+            "{} frames\n{:<10}{:>10}{:>10}{:>10}{:>10}{:>8}\n",
+            self.frames.len(),
+            "component",
+            "mean",
+            "min",
+            "max",
+            "p95",
+            "share"
+        );
+        for (name, stats) in self.stats() {
+            writeln!(
+                table,
+                "{:<10}{:>10.3}{:>10.3}{:>10.3}{:>10.3}{:>7.1}%",
+                name,
+                millis(stats.mean),
+                millis(stats.min),
+                millis(stats.max),
+                millis(stats.p95),
+                stats.share * 100.0
+            )
+            .unwrap();
+        }
+        table
+    }
+
+    /// The statistics as JSON, the times are in microseconds:
+    /// `{"frames": 600, "components": {"cpu": {"mean_us": 2510.3, ...}, ...}}`
+    pub fn to_json(&self) -> String {
+        let micros = |time: Duration| time.as_secs_f64() * 1_000_000.0;
+        let components: Vec<String> = self
+            .stats()
+            .iter()
+            .map(|(name, stats)| {
+                format!(
+                    "\"{}\": {{\"mean_us\": {:.3}, \"min_us\": {:.3}, \"max_us\": {:.3}, \"p95_us\": {:.3}, \"share\": {:.4}}}",
+                    name,
+                    micros(stats.mean),
+                    micros(stats.min),
+                    micros(stats.max),
+                    micros(stats.p95),
+                    stats.share
+                )
+            })
+            .collect();
+        format!(
+            "{{\"frames\": {}, \"components\": {{{}}}}}",
+            self.frames.len(),
+            components.join(", ")
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PerfCounters {
+    interval_start: Instant,
+    frames: u32,
+    instructions: u64,
+    cpu_cycles: u64,
+    /// The sum of the frames of the interval
+    times: FrameTimes,
+    /// The gpu, sound and DMA time of the frame being run
+    frame: FrameTimes,
+    report: Option<PerfReport>,
+    profile: Option<Profile>,
+}
+
+impl Default for PerfCounters {
+    fn default() -> PerfCounters {
+        PerfCounters {
+            interval_start: now(),
+            frames: 0,
+            instructions: 0,
+            cpu_cycles: 0,
+            times: FrameTimes::default(),
+            frame: FrameTimes::default(),
+            report: None,
+            profile: None,
+        }
+    }
+}
+
+impl PerfCounters {
+    #[inline]
+    pub fn add_instruction(&mut self, cycles: usize) {
+        self.instructions += 1;
+        self.cpu_cycles += cycles as u64;
+    }
+
+    #[inline]
+    pub fn add_time(&mut self, subsystem: Subsystem, time: Duration) {
+        match subsystem {
+            Subsystem::Ppu => self.frame.ppu += time,
+            Subsystem::Apu => self.frame.apu += time,
+            Subsystem::Dma => self.frame.dma += time,
+        }
+    }
+
+    /// Accounts a frame that took `frame_time`, and makes a new report once the interval is over
+    pub fn end_frame(&mut self, frame_time: Duration) {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.total = frame_time;
+        frame.cpu = frame_time
+            .checked_sub(frame.ppu + frame.apu + frame.dma)
+            .unwrap_or_default();
+        if let Some(profile) = &mut self.profile {
+            profile.push(frame);
+        }
+        self.frames += 1;
+        self.times += frame;
+
+        let elapsed = self.interval_start.elapsed();
+        if elapsed >= REPORT_INTERVAL {
+            *self = PerfCounters {
+                report: Some(self.make_report(elapsed)),
+                profile: self.profile.take(),
+                ..PerfCounters::default()
+            };
+        }
+    }
+
+    fn make_report(&self, elapsed: Duration) -> PerfReport {
+        let frames = self.frames.max(1);
+        let per_frame = |time: Duration| time / frames;
+        PerfReport {
+            frames_per_second: self.frames as f64 / elapsed.as_secs_f64(),
+            instructions_per_second: self.instructions as f64 / elapsed.as_secs_f64(),
+            cpu_cycles_per_frame: self.cpu_cycles as f64 / frames as f64,
+            frame_time: per_frame(self.times.total),
+            cpu_time: per_frame(self.times.cpu),
+            ppu_time: per_frame(self.times.ppu),
+            apu_time: per_frame(self.times.apu),
+            dma_time: per_frame(self.times.dma),
+        }
+    }
+
+    /// The report of the last complete interval, `None` during the first one
+    pub fn report(&self) -> Option<PerfReport> {
+        self.report
+    }
+
+    /// Number of instructions executed in the current interval
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Starts recording the times of the next `frames` frames
+    pub fn start_profile(&mut self, frames: usize) {
+        self.profile = Some(Profile::new(frames));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Returns the profile once it has all of its frames
+    pub fn take_complete_profile(&mut self) -> Option<Profile> {
+        match &self.profile {
+            Some(profile) if profile.is_complete() => self.profile.take(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_perf_report() {
+        let mut counters = PerfCounters::default();
+        for _ in 0..100 {
+            counters.add_instruction(2);
+        }
+        counters.add_time(Subsystem::Ppu, millis(4));
+        counters.add_time(Subsystem::Dma, millis(2));
+        counters.end_frame(millis(10));
+
+        let report = counters.make_report(millis(500));
+        assert_eq!(report.frames_per_second, 2.0);
+        assert_eq!(report.instructions_per_second, 200.0);
+        assert_eq!(report.cpu_cycles_per_frame, 200.0);
+        assert_eq!(report.cpu_time, millis(4));
+        assert_eq!(report.ppu_time, millis(4));
+        assert_eq!(report.apu_time, Duration::default());
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut counters = PerfCounters::default();
+        counters.start_profile(20);
+        for i in 1..=20 {
+            counters.add_time(Subsystem::Ppu, millis(i));
+            counters.end_frame(millis(4 * i));
+            assert_eq!(counters.take_complete_profile().is_some(), i == 20);
+        }
+        assert!(counters.profile().is_none());
+
+        let mut profile = Profile::new(20);
+        for i in 1..=20 {
+            profile.push(FrameTimes {
+                total: millis(4 * i),
+                cpu: millis(3 * i),
+                ppu: millis(i),
+                ..FrameTimes::default()
+            });
+        }
+        // the extra frames are dropped
+        profile.push(FrameTimes::default());
+        assert_eq!(profile.frames().len(), 20);
+
+        let stats = profile.stats();
+        assert_eq!(stats[1].0, "cpu");
+        assert_eq!(stats[1].1.min, millis(3));
+        assert_eq!(stats[1].1.max, millis(60));
+        assert_eq!(stats[1].1.p95, millis(57));
+        assert_eq!(stats[2].1.mean, Duration::from_micros(10_500));
+        assert_eq!(stats[2].1.share, 0.25);
+
+        assert!(profile.to_table().contains("ppu"));
+        let json = profile.to_json();
+        assert!(json
+            .starts_with("{\"frames\": 20, \"components\": {\"frame\": {\"mean_us\": 42000.000"));
+        assert!(json.ends_with("\"share\": 0.0000}}}"));
+    }
+}
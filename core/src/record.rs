@@ -0,0 +1,161 @@
+/// Dumps the video and audio of the emulation, for gameplay captures, TAS encodes and bug reports
+///
+/// Every emulated frame appends one rgb24 frame to the video and the audio samples produced during
+/// that frame to the audio, so both streams stay in sync whatever speed the emulation runs at.
+/// Encoding the raw streams is left to the frontends.
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::screenshot::Screenshot;
+
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// The files of a completed recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    /// The raw rgb24 240x160 frames, at the refresh rate of the hardware (16777216/280896 fps)
+    pub video: PathBuf,
+    /// 16 bit stereo PCM
+    pub audio: PathBuf,
+    pub frames: usize,
+}
+
+/// Writes a 16 bit stereo WAV file, the sizes in the header are filled in by `finish`
+struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    fn new(mut writer: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        let channels = 2u16;
+        let bytes_per_frame = channels * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // integer PCM
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * bytes_per_frame as u32).to_le_bytes())?;
+        writer.write_all(&bytes_per_frame.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            writer,
+            data_size: 0,
+        })
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.writer.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn with_added_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// A recording in progress, see `GameBoyAdvance::start_recording`
+pub struct Recorder {
+    video: BufWriter<File>,
+    audio: WavWriter<BufWriter<File>>,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    frames: usize,
+}
+
+impl Recorder {
+    /// Writes `<path>.rgb` and `<path>.wav`, `sample_rate` is the rate of the samples passed to
+    /// `write_frame`
+    pub fn start(path: &Path, sample_rate: u32) -> io::Result<Recorder> {
+        let video_path = with_added_extension(path, "rgb");
+        let audio_path = with_added_extension(path, "wav");
+        let video = BufWriter::new(File::create(&video_path)?);
+        let audio = WavWriter::new(BufWriter::new(File::create(&audio_path)?), sample_rate)?;
+        info!("recording to {:?}", path);
+        Ok(Recorder {
+            video,
+            audio,
+            video_path,
+            audio_path,
+            frames: 0,
+        })
+    }
+
+    /// Number of frames recorded so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Appends a frame along with the interleaved stereo samples produced while it was emulated
+    pub fn write_frame(&mut self, frame: &Screenshot, samples: &[i16]) -> io::Result<()> {
+        self.video.write_all(&frame.to_rgb(1))?;
+        self.audio.write_samples(samples)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Completes the files
+    pub fn finish(self) -> io::Result<Recording> {
+        let Recorder {
+            mut video,
+            audio,
+            video_path,
+            audio_path,
+            frames,
+        } = self;
+        video.flush()?;
+        audio.finish()?;
+        info!("recorded {} frames", frames);
+        Ok(Recording {
+            video: video_path,
+            audio: audio_path,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write_samples(&[1, -1, 2, -2]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), WAV_HEADER_SIZE as usize + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &(36u32 + 8).to_le_bytes());
+        assert_eq!(&bytes[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&bytes[40..44], &8u32.to_le_bytes());
+        assert_eq!(&bytes[44..], &[1, 0, 0xff, 0xff, 2, 0, 0xfe, 0xff]);
+    }
+}
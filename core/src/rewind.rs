@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+/// Keeps the machine states of the last few seconds, taken every `interval` frames.
+///
+/// Only the newest state is kept in full, older ones are stored as the deflate compressed xor with
+/// the state that followed them. Consecutive states mostly differ in a few pages of RAM, so the
+/// deltas compress to a small fraction of a full state.
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    interval: usize,
+    capacity: usize,
+    frames_since_snapshot: usize,
+    latest: Option<Vec<u8>>,
+    /// Each delta turns the state after it back into the state before it
+    deltas: VecDeque<Delta>,
+}
+
+#[derive(Debug, Clone)]
+struct Delta {
+    /// Length of the older state, states don't always serialize to the same size
+    len: usize,
+    compressed: Vec<u8>,
+}
+
+fn xor_into(dst: &mut Vec<u8>, src: &[u8]) {
+    if dst.len() < src.len() {
+        dst.resize(src.len(), 0);
+    }
+    for (d, s) in dst.iter_mut().zip(src.iter()) {
+        *d ^= *s;
+    }
+}
+
+impl Delta {
+    fn new(older: &[u8], newer: &[u8]) -> Delta {
+        let mut diff = older.to_vec();
+        xor_into(&mut diff, newer);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&diff).unwrap();
+        Delta {
+            len: older.len(),
+            compressed: encoder.finish().unwrap(),
+        }
+    }
+
+    fn apply(&self, newer: &[u8]) -> Vec<u8> {
+        let mut diff = Vec::new();
+        DeflateDecoder::new(&self.compressed[..])
+            .read_to_end(&mut diff)
+            .unwrap();
+        xor_into(&mut diff, newer);
+        diff.truncate(self.len);
+        diff
+    }
+}
+
+impl RewindBuffer {
+    /// Takes a snapshot every `interval` frames and keeps up to `capacity` of them
+    pub fn new(interval: usize, capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frames_since_snapshot: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Counts a frame, returns true when a snapshot should be pushed
+    pub fn end_frame(&mut self) -> bool {
+        self.frames_since_snapshot += 1;
+        self.frames_since_snapshot >= self.interval
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        self.frames_since_snapshot = 0;
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(Delta::new(&latest, &state));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Removes and returns the newest snapshot
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let state = self.latest.take()?;
+        self.latest = self.deltas.pop_back().map(|delta| delta.apply(&state));
+        self.frames_since_snapshot = 0;
+        Some(state)
+    }
+
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.frames_since_snapshot = 0;
+    }
+
+    /// Memory used by the snapshots, in bytes
+    pub fn size(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |state| state.len());
+        let deltas: usize = self.deltas.iter().map(|d| d.compressed.len()).sum();
+        latest + deltas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewind_buffer() {
+        let mut rewind = RewindBuffer::new(2, 3);
+        assert!(!rewind.end_frame());
+        assert!(rewind.end_frame());
+
+        let states: Vec<Vec<u8>> = vec![vec![1; 100], vec![2; 120], vec![3; 80], vec![4; 100]];
+        for state in states.iter() {
+            rewind.push(state.clone());
+        }
+        // the oldest state was dropped
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop().as_ref(), Some(&states[3]));
+        assert_eq!(rewind.pop().as_ref(), Some(&states[2]));
+        assert_eq!(rewind.pop().as_ref(), Some(&states[1]));
+        assert_eq!(rewind.pop(), None);
+    }
+}
@@ -0,0 +1,298 @@
+/// Save state files for frontends that keep numbered slots per game
+///
+/// A state file starts with a header holding the game code, the time it was saved and a half
+/// size thumbnail of the screen, so a slot can be previewed without restoring it. The rest of
+/// the file is the output of `GameBoyAdvance::save_state`.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::gba::GameBoyAdvance;
+use super::gpu::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use super::screenshot::Screenshot;
+
+const STATE_FILE_MAGIC: &[u8; 4] = b"RBAF";
+const STATE_FILE_VERSION: u32 = 1;
+/// magic, version, game code, timestamp, thumbnail width and height
+const HEADER_SIZE: usize = 4 + 4 + 4 + 8 + 2 + 2;
+
+pub const THUMBNAIL_WIDTH: usize = DISPLAY_WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = DISPLAY_HEIGHT / 2;
+
+/// Number of slots of every game, the slots are numbered from 0
+pub const NUM_SLOTS: usize = 10;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A `THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT` picture, each pixel is the average of 2x2 pixels of
+/// the screen. The pixels are stored in the 15 bit format of the GBA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thumbnail {
+    pub pixels: Vec<u16>,
+}
+
+impl Thumbnail {
+    pub fn from_screenshot(screenshot: &Screenshot) -> Thumbnail {
+        let mut pixels = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        for y in 0..THUMBNAIL_HEIGHT {
+            for x in 0..THUMBNAIL_WIDTH {
+                let mut sum = [0u32; 3];
+                for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let pixel = screenshot.pixels[(2 * y + dy) * DISPLAY_WIDTH + 2 * x + dx];
+                    let [_, r, g, b] = pixel.to_be_bytes();
+                    sum[0] += r as u32;
+                    sum[1] += g as u32;
+                    sum[2] += b as u32;
+                }
+                let [r, g, b] = [(sum[0] / 4) >> 3, (sum[1] / 4) >> 3, (sum[2] / 4) >> 3];
+                pixels.push((b << 10 | g << 5 | r) as u16);
+            }
+        }
+        Thumbnail { pixels }
+    }
+
+    pub fn width(&self) -> usize {
+        THUMBNAIL_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        THUMBNAIL_HEIGHT
+    }
+
+    /// The pixels in the Xrgb8888 format
+    pub fn to_xrgb8888(&self) -> Vec<u32> {
+        self.pixels
+            .iter()
+            .map(|&c| {
+                let expand = |v: u16| {
+                    let v = (v & 0x1f) as u32;
+                    v << 3 | v >> 2
+                };
+                expand(c) << 16 | expand(c >> 5) << 8 | expand(c >> 10)
+            })
+            .collect()
+    }
+}
+
+/// A save state along with its header
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateFile {
+    /// The game code of the cartridge header, empty for states without a header
+    pub game_code: String,
+    /// Seconds since the unix epoch, 0 when unknown
+    pub saved_at: u64,
+    pub thumbnail: Option<Thumbnail>,
+    pub state: Vec<u8>,
+}
+
+impl StateFile {
+    /// Saves the state of `gba` with a thumbnail of the last frame.
+    /// The time is given by the frontend since not every target has a clock.
+    pub fn capture(gba: &GameBoyAdvance, saved_at: u64) -> bincode::Result<StateFile> {
+        Ok(StateFile {
+            game_code: gba.get_game_code(),
+            saved_at,
+            thumbnail: Some(Thumbnail::from_screenshot(&gba.screenshot())),
+            state: gba.save_state()?,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let thumbnail = self.thumbnail.as_ref().map_or(&[][..], |t| &t.pixels);
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + thumbnail.len() * 2 + self.state.len());
+        bytes.extend_from_slice(STATE_FILE_MAGIC);
+        bytes.extend_from_slice(&STATE_FILE_VERSION.to_le_bytes());
+        let mut game_code = [0; 4];
+        for (dst, src) in game_code.iter_mut().zip(self.game_code.bytes()) {
+            *dst = src;
+        }
+        bytes.extend_from_slice(&game_code);
+        bytes.extend_from_slice(&self.saved_at.to_le_bytes());
+        let (width, height) = match self.thumbnail {
+            Some(_) => (THUMBNAIL_WIDTH as u16, THUMBNAIL_HEIGHT as u16),
+            None => (0, 0),
+        };
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        for pixel in thumbnail {
+            bytes.extend_from_slice(&pixel.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.state);
+        bytes
+    }
+
+    /// Parses a state file, files without the header are taken as a bare save state
+    pub fn decode(bytes: &[u8]) -> io::Result<StateFile> {
+        if !bytes.starts_with(STATE_FILE_MAGIC) {
+            return Ok(StateFile {
+                game_code: String::new(),
+                saved_at: 0,
+                thumbnail: None,
+                state: bytes.to_vec(),
+            });
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(invalid_data("truncated state file header".to_string()));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let mut word = [0; 4];
+        word.copy_from_slice(&bytes[4..8]);
+        let version = u32::from_le_bytes(word);
+        if version != STATE_FILE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported state file version {} (expected {})",
+                version, STATE_FILE_VERSION
+            )));
+        }
+        let game_code = String::from_utf8_lossy(&bytes[8..12])
+            .trim_end_matches('\0')
+            .to_string();
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&bytes[12..20]);
+        let saved_at = u64::from_le_bytes(timestamp);
+
+        let (width, height) = (u16_at(20) as usize, u16_at(22) as usize);
+        let thumbnail = match (width, height) {
+            (0, 0) => None,
+            (THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT) => {
+                let end = HEADER_SIZE + width * height * 2;
+                if bytes.len() < end {
+                    return Err(invalid_data("truncated state file thumbnail".to_string()));
+                }
+                Some(Thumbnail {
+                    pixels: (HEADER_SIZE..end).step_by(2).map(u16_at).collect(),
+                })
+            }
+            _ => {
+                return Err(invalid_data(format!(
+                    "unexpected thumbnail size {}x{}",
+                    width, height
+                )))
+            }
+        };
+        let state_start = HEADER_SIZE + width * height * 2;
+        Ok(StateFile {
+            game_code,
+            saved_at,
+            thumbnail,
+            state: bytes[state_start..].to_vec(),
+        })
+    }
+
+    pub fn read(path: &Path) -> io::Result<StateFile> {
+        StateFile::decode(&fs::read(path)?)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+}
+
+/// The state files of one game, `<key>.ss<slot>` and `<key>.autosave` in the state directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateSlots {
+    dir: PathBuf,
+    key: String,
+}
+
+impl StateSlots {
+    /// The files are named after the game code, or `fallback_name` (usually the rom file name)
+    /// for homebrew roms that have no proper game code
+    pub fn new(dir: &Path, game_code: &str, fallback_name: &str) -> StateSlots {
+        let key = if game_code.len() == 4 && game_code.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            game_code.to_string()
+        } else {
+            fallback_name.to_string()
+        };
+        StateSlots {
+            dir: dir.to_path_buf(),
+            key,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn slot_path(&self, slot: usize) -> PathBuf {
+        assert!(slot < NUM_SLOTS, "invalid save state slot {}", slot);
+        self.dir.join(format!("{}.ss{}", self.key, slot))
+    }
+
+    /// The state written when the emulator exits
+    pub fn autosave_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.autosave", self.key))
+    }
+
+    /// The header of every slot that has a state file, the save states themselves are not kept
+    pub fn list(&self) -> Vec<(usize, StateFile)> {
+        (0..NUM_SLOTS)
+            .filter_map(|slot| {
+                let path = self.slot_path(slot);
+                if !path.is_file() {
+                    return None;
+                }
+                match StateFile::read(&path) {
+                    Ok(mut file) => {
+                        file.state = Vec::new();
+                        Some((slot, file))
+                    }
+                    Err(e) => {
+                        warn!("can't read {:?}: {}", path, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_file() {
+        let mut pixels = vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        // the top-left 2x2 block averages to (0x80, 0x40, 0xf8)
+        pixels[0] = 0x00ff_80f8;
+        pixels[1] = 0x0001_00f8;
+        pixels[DISPLAY_WIDTH] = 0x00ff_80f8;
+        pixels[DISPLAY_WIDTH + 1] = 0x0001_00f8;
+        let thumbnail = Thumbnail::from_screenshot(&Screenshot { pixels });
+        assert_eq!(thumbnail.pixels.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        assert_eq!(thumbnail.pixels[0], 0x1f << 10 | 0x08 << 5 | 0x10);
+        assert_eq!(thumbnail.to_xrgb8888()[0], 0x0084_42ff);
+
+        let file = StateFile {
+            game_code: "BPEE".to_string(),
+            saved_at: 1_700_000_000,
+            thumbnail: Some(thumbnail),
+            state: vec![1, 2, 3],
+        };
+        let bytes = file.encode();
+        assert_eq!(
+            bytes.len(),
+            HEADER_SIZE + THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 2 + 3
+        );
+        assert_eq!(StateFile::decode(&bytes).unwrap(), file);
+        assert!(StateFile::decode(&bytes[..HEADER_SIZE + 10]).is_err());
+
+        // save states from before the slots don't have a header
+        let bare = StateFile::decode(&[5, 6, 7]).unwrap();
+        assert_eq!(bare.thumbnail, None);
+        assert_eq!(bare.state, vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn test_slot_paths() {
+        let slots = StateSlots::new(Path::new("states"), "BPEE", "pokemon");
+        assert_eq!(slots.slot_path(3), Path::new("states/BPEE.ss3"));
+        assert_eq!(slots.autosave_path(), Path::new("states/BPEE.autosave"));
+        // homebrew roms often have a blank game code
+        let slots = StateSlots::new(Path::new("states"), "\0\0\0\0", "demo");
+        assert_eq!(slots.key(), "demo");
+    }
+}
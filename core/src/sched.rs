@@ -1,4 +1,3 @@
-use std::cell::Cell;
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
@@ -42,62 +41,41 @@ pub struct Event {
     typ: EventType,
     /// Timestamp in cycles
     time: usize,
-    cancel: Cell<bool>,
+    /// Events due on the same cycle are handled in the order they were scheduled in
+    seq: u64,
 }
 
 impl Event {
-    fn new(typ: EventType, time: usize) -> Event {
-        Event {
-            typ,
-            time,
-            cancel: Cell::new(false),
-        }
+    fn new(typ: EventType, time: usize, seq: u64) -> Event {
+        Event { typ, time, seq }
     }
 
     #[inline]
     fn get_type(&self) -> EventType {
         self.typ
     }
-
-    fn is_canceled(&self) -> bool {
-        self.cancel.get()
-    }
 }
 
+/// Implement custom reverse ordering, so the earliest event is at the top of the heap
 impl Ord for Event {
+    #[inline]
     fn cmp(&self, other: &Self) -> Ordering {
-        self.time.cmp(&other.time).reverse()
+        (self.time, self.seq)
+            .cmp(&(other.time, other.seq))
+            .reverse()
     }
 }
 
-/// Implement custom reverse ordering
 impl PartialOrd for Event {
     #[inline]
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        other.time.partial_cmp(&self.time)
-    }
-
-    #[inline]
-    fn lt(&self, other: &Self) -> bool {
-        other.time < self.time
-    }
-    #[inline]
-    fn le(&self, other: &Self) -> bool {
-        other.time <= self.time
-    }
-    #[inline]
-    fn gt(&self, other: &Self) -> bool {
-        other.time > self.time
-    }
-    #[inline]
-    fn ge(&self, other: &Self) -> bool {
-        other.time >= self.time
+        Some(self.cmp(other))
     }
 }
 
 impl PartialEq for Event {
     fn eq(&self, other: &Self) -> bool {
-        self.time == other.time
+        self.time == other.time && self.seq == other.seq
     }
 }
 
@@ -111,6 +89,7 @@ impl PartialEq for Event {
 pub struct Scheduler {
     timestamp: usize,
     events: BinaryHeap<Event>,
+    next_seq: u64,
 }
 
 pub type SharedScheduler = Shared<Scheduler>;
@@ -120,6 +99,7 @@ impl Scheduler {
         let sched = Scheduler {
             timestamp: 0,
             events: BinaryHeap::with_capacity(NUM_EVENTS),
+            next_seq: 0,
         };
         SharedScheduler::new(sched)
     }
@@ -142,17 +122,18 @@ impl Scheduler {
 
     /// Schedule an event to be executed in `cycles` cycles from now
     pub fn push(&mut self, typ: EventType, cycles: usize) {
-        let event = Event::new(typ, self.timestamp + cycles);
+        let event = Event::new(typ, self.timestamp + cycles, self.next_seq);
+        self.next_seq += 1;
         self.events.push(event);
     }
 
     /// Cancel all events with type `typ`
     /// This method is rather expansive to call
     pub fn cancel(&mut self, typ: EventType) {
-        self.events
-            .iter()
-            .filter(|e| e.typ == typ)
-            .for_each(|e| e.cancel.set(true));
+        // the events are removed right away, so the next event is always one that will be handled
+        let mut events = std::mem::replace(&mut self.events, BinaryHeap::new()).into_vec();
+        events.retain(|e| e.typ != typ);
+        self.events = BinaryHeap::from(events);
     }
 
     pub fn push_gpu_event(&mut self, e: GpuEvent, cycles: usize) {
@@ -174,11 +155,7 @@ impl Scheduler {
             if self.timestamp >= event.time {
                 // remove the event
                 let event = self.events.pop().unwrap_or_else(|| unreachable!());
-                if !event.is_canceled() {
-                    Some((event.get_type(), self.timestamp - event.time))
-                } else {
-                    None
-                }
+                Some((event.get_type(), self.timestamp - event.time))
             } else {
                 None
             }
@@ -295,8 +272,31 @@ mod test {
             .push(EventType::Apu(ApuEvent::Psg4Generate), 72);
 
         assert_eq!(
-            sched.events.pop(),
-            Some(Event::new(EventType::Apu(ApuEvent::Psg2Generate), 13))
+            sched.events.pop().map(|e| (e.get_type(), e.time)),
+            Some((EventType::Apu(ApuEvent::Psg2Generate), 13))
+        );
+    }
+
+    #[test]
+    fn test_scheduler_same_cycle_order() {
+        let mut sched = Scheduler::new_shared();
+        sched.push(EventType::TimerOverflow(1), 10);
+        sched.push(EventType::Gpu(GpuEvent::HBlank), 10);
+        sched.push(EventType::TimerOverflow(0), 10);
+        sched.push(EventType::TimerOverflow(2), 5);
+        sched.cancel(EventType::TimerOverflow(2));
+
+        assert_eq!(sched.get_cycles_to_next_event(), 10);
+        sched.update(10);
+        let order: Vec<EventType> =
+            std::iter::from_fn(|| sched.pop_pending_event().map(|(e, _)| e)).collect();
+        assert_eq!(
+            order,
+            vec![
+                EventType::TimerOverflow(1),
+                EventType::Gpu(GpuEvent::HBlank),
+                EventType::TimerOverflow(0),
+            ]
         );
     }
 
@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use super::gpu::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for byte in chunks.iter().flat_map(|chunk| chunk.iter()) {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(&[&kind[..], data]).to_be_bytes())
+}
+
+/// A copy of the screen, see `GameBoyAdvance::screenshot`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screenshot {
+    /// DISPLAY_WIDTH * DISPLAY_HEIGHT pixels in the Xrgb8888 format
+    pub pixels: Vec<u32>,
+}
+
+impl Screenshot {
+    pub fn width(&self) -> usize {
+        DISPLAY_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        DISPLAY_HEIGHT
+    }
+
+    /// Returns the pixels as rgb bytes, each pixel repeated `scale` times in both directions
+    pub fn to_rgb(&self, scale: usize) -> Vec<u8> {
+        let scale = scale.max(1);
+        let mut rgb = Vec::with_capacity(self.pixels.len() * scale * scale * 3);
+        for line in self.pixels.chunks(DISPLAY_WIDTH) {
+            let start = rgb.len();
+            for pixel in line {
+                let [_, r, g, b] = pixel.to_be_bytes();
+                for _ in 0..scale {
+                    rgb.extend_from_slice(&[r, g, b]);
+                }
+            }
+            let scaled_line = rgb[start..].to_vec();
+            for _ in 1..scale {
+                rgb.extend_from_slice(&scaled_line);
+            }
+        }
+        rgb
+    }
+
+    /// Encodes the screenshot as a PNG, scaled up by an integer factor
+    pub fn write_png<W: Write>(&self, mut writer: W, scale: usize) -> io::Result<()> {
+        let scale = scale.max(1);
+        let width = (DISPLAY_WIDTH * scale) as u32;
+        let height = (DISPLAY_HEIGHT * scale) as u32;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // 8 bits per channel, truecolor, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for line in self.to_rgb(scale).chunks(width as usize * 3) {
+            // every line starts with its filter type, 0 is none
+            encoder.write_all(&[0])?;
+            encoder.write_all(line)?;
+        }
+        let data = encoder.finish()?;
+
+        writer.write_all(&PNG_SIGNATURE)?;
+        write_chunk(&mut writer, b"IHDR", &header)?;
+        write_chunk(&mut writer, b"IDAT", &data)?;
+        write_chunk(&mut writer, b"IEND", &[])?;
+        writer.flush()
+    }
+
+    pub fn save_png(&self, path: &Path, scale: usize) -> io::Result<()> {
+        self.write_png(BufWriter::new(File::create(path)?), scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screenshot_png() {
+        let mut pixels = vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        pixels[1] = 0x00ff_8040;
+        let screenshot = Screenshot { pixels };
+
+        let rgb = screenshot.to_rgb(2);
+        assert_eq!(rgb.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT * 4 * 3);
+        // the second pixel is repeated horizontally and on the next line
+        assert_eq!(&rgb[6..12], &[0xff, 0x80, 0x40, 0xff, 0x80, 0x40]);
+        let next_line = DISPLAY_WIDTH * 2 * 3;
+        assert_eq!(&rgb[next_line + 6..next_line + 9], &[0xff, 0x80, 0x40]);
+
+        let mut png = Vec::new();
+        screenshot.write_png(&mut png, 1).unwrap();
+        assert_eq!(&png[..8], &PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // the well known crc of an empty IEND chunk
+        assert_eq!(&png[png.len() - 4..], &[0xae, 0x42, 0x60, 0x82]);
+    }
+}
@@ -0,0 +1,159 @@
+//! Rhai scripts hooking into the emulation, for automated testing, trainers and reverse engineering.
+//!
+//! A script defines any of the following functions, all of them receive the emulator as `gba`:
+//!
+//! ```text
+//! fn on_vblank(gba) { }
+//! fn on_breakpoint(gba, addr) { }
+//! fn on_memory_access(gba, addr, value, is_write) { }
+//! ```
+//!
+//! and can use `gba.reg(r)`, `gba.set_reg(r, value)`, `gba.read8/16/32(addr)`,
+//! `gba.write8/16/32(addr, value)`, `gba.add_breakpoint(addr)`, `gba.watch(addr, len)`
+//! and `gba.frame_count()`. The top level statements run once when the script is loaded.
+//!
+//! `Script::install` registers the script as the frame hook of the emulator. While breakpoints or
+//! watchpoints are set, `GameBoyAdvance::frame` then runs the cpu one instruction at a time so the
+//! hooks can fire, which is a lot slower.
+use std::collections::HashSet;
+use std::path::Path;
+
+use rhai::{Engine, EvalAltResult, Scope, AST};
+
+use crate::arm7tdmi::hooks::{HookResult, WatchKind};
+use crate::bus::Bus;
+use crate::gba::FrameHook;
+use crate::GameBoyAdvance;
+
+/// Gives the script access to the emulator for the duration of a hook call
+#[derive(Clone)]
+struct GbaHandle(*mut GameBoyAdvance);
+
+impl GbaHandle {
+    fn gba(&mut self) -> &mut GameBoyAdvance {
+        // the handle only lives while `Script` borrows the emulator mutably
+        unsafe { &mut *self.0 }
+    }
+}
+
+fn register_api(engine: &mut Engine) {
+    engine.register_type_with_name::<GbaHandle>("Gba");
+    engine.register_fn("reg", |h: &mut GbaHandle, r: i64| {
+        h.gba().cpu.get_reg(r as usize & 0xf) as i64
+    });
+    engine.register_fn("set_reg", |h: &mut GbaHandle, r: i64, value: i64| {
+        h.gba().cpu.set_reg(r as usize & 0xf, value as u32)
+    });
+    engine.register_fn("read8", |h: &mut GbaHandle, addr: i64| {
+        h.gba().sysbus.read_8(addr as u32) as i64
+    });
+    engine.register_fn("read16", |h: &mut GbaHandle, addr: i64| {
+        h.gba().sysbus.read_16(addr as u32) as i64
+    });
+    engine.register_fn("read32", |h: &mut GbaHandle, addr: i64| {
+        h.gba().sysbus.read_32(addr as u32) as i64
+    });
+    engine.register_fn("write8", |h: &mut GbaHandle, addr: i64, value: i64| {
+        h.gba().sysbus.write_8(addr as u32, value as u8)
+    });
+    engine.register_fn("write16", |h: &mut GbaHandle, addr: i64, value: i64| {
+        h.gba().sysbus.write_16(addr as u32, value as u16)
+    });
+    engine.register_fn("write32", |h: &mut GbaHandle, addr: i64, value: i64| {
+        h.gba().sysbus.write_32(addr as u32, value as u32)
+    });
+    engine.register_fn("add_breakpoint", |h: &mut GbaHandle, addr: i64| {
+        h.gba().add_breakpoint(addr as u32);
+    });
+    engine.register_fn("watch", |h: &mut GbaHandle, addr: i64, len: i64| {
+        let addr = addr as u32;
+        h.gba()
+            .add_watchpoint(addr..addr.wrapping_add(len as u32), WatchKind::Access);
+    });
+    engine.register_fn("frame_count", |h: &mut GbaHandle| {
+        h.gba().frame_count() as i64
+    });
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    /// Hooks the script doesn't define, so they are not looked up on every call
+    missing_hooks: HashSet<&'static str>,
+}
+
+impl Script {
+    /// Compiles the script and runs its top level statements
+    pub fn load(path: &Path, gba: &mut GameBoyAdvance) -> Result<Script, String> {
+        let mut engine = Engine::new();
+        register_api(&mut engine);
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("failed to compile {}: {}", path.display(), e))?;
+        let mut scope = Scope::new();
+        scope.push("gba", GbaHandle(gba as *mut GameBoyAdvance));
+        engine
+            .consume_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| format!("failed to run {}: {}", path.display(), e))?;
+        // the handle must not outlive this call
+        scope.clear();
+        Ok(Script {
+            engine,
+            ast,
+            scope,
+            missing_hooks: HashSet::new(),
+        })
+    }
+
+    /// Loads the script and makes `gba.frame()` call its hooks
+    pub fn install(path: &Path, gba: &mut GameBoyAdvance) -> Result<(), String> {
+        let script = Script::load(path, gba)?;
+        gba.set_frame_hook(Some(Box::new(script)));
+        Ok(())
+    }
+
+    fn call_hook<A: rhai::FuncArgs>(&mut self, name: &'static str, args: A) {
+        if self.missing_hooks.contains(name) {
+            return;
+        }
+        let result: Result<(), Box<EvalAltResult>> = self
+            .engine
+            .call_fn(&mut self.scope, &self.ast, name, args)
+            .map(|_: rhai::Dynamic| ());
+        match result {
+            Ok(()) => {}
+            Err(e) => match *e {
+                EvalAltResult::ErrorFunctionNotFound(ref f, _) if f.starts_with(name) => {
+                    self.missing_hooks.insert(name);
+                }
+                _ => warn!("script error in {}: {}", name, e),
+            },
+        }
+    }
+}
+
+impl FrameHook for Script {
+    fn on_hit(&mut self, gba: &mut GameBoyAdvance, hit: HookResult) {
+        let handle = GbaHandle(gba as *mut GameBoyAdvance);
+        match hit {
+            HookResult::Breakpoint(addr) => {
+                self.call_hook("on_breakpoint", (handle, addr as i64));
+            }
+            HookResult::Watchpoint {
+                kind, addr, value, ..
+            } => {
+                let is_write = kind == WatchKind::Write;
+                self.call_hook(
+                    "on_memory_access",
+                    (handle, addr as i64, value as i64, is_write),
+                );
+            }
+        }
+    }
+
+    fn on_frame(&mut self, gba: &mut GameBoyAdvance) {
+        let handle = GbaHandle(gba as *mut GameBoyAdvance);
+        self.call_hook("on_vblank", (handle,));
+    }
+}
@@ -0,0 +1,741 @@
+/// Serial communication port
+///
+/// The normal, UART and general purpose modes are bridged to the host through a
+/// `SerialInterface`, the multiplayer mode links up to four emulators through a `LinkTransport`.
+/// In JOY Bus mode the GBA is a device on the bus, the frontend plays the host (e.g a GameCube)
+/// and sends it commands through `SerialController::joybus_command`.
+/// Transfers complete as soon as the transport has the data, so the baud rate has no effect.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use super::interrupt::{self, Interrupt, InterruptConnect, SharedInterruptFlags};
+use super::iodev::consts::*;
+use super::{LinkTransport, SerialInterface};
+use crate::logging;
+
+/// SIOCNT bits 12-13 with RCNT bit 15 cleared
+const SIO_MODE_NORMAL_8BIT: u16 = 0;
+const SIO_MODE_NORMAL_32BIT: u16 = 1;
+const SIO_MODE_MULTIPLAYER: u16 = 2;
+const SIO_MODE_UART: u16 = 3;
+/// The receive fifo is 4 bytes deep when enabled
+const UART_FIFO_SIZE: usize = 4;
+
+const UART_SEND_FULL: u16 = 1 << 3;
+const UART_RECV_EMPTY: u16 = 1 << 4;
+const UART_ERROR: u16 = 1 << 5;
+const UART_READ_ONLY: u16 = UART_SEND_FULL | UART_RECV_EMPTY | UART_ERROR;
+
+/// SI terminal, set on the children
+const MULTI_CHILD: u16 = 1 << 2;
+/// SD terminal, set when all the players are connected
+const MULTI_READY: u16 = 1 << 3;
+const MULTI_ID_SHIFT: u16 = 4;
+const MULTI_ID: u16 = 0b11 << MULTI_ID_SHIFT;
+const MULTI_ERROR: u16 = 1 << 6;
+/// Start bit when written by the parent, busy bit while the transfer is running
+const MULTI_BUSY: u16 = 1 << 7;
+const MULTI_READ_ONLY: u16 = MULTI_CHILD | MULTI_READY | MULTI_ID | MULTI_ERROR;
+
+/// The shift clock is generated by this GBA, otherwise it comes from the other end
+const NORMAL_INTERNAL_CLOCK: u16 = 1 << 0;
+/// SI terminal, the SO output of the other end
+const NORMAL_SI: u16 = 1 << 2;
+/// Start bit when written, busy bit while the transfer is running
+const NORMAL_BUSY: u16 = 1 << 7;
+
+/// RCNT in general purpose mode: the level of SC, SD, SI and SO in bits 0-3, their direction
+/// (1 for output) in bits 4-7
+const GPIO_DATA: u16 = 0xf;
+const GPIO_DIRECTION_SHIFT: u16 = 4;
+const GPIO_SI: u16 = 1 << 2;
+/// Interrupt when SI goes from high to low
+const GPIO_SI_IRQ: u16 = 1 << 8;
+
+/// JOY Bus commands from the host
+const JOY_STATUS: u8 = 0x00;
+const JOY_READ: u8 = 0x14;
+const JOY_WRITE: u8 = 0x15;
+const JOY_RESET: u8 = 0xff;
+/// The device type a GBA answers to the status and reset commands
+const JOY_DEVICE_TYPE: [u8; 2] = [0x00, 0x04];
+
+/// JOYCNT flags, acknowledged by writing 1
+const JOYCNT_RESET: u16 = 1 << 0;
+const JOYCNT_RECV: u16 = 1 << 1;
+const JOYCNT_SEND: u16 = 1 << 2;
+const JOYCNT_FLAGS: u16 = JOYCNT_RESET | JOYCNT_RECV | JOYCNT_SEND;
+const JOYCNT_IRQ: u16 = 1 << 6;
+
+/// JOY_RECV has data the GBA didn't read yet
+const JOYSTAT_RECV: u16 = 1 << 1;
+/// JOY_TRANS has data the host didn't read yet
+const JOYSTAT_SEND: u16 = 1 << 3;
+/// General purpose flags the host can see in the replies
+const JOYSTAT_GENERAL: u16 = 0b11 << 4;
+
+/// The mode of the serial port, selected by RCNT bits 14-15 and SIOCNT bits 12-13
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SioMode {
+    Normal8Bit,
+    Normal32Bit,
+    Multiplayer,
+    Uart,
+    GeneralPurpose,
+    JoyBus,
+}
+
+bitfield! {
+    #[derive(Serialize, Deserialize, Clone, Copy, Default)]
+    pub struct SioControl(u16);
+    impl Debug;
+    u16;
+    pub baud_rate, _: 1, 0;
+    pub data_8bit, _: 7;
+    pub fifo_enable, _: 8;
+    pub send_enable, _: 10;
+    pub recv_enable, _: 11;
+    pub mode, _: 13, 12;
+    pub irq_enable, _: 14;
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct SerialController {
+    siocnt: SioControl,
+    rcnt: u16,
+    recv_fifo: VecDeque<u8>,
+    /// SIOMULTI0-3, the data every player sent in the last multiplayer transfer.
+    /// SIOMULTI0 and SIOMULTI1 are also SIODATA32 in normal mode.
+    siomulti: [u16; 4],
+    /// Also SIODATA8 in normal mode
+    siomlt_send: u16,
+    /// The input pins in general purpose mode when they were last sampled
+    gpio_inputs: u16,
+    joycnt: u16,
+    joy_recv: u32,
+    joy_trans: u32,
+    joystat: u16,
+    #[serde(skip)]
+    interrupt_flags: SharedInterruptFlags,
+    #[serde(skip)]
+    host: Option<Rc<RefCell<dyn SerialInterface>>>,
+    #[serde(skip)]
+    link: Option<Rc<RefCell<dyn LinkTransport>>>,
+}
+
+impl InterruptConnect for SerialController {
+    fn connect_irq(&mut self, interrupt_flags: SharedInterruptFlags) {
+        self.interrupt_flags = interrupt_flags;
+    }
+}
+
+impl SerialController {
+    pub fn new(interrupt_flags: SharedInterruptFlags) -> SerialController {
+        SerialController {
+            interrupt_flags,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_host(&mut self, host: Option<Rc<RefCell<dyn SerialInterface>>>) {
+        self.host = host;
+    }
+
+    pub fn host(&self) -> Option<Rc<RefCell<dyn SerialInterface>>> {
+        self.host.clone()
+    }
+
+    pub fn set_link(&mut self, link: Option<Rc<RefCell<dyn LinkTransport>>>) {
+        self.link = link;
+    }
+
+    pub fn link(&self) -> Option<Rc<RefCell<dyn LinkTransport>>> {
+        self.link.clone()
+    }
+
+    pub fn mode(&self) -> SioMode {
+        match (self.rcnt >> 14, self.siocnt.mode()) {
+            (3, _) => SioMode::JoyBus,
+            (2, _) => SioMode::GeneralPurpose,
+            (_, SIO_MODE_NORMAL_8BIT) => SioMode::Normal8Bit,
+            (_, SIO_MODE_NORMAL_32BIT) => SioMode::Normal32Bit,
+            (_, SIO_MODE_MULTIPLAYER) => SioMode::Multiplayer,
+            _ => SioMode::Uart,
+        }
+    }
+
+    fn is_multiplayer(&self) -> bool {
+        self.mode() == SioMode::Multiplayer
+    }
+
+    fn is_uart(&self) -> bool {
+        self.mode() == SioMode::Uart
+    }
+
+    fn is_normal(&self) -> bool {
+        matches!(self.mode(), SioMode::Normal8Bit | SioMode::Normal32Bit)
+    }
+
+    fn fifo_size(&self) -> usize {
+        if self.siocnt.fifo_enable() {
+            UART_FIFO_SIZE
+        } else {
+            1
+        }
+    }
+
+    fn signal_irq(&self) {
+        if self.siocnt.irq_enable() {
+            interrupt::signal_irq(&self.interrupt_flags, Interrupt::SerialCommunication);
+        }
+    }
+
+    fn multiplayer_siocnt(&self) -> u16 {
+        let mut value = self.siocnt.0 & !MULTI_READ_ONLY;
+        if let Some(link) = &self.link {
+            let link = link.borrow();
+            let id = link.player_id() as u16 & 3;
+            if id != 0 {
+                value |= MULTI_CHILD;
+            }
+            if link.is_ready() {
+                value |= MULTI_READY;
+            }
+            value |= id << MULTI_ID_SHIFT;
+        }
+        value
+    }
+
+    fn start_multiplayer_transfer(&mut self) {
+        let link = match self.link.clone() {
+            Some(link) => link,
+            None => {
+                // nobody on the other end of the cable
+                self.complete_multiplayer_transfer([self.siomlt_send, 0xffff, 0xffff, 0xffff]);
+                return;
+            }
+        };
+        let is_parent = link.borrow().player_id() == 0;
+        if !is_parent || !link.borrow().is_ready() {
+            // only the parent can start a transfer, and only when everyone is there
+            self.siocnt.0 &= !MULTI_BUSY;
+            return;
+        }
+        link.borrow_mut().start_transfer();
+        self.poll_link();
+    }
+
+    fn complete_multiplayer_transfer(&mut self, data: [u16; 4]) {
+        self.siomulti = data;
+        self.siocnt.0 &= !MULTI_BUSY;
+        self.signal_irq();
+    }
+
+    /// Runs a normal mode transfer, or checks whether the other end clocked the one that is
+    /// waiting for it
+    fn normal_transfer(&mut self) {
+        let internal_clock = self.siocnt.0 & NORMAL_INTERNAL_CLOCK != 0;
+        let (data, bits) = if self.mode() == SioMode::Normal32Bit {
+            (
+                (self.siomulti[1] as u32) << 16 | self.siomulti[0] as u32,
+                32,
+            )
+        } else {
+            (self.siomlt_send as u32 & 0xff, 8)
+        };
+        let received = match &self.host {
+            Some(host) => host.borrow_mut().transfer(data, bits, internal_clock),
+            // with nothing on the other end SI stays high, and only this GBA can drive the clock
+            None if internal_clock => Some(!0 >> (32 - bits)),
+            None => None,
+        };
+        if let Some(received) = received {
+            if bits == 32 {
+                self.siomulti[0] = received as u16;
+                self.siomulti[1] = (received >> 16) as u16;
+            } else {
+                self.siomlt_send = self.siomlt_send & 0xff00 | received as u16 & 0xff;
+            }
+            self.siocnt.0 &= !NORMAL_BUSY;
+            self.signal_irq();
+        }
+    }
+
+    fn gpio_outputs(&self) -> u16 {
+        (self.rcnt >> GPIO_DIRECTION_SHIFT) & GPIO_DATA
+    }
+
+    /// RCNT with the input pins as they were last sampled
+    fn gpio_rcnt(&self) -> u16 {
+        let inputs = GPIO_DATA & !self.gpio_outputs();
+        self.rcnt & !inputs | self.gpio_inputs & inputs
+    }
+
+    /// Reads the input pins from the host, raises the SI interrupt on a falling edge
+    fn sample_gpio(&mut self) {
+        let inputs = match &self.host {
+            Some(host) => host.borrow_mut().gpio() as u16 & GPIO_DATA,
+            None => GPIO_DATA,
+        };
+        let falling = self.gpio_inputs & !inputs & GPIO_SI != 0;
+        if falling && self.gpio_outputs() & GPIO_SI == 0 && self.rcnt & GPIO_SI_IRQ != 0 {
+            interrupt::signal_irq(&self.interrupt_flags, Interrupt::SerialCommunication);
+        }
+        self.gpio_inputs = inputs;
+    }
+
+    /// Handles a command from the JOY Bus host, returns the reply of the GBA.
+    /// Nothing answers while the serial port is not in JOY Bus mode.
+    pub fn joybus_command(&mut self, command: &[u8]) -> Vec<u8> {
+        if self.mode() != SioMode::JoyBus || command.is_empty() {
+            return Vec::new();
+        }
+        let reply = match command[0] {
+            JOY_RESET | JOY_STATUS => {
+                if command[0] == JOY_RESET {
+                    self.joycnt |= JOYCNT_RESET;
+                }
+                let mut reply = JOY_DEVICE_TYPE.to_vec();
+                reply.push(self.joystat as u8);
+                reply
+            }
+            JOY_READ => {
+                self.joystat &= !JOYSTAT_SEND;
+                self.joycnt |= JOYCNT_SEND;
+                let mut reply = self.joy_trans.to_le_bytes().to_vec();
+                reply.push(self.joystat as u8);
+                reply
+            }
+            JOY_WRITE if command.len() >= 5 => {
+                let mut data = [0; 4];
+                data.copy_from_slice(&command[1..5]);
+                self.joy_recv = u32::from_le_bytes(data);
+                self.joystat |= JOYSTAT_RECV;
+                self.joycnt |= JOYCNT_RECV;
+                vec![self.joystat as u8]
+            }
+            _ => {
+                trace!(
+                    target: logging::SIO,
+                    "unknown JOY Bus command {:02x?}",
+                    command
+                );
+                return Vec::new();
+            }
+        };
+        if self.joycnt & JOYCNT_IRQ != 0 {
+            interrupt::signal_irq(&self.interrupt_flags, Interrupt::SerialCommunication);
+        }
+        reply
+    }
+
+    /// Checks whether a multiplayer transfer completed, should be called periodically
+    #[inline]
+    fn poll_link(&mut self) {
+        if self.link.is_none() || !self.is_multiplayer() {
+            return;
+        }
+        let data = self.link.as_ref().unwrap().borrow_mut().poll_transfer();
+        if let Some(data) = data {
+            self.complete_multiplayer_transfer(data);
+        }
+    }
+
+    /// Checks whether the transfer in progress completed and samples the general purpose inputs,
+    /// should be called every h/vblank so games busy waiting on the registers see them change
+    pub fn poll_transfer(&mut self) {
+        match self.mode() {
+            SioMode::Multiplayer => self.poll_link(),
+            SioMode::Normal8Bit | SioMode::Normal32Bit => {
+                if self.siocnt.0 & NORMAL_BUSY != 0 {
+                    self.normal_transfer();
+                }
+            }
+            SioMode::GeneralPurpose => self.sample_gpio(),
+            _ => {}
+        }
+    }
+
+    /// Moves the bytes the host sent into the receive fifo, should be called periodically
+    pub fn poll(&mut self) {
+        self.poll_transfer();
+        if !self.is_uart() || !self.siocnt.recv_enable() {
+            return;
+        }
+        let host = match &self.host {
+            Some(host) => host.clone(),
+            None => return,
+        };
+        let was_empty = self.recv_fifo.is_empty();
+        while self.recv_fifo.len() < self.fifo_size() {
+            match host.borrow_mut().receive() {
+                Some(byte) => self.recv_fifo.push_back(byte),
+                None => break,
+            }
+        }
+        if was_empty && !self.recv_fifo.is_empty() {
+            self.signal_irq();
+        }
+    }
+
+    /// Reads a register without side effects, see `acknowledge_read` for what a cpu read also does
+    pub fn handle_read(&self, io_addr: u32) -> u16 {
+        match io_addr {
+            REG_SIOMULTI0..=REG_SIOMULTI3 => {
+                self.siomulti[((io_addr - REG_SIOMULTI0) / 2) as usize]
+            }
+            REG_SIOCNT if self.is_multiplayer() => self.multiplayer_siocnt(),
+            REG_SIOCNT if self.is_normal() => self.siocnt.0,
+            REG_SIOCNT => {
+                let mut value = self.siocnt.0 & !UART_READ_ONLY;
+                if self.recv_fifo.is_empty() {
+                    value |= UART_RECV_EMPTY;
+                }
+                value
+            }
+            // SIOMLT_SEND in multiplayer mode
+            REG_SIODATA8 if self.is_multiplayer() => self.siomlt_send,
+            REG_SIODATA8 if self.is_normal() => self.siomlt_send & 0xff,
+            REG_SIODATA8 => {
+                let data7 = !self.siocnt.data_8bit();
+                match self.recv_fifo.front() {
+                    Some(&byte) if data7 => (byte & 0x7f) as u16,
+                    Some(&byte) => byte as u16,
+                    None => 0,
+                }
+            }
+            REG_RCNT if self.mode() == SioMode::GeneralPurpose => self.gpio_rcnt(),
+            REG_RCNT => self.rcnt,
+            REG_JOYCNT => self.joycnt,
+            REG_JOY_RECV | REG_JOY_RECV_H => (self.joy_recv >> ((io_addr & 2) * 8)) as u16,
+            REG_JOY_TRANS | REG_JOY_TRANS_H => (self.joy_trans >> ((io_addr & 2) * 8)) as u16,
+            REG_JOYSTAT => self.joystat,
+            _ => 0,
+        }
+    }
+
+    /// What the cpu reading a register does besides returning its value:
+    /// reading SIODATA8 in UART mode pops the receive fifo, reading JOY_RECV clears its flag
+    pub fn acknowledge_read(&mut self, io_addr: u32) {
+        match io_addr {
+            REG_SIODATA8 if self.is_uart() => {
+                self.recv_fifo.pop_front();
+            }
+            REG_JOY_RECV | REG_JOY_RECV_H => self.joystat &= !JOYSTAT_RECV,
+            _ => {}
+        }
+    }
+
+    pub fn handle_write(&mut self, io_addr: u32, value: u16) {
+        match io_addr {
+            REG_SIOCNT => {
+                let was_busy = self.siocnt.0 & MULTI_BUSY != 0;
+                self.siocnt.0 = value & !UART_READ_ONLY;
+                if self.is_multiplayer() {
+                    self.siocnt.0 &= !MULTI_READ_ONLY;
+                    if !was_busy && value & MULTI_BUSY != 0 {
+                        self.start_multiplayer_transfer();
+                    }
+                    return;
+                }
+                if self.is_normal() {
+                    self.siocnt.0 = value & !NORMAL_SI;
+                    if !was_busy && value & NORMAL_BUSY != 0 {
+                        self.normal_transfer();
+                    }
+                    return;
+                }
+                while self.recv_fifo.len() > self.fifo_size() {
+                    self.recv_fifo.pop_back();
+                }
+            }
+            REG_SIODATA8 if self.is_multiplayer() => {
+                self.siomlt_send = value;
+                if let Some(link) = &self.link {
+                    link.borrow_mut().set_send_data(value);
+                }
+            }
+            REG_SIOMULTI0..=REG_SIOMULTI3 => {
+                self.siomulti[((io_addr - REG_SIOMULTI0) / 2) as usize] = value;
+            }
+            REG_SIODATA8 if self.is_normal() => self.siomlt_send = value,
+            REG_SIODATA8 => {
+                if self.is_uart() && self.siocnt.send_enable() {
+                    let byte = if self.siocnt.data_8bit() {
+                        value as u8
+                    } else {
+                        value as u8 & 0x7f
+                    };
+                    if let Some(host) = &self.host {
+                        host.borrow_mut().send(byte);
+                    }
+                    // the byte is sent right away, so the send buffer is empty again
+                    self.signal_irq();
+                }
+            }
+            REG_RCNT => {
+                self.rcnt = value;
+                if self.mode() == SioMode::GeneralPurpose {
+                    if let Some(host) = &self.host {
+                        let outputs = self.gpio_outputs();
+                        host.borrow_mut()
+                            .set_gpio((value & GPIO_DATA & outputs) as u8, outputs as u8);
+                    }
+                    self.sample_gpio();
+                }
+            }
+            REG_JOYCNT => {
+                let flags = self.joycnt & JOYCNT_FLAGS & !value;
+                self.joycnt = flags | value & JOYCNT_IRQ;
+            }
+            REG_JOY_RECV => self.joy_recv = self.joy_recv & 0xffff_0000 | value as u32,
+            REG_JOY_RECV_H => self.joy_recv = self.joy_recv & 0xffff | (value as u32) << 16,
+            REG_JOY_TRANS | REG_JOY_TRANS_H => {
+                if io_addr == REG_JOY_TRANS {
+                    self.joy_trans = self.joy_trans & 0xffff_0000 | value as u32;
+                } else {
+                    self.joy_trans = self.joy_trans & 0xffff | (value as u32) << 16;
+                }
+                self.joystat |= JOYSTAT_SEND;
+            }
+            REG_JOYSTAT => self.joystat = self.joystat & !JOYSTAT_GENERAL | value & JOYSTAT_GENERAL,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LinkHub {
+    players: usize,
+    send: [u16; 4],
+    /// The result of the last transfer, until each player picked it up
+    completed: [Option<[u16; 4]>; 4],
+}
+
+/// Links emulators running in the same process, transfers complete as soon as the parent starts them
+#[derive(Debug)]
+pub struct LocalLink {
+    hub: Rc<RefCell<LinkHub>>,
+    player_id: usize,
+}
+
+impl LocalLink {
+    /// Creates the ends of a link cable for 2 to 4 players, the first one is the parent
+    pub fn group(players: usize) -> Vec<LocalLink> {
+        let players = players.max(2).min(4);
+        let hub = Rc::new(RefCell::new(LinkHub {
+            players,
+            send: [0xffff; 4],
+            completed: [None; 4],
+        }));
+        (0..players)
+            .map(|player_id| LocalLink {
+                hub: hub.clone(),
+                player_id,
+            })
+            .collect()
+    }
+}
+
+impl LinkTransport for LocalLink {
+    fn player_id(&self) -> usize {
+        self.player_id
+    }
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    fn set_send_data(&mut self, data: u16) {
+        self.hub.borrow_mut().send[self.player_id] = data;
+    }
+
+    fn start_transfer(&mut self) {
+        let mut hub = self.hub.borrow_mut();
+        let mut data = [0xffff; 4];
+        data[..hub.players].copy_from_slice(&hub.send[..hub.players]);
+        for player in 0..hub.players {
+            hub.completed[player] = Some(data);
+        }
+    }
+
+    fn poll_transfer(&mut self) -> Option<[u16; 4]> {
+        self.hub.borrow_mut().completed[self.player_id].take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiplayer_transfer() {
+        let mut ports: Vec<SerialController> = LocalLink::group(2)
+            .into_iter()
+            .map(|link| {
+                let mut sio = SerialController::default();
+                sio.set_link(Some(Rc::new(RefCell::new(link))));
+                sio.handle_write(REG_RCNT, 0);
+                sio.handle_write(REG_SIOCNT, 0x2003);
+                sio
+            })
+            .collect();
+
+        assert_eq!(ports[0].handle_read(REG_SIOCNT) & 0x3c, MULTI_READY);
+        assert_eq!(
+            ports[1].handle_read(REG_SIOCNT) & 0x3c,
+            MULTI_READY | MULTI_CHILD | 1 << MULTI_ID_SHIFT
+        );
+
+        ports[0].handle_write(REG_SIODATA8, 0x1234);
+        ports[1].handle_write(REG_SIODATA8, 0x5678);
+        ports[0].handle_write(REG_SIOCNT, 0x2083);
+        // the parent has the data right away, the child once it polls
+        assert_eq!(ports[0].handle_read(REG_SIOCNT) & MULTI_BUSY, 0);
+        assert_eq!(ports[1].handle_read(REG_SIOMULTI0), 0);
+        ports[1].poll_transfer();
+        for sio in ports.iter_mut() {
+            assert_eq!(sio.handle_read(REG_SIOMULTI0), 0x1234);
+            assert_eq!(sio.handle_read(REG_SIOMULTI1), 0x5678);
+            assert_eq!(sio.handle_read(REG_SIOMULTI2), 0xffff);
+        }
+    }
+
+    /// The other end of a normal mode cable, it answers every transfer with the bits inverted
+    struct Inverter {
+        clocked: bool,
+        gpio: u8,
+    }
+
+    impl SerialInterface for Inverter {
+        fn send(&mut self, _byte: u8) {}
+
+        fn transfer(&mut self, data: u32, bits: u32, internal_clock: bool) -> Option<u32> {
+            if internal_clock || self.clocked {
+                Some(!data & (!0 >> (32 - bits)))
+            } else {
+                None
+            }
+        }
+
+        fn gpio(&mut self) -> u8 {
+            self.gpio
+        }
+    }
+
+    #[test]
+    fn test_normal_transfer() {
+        let mut sio = SerialController::default();
+        // 32 bit, internal clock, nothing connected
+        sio.handle_write(REG_SIOCNT, 0x1001);
+        assert_eq!(sio.mode(), SioMode::Normal32Bit);
+        sio.handle_write(REG_SIOMULTI0, 0x5678);
+        sio.handle_write(REG_SIOMULTI1, 0x1234);
+        sio.handle_write(REG_SIOCNT, 0x1081);
+        assert_eq!(sio.handle_read(REG_SIOCNT) & NORMAL_BUSY, 0);
+        assert_eq!(sio.handle_read(REG_SIOMULTI1), 0xffff);
+
+        let host = Rc::new(RefCell::new(Inverter {
+            clocked: false,
+            gpio: 0xf,
+        }));
+        sio.set_host(Some(host.clone()));
+        // 8 bit, external clock, waits for the other end
+        sio.handle_write(REG_SIOCNT, 0x0000);
+        sio.handle_write(REG_SIODATA8, 0x0f);
+        sio.handle_write(REG_SIOCNT, 0x0080);
+        sio.poll();
+        assert_eq!(sio.handle_read(REG_SIOCNT) & NORMAL_BUSY, NORMAL_BUSY);
+        host.borrow_mut().clocked = true;
+        // reading doesn't clock the transfer, only polling does
+        assert_eq!(sio.handle_read(REG_SIOCNT) & NORMAL_BUSY, NORMAL_BUSY);
+        sio.poll_transfer();
+        assert_eq!(sio.handle_read(REG_SIOCNT) & NORMAL_BUSY, 0);
+        assert_eq!(sio.handle_read(REG_SIODATA8), 0xf0);
+    }
+
+    #[test]
+    fn test_general_purpose() {
+        let mut sio = SerialController::default();
+        let host = Rc::new(RefCell::new(Inverter {
+            clocked: false,
+            gpio: 0xf,
+        }));
+        sio.set_host(Some(host.clone()));
+        // SC and SD are outputs, SI and SO inputs
+        sio.handle_write(REG_RCNT, 0x8031);
+        assert_eq!(sio.mode(), SioMode::GeneralPurpose);
+        assert_eq!(sio.handle_read(REG_RCNT), 0x803d);
+        host.borrow_mut().gpio = 0b1000;
+        assert_eq!(sio.handle_read(REG_RCNT), 0x803d);
+        sio.poll_transfer();
+        assert_eq!(sio.handle_read(REG_RCNT), 0x8039);
+    }
+
+    #[test]
+    fn test_uart_read() {
+        struct Sender(VecDeque<u8>);
+
+        impl SerialInterface for Sender {
+            fn send(&mut self, _byte: u8) {}
+
+            fn receive(&mut self) -> Option<u8> {
+                self.0.pop_front()
+            }
+        }
+
+        let mut sio = SerialController::default();
+        sio.set_host(Some(Rc::new(RefCell::new(Sender(vec![0x41, 0x42].into())))));
+        // UART, 8 bit data, fifo and receive enabled
+        sio.handle_write(REG_RCNT, 0);
+        sio.handle_write(REG_SIOCNT, 0x3980);
+        sio.poll();
+        // peeking doesn't pop the fifo, only the cpu read does
+        assert_eq!(sio.handle_read(REG_SIODATA8), 0x41);
+        assert_eq!(sio.handle_read(REG_SIODATA8), 0x41);
+        sio.acknowledge_read(REG_SIODATA8);
+        assert_eq!(sio.handle_read(REG_SIODATA8), 0x42);
+        sio.acknowledge_read(REG_SIODATA8);
+        assert_eq!(
+            sio.handle_read(REG_SIOCNT) & UART_RECV_EMPTY,
+            UART_RECV_EMPTY
+        );
+    }
+
+    #[test]
+    fn test_joybus() {
+        let mut sio = SerialController::default();
+        assert!(sio.joybus_command(&[JOY_STATUS]).is_empty());
+        sio.handle_write(REG_RCNT, 0xc000);
+        sio.handle_write(REG_JOYCNT, JOYCNT_IRQ);
+
+        assert_eq!(sio.joybus_command(&[JOY_RESET]), vec![0x00, 0x04, 0x00]);
+        assert_eq!(sio.handle_read(REG_JOYCNT), JOYCNT_IRQ | JOYCNT_RESET);
+        sio.handle_write(REG_JOYCNT, JOYCNT_IRQ | JOYCNT_RESET);
+        assert_eq!(sio.handle_read(REG_JOYCNT), JOYCNT_IRQ);
+
+        assert_eq!(
+            sio.joybus_command(&[JOY_WRITE, 0x78, 0x56, 0x34, 0x12]),
+            vec![JOYSTAT_RECV as u8]
+        );
+        assert_eq!(sio.handle_read(REG_JOY_RECV_H), 0x1234);
+        assert_eq!(sio.handle_read(REG_JOYSTAT), JOYSTAT_RECV);
+        sio.acknowledge_read(REG_JOY_RECV_H);
+        assert_eq!(sio.handle_read(REG_JOYSTAT), 0);
+
+        sio.handle_write(REG_JOY_TRANS, 0xbeef);
+        sio.handle_write(REG_JOY_TRANS_H, 0xdead);
+        assert_eq!(sio.handle_read(REG_JOYSTAT), JOYSTAT_SEND);
+        assert_eq!(
+            sio.joybus_command(&[JOY_READ]),
+            vec![0xef, 0xbe, 0xad, 0xde, 0x00]
+        );
+        assert_eq!(
+            sio.handle_read(REG_JOYCNT),
+            JOYCNT_IRQ | JOYCNT_RECV | JOYCNT_SEND
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
 use crate::StereoSample;
 
 use serde::{Deserialize, Serialize};
@@ -8,6 +12,136 @@ pub trait Resampler {
     fn feed(&mut self, s: StereoSample<f32>, output: &mut Vec<StereoSample<f32>>);
 }
 
+/// The interpolation used to convert the GBA sample rate to the host's
+#[derive(SmartDefault, Debug, PartialEq, Clone, Copy)]
+pub enum ResamplerKind {
+    /// Cheapest, but aliasing is audible on high pitched channels
+    Linear,
+    #[default]
+    Cosine,
+    /// Band-limited windowed sinc, the cleanest and most expensive
+    Sinc,
+}
+
+impl fmt::Display for ResamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResamplerKind::Linear => write!(f, "linear"),
+            ResamplerKind::Cosine => write!(f, "cosine"),
+            ResamplerKind::Sinc => write!(f, "sinc"),
+        }
+    }
+}
+
+impl FromStr for ResamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(ResamplerKind::Linear),
+            "cosine" => Ok(ResamplerKind::Cosine),
+            "sinc" => Ok(ResamplerKind::Sinc),
+            _ => Err(format!(
+                "invalid resampler {:?}, expected linear, cosine or sinc",
+                s
+            )),
+        }
+    }
+}
+
+/// One of the resamplers, picked at runtime
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum AnyResampler {
+    Linear(LinearResampler),
+    Cosine(CosineResampler),
+    Sinc(SincResampler),
+}
+
+impl AnyResampler {
+    pub fn new(kind: ResamplerKind, in_freq: f32, out_freq: f32) -> AnyResampler {
+        match kind {
+            ResamplerKind::Linear => AnyResampler::Linear(LinearResampler::new(in_freq, out_freq)),
+            ResamplerKind::Cosine => AnyResampler::Cosine(CosineResampler::new(in_freq, out_freq)),
+            ResamplerKind::Sinc => AnyResampler::Sinc(SincResampler::new(in_freq, out_freq)),
+        }
+    }
+
+    pub fn kind(&self) -> ResamplerKind {
+        match self {
+            AnyResampler::Linear(_) => ResamplerKind::Linear,
+            AnyResampler::Cosine(_) => ResamplerKind::Cosine,
+            AnyResampler::Sinc(_) => ResamplerKind::Sinc,
+        }
+    }
+
+    pub fn in_freq(&self) -> f32 {
+        match self {
+            AnyResampler::Linear(r) => r.in_freq,
+            AnyResampler::Cosine(r) => r.in_freq,
+            AnyResampler::Sinc(r) => r.in_freq,
+        }
+    }
+
+    pub fn out_freq(&self) -> f32 {
+        match self {
+            AnyResampler::Linear(r) => r.out_freq,
+            AnyResampler::Cosine(r) => r.out_freq,
+            AnyResampler::Sinc(r) => r.out_freq,
+        }
+    }
+
+    pub fn set_in_freq(&mut self, in_freq: f32) {
+        match self {
+            AnyResampler::Linear(r) => r.in_freq = in_freq,
+            AnyResampler::Cosine(r) => r.in_freq = in_freq,
+            AnyResampler::Sinc(r) => r.in_freq = in_freq,
+        }
+    }
+}
+
+impl Resampler for AnyResampler {
+    #[inline]
+    fn feed(&mut self, s: StereoSample<f32>, output: &mut Vec<StereoSample<f32>>) {
+        match self {
+            AnyResampler::Linear(r) => r.feed(s, output),
+            AnyResampler::Cosine(r) => r.feed(s, output),
+            AnyResampler::Sinc(r) => r.feed(s, output),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LinearResampler {
+    last_in_sample: StereoSample<f32>,
+    phase: f32,
+    pub in_freq: f32,
+    out_freq: f32,
+}
+
+impl Resampler for LinearResampler {
+    fn feed(&mut self, s: StereoSample<f32>, output: &mut Vec<StereoSample<f32>>) {
+        while self.phase < 1.0 {
+            let left = self.last_in_sample.0 + (s.0 - self.last_in_sample.0) * self.phase;
+            let right = self.last_in_sample.1 + (s.1 - self.last_in_sample.1) * self.phase;
+            output.push((left, right));
+            self.phase += self.in_freq / self.out_freq;
+        }
+        self.phase = self.phase - 1.0;
+        self.last_in_sample = s;
+    }
+}
+
+impl LinearResampler {
+    pub fn new(in_freq: f32, out_freq: f32) -> LinearResampler {
+        LinearResampler {
+            last_in_sample: Default::default(),
+            phase: 0.0,
+            in_freq,
+            out_freq,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CosineResampler {
     last_in_sample: StereoSample<f32>,
@@ -44,3 +178,302 @@ impl CosineResampler {
         }
     }
 }
+
+/// Number of input samples each output sample is computed from
+const SINC_TAPS: usize = 16;
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over -1.0..=1.0
+fn blackman(x: f32) -> f32 {
+    if x.abs() > 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+    }
+}
+
+/// Windowed sinc interpolation, low-passed at the lower of the two nyquist frequencies.
+/// Adds a delay of `SINC_TAPS / 2` input samples.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SincResampler {
+    history: VecDeque<StereoSample<f32>>,
+    phase: f32,
+    pub in_freq: f32,
+    out_freq: f32,
+}
+
+impl Resampler for SincResampler {
+    fn feed(&mut self, s: StereoSample<f32>, output: &mut Vec<StereoSample<f32>>) {
+        self.history.pop_front();
+        self.history.push_back(s);
+
+        let cutoff = (self.out_freq / self.in_freq).min(1.0);
+        let half_width = (SINC_TAPS / 2) as f32;
+        while self.phase < 1.0 {
+            // interpolate between the two samples in the middle of the history
+            let center = half_width - 1.0 + self.phase;
+            let (mut left, mut right, mut weights) = (0.0, 0.0, 0.0);
+            for (i, x) in self.history.iter().enumerate() {
+                let t = i as f32 - center;
+                let weight = sinc(cutoff * t) * blackman(t / half_width);
+                left += x.0 * weight;
+                right += x.1 * weight;
+                weights += weight;
+            }
+            output.push((left / weights, right / weights));
+            self.phase += self.in_freq / self.out_freq;
+        }
+        self.phase = self.phase - 1.0;
+    }
+}
+
+impl SincResampler {
+    pub fn new(in_freq: f32, out_freq: f32) -> SincResampler {
+        SincResampler {
+            history: vec![Default::default(); SINC_TAPS].into(),
+            phase: 0.0,
+            in_freq,
+            out_freq,
+        }
+    }
+}
+
+/// How the DirectSound channels go from one fifo sample to the next
+#[derive(SmartDefault, Debug, PartialEq, Clone, Copy)]
+pub enum DmaInterpolation {
+    /// Each sample is held until the next one, like the hardware does
+    #[default]
+    None,
+    /// Ramps between the samples, smoother but a sample period late
+    Linear,
+}
+
+impl fmt::Display for DmaInterpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DmaInterpolation::None => write!(f, "none"),
+            DmaInterpolation::Linear => write!(f, "linear"),
+        }
+    }
+}
+
+impl FromStr for DmaInterpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DmaInterpolation::None),
+            "linear" => Ok(DmaInterpolation::Linear),
+            _ => Err(format!(
+                "invalid interpolation {:?}, expected none or linear",
+                s
+            )),
+        }
+    }
+}
+
+/// Post-processing of the sound output, none of it happens on the hardware
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
+pub struct AudioFilter {
+    pub dma_interpolation: DmaInterpolation,
+    /// Cutoff frequency in Hz of a low-pass filter approximating the response of the GBA speaker,
+    /// no filtering when None
+    pub lowpass_cutoff: Option<f32>,
+}
+
+impl AudioFilter {
+    /// A cutoff that sounds close to the speaker of the original GBA
+    pub const SPEAKER_CUTOFF: f32 = 8000.0;
+}
+
+/// A one-pole low-pass filter, applied to both stereo channels
+///
+/// The cutoff is clamped below the nyquist frequency of the sample rate, so a high cutoff or a
+/// low output rate doesn't turn it into a no-op or worse.
+#[derive(Clone, Debug)]
+pub struct LowPassFilter {
+    cutoff: f32,
+    alpha: f32,
+    state: StereoSample<f32>,
+}
+
+impl LowPassFilter {
+    pub fn new(cutoff: f32, sample_rate: f32) -> LowPassFilter {
+        let mut filter = LowPassFilter {
+            cutoff,
+            alpha: 1.0,
+            state: Default::default(),
+        };
+        filter.set_sample_rate(sample_rate);
+        filter
+    }
+
+    /// The highest cutoff, as a fraction of the sample rate
+    const MAX_CUTOFF_RATIO: f32 = 0.45;
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let cutoff = self.cutoff.min(sample_rate * Self::MAX_CUTOFF_RATIO);
+        self.alpha = 1.0 - (-2.0 * PI * cutoff / sample_rate).exp();
+    }
+
+    #[inline]
+    pub fn apply(&mut self, s: StereoSample<f32>) -> StereoSample<f32> {
+        self.state.0 += self.alpha * (s.0 - self.state.0);
+        self.state.1 += self.alpha * (s.1 - self.state.1);
+        self.state
+    }
+}
+
+/// A linear gain ramp at the output stage.
+/// Cutting the sample stream abruptly (pausing, fast-forwarding, loading a state) is heard as a pop,
+/// so the output is faded out and back in over a few milliseconds instead.
+#[derive(Clone, Debug, Default)]
+pub struct Fader {
+    // starts at 0.0, so the audio fades in when the emulator (re)starts
+    gain: f32,
+}
+
+impl Fader {
+    /// Fade duration in milliseconds
+    const FADE_MS: f32 = 5.0;
+
+    fn step(out_freq: f32) -> f32 {
+        1.0 / (out_freq * Fader::FADE_MS / 1000.0).max(1.0)
+    }
+
+    /// Applies the current gain to `s`, moving the gain towards unity if a fade-in is in progress
+    #[inline]
+    pub fn apply(&mut self, s: StereoSample<f32>, out_freq: f32) -> StereoSample<f32> {
+        let gain = self.gain;
+        if gain < 1.0 {
+            self.gain = (gain + Fader::step(out_freq)).min(1.0);
+        }
+        (s.0 * gain, s.1 * gain)
+    }
+
+    /// Emits a ramp from `last` down to silence.
+    /// The following samples passed through `apply` will fade back in.
+    pub fn fade_out(
+        &mut self,
+        last: StereoSample<f32>,
+        out_freq: f32,
+        output: &mut Vec<StereoSample<f32>>,
+    ) {
+        let step = Fader::step(out_freq);
+        while self.gain > 0.0 {
+            output.push((last.0 * self.gain, last.1 * self.gain));
+            self.gain -= step;
+        }
+        self.gain = 0.0;
+    }
+}
+
+/// Master volume of the output stage, as a percentage of the original level
+#[derive(SmartDefault, Clone, Copy, Debug, PartialEq)]
+pub struct Volume {
+    #[default = 100]
+    percent: u16,
+    muted: bool,
+}
+
+impl Volume {
+    pub const MAX_PERCENT: u16 = 200;
+
+    pub fn new(percent: u16, muted: bool) -> Volume {
+        Volume {
+            percent: percent.min(Volume::MAX_PERCENT),
+            muted,
+        }
+    }
+
+    pub fn percent(&self) -> u16 {
+        self.percent
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Sets the volume, clamped to `MAX_PERCENT`
+    pub fn set_percent(&mut self, percent: u16) {
+        self.percent = percent.min(Volume::MAX_PERCENT);
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    #[inline]
+    pub fn apply(&self, s: StereoSample<f32>) -> StereoSample<f32> {
+        let gain = if self.muted {
+            0.0
+        } else {
+            self.percent as f32 / 100.0
+        };
+        (s.0 * gain, s.1 * gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resamplers_keep_dc_level() {
+        let kinds = [
+            ResamplerKind::Linear,
+            ResamplerKind::Cosine,
+            ResamplerKind::Sinc,
+        ];
+        for &kind in kinds.iter() {
+            let mut resampler = AnyResampler::new(kind, 32768.0, 48000.0);
+            let mut output = Vec::new();
+            for _ in 0..32768 {
+                resampler.feed((100.0, -100.0), &mut output);
+            }
+            // one second of input makes one second of output
+            assert!((output.len() as i32 - 48000).abs() <= 1, "{}", kind);
+            let (left, right) = output[output.len() - 1];
+            assert!((left - 100.0).abs() < 0.01, "{}", kind);
+            assert!((right + 100.0).abs() < 0.01, "{}", kind);
+        }
+    }
+
+    #[test]
+    fn test_lowpass_filter() {
+        let mut filter = LowPassFilter::new(AudioFilter::SPEAKER_CUTOFF, 32768.0);
+        let mut last = (0.0, 0.0);
+        for _ in 0..1000 {
+            last = filter.apply((100.0, -100.0));
+        }
+        assert!((last.0 - 100.0).abs() < 0.01);
+        assert!((last.1 + 100.0).abs() < 0.01);
+
+        // the nyquist frequency is attenuated
+        let mut peak: f32 = 0.0;
+        for i in 0..1000 {
+            let x = if i % 2 == 0 { 100.0 } else { -100.0 };
+            let y = filter.apply((x, x)).0;
+            if i >= 900 {
+                peak = peak.max(y.abs());
+            }
+        }
+        assert!(peak < 100.0 * 0.75, "{}", peak);
+
+        // a cutoff past the nyquist frequency is clamped below it
+        let clamped = LowPassFilter::new(100_000.0, 32768.0);
+        let highest = LowPassFilter::new(32768.0 * LowPassFilter::MAX_CUTOFF_RATIO, 32768.0);
+        assert_eq!(clamped.alpha, highest.alpha);
+        assert!(clamped.alpha < 1.0);
+
+        assert_eq!("linear".parse(), Ok(DmaInterpolation::Linear));
+        assert!("cubic".parse::<DmaInterpolation>().is_err());
+    }
+}
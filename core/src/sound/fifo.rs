@@ -1,4 +1,3 @@
-// TODO write tests or replace with a crate
 const SOUND_FIFO_CAPACITY: usize = 32;
 
 use serde::{Deserialize, Serialize};
@@ -50,3 +49,29 @@ impl SoundFifo {
         self.count = 0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sound_fifo() {
+        let mut fifo = SoundFifo::new();
+        assert_eq!(fifo.read(), 0);
+
+        for i in 0..40 {
+            fifo.write(i as i8);
+        }
+        // writes to a full fifo are dropped
+        assert_eq!(fifo.count(), SOUND_FIFO_CAPACITY);
+        for i in 0..SOUND_FIFO_CAPACITY {
+            assert_eq!(fifo.read(), i as i8);
+        }
+        assert_eq!(fifo.count(), 0);
+
+        fifo.write(-1);
+        fifo.reset();
+        assert_eq!(fifo.count(), 0);
+        assert_eq!(fifo.read(), 0);
+    }
+}
@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 use super::dma::DmaController;
 use super::iodev::consts::*;
 use super::sched::*;
+use crate::logging;
 
 use crate::{AudioInterface, StereoSample};
 
@@ -14,11 +15,14 @@ mod fifo;
 use fifo::SoundFifo;
 
 mod dsp;
-use dsp::{CosineResampler, Resampler};
+use dsp::{AnyResampler, Fader, LowPassFilter, Resampler};
+pub use dsp::{AudioFilter, DmaInterpolation, ResamplerKind, Volume};
+
+mod psg;
+use psg::Psg;
 
 const DMG_RATIOS: [f32; 4] = [0.25, 0.5, 1.0, 0.0];
 const DMA_TIMERS: [usize; 2] = [0, 1];
-const DUTY_RATIOS: [f32; 4] = [0.125, 0.25, 0.5, 0.75];
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct DmaSoundChannel {
@@ -28,6 +32,14 @@ struct DmaSoundChannel {
     enable_left: bool,
     timer_select: usize,
     fifo: SoundFifo,
+    /// The sample before `value`, and when and how often the fifo was last read,
+    /// for `DmaInterpolation::Linear`
+    #[serde(skip)]
+    previous: i8,
+    #[serde(skip)]
+    last_read: usize,
+    #[serde(skip)]
+    read_interval: usize,
 }
 
 impl DmaSoundChannel {
@@ -38,6 +50,32 @@ impl DmaSoundChannel {
             _ => unreachable!(),
         }
     }
+
+    /// Plays the next sample from the fifo, `num_reads` times at `timestamp`
+    fn read_fifo(&mut self, num_reads: usize, timestamp: usize) {
+        for _ in 0..num_reads {
+            self.previous = self.value;
+            self.value = self.fifo.read();
+        }
+        self.read_interval = timestamp.saturating_sub(self.last_read) / num_reads.max(1);
+        self.last_read = timestamp;
+    }
+
+    /// The level of the channel at `timestamp`
+    fn output(&self, interpolation: DmaInterpolation, timestamp: usize) -> f32 {
+        match interpolation {
+            DmaInterpolation::None => self.value as f32,
+            DmaInterpolation::Linear => {
+                let elapsed = timestamp.saturating_sub(self.last_read);
+                let t = if self.read_interval == 0 {
+                    1.0
+                } else {
+                    (elapsed as f32 / self.read_interval as f32).min(1.0)
+                };
+                self.previous as f32 + (self.value as f32 - self.previous as f32) * t
+            }
+        }
+    }
 }
 
 impl Default for DmaSoundChannel {
@@ -49,16 +87,51 @@ impl Default for DmaSoundChannel {
             enable_left: false,
             timer_select: 0,
             fifo: SoundFifo::new(),
+            previous: 0,
+            last_read: 0,
+            read_interval: 0,
         }
     }
 }
 
+/// Read-only snapshot of a DMA sound channel, for hardware inspectors
+#[derive(Debug, Clone, Copy)]
+pub struct DmaSoundState {
+    /// The sample currently being played
+    pub sample: i8,
+    /// 100% when set, 50% otherwise
+    pub full_volume: bool,
+    pub enable_left: bool,
+    pub enable_right: bool,
+    /// The timer that clocks the channel
+    pub timer: usize,
+    /// Number of samples waiting in the fifo
+    pub fifo_count: usize,
+}
+
+/// Read-only snapshot of the sound controller
+#[derive(Debug, Clone, Copy)]
+pub struct SoundState {
+    /// SOUNDCNT_X master enable
+    pub master_enable: bool,
+    /// Channel A and B
+    pub dma_sound: [DmaSoundState; 2],
+    /// PSG master volume (0-7)
+    pub left_volume: usize,
+    pub right_volume: usize,
+    pub sound_bias: u16,
+    /// Whether the PSG channels 1-4 are playing
+    pub psg_enabled: [bool; 4],
+}
+
 const REG_FIFO_A_L: u32 = REG_FIFO_A;
 const REG_FIFO_A_H: u32 = REG_FIFO_A + 2;
 
 const REG_FIFO_B_L: u32 = REG_FIFO_B;
 const REG_FIFO_B_H: u32 = REG_FIFO_B + 2;
 
+const REG_WAVE_RAM_END: u32 = REG_WAVE_RAM + 0xf;
+
 type AudioDeviceRcRefCell = Rc<RefCell<dyn AudioInterface>>;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -85,14 +158,7 @@ pub struct SoundController {
 
     dmg_volume_ratio: f32,
 
-    sqr1_rate: usize,
-    sqr1_timed: bool,
-    sqr1_length: f32,
-    sqr1_duty: f32,
-    sqr1_step_time: usize,
-    sqr1_step_increase: bool,
-    sqr1_initial_vol: usize,
-    sqr1_cur_vol: usize,
+    psg: Psg,
 
     sound_bias: u16,
 
@@ -101,8 +167,23 @@ pub struct SoundController {
 
     dma_sound: [DmaSoundChannel; 2],
 
-    resampler: CosineResampler,
+    resampler: AnyResampler,
     output_buffer: Vec<StereoSample<f32>>,
+
+    #[serde(skip)]
+    filter: AudioFilter,
+    #[serde(skip)]
+    lowpass: Option<LowPassFilter>,
+
+    #[serde(skip)]
+    fader: Fader,
+    #[serde(skip)]
+    last_output: StereoSample<f32>,
+    #[serde(skip)]
+    volume: Volume,
+    /// The output samples, kept while recording
+    #[serde(skip)]
+    recorded_samples: Option<Vec<i16>>,
 }
 
 impl SchedulerConnect for SoundController {
@@ -113,7 +194,11 @@ impl SchedulerConnect for SoundController {
 
 impl SoundController {
     pub fn new(mut scheduler: SharedScheduler, audio_device_sample_rate: f32) -> SoundController {
-        let resampler = CosineResampler::new(32768_f32, audio_device_sample_rate);
+        let resampler = AnyResampler::new(
+            ResamplerKind::default(),
+            32768_f32,
+            audio_device_sample_rate,
+        );
         let cycles_per_sample = 512;
         scheduler.push(EventType::Apu(ApuEvent::Sample), cycles_per_sample);
         SoundController {
@@ -131,27 +216,37 @@ impl SoundController {
             right_sqr2: false,
             right_wave: false,
             right_noise: false,
-            dmg_volume_ratio: 0.0,
-            sqr1_rate: 0,
-            sqr1_timed: false,
-            sqr1_length: 0.0,
-            sqr1_duty: DUTY_RATIOS[0],
-            sqr1_step_time: 0,
-            sqr1_step_increase: false,
-            sqr1_initial_vol: 0,
-            sqr1_cur_vol: 0,
+            dmg_volume_ratio: DMG_RATIOS[0],
+            psg: Psg::default(),
             sound_bias: 0x200,
             sample_rate: 32_768f32,
             dma_sound: [Default::default(), Default::default()],
 
             resampler: resampler,
             output_buffer: Vec::with_capacity(1024),
+
+            filter: AudioFilter::default(),
+            lowpass: None,
+
+            fader: Fader::default(),
+            last_output: (0.0, 0.0),
+            volume: Volume::default(),
+            recorded_samples: None,
         }
     }
 
     pub fn handle_read(&self, io_addr: u32) -> u16 {
         let value = match io_addr {
-            REG_SOUNDCNT_X => cbit(7, self.mse),
+            REG_SOUNDCNT_X => {
+                let psg_enabled = self.psg.channels_enabled();
+                cbit(0, psg_enabled[0])
+                    | cbit(1, psg_enabled[1])
+                    | cbit(2, psg_enabled[2])
+                    | cbit(3, psg_enabled[3])
+                    | cbit(7, self.mse)
+            }
+            REG_SOUND1CNT_L..=REG_SOUND4CNT_H => self.psg.read(io_addr),
+            REG_WAVE_RAM..=REG_WAVE_RAM_END => self.psg.read_wave_ram(io_addr),
             REG_SOUNDCNT_L => {
                 self.left_volume as u16
                     | (self.right_volume as u16) << 4
@@ -204,13 +299,15 @@ impl SoundController {
         if io_addr == REG_SOUNDCNT_X {
             if value & bit(7) != 0 {
                 if !self.mse {
-                    info!("MSE enabled!");
+                    info!(target: logging::APU, "MSE enabled!");
                     self.mse = true;
                 }
             } else {
                 if self.mse {
-                    info!("MSE disabled!");
+                    info!(target: logging::APU, "MSE disabled!");
                     self.mse = false;
+                    self.psg.reset();
+                    self.write_soundcnt_l(0);
                 }
             }
 
@@ -218,28 +315,19 @@ impl SoundController {
             return;
         }
 
-        // TODO - figure out which writes should be disabled when MSE is off
-        // if !self.mse {
-        //     warn!("MSE disabled, refusing to write");
-        //     return;
-        // }
+        // the PSG registers can't be written while the sound is off, the wave RAM can
+        if !self.mse && (REG_SOUND1CNT_L..=REG_SOUNDCNT_L).contains(&io_addr) {
+            return;
+        }
 
         match io_addr {
-            REG_SOUNDCNT_L => {
-                self.left_volume = value.bit_range(0..2) as usize;
-                self.right_volume = value.bit_range(4..6) as usize;
-                self.left_sqr1 = value.bit(8);
-                self.left_sqr2 = value.bit(9);
-                self.left_wave = value.bit(10);
-                self.left_noise = value.bit(11);
-                self.right_sqr1 = value.bit(12);
-                self.right_sqr2 = value.bit(13);
-                self.right_wave = value.bit(14);
-                self.right_noise = value.bit(15);
-            }
+            REG_SOUND1CNT_L..=REG_SOUND4CNT_H => self.psg.write(io_addr, value),
+            REG_WAVE_RAM..=REG_WAVE_RAM_END => self.psg.write_wave_ram(io_addr, value),
+
+            REG_SOUNDCNT_L => self.write_soundcnt_l(value),
 
             REG_SOUNDCNT_H => {
-                self.dmg_volume_ratio = DMG_RATIOS[value.bit_range(0..1) as usize];
+                self.dmg_volume_ratio = DMG_RATIOS[value.bit_range(0..2) as usize];
                 self.dma_sound[0].volume_shift = value.bit(2) as i16;
                 self.dma_sound[1].volume_shift = value.bit(3) as i16;
                 self.dma_sound[0].enable_right = value.bit(8);
@@ -257,22 +345,6 @@ impl SoundController {
                 }
             }
 
-            REG_SOUND1CNT_H => {
-                self.sqr1_length = (64 - value.bit_range(0..5) as usize) as f32 / 256.0;
-                self.sqr1_duty = DUTY_RATIOS[value.bit_range(6..7) as usize];
-                self.sqr1_step_time = value.bit_range(8..10) as usize;
-                self.sqr1_step_increase = value.bit(11);
-                self.sqr1_initial_vol = value.bit_range(12..15) as usize;
-            }
-
-            REG_SOUND1CNT_X => {
-                self.sqr1_rate = value.bit_range(0..10) as usize;
-                self.sqr1_timed = value.bit(14);
-                if value.bit(15) {
-                    self.sqr1_cur_vol = self.sqr1_initial_vol;
-                }
-            }
-
             REG_FIFO_A_L | REG_FIFO_A_H => {
                 self.dma_sound[0].fifo.write((value & 0xff) as i8);
                 self.dma_sound[0].fifo.write(((value >> 8) & 0xff) as i8);
@@ -284,15 +356,26 @@ impl SoundController {
             }
 
             REG_SOUNDBIAS => {
+                let old_resolution = self.amplitude_resolution();
                 self.sound_bias = value & 0xc3fe;
-                let resolution = self.sound_bias.bit_range(14..16) as usize;
-                self.sample_rate = (32768 << resolution) as f32;
-                if self.sample_rate != self.resampler.in_freq {
-                    self.resampler.in_freq = self.sample_rate;
+                let resolution = self.amplitude_resolution();
+                if resolution != old_resolution {
+                    self.sample_rate = (32768 << resolution) as f32;
+                    self.resampler.set_in_freq(self.sample_rate);
+                    if let Some(lowpass) = &mut self.lowpass {
+                        lowpass.set_sample_rate(self.sample_rate);
+                    }
+                    self.cycles_per_sample = 512 >> resolution;
+                    // the next sample is due one sampling cycle from now, at the new rate
+                    self.scheduler.cancel(EventType::Apu(ApuEvent::Sample));
+                    self.scheduler
+                        .push_apu_event(ApuEvent::Sample, self.cycles_per_sample);
+                    info!(
+                        target: logging::APU,
+                        "bias - setting sample frequency to {}hz",
+                        self.sample_rate
+                    );
                 }
-                self.cycles_per_sample = 512 >> resolution;
-                info!("bias - setting sample frequency to {}hz", self.sample_rate);
-                // TODO this will not affect the currently scheduled sample event
             }
 
             _ => {
@@ -305,6 +388,25 @@ impl SoundController {
         }
     }
 
+    /// SOUNDBIAS bits 14-15, each step doubles the sampling rate and drops one bit of the
+    /// 9bit output
+    fn amplitude_resolution(&self) -> usize {
+        self.sound_bias.bit_range(14..16) as usize
+    }
+
+    fn write_soundcnt_l(&mut self, value: u16) {
+        self.left_volume = value.bit_range(0..3) as usize;
+        self.right_volume = value.bit_range(4..7) as usize;
+        self.left_sqr1 = value.bit(8);
+        self.left_sqr2 = value.bit(9);
+        self.left_wave = value.bit(10);
+        self.left_noise = value.bit(11);
+        self.right_sqr1 = value.bit(12);
+        self.right_sqr2 = value.bit(13);
+        self.right_wave = value.bit(14);
+        self.right_noise = value.bit(15);
+    }
+
     pub fn write_fifo(&mut self, id: usize, val: i8) {
         assert!(id == 0 || id == 1);
         self.dma_sound[id].fifo.write(val);
@@ -314,18 +416,21 @@ impl SoundController {
         &mut self,
         dmac: &mut DmaController,
         timer_id: usize,
-        _num_overflows: usize,
+        num_overflows: usize,
     ) {
         if !self.mse {
             return;
         }
 
         const FIFO_INDEX_TO_REG: [u32; 2] = [REG_FIFO_A, REG_FIFO_B];
+        let timestamp = self.scheduler.timestamp();
         for fifo in 0..2 {
             let dma = &mut self.dma_sound[fifo];
 
             if timer_id == dma.timer_select {
-                dma.value = dma.fifo.read();
+                // every overflow plays the next sample, only the last one is heard
+                dma.read_fifo(num_overflows, timestamp);
+                // the fifo asks for 16 more bytes once it's half empty
                 if dma.fifo.count() <= 16 {
                     dmac.notify_sound_fifo(FIFO_INDEX_TO_REG[fifo]);
                 }
@@ -337,34 +442,160 @@ impl SoundController {
     fn on_sample(&mut self, extra_cycles: usize, audio_device: &AudioDeviceRcRefCell) {
         let mut sample = [0f32; 2];
 
+        self.psg.run(self.cycles_per_sample);
+        let psg_outputs = self.psg.outputs();
+        let psg_routing = [
+            [
+                self.left_sqr1,
+                self.left_sqr2,
+                self.left_wave,
+                self.left_noise,
+            ],
+            [
+                self.right_sqr1,
+                self.right_sqr2,
+                self.right_wave,
+                self.right_noise,
+            ],
+        ];
+        let psg_volume = [self.left_volume, self.right_volume];
+
+        let timestamp = self.scheduler.timestamp() - extra_cycles;
+        let interpolation = self.filter.dma_interpolation;
+        let dma_outputs = [
+            self.dma_sound[0].output(interpolation, timestamp),
+            self.dma_sound[1].output(interpolation, timestamp),
+        ];
+        let bias_level = self.sound_bias.bit_range(0..10) as i16;
+        let resolution = self.amplitude_resolution();
         for channel in 0..=1 {
-            let mut dma_sample = 0;
-            for dma in &mut self.dma_sound {
+            let mut psg_sample = 0;
+            for (output, &enabled) in psg_outputs.iter().zip(psg_routing[channel].iter()) {
+                if enabled {
+                    psg_sample += output;
+                }
+            }
+            // each channel is at most +-15, so the PSG peaks close to the DMA channels at 100%
+            psg_sample *= psg_volume[channel] as i16 + 1;
+            let mut dma_sample = (psg_sample as f32 * self.dmg_volume_ratio) as i16;
+            for (dma, output) in self.dma_sound.iter().zip(dma_outputs.iter()) {
                 if dma.is_stereo_channel_enabled(channel) {
-                    let value = dma.value as i16;
-                    dma_sample += value * (2 << dma.volume_shift);
+                    dma_sample += (output * (2 << dma.volume_shift) as f32).round() as i16;
                 }
             }
 
-            apply_bias(&mut dma_sample, self.sound_bias.bit_range(0..10) as i16);
+            apply_bias(&mut dma_sample, bias_level, resolution);
             sample[channel] = dma_sample as i32 as f32;
         }
 
-        let stereo_sample = (sample[0], sample[1]);
+        let mut stereo_sample = (sample[0], sample[1]);
+        if let Some(lowpass) = &mut self.lowpass {
+            stereo_sample = lowpass.apply(stereo_sample);
+        }
         self.resampler.feed(stereo_sample, &mut self.output_buffer);
 
         let mut audio = audio_device.borrow_mut();
-        self.output_buffer.drain(..).for_each(|(left, right)| {
-            audio.push_sample(&[
-                (left.round() as i16) * (std::i16::MAX / 512),
-                (right.round() as i16) * (std::i16::MAX / 512),
-            ]);
-        });
+        let out_freq = audio.get_sample_rate() as f32;
+        for s in self.output_buffer.drain(..) {
+            self.last_output = s;
+            let s = self.fader.apply(s, out_freq);
+            push_output_sample(
+                &mut *audio,
+                self.recorded_samples.as_mut(),
+                self.volume.apply(s),
+            );
+        }
 
         self.scheduler
             .push_apu_event(ApuEvent::Sample, self.cycles_per_sample - extra_cycles);
     }
 
+    /// Ramps the output down to silence instead of cutting it off.
+    /// Should be called before the sample stream is interrupted (pausing, fast-forwarding, loading a state),
+    /// the audio then fades back in once samples are produced again.
+    pub fn fade_out(&mut self, audio_device: &AudioDeviceRcRefCell) {
+        let mut audio = audio_device.borrow_mut();
+        let out_freq = audio.get_sample_rate() as f32;
+        self.output_buffer.clear();
+        self.fader
+            .fade_out(self.last_output, out_freq, &mut self.output_buffer);
+        for s in self.output_buffer.drain(..) {
+            push_output_sample(
+                &mut *audio,
+                self.recorded_samples.as_mut(),
+                self.volume.apply(s),
+            );
+        }
+        self.last_output = (0.0, 0.0);
+    }
+
+    pub fn state(&self) -> SoundState {
+        let dma_state = |dma: &DmaSoundChannel| DmaSoundState {
+            sample: dma.value,
+            full_volume: dma.volume_shift == 1,
+            enable_left: dma.enable_left,
+            enable_right: dma.enable_right,
+            timer: dma.timer_select,
+            fifo_count: dma.fifo.count(),
+        };
+        SoundState {
+            master_enable: self.mse,
+            dma_sound: [dma_state(&self.dma_sound[0]), dma_state(&self.dma_sound[1])],
+            left_volume: self.left_volume,
+            right_volume: self.right_volume,
+            sound_bias: self.sound_bias,
+            psg_enabled: self.psg.channels_enabled(),
+        }
+    }
+
+    pub fn resampler_kind(&self) -> ResamplerKind {
+        self.resampler.kind()
+    }
+
+    /// Switches the resampling algorithm, samples that were not output yet are dropped
+    pub fn set_resampler_kind(&mut self, kind: ResamplerKind) {
+        if kind != self.resampler.kind() {
+            self.resampler =
+                AnyResampler::new(kind, self.resampler.in_freq(), self.resampler.out_freq());
+        }
+    }
+
+    pub fn filter(&self) -> AudioFilter {
+        self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: AudioFilter) {
+        self.filter = filter;
+        self.lowpass = filter
+            .lowpass_cutoff
+            .map(|cutoff| LowPassFilter::new(cutoff, self.sample_rate));
+    }
+
+    pub fn volume(&self) -> Volume {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: Volume) {
+        self.volume = volume;
+    }
+
+    /// Starts or stops keeping a copy of the output samples, see `take_recorded_samples`
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recorded_samples = if recording { Some(Vec::new()) } else { None };
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorded_samples.is_some()
+    }
+
+    /// Returns the interleaved stereo samples output since the last call
+    pub fn take_recorded_samples(&mut self) -> Vec<i16> {
+        self.recorded_samples
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
     pub fn on_event(
         &mut self,
         event: ApuEvent,
@@ -373,13 +604,33 @@ impl SoundController {
     ) {
         match event {
             ApuEvent::Sample => self.on_sample(extra_cycles, audio_device),
-            _ => debug!("got {:?} event", event),
+            _ => debug!(target: logging::APU, "got {:?} event", event),
         }
     }
 }
 
+#[inline]
+fn push_output_sample(
+    audio: &mut dyn AudioInterface,
+    recorded: Option<&mut Vec<i16>>,
+    (left, right): StereoSample<f32>,
+) {
+    // scaled in floating point, so samples amplified past full scale saturate instead of wrapping
+    let scale = (std::i16::MAX / 512) as f32;
+    let sample = [
+        (left.round() * scale) as i16,
+        (right.round() * scale) as i16,
+    ];
+    audio.push_sample(&sample);
+    if let Some(recorded) = recorded {
+        recorded.extend_from_slice(&sample);
+    }
+}
+
+/// Adds the bias level and clips to the 10bit range of the DAC, which then only outputs the top
+/// 9 - `resolution` bits as a PWM signal
 #[inline(always)]
-fn apply_bias(sample: &mut i16, level: i16) {
+fn apply_bias(sample: &mut i16, level: i16, resolution: usize) {
     let mut s = *sample;
     s += level;
     // clamp
@@ -388,6 +639,7 @@ fn apply_bias(sample: &mut i16, level: i16) {
     } else if s < 0 {
         s = 0;
     }
+    s &= !((2 << resolution) - 1);
     s -= level;
     *sample = s;
 }
@@ -405,3 +657,29 @@ fn cbit(idx: u8, value: bool) -> u16 {
 fn bit(idx: u8) -> u16 {
     1 << idx
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_bias() {
+        let bias = |sample: i16, level: i16, resolution: usize| {
+            let mut s = sample;
+            apply_bias(&mut s, level, resolution);
+            s
+        };
+        // 9bit output at the default bias
+        assert_eq!(bias(0, 0x200, 0), 0);
+        assert_eq!(bias(3, 0x200, 0), 2);
+        assert_eq!(bias(-3, 0x200, 0), -4);
+        // 6bit output
+        assert_eq!(bias(21, 0x200, 3), 16);
+        // clipped to the range of the DAC
+        assert_eq!(bias(0x300, 0x200, 0), 0x1fe);
+        assert_eq!(bias(-0x300, 0x200, 0), -0x200);
+        // a lower bias level moves the clipping point
+        assert_eq!(bias(0x300, 0x100, 0), 0x2fe);
+        assert_eq!(bias(-0x300, 0x100, 0), -0x100);
+    }
+}
@@ -0,0 +1,546 @@
+//! The four GameBoy compatible sound channels (PSG)
+//!
+//! Channels are advanced in batches of cycles every time the sound controller produces a sample,
+//! and the output is whatever level each channel is at by then.
+use bit::BitIndex;
+use serde::{Deserialize, Serialize};
+
+use super::super::iodev::consts::*;
+
+/// The frame sequencer runs at 512Hz and clocks the length counters, sweep and envelopes
+const CYCLES_FRAME_SEQUENCER: i32 = 32768;
+
+const DUTY_PATTERNS: [[bool; 8]; 4] = [
+    [false, false, false, false, false, false, false, true],
+    [true, false, false, false, false, false, false, true],
+    [true, false, false, false, false, true, true, true],
+    [false, true, true, true, true, true, true, false],
+];
+
+const WAVE_RAM_BANK_SIZE: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+}
+
+impl LengthCounter {
+    /// Returns false once the counter expired and the channel has to be turned off
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter != 0
+        } else {
+            true
+        }
+    }
+
+    fn reload_if_expired(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    /// In 1/64 seconds, 0 stops the envelope
+    step_time: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u16) {
+        self.step_time = value.bit_range(8..11) as u8;
+        self.increase = value.bit(11);
+        self.initial_volume = value.bit_range(12..16) as u8;
+    }
+
+    fn read(&self) -> u16 {
+        (self.step_time as u16) << 8
+            | (self.increase as u16) << 11
+            | (self.initial_volume as u16) << 12
+    }
+
+    /// A volume of 0 that can't increase turns the channel's DAC off
+    fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    fn restart(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.step_time;
+    }
+
+    fn clock(&mut self) {
+        if self.step_time == 0 {
+            return;
+        }
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.step_time;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Sweep {
+    shift: u8,
+    decrease: bool,
+    /// In 1/128 seconds, 0 stops the sweep
+    time: u8,
+    timer: u8,
+    shadow_frequency: u16,
+}
+
+impl Sweep {
+    fn write(&mut self, value: u16) {
+        self.shift = value.bit_range(0..3) as u8;
+        self.decrease = value.bit(3);
+        self.time = value.bit_range(4..7) as u8;
+    }
+
+    fn read(&self) -> u16 {
+        self.shift as u16 | (self.decrease as u16) << 3 | (self.time as u16) << 4
+    }
+
+    /// The next frequency, it overflowed if above 2047
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow_frequency >> self.shift;
+        if self.decrease {
+            self.shadow_frequency.saturating_sub(delta)
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+}
+
+/// Channels 1 and 2, only channel 1 uses the sweep
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct SquareChannel {
+    enabled: bool,
+    duty: usize,
+    duty_step: usize,
+    frequency: u16,
+    timer: i32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Sweep,
+}
+
+impl SquareChannel {
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 16
+    }
+
+    /// SOUND1CNT_H / SOUND2CNT_L
+    fn write_duty_envelope(&mut self, value: u16) {
+        self.length.counter = 64 - value.bit_range(0..6);
+        self.duty = value.bit_range(6..8) as usize;
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn read_duty_envelope(&self) -> u16 {
+        (self.duty as u16) << 6 | self.envelope.read()
+    }
+
+    /// SOUND1CNT_X / SOUND2CNT_H
+    fn write_control(&mut self, value: u16) {
+        self.frequency = value.bit_range(0..11);
+        self.length.enabled = value.bit(14);
+        if value.bit(15) {
+            self.enabled = self.envelope.dac_enabled();
+            self.length.reload_if_expired(64);
+            self.timer = self.period();
+            self.envelope.restart();
+
+            self.sweep.shadow_frequency = self.frequency;
+            self.sweep.timer = self.sweep.time;
+            if self.sweep.shift != 0 && self.sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn read_control(&self) -> u16 {
+        (self.length.enabled as u16) << 14
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep.time == 0 {
+            return;
+        }
+        if self.sweep.timer > 1 {
+            self.sweep.timer -= 1;
+            return;
+        }
+        self.sweep.timer = self.sweep.time;
+        let frequency = self.sweep.next_frequency();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if self.sweep.shift != 0 {
+            self.sweep.shadow_frequency = frequency;
+            self.frequency = frequency;
+        }
+    }
+
+    fn run(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+        let volume = self.envelope.volume as i16;
+        if DUTY_PATTERNS[self.duty][self.duty_step] {
+            volume
+        } else {
+            -volume
+        }
+    }
+}
+
+/// Channel 3, plays 4bit samples from the wave RAM
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct WaveChannel {
+    dac_enabled: bool,
+    enabled: bool,
+    /// Play both banks as one 64 sample wave
+    two_banks: bool,
+    /// The bank being played, the cpu accesses the other one
+    bank: usize,
+    /// SOUND3CNT_H bits 13-15
+    volume: u16,
+    frequency: u16,
+    timer: i32,
+    position: usize,
+    length: LengthCounter,
+    wave_ram: [u8; 2 * WAVE_RAM_BANK_SIZE],
+}
+
+impl WaveChannel {
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 8
+    }
+
+    fn write_select(&mut self, value: u16) {
+        self.two_banks = value.bit(5);
+        self.bank = value.bit(6) as usize;
+        self.dac_enabled = value.bit(7);
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    fn read_select(&self) -> u16 {
+        (self.two_banks as u16) << 5 | (self.bank as u16) << 6 | (self.dac_enabled as u16) << 7
+    }
+
+    fn write_length_volume(&mut self, value: u16) {
+        self.length.counter = 256 - value.bit_range(0..8);
+        self.volume = value.bit_range(13..16);
+    }
+
+    fn write_control(&mut self, value: u16) {
+        self.frequency = value.bit_range(0..11);
+        self.length.enabled = value.bit(14);
+        if value.bit(15) {
+            self.enabled = self.dac_enabled;
+            self.length.reload_if_expired(256);
+            self.timer = self.period();
+            self.position = 0;
+        }
+    }
+
+    fn read_control(&self) -> u16 {
+        (self.length.enabled as u16) << 14
+    }
+
+    fn wave_ram_index(&self, ofs: usize) -> usize {
+        (1 - self.bank) * WAVE_RAM_BANK_SIZE + ofs
+    }
+
+    fn read_wave_ram(&self, ofs: usize) -> u16 {
+        let index = self.wave_ram_index(ofs);
+        self.wave_ram[index] as u16 | (self.wave_ram[index + 1] as u16) << 8
+    }
+
+    fn write_wave_ram(&mut self, ofs: usize, value: u16) {
+        let index = self.wave_ram_index(ofs);
+        self.wave_ram[index] = value as u8;
+        self.wave_ram[index + 1] = (value >> 8) as u8;
+    }
+
+    fn run(&mut self, cycles: i32) {
+        let num_samples = if self.two_banks { 64 } else { 32 };
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) % num_samples;
+        }
+    }
+
+    fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte_index = (self.bank * WAVE_RAM_BANK_SIZE + self.position / 2) % self.wave_ram.len();
+        let byte = self.wave_ram[byte_index];
+        // the high nibble is played first
+        let sample = if self.position % 2 == 0 {
+            byte >> 4
+        } else {
+            byte & 0xf
+        };
+        let sample = 2 * sample as i16 - 15;
+        match (self.volume >> 2, self.volume & 0b11) {
+            // forced 75%
+            (1, _) => sample * 3 / 4,
+            (_, 0) => 0,
+            (_, 1) => sample,
+            (_, 2) => sample / 2,
+            (_, 3) => sample / 4,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Channel 4, pseudo random noise from a linear feedback shift register
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct NoiseChannel {
+    enabled: bool,
+    /// SOUND4CNT_H bits 0-7
+    control: u16,
+    timer: i32,
+    lfsr: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    fn period(&self) -> i32 {
+        let ratio = self.control.bit_range(0..3) as i32;
+        let shift = self.control.bit_range(4..8) as i32;
+        let divisor = if ratio == 0 { 8 } else { 16 * ratio };
+        (divisor << shift) * 4
+    }
+
+    fn short_mode(&self) -> bool {
+        self.control.bit(3)
+    }
+
+    fn write_length_envelope(&mut self, value: u16) {
+        self.length.counter = 64 - value.bit_range(0..6);
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_control(&mut self, value: u16) {
+        self.control = value & 0xff;
+        self.length.enabled = value.bit(14);
+        if value.bit(15) {
+            self.enabled = self.envelope.dac_enabled();
+            self.length.reload_if_expired(64);
+            self.timer = self.period();
+            self.envelope.restart();
+            self.lfsr = if self.short_mode() { 0x7f } else { 0x7fff };
+        }
+    }
+
+    fn read_control(&self) -> u16 {
+        self.control | (self.length.enabled as u16) << 14
+    }
+
+    fn run(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            let carry = self.lfsr & 1;
+            self.lfsr >>= 1;
+            if carry != 0 {
+                self.lfsr ^= if self.short_mode() { 0x60 } else { 0x6000 };
+            }
+        }
+    }
+
+    fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+        let volume = self.envelope.volume as i16;
+        if self.lfsr & 1 == 0 {
+            volume
+        } else {
+            -volume
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Psg {
+    sqr1: SquareChannel,
+    sqr2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+
+    frame_sequencer_timer: i32,
+    frame_sequencer_step: usize,
+}
+
+impl Psg {
+    /// Turning the sound off clears all the PSG registers, the wave RAM is kept
+    pub fn reset(&mut self) {
+        let wave_ram = self.wave.wave_ram;
+        *self = Psg::default();
+        self.wave.wave_ram = wave_ram;
+    }
+
+    pub fn read(&self, io_addr: u32) -> u16 {
+        match io_addr {
+            REG_SOUND1CNT_L => self.sqr1.sweep.read(),
+            REG_SOUND1CNT_H => self.sqr1.read_duty_envelope(),
+            REG_SOUND1CNT_X => self.sqr1.read_control(),
+            REG_SOUND2CNT_L => self.sqr2.read_duty_envelope(),
+            REG_SOUND2CNT_H => self.sqr2.read_control(),
+            REG_SOUND3CNT_L => self.wave.read_select(),
+            REG_SOUND3CNT_H => self.wave.volume << 13,
+            REG_SOUND3CNT_X => self.wave.read_control(),
+            REG_SOUND4CNT_L => self.noise.envelope.read(),
+            REG_SOUND4CNT_H => self.noise.read_control(),
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, io_addr: u32, value: u16) {
+        match io_addr {
+            REG_SOUND1CNT_L => self.sqr1.sweep.write(value),
+            REG_SOUND1CNT_H => self.sqr1.write_duty_envelope(value),
+            REG_SOUND1CNT_X => self.sqr1.write_control(value),
+            REG_SOUND2CNT_L => self.sqr2.write_duty_envelope(value),
+            REG_SOUND2CNT_H => self.sqr2.write_control(value),
+            REG_SOUND3CNT_L => self.wave.write_select(value),
+            REG_SOUND3CNT_H => self.wave.write_length_volume(value),
+            REG_SOUND3CNT_X => self.wave.write_control(value),
+            REG_SOUND4CNT_L => self.noise.write_length_envelope(value),
+            REG_SOUND4CNT_H => self.noise.write_control(value),
+            _ => {}
+        }
+    }
+
+    pub fn read_wave_ram(&self, io_addr: u32) -> u16 {
+        self.wave
+            .read_wave_ram((io_addr - REG_WAVE_RAM) as usize & 0xe)
+    }
+
+    pub fn write_wave_ram(&mut self, io_addr: u32, value: u16) {
+        self.wave
+            .write_wave_ram((io_addr - REG_WAVE_RAM) as usize & 0xe, value)
+    }
+
+    /// The channel status bits of SOUNDCNT_X
+    pub fn channels_enabled(&self) -> [bool; 4] {
+        [
+            self.sqr1.enabled,
+            self.sqr2.enabled,
+            self.wave.enabled,
+            self.noise.enabled,
+        ]
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        self.frame_sequencer_step = (step + 1) % 8;
+
+        // length counters at 256Hz
+        if step % 2 == 0 {
+            self.sqr1.enabled &= self.sqr1.length.clock();
+            self.sqr2.enabled &= self.sqr2.length.clock();
+            self.wave.enabled &= self.wave.length.clock();
+            self.noise.enabled &= self.noise.length.clock();
+        }
+        // sweep at 128Hz
+        if step == 2 || step == 6 {
+            self.sqr1.clock_sweep();
+        }
+        // envelopes at 64Hz
+        if step == 7 {
+            self.sqr1.envelope.clock();
+            self.sqr2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+    }
+
+    /// Advances all channels by `cycles`
+    pub fn run(&mut self, cycles: usize) {
+        let cycles = cycles as i32;
+        self.frame_sequencer_timer -= cycles;
+        while self.frame_sequencer_timer <= 0 {
+            self.frame_sequencer_timer += CYCLES_FRAME_SEQUENCER;
+            self.clock_frame_sequencer();
+        }
+        self.sqr1.run(cycles);
+        self.sqr2.run(cycles);
+        self.wave.run(cycles);
+        self.noise.run(cycles);
+    }
+
+    /// The current output of each channel, roughly in the range -15..=15
+    pub fn outputs(&self) -> [i16; 4] {
+        [
+            self.sqr1.output(),
+            self.sqr2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_stops_channel() {
+        let mut psg = Psg::default();
+        // max volume, 50% duty, length 63 -> 1 tick left
+        psg.write(REG_SOUND2CNT_L, 0xf000 | 2 << 6 | 63);
+        psg.write(REG_SOUND2CNT_H, 0x8000 | 0x4000 | 0x700);
+        assert_eq!(psg.channels_enabled()[1], true);
+
+        // the first length clock happens at step 0 of the frame sequencer
+        psg.run(1);
+        assert_eq!(psg.channels_enabled()[1], false);
+    }
+
+    #[test]
+    fn test_wave_ram_banks() {
+        let mut psg = Psg::default();
+        // the cpu accesses the bank that isn't selected for playback
+        psg.write(REG_SOUND3CNT_L, 1 << 6);
+        psg.write_wave_ram(REG_WAVE_RAM, 0x1234);
+        psg.write(REG_SOUND3CNT_L, 0);
+        assert_eq!(psg.read_wave_ram(REG_WAVE_RAM), 0);
+        psg.write(REG_SOUND3CNT_L, 1 << 6);
+        assert_eq!(psg.read_wave_ram(REG_WAVE_RAM), 0x1234);
+    }
+}
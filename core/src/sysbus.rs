@@ -1,14 +1,20 @@
+use std::cmp;
+
 use serde::{Deserialize, Serialize};
 
 use super::arm7tdmi;
+use super::arm7tdmi::block_cache::CODE_PAGE_SIZE;
 use super::arm7tdmi::memory::*;
 use super::bios::Bios;
 use super::bus::*;
 use super::cartridge::Cartridge;
+use super::cheats::WriteHook;
 use super::dma::DmaNotifer;
-use super::iodev::{IoDevices, WaitControl};
+use super::iodev::{InternalMemoryControl, IoDevices, WaitControl};
+use super::mgba_debug::DebugPort;
 use super::sched::*;
 use super::util::{Shared, WeakPointer};
+use crate::logging;
 
 pub mod consts {
     pub const WORK_RAM_SIZE: usize = 256 * 1024;
@@ -91,6 +97,22 @@ impl CycleLookupTables {
         self.s_cycles16[PAGE_PALRAM] = 1;
     }
 
+    /// EWRAM is 16bit wide, so 32bit accesses take twice as long
+    pub fn update_ewram_waitstates(&mut self, imc: InternalMemoryControl) {
+        let cycles16 = if imc.enable_ewram() {
+            // 15 would lock up the hardware
+            1 + 15 - cmp::min(imc.ewram_wait_control() as usize, 14)
+        } else {
+            // IWRAM shows through, with its timing
+            1
+        };
+        let cycles32 = if imc.enable_ewram() { 2 * cycles16 } else { 1 };
+        self.n_cycles16[PAGE_EWRAM] = cycles16;
+        self.s_cycles16[PAGE_EWRAM] = cycles16;
+        self.n_cycles32[PAGE_EWRAM] = cycles32;
+        self.s_cycles32[PAGE_EWRAM] = cycles32;
+    }
+
     pub fn update_gamepak_waitstates(&mut self, waitcnt: WaitControl) {
         static S_GAMEPAK_NSEQ_CYCLES: [usize; 4] = [4, 3, 2, 8];
         static S_GAMEPAK_WS0_SEQ_CYCLES: [usize; 2] = [2, 1];
@@ -104,16 +126,14 @@ impl CycleLookupTables {
         let ws1_second_access = waitcnt.ws1_second_access() as usize;
         let ws2_second_access = waitcnt.ws2_second_access() as usize;
 
-        // update SRAM access
+        // update SRAM access, the SRAM bus is 8bit wide so there are no sequential accesses
         let sram_wait_cycles = 1 + S_GAMEPAK_NSEQ_CYCLES[waitcnt.sram_wait_control() as usize];
-        self.n_cycles32[PAGE_SRAM_LO] = sram_wait_cycles;
-        self.n_cycles32[PAGE_SRAM_LO] = sram_wait_cycles;
-        self.n_cycles16[PAGE_SRAM_HI] = sram_wait_cycles;
-        self.n_cycles16[PAGE_SRAM_HI] = sram_wait_cycles;
-        self.s_cycles32[PAGE_SRAM_LO] = sram_wait_cycles;
-        self.s_cycles32[PAGE_SRAM_LO] = sram_wait_cycles;
-        self.s_cycles16[PAGE_SRAM_HI] = sram_wait_cycles;
-        self.s_cycles16[PAGE_SRAM_HI] = sram_wait_cycles;
+        for page in &[PAGE_SRAM_LO, PAGE_SRAM_HI] {
+            self.n_cycles32[*page] = sram_wait_cycles;
+            self.n_cycles16[*page] = sram_wait_cycles;
+            self.s_cycles32[*page] = sram_wait_cycles;
+            self.s_cycles16[*page] = sram_wait_cycles;
+        }
 
         // update both pages of each waitstate
         for i in 0..2 {
@@ -141,6 +161,86 @@ impl CycleLookupTables {
     }
 }
 
+const PREFETCH_BUFFER_SIZE: usize = 8;
+
+#[inline(always)]
+pub fn is_gamepak_rom_page(page: usize) -> bool {
+    page >= PAGE_GAMEPAK_WS0 && page < PAGE_SRAM_LO
+}
+
+/// The gamepak prefetch buffer (WAITCNT bit 14)
+///
+/// While the cpu is busy with anything else than the gamepak bus (internal cycles or accesses to
+/// other memory regions), the prefetcher keeps reading sequential halfwords from ROM into an
+/// 8 halfword fifo. Opcode fetches that hit the fifo only take a single cycle, data accesses to
+/// the gamepak (ROM or SRAM) take the bus away from it and stop it.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct PrefetchBuffer {
+    enabled: bool,
+    active: bool,
+    /// Address of the next halfword the cpu will read from the buffer
+    head: Addr,
+    /// Number of halfwords currently held by the buffer
+    count: usize,
+    /// Cycles left until the halfword being fetched lands in the buffer
+    countdown: usize,
+}
+
+impl PrefetchBuffer {
+    #[inline]
+    fn stop(&mut self) {
+        self.active = false;
+        self.count = 0;
+    }
+
+    /// Restarts prefetching from `addr`
+    #[inline]
+    fn restart(&mut self, addr: Addr, luts: &CycleLookupTables) {
+        self.active = true;
+        self.head = addr;
+        self.count = 0;
+        self.countdown = luts.s_cycles16[((addr >> 24) & 0xF) as usize];
+    }
+
+    /// Lets the prefetcher run for `cycles` cycles of free gamepak bus
+    #[inline]
+    fn step(&mut self, mut cycles: usize, luts: &CycleLookupTables) {
+        while self.active && self.count < PREFETCH_BUFFER_SIZE && cycles > 0 {
+            if cycles < self.countdown {
+                self.countdown -= cycles;
+                return;
+            }
+            cycles -= self.countdown;
+            self.count += 1;
+            let tail = self.head.wrapping_add(2 * self.count as u32);
+            self.countdown = luts.s_cycles16[((tail >> 24) & 0xF) as usize];
+        }
+    }
+
+    /// Handles a read from the gamepak ROM, returns the cycles the access takes
+    /// or None if the access missed the buffer.
+    #[inline]
+    fn read(&mut self, addr: Addr, halfwords: usize, luts: &CycleLookupTables) -> Option<usize> {
+        if !self.active || addr != self.head {
+            return None;
+        }
+        let page = ((addr >> 24) & 0xF) as usize;
+        let cycles = if self.count >= halfwords {
+            self.count -= halfwords;
+            1
+        } else {
+            // wait for the fetch in progress, then read the rest straight from the gamepak
+            let missing = halfwords - self.count - 1;
+            self.count = 0;
+            let cycles = self.countdown + missing * luts.s_cycles16[page];
+            self.countdown = luts.s_cycles16[page];
+            cycles
+        };
+        self.head = addr.wrapping_add(2 * halfwords as u32);
+        Some(cycles)
+    }
+}
+
 #[derive(Clone)]
 pub struct SysBus {
     pub io: Shared<IoDevices>,
@@ -153,10 +253,24 @@ pub struct SysBus {
     pub cartridge: Cartridge,
 
     cycle_luts: CycleLookupTables,
+    prefetch: PrefetchBuffer,
+    /// The EWRAM and IWRAM state set by the internal memory control register
+    wram_disabled: bool,
+    ewram_disabled: bool,
 
     pub trace_access: bool,
+    /// Cheat writes enforced on every write to their location
+    write_hooks: Vec<WriteHook>,
+
+    /// The write generation of every code page of EWRAM and then IWRAM, see `code_generation`
+    code_pages: Box<[u32]>,
+    /// Bumped when all of the memory may have changed at once
+    code_epoch: u32,
 }
 
+const EWRAM_CODE_PAGES: usize = WORK_RAM_SIZE / CODE_PAGE_SIZE as usize;
+const IWRAM_CODE_PAGES: usize = INTERNAL_RAM_SIZE / CODE_PAGE_SIZE as usize;
+
 pub type SysBusPtr = WeakPointer<SysBus>;
 
 impl SchedulerConnect for SysBus {
@@ -177,6 +291,11 @@ impl SysBus {
         let mut luts = CycleLookupTables::default();
         luts.init();
         luts.update_gamepak_waitstates(io.waitcnt);
+        luts.update_ewram_waitstates(io.imc);
+        let prefetch = PrefetchBuffer {
+            enabled: io.waitcnt.prefetch(),
+            ..Default::default()
+        };
 
         SysBus {
             io,
@@ -188,7 +307,13 @@ impl SysBus {
             ewram,
             iwram,
             cycle_luts: luts,
+            prefetch,
+            wram_disabled: io.imc.disable_wram(),
+            ewram_disabled: !io.imc.enable_ewram(),
             trace_access: false,
+            write_hooks: Vec::new(),
+            code_pages: vec![0; EWRAM_CODE_PAGES + IWRAM_CODE_PAGES].into_boxed_slice(),
+            code_epoch: 0,
         }
     }
 
@@ -203,12 +328,28 @@ impl SysBus {
         SysBus::new_with_memories(scheduler, io, cartridge, bios_rom, ewram, iwram)
     }
 
+    pub fn set_write_hooks(&mut self, hooks: Vec<WriteHook>) {
+        self.write_hooks = hooks;
+    }
+
+    #[cold]
+    fn run_write_hooks(&mut self, addr: Addr, width: u32) {
+        // the hooks write through the bus too, so they are set aside while they run
+        let hooks = std::mem::take(&mut self.write_hooks);
+        for hook in &hooks {
+            hook.on_write(self, addr, width);
+        }
+        self.write_hooks = hooks;
+    }
+
     pub fn set_ewram(&mut self, buffer: Box<[u8]>) {
         self.ewram = buffer;
+        self.invalidate_code();
     }
 
     pub fn set_iwram(&mut self, buffer: Box<[u8]>) {
         self.iwram = buffer;
+        self.invalidate_code();
     }
 
     pub fn get_ewram(&self) -> &[u8] {
@@ -219,6 +360,65 @@ impl SysBus {
         &self.iwram
     }
 
+    /// The value the bios returns to reads from outside of it, part of the save state
+    pub fn bios_last_opcode(&self) -> u32 {
+        self.bios.last_opcode()
+    }
+
+    pub fn set_bios_last_opcode(&mut self, value: u32) {
+        self.bios.set_last_opcode(value);
+    }
+
+    pub fn get_ewram_mut(&mut self) -> &mut [u8] {
+        self.invalidate_code();
+        &mut self.ewram
+    }
+
+    pub fn get_iwram_mut(&mut self) -> &mut [u8] {
+        self.invalidate_code();
+        &mut self.iwram
+    }
+
+    /// Makes the cpu decode the code in EWRAM and IWRAM again, for changes that bypass the bus
+    pub fn invalidate_code(&mut self) {
+        self.code_epoch = self.code_epoch.wrapping_add(1);
+    }
+
+    /// The index in `code_pages` of the EWRAM or IWRAM page that holds `addr`
+    #[inline]
+    fn code_page(&self, addr: Addr) -> Option<usize> {
+        let page_size = CODE_PAGE_SIZE as usize;
+        match addr & 0xff000000 {
+            EWRAM_ADDR if !self.ewram_disabled => Some((addr & 0x3_ffff) as usize / page_size),
+            EWRAM_ADDR | IWRAM_ADDR => {
+                Some(EWRAM_CODE_PAGES + (addr & 0x7fff) as usize / page_size)
+            }
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn mark_code_written(&mut self, addr: Addr) {
+        if let Some(page) = self.code_page(addr) {
+            self.code_pages[page] = self.code_pages[page].wrapping_add(1);
+        }
+    }
+
+    /// Copies the parts of an elf image that live in EWRAM or IWRAM into place
+    pub fn load_ram_segments(&mut self) {
+        self.invalidate_code();
+        for (addr, data) in self.cartridge.ram_segments() {
+            let ram = match addr >> 24 {
+                0x02 => &mut self.ewram,
+                _ => &mut self.iwram,
+            };
+            let len = ram.len();
+            for (i, byte) in data.iter().enumerate() {
+                ram[(*addr as usize + i) & (len - 1)] = *byte;
+            }
+        }
+    }
+
     pub fn set_io_devices(&mut self, io_devs: Shared<IoDevices>) {
         self.io = io_devs;
     }
@@ -234,18 +434,46 @@ impl SysBus {
 
     pub fn on_waitcnt_written(&mut self, waitcnt: WaitControl) {
         self.cycle_luts.update_gamepak_waitstates(waitcnt);
+        self.prefetch.enabled = waitcnt.prefetch();
+        if !self.prefetch.enabled {
+            self.prefetch.stop();
+        }
     }
+
+    pub fn on_imc_written(&mut self, imc: InternalMemoryControl) {
+        if imc.ewram_wait_control() == 15 {
+            warn!(
+                target: logging::BUS,
+                "EWRAM wait control set to 15, which locks up the hardware"
+            );
+        }
+        self.cycle_luts.update_ewram_waitstates(imc);
+        self.wram_disabled = imc.disable_wram();
+        self.ewram_disabled = !imc.enable_ewram();
+        // EWRAM addresses may read IWRAM now, or the other way around
+        self.invalidate_code();
+    }
+
     pub fn idle_cycle(&mut self) {
+        self.prefetch.step(1, &self.cycle_luts);
         self.scheduler.update(1);
     }
 
+    /// `fetch` tells opcode fetches, the only accesses served by the prefetch buffer, from data
+    /// accesses
     #[inline(always)]
-    pub fn add_cycles(&mut self, addr: Addr, access: MemoryAccess, width: MemoryAccessWidth) {
+    pub fn add_cycles(
+        &mut self,
+        addr: Addr,
+        access: MemoryAccess,
+        width: MemoryAccessWidth,
+        fetch: bool,
+    ) {
         use MemoryAccess::*;
         use MemoryAccessWidth::*;
         let page = ((addr >> 24) & 0xF) as usize;
 
-        let cycles = unsafe {
+        let cycles = *unsafe {
             match width {
                 MemoryAccess8 | MemoryAccess16 => match access {
                     NonSeq => self.cycle_luts.n_cycles16.get_unchecked(page),
@@ -258,7 +486,29 @@ impl SysBus {
             }
         };
 
-        self.scheduler.update(*cycles);
+        let cycles = if page < PAGE_GAMEPAK_WS0 {
+            // the gamepak bus is free, so the prefetcher can make progress
+            self.prefetch.step(cycles, &self.cycle_luts);
+            cycles
+        } else if !self.prefetch.enabled {
+            cycles
+        } else if !fetch || !is_gamepak_rom_page(page) {
+            self.prefetch.stop();
+            cycles
+        } else {
+            let halfwords = if width == MemoryAccess32 { 2 } else { 1 };
+            match self.prefetch.read(addr, halfwords, &self.cycle_luts) {
+                Some(cycles) => cycles,
+                None => {
+                    // a miss interrupts the prefetcher, which then resumes right after this access
+                    let next = (addr & !1).wrapping_add(2 * halfwords as u32);
+                    self.prefetch.restart(next, &self.cycle_luts);
+                    cycles
+                }
+            }
+        };
+
+        self.scheduler.update(cycles);
     }
 
     /// Helper for "open-bus" accesses
@@ -266,7 +516,7 @@ impl SysBus {
     /// Reading from Unused Memory (00004000-01FFFFFF,10000000-FFFFFFFF)
     /// `addr` is considered to be an address of
     fn read_invalid(&mut self, addr: Addr) -> u32 {
-        warn!("invalid read @{:08x}", addr);
+        warn!(target: logging::BUS, "invalid read @{:08x}", addr);
         use super::arm7tdmi::CpuState;
         let value = match self.arm_core.cpsr.state() {
             CpuState::ARM => self.arm_core.get_prefetched_opcode(),
@@ -302,6 +552,25 @@ impl SysBus {
         };
         value >> ((addr & 3) << 3)
     }
+
+    /// Maps an address of the io region to the io devices, 04000800 is mirrored every 64K
+    #[inline]
+    fn io_offset(addr: Addr) -> Addr {
+        if addr & 0xfffc == 0x0800 {
+            addr & 0xffff
+        } else {
+            addr & 0x00ff_ffff
+        }
+    }
+
+    /// Reads from the unused io area (04000400-04FFFFFF) are open-bus as well,
+    /// except for the mirrors of 04000800 and the debug port
+    #[inline]
+    fn is_io_open_bus(addr: Addr) -> bool {
+        (addr & 0x00ff_ffff) >= 0x400
+            && (addr & 0xfffc) != 0x0800
+            && !DebugPort::is_debug_access(addr & !1)
+    }
 }
 
 /// Todo - implement bound checks for EWRAM/IWRAM
@@ -316,16 +585,12 @@ impl Bus for SysBus {
                     self.read_invalid(addr)
                 }
             }
+            EWRAM_ADDR | IWRAM_ADDR if self.wram_disabled => self.read_invalid(addr),
+            EWRAM_ADDR if self.ewram_disabled => self.iwram.read_32(addr & 0x7ffc),
             EWRAM_ADDR => self.ewram.read_32(addr & 0x3_fffc),
             IWRAM_ADDR => self.iwram.read_32(addr & 0x7ffc),
-            IOMEM_ADDR => {
-                let addr = if addr & 0xfffc == 0x8000 {
-                    0x800
-                } else {
-                    addr & 0x00fffffc
-                };
-                self.io.read_32(addr)
-            }
+            IOMEM_ADDR if Self::is_io_open_bus(addr) => self.read_invalid(addr),
+            IOMEM_ADDR => self.io.read_32(Self::io_offset(addr) & !3),
             PALRAM_ADDR | VRAM_ADDR | OAM_ADDR => self.io.gpu.read_32(addr),
             GAMEPAK_WS0_LO | GAMEPAK_WS0_HI | GAMEPAK_WS1_LO | GAMEPAK_WS1_HI | GAMEPAK_WS2_LO => {
                 self.cartridge.read_32(addr)
@@ -346,16 +611,12 @@ impl Bus for SysBus {
                     self.read_invalid(addr) as u16
                 }
             }
+            EWRAM_ADDR | IWRAM_ADDR if self.wram_disabled => self.read_invalid(addr) as u16,
+            EWRAM_ADDR if self.ewram_disabled => self.iwram.read_16(addr & 0x7ffe),
             EWRAM_ADDR => self.ewram.read_16(addr & 0x3_fffe),
             IWRAM_ADDR => self.iwram.read_16(addr & 0x7ffe),
-            IOMEM_ADDR => {
-                let addr = if addr & 0xfffe == 0x8000 {
-                    0x800
-                } else {
-                    addr & 0x00fffffe
-                };
-                self.io.read_16(addr)
-            }
+            IOMEM_ADDR if Self::is_io_open_bus(addr) => self.read_invalid(addr) as u16,
+            IOMEM_ADDR => self.io.read_16(Self::io_offset(addr) & !1),
             PALRAM_ADDR | VRAM_ADDR | OAM_ADDR => self.io.gpu.read_16(addr),
             GAMEPAK_WS0_LO | GAMEPAK_WS0_HI | GAMEPAK_WS1_LO | GAMEPAK_WS1_HI | GAMEPAK_WS2_LO => {
                 self.cartridge.read_16(addr)
@@ -376,16 +637,12 @@ impl Bus for SysBus {
                     self.read_invalid(addr) as u8
                 }
             }
+            EWRAM_ADDR | IWRAM_ADDR if self.wram_disabled => self.read_invalid(addr) as u8,
+            EWRAM_ADDR if self.ewram_disabled => self.iwram.read_8(addr & 0x7fff),
             EWRAM_ADDR => self.ewram.read_8(addr & 0x3_ffff),
             IWRAM_ADDR => self.iwram.read_8(addr & 0x7fff),
-            IOMEM_ADDR => {
-                let addr = if addr & 0xffff == 0x8000 {
-                    0x800
-                } else {
-                    addr & 0x00ffffff
-                };
-                self.io.read_8(addr)
-            }
+            IOMEM_ADDR if Self::is_io_open_bus(addr) => self.read_invalid(addr) as u8,
+            IOMEM_ADDR => self.io.read_8(Self::io_offset(addr)),
             PALRAM_ADDR | VRAM_ADDR | OAM_ADDR => self.io.gpu.read_8(addr),
             GAMEPAK_WS0_LO | GAMEPAK_WS0_HI | GAMEPAK_WS1_LO | GAMEPAK_WS1_HI | GAMEPAK_WS2_LO => {
                 self.cartridge.read_8(addr)
@@ -400,16 +657,11 @@ impl Bus for SysBus {
     fn write_32(&mut self, addr: Addr, value: u32) {
         match addr & 0xff000000 {
             BIOS_ADDR => {}
+            EWRAM_ADDR | IWRAM_ADDR if self.wram_disabled => {}
+            EWRAM_ADDR if self.ewram_disabled => self.iwram.write_32(addr & 0x7ffc, value),
             EWRAM_ADDR => self.ewram.write_32(addr & 0x3_fffc, value),
             IWRAM_ADDR => self.iwram.write_32(addr & 0x7ffc, value),
-            IOMEM_ADDR => {
-                let addr = if addr & 0xfffc == 0x8000 {
-                    0x800
-                } else {
-                    addr & 0x00fffffc
-                };
-                self.io.write_32(addr, value)
-            }
+            IOMEM_ADDR => self.io.write_32(Self::io_offset(addr) & !3, value),
             PALRAM_ADDR | VRAM_ADDR | OAM_ADDR => self.io.gpu.write_32(addr, value),
             GAMEPAK_WS0_LO => self.cartridge.write_32(addr, value),
             GAMEPAK_WS2_HI => self.cartridge.write_32(addr, value),
@@ -419,22 +671,21 @@ impl Bus for SysBus {
                 // TODO open bus
             }
         }
+        self.mark_code_written(addr);
+        if !self.write_hooks.is_empty() {
+            self.run_write_hooks(addr & !3, 4);
+        }
     }
 
     #[inline]
     fn write_16(&mut self, addr: Addr, value: u16) {
         match addr & 0xff000000 {
             BIOS_ADDR => {}
+            EWRAM_ADDR | IWRAM_ADDR if self.wram_disabled => {}
+            EWRAM_ADDR if self.ewram_disabled => self.iwram.write_16(addr & 0x7ffe, value),
             EWRAM_ADDR => self.ewram.write_16(addr & 0x3_fffe, value),
             IWRAM_ADDR => self.iwram.write_16(addr & 0x7ffe, value),
-            IOMEM_ADDR => {
-                let addr = if addr & 0xfffe == 0x8000 {
-                    0x800
-                } else {
-                    addr & 0x00fffffe
-                };
-                self.io.write_16(addr, value)
-            }
+            IOMEM_ADDR => self.io.write_16(Self::io_offset(addr) & !1, value),
             PALRAM_ADDR | VRAM_ADDR | OAM_ADDR => self.io.gpu.write_16(addr, value),
             GAMEPAK_WS0_LO => self.cartridge.write_16(addr, value),
             GAMEPAK_WS2_HI => self.cartridge.write_16(addr, value),
@@ -444,22 +695,21 @@ impl Bus for SysBus {
                 // TODO open bus
             }
         }
+        self.mark_code_written(addr);
+        if !self.write_hooks.is_empty() {
+            self.run_write_hooks(addr & !1, 2);
+        }
     }
 
     #[inline]
     fn write_8(&mut self, addr: Addr, value: u8) {
         match addr & 0xff000000 {
             BIOS_ADDR => {}
+            EWRAM_ADDR | IWRAM_ADDR if self.wram_disabled => {}
+            EWRAM_ADDR if self.ewram_disabled => self.iwram.write_8(addr & 0x7fff, value),
             EWRAM_ADDR => self.ewram.write_8(addr & 0x3_ffff, value),
             IWRAM_ADDR => self.iwram.write_8(addr & 0x7fff, value),
-            IOMEM_ADDR => {
-                let addr = if addr & 0xffff == 0x8000 {
-                    0x800
-                } else {
-                    addr & 0x00ffffff
-                };
-                self.io.write_8(addr, value)
-            }
+            IOMEM_ADDR => self.io.write_8(Self::io_offset(addr), value),
             PALRAM_ADDR | VRAM_ADDR | OAM_ADDR => self.io.gpu.write_8(addr, value),
             GAMEPAK_WS0_LO => self.cartridge.write_8(addr, value),
             GAMEPAK_WS2_HI => self.cartridge.write_8(addr, value),
@@ -469,6 +719,10 @@ impl Bus for SysBus {
                 // TODO open bus
             }
         }
+        self.mark_code_written(addr);
+        if !self.write_hooks.is_empty() {
+            self.run_write_hooks(addr, 1);
+        }
     }
 }
 
@@ -478,14 +732,7 @@ impl DebugRead for SysBus {
             BIOS_ADDR => self.bios.debug_read_8(addr),
             EWRAM_ADDR => self.ewram.debug_read_8(addr & 0x3_ffff),
             IWRAM_ADDR => self.iwram.debug_read_8(addr & 0x7fff),
-            IOMEM_ADDR => {
-                let addr = if addr & 0xffff == 0x8000 {
-                    0x800
-                } else {
-                    addr & 0x00ffffff
-                };
-                self.io.debug_read_8(addr)
-            }
+            IOMEM_ADDR => self.io.debug_read_8(Self::io_offset(addr)),
             PALRAM_ADDR | VRAM_ADDR | OAM_ADDR => self.io.gpu.debug_read_8(addr),
             GAMEPAK_WS0_LO | GAMEPAK_WS0_HI | GAMEPAK_WS1_LO | GAMEPAK_WS1_HI | GAMEPAK_WS2_LO => {
                 self.cartridge.debug_read_8(addr)
@@ -503,43 +750,103 @@ impl DebugRead for SysBus {
 impl MemoryInterface for SysBus {
     #[inline]
     fn load_8(&mut self, addr: u32, access: MemoryAccess) -> u8 {
-        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess8);
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess8, false);
         self.read_8(addr)
     }
 
     #[inline]
     fn load_16(&mut self, addr: u32, access: MemoryAccess) -> u16 {
-        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess16);
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess16, false);
         self.read_16(addr)
     }
 
     #[inline]
     fn load_32(&mut self, addr: u32, access: MemoryAccess) -> u32 {
-        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess32);
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess32, false);
         self.read_32(addr)
     }
 
     #[inline]
     fn store_8(&mut self, addr: u32, value: u8, access: MemoryAccess) {
-        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess8);
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess8, false);
         self.write_8(addr, value);
     }
 
     #[inline]
     fn store_16(&mut self, addr: u32, value: u16, access: MemoryAccess) {
-        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess8);
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess16, false);
         self.write_16(addr, value);
     }
 
     #[inline]
     fn store_32(&mut self, addr: u32, value: u32, access: MemoryAccess) {
-        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess8);
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess32, false);
         self.write_32(addr, value);
     }
 
+    #[inline]
+    fn fetch_16(&mut self, addr: u32, access: MemoryAccess) -> u16 {
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess16, true);
+        self.read_16(addr)
+    }
+
+    #[inline]
+    fn fetch_32(&mut self, addr: u32, access: MemoryAccess) -> u32 {
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess32, true);
+        self.read_32(addr)
+    }
+
+    #[inline]
+    fn fetch_cycles_16(&mut self, addr: u32, access: MemoryAccess) {
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess16, true);
+    }
+
+    #[inline]
+    fn fetch_cycles_32(&mut self, addr: u32, access: MemoryAccess) {
+        self.add_cycles(addr, access, MemoryAccessWidth::MemoryAccess32, true);
+    }
+
+    /// The gamepak rom only changes when the cartridge is removed, and its first code page holds
+    /// the gpio registers. The bios is left out, fetching from it also sets its open bus value.
+    #[inline]
+    fn code_generation(&self, addr: u32) -> Option<u32> {
+        match addr & 0xff000000 {
+            EWRAM_ADDR | IWRAM_ADDR if self.wram_disabled => None,
+            EWRAM_ADDR | IWRAM_ADDR => self
+                .code_page(addr)
+                .map(|page| self.code_epoch.wrapping_add(self.code_pages[page])),
+            GAMEPAK_WS0_LO | GAMEPAK_WS0_HI | GAMEPAK_WS1_LO | GAMEPAK_WS1_HI | GAMEPAK_WS2_LO
+                if self.cartridge.is_inserted() && addr & 0x01ff_ff00 != 0 =>
+            {
+                Some(self.code_epoch)
+            }
+            _ => None,
+        }
+    }
+
+    /// Events and irqs are handled between the steps of the cpu, and nothing else changes until
+    /// the cpu writes to memory
+    #[inline]
+    fn can_run_ahead(&self) -> bool {
+        self.scheduler.timestamp() <= self.scheduler.timestamp_of_next_event()
+            && self.io.intc.irq_idle()
+    }
+
     #[inline]
     fn idle_cycle(&mut self) {
-        self.scheduler.update(1)
+        SysBus::idle_cycle(self)
+    }
+
+    fn cycles(&self) -> usize {
+        self.scheduler.timestamp()
+    }
+
+    fn is_executable(&self, addr: u32) -> bool {
+        match (addr >> 24) as usize {
+            PAGE_BIOS => addr < 0x4000,
+            PAGE_EWRAM | PAGE_IWRAM | PAGE_PALRAM | PAGE_VRAM | PAGE_OAM => true,
+            page => is_gamepak_rom_page(page),
+        }
     }
 }
 
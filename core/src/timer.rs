@@ -3,12 +3,16 @@ use super::interrupt::{self, Interrupt, InterruptConnect, SharedInterruptFlags};
 use super::iodev::consts::*;
 use super::sched::{EventType, Scheduler, SchedulerConnect, SharedScheduler};
 use super::sound::SoundController;
+use crate::logging;
 
 use num::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
 const SHIFT_LUT: [usize; 4] = [0, 6, 8, 10];
 
+/// Writable bits of TMxCNT_H
+const TIMER_CTL_MASK: u16 = 0x00c7;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Timer {
     // registers
@@ -48,10 +52,12 @@ impl Timer {
         0x1_0000 - (self.data as u32)
     }
 
+    /// Returns the value of the counter at `timestamp`, for timers that are driven by the scheduler.
+    /// `data` itself only holds the value the counter had at `start_time`.
     #[inline]
-    fn sync_timer_data(&mut self, timestamp: usize) {
+    fn data_at(&self, timestamp: usize) -> u16 {
         let ticks_passed = (timestamp - self.start_time) >> self.prescalar_shift;
-        self.data += ticks_passed as u16;
+        self.data.wrapping_add(ticks_passed as u16)
     }
 
     #[inline]
@@ -91,6 +97,19 @@ impl Timer {
     }
 }
 
+/// Read-only snapshot of a timer, for hardware inspectors
+#[derive(Debug, Clone, Copy)]
+pub struct TimerState {
+    pub id: usize,
+    pub enabled: bool,
+    pub counter: u16,
+    pub reload: u16,
+    /// Number of cycles per tick, 1, 64, 256 or 1024
+    pub prescaler: usize,
+    pub cascade: bool,
+    pub irq_enabled: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Timers {
     #[serde(skip)]
@@ -170,7 +189,7 @@ impl Timers {
         if id != 3 {
             let next_timer_id = id + 1;
             let next_timer = &mut self.timers[next_timer_id];
-            if next_timer.ctl.cascade() {
+            if next_timer.ctl.enabled() && next_timer.ctl.cascade() {
                 if next_timer.update(1) > 0 {
                     drop(next_timer);
                     self.handle_timer_overflow(next_timer_id, apu, dmac);
@@ -202,11 +221,19 @@ impl Timers {
 
     pub fn write_timer_ctl(&mut self, id: usize, value: u16) {
         let timer = &mut self.timers[id];
-        let new_ctl = TimerCtl(value);
-        #[cfg(feature = "debugger")]
+        let new_ctl = TimerCtl(value & TIMER_CTL_MASK);
         let old_enabled = timer.ctl.enabled();
         let new_enabled = new_ctl.enabled();
-        let cascade = new_ctl.cascade();
+        // timer 0 has no previous timer to count up with, so it ignores the cascade bit
+        let cascade = id != 0 && new_ctl.cascade();
+        if timer.is_scheduled {
+            // freeze the counter before the timing changes
+            timer.data = timer.data_at(self.scheduler.timestamp());
+        }
+        if new_enabled && !old_enabled {
+            // the counter is reloaded when the timer is started
+            timer.data = timer.initial_data;
+        }
         timer.prescalar_shift = SHIFT_LUT[new_ctl.prescalar() as usize];
         timer.ctl = new_ctl;
         if new_enabled && !cascade {
@@ -222,6 +249,7 @@ impl Timers {
         {
             if self.trace && old_enabled != new_enabled {
                 trace!(
+                    target: logging::TIMER,
                     "TMR{} {}",
                     id,
                     if new_enabled { "enabled" } else { "disabled" }
@@ -231,15 +259,28 @@ impl Timers {
     }
 
     #[inline]
-    fn read_timer_data(&mut self, id: usize) -> u16 {
-        let timer = &mut self.timers[id];
+    fn read_timer_data(&self, id: usize) -> u16 {
+        let timer = &self.timers[id];
         if timer.is_scheduled {
             // this timer is controlled by the scheduler so we need to manually calculate
             // the current value of the counter
-            timer.sync_timer_data(self.scheduler.timestamp());
+            timer.data_at(self.scheduler.timestamp())
+        } else {
+            timer.data
         }
+    }
 
-        timer.data
+    pub fn state(&self, id: usize) -> TimerState {
+        let timer = &self.timers[id];
+        TimerState {
+            id,
+            enabled: timer.ctl.enabled(),
+            counter: self.read_timer_data(id),
+            reload: timer.initial_data,
+            prescaler: 1 << SHIFT_LUT[timer.ctl.prescalar() as usize],
+            cascade: timer.ctl.cascade(),
+            irq_enabled: timer.ctl.irq_enabled(),
+        }
     }
 
     pub fn handle_read(&mut self, io_addr: u32) -> u16 {
@@ -256,30 +297,20 @@ impl Timers {
         }
     }
 
+    /// Writes to TMxCNT_L only set the reload value, the counter picks it up on the next
+    /// overflow or when the timer is started
     pub fn handle_write(&mut self, io_addr: u32, value: u16) {
         match io_addr {
-            REG_TM0CNT_L => {
-                self.timers[0].data = value;
-                self.timers[0].initial_data = value;
-            }
+            REG_TM0CNT_L => self.timers[0].initial_data = value,
             REG_TM0CNT_H => self.write_timer_ctl(0, value),
 
-            REG_TM1CNT_L => {
-                self.timers[1].data = value;
-                self.timers[1].initial_data = value;
-            }
+            REG_TM1CNT_L => self.timers[1].initial_data = value,
             REG_TM1CNT_H => self.write_timer_ctl(1, value),
 
-            REG_TM2CNT_L => {
-                self.timers[2].data = value;
-                self.timers[2].initial_data = value;
-            }
+            REG_TM2CNT_L => self.timers[2].initial_data = value,
             REG_TM2CNT_H => self.write_timer_ctl(2, value),
 
-            REG_TM3CNT_L => {
-                self.timers[3].data = value;
-                self.timers[3].initial_data = value;
-            }
+            REG_TM3CNT_L => self.timers[3].initial_data = value,
             REG_TM3CNT_H => self.write_timer_ctl(3, value),
             _ => unreachable!(),
         }
@@ -7,18 +7,18 @@ use std::ptr;
 use std::time;
 
 #[cfg(not(target_arch = "wasm32"))]
-type Instant = time::Instant;
+pub(crate) type Instant = time::Instant;
 #[cfg(not(target_arch = "wasm32"))]
-fn now() -> Instant {
+pub(crate) fn now() -> Instant {
     time::Instant::now()
 }
 
 #[cfg(target_arch = "wasm32")]
 use instant;
 #[cfg(target_arch = "wasm32")]
-type Instant = instant::Instant;
+pub(crate) type Instant = instant::Instant;
 #[cfg(target_arch = "wasm32")]
-fn now() -> Instant {
+pub(crate) fn now() -> Instant {
     instant::Instant::now()
 }
 
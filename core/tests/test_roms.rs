@@ -0,0 +1,162 @@
+//! Boots the test roms listed in `tests/roms/test_roms.yaml` headlessly and checks their results,
+//! so regressions in the cpu and the ppu show up in `cargo test`.
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use rustboyadvance_core::prelude::*;
+
+struct TestHardware;
+
+#[cfg(not(feature = "no_video_interface"))]
+impl VideoInterface for TestHardware {}
+impl AudioInterface for TestHardware {}
+impl InputInterface for TestHardware {}
+
+#[derive(Debug)]
+enum Expected {
+    Register(usize, u32),
+    Memory(u32, u32),
+    FrameHash(u64),
+}
+
+#[derive(Debug)]
+struct TestRom {
+    rom: String,
+    frames: usize,
+    expected: Expected,
+}
+
+fn parse_pair(yaml: &Yaml) -> Option<(u32, u32)> {
+    let pair = yaml.as_vec()?;
+    match pair.as_slice() {
+        [a, b] => Some((a.as_i64()? as u32, b.as_i64()? as u32)),
+        _ => None,
+    }
+}
+
+fn parse_manifest(content: &str) -> Vec<TestRom> {
+    let docs = YamlLoader::load_from_str(content).expect("failed to parse the test rom list");
+    docs[0]
+        .as_vec()
+        .expect("the test rom list should be a list")
+        .iter()
+        .map(|entry| {
+            let rom = entry["rom"].as_str().expect("missing rom").to_string();
+            let frames = entry["frames"].as_i64().expect("missing frames") as usize;
+            let expected = if !entry["register"].is_badvalue() {
+                let (reg, value) = parse_pair(&entry["register"])
+                    .unwrap_or_else(|| panic!("{}: invalid register", rom));
+                Expected::Register(reg as usize, value)
+            } else if !entry["memory"].is_badvalue() {
+                let (addr, value) = parse_pair(&entry["memory"])
+                    .unwrap_or_else(|| panic!("{}: invalid memory", rom));
+                Expected::Memory(addr, value)
+            } else if let Some(hash) = entry["frame_hash"].as_str() {
+                let hash = u64::from_str_radix(hash.trim_start_matches("0x"), 16)
+                    .unwrap_or_else(|_| panic!("{}: invalid frame_hash", rom));
+                Expected::FrameHash(hash)
+            } else {
+                panic!("{}: missing register, memory or frame_hash", rom)
+            };
+            TestRom {
+                rom,
+                frames,
+                expected,
+            }
+        })
+        .collect()
+}
+
+/// FNV-1a, so the hashes stay the same across rust versions
+fn frame_hash(frame_buffer: &[u32]) -> u64 {
+    frame_buffer
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, pixel| {
+            pixel.to_le_bytes().iter().fold(hash, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+        })
+}
+
+fn create_gba(rom: Vec<u8>) -> GameBoyAdvance {
+    let bios = include_bytes!("../benches/roms/normatt_gba_bios.bin");
+    let gamepak = GamepakBuilder::new()
+        .take_buffer(rom.into_boxed_slice())
+        .without_backup_to_file()
+        .build()
+        .unwrap();
+
+    let hardware = Rc::new(RefCell::new(TestHardware));
+    #[cfg(not(feature = "no_video_interface"))]
+    let mut gba = GameBoyAdvance::new(
+        bios.to_vec().into_boxed_slice(),
+        gamepak,
+        hardware.clone(),
+        hardware.clone(),
+        hardware.clone(),
+    );
+    #[cfg(feature = "no_video_interface")]
+    let mut gba = GameBoyAdvance::new(
+        bios.to_vec().into_boxed_slice(),
+        gamepak,
+        hardware.clone(),
+        hardware.clone(),
+    );
+    gba.skip_bios();
+    gba
+}
+
+fn run_test_rom(test_rom: &TestRom, rom: Vec<u8>) -> Result<(), String> {
+    let mut gba = create_gba(rom);
+    for _ in 0..test_rom.frames {
+        gba.frame();
+    }
+
+    let hash = frame_hash(gba.get_frame_buffer());
+    let (what, actual, expected) = match test_rom.expected {
+        Expected::Register(reg, value) => (
+            format!("r{}", reg),
+            gba.cpu.get_reg(reg) as u64,
+            value as u64,
+        ),
+        Expected::Memory(addr, value) => (
+            format!("[{:#010x}]", addr),
+            gba.sysbus.read_32(addr) as u64,
+            value as u64,
+        ),
+        Expected::FrameHash(expected) => ("frame hash".to_string(), hash, expected),
+    };
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is {:#x}, expected {:#x}",
+            what, actual, expected
+        ))
+    }
+}
+
+#[test]
+fn test_roms() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    let manifest = fs::read_to_string(dir.join("test_roms.yaml")).unwrap();
+
+    let mut failures = Vec::new();
+    for test_rom in parse_manifest(&manifest) {
+        let rom = match fs::read(dir.join(&test_rom.rom)) {
+            Ok(rom) => rom,
+            Err(_) => {
+                println!("{}: skipped, rom not found", test_rom.rom);
+                continue;
+            }
+        };
+        if let Err(e) = run_test_rom(&test_rom, rom) {
+            failures.push(format!("{}: {}", test_rom.rom, e));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
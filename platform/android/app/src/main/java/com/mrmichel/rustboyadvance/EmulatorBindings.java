@@ -1,5 +1,7 @@
 package com.mrmichel.rustboyadvance;
 
+import android.view.Surface;
+
 /**
  * JNI wrapper to the rust core
  */
@@ -14,9 +16,9 @@ public class EmulatorBindings {
      *
      * @param bios        bytearray of the GBA bios
      * @param rom         bytearray of the rom to run
-     * @param renderer    renderer instance
-     * @param audioPlayer audio player instance
-     * @param keypad      Keypad instance
+     * @param renderer    renderer instance, or null to render to the surface given to setSurface
+     * @param audioPlayer audio player instance, or null to pull the samples with readAudioSamples
+     * @param keypad      Keypad instance, or null to only use setKeyState
      * @param save_name   name of the save file TODO remove this
      * @param skipBios    skip bios
      * @return the emulator context to use pass to other methods in this class
@@ -30,9 +32,9 @@ public class EmulatorBindings {
      * @param bios        bytearray of the GBA bios
      * @param rom         bytearray of the rom to run
      * @param savedState  saved state buffer
-     * @param renderer    renderer instance
-     * @param audioPlayer audio player instance
-     * @param keypad      Keypad instance
+     * @param renderer    renderer instance, or null to render to the surface given to setSurface
+     * @param audioPlayer audio player instance, or null to pull the samples with readAudioSamples
+     * @param keypad      Keypad instance, or null to only use setKeyState
      * @return
      * @throws NativeBindingException
      */
@@ -84,12 +86,38 @@ public class EmulatorBindings {
     public static native String getGameCode(long ctx);
 
     /**
-     * Sets the keystate
+     * Sets the keystate, e.g from a touch overlay. A key is pressed if it is pressed either here
+     * or on the Keypad given when opening the emulator.
      *
-     * @param keyState
+     * @param keyState KEYINPUT value, a cleared bit is a pressed key
      */
     public static native void setKeyState(long ctx, int keyState);
 
+    /**
+     * Renders the frames to a surface instead of the renderer, can be called while running
+     *
+     * @param ctx
+     * @param surface the surface to draw to, or null to go back to the renderer
+     * @throws NativeBindingException
+     */
+    public static native void setSurface(long ctx, Surface surface) throws NativeBindingException;
+
+    /**
+     * Reads the samples played since the last call, when the emulator was opened without an
+     * audio player. They are interleaved stereo 16bit PCM, ready to be written to an AudioTrack.
+     *
+     * @param ctx
+     * @param buffer filled with the samples
+     * @return the number of samples read
+     */
+    public static native int readAudioSamples(long ctx, short[] buffer);
+
+    /**
+     * @param ctx
+     * @return The sample rate of the audio, to configure an AudioTrack with
+     */
+    public static native int getSampleRate(long ctx);
+
     /**
      * Saves the state
      *
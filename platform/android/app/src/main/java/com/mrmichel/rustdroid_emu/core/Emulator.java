@@ -1,5 +1,7 @@
 package com.mrmichel.rustdroid_emu.core;
 
+import android.view.Surface;
+
 import com.mrmichel.rustboyadvance.EmulatorBindings;
 import com.mrmichel.rustboyadvance.IFrameRenderer;
 import com.mrmichel.rustboyadvance.Keypad;
@@ -57,6 +59,14 @@ public class Emulator {
 
     }
 
+    public void setSurface(Surface surface) throws EmulatorBindings.NativeBindingException {
+        EmulatorBindings.setSurface(this.ctx, surface);
+    }
+
+    public void setKeyState(int keyState) {
+        EmulatorBindings.setKeyState(this.ctx, keyState);
+    }
+
     public int[] getFrameBuffer() {
         return EmulatorBindings.getFrameBuffer(this.ctx);
     }
@@ -14,6 +14,7 @@ use unsafe_unwrap::UnsafeUnwrap;
 use rustboyadvance_core::keypad::Keys as GbaButton;
 use rustboyadvance_core::prelude::*;
 use rustboyadvance_core::util::audio::AudioRingBuffer;
+use rustboyadvance_core::OutputFormat;
 
 use std::path::Path;
 
@@ -85,11 +86,15 @@ impl libretro_backend::Core for RustBoyAdvanceCore {
         let system_directory_path = Path::new(&system_directory);
 
         let bios_path = system_directory_path.join("gba_bios.bin");
-        if !bios_path.exists() {
-            error!("bios file missing, please place it in {:?}", bios_path);
-            return LoadGameResult::Failed(game_data);
-        }
-        let bios = read_bin_file(&bios_path);
+        let bios = if bios_path.exists() {
+            read_bin_file(&bios_path)
+        } else {
+            warn!(
+                "bios file missing, falling back to high level emulation. For better compatibility place it in {:?}",
+                bios_path
+            );
+            Ok(Vec::new())
+        };
 
         if game_data.is_empty() {
             error!("game data is empty!");
@@ -110,19 +115,20 @@ impl libretro_backend::Core for RustBoyAdvanceCore {
         match (result, bios) {
             (Ok(gamepak), Ok(bios)) => {
                 let av_info = AudioVideoInfo::new()
-                    .video(240, 160, 60.0, PixelFormat::ARGB8888)
+                    .video(240, 160, 60.0, PixelFormat::RGB565)
                     .audio(44100.0);
 
                 let hwif = Rc::new(RefCell::new(HwInterface {
                     key_state: rustboyadvance_core::keypad::KEYINPUT_ALL_RELEASED,
                     audio_ring_buffer: AudioRingBuffer::new(),
                 }));
-                let gba = GameBoyAdvance::new(
+                let mut gba = GameBoyAdvance::new(
                     bios.into_boxed_slice(),
                     gamepak,
                     hwif.clone(),
                     hwif.clone(),
                 );
+                gba.set_output_format(OutputFormat::Rgb565);
 
                 self.hwif = Some(hwif);
                 self.gba = Some(gba);
@@ -152,8 +158,8 @@ impl libretro_backend::Core for RustBoyAdvanceCore {
 
         gba.frame();
 
-        let framebuffer = gba.get_frame_buffer();
-        let bytes_per_pixel = 4;
+        let framebuffer = gba.get_frame_buffer_rgb565();
+        let bytes_per_pixel = 2;
         let framebuffer_size = 240 * 160;
         let uploaded_frame = unsafe {
             std::slice::from_raw_parts(
@@ -181,6 +187,9 @@ impl libretro_backend::Core for RustBoyAdvanceCore {
 
     fn on_unload_game(&mut self) -> GameData {
         debug!("on_unload_game");
+        if let Some(gba) = &mut self.gba {
+            gba.flush_save();
+        }
         self.game_data.take().unwrap()
     }
     // ...
@@ -1,12 +1,14 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use std::convert::TryFrom;
 use std::path::Path;
 use std::time;
 
 #[macro_use]
 extern crate clap;
 
+use rustboyadvance_core::cartridge::BackupType;
 use rustboyadvance_core::keypad;
 use rustboyadvance_core::prelude::*;
 use rustboyadvance_core::util::FpsCounter;
@@ -86,8 +88,17 @@ fn main() {
     let rom_path = Path::new(matches.value_of("game_rom").unwrap());
     let rom_name = rom_path.file_name().unwrap().to_str().unwrap();
 
-    let bios_bin = read_bin_file(bios_path).unwrap();
-    let cart = GamepakBuilder::new().file(rom_path).build().unwrap();
+    let bios_bin = read_bin_file(bios_path).unwrap_or_else(|_| {
+        println!("Missing BIOS file, falling back to high level emulation of the BIOS functions");
+        Vec::new()
+    });
+    let mut builder = GamepakBuilder::new()
+        .save_type(BackupType::try_from(matches.value_of("save_type").unwrap()).unwrap())
+        .file(rom_path);
+    if matches.occurrences_of("rtc") != 0 {
+        builder = builder.with_rtc();
+    }
+    let cart = builder.build().unwrap();
 
     let minifb = Rc::new(RefCell::new(MiniFb {
         window: Window::new(
@@ -115,12 +126,19 @@ fn main() {
     if skip_bios {
         gba.skip_bios();
     }
+    let watchdog = matches
+        .value_of("watchdog")
+        .map(|flushes| flushes.parse().expect("invalid watchdog limit"));
+    gba.cpu.set_watchdog_limit(watchdog);
 
     let frame_time = time::Duration::new(0, 1_000_000_000u32 / 60);
     loop {
         let start_time = time::Instant::now();
 
         gba.frame();
+        if let Some(err) = gba.cpu.take_error() {
+            println!("cpu error: {}", err);
+        }
 
         if let Some(fps) = fps_counter.tick() {
             let title = format!("{} ({} fps)", rom_name, fps);
@@ -1,3 +1,5 @@
+use std::cmp;
+
 use sdl2;
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpec, AudioSpecDesired};
 
@@ -50,11 +52,19 @@ impl AudioInterface for Sdl2AudioPlayer {
     }
 }
 
-pub fn create_audio_player(sdl: &sdl2::Sdl) -> Sdl2AudioPlayer {
+/// `latency` is the size of the device buffer in milliseconds, SDL picks one when it's None
+pub fn create_audio_player(sdl: &sdl2::Sdl, latency: Option<u32>) -> Sdl2AudioPlayer {
+    let sample_rate = 44_100;
     let desired_spec = AudioSpecDesired {
-        freq: Some(44_100),
+        freq: Some(sample_rate),
         channels: Some(2), // stereo
-        samples: None,
+        // SDL wants a power of two
+        samples: latency.map(|ms| {
+            cmp::min(
+                (sample_rate as u32 * ms / 1000).next_power_of_two(),
+                1 << 15,
+            ) as u16
+        }),
     };
 
     let audio_subsystem = sdl.audio().unwrap();
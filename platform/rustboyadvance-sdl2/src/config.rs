@@ -0,0 +1,198 @@
+//! The configuration file, `~/.config/rustboyadvance/config.toml` unless `--config` is given.
+//!
+//! ```toml
+//! bios = "/path/to/gba_bios.bin"
+//! save_dir = "/path/to/saves"
+//! state_dir = "/path/to/states"
+//!
+//! [keys]
+//! a = "X"
+//! b = "Z"
+//! start = "Return"
+//!
+//! [keys2]
+//! a = "H"
+//!
+//! [turbo]
+//! rate = 2
+//!
+//! [turbo.keys]
+//! a = "C"
+//!
+//! [combos]
+//! Q = ["a", "b"]
+//!
+//! [macros]
+//! W = "down:2 down+right:2 right:2 a"
+//!
+//! [video]
+//! scale = 4
+//! filter = "scale2x"
+//!
+//! [audio]
+//! latency = 40
+//! interpolation = "linear"
+//! lowpass = "speaker"
+//!
+//! [game.BPEE]
+//! save_type = "flash128k"
+//! rtc = true
+//! ```
+//!
+//! Every entry is optional, and the command line options take priority over the file.
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bios: Option<PathBuf>,
+    /// Directory for the game save files, instead of next to the rom
+    pub save_dir: Option<PathBuf>,
+    /// Directory for the save states, instead of next to the rom
+    pub state_dir: Option<PathBuf>,
+    /// Keyboard bindings, the SDL key name by GBA key (a, b, l, r, start, select, up, down, left
+    /// and right). The keys that aren't listed keep their default binding.
+    pub keys: HashMap<String, String>,
+    /// Keyboard bindings of player 2 with `--link-local`, like `keys`
+    pub keys2: HashMap<String, String>,
+    pub turbo: TurboConfig,
+    /// Keyboard keys that press several GBA keys at once, by SDL key name
+    pub combos: HashMap<String, Vec<String>>,
+    /// Keyboard keys that play a sequence of inputs, by SDL key name, see `input::Macro::parse`
+    pub macros: HashMap<String, String>,
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    /// Settings for specific games, by game code
+    #[serde(rename = "game")]
+    pub games: HashMap<String, GameConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TurboConfig {
+    /// Frames the turbo keys stay pressed, then as many frames released
+    pub rate: Option<u32>,
+    /// The SDL key name by GBA key, these keys press the GBA key repeatedly while they are held
+    pub keys: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    pub scale: Option<u32>,
+    pub fullscreen: bool,
+    pub filter: ScaleFilter,
+}
+
+/// How the screen is scaled to the window size
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleFilter {
+    Nearest,
+    /// Nearest neighbor at the largest whole multiple of the GBA resolution that fits
+    Integer,
+    /// Bilinear
+    Linear,
+    /// The Scale2x (EPX) pixel art filter, then nearest neighbor
+    Scale2x,
+}
+
+impl Default for ScaleFilter {
+    fn default() -> ScaleFilter {
+        ScaleFilter::Nearest
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ScaleFilter, String> {
+        match s {
+            "nearest" => Ok(ScaleFilter::Nearest),
+            "integer" => Ok(ScaleFilter::Integer),
+            "linear" => Ok(ScaleFilter::Linear),
+            "scale2x" => Ok(ScaleFilter::Scale2x),
+            _ => Err(format!("unknown video filter {:?}", s)),
+        }
+    }
+}
+
+impl ScaleFilter {
+    /// The value of the SDL_RENDER_SCALE_QUALITY hint
+    pub fn sdl_hint(self) -> &'static str {
+        match self {
+            ScaleFilter::Linear => "linear",
+            _ => "nearest",
+        }
+    }
+
+    /// The filter after this one, for switching at runtime
+    pub fn next(self) -> ScaleFilter {
+        match self {
+            ScaleFilter::Nearest => ScaleFilter::Integer,
+            ScaleFilter::Integer => ScaleFilter::Linear,
+            ScaleFilter::Linear => ScaleFilter::Scale2x,
+            ScaleFilter::Scale2x => ScaleFilter::Nearest,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Size of the audio device buffer in milliseconds, left to SDL when missing
+    pub latency: Option<u32>,
+    /// Same as `--audio-interpolation`
+    pub interpolation: Option<String>,
+    /// Same as `--audio-lowpass`
+    pub lowpass: Option<String>,
+}
+
+/// Takes the same values as the command line options of the same name
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    pub save_type: Option<String>,
+    pub flash_vendor: Option<String>,
+    pub rtc: bool,
+    pub frameskip: Option<String>,
+}
+
+impl Config {
+    fn default_path() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("rustboyadvance").join("config.toml"))
+    }
+
+    /// Loads `path`, or the default config file if it exists
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Config::default_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+        info!("Loaded config from {}", path.display());
+        Ok(config)
+    }
+
+    /// The settings for the game with `game_code`
+    pub fn game(&self, game_code: &str) -> GameConfig {
+        self.games.get(game_code).cloned().unwrap_or_default()
+    }
+}
@@ -0,0 +1,52 @@
+/// Encodes the raw recordings of the core with an `ffmpeg` child process
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+
+use rustboyadvance_core::gpu::consts::CYCLES_FULL_REFRESH;
+use rustboyadvance_core::prelude::*;
+
+/// The ffmpeg input options that describe the raw video
+fn video_args() -> Vec<String> {
+    vec![
+        "-f".to_string(),
+        "rawvideo".to_string(),
+        "-pixel_format".to_string(),
+        "rgb24".to_string(),
+        "-video_size".to_string(),
+        format!("{}x{}", DISPLAY_WIDTH, DISPLAY_HEIGHT),
+        // the exact refresh rate of the hardware, about 59.7275 fps
+        "-framerate".to_string(),
+        format!("16777216/{}", CYCLES_FULL_REFRESH),
+    ]
+}
+
+/// Encodes `recording` into `output` in the background, `args` (e.g codec options) are passed to
+/// ffmpeg before the output file. The raw files are removed once ffmpeg succeeded.
+pub fn encode(recording: Recording, output: PathBuf, args: &[String]) -> io::Result<()> {
+    let mut child = Command::new("ffmpeg")
+        .arg("-y")
+        .args(video_args())
+        .arg("-i")
+        .arg(&recording.video)
+        .arg("-i")
+        .arg(&recording.audio)
+        .args(args)
+        .arg(&output)
+        .spawn()?;
+    thread::spawn(move || {
+        match child.wait() {
+            Ok(status) if status.success() => {
+                info!("encoded the recording to {:?}", output);
+                let _ = fs::remove_file(&recording.video);
+                let _ = fs::remove_file(&recording.audio);
+            }
+            // the dump is kept, so it can be encoded by hand
+            Ok(status) => warn!("ffmpeg failed ({}), the dump is kept", status),
+            Err(e) => warn!("ffmpeg failed: {}", e),
+        }
+    });
+    Ok(())
+}
@@ -1,35 +1,198 @@
+use std::collections::HashMap;
+
 use sdl2::controller::Axis;
 use sdl2::controller::Button;
 use sdl2::keyboard::Scancode;
 
-use rustboyadvance_core::keypad as gba_keypad;
+use rustboyadvance_core::keypad::{self as gba_keypad, KeyState};
 use rustboyadvance_core::InputInterface;
 
 use bit;
 use bit::BitIndex;
 
+use super::config::Config;
+
+/// The turbo rates F8 cycles through, in frames
+pub const TURBO_RATES: [u32; 4] = [1, 2, 4, 8];
+const DEFAULT_TURBO_RATE: u32 = 2;
+
+/// Keys the frontend handles itself, see the special key bindings in the README
+const HOTKEYS: [Scancode; 30] = [
+    Scancode::Space,
+    Scancode::Tab,
+    Scancode::P,
+    Scancode::F,
+    Scancode::R,
+    Scancode::M,
+    Scancode::Minus,
+    Scancode::Equals,
+    Scancode::Num0,
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Num5,
+    Scancode::Num6,
+    Scancode::Num7,
+    Scancode::Num8,
+    Scancode::Num9,
+    Scancode::F1,
+    Scancode::F2,
+    Scancode::F3,
+    Scancode::F4,
+    Scancode::F5,
+    Scancode::F6,
+    Scancode::F7,
+    Scancode::F8,
+    Scancode::F9,
+    Scancode::F10,
+    Scancode::F11,
+    Scancode::F12,
+];
+
+/// A sequence of key states, each held for a number of frames
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    steps: Vec<(KeyState, u32)>,
+}
+
+impl Macro {
+    /// Parses steps like `down:2 down+right:2 right:2 a`, the keys joined with `+` are held for
+    /// the number of frames after the colon (1 when missing), `-` holds no key
+    pub fn parse(text: &str) -> Result<Macro, String> {
+        let mut steps = Vec::new();
+        for step in text.split_whitespace() {
+            let mut parts = step.splitn(2, ':');
+            let keys = parts.next().unwrap_or_default();
+            let frames = match parts.next() {
+                Some(frames) => frames
+                    .parse()
+                    .map_err(|_| format!("invalid frame count in {:?}", step))?,
+                None => 1,
+            };
+            let mut state = KeyState::empty();
+            if keys != "-" {
+                for key in keys.split('+') {
+                    let key =
+                        parse_gba_key(key).ok_or_else(|| format!("unknown GBA key {:?}", key))?;
+                    state |= KeyState::from(key);
+                }
+            }
+            steps.push((state, frames));
+        }
+        if steps.is_empty() {
+            return Err("empty macro".to_string());
+        }
+        Ok(Macro { steps })
+    }
+
+    /// Number of frames the macro lasts
+    pub fn frames(&self) -> u32 {
+        self.steps.iter().map(|(_, frames)| frames).sum()
+    }
+
+    /// The keys held at `frame` frames from the start
+    fn keys_at(&self, frame: u32) -> KeyState {
+        let mut start = 0;
+        for (keys, frames) in &self.steps {
+            start += frames;
+            if frame < start {
+                return *keys;
+            }
+        }
+        KeyState::empty()
+    }
+}
+
 pub struct Sdl2Input {
+    /// The controller buttons
     keyinput: u16,
     axis_keyinput: u16,
+    /// The GBA keys pressed by each held keyboard key, a GBA key is released once none of them
+    /// presses it
+    keyboard_held: HashMap<Scancode, KeyState>,
+    /// The KEYINPUT bit of each bound keyboard key
+    keymap: HashMap<Scancode, usize>,
+    /// Keyboard keys that press several GBA keys at once
+    combos: HashMap<Scancode, KeyState>,
+    /// Keyboard keys that press a GBA key repeatedly while held
+    turbo_keymap: HashMap<Scancode, KeyState>,
+    /// The GBA keys pressed by each held turbo key
+    turbo_held: HashMap<Scancode, KeyState>,
+    /// Frames the turbo keys stay pressed, then as many frames released
+    turbo_rate: u32,
+    /// Frames since the first turbo key was pressed
+    turbo_frame: u32,
+    macros: HashMap<Scancode, Macro>,
+    /// The macro being played, and the number of frames it ran
+    playing_macro: Option<(Macro, u32)>,
 }
 
 impl InputInterface for Sdl2Input {
     fn poll(&mut self) -> u16 {
-        !(!self.keyinput | !self.axis_keyinput)
+        let mut pressed = !self.keyinput | !self.axis_keyinput;
+        pressed |= union(self.keyboard_held.values()).bits();
+        if !self.turbo_held.is_empty() {
+            if (self.turbo_frame / self.turbo_rate) % 2 == 0 {
+                pressed |= union(self.turbo_held.values()).bits();
+            }
+            self.turbo_frame += 1;
+        }
+        if let Some((playing, frame)) = &mut self.playing_macro {
+            pressed |= playing.keys_at(*frame).bits();
+            *frame += 1;
+            if *frame >= playing.frames() {
+                self.playing_macro = None;
+            }
+        }
+        !pressed
     }
 }
 
+fn union<'a>(keys: impl Iterator<Item = &'a KeyState>) -> KeyState {
+    keys.fold(KeyState::empty(), |union, &keys| union | keys)
+}
+
 impl Sdl2Input {
+    /// Called once for every key press, not for the repeated events of a held key
     pub fn on_keyboard_key_down(&mut self, scancode: Scancode) {
-        if let Some(key) = scancode_to_keypad(scancode) {
-            self.keyinput.set_bit(key as usize, false);
+        let mut keys = self.combos.get(&scancode).copied().unwrap_or_default();
+        if let Some(&bit) = self.keymap.get(&scancode) {
+            keys |= KeyState::from_bits_truncate(1 << bit);
+        }
+        if !keys.is_empty() {
+            self.keyboard_held.insert(scancode, keys);
+        }
+        if let Some(&keys) = self.turbo_keymap.get(&scancode) {
+            if self.turbo_held.is_empty() {
+                self.turbo_frame = 0;
+            }
+            self.turbo_held.insert(scancode, keys);
+        }
+        if let Some(playing) = self.macros.get(&scancode) {
+            self.playing_macro = Some((playing.clone(), 0));
         }
     }
 
     pub fn on_keyboard_key_up(&mut self, scancode: Scancode) {
-        if let Some(key) = scancode_to_keypad(scancode) {
-            self.keyinput.set_bit(key as usize, true);
-        }
+        self.keyboard_held.remove(&scancode);
+        self.turbo_held.remove(&scancode);
+    }
+
+    /// Whether `scancode` presses a GBA key, or plays a macro
+    pub fn binds(&self, scancode: Scancode) -> bool {
+        self.keymap.contains_key(&scancode)
+            || self.combos.contains_key(&scancode)
+            || self.turbo_keymap.contains_key(&scancode)
+            || self.macros.contains_key(&scancode)
+    }
+
+    pub fn turbo_rate(&self) -> u32 {
+        self.turbo_rate
+    }
+
+    pub fn set_turbo_rate(&mut self, frames: u32) {
+        self.turbo_rate = frames.max(1);
     }
 
     pub fn on_controller_button_down(&mut self, button: Button) {
@@ -70,18 +233,58 @@ impl Sdl2Input {
     }
 }
 
-fn scancode_to_keypad(scancode: Scancode) -> Option<gba_keypad::Keys> {
-    match scancode {
-        Scancode::Up => Some(gba_keypad::Keys::Up),
-        Scancode::Down => Some(gba_keypad::Keys::Down),
-        Scancode::Left => Some(gba_keypad::Keys::Left),
-        Scancode::Right => Some(gba_keypad::Keys::Right),
-        Scancode::Z => Some(gba_keypad::Keys::ButtonB),
-        Scancode::X => Some(gba_keypad::Keys::ButtonA),
-        Scancode::Return => Some(gba_keypad::Keys::Start),
-        Scancode::Backspace => Some(gba_keypad::Keys::Select),
-        Scancode::A => Some(gba_keypad::Keys::ButtonL),
-        Scancode::S => Some(gba_keypad::Keys::ButtonR),
+fn default_keymap() -> HashMap<Scancode, usize> {
+    use gba_keypad::Keys as GbaKeys;
+    vec![
+        (Scancode::Up, GbaKeys::Up),
+        (Scancode::Down, GbaKeys::Down),
+        (Scancode::Left, GbaKeys::Left),
+        (Scancode::Right, GbaKeys::Right),
+        (Scancode::Z, GbaKeys::ButtonB),
+        (Scancode::X, GbaKeys::ButtonA),
+        (Scancode::Return, GbaKeys::Start),
+        (Scancode::Backspace, GbaKeys::Select),
+        (Scancode::A, GbaKeys::ButtonL),
+        (Scancode::S, GbaKeys::ButtonR),
+    ]
+    .into_iter()
+    .map(|(scancode, key)| (scancode, key as usize))
+    .collect()
+}
+
+/// The layout of player 2 with `--link-local`, on the other side of the keyboard
+fn player2_keymap() -> HashMap<Scancode, usize> {
+    use gba_keypad::Keys as GbaKeys;
+    vec![
+        (Scancode::I, GbaKeys::Up),
+        (Scancode::K, GbaKeys::Down),
+        (Scancode::J, GbaKeys::Left),
+        (Scancode::L, GbaKeys::Right),
+        (Scancode::G, GbaKeys::ButtonB),
+        (Scancode::H, GbaKeys::ButtonA),
+        (Scancode::U, GbaKeys::Start),
+        (Scancode::O, GbaKeys::Select),
+        (Scancode::T, GbaKeys::ButtonL),
+        (Scancode::Y, GbaKeys::ButtonR),
+    ]
+    .into_iter()
+    .map(|(scancode, key)| (scancode, key as usize))
+    .collect()
+}
+
+fn parse_gba_key(name: &str) -> Option<gba_keypad::Keys> {
+    use gba_keypad::Keys as GbaKeys;
+    match name {
+        "a" => Some(GbaKeys::ButtonA),
+        "b" => Some(GbaKeys::ButtonB),
+        "l" => Some(GbaKeys::ButtonL),
+        "r" => Some(GbaKeys::ButtonR),
+        "start" => Some(GbaKeys::Start),
+        "select" => Some(GbaKeys::Select),
+        "up" => Some(GbaKeys::Up),
+        "down" => Some(GbaKeys::Down),
+        "left" => Some(GbaKeys::Left),
+        "right" => Some(GbaKeys::Right),
         _ => None,
     }
 }
@@ -102,9 +305,157 @@ fn controller_button_to_keypad(button: Button) -> Option<gba_keypad::Keys> {
     }
 }
 
-pub fn create_input() -> Sdl2Input {
+/// Parses a keyboard key to bind, the hotkeys can't be
+fn parse_scancode(key_name: &str) -> Result<Scancode, String> {
+    let scancode = Scancode::from_name(key_name)
+        .ok_or_else(|| format!("unknown keyboard key {:?}", key_name))?;
+    if HOTKEYS.contains(&scancode) {
+        return Err(format!(
+            "{} is a hotkey and can't be bound",
+            scancode.name()
+        ));
+    }
+    Ok(scancode)
+}
+
+fn parse_gba_key_or_err(name: &str) -> Result<gba_keypad::Keys, String> {
+    parse_gba_key(name).ok_or_else(|| format!("unknown GBA key {:?}", name))
+}
+
+/// `keys` has the SDL name of the keyboard key by GBA key name, and replaces the binding of these
+/// GBA keys in `keymap`
+fn bind_keys(
+    mut keymap: HashMap<Scancode, usize>,
+    keys: &HashMap<String, String>,
+) -> Result<HashMap<Scancode, usize>, String> {
+    for (gba_key, key_name) in keys {
+        let bit = parse_gba_key_or_err(gba_key)? as usize;
+        let scancode = parse_scancode(key_name)?;
+        keymap.retain(|_, bound| *bound != bit);
+        keymap.insert(scancode, bit);
+    }
+    Ok(keymap)
+}
+
+fn new_input(keymap: HashMap<Scancode, usize>) -> Sdl2Input {
     Sdl2Input {
         keyinput: gba_keypad::KEYINPUT_ALL_RELEASED,
         axis_keyinput: gba_keypad::KEYINPUT_ALL_RELEASED,
+        keyboard_held: HashMap::new(),
+        keymap,
+        combos: HashMap::new(),
+        turbo_keymap: HashMap::new(),
+        turbo_held: HashMap::new(),
+        turbo_rate: DEFAULT_TURBO_RATE,
+        turbo_frame: 0,
+        macros: HashMap::new(),
+        playing_macro: None,
+    }
+}
+
+/// The `[keys]` of the config replace the default binding of their GBA keys. The turbo keys,
+/// combos and macros are bound on top.
+pub fn create_input(config: &Config) -> Result<Sdl2Input, String> {
+    let keymap = bind_keys(default_keymap(), &config.keys)?;
+    let mut turbo_keymap = HashMap::new();
+    for (gba_key, key_name) in &config.turbo.keys {
+        let key = parse_gba_key_or_err(gba_key)?;
+        turbo_keymap.insert(parse_scancode(key_name)?, KeyState::from(key));
+    }
+    let mut combos = HashMap::new();
+    for (key_name, gba_keys) in &config.combos {
+        let mut keys = KeyState::empty();
+        for gba_key in gba_keys {
+            keys |= KeyState::from(parse_gba_key_or_err(gba_key)?);
+        }
+        combos.insert(parse_scancode(key_name)?, keys);
+    }
+    let mut macros = HashMap::new();
+    for (key_name, text) in &config.macros {
+        let parsed = Macro::parse(text).map_err(|e| format!("macro {}: {}", key_name, e))?;
+        macros.insert(parse_scancode(key_name)?, parsed);
+    }
+    Ok(Sdl2Input {
+        combos,
+        turbo_keymap,
+        turbo_rate: config.turbo.rate.unwrap_or(DEFAULT_TURBO_RATE).max(1),
+        macros,
+        ..new_input(keymap)
+    })
+}
+
+/// The input of player 2 with `--link-local`, the `[keys2]` of the config replace the default
+/// binding of their GBA keys. None of its keys may be bound for player 1.
+pub fn create_player2_input(config: &Config, player1: &Sdl2Input) -> Result<Sdl2Input, String> {
+    let keymap = bind_keys(player2_keymap(), &config.keys2)?;
+    if let Some(scancode) = keymap.keys().find(|&&scancode| player1.binds(scancode)) {
+        return Err(format!("{} is bound for both players", scancode.name()));
+    }
+    Ok(new_input(keymap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gba_keypad::Keys as GbaKeys;
+
+    #[test]
+    fn test_macro_parse() {
+        let parsed = Macro::parse("down:2 down+right:3 - a").unwrap();
+        assert_eq!(
+            parsed.steps,
+            vec![
+                (KeyState::from(GbaKeys::Down), 2),
+                (
+                    KeyState::from(GbaKeys::Down) | KeyState::from(GbaKeys::Right),
+                    3
+                ),
+                (KeyState::empty(), 1),
+                (KeyState::from(GbaKeys::ButtonA), 1),
+            ]
+        );
+        assert_eq!(parsed.frames(), 7);
+
+        assert!(Macro::parse("").is_err());
+        assert!(Macro::parse("a:x").is_err());
+        assert!(Macro::parse("a+c").is_err());
+    }
+
+    #[test]
+    fn test_macro_keys_at() {
+        let parsed = Macro::parse("down:2 - a").unwrap();
+        assert_eq!(parsed.keys_at(0), KeyState::from(GbaKeys::Down));
+        assert_eq!(parsed.keys_at(1), KeyState::from(GbaKeys::Down));
+        assert_eq!(parsed.keys_at(2), KeyState::empty());
+        assert_eq!(parsed.keys_at(3), KeyState::from(GbaKeys::ButtonA));
+        assert_eq!(parsed.keys_at(4), KeyState::empty());
+    }
+
+    #[test]
+    fn test_combo_release() {
+        let mut input = new_input(default_keymap());
+        input.combos.insert(
+            Scancode::Q,
+            KeyState::from(GbaKeys::ButtonA) | KeyState::from(GbaKeys::ButtonB),
+        );
+        input.on_keyboard_key_down(Scancode::X);
+        input.on_keyboard_key_down(Scancode::Q);
+        input.on_keyboard_key_up(Scancode::Q);
+        // A is still held with its own key
+        assert_eq!(
+            KeyState::from_keyinput(input.poll()),
+            KeyState::from(GbaKeys::ButtonA)
+        );
+        input.on_keyboard_key_up(Scancode::X);
+        assert_eq!(KeyState::from_keyinput(input.poll()), KeyState::empty());
+    }
+
+    #[test]
+    fn test_hotkeys_not_bindable() {
+        assert_eq!(parse_scancode("C"), Ok(Scancode::C));
+        assert!(parse_scancode("F").is_err());
+        assert!(parse_scancode("F8").is_err());
+        assert!(parse_scancode("0").is_err());
     }
 }
@@ -1,23 +1,28 @@
 use sdl2;
-use sdl2::controller::Button;
+use sdl2::controller::{Button, GameController};
 use sdl2::event::{Event, WindowEvent};
 use sdl2::image::{InitFlag, LoadSurface, LoadTexture};
 use sdl2::keyboard::Scancode;
+use sdl2::messagebox::{
+    show_message_box, ButtonData, ClickedButton, MessageBoxButtonFlag, MessageBoxFlag,
+};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
 use sdl2::surface::Surface;
+use sdl2::video::FullscreenType;
 
 use sdl2::EventPump;
 
 use bytesize;
-use spin_sleep;
+use chrono::NaiveDateTime;
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use std::env;
 use std::fs;
-use std::io::Cursor;
+use std::io::{self, Cursor};
 use std::path::{Path, PathBuf};
 use std::process;
 use std::time;
@@ -33,26 +38,322 @@ use flexi_logger;
 use flexi_logger::*;
 
 mod audio;
+mod config;
+mod ffmpeg;
 mod input;
+mod rom_browser;
+mod serial;
+mod settings;
+mod tcp_link;
 mod video;
 
 use audio::{create_audio_player, create_dummy_player};
-use input::create_input;
-use video::{create_video_interface, SCREEN_HEIGHT, SCREEN_WIDTH};
+use config::{Config, GameConfig};
+use input::{create_input, create_player2_input, Sdl2Input, TURBO_RATES};
+use serial::SerialBridge;
+use settings::Settings;
+use tcp_link::TcpLink;
+use video::{create_video_interface, Sdl2Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
 
-use rustboyadvance_core::cartridge::BackupType;
+use rustboyadvance_core::cartridge::{BackupType, FlashVendor};
+use rustboyadvance_core::logging::{LogCapture, LogFilter};
+use rustboyadvance_core::meminit::MemoryInit;
+use rustboyadvance_core::movie::Movie;
+use rustboyadvance_core::pacing::{FastForward, FramePacer};
+use rustboyadvance_core::perf::{PerfReport, Profile};
 use rustboyadvance_core::prelude::*;
+use rustboyadvance_core::savestate_file::{StateFile, StateSlots};
+use rustboyadvance_core::sio::LocalLink;
+use rustboyadvance_core::sound::{AudioFilter, DmaInterpolation, ResamplerKind, Volume};
 use rustboyadvance_core::util::spawn_and_run_gdb_server;
 use rustboyadvance_core::util::FpsCounter;
 
+#[cfg(feature = "debugger")]
+use rustboyadvance_core::arm7tdmi::trace::{InstructionTrace, TraceFilter};
+
 const LOG_DIR: &str = ".logs";
+/// Log records kept in memory for the full screen debugger
+const LOG_CAPTURE_SIZE: usize = 1024;
 const DEFAULT_GDB_SERVER_ADDR: &'static str = "localhost:1337";
+/// Frames between rewind snapshots, holding the rewind key goes back this many frames per frame
+const REWIND_INTERVAL: usize = 10;
 
 const CANVAS_WIDTH: u32 = SCREEN_WIDTH;
 const CANVAS_HEIGHT: u32 = SCREEN_HEIGHT;
 
-fn get_savestate_path(rom_filename: &Path) -> PathBuf {
-    rom_filename.with_extension("savestate")
+const VOLUME_STEP: u16 = 10;
+const RTC_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+/// How long on-screen messages stay in the window title
+const OSD_DURATION: time::Duration = time::Duration::from_secs(2);
+
+/// The save state slots of the running game, in the state directory or next to the rom
+fn get_state_slots(gba: &GameBoyAdvance, rom_path: &str, state_dir: Option<&Path>) -> StateSlots {
+    let rom_path = Path::new(rom_path);
+    let dir = state_dir
+        .or_else(|| rom_path.parent())
+        .unwrap_or_else(|| Path::new("."));
+    let rom_stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    let slots = StateSlots::new(dir, &gba.get_game_code(), &rom_stem);
+    // older versions kept a single `<rom>.savestate`, it becomes slot 0
+    let legacy_path = dir.join(format!("{}.savestate", rom_stem));
+    let slot_path = slots.slot_path(0);
+    if legacy_path.is_file() && !slot_path.exists() {
+        match fs::rename(&legacy_path, &slot_path) {
+            Ok(()) => info!("Moved {:?} to save state slot 0", legacy_path),
+            Err(e) => warn!("failed to move {:?} to {:?}: {}", legacy_path, slot_path, e),
+        }
+    }
+    slots
+}
+
+fn save_state_file(gba: &GameBoyAdvance, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let saved_at = chrono::Utc::now().timestamp().max(0) as u64;
+    let bytes = StateFile::capture(gba, saved_at)?.encode();
+    fs::write(path, &bytes)?;
+    info!(
+        "Saved to {:?} ({})",
+        path,
+        bytesize::ByteSize::b(bytes.len() as u64)
+    );
+    Ok(())
+}
+
+fn load_state_file(
+    gba: &mut GameBoyAdvance,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = StateFile::read(path)?;
+    let game_code = gba.get_game_code();
+    if !file.game_code.is_empty() && file.game_code != game_code.trim_end_matches('\0') {
+        return Err(format!("{:?} is a state of another game ({})", path, file.game_code).into());
+    }
+    info!("Restoring state from {:?}...", path);
+    gba.restore_state(&file.state)?;
+    Ok(())
+}
+
+/// "Slot N" along with the time the slot was saved
+fn slot_message(slots: &StateSlots, slot: usize) -> String {
+    match StateFile::read(&slots.slot_path(slot)) {
+        Ok(file) if file.saved_at != 0 => {
+            let saved_at = time::UNIX_EPOCH + time::Duration::from_secs(file.saved_at);
+            let saved_at = chrono::DateTime::<chrono::Local>::from(saved_at);
+            format!("Slot {} ({})", slot, saved_at.format(RTC_TIME_FORMAT))
+        }
+        Ok(_) => format!("Slot {}", slot),
+        Err(_) => format!("Slot {} (empty)", slot),
+    }
+}
+
+/// Asks whether to continue from the state saved when the emulator last exited
+fn ask_restore_autosave(rom_name: &str) -> bool {
+    let buttons = [
+        ButtonData {
+            flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT,
+            button_id: 1,
+            text: "Continue",
+        },
+        ButtonData {
+            flags: MessageBoxButtonFlag::ESCAPEKEY_DEFAULT,
+            button_id: 0,
+            text: "Start over",
+        },
+    ];
+    let message = format!(
+        "{} was running when the emulator last exited, continue from there?",
+        rom_name
+    );
+    match show_message_box(
+        MessageBoxFlag::INFORMATION,
+        &buttons,
+        "RustBoyAdvance",
+        &message,
+        None,
+        None,
+    ) {
+        Ok(ClickedButton::CustomButton(button)) => button.button_id == 1,
+        Ok(ClickedButton::CloseButton) => false,
+        Err(e) => {
+            warn!("failed to show the autosave prompt: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Restores the state saved when the emulator last exited while running this rom, if the user
+/// chooses to continue from there
+fn offer_autosave(gba: &mut GameBoyAdvance, slots: &StateSlots, rom_name: &str) {
+    let autosave_path = slots.autosave_path();
+    if autosave_path.is_file() && ask_restore_autosave(rom_name) {
+        if let Err(e) = load_state_file(gba, &autosave_path) {
+            error!("failed to restore {:?}: {}", autosave_path, e);
+        }
+    }
+}
+
+/// The value of the option `name` when it's on the command line, then `config`, then the default
+/// value of the option
+fn arg_or_config<'a>(
+    matches: &'a clap::ArgMatches,
+    name: &str,
+    config: Option<&'a str>,
+) -> Option<&'a str> {
+    if matches.occurrences_of(name) == 0 {
+        config.or_else(|| matches.value_of(name))
+    } else {
+        matches.value_of(name)
+    }
+}
+
+/// The audio post-processing given by `--audio-interpolation` and `--audio-lowpass`
+fn parse_audio_filter(
+    matches: &clap::ArgMatches,
+    config: &Config,
+) -> Result<AudioFilter, Box<dyn std::error::Error>> {
+    let dma_interpolation: DmaInterpolation = arg_or_config(
+        matches,
+        "audio_interpolation",
+        config.audio.interpolation.as_deref(),
+    )
+    .unwrap()
+    .parse()?;
+    let lowpass_cutoff =
+        match arg_or_config(matches, "audio_lowpass", config.audio.lowpass.as_deref()) {
+            None => None,
+            Some("speaker") => Some(AudioFilter::SPEAKER_CUTOFF),
+            Some(hz) => match hz.parse::<f32>() {
+                Ok(cutoff) if cutoff > 0.0 => Some(cutoff),
+                _ => {
+                    return Err(format!("invalid low-pass cutoff {:?}, expected Hz > 0", hz).into())
+                }
+            },
+        };
+    Ok(AudioFilter {
+        dma_interpolation,
+        lowpass_cutoff,
+    })
+}
+
+/// A builder for the rom at `rom_path`, with the save settings of its config file section
+fn gamepak_builder(
+    rom_path: &Path,
+    game: &GameConfig,
+    save_dir: Option<&Path>,
+) -> Result<GamepakBuilder, Box<dyn std::error::Error>> {
+    let mut builder = GamepakBuilder::new().file(rom_path);
+    if let Some(save_dir) = save_dir {
+        builder = builder.save_dir(save_dir);
+    }
+    if let Some(save_type) = &game.save_type {
+        builder = builder.save_type(BackupType::try_from(save_type.as_str())?);
+    }
+    if let Some(vendor) = &game.flash_vendor {
+        builder = builder.flash_vendor(FlashVendor::try_from(vendor.as_str())?);
+    }
+    if game.rtc {
+        builder = builder.with_rtc();
+    }
+    Ok(builder)
+}
+
+/// The save file of player 2 when both players of `--link-local` run the same rom
+fn player2_save_path(rom_path: &Path, save_dir: Option<&Path>) -> PathBuf {
+    let dir = save_dir
+        .or_else(|| rom_path.parent())
+        .unwrap_or_else(|| Path::new("."));
+    let rom_stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    dir.join(format!("{}-player2.sav", rom_stem))
+}
+
+/// The config file section of the rom at `rom_path`, the roms are matched by game code
+fn game_config(config: &Config, rom_path: &Path) -> GameConfig {
+    if config.games.is_empty() {
+        return GameConfig::default();
+    }
+    match GamepakBuilder::new()
+        .file(rom_path)
+        .without_backup_to_file()
+        .build()
+    {
+        Ok(cartridge) => config.game(&cartridge.header.game_code),
+        Err(_) => GameConfig::default(),
+    }
+}
+
+fn get_capture_path(rom_filename: &Path, extension: &str) -> PathBuf {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let rom_name = rom_filename
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    rom_filename.with_file_name(format!("{}-{}.{}", rom_name, timestamp, extension))
+}
+
+/// Completes the recording in progress, if any, and encodes it to mp4 when `encode` is set
+fn stop_recording(gba: &mut GameBoyAdvance, encode: bool) -> io::Result<()> {
+    match gba.stop_recording()? {
+        Some(recording) if encode => {
+            // `<rom>-<timestamp>.dump.rgb` is encoded to `<rom>-<timestamp>.mp4`
+            let output = recording.video.with_extension("").with_extension("mp4");
+            ffmpeg::encode(recording, output, &[])
+        }
+        _ => Ok(()),
+    }
+}
+
+fn window_title(rom_name: &str, fps: u32, osd: Option<&str>) -> String {
+    match osd {
+        Some(message) => format!("{} ({} fps) - {}", rom_name, fps, message),
+        None => format!("{} ({} fps)", rom_name, fps),
+    }
+}
+
+/// The performance counters, shown in the window title with F4
+fn perf_message(report: &PerfReport) -> String {
+    let millis = |time: time::Duration| time.as_secs_f64() * 1000.0;
+    format!(
+        "{:.1} MIPS, cpu load {:.0}%, {:.2}ms per frame (cpu {:.2}, ppu {:.2}, apu {:.2}, dma {:.2})",
+        report.instructions_per_second / 1_000_000.0,
+        report.cpu_load() * 100.0,
+        millis(report.frame_time),
+        millis(report.cpu_time),
+        millis(report.ppu_time),
+        millis(report.apu_time),
+        millis(report.dma_time)
+    )
+}
+
+/// Writes the statistics of a `--profile` run to `output`, or to the log
+fn write_profile(profile: &Profile, output: Option<&str>) -> std::io::Result<()> {
+    match output {
+        Some(path) if path.ends_with(".json") => fs::write(path, profile.to_json()),
+        Some(path) => fs::write(path, profile.to_table()),
+        None => {
+            info!("profile of the first frames:\n{}", profile.to_table());
+            Ok(())
+        }
+    }
+}
+
+fn volume_message(volume: Volume) -> String {
+    if volume.is_muted() {
+        "Muted".to_string()
+    } else {
+        format!("Volume {}%", volume.percent())
+    }
+}
+
+fn turbo_message(rate: u32) -> String {
+    // a press and a release every 2 * rate frames, at 60 frames per second
+    format!("Turbo: {} presses per second", 30 / rate)
+}
+
+fn get_rom_name(rom_path: &str) -> String {
+    Path::new(rom_path)
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned()
 }
 
 /// Waits for the user to drag a rom file to window
@@ -98,13 +399,130 @@ fn wait_for_rom(canvas: &mut WindowCanvas, event_pump: &mut EventPump) -> Result
     }
 }
 
+/// Applies the `--rtc-*` options to the cartridge clock
+fn configure_rtc(
+    gba: &mut GameBoyAdvance,
+    matches: &clap::ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let time = matches.value_of("rtc_time");
+    let speed = matches.value_of("rtc_speed");
+    let freeze = matches.occurrences_of("rtc_freeze") != 0;
+    if time.is_none() && speed.is_none() && !freeze {
+        return Ok(());
+    }
+    let clock = match gba.rtc_clock_mut() {
+        Some(clock) => clock,
+        None => {
+            warn!("the cartridge has no RTC, ignoring the rtc options (try --rtc)");
+            return Ok(());
+        }
+    };
+    if let Some(time) = time {
+        clock.set_time(NaiveDateTime::parse_from_str(time, RTC_TIME_FORMAT)?);
+    }
+    if let Some(speed) = speed {
+        let speed: f64 = speed.parse()?;
+        if speed < 0.0 {
+            return Err("the rtc speed can't be negative".into());
+        }
+        clock.set_speed(speed);
+    }
+    if freeze {
+        clock.set_frozen(true);
+    }
+    Ok(())
+}
+
+/// The options that apply to every rom of the session, see `create_gba`
+#[derive(Clone)]
+struct SessionOptions {
+    bios: Box<[u8]>,
+    video: Rc<RefCell<dyn VideoInterface>>,
+    audio: Rc<RefCell<dyn AudioInterface>>,
+    input: Rc<RefCell<dyn InputInterface>>,
+    mem_init: MemoryInit,
+    obj_cycle_limit: bool,
+    threaded_render: bool,
+    rewind_capacity: usize,
+    watchdog: Option<u32>,
+    resampler: ResamplerKind,
+    audio_filter: AudioFilter,
+    serial: Option<Rc<RefCell<dyn SerialInterface>>>,
+    link: Option<Rc<RefCell<dyn LinkTransport>>>,
+}
+
+/// Creates an emulator running `gamepak` with the options of the session
+fn create_gba(
+    options: &SessionOptions,
+    gamepak: Cartridge,
+    frameskip: FrameSkip,
+    skip_bios: bool,
+    matches: &clap::ArgMatches,
+) -> Result<GameBoyAdvance, Box<dyn std::error::Error>> {
+    let mut gba = GameBoyAdvance::new(
+        options.bios.clone(),
+        gamepak,
+        options.video.clone(),
+        options.audio.clone(),
+        options.input.clone(),
+    );
+    gba.init_memory(options.mem_init);
+    if skip_bios {
+        gba.skip_bios();
+    }
+    configure_rtc(&mut gba, matches)?;
+    gba.set_serial_interface(options.serial.clone());
+    gba.set_link_transport(options.link.clone());
+    gba.set_obj_cycle_limit(options.obj_cycle_limit);
+    gba.set_frameskip(frameskip);
+    gba.set_threaded_rendering(options.threaded_render)?;
+    gba.set_rewind(REWIND_INTERVAL, options.rewind_capacity);
+    gba.cpu.set_watchdog_limit(options.watchdog);
+    #[cfg(feature = "jit")]
+    gba.cpu.set_jit_enabled(true);
+    gba.set_resampler(options.resampler);
+    gba.set_audio_filter(options.audio_filter);
+    Ok(gba)
+}
+
+/// The second emulator of `--link-local`, on the other end of the link cable and on the right
+/// half of the window
+struct Player2 {
+    gba: GameBoyAdvance,
+    input: Rc<RefCell<Sdl2Input>>,
+    controller: Option<GameController>,
+}
+
+impl Player2 {
+    /// Whether `which` is the instance id of the game controller of player 2
+    fn owns_controller(&self, which: i32) -> bool {
+        self.controller
+            .as_ref()
+            .map_or(false, |controller| controller.instance_id() == which)
+    }
+}
+
 fn ask_download_bios() {
     const OPEN_SOURCE_BIOS_URL: &'static str =
         "https://github.com/Nebuleon/ReGBA/raw/master/bios/gba_bios.bin";
-    println!("Missing BIOS file. If you don't have the original GBA BIOS, you can download an open-source bios from {}", OPEN_SOURCE_BIOS_URL);
+    println!("Missing BIOS file, falling back to high level emulation of the BIOS functions. If you don't have the original GBA BIOS, you can download an open-source bios from {}", OPEN_SOURCE_BIOS_URL);
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Logs to a file and to stderr, or with `capture` only keeps the records in memory, for the full
+/// screen debugger that would be drawn over by the lines on stderr.
+fn init_logger(capture: bool) -> Option<&'static LogCapture> {
+    if capture {
+        let spec = env::var("RUST_LOG").unwrap_or_default();
+        let filter = spec.parse::<LogFilter>().unwrap_or_else(|e| {
+            eprintln!("RUST_LOG: {}", e);
+            LogFilter::default()
+        });
+        return Some(
+            LogCapture::new(filter, LOG_CAPTURE_SIZE)
+                .install()
+                .expect("the logger was already set"),
+        );
+    }
     fs::create_dir_all(LOG_DIR).expect(&format!("could not create log directory ({})", LOG_DIR));
     flexi_logger::Logger::with_env_or_str("info")
         .log_to_file()
@@ -114,23 +532,52 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .format_for_stderr(colored_default_format)
         .start()
         .unwrap();
+    None
+}
 
-    let mut frame_limiter = true;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let yaml = load_yaml!("cli.yml");
     let matches = clap::App::from_yaml(yaml).get_matches();
 
-    let bios_path = Path::new(matches.value_of("bios").unwrap_or_default());
-    let bios_bin = match read_bin_file(bios_path) {
+    #[cfg(feature = "debugger_tui")]
+    let log_capture = init_logger(matches.is_present("debug") && matches.is_present("tui"));
+    #[cfg(not(feature = "debugger_tui"))]
+    init_logger(false);
+
+    let config = Config::load(matches.value_of("config").map(Path::new))?;
+    if let Some(state_dir) = &config.state_dir {
+        fs::create_dir_all(state_dir)?;
+    }
+
+    let bios_path = match (&config.bios, matches.occurrences_of("bios")) {
+        (Some(path), 0) => path.clone(),
+        _ => PathBuf::from(matches.value_of("bios").unwrap_or_default()),
+    };
+    let bios_bin = match read_bin_file(&bios_path) {
         Ok(bios) => bios.into_boxed_slice(),
         _ => {
             ask_download_bios();
-            std::process::exit(0);
+            Box::default()
         }
     };
 
     let skip_bios = matches.occurrences_of("skip_bios") != 0;
 
     let debug = matches.occurrences_of("debug") != 0;
+    let debug_on_error = matches.occurrences_of("debug_on_error") != 0;
+    let tui = matches.occurrences_of("tui") != 0;
+    #[cfg(not(feature = "debugger"))]
+    {
+        if debug_on_error {
+            warn!("--debug-on-error requires the 'debugger' feature");
+        }
+    }
+    #[cfg(not(feature = "debugger_tui"))]
+    {
+        if tui {
+            warn!("--tui requires the 'debugger_tui' feature");
+        }
+    }
     let silent = matches.occurrences_of("silent") != 0;
     let with_gdbserver = matches.occurrences_of("with_gdbserver") != 0;
 
@@ -139,14 +586,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut event_pump = sdl_context.event_pump()?;
 
+    let scale: u32 = match (config.video.scale, matches.occurrences_of("scale")) {
+        (Some(scale), 0) => scale,
+        _ => matches.value_of("scale").unwrap().parse()?,
+    };
+    if scale == 0 {
+        return Err("the window scale must be at least 1".into());
+    }
+
+    // the screen of player 2 goes to the right of the first one
+    let link_local = matches.occurrences_of("link_local") != 0;
+    let screens = if link_local { 2 } else { 1 };
+
     let video_subsystem = sdl_context.video()?;
     let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
     let mut window = video_subsystem
-        .window("RustBoyAdvance", SCREEN_WIDTH * 3, SCREEN_HEIGHT * 3)
+        .window(
+            "RustBoyAdvance",
+            SCREEN_WIDTH * scale * screens,
+            SCREEN_HEIGHT * scale,
+        )
         .opengl()
         .position_centered()
         .resizable()
         .build()?;
+    if matches.occurrences_of("fullscreen") != 0 || config.video.fullscreen {
+        window.set_fullscreen(FullscreenType::Desktop)?;
+    }
 
     let window_icon = Surface::from_file("assets/icon.png")?;
     window.set_icon(window_icon);
@@ -175,48 +641,217 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
+    let mut settings = Settings::load();
+    if let Some(dirs) = matches.values_of("rom_dir") {
+        for dir in dirs.map(PathBuf::from) {
+            if !settings.rom_dirs.contains(&dir) {
+                settings.rom_dirs.push(dir);
+            }
+        }
+    }
+
     let mut rom_path = match matches.value_of("game_rom") {
         Some(path) => path.to_string(),
         _ => {
-            info!("[!] Rom file missing, please drag a rom file into the emulator window...");
-            wait_for_rom(&mut canvas, &mut event_pump)?
+            let roms = rom_browser::scan_rom_dirs(&settings.rom_dirs);
+            if roms.is_empty() {
+                info!("[!] Rom file missing, please drag a rom file into the emulator window...");
+                wait_for_rom(&mut canvas, &mut event_pump)?
+            } else {
+                rom_browser::browse_roms(
+                    &mut canvas,
+                    &mut event_pump,
+                    &roms,
+                    settings.last_rom.as_deref(),
+                )?
+            }
         }
     };
+    settings.last_rom = Some(PathBuf::from(&rom_path));
+    settings.save();
 
-    let video = Rc::new(RefCell::new(create_video_interface(canvas)));
+    let filter = match matches.value_of("video_filter") {
+        Some(name) => name.parse()?,
+        None => config.video.filter,
+    };
+    let video = Rc::new(RefCell::new(create_video_interface(
+        canvas, filter, screens,
+    )?));
     let audio: Rc<RefCell<dyn AudioInterface>> = if silent {
         Rc::new(RefCell::new(create_dummy_player()))
     } else {
-        Rc::new(RefCell::new(create_audio_player(&sdl_context)))
+        Rc::new(RefCell::new(create_audio_player(
+            &sdl_context,
+            config.audio.latency,
+        )))
     };
-    let input = Rc::new(RefCell::new(create_input()));
+    let input = create_input(&config).map_err(|e| format!("invalid input config: {}", e))?;
+    let input = Rc::new(RefCell::new(input));
+
+    let state_dir = config.state_dir.as_deref();
 
-    let mut savestate_path = get_savestate_path(&Path::new(&rom_path));
+    let mut rom_name = get_rom_name(&rom_path);
 
-    let mut rom_name = Path::new(&rom_path).file_name().unwrap().to_str().unwrap();
+    let save_dir = matches
+        .value_of("save_dir")
+        .map(PathBuf::from)
+        .or_else(|| config.save_dir.clone());
 
+    let game = game_config(&config, Path::new(&rom_path));
+    let save_type = arg_or_config(&matches, "save_type", game.save_type.as_deref()).unwrap();
+    let flash_vendor =
+        arg_or_config(&matches, "flash_vendor", game.flash_vendor.as_deref()).unwrap();
     let mut builder = GamepakBuilder::new()
-        .save_type(BackupType::try_from(
-            matches.value_of("save_type").unwrap(),
-        )?)
+        .save_type(BackupType::try_from(save_type)?)
+        .flash_vendor(FlashVendor::try_from(flash_vendor)?)
         .file(Path::new(&rom_path));
 
-    if matches.occurrences_of("rtc") != 0 {
+    if let Some(save_dir) = &save_dir {
+        builder = builder.save_dir(save_dir);
+    }
+
+    if matches.occurrences_of("rtc") != 0 || game.rtc {
         builder = builder.with_rtc();
     }
 
     let gamepak = builder.build()?;
 
-    let mut gba = GameBoyAdvance::new(
-        bios_bin.clone(),
-        gamepak,
-        video.clone(),
-        audio.clone(),
-        input.clone(),
-    );
+    let serial: Option<Rc<RefCell<dyn SerialInterface>>> =
+        if let Some(addr) = matches.value_of("serial_tcp") {
+            Some(Rc::new(RefCell::new(SerialBridge::connect_tcp(addr)?)))
+        } else if let Some(path) = matches.value_of("serial_device") {
+            Some(Rc::new(RefCell::new(SerialBridge::open_device(path)?)))
+        } else {
+            None
+        };
 
-    if skip_bios {
-        gba.skip_bios();
+    // the other end of the cable with --link-local
+    let mut player2_link = None;
+    let link: Option<Rc<RefCell<dyn LinkTransport>>> =
+        if let Some(addr) = matches.value_of("link_host") {
+            Some(Rc::new(RefCell::new(TcpLink::host(addr)?)))
+        } else if let Some(addr) = matches.value_of("link_join") {
+            Some(Rc::new(RefCell::new(TcpLink::join(addr)?)))
+        } else if link_local {
+            let mut ends = LocalLink::group(2);
+            player2_link = ends.pop();
+            ends.pop()
+                .map(|end| Rc::new(RefCell::new(end)) as Rc<RefCell<dyn LinkTransport>>)
+        } else {
+            None
+        };
+
+    let rewind_capacity = match matches.value_of("rewind") {
+        Some(seconds) => seconds.parse::<usize>()? * 60 / REWIND_INTERVAL,
+        None => 0,
+    };
+
+    let session = SessionOptions {
+        bios: bios_bin,
+        video: video.clone(),
+        audio: audio.clone(),
+        input: input.clone(),
+        mem_init: matches.value_of("mem_init").unwrap_or("zero").parse()?,
+        obj_cycle_limit: matches.occurrences_of("no_sprite_limit") == 0,
+        threaded_render: matches.occurrences_of("threaded_render") != 0,
+        rewind_capacity,
+        watchdog: matches
+            .value_of("watchdog")
+            .map(|flushes| flushes.parse())
+            .transpose()?,
+        resampler: matches.value_of("resampler").unwrap().parse()?,
+        audio_filter: parse_audio_filter(&matches, &config)?,
+        serial,
+        link,
+    };
+
+    let frameskip: FrameSkip = arg_or_config(&matches, "frameskip", game.frameskip.as_deref())
+        .unwrap_or("off")
+        .parse()?;
+    let mut gba = create_gba(&session, gamepak, frameskip, skip_bios, &matches)?;
+
+    let mut player2 = match player2_link {
+        Some(link) => {
+            let player2_input = create_player2_input(&config, &input.borrow())
+                .map_err(|e| format!("invalid input config: {}", e))?;
+            let player2_input = Rc::new(RefCell::new(player2_input));
+            let player2_rom = matches.value_of("player2_rom");
+            let player2_rom_path = Path::new(player2_rom.unwrap_or(&rom_path[..]));
+            let game = game_config(&config, player2_rom_path);
+            let mut builder = gamepak_builder(player2_rom_path, &game, save_dir.as_deref())?;
+            if player2_rom.is_none() {
+                // both players run the same game, each one with its own save
+                builder =
+                    builder.save_path(&player2_save_path(player2_rom_path, save_dir.as_deref()));
+            }
+            let options = SessionOptions {
+                video: Rc::new(RefCell::new(Sdl2Screen::new(video.clone(), 1))),
+                audio: Rc::new(RefCell::new(create_dummy_player())),
+                input: player2_input.clone(),
+                serial: None,
+                link: Some(Rc::new(RefCell::new(link))),
+                ..session.clone()
+            };
+            let frameskip: FrameSkip =
+                arg_or_config(&matches, "frameskip", game.frameskip.as_deref())
+                    .unwrap_or("off")
+                    .parse()?;
+            let player2_gba =
+                create_gba(&options, builder.build()?, frameskip, skip_bios, &matches)?;
+            let controller = match available_controllers.get(1) {
+                Some(&id) => {
+                    let controller = controller_subsystem.open(id)?;
+                    info!("Player 2 game controller: {}", controller.name());
+                    Some(controller)
+                }
+                None => None,
+            };
+            info!(
+                "Player 2 runs {}",
+                get_rom_name(&player2_rom_path.to_string_lossy())
+            );
+            Some(Player2 {
+                gba: player2_gba,
+                input: player2_input,
+                controller,
+            })
+        }
+        None => None,
+    };
+
+    let record_ffmpeg = matches.occurrences_of("record_ffmpeg") != 0;
+    let mut rewinding = false;
+
+    for (i, cheat) in matches.values_of("cheat").into_iter().flatten().enumerate() {
+        let mut parts = cheat.splitn(2, ':');
+        let format: CheatFormat = parts.next().unwrap().parse()?;
+        let code = parts.next().ok_or("expected --cheat FORMAT:CODE")?;
+        gba.cheats_mut()
+            .add(&format!("cheat {}", i), format, code)?;
+    }
+
+    if matches.occurrences_of("analyze_idle_loop") != 0 {
+        gba.set_idle_loop_analysis(true);
+    }
+
+    gba.set_volume(settings.volume);
+
+    if let Some(frames) = matches.value_of("profile") {
+        gba.start_profiling(frames.parse()?);
+    }
+
+    if let Some(path) = matches.value_of("trace") {
+        #[cfg(feature = "debugger")]
+        {
+            let filter: TraceFilter = matches.value_of("trace_filter").unwrap_or("").parse()?;
+            gba.cpu
+                .set_trace(Some(InstructionTrace::create(Path::new(path), filter)?));
+            info!("tracing instructions to {}", path);
+        }
+        #[cfg(not(feature = "debugger"))]
+        {
+            warn!("--trace {} requires the 'debugger' feature", path);
+        }
     }
 
     if debug {
@@ -225,6 +860,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             gba.cpu.set_verbose(true);
             let mut debugger = Debugger::new();
             info!("starting debugger...");
+            #[cfg(feature = "debugger_tui")]
+            {
+                debugger.log_capture = log_capture;
+                if tui {
+                    debugger.tui(&mut gba).unwrap();
+                }
+            }
             debugger
                 .repl(&mut gba, matches.value_of("script_file"))
                 .unwrap();
@@ -237,23 +879,124 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    let mut state_slots = get_state_slots(&gba, &rom_path, state_dir);
+    // the slot used by the quick-save and quick-load keys
+    let mut state_slot = 0;
+    if let Some(path) = matches.value_of("play_movie") {
+        let movie = Movie::load(Path::new(path))?;
+        info!("playing {} ({} frames)", path, movie.len());
+        gba.play_movie(movie)?;
+    } else {
+        offer_autosave(&mut gba, &state_slots, &rom_name);
+    }
+
     if with_gdbserver {
         spawn_and_run_gdb_server(&mut gba, DEFAULT_GDB_SERVER_ADDR)?;
     }
 
+    // the script hooks stay with the emulator of the rom it was loaded for
+    #[cfg(feature = "scripting")]
+    {
+        if let Some(path) = matches.value_of("hook_script") {
+            Script::install(Path::new(path), &mut gba)?;
+        }
+    }
+    #[cfg(not(feature = "scripting"))]
+    {
+        if matches.value_of("hook_script").is_some() {
+            warn!("--hook-script requires the 'scripting' feature");
+        }
+    }
+
+    // Roms that were loaded in this session but are not currently running, along with their emulator state
+    let mut parked_roms: Vec<(String, GameBoyAdvance)> = Vec::new();
+
     let mut fps_counter = FpsCounter::default();
-    let frame_time = time::Duration::new(0, 1_000_000_000u32 / 60);
+    let mut fps = 0;
+    // message shown in the window title, along with the time it was posted
+    let mut osd: Option<(String, time::Instant)> = None;
+    let mut show_perf = false;
+    let mut pacer = FramePacer::default();
+    // the speed selected with Tab, holding Space runs as fast as possible regardless
+    let mut fast_forward = FastForward::Off;
     'running: loop {
-        let start_time = time::Instant::now();
-
         for event in event_pump.poll_iter() {
             match event {
                 Event::KeyDown {
                     scancode: Some(scancode),
+                    repeat,
                     ..
                 } => match scancode {
-                    Scancode::Space => frame_limiter = false,
-                    k => input.borrow_mut().on_keyboard_key_down(k),
+                    Scancode::Space => {
+                        if pacer.fast_forward() != FastForward::Unlimited {
+                            gba.fade_out_audio();
+                        }
+                        pacer.set_fast_forward(FastForward::Unlimited);
+                    }
+                    Scancode::Tab | Scancode::P => {
+                        gba.fade_out_audio();
+                        let message = if scancode == Scancode::P {
+                            pacer.set_paused(!pacer.is_paused());
+                            if pacer.is_paused() {
+                                "Paused".to_string()
+                            } else {
+                                "Resumed".to_string()
+                            }
+                        } else {
+                            fast_forward = fast_forward.next();
+                            pacer.set_fast_forward(fast_forward);
+                            format!("Fast-forward: {:?}", fast_forward)
+                        };
+                        info!("{}", message);
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(&message),
+                        ));
+                        osd = Some((message, time::Instant::now()));
+                    }
+                    Scancode::F if !repeat => {
+                        let mut video = video.borrow_mut();
+                        let filter = video.filter().next();
+                        video.set_filter(filter)?;
+                        let message = format!("Video filter: {:?}", filter);
+                        info!("{}", message);
+                        video.set_window_title(&window_title(&rom_name, fps, Some(&message)));
+                        osd = Some((message, time::Instant::now()));
+                    }
+                    Scancode::R => rewinding = true,
+                    Scancode::Minus | Scancode::Equals | Scancode::M => {
+                        let mut volume = settings.volume;
+                        match scancode {
+                            Scancode::M => volume.set_muted(!volume.is_muted()),
+                            Scancode::Minus => {
+                                volume.set_percent(volume.percent().saturating_sub(VOLUME_STEP));
+                                volume.set_muted(false);
+                            }
+                            _ => {
+                                volume.set_percent(volume.percent() + VOLUME_STEP);
+                                volume.set_muted(false);
+                            }
+                        }
+                        gba.set_volume(volume);
+                        settings.volume = volume;
+                        settings.save();
+                        let message = volume_message(volume);
+                        info!("{}", message);
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(&message),
+                        ));
+                        osd = Some((message, time::Instant::now()));
+                    }
+                    k if !repeat => {
+                        input.borrow_mut().on_keyboard_key_down(k);
+                        if let Some(player2) = &player2 {
+                            player2.input.borrow_mut().on_keyboard_key_down(k);
+                        }
+                    }
+                    _ => {}
                 },
                 Event::KeyUp {
                     scancode: Some(scancode),
@@ -261,6 +1004,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 } => match scancode {
                     #[cfg(feature = "debugger")]
                     Scancode::F1 => {
+                        gba.fade_out_audio();
                         let mut debugger = Debugger::new();
                         info!("starting debugger...");
                         debugger
@@ -270,40 +1014,198 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                     #[cfg(feature = "gdb")]
                     Scancode::F2 => spawn_and_run_gdb_server(&mut gba, DEFAULT_GDB_SERVER_ADDR)?,
-                    Scancode::F5 => {
-                        info!("Saving state ...");
-                        let save = gba.save_state()?;
-                        write_bin_file(&savestate_path, &save)?;
-                        info!(
-                            "Saved to {:?} ({})",
-                            savestate_path,
-                            bytesize::ByteSize::b(save.len() as u64)
-                        );
+                    Scancode::F3 => {
+                        // switch to the next rom loaded in this session
+                        if !parked_roms.is_empty() {
+                            gba.fade_out_audio();
+                            gba.flush_save();
+                            stop_recording(&mut gba, record_ffmpeg)?;
+                            let (next_path, next_gba) = parked_roms.remove(0);
+                            let previous = std::mem::replace(&mut gba, next_gba);
+                            gba.set_volume(settings.volume);
+                            gba.set_perf_counters(show_perf);
+                            parked_roms
+                                .push((std::mem::replace(&mut rom_path, next_path), previous));
+                            state_slots = get_state_slots(&gba, &rom_path, state_dir);
+                            rom_name = get_rom_name(&rom_path);
+                            settings.last_rom = Some(PathBuf::from(&rom_path));
+                            settings.save();
+                            info!("Switched to {}", rom_name);
+                        }
+                    }
+                    Scancode::F4 => {
+                        show_perf = !show_perf;
+                        gba.set_perf_counters(show_perf);
+                    }
+                    Scancode::F5 | Scancode::F9 => {
+                        let path = state_slots.slot_path(state_slot);
+                        let message = if scancode == Scancode::F5 {
+                            match save_state_file(&gba, &path) {
+                                Ok(()) => format!("Saved slot {}", state_slot),
+                                Err(e) => {
+                                    error!("failed to save {:?}: {}", path, e);
+                                    format!("Failed to save slot {}", state_slot)
+                                }
+                            }
+                        } else if !path.is_file() {
+                            format!("Slot {} is empty, save it with F5", state_slot)
+                        } else {
+                            match load_state_file(&mut gba, &path) {
+                                Ok(()) => format!("Loaded slot {}", state_slot),
+                                Err(e) => {
+                                    error!("failed to load {:?}: {}", path, e);
+                                    format!("Failed to load slot {}", state_slot)
+                                }
+                            }
+                        };
+                        info!("{}", message);
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(&message),
+                        ));
+                        osd = Some((message, time::Instant::now()));
+                    }
+                    Scancode::Num0
+                    | Scancode::Num1
+                    | Scancode::Num2
+                    | Scancode::Num3
+                    | Scancode::Num4
+                    | Scancode::Num5
+                    | Scancode::Num6
+                    | Scancode::Num7
+                    | Scancode::Num8
+                    | Scancode::Num9 => {
+                        // the scancodes of the number row go from 1 to 9, then 0
+                        state_slot = (scancode as usize + 1 - Scancode::Num1 as usize) % 10;
+                        let message = slot_message(&state_slots, state_slot);
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(&message),
+                        ));
+                        osd = Some((message, time::Instant::now()));
+                    }
+                    Scancode::F12 => {
+                        let path = get_capture_path(Path::new(&rom_path), "png");
+                        gba.screenshot().save_png(&path, scale as usize)?;
+                        info!("Screenshot saved to {:?}", path);
+                    }
+                    Scancode::F10 => {
+                        let message = if gba.is_recording() {
+                            stop_recording(&mut gba, record_ffmpeg)?;
+                            "Recording stopped"
+                        } else {
+                            gba.start_recording(&get_capture_path(Path::new(&rom_path), "dump"))?;
+                            "Recording"
+                        };
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(message),
+                        ));
+                        osd = Some((message.to_string(), time::Instant::now()));
+                    }
+                    Scancode::F6 => {
+                        let message = if gba.is_recording_movie() {
+                            let path = get_capture_path(Path::new(&rom_path), "rbm");
+                            let movie = gba.stop_movie().unwrap();
+                            movie.save(&path)?;
+                            info!("Movie saved to {:?} ({} frames)", path, movie.len());
+                            "Movie recording stopped"
+                        } else {
+                            gba.start_movie_recording()?;
+                            "Recording movie"
+                        };
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(message),
+                        ));
+                        osd = Some((message.to_string(), time::Instant::now()));
+                    }
+                    Scancode::F8 => {
+                        let mut input = input.borrow_mut();
+                        let next = TURBO_RATES
+                            .iter()
+                            .position(|&rate| rate == input.turbo_rate())
+                            .map_or(0, |i| (i + 1) % TURBO_RATES.len());
+                        input.set_turbo_rate(TURBO_RATES[next]);
+                        let message = turbo_message(input.turbo_rate());
+                        info!("{}", message);
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(&message),
+                        ));
+                        osd = Some((message, time::Instant::now()));
                     }
-                    Scancode::F9 => {
-                        if savestate_path.is_file() {
-                            let save = read_bin_file(&savestate_path)?;
-                            info!("Restoring state from {:?}...", savestate_path);
-                            gba.restore_state(&save)?;
-                            info!("Restored!");
+                    Scancode::F11 => video.borrow_mut().toggle_fullscreen()?,
+                    Scancode::F7 => {
+                        let inserted = !gba.is_cartridge_inserted();
+                        gba.set_cartridge_inserted(inserted);
+                        let message = if inserted {
+                            "Cartridge inserted"
                         } else {
-                            info!("Savestate not created, please create one by pressing F5");
+                            "Cartridge removed"
+                        };
+                        info!("{}", message);
+                        video.borrow_mut().set_window_title(&window_title(
+                            &rom_name,
+                            fps,
+                            Some(message),
+                        ));
+                        osd = Some((message.to_string(), time::Instant::now()));
+                    }
+                    Scancode::Space => {
+                        gba.fade_out_audio();
+                        pacer.set_fast_forward(fast_forward);
+                    }
+                    Scancode::R => rewinding = false,
+                    k => {
+                        input.borrow_mut().on_keyboard_key_up(k);
+                        if let Some(player2) = &player2 {
+                            player2.input.borrow_mut().on_keyboard_key_up(k);
                         }
                     }
-                    Scancode::Space => frame_limiter = true,
-                    k => input.borrow_mut().on_keyboard_key_up(k),
                 },
-                Event::ControllerButtonDown { button, .. } => match button {
-                    Button::RightStick => frame_limiter = !frame_limiter,
-                    b => input.borrow_mut().on_controller_button_down(b),
+                Event::ControllerButtonDown { which, button, .. } => match &player2 {
+                    Some(player2) if player2.owns_controller(which as i32) => {
+                        player2.input.borrow_mut().on_controller_button_down(button);
+                    }
+                    _ => match button {
+                        Button::RightStick => {
+                            gba.fade_out_audio();
+                            fast_forward = match fast_forward {
+                                FastForward::Off => FastForward::Unlimited,
+                                _ => FastForward::Off,
+                            };
+                            pacer.set_fast_forward(fast_forward);
+                        }
+                        b => input.borrow_mut().on_controller_button_down(b),
+                    },
+                },
+                Event::ControllerButtonUp { which, button, .. } => match &player2 {
+                    Some(player2) if player2.owns_controller(which as i32) => {
+                        player2.input.borrow_mut().on_controller_button_up(button);
+                    }
+                    _ => input.borrow_mut().on_controller_button_up(button),
+                },
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => match &player2 {
+                    Some(player2) if player2.owns_controller(which as i32) => {
+                        player2.input.borrow_mut().on_axis_motion(axis, value);
+                    }
+                    _ => input.borrow_mut().on_axis_motion(axis, value),
                 },
-                Event::ControllerButtonUp { button, .. } => {
-                    input.borrow_mut().on_controller_button_up(button);
-                }
-                Event::ControllerAxisMotion { axis, value, .. } => {
-                    input.borrow_mut().on_axis_motion(axis, value);
-                }
                 Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(player2) = &mut player2 {
+                        if player2.owns_controller(which as i32) {
+                            info!("Removing the game controller of player 2");
+                            player2.controller = None;
+                        }
+                    }
                     let removed = if let Some(active_controller) = &active_controller {
                         active_controller.instance_id() == (which as i32)
                     } else {
@@ -322,48 +1224,156 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         let controller = controller_subsystem.open(which)?;
                         info!("Adding game controller: {}", controller.name());
                         active_controller = Some(controller);
+                    } else if let Some(player2) = &mut player2 {
+                        if player2.controller.is_none() {
+                            let controller = controller_subsystem.open(which)?;
+                            info!("Adding game controller for player 2: {}", controller.name());
+                            player2.controller = Some(controller);
+                        }
                     }
                 }
                 Event::Quit { .. } => break 'running,
+                Event::DropFile { filename, .. } if filename == rom_path => {
+                    info!("{} is already running", rom_name);
+                }
                 Event::DropFile { filename, .. } => {
-                    // load the new rom
+                    gba.fade_out_audio();
+                    gba.flush_save();
+                    stop_recording(&mut gba, record_ffmpeg)?;
+                    // keep the running rom in the session, so we can switch back to it later
+                    let (parked, newly_loaded) = if let Some(index) =
+                        parked_roms.iter().position(|(path, _)| *path == filename)
+                    {
+                        // this rom is already loaded, just switch to it
+                        let (_, parked_gba) = parked_roms.remove(index);
+                        (std::mem::replace(&mut gba, parked_gba), false)
+                    } else {
+                        // load the new rom
+                        // the save type options of the command line are for the first rom
+                        let game = game_config(&config, Path::new(&filename));
+                        let gamepak =
+                            gamepak_builder(Path::new(&filename), &game, save_dir.as_deref())?
+                                .build()?;
+                        let frameskip: FrameSkip =
+                            arg_or_config(&matches, "frameskip", game.frameskip.as_deref())
+                                .unwrap_or("off")
+                                .parse()?;
+                        let new_gba = create_gba(&session, gamepak, frameskip, true, &matches)?;
+                        (std::mem::replace(&mut gba, new_gba), true)
+                    };
+                    gba.set_volume(settings.volume);
+                    gba.set_perf_counters(show_perf);
+                    parked_roms.push((rom_path, parked));
                     rom_path = filename;
-                    savestate_path = get_savestate_path(&Path::new(&rom_path));
-                    rom_name = Path::new(&rom_path).file_name().unwrap().to_str().unwrap();
-                    let gamepak = GamepakBuilder::new().file(Path::new(&rom_path)).build()?;
-                    let bios_bin = read_bin_file(bios_path).unwrap();
-
-                    // create a new emulator - TODO, export to a function
-                    gba = GameBoyAdvance::new(
-                        bios_bin.into_boxed_slice(),
-                        gamepak,
-                        video.clone(),
-                        audio.clone(),
-                        input.clone(),
+                    state_slots = get_state_slots(&gba, &rom_path, state_dir);
+                    rom_name = get_rom_name(&rom_path);
+                    if newly_loaded {
+                        offer_autosave(&mut gba, &state_slots, &rom_name);
+                    }
+                    settings.last_rom = Some(PathBuf::from(&rom_path));
+                    settings.save();
+                    info!(
+                        "Switched to {} ({} roms loaded)",
+                        rom_name,
+                        parked_roms.len() + 1
                     );
-                    gba.skip_bios();
                 }
                 _ => {}
             }
         }
 
+        if pacer.is_paused() {
+            pacer.wait();
+            continue;
+        }
+        if rewinding {
+            gba.rewind();
+        }
         gba.frame();
-
-        if let Some(fps) = fps_counter.tick() {
-            let title = format!("{} ({} fps)", rom_name, fps);
-            video.borrow_mut().set_window_title(&title);
+        if let Some(err) = gba.cpu.take_error() {
+            error!("{}", err);
+            #[cfg(feature = "debugger")]
+            {
+                if let Some(history) = &gba.cpu.dbg.error_history {
+                    history.dump();
+                }
+                if debug_on_error {
+                    gba.fade_out_audio();
+                    let mut debugger = Debugger::new();
+                    info!("starting debugger...");
+                    debugger.repl(&mut gba, None).unwrap();
+                    info!("ending debugger...")
+                }
+            }
         }
 
-        if frame_limiter {
-            let time_passed = start_time.elapsed();
-            let delay = frame_time.checked_sub(time_passed);
-            match delay {
-                None => {}
-                Some(delay) => {
-                    spin_sleep::sleep(delay);
+        if let Some(player2) = &mut player2 {
+            player2.gba.frame();
+            if let Some(err) = player2.gba.cpu.take_error() {
+                error!("player 2: {}", err);
+                #[cfg(feature = "debugger")]
+                {
+                    if let Some(history) = &player2.gba.cpu.dbg.error_history {
+                        history.dump();
+                    }
                 }
-            };
+            }
         }
+
+        if let Some(profile) = gba.take_profile() {
+            write_profile(&profile, matches.value_of("profile_output"))?;
+            gba.set_perf_counters(show_perf);
+        }
+
+        let mut update_title = false;
+        if let Some(frames) = fps_counter.tick() {
+            fps = frames;
+            update_title = true;
+        }
+        if let Some((_, posted)) = &osd {
+            if posted.elapsed() >= OSD_DURATION {
+                osd = None;
+                update_title = true;
+            }
+        }
+        if update_title {
+            // a --profile run keeps the counters on without them being shown
+            let perf = gba
+                .perf_report()
+                .filter(|_| show_perf)
+                .map(|report| perf_message(&report));
+            let message = osd
+                .as_ref()
+                .map(|(message, _)| message.as_str())
+                .or_else(|| perf.as_deref());
+            video
+                .borrow_mut()
+                .set_window_title(&window_title(&rom_name, fps, message));
+        }
+
+        pacer.wait();
+    }
+
+    gba.flush_save();
+    if let Some(player2) = &mut player2 {
+        player2.gba.flush_save();
+    }
+    stop_recording(&mut gba, record_ffmpeg)?;
+    if gba.is_recording_movie() {
+        let path = get_capture_path(Path::new(&rom_path), "rbm");
+        gba.stop_movie().unwrap().save(&path)?;
+        info!("Movie saved to {:?}", path);
+    }
+    // every game of the session can be continued on its next launch
+    for (path, parked) in parked_roms.iter_mut() {
+        parked.flush_save();
+        let slots = get_state_slots(parked, path.as_str(), state_dir);
+        if let Err(e) = save_state_file(parked, &slots.autosave_path()) {
+            error!("failed to autosave {}: {}", path, e);
+        }
+    }
+    if let Err(e) = save_state_file(&gba, &state_slots.autosave_path()) {
+        error!("failed to autosave {}: {}", rom_path, e);
     }
 
     Ok(())
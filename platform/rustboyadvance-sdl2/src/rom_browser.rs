@@ -0,0 +1,287 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use sdl2::controller::Button;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::WindowCanvas;
+use sdl2::EventPump;
+
+use rustboyadvance_core::cartridge::header;
+
+use super::{CANVAS_HEIGHT, CANVAS_WIDTH};
+
+const ROM_EXTENSIONS: [&str; 3] = ["gba", "agb", "mb"];
+
+const GLYPH_WIDTH: i32 = 5;
+const GLYPH_HEIGHT: i32 = 7;
+const CHAR_WIDTH: i32 = GLYPH_WIDTH + 1;
+const ROW_HEIGHT: i32 = GLYPH_HEIGHT + 2;
+const MARGIN: i32 = 4;
+const LIST_TOP: i32 = 14;
+const VISIBLE_ROWS: usize = ((CANVAS_HEIGHT as i32 - LIST_TOP) / ROW_HEIGHT) as usize;
+
+/// Column offsets (in characters) of the game title, game code and file name
+const TITLE_COLUMN: i32 = 0;
+const CODE_COLUMN: i32 = 13;
+const FILE_COLUMN: i32 = 18;
+
+/// A rom found in one of the rom directories
+#[derive(Debug)]
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub game_title: String,
+    pub game_code: String,
+}
+
+impl RomEntry {
+    fn new(path: PathBuf) -> RomEntry {
+        let (game_title, game_code) = match read_header(&path) {
+            Some(header) => (header.game_title, header.game_code),
+            None => (String::new(), String::new()),
+        };
+        RomEntry {
+            path,
+            game_title,
+            game_code,
+        }
+    }
+
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+fn read_header(path: &Path) -> Option<header::CartridgeHeader> {
+    let mut bytes = [0u8; 0xc0];
+    fs::File::open(path).ok()?.read_exact(&mut bytes).ok()?;
+    header::parse(&bytes).ok()
+}
+
+/// Lists the roms in `dirs` (not recursively), sorted by file name
+pub fn scan_rom_dirs(dirs: &[PathBuf]) -> Vec<RomEntry> {
+    let mut paths = Vec::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("failed to read rom directory {:?}: {}", dir, e);
+                continue;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let is_rom = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ROM_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                .unwrap_or(false);
+            if is_rom && path.is_file() {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    paths.sort_by_key(|path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+    });
+    paths.into_iter().map(RomEntry::new).collect()
+}
+
+/// Shows the list of `roms` and lets the user pick one with the keyboard or a game controller.
+/// Dropping a rom file into the window also selects it. `roms` must not be empty.
+pub fn browse_roms(
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+    roms: &[RomEntry],
+    last_played: Option<&Path>,
+) -> Result<String, String> {
+    let mut selected = last_played
+        .and_then(|last| roms.iter().position(|rom| rom.path == last))
+        .unwrap_or(0);
+    let mut scroll = 0;
+
+    loop {
+        if selected < scroll {
+            scroll = selected;
+        } else if selected >= scroll + VISIBLE_ROWS {
+            scroll = selected + 1 - VISIBLE_ROWS;
+        }
+        draw_rom_list(canvas, roms, selected, scroll)?;
+
+        let event = event_pump.wait_event();
+        let action = match event {
+            Event::KeyDown {
+                scancode: Some(scancode),
+                ..
+            } => match scancode {
+                Scancode::Up => Some(Action::Up),
+                Scancode::Down => Some(Action::Down),
+                Scancode::PageUp | Scancode::Left => Some(Action::PageUp),
+                Scancode::PageDown | Scancode::Right => Some(Action::PageDown),
+                Scancode::Return | Scancode::X => Some(Action::Select),
+                _ => None,
+            },
+            Event::ControllerButtonDown { button, .. } => match button {
+                Button::DPadUp => Some(Action::Up),
+                Button::DPadDown => Some(Action::Down),
+                Button::DPadLeft | Button::LeftShoulder => Some(Action::PageUp),
+                Button::DPadRight | Button::RightShoulder => Some(Action::PageDown),
+                Button::A | Button::Start => Some(Action::Select),
+                _ => None,
+            },
+            Event::DropFile { filename, .. } => return Ok(filename),
+            Event::Quit { .. } => process::exit(0),
+            Event::Window {
+                win_event: WindowEvent::SizeChanged(..),
+                ..
+            }
+            | Event::Window {
+                win_event: WindowEvent::Restored,
+                ..
+            } => None,
+            _ => continue,
+        };
+        match action {
+            Some(Action::Up) => selected = selected.saturating_sub(1),
+            Some(Action::Down) => selected = (selected + 1).min(roms.len() - 1),
+            Some(Action::PageUp) => selected = selected.saturating_sub(VISIBLE_ROWS),
+            Some(Action::PageDown) => selected = (selected + VISIBLE_ROWS).min(roms.len() - 1),
+            Some(Action::Select) => return Ok(roms[selected].path.to_string_lossy().into_owned()),
+            None => {}
+        }
+    }
+}
+
+enum Action {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Select,
+}
+
+fn draw_rom_list(
+    canvas: &mut WindowCanvas,
+    roms: &[RomEntry],
+    selected: usize,
+    scroll: usize,
+) -> Result<(), String> {
+    canvas.set_draw_color(Color::RGB(0xDD, 0xDD, 0xDD));
+    canvas.clear();
+
+    canvas.set_draw_color(Color::RGB(0x30, 0x30, 0x60));
+    draw_text(
+        canvas,
+        MARGIN,
+        3,
+        &format!("SELECT A ROM ({}/{})", selected + 1, roms.len()),
+    )?;
+
+    for (row, rom) in roms.iter().enumerate().skip(scroll).take(VISIBLE_ROWS) {
+        let y = LIST_TOP + (row - scroll) as i32 * ROW_HEIGHT;
+        if row == selected {
+            canvas.set_draw_color(Color::RGB(0x30, 0x30, 0x60));
+            canvas.fill_rect(Rect::new(0, y - 1, CANVAS_WIDTH, ROW_HEIGHT as u32))?;
+            canvas.set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
+        } else {
+            canvas.set_draw_color(Color::RGB(0x20, 0x20, 0x20));
+        }
+        draw_text(canvas, column_x(TITLE_COLUMN), y, &rom.game_title)?;
+        draw_text(canvas, column_x(CODE_COLUMN), y, &rom.game_code)?;
+        let max_chars = ((CANVAS_WIDTH as i32 - column_x(FILE_COLUMN)) / CHAR_WIDTH) as usize;
+        let file_name: String = rom.file_name().chars().take(max_chars).collect();
+        draw_text(canvas, column_x(FILE_COLUMN), y, &file_name)?;
+    }
+
+    canvas.present();
+    Ok(())
+}
+
+fn column_x(column: i32) -> i32 {
+    MARGIN + column * CHAR_WIDTH
+}
+
+/// Draws `text` with the current draw color, lower case letters are drawn as upper case
+fn draw_text(canvas: &mut WindowCanvas, x: i32, y: i32, text: &str) -> Result<(), String> {
+    let mut points = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        let glyph = glyph(c.to_ascii_uppercase());
+        let glyph_x = x + i as i32 * CHAR_WIDTH;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                    points.push(Point::new(glyph_x + col, y + row as i32));
+                }
+            }
+        }
+    }
+    canvas.draw_points(points.as_slice())
+}
+
+/// 5x7 bitmap font, each row is stored in the low 5 bits (msb is the leftmost pixel)
+#[rustfmt::skip]
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '\'' => [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' | '[' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' | ']' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '&' => [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        // unsupported characters
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
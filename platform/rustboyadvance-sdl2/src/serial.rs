@@ -0,0 +1,63 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use rustboyadvance_core::SerialInterface;
+
+/// Bridges the GBA serial port (UART mode) to a host stream, e.g a TCP socket or a pseudo-terminal.
+/// Incoming bytes are read on a separate thread so the emulation never blocks on the host.
+pub struct SerialBridge {
+    writer: Box<dyn Write>,
+    received: Receiver<u8>,
+}
+
+impl SerialBridge {
+    fn new<R: Read + Send + 'static>(reader: R, writer: Box<dyn Write>) -> SerialBridge {
+        let (sender, received) = channel();
+        thread::spawn(move || {
+            for byte in reader.bytes() {
+                match byte {
+                    Ok(byte) => {
+                        if sender.send(byte).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("serial bridge: read failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        SerialBridge { writer, received }
+    }
+
+    /// Connects to a TCP server, e.g `nc -l 4000`
+    pub fn connect_tcp(addr: &str) -> io::Result<SerialBridge> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        info!("serial port connected to {}", addr);
+        Ok(SerialBridge::new(stream.try_clone()?, Box::new(stream)))
+    }
+
+    /// Opens a character device, e.g a pseudo-terminal created with `socat -d -d pty,raw,echo=0 -`
+    pub fn open_device(path: &str) -> io::Result<SerialBridge> {
+        let device = OpenOptions::new().read(true).write(true).open(path)?;
+        info!("serial port connected to {}", path);
+        Ok(SerialBridge::new(device.try_clone()?, Box::new(device)))
+    }
+}
+
+impl SerialInterface for SerialBridge {
+    fn send(&mut self, byte: u8) {
+        if let Err(e) = self.writer.write_all(&[byte]) {
+            warn!("serial bridge: write failed: {}", e);
+        }
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.received.try_recv().ok()
+    }
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustboyadvance_core::sound::Volume;
+
+const SETTINGS_FILE: &str = "rustboyadvance-sdl2.cfg";
+
+/// Frontend settings that are kept between sessions, stored as `key=value` lines
+#[derive(Debug, Default)]
+pub struct Settings {
+    pub volume: Volume,
+    /// Directories listed by the rom browser
+    pub rom_dirs: Vec<PathBuf>,
+    pub last_rom: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Loads the settings file, missing or malformed entries keep their default value
+    pub fn load() -> Settings {
+        let mut settings = Settings::default();
+        let path = Path::new(SETTINGS_FILE);
+        if !path.is_file() {
+            return settings;
+        }
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("failed to read {}: {}", SETTINGS_FILE, e);
+                return settings;
+            }
+        };
+        for line in content.lines() {
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => continue,
+            };
+            match key {
+                "volume" => match value.parse() {
+                    Ok(percent) => settings.volume.set_percent(percent),
+                    Err(_) => warn!("invalid volume in {}: {}", SETTINGS_FILE, value),
+                },
+                "muted" => match value.parse() {
+                    Ok(muted) => settings.volume.set_muted(muted),
+                    Err(_) => warn!("invalid mute setting in {}: {}", SETTINGS_FILE, value),
+                },
+                "rom_dirs" => {
+                    settings.rom_dirs = env::split_paths(value)
+                        .filter(|dir| !dir.as_os_str().is_empty())
+                        .collect()
+                }
+                "last_rom" if !value.is_empty() => settings.last_rom = Some(PathBuf::from(value)),
+                "last_rom" => {}
+                _ => warn!("unknown setting in {}: {}", SETTINGS_FILE, key),
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let rom_dirs = env::join_paths(&self.rom_dirs).unwrap_or_else(|e| {
+            warn!("can't store the rom directories: {}", e);
+            Default::default()
+        });
+        let last_rom = self
+            .last_rom
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let content = format!(
+            "volume={}\nmuted={}\nrom_dirs={}\nlast_rom={}\n",
+            self.volume.percent(),
+            self.volume.is_muted(),
+            rom_dirs.to_string_lossy(),
+            last_rom
+        );
+        if let Err(e) = fs::write(SETTINGS_FILE, content) {
+            warn!("failed to write {}: {}", SETTINGS_FILE, e);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+/// A link cable between two emulators over TCP
+///
+/// A round trip per transfer would stall the parent for the whole network latency, several times a
+/// frame. Instead the child pushes its SIOMLT_SEND to the parent whenever the game changes it, and
+/// the parent completes its transfers right away with the last value it got. The results are sent
+/// back to the child, which applies them in order, a little later than the parent did.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use rustboyadvance_core::logging;
+use rustboyadvance_core::LinkTransport;
+
+const MSG_SEND_DATA: u8 = 1;
+const MSG_TRANSFER: u8 = 2;
+
+enum Message {
+    Connected(TcpStream),
+    /// The child's SIOMLT_SEND
+    SendData(u16),
+    /// The result of a transfer the parent made
+    Transfer([u16; 4]),
+    Disconnected,
+}
+
+fn read_messages(mut stream: TcpStream, sender: Sender<Message>) {
+    let mut tag = [0; 1];
+    loop {
+        let message = match stream.read_exact(&mut tag).map(|_| tag[0]) {
+            Ok(MSG_SEND_DATA) => {
+                let mut data = [0; 2];
+                stream
+                    .read_exact(&mut data)
+                    .map(|_| Message::SendData(u16::from_le_bytes(data)))
+            }
+            Ok(MSG_TRANSFER) => {
+                let mut bytes = [0; 8];
+                stream.read_exact(&mut bytes).map(|_| {
+                    let mut data = [0; 4];
+                    for (i, word) in data.iter_mut().enumerate() {
+                        *word = u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+                    }
+                    Message::Transfer(data)
+                })
+            }
+            Ok(tag) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown message {}", tag),
+            )),
+            Err(e) => Err(e),
+        };
+        match message {
+            Ok(message) => {
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                warn!(target: logging::SIO, "link cable: {}", e);
+                let _ = sender.send(Message::Disconnected);
+                return;
+            }
+        }
+    }
+}
+
+fn spawn_reader(stream: &TcpStream, sender: Sender<Message>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let reader = stream.try_clone()?;
+    thread::spawn(move || read_messages(reader, sender));
+    Ok(())
+}
+
+pub struct TcpLink {
+    player_id: usize,
+    stream: Option<TcpStream>,
+    received: Receiver<Message>,
+    send_data: u16,
+    /// The last SIOMLT_SEND the child sent, only used by the parent
+    remote_send_data: u16,
+    completed: VecDeque<[u16; 4]>,
+}
+
+impl TcpLink {
+    fn new(player_id: usize, received: Receiver<Message>) -> TcpLink {
+        TcpLink {
+            player_id,
+            stream: None,
+            received,
+            send_data: 0xffff,
+            remote_send_data: 0xffff,
+            completed: VecDeque::new(),
+        }
+    }
+
+    /// Waits for the child to connect in the background, this end is the parent
+    pub fn host<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(addr)?;
+        info!(
+            target: logging::SIO,
+            "link cable: waiting for player 2 on {}",
+            listener.local_addr()?
+        );
+        let (sender, received) = channel();
+        thread::spawn(move || {
+            let stream = match listener.accept() {
+                Ok((stream, peer)) => {
+                    info!(target: logging::SIO, "link cable: player 2 joined from {}", peer);
+                    stream
+                }
+                Err(e) => {
+                    warn!(target: logging::SIO, "link cable: accept failed: {}", e);
+                    return;
+                }
+            };
+            let connected = spawn_reader(&stream, sender.clone())
+                .and_then(|_| stream.try_clone())
+                .map(Message::Connected);
+            match connected {
+                Ok(message) => {
+                    let _ = sender.send(message);
+                }
+                Err(e) => warn!(target: logging::SIO, "link cable: {}", e),
+            }
+        });
+        Ok(TcpLink::new(0, received))
+    }
+
+    /// Connects to a parent started with `TcpLink::host`, this end is the child
+    pub fn join<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let stream = TcpStream::connect(addr)?;
+        info!(target: logging::SIO, "link cable: joined {}", stream.peer_addr()?);
+        let (sender, received) = channel();
+        spawn_reader(&stream, sender)?;
+        let mut link = TcpLink::new(1, received);
+        link.stream = Some(stream);
+        Ok(link)
+    }
+
+    fn write(&mut self, message: &[u8]) {
+        if let Some(stream) = &mut self.stream {
+            if let Err(e) = stream.write_all(message) {
+                warn!(target: logging::SIO, "link cable: {}", e);
+                self.stream = None;
+            }
+        }
+    }
+
+    fn handle_messages(&mut self) {
+        while let Ok(message) = self.received.try_recv() {
+            match message {
+                Message::Connected(stream) => {
+                    self.stream = Some(stream);
+                }
+                Message::SendData(data) => self.remote_send_data = data,
+                Message::Transfer(data) => self.completed.push_back(data),
+                Message::Disconnected => {
+                    info!(target: logging::SIO, "link cable: the other player left");
+                    self.stream = None;
+                }
+            }
+        }
+    }
+}
+
+impl LinkTransport for TcpLink {
+    fn player_id(&self) -> usize {
+        self.player_id
+    }
+
+    fn is_ready(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn set_send_data(&mut self, data: u16) {
+        self.handle_messages();
+        if self.send_data == data {
+            return;
+        }
+        self.send_data = data;
+        if self.player_id != 0 {
+            let data = data.to_le_bytes();
+            self.write(&[MSG_SEND_DATA, data[0], data[1]]);
+        }
+    }
+
+    fn start_transfer(&mut self) {
+        self.handle_messages();
+        let data = [self.send_data, self.remote_send_data, 0xffff, 0xffff];
+        let mut message = vec![MSG_TRANSFER];
+        for word in data.iter() {
+            message.extend_from_slice(&word.to_le_bytes());
+        }
+        self.write(&message);
+        self.completed.push_back(data);
+    }
+
+    fn poll_transfer(&mut self) -> Option<[u16; 4]> {
+        self.handle_messages();
+        self.completed.pop_front()
+    }
+}
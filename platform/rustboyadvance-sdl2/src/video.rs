@@ -1,59 +1,208 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
 use sdl2::render::{Texture, TextureCreator, WindowCanvas};
-use sdl2::video::WindowContext;
+use sdl2::video::{FullscreenType, WindowContext};
 
 use rustboyadvance_core::gpu::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use rustboyadvance_core::VideoInterface;
 
+use super::config::ScaleFilter;
+
 pub const SCREEN_WIDTH: u32 = DISPLAY_WIDTH as u32;
 pub const SCREEN_HEIGHT: u32 = DISPLAY_HEIGHT as u32;
 
 pub struct Sdl2Video<'a> {
-    _tc: TextureCreator<WindowContext>, // only kept alive because of the texture
-    texture: Texture<'a>,               // TODO - what happens if _tc is destroyed first ?
+    tc: TextureCreator<WindowContext>, // only kept alive because of the textures
+    /// One texture per screen, the screens are side by side from left to right
+    textures: Vec<Texture<'a>>, // TODO - what happens if tc is destroyed first ?
     canvas: WindowCanvas,
+    filter: ScaleFilter,
+    /// The frame after the cpu filters
+    scaled: Vec<u32>,
 }
 
 impl<'a> Sdl2Video<'a> {
     pub fn set_window_title(&mut self, title: &str) {
         self.canvas.window_mut().set_title(&title).unwrap();
     }
-}
 
-impl<'a> VideoInterface for Sdl2Video<'a> {
-    fn render(&mut self, buffer: &[u32]) {
-        self.texture
+    /// Switches between a window and fullscreen at the desktop resolution
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+        let next = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(next)
+    }
+
+    pub fn filter(&self) -> ScaleFilter {
+        self.filter
+    }
+
+    /// Takes effect from the next frame
+    pub fn set_filter(&mut self, filter: ScaleFilter) -> Result<(), String> {
+        for texture in self.textures.iter_mut() {
+            *texture = create_texture(&mut self.tc, filter);
+        }
+        self.canvas
+            .set_integer_scale(filter == ScaleFilter::Integer)?;
+        self.filter = filter;
+        Ok(())
+    }
+
+    /// Updates the screen at `index`, the window is presented along with the last screen
+    fn render_screen(&mut self, index: usize, buffer: &[u32]) {
+        let (pixels, width) = match self.filter {
+            ScaleFilter::Scale2x => {
+                scale2x(
+                    buffer,
+                    SCREEN_WIDTH as usize,
+                    SCREEN_HEIGHT as usize,
+                    &mut self.scaled,
+                );
+                (&self.scaled[..], SCREEN_WIDTH * 2)
+            }
+            _ => (buffer, SCREEN_WIDTH),
+        };
+        self.textures[index]
             .update(
                 None,
-                unsafe { std::mem::transmute::<&[u32], &[u8]>(buffer) },
-                (SCREEN_WIDTH as usize) * 4,
+                unsafe { std::mem::transmute::<&[u32], &[u8]>(pixels) },
+                (width as usize) * 4,
             )
             .unwrap();
+        if index + 1 < self.textures.len() {
+            return;
+        }
         self.canvas.set_draw_color(Color::RGB(0, 0, 0));
         self.canvas.clear();
-        self.canvas
-            .copy(
-                &self.texture,
-                None,
-                Some(Rect::new(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT)),
-            )
-            .unwrap();
+        // the canvas has the logical size of the GBA screens, the larger textures of the cpu
+        // filters still show at their full resolution
+        for (i, texture) in self.textures.iter().enumerate() {
+            let x = (i as u32 * SCREEN_WIDTH) as i32;
+            self.canvas
+                .copy(
+                    texture,
+                    None,
+                    Some(Rect::new(x, 0, SCREEN_WIDTH, SCREEN_HEIGHT)),
+                )
+                .unwrap();
+        }
         self.canvas.present();
     }
 }
 
-pub fn create_video_interface<'a>(canvas: WindowCanvas) -> Sdl2Video<'a> {
-    let mut tc = canvas.texture_creator();
-    let texture = unsafe {
-        let tc_ptr = &mut tc as *mut TextureCreator<WindowContext>;
+impl<'a> VideoInterface for Sdl2Video<'a> {
+    fn render(&mut self, buffer: &[u32]) {
+        self.render_screen(0, buffer);
+    }
+}
+
+/// Another screen of the window, for the second emulator of `--link-local`
+pub struct Sdl2Screen<'a> {
+    video: Rc<RefCell<Sdl2Video<'a>>>,
+    index: usize,
+}
+
+impl<'a> Sdl2Screen<'a> {
+    pub fn new(video: Rc<RefCell<Sdl2Video<'a>>>, index: usize) -> Sdl2Screen<'a> {
+        assert!(index < video.borrow().textures.len());
+        Sdl2Screen { video, index }
+    }
+}
+
+impl<'a> VideoInterface for Sdl2Screen<'a> {
+    fn render(&mut self, buffer: &[u32]) {
+        self.video.borrow_mut().render_screen(self.index, buffer);
+    }
+}
+
+/// Scale2x (also known as EPX) doubles the resolution of pixel art. Each pixel becomes 2x2, and
+/// the corners that sit on an edge between two matching neighbors take their color, which
+/// smooths the diagonals without blurring.
+fn scale2x(src: &[u32], width: usize, height: usize, dst: &mut Vec<u32>) {
+    dst.resize(width * height * 4, 0);
+    for y in 0..height {
+        for x in 0..width {
+            let p = src[y * width + x];
+            let up = if y > 0 { src[(y - 1) * width + x] } else { p };
+            let down = if y + 1 < height {
+                src[(y + 1) * width + x]
+            } else {
+                p
+            };
+            let left = if x > 0 { src[y * width + x - 1] } else { p };
+            let right = if x + 1 < width {
+                src[y * width + x + 1]
+            } else {
+                p
+            };
+            let mut out = [p; 4];
+            if up != down && left != right {
+                if left == up {
+                    out[0] = up;
+                }
+                if up == right {
+                    out[1] = right;
+                }
+                if left == down {
+                    out[2] = left;
+                }
+                if down == right {
+                    out[3] = down;
+                }
+            }
+            let i = 2 * y * 2 * width + 2 * x;
+            dst[i] = out[0];
+            dst[i + 1] = out[1];
+            dst[i + 2 * width] = out[2];
+            dst[i + 2 * width + 1] = out[3];
+        }
+    }
+}
+
+fn create_texture<'a>(tc: &mut TextureCreator<WindowContext>, filter: ScaleFilter) -> Texture<'a> {
+    // the texture is created with the scale quality set at that time
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", filter.sdl_hint());
+    let factor = match filter {
+        ScaleFilter::Scale2x => 2,
+        _ => 1,
+    };
+    unsafe {
+        let tc_ptr = tc as *mut TextureCreator<WindowContext>;
         (*tc_ptr)
-            .create_texture_streaming(PixelFormatEnum::BGRA32, SCREEN_WIDTH, SCREEN_HEIGHT)
+            .create_texture_streaming(
+                PixelFormatEnum::BGRA32,
+                SCREEN_WIDTH * factor,
+                SCREEN_HEIGHT * factor,
+            )
             .unwrap()
-    };
-    Sdl2Video {
-        _tc: tc,
-        texture: texture,
-        canvas: canvas,
     }
 }
+
+/// `screens` GBA screens are shown side by side
+pub fn create_video_interface<'a>(
+    mut canvas: WindowCanvas,
+    filter: ScaleFilter,
+    screens: u32,
+) -> Result<Sdl2Video<'a>, String> {
+    let mut tc = canvas.texture_creator();
+    let textures = (0..screens)
+        .map(|_| create_texture(&mut tc, filter))
+        .collect();
+    canvas
+        .set_logical_size(SCREEN_WIDTH * screens, SCREEN_HEIGHT)
+        .map_err(|e| e.to_string())?;
+    canvas.set_integer_scale(filter == ScaleFilter::Integer)?;
+    Ok(Sdl2Video {
+        tc,
+        textures,
+        canvas,
+        filter,
+        scaled: Vec::new(),
+    })
+}
@@ -48,14 +48,14 @@ impl Interface {
 }
 
 impl VideoInterface for Interface {
+    /// The canvas ImageData layout, so frames are copied as they are
+    fn output_format(&self) -> OutputFormat {
+        OutputFormat::Rgba8888
+    }
+
     fn render(&mut self, buffer: &[u32]) {
-        // TODO optimize
-        for i in 0..buffer.len() {
-            let color = buffer[i];
-            self.frame[4 * i + 0] = ((color >> 16) & 0xff) as u8;
-            self.frame[4 * i + 1] = ((color >> 8) & 0xff) as u8;
-            self.frame[4 * i + 2] = (color & 0xff) as u8;
-            self.frame[4 * i + 3] = 255;
+        for (pixel, color) in self.frame.chunks_exact_mut(4).zip(buffer.iter()) {
+            pixel.copy_from_slice(&color.to_ne_bytes());
         }
     }
 }
@@ -111,8 +111,18 @@ impl Emulator {
         self.gba.skip_bios();
     }
 
+    /// `mode` is "off", "auto" or the number of frames to skip after each drawn frame
+    pub fn set_frameskip(&mut self, mode: &str) -> Result<(), JsValue> {
+        let mode: FrameSkip = mode.parse().map_err(|e: String| JsValue::from_str(&e))?;
+        self.gba.set_frameskip(mode);
+        Ok(())
+    }
+
     pub fn run_frame(&mut self, ctx: &CanvasRenderingContext2d) -> Result<(), JsValue> {
         self.gba.frame();
+        if self.gba.frame_skipped() {
+            return Ok(());
+        }
         let mut frame_buffer = &mut self.interface.borrow_mut().frame;
         let data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
             Clamped(&mut frame_buffer),